@@ -0,0 +1,67 @@
+//! Cost of rejecting requests for unknown devboxes, as sent by scanners.
+//!
+//! Compares the full host parse and registry lookup with the uniqueID filter
+//! consulted first by the proxy.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use httpgate::proxy::DevboxProxy;
+use httpgate::registry::DevboxRegistry;
+
+const DEVBOXES: usize = 10_000;
+
+fn registry() -> DevboxRegistry {
+    let registry = DevboxRegistry::new();
+    for i in 0..DEVBOXES {
+        registry.register_devbox(
+            format!("devbox-app-{i}"),
+            format!("ns-{}", i % 100),
+            format!("devbox{i}"),
+        );
+    }
+    registry
+}
+
+/// Hosts of devboxes that don't exist
+fn junk_hosts() -> Vec<String> {
+    (0..1000)
+        .map(|i| format!("devbox-scan-{i:x}-8080.devbox.example.com"))
+        .collect()
+}
+
+fn bench_miss(c: &mut Criterion) {
+    let registry = registry();
+    let hosts = junk_hosts();
+    let mut group = c.benchmark_group("unknown_host");
+
+    group.bench_function("parse_and_lookup", |b| {
+        b.iter(|| {
+            for host in &hosts {
+                let found = DevboxProxy::parse_host(host)
+                    .and_then(|(_, unique_id, _)| registry.get_devbox(&unique_id));
+                black_box(found.is_some());
+            }
+        });
+    });
+
+    group.bench_function("filter_first", |b| {
+        b.iter(|| {
+            for host in &hosts {
+                let passed = DevboxProxy::candidate_unique_id(host)
+                    .is_some_and(|unique_id| registry.may_contain_devbox(unique_id));
+                // False positives fall through to the full path
+                let found = passed
+                    && DevboxProxy::parse_host(host)
+                        .and_then(|(_, unique_id, _)| registry.get_devbox(&unique_id))
+                        .is_some();
+                black_box(found);
+            }
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_miss);
+criterion_main!(benches);
@@ -0,0 +1,171 @@
+//! Cost of routing a request to its backend.
+//!
+//! Covers host parsing across the host shapes clients send (and against the
+//! regex a custom `HOST_PATTERN` is matched with), backend resolution as the
+//! registry grows, and resolution from many threads at once, as the proxy's
+//! worker threads do.
+
+use std::hint::black_box;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use httpgate::blocklist::Blocklist;
+use httpgate::config::Config;
+use httpgate::proxy::{
+    resolve_backend, BackendResult, DevboxProxy, HostParser, DEFAULT_HOST_PATTERN,
+};
+use httpgate::registry::DevboxRegistry;
+use regex::Regex;
+
+const HOSTS: [(&str, &str); 5] = [
+    ("http", "devbox-my-app-8080.devbox.sealos.io"),
+    ("grpc", "devboxgrpc-my-app-50051.devbox.sealos.io"),
+    ("with_port", "devbox-my-app-8080.devbox.sealos.io:443"),
+    (
+        "long_id",
+        "devbox-outdoor-before-walking-quietly-78648-8080.devbox.sealos.io",
+    ),
+    ("invalid", "www.example.com"),
+];
+
+/// Registry holding `size` devboxes, each with a running pod
+fn registry(size: usize) -> DevboxRegistry {
+    let registry = DevboxRegistry::new();
+    for i in 0..size {
+        let namespace = format!("ns-{}", i % 100);
+        let devbox_name = format!("devbox{i}");
+        registry.register_devbox(
+            format!("devbox-app-{i}"),
+            namespace.clone(),
+            devbox_name.clone(),
+        );
+        registry
+            .update_pod_ip(
+                &namespace,
+                &devbox_name,
+                format!("10.{}.{}.{}", i >> 16 & 0xff, i >> 8 & 0xff, i & 0xff),
+            )
+            .unwrap();
+    }
+    registry
+}
+
+fn bench_parse_host(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_host");
+
+    for (name, host) in HOSTS {
+        group.bench_with_input(BenchmarkId::from_parameter(name), host, |b, host| {
+            b.iter(|| black_box(DevboxProxy::parse_host(black_box(host))));
+        });
+    }
+
+    group.finish();
+}
+
+/// The default parser against the same scheme as a regex pattern
+fn bench_parse_host_regex(c: &mut Criterion) {
+    let parsers = [
+        ("manual", HostParser::Default),
+        (
+            "regex",
+            HostParser::Pattern(Regex::new(DEFAULT_HOST_PATTERN).unwrap()),
+        ),
+    ];
+    let mut group = c.benchmark_group("parse_host_scheme");
+
+    for (parser_name, parser) in &parsers {
+        for (name, host) in HOSTS {
+            group.bench_with_input(BenchmarkId::new(*parser_name, name), host, |b, host| {
+                b.iter(|| black_box(parser.parse(black_box(host))));
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_resolve(c: &mut Criterion) {
+    let blocklist = Blocklist::from_config(&Config::default());
+    let mut group = c.benchmark_group("resolve_backend");
+
+    for size in [100, 10_000, 100_000] {
+        let registry = registry(size);
+        let unique_id = format!("devbox-app-{}", size / 2);
+        assert!(matches!(
+            resolve_backend(&registry, &blocklist, &unique_id, 8080),
+            BackendResult::Ok(..)
+        ));
+
+        group.bench_with_input(BenchmarkId::new("hit", size), &unique_id, |b, id| {
+            b.iter(|| black_box(resolve_backend(&registry, &blocklist, id, 8080)));
+        });
+        group.bench_with_input(BenchmarkId::new("miss", size), "devbox-gone", |b, id| {
+            b.iter(|| black_box(resolve_backend(&registry, &blocklist, id, 8080)));
+        });
+    }
+
+    group.finish();
+}
+
+/// Wall time for `threads` threads to each resolve `iters` hosts
+fn resolve_concurrently(
+    registry: &DevboxRegistry,
+    blocklist: &Blocklist,
+    unique_ids: &[String],
+    threads: usize,
+    iters: u64,
+) -> Duration {
+    let barrier = Barrier::new(threads + 1);
+    thread::scope(|scope| {
+        for offset in 0..threads {
+            let barrier = &barrier;
+            scope.spawn(move || {
+                barrier.wait();
+                for i in 0..iters as usize {
+                    let id = &unique_ids[(i + offset * 7919) % unique_ids.len()];
+                    black_box(resolve_backend(registry, blocklist, id, 8080));
+                }
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        let start = Instant::now();
+        barrier.wait();
+        start.elapsed()
+    })
+}
+
+fn bench_concurrent(c: &mut Criterion) {
+    const DEVBOXES: usize = 10_000;
+
+    let registry = registry(DEVBOXES);
+    let blocklist = Blocklist::from_config(&Config::default());
+    let unique_ids: Vec<String> = (0..DEVBOXES).map(|i| format!("devbox-app-{i}")).collect();
+    let mut group = c.benchmark_group("resolve_concurrent");
+
+    for threads in [1, 4, 8] {
+        group.throughput(Throughput::Elements(threads as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iters| {
+                    resolve_concurrently(&registry, &blocklist, &unique_ids, threads, iters)
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_parse_host,
+    bench_parse_host_regex,
+    bench_resolve,
+    bench_concurrent
+);
+criterion_main!(benches);
@@ -0,0 +1,126 @@
+//! Cost of decoding Devbox watch events, full and slim.
+//!
+//! Decodes the same object, as the API server sends it, into [`Devbox`] and
+//! [`SlimDevbox`], and reports the heap each decoded object keeps alive,
+//! which is what a list page of many Devboxes holds at once.
+//!
+//! On the object below, a full Devbox keeps about 16 KiB and a slim one
+//! about 1 KiB; objects with more managed fields and status widen the gap.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use httpgate::crd::{Devbox, SlimDevbox};
+use serde::de::DeserializeOwned;
+
+/// Counts the bytes allocated and not yet freed
+struct Counting;
+
+static LIVE_BYTES: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE_BYTES.fetch_add(layout.size() as isize, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size() as isize, Ordering::Relaxed);
+        System.dealloc(ptr, layout);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// A Devbox as the controller leaves it
+fn object() -> Vec<u8> {
+    let managed_field = |manager: &str| {
+        serde_json::json!({
+            "manager": manager,
+            "operation": "Update",
+            "apiVersion": "devbox.sealos.io/v1alpha2",
+            "time": "2024-05-01T12:00:00Z",
+            "fieldsType": "FieldsV1",
+            "fieldsV1": {
+                "f:metadata": {"f:annotations": {".": {}}, "f:labels": {".": {}}},
+                "f:spec": {".": {}, "f:config": {".": {}, "f:appPorts": {}}, "f:state": {}},
+                "f:status": {".": {}, "f:network": {".": {}, "f:uniqueID": {}}, "f:phase": {}}
+            }
+        })
+    };
+    serde_json::to_vec(&serde_json::json!({
+        "apiVersion": "devbox.sealos.io/v1alpha2",
+        "kind": "Devbox",
+        "metadata": {
+            "name": "my-app",
+            "namespace": "ns-abcdefgh",
+            "uid": "3f6c2a1e-7d4b-4c8e-9a0f-1b2c3d4e5f60",
+            "resourceVersion": "123456789",
+            "generation": 4,
+            "creationTimestamp": "2024-05-01T12:00:00Z",
+            "labels": {
+                "app.kubernetes.io/name": "my-app",
+                "app.kubernetes.io/part-of": "devbox",
+                "devbox.sealos.io/template": "ubuntu-22.04"
+            },
+            "annotations": {"devbox.sealos.io/response-cache": "true"},
+            "finalizers": ["devbox.sealos.io/finalizer"],
+            "managedFields": [managed_field("devbox-controller"), managed_field("sealos-desktop")]
+        },
+        "spec": {
+            "state": "Running",
+            "resource": {"cpu": "2", "memory": "4Gi"},
+            "image": "ghcr.io/labring-actions/devbox/ubuntu-22.04:latest",
+            "config": {
+                "appPorts": [{"name": "app", "port": 8080, "protocol": "TCP", "targetPort": 8080}],
+                "ports": [{"containerPort": 22, "name": "devbox-ssh-port", "protocol": "TCP"}],
+                "user": "devbox",
+                "workingDir": "/home/devbox/project",
+                "releaseCommand": ["/bin/bash", "-c"],
+                "releaseArgs": ["/home/devbox/project/entrypoint.sh"]
+            }
+        },
+        "status": {
+            "network": {"uniqueID": "outdoor-before-78648", "nodePort": 31234, "type": "NodePort"},
+            "phase": "Running",
+            "commitHistory": [{"image": "registry/ns-abcdefgh/my-app:v1", "status": "Success"}],
+            "lastState": {"terminated": {"exitCode": 0, "reason": "Completed"}}
+        }
+    }))
+    .unwrap()
+}
+
+/// Heap each of `count` decoded objects keeps alive
+fn bytes_per_object<K: DeserializeOwned>(json: &[u8], count: usize) -> isize {
+    let before = LIVE_BYTES.load(Ordering::Relaxed);
+    let decoded: Vec<K> = (0..count)
+        .map(|_| serde_json::from_slice(json).unwrap())
+        .collect();
+    let after = LIVE_BYTES.load(Ordering::Relaxed);
+    drop(black_box(decoded));
+    (after - before) / count as isize
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let json = object();
+    eprintln!(
+        "bytes per decoded object: full {}, slim {}",
+        bytes_per_object::<Devbox>(&json, 1000),
+        bytes_per_object::<SlimDevbox>(&json, 1000)
+    );
+
+    let mut group = c.benchmark_group("decode_devbox");
+    group.bench_function("full", |b| {
+        b.iter(|| black_box(serde_json::from_slice::<Devbox>(black_box(&json)).unwrap()));
+    });
+    group.bench_function("slim", |b| {
+        b.iter(|| black_box(serde_json::from_slice::<SlimDevbox>(black_box(&json)).unwrap()));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
@@ -0,0 +1,188 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use http::HeaderMap;
+use pingora_http::RequestHeader;
+
+use crate::config::REDACTED;
+
+/// Header carrying the client's request ID
+const X_REQUEST_ID: &str = "x-request-id";
+
+/// Sampling key of requests without an ID
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the access records of `req` are sampled at `ratio`.
+///
+/// The decision follows the request's `X-Request-Id`, so every line logged
+/// for a request, here and by the hops before and after it, is kept or
+/// dropped together. Requests without an ID are sampled by arrival order.
+pub fn is_sampled(req: &RequestHeader, ratio: f64) -> bool {
+    ratio >= 1.0 || is_key_sampled(sample_key(req), ratio)
+}
+
+/// Key the access records of `req` are sampled by, taken when the request
+/// arrives so the ratio can be picked once it is routed (see
+/// [`is_sampled`]).
+pub fn sample_key(req: &RequestHeader) -> u64 {
+    match req.headers.get(X_REQUEST_ID) {
+        Some(id) => hash(id.as_bytes()),
+        None => hash(&NEXT_REQUEST.fetch_add(1, Ordering::Relaxed).to_le_bytes()),
+    }
+}
+
+/// Whether the access records of a request with sampling key `key` are
+/// sampled at `ratio`. A key sampled at a ratio is sampled at higher ones.
+pub fn is_key_sampled(key: u64, ratio: f64) -> bool {
+    ratio >= 1.0 || sampled(key, ratio)
+}
+
+/// Whether a request is logged: failures always are, successes if sampled.
+///
+/// Status 0 means no response was sent.
+pub fn should_log(status: u16, failed: bool, sampled: bool) -> bool {
+    failed || !(100..400).contains(&status) || sampled
+}
+
+/// `headers` as a JSON object for a log record, with the values of the
+/// `sensitive` ones (lowercase names) replaced. Repeated headers are joined
+/// with commas.
+pub fn redacted_headers(headers: &HeaderMap, sensitive: &[String]) -> String {
+    let dump: serde_json::Map<String, serde_json::Value> = headers
+        .keys()
+        .map(|name| {
+            let value = if sensitive.iter().any(|s| s == name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                headers
+                    .get_all(name)
+                    .iter()
+                    .map(|v| String::from_utf8_lossy(v.as_bytes()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            (name.as_str().to_string(), value.into())
+        })
+        .collect();
+    serde_json::Value::Object(dump).to_string()
+}
+
+/// Whether a request whose key hashes to `hash` is in the sampled `ratio`
+fn sampled(hash: u64, ratio: f64) -> bool {
+    // Exact for the ratios that matter; 2^64 * ratio saturates at u64::MAX
+    (hash as f64) < ratio * u64::MAX as f64
+}
+
+/// FNV-1a with a final mix, so IDs differing in their last characters
+/// (counters, timestamps) still spread over the whole range
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: Option<&str>) -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        if let Some(id) = id {
+            req.insert_header(X_REQUEST_ID, id).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn test_sampling_deterministic_per_request_id() {
+        for i in 0..100 {
+            let req = request(Some(&format!("req-{i}")));
+            let first = is_sampled(&req, 0.3);
+            for _ in 0..5 {
+                assert_eq!(is_sampled(&req, 0.3), first);
+            }
+            // A request sampled at a ratio stays sampled at higher ones
+            if first {
+                assert!(is_sampled(&req, 0.5));
+            }
+        }
+    }
+
+    #[test]
+    fn test_sampling_ratio() {
+        let count = |ratio: f64| {
+            (0..10_000)
+                .filter(|i| is_sampled(&request(Some(&format!("{i:08x}"))), ratio))
+                .count()
+        };
+        assert_eq!(count(0.0), 0);
+        assert_eq!(count(1.0), 10_000);
+        let sampled = count(0.01);
+        assert!((50..=150).contains(&sampled), "{sampled}");
+        let sampled = count(0.5);
+        assert!((4_700..=5_300).contains(&sampled), "{sampled}");
+
+        // Requests without an ID are spread the same way
+        let sampled = (0..10_000)
+            .filter(|_| is_sampled(&request(None), 0.1))
+            .count();
+        assert!((800..=1_200).contains(&sampled), "{sampled}");
+    }
+
+    #[test]
+    fn test_redacted_headers() {
+        let mut req = request(Some("abc"));
+        req.insert_header("authorization", "Bearer secret").unwrap();
+        req.insert_header("cookie", "devbox_preview=token").unwrap();
+        req.append_header("accept", "text/html").unwrap();
+        req.append_header("accept", "*/*").unwrap();
+        let sensitive = ["authorization".to_string(), "cookie".to_string()];
+
+        let dump = redacted_headers(&req.headers, &sensitive);
+        assert!(
+            !dump.contains("secret") && !dump.contains("token"),
+            "{dump}"
+        );
+        let dump: serde_json::Value = serde_json::from_str(&dump).unwrap();
+        assert_eq!(
+            dump,
+            serde_json::json!({
+                "accept": "text/html, */*",
+                "authorization": REDACTED,
+                "cookie": REDACTED,
+                "x-request-id": "abc",
+            })
+        );
+
+        // Nothing is redacted unless listed
+        let dump = redacted_headers(&req.headers, &[]);
+        assert!(dump.contains("Bearer secret"), "{dump}");
+    }
+
+    #[test]
+    fn test_should_log() {
+        for (status, failed, sampled, expected) in [
+            (200, false, true, true),
+            (200, false, false, false),
+            (304, false, false, false),
+            (101, false, false, false),
+            // Errors are always logged
+            (404, false, false, true),
+            (503, false, false, true),
+            (502, true, false, true),
+            (200, true, false, true),
+            (0, false, false, true),
+        ] {
+            assert_eq!(
+                should_log(status, failed, sampled),
+                expected,
+                "{status} failed={failed} sampled={sampled}"
+            );
+        }
+    }
+}
@@ -0,0 +1,970 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use bytes::Bytes;
+use dashmap::DashMap;
+use http::header::{ACCEPT, CONTENT_TYPE, HOST, LOCATION, RETRY_AFTER, USER_AGENT};
+use http::{HeaderMap, Method, Request, Uri};
+use http_body_util::{BodyExt, Full};
+use hyper_util::rt::TokioIo;
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING, ECDSA_P256_SHA256_FIXED_SIGNING,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+use tracing::{debug, error, info, warn};
+
+use crate::config::Config;
+use crate::metrics;
+use crate::preview::unix_now;
+use crate::tls;
+use crate::tls_reload::{CertStore, ServerCert};
+
+/// Path prefix of HTTP-01 challenges (RFC 8555, section 8.3)
+pub const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Cached account key, in the cache directory
+const ACCOUNT_KEY_FILE: &str = "account.key";
+
+/// Time allowed for one request to the ACME server
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Times an authorization or order is polled before giving up
+const POLL_ATTEMPTS: u32 = 30;
+
+/// Time between polls, unless the server asks for another with `Retry-After`
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Time between renewal checks of a certificate not due yet
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 3600);
+
+/// Delay before retrying a failed order, doubled up to [`MAX_RETRY_DELAY`]
+const RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(6 * 3600);
+
+/// Key authorizations of pending HTTP-01 challenges, by token.
+///
+/// Shared by the ACME client, which adds them while an order is validated,
+/// and every proxy, which answers the CA's requests for them before the
+/// host is routed to a devbox.
+#[derive(Debug, Default)]
+pub struct Challenges {
+    pending: DashMap<String, String>,
+}
+
+impl Challenges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, token: String, key_authorization: String) {
+        self.pending.insert(token, key_authorization);
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.pending.remove(token);
+    }
+
+    /// The key authorization to answer a request for `path` with, if it
+    /// is a pending challenge.
+    ///
+    /// Other challenge paths are left to the devbox, which may be
+    /// validating names of its own.
+    pub fn response(&self, path: &str) -> Option<String> {
+        let token = path.strip_prefix(CHALLENGE_PREFIX)?;
+        self.pending.get(token).map(|entry| entry.value().clone())
+    }
+}
+
+/// The ACME account key and the certificate, kept on disk across
+/// restarts.
+///
+/// The certificate is stored as `<first domain>.crt` and `.key`, the layout
+/// of `tls_cert_dir` listeners. Files are replaced atomically, and keys are
+/// only readable by the gateway.
+#[derive(Debug, Clone)]
+pub struct AcmeCache {
+    dir: PathBuf,
+}
+
+impl AcmeCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn cert_paths(&self, domains: &[String]) -> (PathBuf, PathBuf) {
+        let name = domains.first().map_or("certificate", String::as_str);
+        (
+            self.dir.join(format!("{name}.crt")),
+            self.dir.join(format!("{name}.key")),
+        )
+    }
+
+    /// The cached account key as PKCS#8, creating (and caching) one if
+    /// there is none yet.
+    pub fn account_key(&self) -> Result<Vec<u8>, String> {
+        let path = self.dir.join(ACCOUNT_KEY_FILE);
+        match std::fs::read(&path) {
+            Ok(pem) => private_key_der(&pem).map_err(|e| format!("{}: {e}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let key = generate_key()?;
+                self.write(&path, pem("PRIVATE KEY", &key).as_bytes(), true)?;
+                info!(path = %path.display(), "Created ACME account key");
+                Ok(key)
+            }
+            Err(e) => Err(format!("Failed to read {}: {e}", path.display())),
+        }
+    }
+
+    /// The cached certificate for `domains`, if any.
+    pub fn load_cert(&self, domains: &[String]) -> Result<Option<ServerCert>, String> {
+        let (cert_path, key_path) = self.cert_paths(domains);
+        let read = |path: &Path| match std::fs::read(path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read {}: {e}", path.display())),
+        };
+        let (Some(cert_pem), Some(key_pem)) = (read(&cert_path)?, read(&key_path)?) else {
+            return Ok(None);
+        };
+        ServerCert::from_pem(&cert_pem, &key_pem)
+            .map(Some)
+            .map_err(|e| format!("{}: {e}", cert_path.display()))
+    }
+
+    /// Cache the PEM certificate chain and key issued for `domains`.
+    pub fn store_cert(
+        &self,
+        domains: &[String],
+        cert_pem: &str,
+        key_pem: &str,
+    ) -> Result<(), String> {
+        let (cert_path, key_path) = self.cert_paths(domains);
+        // The key first, so a crash in between leaves a mismatched pair
+        // that is refused rather than an old key served with a new chain
+        self.write(&key_path, key_pem.as_bytes(), true)?;
+        self.write(&cert_path, cert_pem.as_bytes(), false)
+    }
+
+    fn write(&self, path: &Path, data: &[u8], private: bool) -> Result<(), String> {
+        let fail = |e: std::io::Error| format!("Failed to write {}: {e}", path.display());
+        std::fs::create_dir_all(&self.dir).map_err(fail)?;
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+        let tmp = self.dir.join(format!(".{name}.tmp"));
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        if private {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp).map_err(fail)?;
+        file.write_all(data).map_err(fail)?;
+        file.sync_all().map_err(fail)?;
+        std::fs::rename(&tmp, path).map_err(fail)
+    }
+}
+
+/// Whether `cert` must be replaced at `now`: it expires within
+/// `renew_before`, or doesn't cover all of `domains`.
+pub fn renewal_due(
+    cert: &ServerCert,
+    domains: &[String],
+    now: u64,
+    renew_before: Duration,
+) -> bool {
+    let renew_at = cert
+        .not_after
+        .saturating_sub(i64::try_from(renew_before.as_secs()).unwrap_or(i64::MAX));
+    i64::try_from(now).unwrap_or(i64::MAX) >= renew_at
+        || domains.iter().any(|domain| !cert.names.contains(domain))
+}
+
+/// Orders a certificate for the ACME listeners from `ACME_DIRECTORY_URL`
+/// with HTTP-01 challenges, then keeps it renewed.
+///
+/// The cached certificate is served right away; a new one is only ordered
+/// once it is due for renewal (or covers other names). Challenges are
+/// answered by the proxies sharing [`Challenges`], so the CA must reach a
+/// cleartext listener on port 80 for the ordered names.
+pub struct AcmeManager {
+    directory_url: String,
+    email: Option<String>,
+    domains: Vec<String>,
+    renew_before: Duration,
+    cache: AcmeCache,
+    challenges: Arc<Challenges>,
+    certs: Arc<CertStore>,
+}
+
+impl AcmeManager {
+    /// Names the certificate covers: `ACME_DOMAINS`, else the domain
+    /// suffixes of the listeners with `acme=true`.
+    pub fn domains(config: &Config) -> Vec<String> {
+        if !config.acme_domains.is_empty() {
+            return config.acme_domains.clone();
+        }
+        let mut domains: Vec<String> = Vec::new();
+        for suffix in config
+            .listeners
+            .iter()
+            .filter(|l| l.acme)
+            .flat_map(|l| &l.policy.domain_suffixes)
+        {
+            if !domains.contains(suffix) {
+                domains.push(suffix.clone());
+            }
+        }
+        domains
+    }
+
+    pub fn from_config(
+        config: &Config,
+        challenges: Arc<Challenges>,
+        certs: Arc<CertStore>,
+    ) -> Self {
+        Self {
+            directory_url: config.acme_directory_url.clone(),
+            email: config.acme_email.clone(),
+            domains: Self::domains(config),
+            renew_before: config.acme_renew_before,
+            cache: AcmeCache::new(&config.acme_cache_dir),
+            challenges,
+            certs,
+        }
+    }
+
+    /// Serve the cached certificate, then order and renew it forever.
+    pub async fn run(self) {
+        let mut current = match self.cache.load_cert(&self.domains) {
+            Ok(cached) => cached,
+            Err(e) => {
+                warn!(error = %e, "Ignoring unusable cached ACME certificate");
+                None
+            }
+        };
+        if let Some(cert) = &current {
+            info!(domains = ?self.domains, not_after = cert.not_after, "Serving cached ACME certificate");
+            self.certs.swap(cert.clone());
+        }
+
+        let mut retry_delay = RETRY_DELAY;
+        loop {
+            let due = current
+                .as_ref()
+                .is_none_or(|cert| renewal_due(cert, &self.domains, unix_now(), self.renew_before));
+            if !due {
+                tokio::time::sleep(CHECK_INTERVAL).await;
+                continue;
+            }
+
+            match self.order().await {
+                Ok(cert) => {
+                    metrics::ACME_ORDERS_TOTAL
+                        .with_label_values(&["issued"])
+                        .inc();
+                    info!(domains = ?self.domains, not_after = cert.not_after, "ACME certificate issued");
+                    self.certs.swap(cert.clone());
+                    current = Some(cert);
+                    retry_delay = RETRY_DELAY;
+                }
+                Err(e) => {
+                    metrics::ACME_ORDERS_TOTAL
+                        .with_label_values(&["failed"])
+                        .inc();
+                    error!(
+                        domains = ?self.domains,
+                        error = %e,
+                        retry_in = ?retry_delay,
+                        "ACME certificate order failed"
+                    );
+                    tokio::time::sleep(retry_delay).await;
+                    retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        }
+    }
+
+    /// Order a certificate, cache it and return it.
+    async fn order(&self) -> Result<ServerCert, String> {
+        let account_key = self.cache.account_key()?;
+        let mut client = AcmeClient::connect(&self.directory_url, &account_key).await?;
+        client.register(self.email.as_deref()).await?;
+
+        let mut tokens = Vec::new();
+        let issued = client
+            .issue(&self.domains, &self.challenges, &mut tokens)
+            .await;
+        for token in &tokens {
+            self.challenges.remove(token);
+        }
+        let (cert_pem, key_pem) = issued?;
+
+        let cert = ServerCert::from_pem(cert_pem.as_bytes(), key_pem.as_bytes())?;
+        self.cache.store_cert(&self.domains, &cert_pem, &key_pem)?;
+        Ok(cert)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    #[serde(default)]
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+}
+
+struct Response {
+    status: u16,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Response {
+    fn header(&self, name: impl http::header::AsHeaderName) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    fn json<T: for<'de> Deserialize<'de>>(&self) -> Result<T, String> {
+        serde_json::from_slice(&self.body).map_err(|e| format!("invalid ACME response: {e}"))
+    }
+
+    /// Delay the server asked for before polling again
+    fn retry_after(&self) -> Duration {
+        self.header(RETRY_AFTER)
+            .and_then(|v| v.trim().parse().ok())
+            .map_or(POLL_INTERVAL, |secs: u64| {
+                Duration::from_secs(secs.clamp(1, 60))
+            })
+    }
+}
+
+/// Minimal ACME (RFC 8555) client for HTTP-01 orders, signing requests with
+/// an ECDSA P-256 account key.
+struct AcmeClient {
+    directory: Directory,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    /// Account URL, once registered
+    kid: Option<String>,
+    nonce: Option<String>,
+    tls: TlsConnector,
+}
+
+impl AcmeClient {
+    async fn connect(directory_url: &str, account_key: &[u8]) -> Result<Self, String> {
+        let rng = SystemRandom::new();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, account_key, &rng)
+            .map_err(|e| format!("invalid ACME account key: {e}"))?;
+
+        let mut client = Self {
+            directory: Directory {
+                new_nonce: String::new(),
+                new_account: String::new(),
+                new_order: String::new(),
+            },
+            key,
+            rng,
+            kid: None,
+            nonce: None,
+            tls: tls::client_connector(),
+        };
+        let response = client.fetch(Method::GET, directory_url, None).await?;
+        if response.status != 200 {
+            return Err(format!("ACME directory returned {}", response.status));
+        }
+        client.directory = response.json()?;
+        Ok(client)
+    }
+
+    /// Register the account of the key (or find the existing one).
+    async fn register(&mut self, email: Option<&str>) -> Result<(), String> {
+        let contact: Vec<String> = email.iter().map(|e| format!("mailto:{e}")).collect();
+        let payload = json!({ "termsOfServiceAgreed": true, "contact": contact });
+        let url = self.directory.new_account.clone();
+        let response = self.post(&url, Some(&payload)).await?;
+        let kid = response
+            .header(LOCATION)
+            .ok_or("ACME account response has no Location")?;
+        debug!(account = %kid, "Registered ACME account");
+        self.kid = Some(kid.to_string());
+        Ok(())
+    }
+
+    /// Order a certificate for `domains`, answering its challenges through
+    /// `challenges`. Tokens added there are pushed to `tokens` so the
+    /// caller can remove them whatever the outcome.
+    ///
+    /// Returns the PEM certificate chain and private key.
+    async fn issue(
+        &mut self,
+        domains: &[String],
+        challenges: &Challenges,
+        tokens: &mut Vec<String>,
+    ) -> Result<(String, String), String> {
+        let identifiers: Vec<Value> = domains
+            .iter()
+            .map(|d| json!({ "type": "dns", "value": d }))
+            .collect();
+        let url = self.directory.new_order.clone();
+        let response = self
+            .post(&url, Some(&json!({ "identifiers": identifiers })))
+            .await?;
+        let order_url = response
+            .header(LOCATION)
+            .ok_or("ACME order response has no Location")?
+            .to_string();
+        let order: Order = response.json()?;
+
+        for url in &order.authorizations {
+            let authorization: Authorization = self.post(url, None).await?.json()?;
+            if authorization.status == "valid" {
+                continue;
+            }
+            let challenge = authorization
+                .challenges
+                .iter()
+                .find(|c| c.kind == "http-01")
+                .ok_or_else(|| {
+                    format!(
+                        "no http-01 challenge for {}",
+                        authorization.identifier.value
+                    )
+                })?;
+            challenges.insert(
+                challenge.token.clone(),
+                self.key_authorization(&challenge.token),
+            );
+            tokens.push(challenge.token.clone());
+            self.post(&challenge.url, Some(&json!({}))).await?;
+            self.poll(url, &authorization.identifier.value).await?;
+        }
+
+        let key = generate_key()?;
+        let csr = csr(&key, domains)?;
+        self.post(
+            &order.finalize,
+            Some(&json!({ "csr": URL_SAFE_NO_PAD.encode(csr) })),
+        )
+        .await?;
+        let order: Order = self.poll(&order_url, "order").await?.json()?;
+        let certificate = order
+            .certificate
+            .ok_or("ACME order is valid but has no certificate")?;
+        let response = self.post(&certificate, None).await?;
+        let chain =
+            String::from_utf8(response.body.to_vec()).map_err(|_| "ACME certificate is not PEM")?;
+        Ok((chain, pem("PRIVATE KEY", &key)))
+    }
+
+    /// Poll `url` until its status is "valid", failing on "invalid".
+    async fn poll(&mut self, url: &str, what: &str) -> Result<Response, String> {
+        for _ in 0..POLL_ATTEMPTS {
+            let response = self.post(url, None).await?;
+            let status: Value = response.json()?;
+            match status["status"].as_str() {
+                Some("valid") => return Ok(response),
+                Some("invalid") => {
+                    return Err(format!(
+                        "ACME validation of {what} failed: {}",
+                        response_detail(&status)
+                    ))
+                }
+                _ => tokio::time::sleep(response.retry_after()).await,
+            }
+        }
+        Err(format!("ACME validation of {what} timed out"))
+    }
+
+    /// `<token>.<account key thumbprint>` (RFC 8555, section 8.1)
+    fn key_authorization(&self, token: &str) -> String {
+        let (x, y) = self.public_coordinates();
+        // RFC 7638: the required members in lexicographic order
+        let jwk = format!(r#"{{"crv":"P-256","kty":"EC","x":"{x}","y":"{y}"}}"#);
+        let thumbprint = URL_SAFE_NO_PAD.encode(Sha256::digest(jwk.as_bytes()));
+        format!("{token}.{thumbprint}")
+    }
+
+    /// Base64url x and y of the uncompressed public key
+    fn public_coordinates(&self) -> (String, String) {
+        let point = &self.key.public_key().as_ref()[1..];
+        let (x, y) = point.split_at(point.len() / 2);
+        (URL_SAFE_NO_PAD.encode(x), URL_SAFE_NO_PAD.encode(y))
+    }
+
+    /// POST a JWS of `payload` (POST-as-GET without one), retrying once if
+    /// the server rejects the nonce.
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Response, String> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => {
+                    let url = self.directory.new_nonce.clone();
+                    self.fetch(Method::HEAD, &url, None).await?;
+                    self.nonce.take().ok_or("ACME server sent no nonce")?
+                }
+            };
+            let body = self.jws(url, &nonce, payload)?;
+            let response = self.fetch(Method::POST, url, Some(body)).await?;
+            if response.status < 400 {
+                return Ok(response);
+            }
+            let problem: Value = response.json().unwrap_or_default();
+            if !retried && problem["type"] == "urn:ietf:params:acme:error:badNonce" {
+                retried = true;
+                continue;
+            }
+            return Err(format!(
+                "ACME request to {url} failed with {}: {}",
+                response.status,
+                response_detail(&problem)
+            ));
+        }
+    }
+
+    /// Flattened JWS of `payload` for `url` (RFC 8555, section 6.2)
+    fn jws(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<Vec<u8>, String> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => {
+                let (x, y) = self.public_coordinates();
+                protected["jwk"] = json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y });
+            }
+        }
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload = payload.map_or_else(String::new, |p| URL_SAFE_NO_PAD.encode(p.to_string()));
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+            .map_err(|_| "failed to sign ACME request")?;
+        let body = json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+        });
+        Ok(body.to_string().into_bytes())
+    }
+
+    async fn fetch(
+        &mut self,
+        method: Method,
+        url: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Response, String> {
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, self.send(method, url, body))
+            .await
+            .map_err(|_| format!("ACME request to {url} timed out"))??;
+        if let Some(nonce) = response.header("replay-nonce") {
+            self.nonce = Some(nonce.to_string());
+        }
+        Ok(response)
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Response, String> {
+        let uri: Uri = url
+            .parse()
+            .map_err(|e| format!("invalid ACME URL {url:?}: {e}"))?;
+        let host = uri
+            .host()
+            .ok_or_else(|| format!("invalid ACME URL {url:?}"))?;
+        let https = uri.scheme_str() != Some("http");
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+        let authority = uri.authority().map_or(host, |a| a.as_str());
+
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri.path_and_query().map_or("/", |p| p.as_str()))
+            .header(HOST, authority)
+            .header(USER_AGENT, concat!("httpgate/", env!("CARGO_PKG_VERSION")))
+            .header(
+                ACCEPT,
+                "application/json, application/pem-certificate-chain",
+            );
+        if body.is_some() {
+            request = request.header(CONTENT_TYPE, "application/jose+json");
+        }
+        let request = request
+            .body(Full::new(Bytes::from(body.unwrap_or_default())))
+            .map_err(|e| e.to_string())?;
+
+        let tcp = TcpStream::connect((host, port))
+            .await
+            .map_err(|e| format!("Failed to connect to {host}:{port}: {e}"))?;
+        if https {
+            let server_name = ServerName::try_from(host.to_string())
+                .map_err(|e| format!("invalid ACME server name {host:?}: {e}"))?;
+            let tls = self
+                .tls
+                .connect(server_name, tcp)
+                .await
+                .map_err(|e| format!("TLS handshake with {host} failed: {e}"))?;
+            send_request(tls, request).await
+        } else {
+            send_request(tcp, request).await
+        }
+    }
+}
+
+async fn send_request<S>(io: S, request: Request<Full<Bytes>>) -> Result<Response, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(io))
+        .await
+        .map_err(|e| e.to_string())?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!(error = %e, "ACME connection failed");
+        }
+    });
+    let (parts, body) = sender
+        .send_request(request)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_parts();
+    let body = body.collect().await.map_err(|e| e.to_string())?.to_bytes();
+    Ok(Response {
+        status: parts.status.as_u16(),
+        headers: parts.headers,
+        body,
+    })
+}
+
+/// Detail of an ACME problem document (RFC 8555, section 6.7)
+fn response_detail(problem: &Value) -> String {
+    problem["error"]["detail"]
+        .as_str()
+        .or_else(|| problem["detail"].as_str())
+        .unwrap_or("no details")
+        .to_string()
+}
+
+/// A new ECDSA P-256 key, as PKCS#8
+fn generate_key() -> Result<Vec<u8>, String> {
+    EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+        .map(|key| key.as_ref().to_vec())
+        .map_err(|_| "failed to generate a key".to_string())
+}
+
+/// PKCS#8 DER of a PEM private key
+fn private_key_der(pem: &[u8]) -> Result<Vec<u8>, String> {
+    match rustls_pemfile::private_key(&mut &pem[..]) {
+        Ok(Some(key)) => Ok(key.secret_der().to_vec()),
+        Ok(None) => Err("no private key found".to_string()),
+        Err(e) => Err(format!("invalid private key PEM: {e}")),
+    }
+}
+
+fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {label}-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {label}-----\n"));
+    pem
+}
+
+/// DER OIDs of the certificate request
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_PRIME256V1: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_ECDSA_SHA256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_EXTENSION_REQUEST: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x0e,
+];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x11];
+const OID_COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+
+/// DER element with `tag` around the concatenation of `parts`
+fn der(tag: u8, parts: &[&[u8]]) -> Vec<u8> {
+    let content = parts.concat();
+    let mut out = vec![tag];
+    if content.len() < 0x80 {
+        out.push(content.len() as u8);
+    } else {
+        let len = content.len().to_be_bytes();
+        let skip = len.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (len.len() - skip) as u8);
+        out.extend_from_slice(&len[skip..]);
+    }
+    out.extend_from_slice(&content);
+    out
+}
+
+/// PKCS#10 certificate request for `names`, signed with the P-256 `key`.
+///
+/// The first name is the subject's common name (if it fits), and all are
+/// requested as subjectAltNames.
+fn csr(key: &[u8], names: &[String]) -> Result<Vec<u8>, String> {
+    let rng = SystemRandom::new();
+    let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, key, &rng)
+        .map_err(|e| format!("invalid certificate key: {e}"))?;
+
+    let subject = match names.first().filter(|name| name.len() <= 64) {
+        Some(name) => der(
+            0x30,
+            &[&der(
+                0x31,
+                &[&der(
+                    0x30,
+                    &[OID_COMMON_NAME, &der(0x0c, &[name.as_bytes()])],
+                )],
+            )],
+        ),
+        None => der(0x30, &[]),
+    };
+    let public_key = der(
+        0x30,
+        &[
+            &der(0x30, &[OID_EC_PUBLIC_KEY, OID_PRIME256V1]),
+            &der(0x03, &[&[0], key.public_key().as_ref()]),
+        ],
+    );
+    let alt_names: Vec<Vec<u8>> = names.iter().map(|n| der(0x82, &[n.as_bytes()])).collect();
+    let alt_names: Vec<&[u8]> = alt_names.iter().map(Vec::as_slice).collect();
+    let extension = der(
+        0x30,
+        &[OID_SUBJECT_ALT_NAME, &der(0x04, &[&der(0x30, &alt_names)])],
+    );
+    let attributes = der(
+        0xa0,
+        &[&der(
+            0x30,
+            &[
+                OID_EXTENSION_REQUEST,
+                &der(0x31, &[&der(0x30, &[&extension])]),
+            ],
+        )],
+    );
+    let info = der(
+        0x30,
+        &[&[0x02, 0x01, 0x00], &subject, &public_key, &attributes],
+    );
+
+    let signature = key
+        .sign(&rng, &info)
+        .map_err(|_| "failed to sign the certificate request")?;
+    Ok(der(
+        0x30,
+        &[
+            &info,
+            &der(0x30, &[OID_ECDSA_SHA256]),
+            &der(0x03, &[&[0], signature.as_ref()]),
+        ],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls_reload::der_element;
+
+    const DEVBOX_CERT: &[u8] = include_bytes!("../tests/fixtures/tls.crt");
+    const DEVBOX_KEY: &[u8] = include_bytes!("../tests/fixtures/tls.key");
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("httpgate-acme-{name}-{}", std::process::id()))
+    }
+
+    #[test]
+    fn test_challenge_response() {
+        let challenges = Challenges::new();
+        let path = format!("{CHALLENGE_PREFIX}token-1");
+        assert_eq!(challenges.response(&path), None);
+
+        challenges.insert("token-1".to_string(), "token-1.thumbprint".to_string());
+        assert_eq!(
+            challenges.response(&path).as_deref(),
+            Some("token-1.thumbprint")
+        );
+        // Other tokens and paths are the devbox's
+        assert_eq!(
+            challenges.response(&format!("{CHALLENGE_PREFIX}token-2")),
+            None
+        );
+        assert_eq!(challenges.response("/token-1"), None);
+        assert_eq!(challenges.response(CHALLENGE_PREFIX), None);
+
+        challenges.remove("token-1");
+        assert_eq!(challenges.response(&path), None);
+    }
+
+    #[test]
+    fn test_cache_account_key() {
+        let dir = temp_dir("account");
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = AcmeCache::new(&dir);
+
+        let key = cache.account_key().unwrap();
+        assert!(EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &key,
+            &SystemRandom::new()
+        )
+        .is_ok());
+        // Created once, then reused across restarts
+        assert_eq!(AcmeCache::new(&dir).account_key().unwrap(), key);
+
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(dir.join(ACCOUNT_KEY_FILE))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_cert() {
+        let dir = temp_dir("cert");
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = AcmeCache::new(&dir);
+        let domains = vec!["devbox.local".to_string()];
+        assert!(cache.load_cert(&domains).unwrap().is_none());
+
+        let cert_pem = std::str::from_utf8(DEVBOX_CERT).unwrap();
+        let key_pem = std::str::from_utf8(DEVBOX_KEY).unwrap();
+        cache.store_cert(&domains, cert_pem, key_pem).unwrap();
+        assert!(dir.join("devbox.local.crt").exists());
+        assert!(dir.join("devbox.local.key").exists());
+
+        let cert = AcmeCache::new(&dir).load_cert(&domains).unwrap().unwrap();
+        assert!(cert.names.contains(&"devbox.local".to_string()));
+
+        // A half-written pair is refused rather than served
+        std::fs::write(dir.join("devbox.local.key"), b"garbage").unwrap();
+        assert!(cache.load_cert(&domains).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_renewal_due() {
+        // *.devbox.local, devbox.local
+        let cert = ServerCert::from_pem(DEVBOX_CERT, DEVBOX_KEY).unwrap();
+        let domains = vec!["devbox.local".to_string()];
+        let renew_before = Duration::from_secs(30 * 24 * 3600);
+        let renew_at = (cert.not_after - 30 * 24 * 3600) as u64;
+
+        assert!(!renewal_due(&cert, &domains, renew_at - 1, renew_before));
+        assert!(renewal_due(&cert, &domains, renew_at, renew_before));
+        // A certificate for other names is replaced right away
+        let domains = vec!["example.com".to_string()];
+        assert!(renewal_due(&cert, &domains, renew_at - 1, renew_before));
+    }
+
+    #[test]
+    fn test_domains() {
+        let config = Config {
+            listeners: crate::config::parse_listeners(
+                "name=a,addr=0.0.0.0:443,domains=a.example.com|b.example.com,acme=true;
+                 name=b,addr=0.0.0.0:8443,domains=a.example.com,acme=true;
+                 name=c,addr=0.0.0.0:8080,domains=c.example.com",
+                &Config::default(),
+            )
+            .unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            AcmeManager::domains(&config),
+            ["a.example.com", "b.example.com"]
+        );
+
+        let config = Config {
+            acme_domains: vec!["devbox.example.com".to_string()],
+            ..config
+        };
+        assert_eq!(AcmeManager::domains(&config), ["devbox.example.com"]);
+    }
+
+    #[test]
+    fn test_csr() {
+        let key = generate_key().unwrap();
+        let names = vec!["devbox.example.com".to_string(), "example.com".to_string()];
+        let request = csr(&key, &names).unwrap();
+
+        let (request, rest) = der_element(&request, 0x30).unwrap();
+        assert!(rest.is_empty());
+        let info_len = request.len() - der_element(request, 0x30).unwrap().1.len();
+        let (info, rest) = request.split_at(info_len);
+        let (_, rest) = der_element(rest, 0x30).unwrap();
+        let (signature, _) = der_element(rest, 0x03).unwrap();
+
+        // Signed by the key
+        let pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &key, &SystemRandom::new())
+                .unwrap();
+        ring::signature::UnparsedPublicKey::new(
+            &ring::signature::ECDSA_P256_SHA256_ASN1,
+            pair.public_key().as_ref(),
+        )
+        .verify(info, &signature[1..])
+        .unwrap();
+
+        // Requesting every name
+        for name in &names {
+            let alt_name = der(0x82, &[name.as_bytes()]);
+            assert!(
+                info.windows(alt_name.len()).any(|w| w == alt_name),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_pem_roundtrip() {
+        let key = generate_key().unwrap();
+        assert_eq!(
+            private_key_der(pem("PRIVATE KEY", &key).as_bytes()).unwrap(),
+            key
+        );
+        assert!(private_key_der(b"").is_err());
+    }
+}
@@ -0,0 +1,523 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use kube::api::{Patch, PatchParams};
+use kube::{Api, Client};
+use serde_json::json;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::crd::Devbox;
+use crate::error::{Error, Result};
+use crate::preview::unix_now;
+use crate::registry::DevboxRegistry;
+
+/// Devbox annotation holding the Unix time of the last request, written when
+/// `ACTIVITY_REPORTING=crd`
+pub const ANNOTATION_LAST_ACTIVITY: &str = "devbox.sealos.io/last-activity-at";
+
+#[derive(Debug, Default)]
+struct Activity {
+    /// Unix seconds of the last request start or end
+    last_seen: AtomicU64,
+    /// Requests in progress, including upgraded (WebSocket) connections
+    open: AtomicUsize,
+    /// Port of the last request
+    port: AtomicU16,
+    /// Whether the devbox was reported idle and no request came since
+    idle: AtomicBool,
+}
+
+/// Last-request timestamps per devbox uniqueID, for idle detection.
+///
+/// A request counts as activity for as long as it is in progress, so a
+/// long-lived WebSocket connection keeps its devbox active even when no
+/// frames are exchanged.
+#[derive(Debug, Default)]
+pub struct ActivityTracker {
+    entries: DashMap<String, Arc<Activity>>,
+    /// Where the uniqueIDs of devboxes requested after being reported idle
+    /// are sent
+    wakes: Option<mpsc::UnboundedSender<String>>,
+}
+
+impl ActivityTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send the uniqueID of each devbox requested after it was reported
+    /// idle (see [`Self::mark_idle`]) to `wakes`.
+    #[must_use]
+    pub fn with_wakes(mut self, wakes: mpsc::UnboundedSender<String>) -> Self {
+        self.wakes = Some(wakes);
+        self
+    }
+
+    fn entry(&self, unique_id: &str) -> Arc<Activity> {
+        if let Some(entry) = self.entries.get(unique_id) {
+            return Arc::clone(&entry);
+        }
+        Arc::clone(&self.entries.entry(unique_id.to_string()).or_default())
+    }
+
+    /// Mark the start of a request to `port` of `unique_id` at `now`; it
+    /// stays active until the returned guard is dropped.
+    pub fn begin_at(&self, unique_id: &str, port: u16, now: u64) -> ActivityGuard {
+        let entry = self.entry(unique_id);
+        entry.last_seen.store(now, Ordering::Relaxed);
+        entry.port.store(port, Ordering::Relaxed);
+        entry.open.fetch_add(1, Ordering::Relaxed);
+        self.wake_entry(unique_id, &entry);
+        ActivityGuard(entry)
+    }
+
+    /// Signal a wake if `unique_id` was reported idle, for requests that
+    /// find it without a running Pod and so never begin.
+    pub fn wake(&self, unique_id: &str) {
+        if let Some(entry) = self.entries.get(unique_id) {
+            self.wake_entry(unique_id, &entry);
+        }
+    }
+
+    fn wake_entry(&self, unique_id: &str, entry: &Activity) {
+        if entry.idle.swap(false, Ordering::Relaxed) {
+            if let Some(wakes) = &self.wakes {
+                let _ = wakes.send(unique_id.to_string());
+            }
+        }
+    }
+
+    /// Mark `unique_id` as reported idle if it had no activity for
+    /// `idle_after` seconds at `now`.
+    ///
+    /// Returns whether it was newly marked, i.e. should be reported.
+    pub fn mark_idle(&self, unique_id: &str, now: u64, idle_after: u64) -> bool {
+        self.entries.get(unique_id).is_some_and(|entry| {
+            now.saturating_sub(Self::effective(&entry, now)) >= idle_after
+                && !entry.idle.swap(true, Ordering::Relaxed)
+        })
+    }
+
+    /// Undo [`Self::mark_idle`], for devboxes whose report failed.
+    pub fn clear_idle(&self, unique_id: &str) {
+        if let Some(entry) = self.entries.get(unique_id) {
+            entry.idle.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Mark the start of a request to `port` of `unique_id`.
+    pub fn begin(&self, unique_id: &str, port: u16) -> ActivityGuard {
+        self.begin_at(unique_id, port, unix_now())
+    }
+
+    /// Unix seconds of the last activity of `unique_id`, or `now` while a
+    /// request is in progress.
+    pub fn last_seen(&self, unique_id: &str, now: u64) -> Option<u64> {
+        self.entries
+            .get(unique_id)
+            .map(|entry| Self::effective(&entry, now))
+    }
+
+    fn effective(entry: &Activity, now: u64) -> u64 {
+        if entry.open.load(Ordering::Relaxed) > 0 {
+            now
+        } else {
+            entry.last_seen.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Last activity of every tracked devbox, see [`Self::last_seen`].
+    pub fn snapshot(&self, now: u64) -> Vec<(String, u64)> {
+        self.entries
+            .iter()
+            .map(|entry| (entry.key().clone(), Self::effective(&entry, now)))
+            .collect()
+    }
+
+    /// Last activity of every tracked devbox together with the port of its
+    /// last request, see [`Self::last_seen`].
+    pub fn backends(&self, now: u64) -> Vec<(String, u16, u64)> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let port = entry.port.load(Ordering::Relaxed);
+                (entry.key().clone(), port, Self::effective(&entry, now))
+            })
+            .collect()
+    }
+
+    /// Seconds since the last request of every tracked devbox.
+    pub fn idle_seconds(&self, now: u64) -> BTreeMap<String, u64> {
+        self.snapshot(now)
+            .into_iter()
+            .map(|(unique_id, last_seen)| (unique_id, now.saturating_sub(last_seen)))
+            .collect()
+    }
+
+    /// Drop idle entries of devboxes that are no longer registered.
+    pub fn prune(&self, registry: &DevboxRegistry) {
+        self.entries.retain(|unique_id, entry| {
+            entry.open.load(Ordering::Relaxed) > 0 || registry.get_devbox(unique_id).is_some()
+        });
+    }
+}
+
+/// A request in progress, ended on drop.
+#[derive(Debug)]
+pub struct ActivityGuard(Arc<Activity>);
+
+impl Drop for ActivityGuard {
+    fn drop(&mut self) {
+        self.0.last_seen.store(unix_now(), Ordering::Relaxed);
+        self.0.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Records the last activity of a devbox on the API server.
+#[async_trait]
+pub trait ActivityPatcher: Send + Sync {
+    /// Set the last activity of Devbox `devbox_name` in `namespace` of
+    /// `cluster`.
+    async fn patch(
+        &self,
+        cluster: &str,
+        namespace: &str,
+        devbox_name: &str,
+        last_activity: u64,
+    ) -> Result<()>;
+}
+
+/// [`ActivityPatcher`] writing the [`ANNOTATION_LAST_ACTIVITY`] annotation.
+pub struct ApiActivityPatcher {
+    /// Client of each cluster, by name
+    clients: HashMap<String, Client>,
+}
+
+impl ApiActivityPatcher {
+    pub const fn new(clients: HashMap<String, Client>) -> Self {
+        Self { clients }
+    }
+}
+
+#[async_trait]
+impl ActivityPatcher for ApiActivityPatcher {
+    async fn patch(
+        &self,
+        cluster: &str,
+        namespace: &str,
+        devbox_name: &str,
+        last_activity: u64,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get(cluster)
+            .ok_or_else(|| Error::Config(format!("no client for cluster {cluster:?}")))?;
+        let devboxes: Api<Devbox> = Api::namespaced(client.clone(), namespace);
+        let patch = json!({
+            "metadata": {
+                "annotations": { ANNOTATION_LAST_ACTIVITY: last_activity.to_string() }
+            }
+        });
+        devboxes
+            .patch(devbox_name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Last patch sent for a devbox
+#[derive(Debug, Clone, Copy)]
+struct Reported {
+    /// When the patch was attempted
+    at: u64,
+    /// Activity it carried (if it succeeded)
+    last_activity: u64,
+}
+
+/// Periodically pushes devbox activity to the Devbox resources, so an
+/// auto-hibernation controller can find idle devboxes.
+///
+/// A devbox is patched only when it had activity since its last patch, and
+/// at most once per `interval`, whether or not the patch succeeded.
+pub struct ActivityReporter {
+    tracker: Arc<ActivityTracker>,
+    registry: Arc<DevboxRegistry>,
+    patcher: Box<dyn ActivityPatcher>,
+    interval: Duration,
+    reported: HashMap<String, Reported>,
+}
+
+impl ActivityReporter {
+    pub fn new(
+        tracker: Arc<ActivityTracker>,
+        registry: Arc<DevboxRegistry>,
+        patcher: Box<dyn ActivityPatcher>,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            tracker,
+            registry,
+            patcher,
+            interval,
+            reported: HashMap::new(),
+        }
+    }
+
+    /// Report every `interval` forever.
+    pub async fn run(mut self) {
+        info!(interval = ?self.interval, "Starting devbox activity reporting");
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            self.report(unix_now()).await;
+        }
+    }
+
+    /// Patch the devboxes that are due at `now`, returning how many were
+    /// patched successfully.
+    pub async fn report(&mut self, now: u64) -> usize {
+        self.tracker.prune(&self.registry);
+        let tracked = &self.tracker.entries;
+        self.reported
+            .retain(|unique_id, _| tracked.contains_key(unique_id));
+
+        let interval = self.interval.as_secs();
+        let mut patched = 0;
+        for (unique_id, last_activity) in self.tracker.snapshot(now) {
+            let previous = self.reported.get(&unique_id).copied();
+            if previous.is_some_and(|r| {
+                last_activity <= r.last_activity || now < r.at.saturating_add(interval)
+            }) {
+                continue;
+            }
+            let Some(info) = self.registry.get_devbox(&unique_id) else {
+                continue;
+            };
+
+            let reported = match self
+                .patcher
+                .patch(
+                    &info.cluster,
+                    &info.namespace,
+                    &info.devbox_name,
+                    last_activity,
+                )
+                .await
+            {
+                Ok(()) => {
+                    debug!(
+                        unique_id = %unique_id,
+                        last_activity = last_activity,
+                        "Reported devbox activity"
+                    );
+                    patched += 1;
+                    last_activity
+                }
+                Err(e) => {
+                    warn!(
+                        unique_id = %unique_id,
+                        cluster = %info.cluster,
+                        namespace = %info.namespace,
+                        devbox_name = %info.devbox_name,
+                        error = %e,
+                        "Failed to report devbox activity"
+                    );
+                    previous.map_or(0, |r| r.last_activity)
+                }
+            };
+            self.reported.insert(
+                unique_id,
+                Reported {
+                    at: now,
+                    last_activity: reported,
+                },
+            );
+        }
+        patched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const NOW: u64 = 1_700_000_000;
+
+    /// Fake API server recording patches.
+    #[derive(Default)]
+    struct FakePatcher {
+        patches: Mutex<Vec<(String, u64)>>,
+        fail: Mutex<bool>,
+    }
+
+    #[async_trait]
+    impl ActivityPatcher for Arc<FakePatcher> {
+        async fn patch(
+            &self,
+            _cluster: &str,
+            _namespace: &str,
+            devbox_name: &str,
+            last_activity: u64,
+        ) -> Result<()> {
+            if *self.fail.lock().unwrap() {
+                return Err(Error::Config("api server unavailable".to_string()));
+            }
+            self.patches
+                .lock()
+                .unwrap()
+                .push((devbox_name.to_string(), last_activity));
+            Ok(())
+        }
+    }
+
+    fn registry() -> Arc<DevboxRegistry> {
+        let registry = Arc::new(DevboxRegistry::new());
+        for (id, name) in [("app-a", "devbox-a"), ("app-b", "devbox-b")] {
+            registry.register_devbox(id.to_string(), "ns".to_string(), name.to_string());
+        }
+        registry
+    }
+
+    #[test]
+    fn test_last_seen() {
+        let tracker = ActivityTracker::new();
+        assert_eq!(tracker.last_seen("app-a", NOW), None);
+
+        drop(tracker.begin_at("app-a", 8080, NOW - 100));
+        // Ending the request is activity too
+        let ended = tracker.last_seen("app-a", NOW).unwrap();
+        assert!(ended >= unix_now() - 1);
+
+        // Requests never move the timestamp of other devboxes
+        let _guard = tracker.begin_at("app-b", 8080, NOW - 50);
+        assert_eq!(tracker.snapshot(NOW).len(), 2);
+        assert_eq!(tracker.last_seen("app-a", NOW), Some(ended));
+    }
+
+    #[test]
+    fn test_open_connection_counts_as_active() {
+        let tracker = ActivityTracker::new();
+        let websocket = tracker.begin_at("app-a", 8080, NOW - 3600);
+        let request = tracker.begin_at("app-a", 8080, NOW - 10);
+
+        // Active for as long as any connection is open, however long ago it started
+        assert_eq!(tracker.last_seen("app-a", NOW), Some(NOW));
+        assert_eq!(tracker.idle_seconds(NOW)["app-a"], 0);
+        drop(request);
+        assert_eq!(tracker.last_seen("app-a", NOW + 60), Some(NOW + 60));
+
+        drop(websocket);
+        let closed = tracker.last_seen("app-a", NOW + 120).unwrap();
+        assert!(closed >= unix_now() - 1);
+        assert_eq!(
+            tracker.idle_seconds(closed + 30)["app-a"],
+            30,
+            "idle since the connection closed"
+        );
+    }
+
+    #[test]
+    fn test_prune() {
+        let registry = registry();
+        let tracker = ActivityTracker::new();
+        drop(tracker.begin_at("app-a", 8080, NOW));
+        drop(tracker.begin_at("gone", 8080, NOW));
+        let _open = tracker.begin_at("gone-open", 8080, NOW);
+
+        tracker.prune(&registry);
+        let mut ids: Vec<_> = tracker
+            .snapshot(NOW)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["app-a", "gone-open"]);
+    }
+
+    #[tokio::test]
+    async fn test_report_rate_limited_per_devbox() {
+        let registry = registry();
+        let tracker = Arc::new(ActivityTracker::new());
+        let patcher = Arc::new(FakePatcher::default());
+        let mut reporter = ActivityReporter::new(
+            Arc::clone(&tracker),
+            registry,
+            Box::new(Arc::clone(&patcher)),
+            Duration::from_secs(60),
+        );
+
+        let _a = tracker.begin_at("app-a", 8080, NOW);
+        drop(tracker.begin_at("app-b", 8080, NOW));
+        let b_seen = tracker.last_seen("app-b", NOW).unwrap();
+        assert_eq!(reporter.report(NOW).await, 2);
+
+        // app-a is still active, but was patched less than an interval ago;
+        // app-b had no new activity
+        assert_eq!(reporter.report(NOW + 30).await, 0);
+        assert_eq!(reporter.report(NOW + 59).await, 0);
+
+        assert_eq!(reporter.report(NOW + 60).await, 1);
+        assert_eq!(reporter.report(NOW + 90).await, 0);
+
+        let mut patches = patcher.patches.lock().unwrap().clone();
+        patches.sort();
+        assert_eq!(
+            patches,
+            vec![
+                ("devbox-a".to_string(), NOW),
+                ("devbox-a".to_string(), NOW + 60),
+                ("devbox-b".to_string(), b_seen),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_report_failures_are_rate_limited() {
+        let tracker = Arc::new(ActivityTracker::new());
+        let patcher = Arc::new(FakePatcher::default());
+        let mut reporter = ActivityReporter::new(
+            Arc::clone(&tracker),
+            registry(),
+            Box::new(Arc::clone(&patcher)),
+            Duration::from_secs(60),
+        );
+
+        let _a = tracker.begin_at("app-a", 8080, NOW);
+        *patcher.fail.lock().unwrap() = true;
+        assert_eq!(reporter.report(NOW).await, 0);
+
+        // Not retried until the interval has passed
+        *patcher.fail.lock().unwrap() = false;
+        assert_eq!(reporter.report(NOW + 10).await, 0);
+        assert_eq!(reporter.report(NOW + 60).await, 1);
+        assert_eq!(
+            *patcher.patches.lock().unwrap(),
+            vec![("devbox-a".to_string(), NOW + 60)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_report_skips_unregistered_devboxes() {
+        let tracker = Arc::new(ActivityTracker::new());
+        let patcher = Arc::new(FakePatcher::default());
+        let mut reporter = ActivityReporter::new(
+            Arc::clone(&tracker),
+            registry(),
+            Box::new(Arc::clone(&patcher)),
+            Duration::from_secs(60),
+        );
+
+        let _open = tracker.begin_at("unknown-app", 8080, NOW);
+        assert_eq!(reporter.report(NOW).await, 0);
+        assert!(patcher.patches.lock().unwrap().is_empty());
+    }
+}
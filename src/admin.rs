@@ -0,0 +1,1274 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use http::{header, HeaderMap, Method, Response, StatusCode, Uri};
+use pingora_core::apps::http_app::{HttpServer, ServeHttp};
+use pingora_core::protocols::http::ServerSession;
+use pingora_core::services::listening::Service;
+use serde::Serialize;
+use serde_json::json;
+use tokio::net::TcpStream;
+use tracing::{debug, error, info};
+
+use crate::activity::ActivityTracker;
+use crate::blocklist::{BlockEntry, Blocklist};
+use crate::cache::ResponseCache;
+use crate::config::Config;
+#[cfg(feature = "debug-endpoints")]
+use crate::debug;
+use crate::metrics;
+use crate::preview::{self, PreviewSigner, TOKEN_QUERY_PARAM};
+use crate::registry::DevboxRegistry;
+use crate::routing::{resolve_backend, BackendResult};
+use crate::snapshot::{self, ImportMode};
+use crate::status::GatewayStatus;
+use crate::watcher::ResyncSignal;
+
+/// Time allowed for the warmup connect check
+const WARMUP_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default lifetime of minted preview tokens
+const DEFAULT_PREVIEW_TTL_SECS: u64 = 3600;
+
+/// Longest lifetime a preview token can be minted with
+const MAX_PREVIEW_TTL_SECS: u64 = 7 * 24 * 3600;
+
+/// Largest registry export accepted by `POST /registry/import`
+const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Operator-facing HTTP API, served on `ADMIN_ADDR`.
+///
+/// Routes:
+/// - `GET /healthz`: whether the watchers of every cluster have synced, and
+///   the registry's size against `MAX_REGISTRY_ENTRIES`
+/// - `GET /status`: the gateway's own health: watch states by cluster,
+///   registry size, configuration fingerprint, uptime and build
+/// - `GET /blocklist`: active blocklist entries with their blocked request counts
+/// - `POST /blocklist/reload`: re-read the blocklist file
+/// - `POST /registry/reload`: have the watchers list every Devbox and Pod
+///   again, replacing the registry's entries, for when it seems to have
+///   drifted from the clusters
+/// - `GET /registry/export`: every registry entry as a versioned JSON
+///   document (see [`snapshot::RegistryExport`])
+/// - `POST /registry/import[?mode=merge|replace]`: load an exported
+///   document, reporting the entries skipped as invalid; `replace` clears
+///   the registry first
+/// - `POST /warmup/{unique_id}/{port}[?connect=true]`: whether the devbox is
+///   ready to serve, optionally checking that its port accepts connections
+/// - `POST /preview/{unique_id}/{port}[?ttl=<secs>]`: mint a preview token
+///   (requires `SIGNING_KEY`)
+/// - `GET /activity`: seconds since the last request of each devbox
+/// - `GET /devboxes/{unique_id}`: a registered devbox, with its Pod IP and
+///   when it was last routed to
+/// - `GET /clusters`: devbox and pod counts and watch health of each cluster
+/// - `GET /config`: the effective configuration, with secrets redacted
+/// - `POST /cache/purge[/{unique_id}]`: drop the cached responses of a
+///   devbox, or all of them (requires `CACHE_MAX_BYTES`)
+///
+/// With the `debug-endpoints` build feature and `DEBUG_ENDPOINTS`, also
+/// these, which require `Authorization: Bearer <ADMIN_TOKEN>`:
+/// - `GET /debug/pprof/profile[?seconds=<secs>&format=pprof]`: a CPU
+///   profile as a flamegraph SVG or pprof protobuf
+/// - `GET /debug/heap`: allocator statistics (requires the `jemalloc` build
+///   feature)
+/// - `GET /debug/runtime`: Tokio runtime task and queue counters
+pub struct AdminApp {
+    registry: Arc<DevboxRegistry>,
+    blocklist: Arc<Blocklist>,
+    preview: Option<PreviewSigner>,
+    activity: Option<Arc<ActivityTracker>>,
+    cache: Option<Arc<ResponseCache>>,
+    config: Option<Arc<Config>>,
+    resync: Option<ResyncSignal>,
+    /// When the app was created, at startup
+    started: Instant,
+    /// Token required by the debug endpoints, which are off if unset
+    #[cfg(feature = "debug-endpoints")]
+    debug_token: Option<String>,
+}
+
+impl AdminApp {
+    pub fn new(registry: Arc<DevboxRegistry>, blocklist: Arc<Blocklist>) -> Self {
+        Self {
+            registry,
+            blocklist,
+            preview: None,
+            activity: None,
+            cache: None,
+            config: None,
+            resync: None,
+            started: Instant::now(),
+            #[cfg(feature = "debug-endpoints")]
+            debug_token: None,
+        }
+    }
+
+    /// Enable minting preview tokens signed by `signer`.
+    #[must_use]
+    pub fn with_preview_signer(mut self, signer: PreviewSigner) -> Self {
+        self.preview = Some(signer);
+        self
+    }
+
+    /// Report devbox activity recorded by `tracker`.
+    #[must_use]
+    pub fn with_activity_tracker(mut self, tracker: Arc<ActivityTracker>) -> Self {
+        self.activity = Some(tracker);
+        self
+    }
+
+    /// Purge responses from `cache`.
+    #[must_use]
+    pub fn with_response_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Serve `config` as the effective configuration.
+    #[must_use]
+    pub fn with_config(mut self, config: Arc<Config>) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Raise `signal` to reload the registry.
+    #[must_use]
+    pub fn with_resync(mut self, signal: ResyncSignal) -> Self {
+        self.resync = Some(signal);
+        self
+    }
+
+    /// Serve the debug endpoints to requests bearing `token`.
+    #[cfg(feature = "debug-endpoints")]
+    #[must_use]
+    pub fn with_debug_endpoints(mut self, token: String) -> Self {
+        self.debug_token = Some(token);
+        self
+    }
+
+    /// Wrap the app in a listening service; add addresses with `add_tcp`.
+    pub fn into_service(self) -> Service<HttpServer<Self>> {
+        Service::new("httpgate-admin".to_string(), HttpServer::new_app(self))
+    }
+
+    async fn handle(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Response<Vec<u8>> {
+        #[cfg(feature = "debug-endpoints")]
+        if let (Some(route), Some(token)) = (uri.path().strip_prefix("/debug/"), &self.debug_token)
+        {
+            if !debug::authorized(headers, token) {
+                return error_response(StatusCode::UNAUTHORIZED, "unauthorized");
+            }
+            if method != Method::GET {
+                return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+            }
+            return self.handle_debug(route, uri).await;
+        }
+        #[cfg(not(feature = "debug-endpoints"))]
+        let _ = headers;
+
+        if let Some(target) = uri.path().strip_prefix("/warmup/") {
+            if method != Method::POST {
+                return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+            }
+            let connect = matches!(query_param(uri, "connect"), Some("true" | "1"));
+            return self.warmup(target, connect).await;
+        }
+        if let Some(target) = uri.path().strip_prefix("/preview/") {
+            if method != Method::POST {
+                return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+            }
+            return self.mint_preview(target, query_param(uri, "ttl"));
+        }
+        if let Some(target) = uri.path().strip_prefix("/cache/purge") {
+            if method != Method::POST {
+                return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+            }
+            return self.purge_cache(target);
+        }
+        if let Some(unique_id) = uri.path().strip_prefix("/devboxes/") {
+            if method != Method::GET {
+                return error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+            }
+            return self.get_devbox(unique_id);
+        }
+
+        match (uri.path(), method) {
+            ("/healthz", &Method::GET) => self.get_healthz(),
+            ("/status", &Method::GET) => json_response(StatusCode::OK, &self.status()),
+            ("/blocklist", &Method::GET) => self.get_blocklist(),
+            ("/blocklist/reload", &Method::POST) => self.reload_blocklist(),
+            ("/registry/reload", &Method::POST) => self.reload_registry(),
+            ("/registry/export", &Method::GET) => {
+                json_response(StatusCode::OK, &snapshot::export(&self.registry))
+            }
+            ("/registry/import", &Method::POST) => self.import_registry(uri, body),
+            ("/activity", &Method::GET) => self.get_activity(),
+            ("/clusters", &Method::GET) => json_response(StatusCode::OK, &self.registry.clusters()),
+            ("/config", &Method::GET) => match &self.config {
+                Some(config) => json_response(StatusCode::OK, config.as_ref()),
+                None => error_response(StatusCode::NOT_FOUND, "configuration is not exposed"),
+            },
+            (
+                "/healthz" | "/status" | "/blocklist" | "/blocklist/reload" | "/registry/reload"
+                | "/registry/export" | "/registry/import" | "/activity" | "/clusters" | "/config",
+                _,
+            ) => error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"),
+            _ => error_response(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    /// Serve the debug endpoint `route` to an authorized request.
+    #[cfg(feature = "debug-endpoints")]
+    async fn handle_debug(&self, route: &str, uri: &Uri) -> Response<Vec<u8>> {
+        match route {
+            "pprof/profile" => {
+                let seconds = match query_param(uri, "seconds").map(str::parse) {
+                    None => debug::DEFAULT_PROFILE_SECS,
+                    Some(Ok(secs)) if (1..=debug::MAX_PROFILE_SECS).contains(&secs) => secs,
+                    Some(_) => {
+                        return error_response(
+                            StatusCode::BAD_REQUEST,
+                            &format!("seconds must be between 1 and {}", debug::MAX_PROFILE_SECS),
+                        )
+                    }
+                };
+                let Some(format) = query_param(uri, "format").map_or(
+                    Some(debug::ProfileFormat::Flamegraph),
+                    debug::ProfileFormat::parse,
+                ) else {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        "format must be flamegraph or pprof",
+                    );
+                };
+                info!(seconds, "Taking CPU profile");
+                match debug::cpu_profile(Duration::from_secs(seconds), format).await {
+                    Ok(body) => Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, format.content_type())
+                        .header(header::CONTENT_LENGTH, body.len())
+                        .body(body)
+                        .unwrap(),
+                    Err(e @ debug::ProfileError::Busy) => {
+                        error_response(StatusCode::CONFLICT, &e.to_string())
+                    }
+                    Err(e) => {
+                        error!(error = %e, "CPU profile failed");
+                        error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+                    }
+                }
+            }
+            #[cfg(feature = "jemalloc")]
+            "heap" => match debug::HeapStats::read() {
+                Ok(stats) => json_response(StatusCode::OK, &stats),
+                Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
+            },
+            #[cfg(not(feature = "jemalloc"))]
+            "heap" => error_response(
+                StatusCode::NOT_FOUND,
+                "heap stats need the jemalloc build feature",
+            ),
+            "runtime" => match debug::RuntimeStats::collect() {
+                Some(stats) => json_response(StatusCode::OK, &stats),
+                None => error_response(StatusCode::NOT_FOUND, "no Tokio runtime"),
+            },
+            _ => error_response(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    /// Resolve `{unique_id}/{port}` the way the proxy would.
+    ///
+    /// Responds 200 once requests would be routed (and, with `connect`, the
+    /// port accepts TCP connections), so callers can poll until ready.
+    async fn warmup(&self, target: &str, connect: bool) -> Response<Vec<u8>> {
+        let Some((unique_id, port)) = parse_target(target) else {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "expected /warmup/{unique_id}/{port}",
+            );
+        };
+
+        let mut devbox = None;
+        let (status, state, pod_ip) =
+            match resolve_backend(&self.registry, &self.blocklist, unique_id, port) {
+                BackendResult::Ok(endpoint, port, info) => {
+                    devbox = Some(info);
+                    if connect && !Self::accepts_connections(&endpoint.ip, port).await {
+                        (
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "unreachable",
+                            Some(endpoint.ip),
+                        )
+                    } else {
+                        (StatusCode::OK, "ready", Some(endpoint.ip))
+                    }
+                }
+                BackendResult::NotFound => (StatusCode::NOT_FOUND, "not_found", None),
+                BackendResult::NotRunning => {
+                    devbox = self.registry.get_devbox(unique_id);
+                    (StatusCode::SERVICE_UNAVAILABLE, "not_running", None)
+                }
+                BackendResult::InvalidAddress(_, info, _) => {
+                    devbox = Some(info);
+                    (StatusCode::SERVICE_UNAVAILABLE, "invalid_address", None)
+                }
+                BackendResult::Blocked(_) => (
+                    StatusCode::from_u16(self.blocklist.status).unwrap_or(StatusCode::FORBIDDEN),
+                    "blocked",
+                    None,
+                ),
+            };
+
+        json_response(
+            status,
+            &json!({
+                "unique_id": unique_id,
+                "port": port,
+                "state": state,
+                "ready": status == StatusCode::OK,
+                "cluster": devbox.as_ref().map(|d| &*d.cluster),
+                "namespace": devbox.as_ref().map(|d| &d.namespace),
+                "devbox_name": devbox.as_ref().map(|d| &d.devbox_name),
+                "pod_ip": pod_ip,
+            }),
+        )
+    }
+
+    /// Mint a preview token for `{unique_id}/{port}`.
+    fn mint_preview(&self, target: &str, ttl: Option<&str>) -> Response<Vec<u8>> {
+        let Some(signer) = &self.preview else {
+            return error_response(StatusCode::NOT_FOUND, "preview links are disabled");
+        };
+        let Some((unique_id, port)) = parse_target(target) else {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "expected /preview/{unique_id}/{port}",
+            );
+        };
+        let ttl = match ttl.map(str::parse::<u64>) {
+            None => DEFAULT_PREVIEW_TTL_SECS,
+            Some(Ok(ttl)) if (1..=MAX_PREVIEW_TTL_SECS).contains(&ttl) => ttl,
+            Some(_) => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!("ttl must be between 1 and {MAX_PREVIEW_TTL_SECS} seconds"),
+                );
+            }
+        };
+        if self.registry.get_devbox(unique_id).is_none() {
+            return error_response(StatusCode::NOT_FOUND, "devbox not found");
+        }
+
+        let expires_at = preview::unix_now() + ttl;
+        let token = signer.mint(unique_id, port, expires_at);
+        info!(
+            unique_id = %unique_id,
+            port = port,
+            expires_at = expires_at,
+            "Preview token minted"
+        );
+        json_response(
+            StatusCode::OK,
+            &json!({
+                "unique_id": unique_id,
+                "port": port,
+                "token": token,
+                "query": format!("{TOKEN_QUERY_PARAM}={token}"),
+                "expires_at": expires_at,
+            }),
+        )
+    }
+
+    /// Drop cached responses: those of `/{unique_id}`, or all for an empty
+    /// target.
+    fn purge_cache(&self, target: &str) -> Response<Vec<u8>> {
+        let Some(cache) = &self.cache else {
+            return error_response(StatusCode::NOT_FOUND, "response cache is disabled");
+        };
+        let purged = match target {
+            "" | "/" => cache.purge_all(),
+            target => match target.strip_prefix('/').filter(|id| !id.contains('/')) {
+                Some(unique_id) => cache.purge(unique_id),
+                None => {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        "expected /cache/purge[/{unique_id}]",
+                    );
+                }
+            },
+        };
+        info!(target = %target, purged = purged, "Response cache purged via admin API");
+        json_response(StatusCode::OK, &json!({ "purged": purged }))
+    }
+
+    /// Snapshot of the gateway's own health.
+    fn status(&self) -> GatewayStatus {
+        GatewayStatus::collect(
+            &self.registry,
+            self.config.as_deref(),
+            self.started.elapsed(),
+            preview::unix_now(),
+        )
+    }
+
+    /// Readiness: the registry holds every cluster's devboxes and Pods.
+    fn get_healthz(&self) -> Response<Vec<u8>> {
+        let GatewayStatus {
+            ready, registry, ..
+        } = self.status();
+        let status = if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        json_response(status, &json!({ "ready": ready, "registry": registry }))
+    }
+
+    /// Seconds since the last request of each registered devbox; devboxes
+    /// with a request or WebSocket connection in progress report 0.
+    fn get_activity(&self) -> Response<Vec<u8>> {
+        let Some(tracker) = &self.activity else {
+            return error_response(StatusCode::NOT_FOUND, "activity tracking is disabled");
+        };
+        let mut idle = tracker.idle_seconds(preview::unix_now());
+        idle.retain(|unique_id, _| self.registry.get_devbox(unique_id).is_some());
+        json_response(StatusCode::OK, &idle)
+    }
+
+    /// A registered devbox and when it was last routed to (Unix seconds,
+    /// `null` if not since it was registered), for scale-to-zero tooling.
+    fn get_devbox(&self, unique_id: &str) -> Response<Vec<u8>> {
+        let Some(devbox) = self.registry.get_devbox(unique_id) else {
+            return error_response(StatusCode::NOT_FOUND, "devbox not found");
+        };
+        let endpoint =
+            self.registry
+                .get_pod_endpoint(&devbox.cluster, &devbox.namespace, &devbox.devbox_name);
+        json_response(
+            StatusCode::OK,
+            &json!({
+                "unique_id": unique_id,
+                "cluster": &*devbox.cluster,
+                "namespace": devbox.namespace,
+                "devbox_name": devbox.devbox_name,
+                "app_port": devbox.app_port,
+                "source": devbox.source,
+                "pod_ip": endpoint.map(|e| e.ip),
+                "last_access": devbox.last_access(),
+            }),
+        )
+    }
+
+    async fn accepts_connections(ip: &str, port: u16) -> bool {
+        match tokio::time::timeout(WARMUP_CONNECT_TIMEOUT, TcpStream::connect((ip, port))).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => {
+                debug!(pod_ip = %ip, port = port, error = %e, "Warmup connect failed");
+                false
+            }
+            Err(_) => {
+                debug!(pod_ip = %ip, port = port, "Warmup connect timed out");
+                false
+            }
+        }
+    }
+
+    fn get_blocklist(&self) -> Response<Vec<u8>> {
+        #[derive(Serialize)]
+        struct Entry {
+            #[serde(flatten)]
+            entry: BlockEntry,
+            blocked_requests: u64,
+        }
+
+        let entries: Vec<Entry> = self
+            .blocklist
+            .snapshot()
+            .entries()
+            .into_iter()
+            .map(|entry| Entry {
+                blocked_requests: metrics::BLOCKED_REQUESTS_TOTAL
+                    .with_label_values(&[&entry.to_string()])
+                    .get(),
+                entry,
+            })
+            .collect();
+
+        json_response(
+            StatusCode::OK,
+            &json!({
+                "status": self.blocklist.status,
+                "message": self.blocklist.message,
+                "entries": entries,
+            }),
+        )
+    }
+
+    /// Ask the watchers to relist; the registry is replaced as each one's
+    /// initial list completes, which `/clusters` reports.
+    fn reload_registry(&self) -> Response<Vec<u8>> {
+        let Some(signal) = &self.resync else {
+            return error_response(StatusCode::NOT_FOUND, "registry reload is not available");
+        };
+        let watchers = signal.request();
+        info!(watchers, "Registry reload requested via admin API");
+        json_response(StatusCode::ACCEPTED, &json!({ "watchers": watchers }))
+    }
+
+    fn import_registry(&self, uri: &Uri, body: &[u8]) -> Response<Vec<u8>> {
+        let mode = match query_param(uri, "mode").map(str::parse::<ImportMode>) {
+            None => ImportMode::default(),
+            Some(Ok(mode)) => mode,
+            Some(Err(e)) => return error_response(StatusCode::BAD_REQUEST, &e),
+        };
+        match snapshot::parse(body) {
+            Ok(export) => json_response(
+                StatusCode::OK,
+                &snapshot::import(&self.registry, &export, mode),
+            ),
+            Err(e) => error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+        }
+    }
+
+    fn reload_blocklist(&self) -> Response<Vec<u8>> {
+        match self.blocklist.reload() {
+            Ok(()) => {
+                info!("Blocklist reloaded via admin API");
+                self.get_blocklist()
+            }
+            Err(e) => {
+                error!(error = %e, "Blocklist reload failed");
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ServeHttp for AdminApp {
+    async fn response(&self, http_session: &mut ServerSession) -> Response<Vec<u8>> {
+        // Only imports have a body worth reading
+        let body = if http_session.req_header().uri.path() == "/registry/import" {
+            match read_body(http_session, MAX_IMPORT_BYTES).await {
+                Ok(body) => body,
+                Err(resp) => return resp,
+            }
+        } else {
+            Vec::new()
+        };
+        let req = http_session.req_header();
+        self.handle(&req.method, &req.uri, &req.headers, &body)
+            .await
+    }
+}
+
+/// Read the request body, refusing bodies over `max` bytes.
+async fn read_body(session: &mut ServerSession, max: usize) -> Result<Vec<u8>, Response<Vec<u8>>> {
+    let mut body = Vec::new();
+    loop {
+        match session.read_request_body().await {
+            Ok(Some(chunk)) if body.len() + chunk.len() > max => {
+                return Err(error_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "request body too large",
+                ));
+            }
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            Ok(None) => return Ok(body),
+            Err(e) => {
+                return Err(error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!("failed to read request body: {e}"),
+                ));
+            }
+        }
+    }
+}
+
+/// Parse a `{unique_id}/{port}` route target.
+fn parse_target(target: &str) -> Option<(&str, u16)> {
+    let (unique_id, port) = target.split_once('/')?;
+    let port = port.parse().ok()?;
+    (!unique_id.is_empty()).then_some((unique_id, port))
+}
+
+/// Value of a query string parameter.
+fn query_param<'a>(uri: &'a Uri, name: &str) -> Option<&'a str> {
+    uri.query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Vec<u8>> {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONTENT_LENGTH, body.len())
+        .body(body)
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Vec<u8>> {
+    json_response(status, &json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ListenerTls, RetryPolicy, REDACTED};
+    use crate::registry::{WatchKind, DEFAULT_CLUSTER, DEFAULT_MAX_ENTRIES};
+
+    fn app(config: &Config) -> AdminApp {
+        // Warmup checks connect to local listeners
+        AdminApp::new(
+            Arc::new(DevboxRegistry::new().with_loopback_backends(true)),
+            Arc::new(Blocklist::from_config(config)),
+        )
+    }
+
+    async fn request(app: &AdminApp, method: Method, uri: &str) -> Response<Vec<u8>> {
+        request_with_body(app, method, uri, b"").await
+    }
+
+    async fn request_with_body(
+        app: &AdminApp,
+        method: Method,
+        uri: &str,
+        body: &[u8],
+    ) -> Response<Vec<u8>> {
+        app.handle(&method, &uri.parse().unwrap(), &HeaderMap::new(), body)
+            .await
+    }
+
+    fn body(resp: &Response<Vec<u8>>) -> serde_json::Value {
+        serde_json::from_slice(resp.body()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_blocklist() {
+        let config = Config {
+            blocked_unique_ids: vec!["admin-test-app".to_string()],
+            blocked_namespaces: vec!["admin-test-ns".to_string()],
+            blocked_status: 451,
+            ..Default::default()
+        };
+        let app = app(&config);
+        app.blocklist.check("admin-test-app", None);
+        app.blocklist.check("admin-test-app", None);
+
+        let resp = request(&app, Method::GET, "/blocklist").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            body(&resp),
+            json!({
+                "status": 451,
+                "message": config.blocked_message,
+                "entries": [
+                    {"kind": "unique_id", "value": "admin-test-app", "blocked_requests": 2},
+                    {"kind": "namespace", "value": "admin-test-ns", "blocked_requests": 0},
+                ],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reload_blocklist() {
+        let path = std::env::temp_dir().join(format!("httpgate-admin-{}", std::process::id()));
+        std::fs::write(&path, "namespace:admin-reload-ns\n").unwrap();
+        let app = app(&Config {
+            blocklist_file: Some(path.to_str().unwrap().to_string()),
+            ..Default::default()
+        });
+
+        let resp = request(&app, Method::POST, "/blocklist/reload").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(body(&resp)["entries"][0]["value"], "admin-reload-ns");
+
+        std::fs::remove_file(&path).unwrap();
+        let resp = request(&app, Method::POST, "/blocklist/reload").await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        // The previous entries stay active
+        assert!(app
+            .blocklist
+            .check("app", Some("admin-reload-ns"))
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_reload_registry() {
+        let resp = request(&app(&Config::default()), Method::POST, "/registry/reload").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let signal = ResyncSignal::default();
+        let mut listener = signal.subscribe();
+        let app = app(&Config::default()).with_resync(signal);
+        let resp = request(&app, Method::POST, "/registry/reload").await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        assert_eq!(body(&resp), json!({ "watchers": 1 }));
+        // The watcher sees the request
+        listener.requested().await;
+
+        assert_eq!(
+            request(&app, Method::GET, "/registry/reload")
+                .await
+                .status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_registry_export_import() {
+        let source = app(&Config::default());
+        source.registry.register_devbox(
+            "admin-export".to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+        source
+            .registry
+            .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+            .unwrap();
+        let resp = request(&source, Method::GET, "/registry/export").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let export = body(&resp);
+        assert_eq!(export["version"], 1);
+        assert_eq!(export["devboxes"][0]["unique_id"], "admin-export");
+        assert_eq!(export["pod_ips"][0]["ip"], "127.0.0.1");
+
+        let target = app(&Config::default());
+        target.registry.register_devbox(
+            "stale".to_string(),
+            "ns-test".to_string(),
+            "devbox2".to_string(),
+        );
+        let resp = request_with_body(
+            &target,
+            Method::POST,
+            "/registry/import?mode=replace",
+            resp.body(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            body(&resp),
+            json!({ "devboxes": 1, "pod_ips": 1, "errors": [] })
+        );
+        assert!(target.registry.get_devbox("stale").is_none());
+        let resp = request(&target, Method::POST, "/warmup/admin-export/8080").await;
+        assert_eq!(body(&resp)["state"], "ready");
+
+        // Documents that can't be imported change nothing
+        for (uri, document) in [
+            ("/registry/import", &b"{\"version\": 7}"[..]),
+            ("/registry/import", b"{\"devboxes\": []}"),
+            ("/registry/import?mode=upsert", b"{\"version\": 1}"),
+        ] {
+            let resp = request_with_body(&target, Method::POST, uri, document).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+        assert_eq!(target.registry.devbox_count(), 1);
+
+        assert_eq!(
+            request(&target, Method::POST, "/registry/export")
+                .await
+                .status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_routes() {
+        let app = app(&Config::default());
+        assert_eq!(
+            request(&app, Method::GET, "/nope").await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            request(&app, Method::GET, "/blocklist/reload")
+                .await
+                .status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            request(&app, Method::GET, "/warmup/my-app/8080")
+                .await
+                .status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        for uri in ["/warmup/my-app", "/warmup/my-app/http", "/warmup//8080"] {
+            assert_eq!(
+                request(&app, Method::POST, uri).await.status(),
+                StatusCode::BAD_REQUEST,
+                "{uri}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warmup() {
+        let app = app(&Config {
+            blocked_unique_ids: vec!["warmup-blocked".to_string()],
+            ..Default::default()
+        });
+        app.registry.register_devbox(
+            "warmup-app".to_string(),
+            "ns-warmup".to_string(),
+            "devbox1".to_string(),
+        );
+
+        let resp = request(&app, Method::POST, "/warmup/warmup-app/8080").await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body(&resp)["state"], "not_running");
+        assert_eq!(body(&resp)["ready"], false);
+        assert_eq!(body(&resp)["devbox_name"], "devbox1");
+
+        app.registry
+            .update_pod_ip("ns-warmup", "devbox1", "127.0.0.1".to_string())
+            .unwrap();
+        let resp = request(&app, Method::POST, "/warmup/warmup-app/8080").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            body(&resp),
+            json!({
+                "unique_id": "warmup-app",
+                "port": 8080,
+                "state": "ready",
+                "ready": true,
+                "cluster": "default",
+                "namespace": "ns-warmup",
+                "devbox_name": "devbox1",
+                "pod_ip": "127.0.0.1",
+            })
+        );
+
+        let resp = request(&app, Method::POST, "/warmup/unknown-app/8080").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body(&resp)["state"], "not_found");
+
+        let resp = request(&app, Method::POST, "/warmup/warmup-blocked/8080").await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(body(&resp)["state"], "blocked");
+    }
+
+    #[tokio::test]
+    async fn test_healthz() {
+        let app = app(&Config::default());
+        app.registry.add_cluster(DEFAULT_CLUSTER);
+        let resp = request(&app, Method::GET, "/healthz").await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body(&resp)["ready"], false);
+
+        for kind in [WatchKind::Devboxes, WatchKind::Pods] {
+            app.registry.record_watch_synced(DEFAULT_CLUSTER, kind);
+        }
+        let resp = request(&app, Method::GET, "/healthz").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(body(&resp)["ready"], true);
+    }
+
+    #[tokio::test]
+    async fn test_healthz_registry_usage() {
+        let app = app(&Config::default());
+        app.registry.register_devbox(
+            "usage-app".to_string(),
+            "ns-usage".to_string(),
+            "devbox1".to_string(),
+        );
+        let resp = request(&app, Method::GET, "/healthz").await;
+        let registry = &body(&resp)["registry"];
+        assert_eq!(registry["entries"], 1);
+        assert_eq!(registry["max_entries"], DEFAULT_MAX_ENTRIES);
+        assert_eq!(registry["full"], false);
+        assert!(registry["approx_memory_bytes"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_status() {
+        let admin = app(&Config::default()).with_config(Arc::default());
+        admin.registry.add_cluster(DEFAULT_CLUSTER);
+        admin
+            .registry
+            .record_watch_synced(DEFAULT_CLUSTER, WatchKind::Devboxes);
+        admin
+            .registry
+            .record_watch_synced(DEFAULT_CLUSTER, WatchKind::Pods);
+        admin
+            .registry
+            .record_watch_error(DEFAULT_CLUSTER, WatchKind::Pods, "connection refused");
+
+        // Stale, but still serving: ready all the same
+        let resp = request(&admin, Method::GET, "/status").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let status = body(&resp);
+        assert_eq!(status["ready"], true);
+        assert_eq!(status["watchers"]["default"]["devboxes"]["state"], "ok");
+        assert_eq!(status["watchers"]["default"]["pods"]["state"], "stale");
+        assert!(status["config_fingerprint"].is_string());
+        assert_eq!(
+            request(&admin, Method::GET, "/healthz").await.status(),
+            StatusCode::OK
+        );
+
+        // Without the configuration there is nothing to fingerprint
+        let resp = request(&app(&Config::default()), Method::GET, "/status").await;
+        assert_eq!(body(&resp)["config_fingerprint"], json!(null));
+    }
+
+    #[tokio::test]
+    async fn test_get_clusters() {
+        let app = app(&Config::default());
+        app.registry.add_cluster("west");
+        app.registry.register_devbox(
+            "clusters-app".to_string(),
+            "ns-clusters".to_string(),
+            "devbox1".to_string(),
+        );
+        app.registry
+            .update_pod_ip("ns-clusters", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        app.registry
+            .record_watch_synced(DEFAULT_CLUSTER, WatchKind::Devboxes);
+        app.registry
+            .record_watch_error("west", WatchKind::Pods, "connection refused");
+
+        let resp = request(&app, Method::GET, "/clusters").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let clusters = body(&resp);
+        assert_eq!(clusters["default"]["devboxes"], 1);
+        assert_eq!(clusters["default"]["pod_ips"], 1);
+        assert_eq!(clusters["default"]["devbox_watch"]["synced"], true);
+        assert_eq!(clusters["default"]["pod_watch"]["synced"], false);
+        assert_eq!(clusters["west"]["devboxes"], 0);
+        assert_eq!(clusters["west"]["pod_watch"]["failing"], true);
+        assert_eq!(
+            clusters["west"]["pod_watch"]["last_error"],
+            "connection refused"
+        );
+
+        assert_eq!(
+            request(&app, Method::POST, "/clusters").await.status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_warmup_connect_check() {
+        let app = app(&Config::default());
+        app.registry.register_devbox(
+            "warmup-connect".to_string(),
+            "ns-warmup".to_string(),
+            "devbox1".to_string(),
+        );
+        app.registry
+            .update_pod_ip("ns-warmup", "devbox1", "127.0.0.1".to_string())
+            .unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let uri = format!("/warmup/warmup-connect/{port}?connect=true");
+
+        let resp = request(&app, Method::POST, &uri).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(body(&resp)["state"], "ready");
+
+        // Nothing listening on the port any more
+        drop(listener);
+        let resp = request(&app, Method::POST, &uri).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body(&resp)["state"], "unreachable");
+        assert_eq!(body(&resp)["pod_ip"], "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_mint_preview() {
+        let app = app(&Config::default()).with_preview_signer(PreviewSigner::new("admin-key"));
+        app.registry.register_devbox(
+            "preview-app".to_string(),
+            "ns-preview".to_string(),
+            "devbox1".to_string(),
+        );
+
+        let resp = request(&app, Method::POST, "/preview/preview-app/8080?ttl=60").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = body(&resp);
+        let expires_at = body["expires_at"].as_u64().unwrap();
+        assert!(expires_at > preview::unix_now());
+        assert!(expires_at <= preview::unix_now() + 60);
+
+        // The token is valid for exactly this devbox and port
+        let token = body["token"].as_str().unwrap();
+        assert_eq!(body["query"], format!("hg_token={token}"));
+        let signer = PreviewSigner::new("admin-key");
+        let now = preview::unix_now();
+        assert!(signer.verify(token, "preview-app", 8080, now).is_ok());
+        assert!(signer.verify(token, "preview-app", 8081, now).is_err());
+
+        for (uri, status) in [
+            ("/preview/unknown-app/8080", StatusCode::NOT_FOUND),
+            ("/preview/preview-app/8080?ttl=0", StatusCode::BAD_REQUEST),
+            (
+                "/preview/preview-app/8080?ttl=9999999",
+                StatusCode::BAD_REQUEST,
+            ),
+            ("/preview/preview-app", StatusCode::BAD_REQUEST),
+        ] {
+            assert_eq!(
+                request(&app, Method::POST, uri).await.status(),
+                status,
+                "{uri}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mint_preview_disabled() {
+        let app = app(&Config::default());
+        let resp = request(&app, Method::POST, "/preview/preview-app/8080").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_activity() {
+        let tracker = Arc::new(ActivityTracker::new());
+        let app = app(&Config::default()).with_activity_tracker(Arc::clone(&tracker));
+        for id in ["idle-app", "busy-app"] {
+            app.registry
+                .register_devbox(id.to_string(), "ns".to_string(), id.to_string());
+        }
+
+        let now = preview::unix_now();
+        drop(tracker.begin_at("idle-app", 8080, now - 120));
+        let _websocket = tracker.begin_at("busy-app", 8080, now - 3600);
+        let _unregistered = tracker.begin_at("unknown-app", 8080, now);
+
+        let resp = request(&app, Method::GET, "/activity").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = body(&resp);
+        assert_eq!(body["busy-app"], 0);
+        assert!(body["idle-app"].as_u64().unwrap() <= 1);
+        assert!(body.get("unknown-app").is_none());
+
+        assert_eq!(
+            request(&app, Method::POST, "/activity").await.status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_devbox() {
+        let app = app(&Config::default());
+        app.registry.register_devbox(
+            "my-app".to_string(),
+            "ns".to_string(),
+            "devbox1".to_string(),
+        );
+        app.registry
+            .update_pod_ip("ns", "devbox1", "10.0.0.5".to_string())
+            .unwrap();
+
+        let resp = request(&app, Method::GET, "/devboxes/my-app").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            body(&resp),
+            json!({
+                "unique_id": "my-app",
+                "cluster": "default",
+                "namespace": "ns",
+                "devbox_name": "devbox1",
+                "app_port": null,
+                "source": "watch",
+                "pod_ip": "10.0.0.5",
+                "last_access": null,
+            })
+        );
+
+        app.registry
+            .get_devbox("my-app")
+            .unwrap()
+            .touch(1_700_000_000);
+        let resp = request(&app, Method::GET, "/devboxes/my-app").await;
+        assert_eq!(body(&resp)["last_access"], 1_700_000_000);
+
+        let resp = request(&app, Method::GET, "/devboxes/unknown-app").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            request(&app, Method::DELETE, "/devboxes/my-app")
+                .await
+                .status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_activity_disabled() {
+        let app = app(&Config::default());
+        let resp = request(&app, Method::GET, "/activity").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_purge_cache() {
+        let cache = Arc::new(ResponseCache::new(1024 * 1024, 1024));
+        let app = app(&Config::default()).with_response_cache(Arc::clone(&cache));
+        let resp_headers = http::HeaderMap::from_iter([(
+            header::CACHE_CONTROL,
+            http::HeaderValue::from_static("public, max-age=60"),
+        )]);
+        let devbox = crate::registry::DevboxInfo::new("ns".to_string(), "devbox1".to_string());
+        for (unique_id, target) in [("app", "/a.js"), ("app", "/b.js"), ("other", "/a.js")] {
+            let key = crate::cache::CacheKey::new(unique_id, 3000, &target.parse().unwrap());
+            let mut fill = cache
+                .start_fill(
+                    key,
+                    1,
+                    &http::HeaderMap::new(),
+                    StatusCode::OK,
+                    &resp_headers,
+                )
+                .unwrap();
+            fill.push(b"body");
+            cache.finish_fill(fill, &devbox);
+        }
+
+        let resp = request(&app, Method::POST, "/cache/purge/app").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(body(&resp)["purged"], 2);
+        assert_eq!(cache.response_count(), 1);
+
+        let resp = request(&app, Method::POST, "/cache/purge").await;
+        assert_eq!(body(&resp)["purged"], 1);
+        assert_eq!(cache.response_count(), 0);
+
+        assert_eq!(
+            request(&app, Method::POST, "/cache/purge/a/b")
+                .await
+                .status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            request(&app, Method::GET, "/cache/purge").await.status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_purge_cache_disabled() {
+        let app = app(&Config::default());
+        let resp = request(&app, Method::POST, "/cache/purge").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_config() {
+        let mut config = Config {
+            host_pattern: Some(r"^(.+)-(\d+)\.example\.com$".parse().unwrap()),
+            signing_key: Some("hunter2".to_string()),
+            internal_cidrs: vec!["10.0.0.0/8".parse().unwrap()],
+            upstream_retry_policy: RetryPolicy::IdempotencyKey,
+            tarpit_header_delay: Duration::from_millis(1500),
+            ..Default::default()
+        };
+        config.listeners[0].tls = Some(ListenerTls {
+            cert_path: "/etc/tls/tls.crt".to_string(),
+            key_path: "/etc/tls/tls.key".to_string(),
+        });
+        let admin = app(&Config::default()).with_config(Arc::new(config));
+
+        let resp = request(&admin, Method::GET, "/config").await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let config = body(&resp);
+        assert_eq!(config["host_pattern"], r"^(.+)-(\d+)\.example\.com$");
+        assert_eq!(config["internal_cidrs"], json!(["10.0.0.0/8"]));
+        assert_eq!(config["upstream_retry_policy"], "idempotency-key");
+        assert_eq!(config["tarpit_header_delay"], 1.5);
+        assert_eq!(config["slow_request_threshold"], json!(null));
+        assert_eq!(
+            config["listeners"][0]["tls"]["cert_path"],
+            "/etc/tls/tls.crt"
+        );
+
+        // Secrets never leave the gateway
+        assert_eq!(config["signing_key"], REDACTED);
+        assert_eq!(config["listeners"][0]["tls"]["key_path"], REDACTED);
+        assert!(!String::from_utf8_lossy(resp.body()).contains("hunter2"));
+        assert!(!String::from_utf8_lossy(resp.body()).contains("tls.key"));
+
+        // Unset secrets stay null
+        let admin = app(&Config::default()).with_config(Arc::default());
+        let resp = request(&admin, Method::GET, "/config").await;
+        assert_eq!(body(&resp)["signing_key"], json!(null));
+    }
+
+    #[tokio::test]
+    async fn test_get_config_disabled() {
+        let app = app(&Config::default());
+        let resp = request(&app, Method::GET, "/config").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(feature = "debug-endpoints")]
+    #[tokio::test]
+    async fn test_debug_endpoints() {
+        async fn debug_request(
+            app: &AdminApp,
+            method: Method,
+            uri: &str,
+            token: Option<&str>,
+        ) -> Response<Vec<u8>> {
+            let mut headers = HeaderMap::new();
+            if let Some(token) = token {
+                headers.insert(
+                    header::AUTHORIZATION,
+                    format!("Bearer {token}").parse().unwrap(),
+                );
+            }
+            app.handle(&method, &uri.parse().unwrap(), &headers, b"")
+                .await
+        }
+
+        // Not served unless enabled
+        let disabled = app(&Config::default());
+        let resp = debug_request(&disabled, Method::GET, "/debug/runtime", Some("s3cret")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let app = app(&Config::default()).with_debug_endpoints("s3cret".to_string());
+        for uri in [
+            "/debug/runtime",
+            "/debug/heap",
+            "/debug/pprof/profile",
+            "/debug/nope",
+        ] {
+            let resp = debug_request(&app, Method::GET, uri, None).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{uri}");
+            let resp = debug_request(&app, Method::GET, uri, Some("wrong")).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{uri}");
+        }
+
+        let resp = debug_request(&app, Method::GET, "/debug/runtime", Some("s3cret")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(body(&resp)["workers"], 1);
+
+        let resp = debug_request(&app, Method::POST, "/debug/runtime", Some("s3cret")).await;
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let resp = debug_request(&app, Method::GET, "/debug/nope", Some("s3cret")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Bad parameters are refused before profiling starts
+        for uri in [
+            "/debug/pprof/profile?seconds=0",
+            "/debug/pprof/profile?seconds=3600",
+            "/debug/pprof/profile?format=json",
+        ] {
+            let resp = debug_request(&app, Method::GET, uri, Some("s3cret")).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+}
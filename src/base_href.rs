@@ -0,0 +1,299 @@
+use bytes::Bytes;
+use http::header::{ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG};
+use http::StatusCode;
+use pingora_http::ResponseHeader;
+
+/// Bytes at the start of a document searched for its `<head>` tag; the rest
+/// of documents without one by then is passed through unchanged
+pub const MAX_SEARCH_BYTES: usize = 64 * 1024;
+
+/// Whether a `<base href>` can be injected into a response: a complete,
+/// uncompressed HTML document.
+pub fn applies(resp: &ResponseHeader) -> bool {
+    if resp.status != StatusCode::OK {
+        return false;
+    }
+    let is_html = resp
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or(v).trim())
+        .is_some_and(|media_type| media_type.eq_ignore_ascii_case("text/html"));
+    let encoded = resp
+        .headers
+        .get(CONTENT_ENCODING)
+        .is_some_and(|v| !v.as_bytes().eq_ignore_ascii_case(b"identity"));
+    is_html && !encoded
+}
+
+/// Adjust the headers of a response whose body gets a `<base href>`: its
+/// length changes, so it is sent without `Content-Length`, ranges of the
+/// backend's body no longer apply and its `ETag` becomes weak.
+pub fn prepare(resp: &mut ResponseHeader) -> pingora_core::Result<()> {
+    resp.remove_header(&CONTENT_LENGTH);
+    resp.remove_header(&ACCEPT_RANGES);
+    let strong = resp
+        .headers
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| v.starts_with('"'))
+        .map(|v| format!("W/{v}"));
+    if let Some(weak) = strong {
+        resp.insert_header(ETAG, weak)?;
+    }
+    Ok(())
+}
+
+/// Inserts a `<base href>` tag right after the `<head>` tag of an HTML body
+/// streamed in chunks.
+///
+/// A `<head>` tag split across chunks is held back until it is complete, so
+/// the tag always lands after it; a document without one in its first
+/// [`MAX_SEARCH_BYTES`] is left as it is.
+#[derive(Debug)]
+pub struct BaseHrefInjector {
+    tag: Bytes,
+    /// Bytes held back, from the start of what may be the `<head>` tag
+    pending: Vec<u8>,
+    /// Bytes of the body passed on or held back so far
+    searched: usize,
+    /// Whether the body is passed through from here on
+    done: bool,
+    injected: bool,
+}
+
+impl BaseHrefInjector {
+    /// Inject `<base href="{href}">`. `href` must not contain quotes or
+    /// angle brackets (see `DevboxPolicy::base_href`).
+    pub fn new(href: &str) -> Self {
+        let href = href.replace('&', "&amp;");
+        Self {
+            tag: Bytes::from(format!("<base href=\"{href}\">")),
+            pending: Vec::new(),
+            searched: 0,
+            done: false,
+            injected: false,
+        }
+    }
+
+    /// Whether the tag was injected
+    pub fn injected(&self) -> bool {
+        self.injected
+    }
+
+    /// Rewrite the next `body` chunk in place, flushing anything held back
+    /// at `end_of_stream`. A chunk held back entirely becomes `None`.
+    pub fn filter(&mut self, body: &mut Option<Bytes>, end_of_stream: bool) {
+        if self.done {
+            return;
+        }
+        let chunk = body.take().unwrap_or_default();
+        let mut buf = std::mem::take(&mut self.pending);
+        buf.extend_from_slice(&chunk);
+        // Past the point a `<head>` tag can be expected, give up
+        let search = if self.searched < MAX_SEARCH_BYTES {
+            find_head(&buf)
+        } else {
+            Search::NotFound
+        };
+        self.searched += chunk.len();
+
+        match search {
+            Search::Found(end) => {
+                self.done = true;
+                self.injected = true;
+                let mut out = Vec::with_capacity(buf.len() + self.tag.len());
+                out.extend_from_slice(&buf[..end]);
+                out.extend_from_slice(&self.tag);
+                out.extend_from_slice(&buf[end..]);
+                buf = out;
+            }
+            Search::Partial(start) if !end_of_stream => {
+                self.pending = buf.split_off(start);
+            }
+            Search::Partial(_) | Search::NotFound => {
+                self.done = end_of_stream || self.searched >= MAX_SEARCH_BYTES;
+            }
+        }
+        *body = (!buf.is_empty()).then(|| Bytes::from(buf));
+    }
+}
+
+/// Where a `<head>` tag is in a buffer
+#[derive(Debug, PartialEq, Eq)]
+enum Search {
+    /// Complete, ending before this offset
+    Found(usize),
+    /// Possibly starting at this offset, continued in a later chunk
+    Partial(usize),
+    NotFound,
+}
+
+/// Find the first `<head>` tag (with or without attributes) in `buf`,
+/// telling `<header>` and the like apart.
+fn find_head(buf: &[u8]) -> Search {
+    const OPEN: &[u8] = b"<head";
+    let mut from = 0;
+    while let Some(offset) = buf[from..].iter().position(|&b| b == b'<') {
+        let start = from + offset;
+        let rest = &buf[start..];
+        if rest.len() < OPEN.len() {
+            return if rest.eq_ignore_ascii_case(&OPEN[..rest.len()]) {
+                Search::Partial(start)
+            } else {
+                Search::NotFound
+            };
+        }
+        if rest[..OPEN.len()].eq_ignore_ascii_case(OPEN) {
+            match rest.get(OPEN.len()) {
+                None => return Search::Partial(start),
+                Some(b'>' | b'/' | b' ' | b'\t' | b'\n' | b'\r' | b'\x0c') => {
+                    return match rest.iter().position(|&b| b == b'>') {
+                        Some(end) => Search::Found(start + end + 1),
+                        None => Search::Partial(start),
+                    };
+                }
+                Some(_) => {}
+            }
+        }
+        from = start + 1;
+    }
+    Search::NotFound
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAG: &str = "<base href=\"/app/\">";
+
+    /// Feed `chunks` through an injector, returning the chunks it passes on
+    fn run(chunks: &[&str]) -> (Vec<String>, bool) {
+        let mut injector = BaseHrefInjector::new("/app/");
+        let mut out = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut body = Some(Bytes::from(chunk.to_string()));
+            injector.filter(&mut body, i == chunks.len() - 1);
+            if let Some(body) = body {
+                out.push(String::from_utf8(body.to_vec()).unwrap());
+            }
+        }
+        (out, injector.injected())
+    }
+
+    #[test]
+    fn test_find_head() {
+        assert_eq!(find_head(b"<html><head><title>"), Search::Found(12));
+        assert_eq!(find_head(b"<HEAD lang=\"en\">x"), Search::Found(16));
+        assert_eq!(find_head(b"<header><head/>"), Search::Found(15));
+        assert_eq!(find_head(b"<html><he"), Search::Partial(6));
+        assert_eq!(find_head(b"<html><head"), Search::Partial(6));
+        assert_eq!(find_head(b"<html><head class=\"a"), Search::Partial(6));
+        assert_eq!(find_head(b"<html><body>"), Search::NotFound);
+        assert_eq!(find_head(b"<html><headers>"), Search::NotFound);
+        assert_eq!(find_head(b"a < b"), Search::NotFound);
+    }
+
+    #[test]
+    fn test_small_body() {
+        let html = "<!doctype html><html><head><title>App</title></head></html>";
+        let (out, injected) = run(&[html]);
+        assert!(injected);
+        assert_eq!(
+            out.concat(),
+            format!("<!doctype html><html><head>{TAG}<title>App</title></head></html>")
+        );
+    }
+
+    #[test]
+    fn test_head_spanning_chunks() {
+        let expected = format!("<html><head lang=\"en\">{TAG}<title>App</title></html>");
+        let html = "<html><head lang=\"en\"><title>App</title></html>";
+        // Every split of the document in two and three chunks
+        for i in 0..=html.len() {
+            for j in i..=html.len() {
+                let (out, injected) = run(&[&html[..i], &html[i..j], &html[j..]]);
+                assert!(injected, "{i} {j}");
+                assert_eq!(out.concat(), expected, "{i} {j}");
+            }
+        }
+
+        // Bytes before a possible tag aren't held back
+        let (out, _) = run(&["<html><he", "ad>", "</html>"]);
+        assert_eq!(out[0], "<html>");
+    }
+
+    #[test]
+    fn test_no_head() {
+        for chunks in [
+            &["<html><body>", "<header>hi</header>", "</body></html>"][..],
+            &["plain <he"],
+            &["<html><head"],
+        ] {
+            let (out, injected) = run(chunks);
+            assert!(!injected, "{chunks:?}");
+            assert_eq!(out.concat(), chunks.concat(), "{chunks:?}");
+        }
+
+        // Given up on after the start of the document
+        let filler = "x".repeat(MAX_SEARCH_BYTES);
+        let (out, injected) = run(&[&filler, "<head>", "</html>"]);
+        assert!(!injected);
+        assert_eq!(out.concat(), format!("{filler}<head></html>"));
+    }
+
+    #[test]
+    fn test_href_escaped() {
+        let mut injector = BaseHrefInjector::new("/app/?a=1&b=2");
+        let mut body = Some(Bytes::from_static(b"<head>"));
+        injector.filter(&mut body, true);
+        assert_eq!(
+            body.unwrap(),
+            "<head><base href=\"/app/?a=1&amp;b=2\">".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_applies() {
+        let response = |status: u16, headers: &[(&'static str, &str)]| {
+            let mut resp = ResponseHeader::build(status, None).unwrap();
+            for (name, value) in headers {
+                resp.insert_header(*name, *value).unwrap();
+            }
+            resp
+        };
+        assert!(applies(&response(
+            200,
+            &[("content-type", "text/html; charset=utf-8")]
+        )));
+        assert!(applies(&response(
+            200,
+            &[
+                ("content-type", "Text/HTML"),
+                ("content-encoding", "identity")
+            ]
+        )));
+        assert!(!applies(&response(200, &[("content-type", "text/plain")])));
+        assert!(!applies(&response(200, &[])));
+        assert!(!applies(&response(206, &[("content-type", "text/html")])));
+        assert!(!applies(&response(304, &[("content-type", "text/html")])));
+        assert!(!applies(&response(
+            200,
+            &[("content-type", "text/html"), ("content-encoding", "gzip")]
+        )));
+
+        let mut resp = response(
+            200,
+            &[
+                ("content-type", "text/html"),
+                ("content-length", "100"),
+                ("accept-ranges", "bytes"),
+                ("etag", "\"v1\""),
+            ],
+        );
+        prepare(&mut resp).unwrap();
+        assert!(resp.headers.get(CONTENT_LENGTH).is_none());
+        assert!(resp.headers.get(ACCEPT_RANGES).is_none());
+        assert_eq!(resp.headers.get(ETAG).unwrap(), "W/\"v1\"");
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Mutex;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use http::header::AUTHORIZATION;
+use http::HeaderMap;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio::sync::Semaphore;
+
+/// `WWW-Authenticate` challenge of devboxes behind a basic-auth gate
+pub const CHALLENGE: &str = "Basic realm=\"devbox\", charset=\"UTF-8\"";
+
+/// Bounds of the rounds SHA-crypt hashes may set. Anyone can make the
+/// gateway verify a password, so costlier hashes are refused rather than
+/// letting a devbox's users tie up the blocking pool.
+const MIN_ROUNDS: u32 = 1000;
+const MAX_ROUNDS: u32 = 10_000;
+
+/// Highest cost bcrypt hashes may set, for the same reason
+const MAX_BCRYPT_COST: u32 = 12;
+
+/// Most passwords verified at once, across devboxes. Further requests wait
+/// their turn instead of piling up on the blocking pool; how many of them a
+/// client can have waiting is bounded by the per-client in-flight limit.
+const MAX_VERIFICATIONS: usize = 4;
+
+static VERIFICATIONS: Semaphore = Semaphore::const_new(MAX_VERIFICATIONS);
+
+/// Longest apr1 salt; longer ones are truncated
+const MAX_APR1_SALT_LEN: usize = 8;
+
+/// Most verified credentials remembered per devbox, so that each request
+/// doesn't pay for thousands of hashing rounds
+const MAX_VERIFIED: usize = 64;
+
+/// Alphabet of the crypt encodings
+const CRYPT_ALPHABET: &[u8; 64] =
+    b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Order the bytes of an apr1 digest are encoded in, three at a time
+const APR1_ORDER: [[usize; 3]; 5] = [[0, 6, 12], [1, 7, 13], [2, 8, 14], [3, 9, 15], [4, 10, 5]];
+
+/// Users allowed through a devbox's basic-auth gate.
+///
+/// Parsed from htpasswd lines (`user:hash`, separated by newlines or
+/// commas) with apr1 hashes, as made by `htpasswd` by default, bcrypt ones
+/// (`htpasswd -B`) or SHA-crypt ones (`htpasswd -2` or `-5`, or
+/// `mkpasswd -m sha-256`).
+pub struct BasicAuth {
+    /// Users and their hashes
+    users: Vec<(String, String)>,
+    /// SHA-256 of the `Authorization` values already verified
+    verified: Mutex<HashSet<[u8; 32]>>,
+}
+
+impl BasicAuth {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut users = Vec::new();
+        for line in value.split(['\n', ',']).map(str::trim) {
+            if line.is_empty() {
+                continue;
+            }
+            let (user, hash) = line
+                .split_once(':')
+                .ok_or_else(|| format!("expected user:hash, got {line}"))?;
+            if user.is_empty() {
+                return Err("empty user".to_string());
+            }
+            if Scheme::of(hash).is_none() {
+                return Err(format!(
+                    "unsupported hash for {user} (expected bcrypt of cost up to \
+                     {MAX_BCRYPT_COST}, $apr1$, or $5$/$6$ of up to {MAX_ROUNDS} rounds)"
+                ));
+            }
+            users.push((user.to_string(), hash.to_string()));
+        }
+        if users.is_empty() {
+            return Err("no users".to_string());
+        }
+        Ok(Self::with_users(users))
+    }
+
+    /// A gate letting nobody through, for devboxes whose gate is invalid.
+    pub fn deny_all() -> Self {
+        Self::with_users(Vec::new())
+    }
+
+    fn with_users(users: Vec<(String, String)>) -> Self {
+        Self {
+            users,
+            verified: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Whether the `Authorization` header of a request has the credentials
+    /// of one of the users. Hashing runs on the blocking pool, as it takes
+    /// milliseconds by design.
+    pub async fn check(&self, headers: &HeaderMap) -> bool {
+        let Some(value) = headers.get(AUTHORIZATION) else {
+            return false;
+        };
+        let key: [u8; 32] = Sha256::digest(value.as_bytes()).into();
+        if self.verified.lock().unwrap().contains(&key) {
+            return true;
+        }
+
+        let Some((user, password)) = value
+            .to_str()
+            .ok()
+            .and_then(|v| v.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("basic"))
+            .and_then(|(_, credentials)| STANDARD.decode(credentials.trim()).ok())
+            .and_then(|credentials| String::from_utf8(credentials).ok())
+            .and_then(|credentials| {
+                let (user, password) = credentials.split_once(':')?;
+                Some((user.to_string(), password.to_string()))
+            })
+        else {
+            return false;
+        };
+        // Unknown users are verified against the first user's hash all the
+        // same, so the time taken doesn't tell which users exist
+        let known = self.users.iter().find(|(u, _)| *u == user);
+        let Some((_, hash)) = known.or(self.users.first()) else {
+            return false;
+        };
+        let hash = hash.clone();
+        let Ok(_permit) = VERIFICATIONS.acquire().await else {
+            return false;
+        };
+        let verified = tokio::task::spawn_blocking(move || verify(&hash, &password))
+            .await
+            .unwrap_or(false);
+        if !verified || known.is_none() {
+            return false;
+        }
+
+        let mut verified = self.verified.lock().unwrap();
+        if verified.len() >= MAX_VERIFIED {
+            verified.clear();
+        }
+        verified.insert(key);
+        true
+    }
+}
+
+impl fmt::Debug for BasicAuth {
+    /// Lists the users only, keeping hashes out of logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BasicAuth")
+            .field(
+                "users",
+                &self.users.iter().map(|(u, _)| u).collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl Clone for BasicAuth {
+    fn clone(&self) -> Self {
+        Self::with_users(self.users.clone())
+    }
+}
+
+impl PartialEq for BasicAuth {
+    fn eq(&self, other: &Self) -> bool {
+        self.users == other.users
+    }
+}
+
+impl Eq for BasicAuth {}
+
+/// Whether `password` matches `hash`
+fn verify(hash: &str, password: &str) -> bool {
+    match Scheme::of(hash) {
+        Some(Scheme::Bcrypt) => bcrypt::verify(password, hash).unwrap_or(false),
+        Some(Scheme::Apr1) => apr1(password.as_bytes(), hash)
+            .is_some_and(|computed| bool::from(computed.as_bytes().ct_eq(hash.as_bytes()))),
+        Some(Scheme::Sha256Crypt) => sha_crypt::sha256_check(password, hash).is_ok(),
+        Some(Scheme::Sha512Crypt) => sha_crypt::sha512_check(password, hash).is_ok(),
+        None => false,
+    }
+}
+
+/// Hash schemes of htpasswd lines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scheme {
+    Bcrypt,
+    Apr1,
+    Sha256Crypt,
+    Sha512Crypt,
+}
+
+impl Scheme {
+    /// The scheme of `hash`, if it's a well-formed hash of a supported one
+    fn of(hash: &str) -> Option<Self> {
+        let is_encoded =
+            |s: &str, len: usize| s.len() == len && s.bytes().all(|b| CRYPT_ALPHABET.contains(&b));
+        let mut parts = hash.split('$');
+        if parts.next() != Some("") {
+            return None;
+        }
+        let scheme = match parts.next()? {
+            "2a" | "2b" | "2x" | "2y" => {
+                let cost: u32 = parts.next().filter(|c| c.len() == 2)?.parse().ok()?;
+                let encoded = parts.next()?;
+                ((4..=MAX_BCRYPT_COST).contains(&cost) && is_encoded(encoded, 53))
+                    .then_some(Self::Bcrypt)?
+            }
+            "apr1" => {
+                let salt = parts.next()?;
+                let encoded = parts.next()?;
+                (!salt.is_empty() && is_encoded(encoded, 22)).then_some(Self::Apr1)?
+            }
+            id @ ("5" | "6") => {
+                let mut salt = parts.next()?;
+                if let Some(rounds) = salt.strip_prefix("rounds=") {
+                    let rounds: u32 = rounds.parse().ok()?;
+                    if !(MIN_ROUNDS..=MAX_ROUNDS).contains(&rounds) {
+                        return None;
+                    }
+                    salt = parts.next()?;
+                }
+                let encoded = parts.next()?;
+                if salt.contains(|c: char| c.is_whitespace()) {
+                    return None;
+                }
+                if id == "5" {
+                    is_encoded(encoded, 43).then_some(Self::Sha256Crypt)?
+                } else {
+                    is_encoded(encoded, 86).then_some(Self::Sha512Crypt)?
+                }
+            }
+            _ => return None,
+        };
+        parts.next().is_none().then_some(scheme)
+    }
+}
+
+/// Hash `password` with the salt of the apr1 `hash`, returning the complete
+/// hash. No maintained crate implements Apache's MD5 variant, so it's done
+/// here after `apr_md5_encode`.
+fn apr1(password: &[u8], hash: &str) -> Option<String> {
+    let salt = hash.strip_prefix("$apr1$")?.split('$').next()?;
+    let salt = salt.get(..salt.len().min(MAX_APR1_SALT_LEN))?;
+
+    let alternate = Md5::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+    let mut ctx = Md5::new()
+        .chain_update(password)
+        .chain_update("$apr1$")
+        .chain_update(salt);
+    for chunk in password.chunks(alternate.len()) {
+        ctx.update(&alternate[..chunk.len()]);
+    }
+    let mut n = password.len();
+    while n > 0 {
+        if n & 1 == 1 {
+            ctx.update([0]);
+        } else {
+            ctx.update(&password[..1]);
+        }
+        n >>= 1;
+    }
+    let mut result = ctx.finalize();
+
+    for i in 0..1000 {
+        let mut c = Md5::new();
+        if i & 1 == 1 {
+            c.update(password);
+        } else {
+            c.update(result);
+        }
+        if i % 3 != 0 {
+            c.update(salt);
+        }
+        if i % 7 != 0 {
+            c.update(password);
+        }
+        if i & 1 == 1 {
+            c.update(result);
+        } else {
+            c.update(password);
+        }
+        result = c.finalize();
+    }
+
+    let mut out = format!("$apr1${salt}$");
+    for [a, b, c] in APR1_ORDER {
+        encode_24bit(&mut out, result[a], result[b], result[c], 4);
+    }
+    encode_24bit(&mut out, 0, 0, result[11], 2);
+    Some(out)
+}
+
+/// Append the `n` low 6-bit groups of the three bytes, least significant
+/// first
+fn encode_24bit(out: &mut String, b2: u8, b1: u8, b0: u8, n: usize) {
+    let mut w = (u32::from(b2) << 16) | (u32::from(b1) << 8) | u32::from(b0);
+    for _ in 0..n {
+        out.push(char::from(CRYPT_ALPHABET[(w & 0x3f) as usize]));
+        w >>= 6;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "alice:$5$Jd8KH1n2QJbNQzWt$ktOmKQgKg2aBmNJksRjKOIUBcgDyUITocPevjz9gqn5";
+    const BOB: &str = "bob:$6$rounds=1000$Jd8KH1n2QJbNQzWt$iC98cjJgR/yuAO5XFAOmtJNv5WHppjiu680OMNrOYDSFj89YRaxC7lE6OOQE/4EuVLdxe62uYpPsvj/EBV/h01";
+    const CAROL: &str = "carol:$2b$05$/ubRtA.GOaEHsn.91R1FE.VY8O5QgmuVfr8KOyj89GZRwJh6SdwDq";
+    const DAVE: &str = "dave:$apr1$Jd8KH1n2$w/Eo9G/CAjsPMjm3hUwV/1";
+
+    fn authorization(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, value.parse().unwrap());
+        headers
+    }
+
+    fn basic(credentials: &str) -> HeaderMap {
+        authorization(&format!("Basic {}", STANDARD.encode(credentials)))
+    }
+
+    #[test]
+    fn test_apr1() {
+        for (password, hash) in [
+            ("s3cret", "$apr1$salt$SjIo4E2zA7mrxBvk039ZN."),
+            ("s3cret", "$apr1$Jd8KH1n2$w/Eo9G/CAjsPMjm3hUwV/1"),
+        ] {
+            assert_eq!(Scheme::of(hash), Some(Scheme::Apr1));
+            assert_eq!(apr1(password.as_bytes(), hash).as_deref(), Some(hash));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let auth = BasicAuth::parse(&format!("{ALICE}\n{BOB}\n{CAROL}\n{DAVE}")).unwrap();
+        for user in ["alice", "bob", "carol", "dave"] {
+            assert!(
+                auth.check(&basic(&format!("{user}:s3cret"))).await,
+                "{user}"
+            );
+        }
+        assert!(
+            auth.check(&authorization(&format!(
+                "basic {}",
+                STANDARD.encode("alice:s3cret")
+            )))
+            .await
+        );
+        // Remembered once verified
+        assert!(auth.check(&basic("alice:s3cret")).await);
+        assert_eq!(auth.verified.lock().unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_check_invalid() {
+        let auth = BasicAuth::parse(&format!("{ALICE}\n{CAROL}\n{DAVE}")).unwrap();
+        for headers in [
+            HeaderMap::new(),
+            basic("alice:wrong"),
+            basic("carol:wrong"),
+            basic("dave:wrong"),
+            basic("alice:s3cret "),
+            // Verified against alice's hash, which the password matches
+            basic("mallory:s3cret"),
+            basic("alice"),
+            authorization("Bearer s3cret"),
+            authorization("Basic not-base64!"),
+        ] {
+            assert!(!auth.check(&headers).await, "{headers:?}");
+        }
+        assert!(auth.verified.lock().unwrap().is_empty());
+        assert!(!BasicAuth::deny_all().check(&basic("alice:s3cret")).await);
+    }
+
+    #[test]
+    fn test_parse() {
+        let auth = BasicAuth::parse(&format!(" {ALICE} , {BOB},{CAROL}\n{DAVE}")).unwrap();
+        assert_eq!(auth.users.len(), 4);
+        assert!(!format!("{auth:?}").contains("$5$"));
+
+        for value in [
+            "",
+            "alice",
+            ":$5$salt$ktOmKQgKg2aBmNJksRjKOIUBcgDyUITocPevjz9gqn5",
+            "alice:{SHA}kd/Z3bQZiv/FwZTNjObTOP3kcOI=",
+            "alice:$1$salt$qb2Tmb9eo/Ecm1a3ovsKe0",
+            "alice:$5$salt$tooshort",
+            "alice:$5$rounds=many$salt$ktOmKQgKg2aBmNJksRjKOIUBcgDyUITocPevjz9gqn5",
+            "alice:$5$rounds=10$salt$ktOmKQgKg2aBmNJksRjKOIUBcgDyUITocPevjz9gqn5",
+            "alice:$2b$5$/ubRtA.GOaEHsn.91R1FE.VY8O5QgmuVfr8KOyj89GZRwJh6SdwDq",
+            "alice:$2b$05$/ubRtA.GOaEHsn.91R1FE.VY8O5Qgmu",
+            // Too costly to verify on every request
+            "alice:$5$rounds=999999999$Jd8KH1n2QJbNQzWt$ktOmKQgKg2aBmNJksRjKOIUBcgDyUITocPevjz9gqn5",
+            "alice:$6$rounds=10001$Jd8KH1n2QJbNQzWt$iC98cjJgR/yuAO5XFAOmtJNv5WHppjiu680OMNrOYDSFj89YRaxC7lE6OOQE/4EuVLdxe62uYpPsvj/EBV/h01",
+            "alice:$2b$13$/ubRtA.GOaEHsn.91R1FE.VY8O5QgmuVfr8KOyj89GZRwJh6SdwDq",
+            "alice:$apr1$$w/Eo9G/CAjsPMjm3hUwV/1",
+        ] {
+            assert!(BasicAuth::parse(value).is_err(), "{value}");
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use serde::Serialize;
+use tracing::info;
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::metrics;
+
+/// A blocked devbox or namespace.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum BlockEntry {
+    UniqueId(String),
+    Namespace(String),
+}
+
+impl fmt::Display for BlockEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UniqueId(id) => write!(f, "unique_id:{id}"),
+            Self::Namespace(ns) => write!(f, "namespace:{ns}"),
+        }
+    }
+}
+
+/// Set of blocked devboxes and namespaces.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockSet {
+    pub unique_ids: BTreeSet<String>,
+    pub namespaces: BTreeSet<String>,
+}
+
+impl BlockSet {
+    /// Parse a blocklist file.
+    ///
+    /// One entry per line, either `unique_id:<id>` or `namespace:<name>`.
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn parse(content: &str) -> std::result::Result<Self, String> {
+        let mut set = Self::default();
+        for (lineno, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (kind, value) = line
+                .split_once(':')
+                .map(|(k, v)| (k.trim(), v.trim()))
+                .filter(|(_, v)| !v.is_empty())
+                .ok_or_else(|| format!("line {}: expected <kind>:<value>", lineno + 1))?;
+            match kind {
+                "unique_id" => set.unique_ids.insert(value.to_string()),
+                "namespace" => set.namespaces.insert(value.to_string()),
+                other => return Err(format!("line {}: unknown kind {other:?}", lineno + 1)),
+            };
+        }
+        Ok(set)
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.unique_ids.extend(other.unique_ids);
+        self.namespaces.extend(other.namespaces);
+    }
+
+    /// All entries, unique IDs first.
+    pub fn entries(&self) -> Vec<BlockEntry> {
+        self.unique_ids
+            .iter()
+            .cloned()
+            .map(BlockEntry::UniqueId)
+            .chain(self.namespaces.iter().cloned().map(BlockEntry::Namespace))
+            .collect()
+    }
+}
+
+/// Runtime blocklist consulted on every request.
+///
+/// Combines the static `BLOCKED_UNIQUE_IDS` / `BLOCKED_NAMESPACES` entries
+/// with the entries of `BLOCKLIST_FILE`, which is re-read on SIGHUP or via
+/// the admin API. Checks happen at request time, so a namespace block also
+/// covers devboxes registered after it was added.
+pub struct Blocklist {
+    static_entries: BlockSet,
+    file: Option<String>,
+    active: RwLock<Arc<BlockSet>>,
+    /// Status returned for blocked requests (403 or 451)
+    pub status: u16,
+    /// Body returned for blocked requests
+    pub message: String,
+}
+
+impl Blocklist {
+    /// Create a blocklist with the static entries of `config`.
+    ///
+    /// The file, if any, is not read until [`Self::reload`] is called.
+    pub fn from_config(config: &Config) -> Self {
+        let static_entries = BlockSet {
+            unique_ids: config.blocked_unique_ids.iter().cloned().collect(),
+            namespaces: config.blocked_namespaces.iter().cloned().collect(),
+        };
+        Self {
+            active: RwLock::new(Arc::new(static_entries.clone())),
+            static_entries,
+            file: config.blocklist_file.clone(),
+            status: config.blocked_status,
+            message: config.blocked_message.clone(),
+        }
+    }
+
+    /// Re-read the blocklist file and swap in the new entries.
+    ///
+    /// On error the previous entries stay active.
+    pub fn reload(&self) -> Result<()> {
+        let mut set = self.static_entries.clone();
+        if let Some(path) = &self.file {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| Error::Config(format!("Failed to read blocklist {path}: {e}")))?;
+            let entries = BlockSet::parse(&content)
+                .map_err(|msg| Error::Config(format!("Invalid blocklist {path}: {msg}")))?;
+            set.merge(entries);
+        }
+
+        info!(
+            unique_ids = set.unique_ids.len(),
+            namespaces = set.namespaces.len(),
+            "Blocklist loaded"
+        );
+        *self.active.write().unwrap() = Arc::new(set);
+        Ok(())
+    }
+
+    /// Currently active entries.
+    pub fn snapshot(&self) -> Arc<BlockSet> {
+        Arc::clone(&self.active.read().unwrap())
+    }
+
+    /// Check a request, counting it against the matching entry if blocked.
+    ///
+    /// `namespace` is `None` when the devbox is not registered, in which
+    /// case only the unique ID is checked.
+    pub fn check(&self, unique_id: &str, namespace: Option<&str>) -> Option<BlockEntry> {
+        let set = self.snapshot();
+        let entry = if set.unique_ids.contains(unique_id) {
+            BlockEntry::UniqueId(unique_id.to_string())
+        } else {
+            let ns = namespace.filter(|ns| set.namespaces.contains(*ns))?;
+            BlockEntry::Namespace(ns.to_string())
+        };
+
+        metrics::BLOCKED_REQUESTS_TOTAL
+            .with_label_values(&[&entry.to_string()])
+            .inc();
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(file: Option<String>) -> Config {
+        Config {
+            blocked_unique_ids: vec!["static-id".to_string()],
+            blocklist_file: file,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let set = BlockSet::parse(
+            "# abuse reports\n\nunique_id: bad-app\nnamespace:ns-spam\n  namespace:ns-other  \n",
+        )
+        .unwrap();
+        assert_eq!(
+            set.entries(),
+            vec![
+                BlockEntry::UniqueId("bad-app".to_string()),
+                BlockEntry::Namespace("ns-other".to_string()),
+                BlockEntry::Namespace("ns-spam".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(BlockSet::parse("bad-app").is_err());
+        assert!(BlockSet::parse("unique_id:").is_err());
+        assert!(BlockSet::parse("pod:bad-app").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let blocklist = Blocklist::from_config(&Config {
+            blocked_unique_ids: vec!["bad-app".to_string()],
+            blocked_namespaces: vec!["ns-spam".to_string()],
+            ..Default::default()
+        });
+
+        assert_eq!(
+            blocklist.check("bad-app", None),
+            Some(BlockEntry::UniqueId("bad-app".to_string()))
+        );
+        // The unique ID entry wins over the namespace
+        assert_eq!(
+            blocklist.check("bad-app", Some("ns-spam")),
+            Some(BlockEntry::UniqueId("bad-app".to_string()))
+        );
+        assert_eq!(
+            blocklist.check("new-app", Some("ns-spam")),
+            Some(BlockEntry::Namespace("ns-spam".to_string()))
+        );
+        assert_eq!(blocklist.check("new-app", None), None);
+        assert_eq!(blocklist.check("good-app", Some("ns-ok")), None);
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = std::env::temp_dir().join(format!("httpgate-blocklist-{}", std::process::id()));
+        std::fs::write(&path, "namespace:ns-spam\n").unwrap();
+
+        let blocklist = Blocklist::from_config(&config(Some(path.to_str().unwrap().to_string())));
+        assert!(blocklist.snapshot().namespaces.is_empty());
+
+        blocklist.reload().unwrap();
+        assert!(blocklist.check("app", Some("ns-spam")).is_some());
+        assert!(blocklist.check("static-id", None).is_some());
+
+        // Entries removed from the file are unblocked; static entries stay
+        std::fs::write(&path, "unique_id:other\n").unwrap();
+        blocklist.reload().unwrap();
+        assert!(blocklist.check("app", Some("ns-spam")).is_none());
+        assert!(blocklist.check("other", None).is_some());
+        assert!(blocklist.check("static-id", None).is_some());
+
+        // An invalid file keeps the previous entries
+        std::fs::write(&path, "garbage\n").unwrap();
+        assert!(blocklist.reload().is_err());
+        assert!(blocklist.check("other", None).is_some());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(blocklist.reload(), Err(Error::Config(_))));
+    }
+}
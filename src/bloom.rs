@@ -0,0 +1,149 @@
+use std::hash::{BuildHasher, RandomState};
+
+/// Bits per expected item; with [`HASHES`] probes this gives a false
+/// positive rate of about 1% at capacity
+const BITS_PER_ITEM: usize = 10;
+
+/// Probes per item
+const HASHES: u64 = 7;
+
+/// Smallest capacity a filter is sized for
+pub const MIN_CAPACITY: usize = 1024;
+
+/// Bloom filter over strings, sized for a fixed capacity.
+///
+/// Items can't be removed; callers count removals with
+/// [`Self::note_removed`] and rebuild the filter once [`Self::needs_rebuild`]
+/// reports that stale or excess items have pushed the false positive rate up.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    /// Bit index mask (the number of bits is a power of two)
+    mask: u64,
+    hasher: RandomState,
+    capacity: usize,
+    /// Items inserted since the filter was created, including removed ones
+    inserted: usize,
+    /// Items removed since the filter was created
+    removed: usize,
+}
+
+impl BloomFilter {
+    /// Create an empty filter sized for `capacity` items (at least
+    /// [`MIN_CAPACITY`]).
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let num_bits = (capacity * BITS_PER_ITEM).next_power_of_two();
+        Self {
+            bits: vec![0; num_bits / 64],
+            mask: num_bits as u64 - 1,
+            hasher: RandomState::new(),
+            capacity,
+            inserted: 0,
+            removed: 0,
+        }
+    }
+
+    /// Create a filter holding `items`, sized for twice as many.
+    pub fn from_items<'a>(items: impl ExactSizeIterator<Item = &'a str>) -> Self {
+        let mut filter = Self::new(items.len() * 2);
+        for item in items {
+            filter.insert(item);
+        }
+        filter
+    }
+
+    /// Bit positions of `item`, by double hashing one 64-bit hash.
+    fn positions(&self, item: &str) -> impl Iterator<Item = u64> {
+        let hash = self.hasher.hash_one(item);
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let mask = self.mask;
+        (0..HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) & mask)
+    }
+
+    pub fn insert(&mut self, item: &str) {
+        for bit in self.positions(item) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.inserted += 1;
+    }
+
+    /// Whether `item` may have been inserted. `false` is definite.
+    pub fn may_contain(&self, item: &str) -> bool {
+        self.positions(item)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Record that an inserted item was removed from the backing set.
+    pub fn note_removed(&mut self) {
+        self.removed += 1;
+    }
+
+    /// Whether the filter holds more items than it was sized for, or more
+    /// than half of its items were removed.
+    pub fn needs_rebuild(&self) -> bool {
+        self.inserted > self.capacity || self.removed * 2 > self.inserted
+    }
+
+    /// Memory held by the bit array
+    pub fn memory_bytes(&self) -> usize {
+        self.bits.len() * size_of::<u64>()
+    }
+
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_false_negatives() {
+        let ids: Vec<String> = (0..10_000).map(|i| format!("devbox-{i}")).collect();
+        let filter = BloomFilter::from_items(ids.iter().map(String::as_str));
+        assert!(ids.iter().all(|id| filter.may_contain(id)));
+        assert!(!filter.needs_rebuild());
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        let mut filter = BloomFilter::new(MIN_CAPACITY);
+        for i in 0..MIN_CAPACITY {
+            filter.insert(&format!("registered-{i}"));
+        }
+
+        let false_positives = (0..100_000)
+            .filter(|i| filter.may_contain(&format!("scanner-{i}")))
+            .count();
+        // ~1% expected at capacity
+        assert!(false_positives < 3_000, "{false_positives}");
+    }
+
+    #[test]
+    fn test_needs_rebuild() {
+        let mut filter = BloomFilter::new(0);
+        assert_eq!(filter.capacity(), MIN_CAPACITY);
+        for i in 0..MIN_CAPACITY {
+            filter.insert(&i.to_string());
+        }
+        assert!(!filter.needs_rebuild());
+
+        // Over capacity
+        filter.insert("one-more");
+        assert!(filter.needs_rebuild());
+
+        // Mostly removed
+        let mut filter = BloomFilter::new(0);
+        for i in 0..10 {
+            filter.insert(&i.to_string());
+        }
+        for _ in 0..5 {
+            filter.note_removed();
+        }
+        assert!(!filter.needs_rebuild());
+        filter.note_removed();
+        assert!(filter.needs_rebuild());
+    }
+}
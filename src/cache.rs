@@ -0,0 +1,885 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::{Method, StatusCode, Uri};
+use lru::LruCache;
+
+use crate::config::Config;
+use crate::metrics;
+use crate::policy::DevboxPolicy;
+use crate::registry::{DevboxInfo, RegistryObserver};
+
+/// Freshness of `immutable` responses without a `max-age`
+const IMMUTABLE_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// Longest freshness honored, whatever the backend asks for
+const MAX_TTL: Duration = Duration::from_secs(365 * 24 * 3600);
+
+/// Variants (by `Vary`) kept per request target
+const MAX_VARIANTS: usize = 4;
+
+/// Headers describing the connection rather than the response, never stored
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Request target on one port of a devbox.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub unique_id: String,
+    pub port: u16,
+    /// Path and query
+    pub target: String,
+}
+
+impl CacheKey {
+    pub fn new(unique_id: &str, port: u16, uri: &Uri) -> Self {
+        Self {
+            unique_id: unique_id.to_string(),
+            port,
+            target: uri
+                .path_and_query()
+                .map_or("/", |target| target.as_str())
+                .to_string(),
+        }
+    }
+}
+
+/// A stored response.
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    /// End-to-end headers, with `Content-Length` set to the body length
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    stored_at: Instant,
+    expires_at: Instant,
+    /// Endpoint generation of the backend that sent the response
+    generation: u64,
+    /// Request headers named by `Vary`, with the values the response is for
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+}
+
+impl CachedResponse {
+    /// Seconds since the response was stored, for the `Age` header
+    pub fn age(&self) -> u64 {
+        self.stored_at.elapsed().as_secs()
+    }
+
+    fn matches(&self, req: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| req.get(name) == value.as_ref())
+    }
+
+    /// Memory accounted to the response
+    fn size(&self) -> u64 {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        (self.body.len() + headers) as u64
+    }
+}
+
+/// A cacheable response being received from the backend.
+pub struct CacheFill {
+    key: CacheKey,
+    response: CachedResponse,
+    body: BytesMut,
+    ttl: Duration,
+    max_bytes: u64,
+}
+
+impl CacheFill {
+    /// Append a body chunk. Returns `false` once the body outgrows the
+    /// object size limit, after which the fill should be dropped.
+    pub fn push(&mut self, chunk: &[u8]) -> bool {
+        if (self.body.len() + chunk.len()) as u64 > self.max_bytes {
+            return false;
+        }
+        self.body.extend_from_slice(chunk);
+        true
+    }
+}
+
+/// Cached keys of one devbox, so its responses can be purged together.
+struct CachedDevbox {
+    cluster: Arc<str>,
+    namespace: String,
+    devbox_name: String,
+    keys: HashSet<CacheKey>,
+}
+
+struct Inner {
+    /// Responses by request target, least recently used first
+    entries: LruCache<CacheKey, Vec<CachedResponse>>,
+    /// Devboxes with cached responses, by uniqueID
+    devboxes: HashMap<String, CachedDevbox>,
+    /// Memory held by `entries`
+    bytes: u64,
+}
+
+impl Inner {
+    fn set_bytes(&mut self, bytes: u64) {
+        metrics::CACHE_BYTES.add(bytes as i64 - self.bytes as i64);
+        self.bytes = bytes;
+    }
+
+    /// Remove the responses of `key`, returning how many there were.
+    fn remove(&mut self, key: &CacheKey) -> usize {
+        let Some(variants) = self.entries.pop(key) else {
+            return 0;
+        };
+        self.forget(key, &variants);
+        variants.len()
+    }
+
+    /// Account for responses of `key` that were taken out of `entries`.
+    fn forget(&mut self, key: &CacheKey, variants: &[CachedResponse]) {
+        let freed: u64 = variants.iter().map(CachedResponse::size).sum();
+        self.set_bytes(self.bytes - freed);
+        if let Some(devbox) = self.devboxes.get_mut(&key.unique_id) {
+            devbox.keys.remove(key);
+            if devbox.keys.is_empty() {
+                self.devboxes.remove(&key.unique_id);
+            }
+        }
+    }
+
+    /// Remove the responses of `unique_id`, returning how many there were.
+    fn purge(&mut self, unique_id: &str) -> usize {
+        let Some(devbox) = self.devboxes.remove(unique_id) else {
+            return 0;
+        };
+        let mut removed = 0;
+        let mut freed = 0;
+        for key in &devbox.keys {
+            if let Some(variants) = self.entries.pop(key) {
+                removed += variants.len();
+                freed += variants.iter().map(CachedResponse::size).sum::<u64>();
+            }
+        }
+        self.set_bytes(self.bytes - freed);
+        removed
+    }
+}
+
+/// Memory-bounded cache of static assets served by devboxes.
+///
+/// Only responses the backend marks as shareable are stored (see
+/// [`freshness`]), keyed by devbox, port and request target, with one
+/// variant per combination of the request headers named by `Vary`. Once
+/// the cache outgrows its memory limit, the least recently used targets are
+/// evicted.
+///
+/// A devbox's responses are purged when it is unregistered or its Pod
+/// endpoint changes (as a [`RegistryObserver`]), and responses of an
+/// earlier endpoint are never served even if they are stored after the
+/// purge, by a request still in flight.
+pub struct ResponseCache {
+    max_bytes: u64,
+    max_object_bytes: u64,
+    /// Only cache devboxes that opt in through their annotation
+    require_annotation: bool,
+    inner: Mutex<Inner>,
+}
+
+impl ResponseCache {
+    /// Cache holding at most `max_bytes` of responses, each at most
+    /// `max_object_bytes` (and at most `max_bytes`).
+    pub fn new(max_bytes: u64, max_object_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            max_object_bytes: max_object_bytes.min(max_bytes),
+            require_annotation: false,
+            inner: Mutex::new(Inner {
+                entries: LruCache::unbounded(),
+                devboxes: HashMap::new(),
+                bytes: 0,
+            }),
+        }
+    }
+
+    /// Only cache the responses of devboxes whose `response-cache`
+    /// annotation turns it on.
+    #[must_use]
+    pub fn with_require_annotation(mut self, require: bool) -> Self {
+        self.require_annotation = require;
+        self
+    }
+
+    /// The cache configured by `CACHE_MAX_BYTES`, if caching is enabled.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config.cache_max_bytes.map(|max_bytes| {
+            Self::new(max_bytes, config.cache_max_object_bytes)
+                .with_require_annotation(config.cache_require_annotation)
+        })
+    }
+
+    /// Whether responses of a devbox with `policy` are cached.
+    pub fn enabled_for(&self, policy: &DevboxPolicy) -> bool {
+        policy.response_cache.unwrap_or(!self.require_annotation)
+    }
+
+    /// Look up the response to a request for `key`, sent to the endpoint of
+    /// `generation` with headers `req`.
+    pub fn lookup(
+        &self,
+        key: &CacheKey,
+        generation: u64,
+        req: &HeaderMap,
+    ) -> Option<CachedResponse> {
+        let hit = self.find(key, generation, req);
+        let result = if hit.is_some() { "hit" } else { "miss" };
+        metrics::CACHE_LOOKUPS_TOTAL
+            .with_label_values(&[result])
+            .inc();
+        hit
+    }
+
+    fn find(&self, key: &CacheKey, generation: u64, req: &HeaderMap) -> Option<CachedResponse> {
+        let mut inner = self.inner.lock().unwrap();
+        let variants = inner.entries.get_mut(key)?;
+
+        // Stored from a previous endpoint by a request racing the purge
+        if variants.iter().any(|v| v.generation != generation) {
+            let removed = inner.remove(key);
+            metrics::CACHE_REMOVALS_TOTAL
+                .with_label_values(&["purged"])
+                .inc_by(removed as u64);
+            return None;
+        }
+
+        let index = variants.iter().position(|v| v.matches(req))?;
+        if variants[index].expires_at > Instant::now() {
+            return Some(variants[index].clone());
+        }
+
+        let expired = variants.remove(index);
+        if variants.is_empty() {
+            inner.entries.pop(key);
+        }
+        inner.forget(key, std::slice::from_ref(&expired));
+        metrics::CACHE_REMOVALS_TOTAL
+            .with_label_values(&["expired"])
+            .inc();
+        None
+    }
+
+    /// Start storing the response to a request for `key`, if it may be
+    /// cached. `req` are the request headers, `status` and `headers` those
+    /// of the response.
+    pub fn start_fill(
+        &self,
+        key: CacheKey,
+        generation: u64,
+        req: &HeaderMap,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Option<CacheFill> {
+        let ttl = freshness(status, headers)?;
+        let content_length = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+        if content_length.is_some_and(|length| length > self.max_object_bytes) {
+            return None;
+        }
+
+        let mut stored = headers.clone();
+        for name in HOP_BY_HOP {
+            stored.remove(name);
+        }
+        stored.remove(header::AGE);
+        let now = Instant::now();
+        Some(CacheFill {
+            key,
+            response: CachedResponse {
+                status,
+                headers: stored,
+                body: Bytes::new(),
+                stored_at: now,
+                expires_at: now,
+                generation,
+                vary: vary_values(headers, req),
+            },
+            body: BytesMut::new(),
+            ttl,
+            max_bytes: self.max_object_bytes,
+        })
+    }
+
+    /// Store a completely received response of `devbox`.
+    pub fn finish_fill(&self, fill: CacheFill, devbox: &DevboxInfo) {
+        let CacheFill {
+            key,
+            mut response,
+            body,
+            ttl,
+            ..
+        } = fill;
+        response.body = body.freeze();
+        response.headers.insert(
+            header::CONTENT_LENGTH,
+            HeaderValue::from(response.body.len()),
+        );
+        response.stored_at = Instant::now();
+        response.expires_at = response.stored_at + ttl;
+        self.insert(key, devbox, response);
+    }
+
+    fn insert(&self, key: CacheKey, devbox: &DevboxInfo, response: CachedResponse) {
+        let size = response.size();
+        if size > self.max_object_bytes {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let variants = inner.entries.get_or_insert_mut(key.clone(), Vec::new);
+        let mut freed = 0;
+        variants.retain(|v| {
+            let replaced = v.vary == response.vary;
+            if replaced {
+                freed += v.size();
+            }
+            !replaced
+        });
+        if variants.len() >= MAX_VARIANTS {
+            freed += variants.remove(0).size();
+        }
+        variants.push(response);
+        let bytes = inner.bytes - freed + size;
+        inner.set_bytes(bytes);
+        inner
+            .devboxes
+            .entry(key.unique_id.clone())
+            .or_insert_with(|| CachedDevbox {
+                cluster: Arc::clone(&devbox.cluster),
+                namespace: devbox.namespace.clone(),
+                devbox_name: devbox.devbox_name.clone(),
+                keys: HashSet::new(),
+            })
+            .keys
+            .insert(key);
+
+        // The new entry is the most recently used, and fits on its own
+        while inner.bytes > self.max_bytes {
+            let Some((key, variants)) = inner.entries.pop_lru() else {
+                break;
+            };
+            inner.forget(&key, &variants);
+            metrics::CACHE_REMOVALS_TOTAL
+                .with_label_values(&["evicted"])
+                .inc_by(variants.len() as u64);
+        }
+    }
+
+    /// Remove the responses of `unique_id`, returning how many there were.
+    pub fn purge(&self, unique_id: &str) -> usize {
+        let removed = self.inner.lock().unwrap().purge(unique_id);
+        metrics::CACHE_REMOVALS_TOTAL
+            .with_label_values(&["purged"])
+            .inc_by(removed as u64);
+        removed
+    }
+
+    /// Remove all responses, returning how many there were.
+    pub fn purge_all(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let removed = inner.entries.iter().map(|(_, v)| v.len()).sum();
+        inner.entries.clear();
+        inner.devboxes.clear();
+        inner.set_bytes(0);
+        metrics::CACHE_REMOVALS_TOTAL
+            .with_label_values(&["purged"])
+            .inc_by(removed as u64);
+        removed
+    }
+
+    /// Number of cached responses
+    pub fn response_count(&self) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.iter().map(|(_, v)| v.len()).sum()
+    }
+
+    /// Memory held by cached responses
+    pub fn size_bytes(&self) -> u64 {
+        self.inner.lock().unwrap().bytes
+    }
+}
+
+impl RegistryObserver for ResponseCache {
+    fn devbox_unregistered(&self, unique_id: &str) {
+        self.purge(unique_id);
+    }
+
+    fn endpoint_changed(&self, cluster: &str, namespace: &str, devbox_name: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let unique_ids: Vec<String> = inner
+            .devboxes
+            .iter()
+            .filter(|(_, d)| {
+                &*d.cluster == cluster && d.namespace == namespace && d.devbox_name == devbox_name
+            })
+            .map(|(unique_id, _)| unique_id.clone())
+            .collect();
+        let removed: usize = unique_ids.iter().map(|id| inner.purge(id)).sum();
+        metrics::CACHE_REMOVALS_TOTAL
+            .with_label_values(&["purged"])
+            .inc_by(removed as u64);
+    }
+}
+
+/// Whether the response to a request may come from, and be stored in, the
+/// cache: plain `GET`s without `Range` or `Cache-Control: no-store`.
+pub fn is_cacheable_request(method: &Method, headers: &HeaderMap) -> bool {
+    method == Method::GET
+        && !headers.contains_key(header::RANGE)
+        && !cache_directives(headers).any(|(name, _)| name.eq_ignore_ascii_case("no-store"))
+}
+
+/// Whether the client asks for a fresh response (e.g. on a hard reload).
+/// The response is still stored.
+pub fn bypasses_cache(headers: &HeaderMap) -> bool {
+    cache_directives(headers).any(|(name, _)| name.eq_ignore_ascii_case("no-cache"))
+        || headers
+            .get(header::PRAGMA)
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"no-cache"))
+}
+
+/// How long a response may be served from the cache, if it may be stored.
+///
+/// Only complete `200` responses the backend marks as shareable are stored:
+/// `Cache-Control: public` with a positive `s-maxage` or `max-age`, or
+/// `immutable` (for a day without a `max-age`). Responses setting cookies,
+/// varying on `*` or marked `private`, `no-store` or `no-cache` never are,
+/// nor are those carrying a `Content-Range`, which would be served as the
+/// whole representation.
+///
+/// Stored bodies are served byte for byte, so `no-transform` doesn't
+/// prevent caching.
+pub fn freshness(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if status != StatusCode::OK
+        || headers.contains_key(header::SET_COOKIE)
+        || headers.contains_key(header::CONTENT_RANGE)
+    {
+        return None;
+    }
+    let varies_on_all = headers.get_all(header::VARY).iter().any(|v| {
+        v.to_str()
+            .map_or(true, |v| v.split(',').any(|name| name.trim() == "*"))
+    });
+    if varies_on_all {
+        return None;
+    }
+
+    let mut public = false;
+    let mut immutable = false;
+    let mut max_age = None;
+    let mut s_maxage = None;
+    for (name, value) in cache_directives(headers) {
+        let seconds = || value.and_then(|v| v.trim_matches('"').parse::<u64>().ok());
+        match name.to_ascii_lowercase().as_str() {
+            "no-store" | "no-cache" | "private" => return None,
+            "public" => public = true,
+            "immutable" => immutable = true,
+            "max-age" => max_age = seconds(),
+            "s-maxage" => s_maxage = seconds(),
+            _ => {}
+        }
+    }
+
+    match s_maxage.or(max_age) {
+        Some(0) => None,
+        Some(seconds) if public || immutable => Some(Duration::from_secs(seconds).min(MAX_TTL)),
+        None if immutable => Some(IMMUTABLE_TTL),
+        _ => None,
+    }
+}
+
+/// `Cache-Control` directives, as names with optional values.
+fn cache_directives(headers: &HeaderMap) -> impl Iterator<Item = (&str, Option<&str>)> {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(|directive| match directive.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (directive, None),
+        })
+}
+
+/// Request headers named by the `Vary` of `resp`, with their values in `req`.
+fn vary_values(resp: &HeaderMap, req: &HeaderMap) -> Vec<(HeaderName, Option<HeaderValue>)> {
+    resp.get_all(header::VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .map(|name| {
+            let value = req.get(&name).cloned();
+            (name, value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::DevboxRegistry;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    fn key(unique_id: &str, target: &str) -> CacheKey {
+        CacheKey::new(unique_id, 3000, &target.parse().unwrap())
+    }
+
+    fn devbox(name: &str) -> DevboxInfo {
+        DevboxInfo::new("ns-1".to_string(), name.to_string())
+    }
+
+    /// Store a `body` for `key` the way the proxy does
+    fn store(cache: &ResponseCache, key: CacheKey, req: &HeaderMap, resp: &HeaderMap, body: &[u8]) {
+        let mut fill = cache
+            .start_fill(key, 1, req, StatusCode::OK, resp)
+            .expect("response should be cacheable");
+        assert!(fill.push(body));
+        cache.finish_fill(fill, &devbox("devbox1"));
+    }
+
+    const IMMUTABLE: (&str, &str) = ("cache-control", "public, max-age=31536000, immutable");
+
+    #[test]
+    fn test_freshness() {
+        for (pairs, expected) in [
+            (vec![IMMUTABLE], Some(MAX_TTL.as_secs())),
+            (vec![("cache-control", "public, max-age=60")], Some(60)),
+            (vec![("cache-control", "Public, Max-Age=\"60\"")], Some(60)),
+            (
+                vec![("cache-control", "public"), ("cache-control", "max-age=60")],
+                Some(60),
+            ),
+            (
+                vec![("cache-control", "public, max-age=60, s-maxage=120")],
+                Some(120),
+            ),
+            (
+                vec![("cache-control", "immutable")],
+                Some(IMMUTABLE_TTL.as_secs()),
+            ),
+            (vec![("cache-control", "max-age=60, immutable")], Some(60)),
+            (
+                vec![IMMUTABLE, ("vary", "accept-encoding")],
+                Some(MAX_TTL.as_secs()),
+            ),
+            // Not marked shareable, or not fresh
+            (vec![], None),
+            (vec![("cache-control", "max-age=60")], None),
+            (vec![("cache-control", "public")], None),
+            (vec![("cache-control", "public, max-age=0")], None),
+            (vec![("cache-control", "public, max-age=soon")], None),
+            (vec![("cache-control", "immutable, max-age=0")], None),
+            // Explicitly not cacheable
+            (
+                vec![("cache-control", "public, max-age=60, no-store")],
+                None,
+            ),
+            (
+                vec![("cache-control", "public, max-age=60, no-cache")],
+                None,
+            ),
+            (
+                vec![("cache-control", "private, max-age=60, immutable")],
+                None,
+            ),
+            (vec![IMMUTABLE, ("set-cookie", "session=1")], None),
+            (vec![IMMUTABLE, ("vary", "accept-encoding, *")], None),
+            (vec![IMMUTABLE, ("content-range", "bytes 0-99/1000")], None),
+            (
+                vec![("cache-control", "public, max-age=60, no-transform")],
+                Some(60),
+            ),
+        ] {
+            let resp = headers(&pairs);
+            let expected = expected.map(Duration::from_secs);
+            assert_eq!(freshness(StatusCode::OK, &resp), expected, "{pairs:?}");
+        }
+
+        let resp = headers(&[IMMUTABLE]);
+        for status in [
+            StatusCode::PARTIAL_CONTENT,
+            StatusCode::NOT_FOUND,
+            StatusCode::NOT_MODIFIED,
+        ] {
+            assert_eq!(freshness(status, &resp), None, "{status}");
+        }
+    }
+
+    #[test]
+    fn test_cacheable_request() {
+        assert!(is_cacheable_request(&Method::GET, &HeaderMap::new()));
+        assert!(!is_cacheable_request(&Method::HEAD, &HeaderMap::new()));
+        assert!(!is_cacheable_request(&Method::POST, &HeaderMap::new()));
+        assert!(!is_cacheable_request(
+            &Method::GET,
+            &headers(&[("range", "bytes=0-99")])
+        ));
+        assert!(!is_cacheable_request(
+            &Method::GET,
+            &headers(&[("cache-control", "no-store")])
+        ));
+
+        assert!(!bypasses_cache(&HeaderMap::new()));
+        assert!(!bypasses_cache(&headers(&[("cache-control", "max-age=0")])));
+        assert!(bypasses_cache(&headers(&[("cache-control", "no-cache")])));
+        assert!(bypasses_cache(&headers(&[("pragma", "no-cache")])));
+    }
+
+    #[test]
+    fn test_store_and_lookup() {
+        let cache = ResponseCache::new(1024 * 1024, 1024);
+        let req = HeaderMap::new();
+        let resp = headers(&[
+            IMMUTABLE,
+            ("content-type", "application/javascript"),
+            ("transfer-encoding", "chunked"),
+            ("connection", "keep-alive"),
+            ("age", "100"),
+        ]);
+        store(
+            &cache,
+            key("app", "/_next/static/app.js"),
+            &req,
+            &resp,
+            b"console.log(1)",
+        );
+
+        let hit = cache
+            .lookup(&key("app", "/_next/static/app.js"), 1, &req)
+            .unwrap();
+        assert_eq!(hit.status, StatusCode::OK);
+        assert_eq!(&hit.body[..], b"console.log(1)");
+        assert_eq!(hit.headers["content-length"], "14");
+        assert_eq!(hit.headers["content-type"], "application/javascript");
+        for name in ["transfer-encoding", "connection", "age"] {
+            assert!(!hit.headers.contains_key(name), "{name}");
+        }
+        assert_eq!(hit.age(), 0);
+
+        // Other targets, ports, devboxes and endpoints miss
+        assert!(cache
+            .lookup(&key("app", "/_next/static/app.js?v=2"), 1, &req)
+            .is_none());
+        assert!(cache
+            .lookup(&key("other", "/_next/static/app.js"), 1, &req)
+            .is_none());
+        let other_port = CacheKey {
+            port: 8080,
+            ..key("app", "/_next/static/app.js")
+        };
+        assert!(cache.lookup(&other_port, 1, &req).is_none());
+        assert!(cache
+            .lookup(&key("app", "/_next/static/app.js"), 2, &req)
+            .is_none());
+        // The stale endpoint's response is gone for good
+        assert!(cache
+            .lookup(&key("app", "/_next/static/app.js"), 1, &req)
+            .is_none());
+        assert_eq!(cache.response_count(), 0);
+        assert_eq!(cache.size_bytes(), 0);
+    }
+
+    #[test]
+    fn test_vary() {
+        let cache = ResponseCache::new(1024 * 1024, 1024);
+        let resp = headers(&[IMMUTABLE, ("vary", "Accept-Encoding")]);
+        let gzip = headers(&[("accept-encoding", "gzip")]);
+        let br = headers(&[("accept-encoding", "br")]);
+        store(&cache, key("app", "/app.js"), &gzip, &resp, b"gzip");
+        store(&cache, key("app", "/app.js"), &br, &resp, b"br");
+        store(&cache, key("app", "/app.js"), &br, &resp, b"br again");
+
+        let lookup = |req: &HeaderMap| cache.lookup(&key("app", "/app.js"), 1, req);
+        assert_eq!(&lookup(&gzip).unwrap().body[..], b"gzip");
+        assert_eq!(&lookup(&br).unwrap().body[..], b"br again");
+        assert!(lookup(&HeaderMap::new()).is_none());
+        assert_eq!(cache.response_count(), 2);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let cache = ResponseCache::new(1024 * 1024, 16);
+        let req = HeaderMap::new();
+
+        // Declared too large
+        let resp = headers(&[IMMUTABLE, ("content-length", "17")]);
+        assert!(cache
+            .start_fill(key("app", "/big.js"), 1, &req, StatusCode::OK, &resp)
+            .is_none());
+
+        // Streamed too large
+        let resp = headers(&[IMMUTABLE]);
+        let mut fill = cache
+            .start_fill(key("app", "/big.js"), 1, &req, StatusCode::OK, &resp)
+            .unwrap();
+        assert!(fill.push(&[0; 10]));
+        assert!(!fill.push(&[0; 10]));
+
+        // Objects are never larger than the whole cache
+        assert_eq!(ResponseCache::new(100, 1000).max_object_bytes, 100);
+    }
+
+    #[test]
+    fn test_expired() {
+        let cache = ResponseCache::new(1024 * 1024, 1024);
+        let req = HeaderMap::new();
+        let resp = headers(&[IMMUTABLE]);
+        let mut fill = cache
+            .start_fill(key("app", "/app.js"), 1, &req, StatusCode::OK, &resp)
+            .unwrap();
+        fill.ttl = Duration::ZERO;
+        cache.finish_fill(fill, &devbox("devbox1"));
+
+        assert!(cache.lookup(&key("app", "/app.js"), 1, &req).is_none());
+        assert_eq!(cache.response_count(), 0);
+        assert_eq!(cache.size_bytes(), 0);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let req = HeaderMap::new();
+        let resp = headers(&[IMMUTABLE]);
+        let size = {
+            let cache = ResponseCache::new(1024 * 1024, 1024);
+            store(&cache, key("app", "/a.js"), &req, &resp, &[0; 100]);
+            cache.size_bytes()
+        };
+
+        // Room for three responses
+        let cache = ResponseCache::new(size * 3, 1024);
+        for target in ["/a.js", "/b.js", "/c.js"] {
+            store(&cache, key("app", target), &req, &resp, &[0; 100]);
+        }
+        assert_eq!(cache.size_bytes(), size * 3);
+
+        // Using a.js makes b.js the least recently used
+        assert!(cache.lookup(&key("app", "/a.js"), 1, &req).is_some());
+        store(&cache, key("app", "/d.js"), &req, &resp, &[0; 100]);
+        assert!(cache.lookup(&key("app", "/b.js"), 1, &req).is_none());
+        for target in ["/a.js", "/c.js", "/d.js"] {
+            assert!(
+                cache.lookup(&key("app", target), 1, &req).is_some(),
+                "{target}"
+            );
+        }
+        assert_eq!(cache.size_bytes(), size * 3);
+        assert_eq!(cache.response_count(), 3);
+    }
+
+    #[test]
+    fn test_enabled_for() {
+        let unset = DevboxPolicy::default();
+        let on = DevboxPolicy {
+            response_cache: Some(true),
+            ..Default::default()
+        };
+        let off = DevboxPolicy {
+            response_cache: Some(false),
+            ..Default::default()
+        };
+
+        let cache = ResponseCache::new(1024, 1024);
+        assert!(cache.enabled_for(&unset));
+        assert!(cache.enabled_for(&on));
+        assert!(!cache.enabled_for(&off));
+
+        let cache = cache.with_require_annotation(true);
+        assert!(!cache.enabled_for(&unset));
+        assert!(cache.enabled_for(&on));
+        assert!(!cache.enabled_for(&off));
+    }
+
+    #[test]
+    fn test_purge() {
+        let cache = ResponseCache::new(1024 * 1024, 1024);
+        let req = HeaderMap::new();
+        let resp = headers(&[IMMUTABLE]);
+        store(&cache, key("app", "/a.js"), &req, &resp, b"a");
+        store(&cache, key("app", "/b.js"), &req, &resp, b"b");
+        store(&cache, key("other", "/a.js"), &req, &resp, b"a");
+
+        assert_eq!(cache.purge("app"), 2);
+        assert_eq!(cache.purge("app"), 0);
+        assert!(cache.lookup(&key("app", "/a.js"), 1, &req).is_none());
+        assert!(cache.lookup(&key("other", "/a.js"), 1, &req).is_some());
+
+        assert_eq!(cache.purge_all(), 1);
+        assert_eq!(cache.response_count(), 0);
+        assert_eq!(cache.size_bytes(), 0);
+    }
+
+    #[test]
+    fn test_purged_on_registry_changes() {
+        let registry = DevboxRegistry::new();
+        let cache = Arc::new(ResponseCache::new(1024 * 1024, 1024));
+        registry.add_observer(Arc::clone(&cache) as Arc<dyn RegistryObserver>);
+        registry.register_devbox("app".to_string(), "ns-1".to_string(), "devbox1".to_string());
+        registry.register_devbox(
+            "other".to_string(),
+            "ns-1".to_string(),
+            "devbox2".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        registry
+            .update_pod_ip("ns-1", "devbox2", "10.0.0.2".to_string())
+            .unwrap();
+
+        let req = HeaderMap::new();
+        let resp = headers(&[IMMUTABLE]);
+        let fill = |unique_id: &str, name: &str| {
+            let mut fill = cache
+                .start_fill(key(unique_id, "/a.js"), 1, &req, StatusCode::OK, &resp)
+                .unwrap();
+            fill.push(b"a");
+            cache.finish_fill(fill, &devbox(name));
+        };
+        fill("app", "devbox1");
+        fill("other", "devbox2");
+
+        // Pod restarted with a new IP
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.3".to_string())
+            .unwrap();
+        assert!(cache.lookup(&key("app", "/a.js"), 1, &req).is_none());
+        assert!(cache.lookup(&key("other", "/a.js"), 1, &req).is_some());
+
+        // Devbox deleted
+        registry.unregister_devbox("other");
+        assert_eq!(cache.response_count(), 0);
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer};
+
+/// An IPv4 or IPv6 address range, e.g. `10.0.0.0/8` or `fd00::/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Whether `ip` is in the range. IPv4-mapped IPv6 addresses match IPv4
+    /// ranges.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Address ranges a client must be in, e.g. from a comma-separated list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CidrSet {
+    cidrs: Vec<Cidr>,
+}
+
+impl CidrSet {
+    /// Parse a comma-separated list of ranges, failing on any invalid one.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut cidrs = value
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Cidr>, _>>()?;
+        cidrs.sort_by_key(|cidr| (cidr.addr, cidr.prefix_len));
+        cidrs.dedup();
+        Ok(Self { cidrs })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cidrs.is_empty()
+    }
+
+    /// Whether `ip` is in any of the ranges.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.cidrs.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// Parse `<addr>/<prefix_len>`, or a bare address as a single-host range.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address in {s:?}"))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse()
+                .ok()
+                .filter(|&len| len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length in {s:?}"))?,
+            None => max_len,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl Serialize for Cidr {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(cidr: &str, ip: &str) -> bool {
+        cidr.parse::<Cidr>().unwrap().contains(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn test_contains() {
+        assert!(contains("10.0.0.0/8", "10.1.2.3"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.1"));
+        assert!(contains("192.168.1.0/24", "192.168.1.255"));
+        assert!(!contains("192.168.1.0/24", "192.168.2.1"));
+        assert!(contains("0.0.0.0/0", "8.8.8.8"));
+        assert!(contains("10.0.0.5", "10.0.0.5"));
+        assert!(!contains("10.0.0.5", "10.0.0.6"));
+
+        assert!(contains("fd00::/8", "fd12::1"));
+        assert!(!contains("fd00::/8", "fe80::1"));
+        assert!(contains("::/0", "2001:db8::1"));
+
+        // IPv4-mapped IPv6 clients match IPv4 ranges, families never mix otherwise
+        assert!(contains("10.0.0.0/8", "::ffff:10.0.0.1"));
+        assert!(!contains("::/0", "10.0.0.1"));
+        assert!(!contains("0.0.0.0/0", "2001:db8::1"));
+    }
+
+    #[test]
+    fn test_cidr_set() {
+        let set = CidrSet::parse("10.8.0.0/16, 203.0.113.0/24,2001:db8::/32,,").unwrap();
+        // Boundaries of each range
+        for inside in [
+            "10.8.0.0",
+            "10.8.255.255",
+            "203.0.113.0",
+            "203.0.113.255",
+            "2001:db8::",
+            "2001:db8:ffff:ffff:ffff:ffff:ffff:ffff",
+            "::ffff:10.8.1.1",
+        ] {
+            assert!(set.contains(inside.parse().unwrap()), "{inside}");
+        }
+        for outside in [
+            "10.7.255.255",
+            "10.9.0.0",
+            "203.0.112.255",
+            "203.0.114.0",
+            "2001:db7:ffff::1",
+            "2001:db9::",
+        ] {
+            assert!(!set.contains(outside.parse().unwrap()), "{outside}");
+        }
+
+        assert!(CidrSet::parse(" , ").unwrap().is_empty());
+        assert!(CidrSet::parse("10.0.0.0/8,10.0.0.0/33").is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "10.0.0.0/8".parse::<Cidr>().unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert_eq!(
+            "fd00::1".parse::<Cidr>().unwrap().to_string(),
+            "fd00::1/128"
+        );
+        for invalid in [
+            "",
+            "10.0.0.0/33",
+            "fd00::/129",
+            "10.0.0/8",
+            "10.0.0.0/x",
+            "/8",
+        ] {
+            assert!(invalid.parse::<Cidr>().is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,568 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, RwLock};
+
+use dashmap::DashMap;
+use pingora_core::Result;
+use pingora_http::RequestHeader;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, UnixTime};
+use tokio_rustls::rustls::server::danger::ClientCertVerifier;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::RootCertStore;
+
+use crate::tls_reload::{self, der_element};
+
+/// Header carrying the subject of the client's verified certificate
+pub const X_CLIENT_CERT_SUBJECT: &str = "x-client-cert-subject";
+
+/// Header carrying the subjectAltNames of the client's verified certificate
+pub const X_CLIENT_CERT_SAN: &str = "x-client-cert-san";
+
+/// Label marking the Secrets and ConfigMaps holding client CA bundles, so
+/// the gateway only watches those
+pub const CLIENT_CA_LABEL: &str = "devbox.sealos.io/client-ca-bundle";
+
+/// Key of the PEM bundle in a client CA Secret or ConfigMap
+pub const CLIENT_CA_KEY: &str = "ca.crt";
+
+/// Certificate chains presented by clients of listeners with
+/// `client_cert=true`, keyed by the socket peer address of each live
+/// connection.
+#[derive(Debug, Default)]
+pub struct ClientCerts {
+    by_peer: DashMap<SocketAddr, Arc<[CertificateDer<'static>]>>,
+}
+
+impl ClientCerts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Chain presented on the connection from `peer` (leaf first), if any.
+    pub fn get(&self, peer: &SocketAddr) -> Option<Arc<[CertificateDer<'static>]>> {
+        self.by_peer.get(peer).map(|r| Arc::clone(r.value()))
+    }
+
+    pub(crate) fn insert(&self, peer: SocketAddr, chain: Vec<CertificateDer<'static>>) {
+        self.by_peer.insert(peer, chain.into());
+    }
+
+    pub(crate) fn remove(&self, peer: &SocketAddr) {
+        self.by_peer.remove(peer);
+    }
+}
+
+/// Kind of object a client CA bundle is read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CaSource {
+    Secret,
+    ConfigMap,
+}
+
+impl CaSource {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Secret => "secret",
+            Self::ConfigMap => "configmap",
+        }
+    }
+}
+
+/// The Secret or ConfigMap, in the devbox's namespace, holding the CA
+/// bundle its clients' certificates must chain to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClientCaRef {
+    pub source: CaSource,
+    pub name: String,
+}
+
+impl ClientCaRef {
+    /// Parse `secret/<name>`, `configmap/<name>` or a bare Secret name.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let (source, name) = match value.split_once('/') {
+            Some(("secret", name)) => (CaSource::Secret, name),
+            Some(("configmap", name)) => (CaSource::ConfigMap, name),
+            Some((kind, _)) => return Err(format!("unknown kind {kind:?}")),
+            None => (CaSource::Secret, value),
+        };
+        let valid = !name.is_empty()
+            && name.len() <= 253
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || matches!(b, b'-' | b'.'));
+        if !valid {
+            return Err(format!("invalid name {name:?}"));
+        }
+        Ok(Self {
+            source,
+            name: name.to_string(),
+        })
+    }
+
+    /// A reference no bundle is ever loaded for, so no certificate is
+    /// accepted, for devboxes whose annotation is invalid.
+    pub fn deny_all() -> Self {
+        Self {
+            source: CaSource::Secret,
+            name: String::new(),
+        }
+    }
+}
+
+impl fmt::Display for ClientCaRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.source.as_str(), self.name)
+    }
+}
+
+/// Why a request to a devbox with a `client-ca` annotation was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientCertError {
+    /// The connection presented no certificate
+    Missing,
+    /// The devbox's CA bundle isn't loaded (missing, unlabeled or invalid)
+    NoBundle,
+    /// The certificate doesn't chain to the bundle, or has expired
+    Invalid(String),
+}
+
+impl ClientCertError {
+    pub const fn label(&self) -> &'static str {
+        match self {
+            Self::Missing => "missing",
+            Self::NoBundle => "no_bundle",
+            Self::Invalid(_) => "invalid",
+        }
+    }
+}
+
+impl fmt::Display for ClientCertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => f.write_str("no client certificate presented"),
+            Self::NoBundle => f.write_str("client CA bundle not loaded"),
+            Self::Invalid(e) => write!(f, "client certificate rejected: {e}"),
+        }
+    }
+}
+
+/// A client certificate verified against the CA bundle of a devbox.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertInfo {
+    /// Subject, as an RFC 4514 string (e.g., "CN=alice,O=Example")
+    pub subject: String,
+    /// subjectAltNames, comma-separated with their type (e.g.,
+    /// "DNS:alice.example.com, email:alice@example.com")
+    pub san: String,
+}
+
+impl ClientCertInfo {
+    pub fn from_cert(cert: &CertificateDer<'_>) -> Self {
+        Self {
+            subject: tls_reload::subject(cert)
+                .and_then(format_name)
+                .unwrap_or_default(),
+            san: tls_reload::subject_alt_names(cert)
+                .map(|names| format_alt_names(&names))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Set the `X-Client-Cert-*` headers of `req` to this certificate,
+    /// replacing any the client sent.
+    pub fn apply(&self, req: &mut RequestHeader) -> Result<()> {
+        strip_headers(req);
+        req.insert_header(X_CLIENT_CERT_SUBJECT, &self.subject)?;
+        if !self.san.is_empty() {
+            req.insert_header(X_CLIENT_CERT_SAN, &self.san)?;
+        }
+        Ok(())
+    }
+}
+
+/// Remove the `X-Client-Cert-*` headers from `req`, so a devbox only sees
+/// those of a certificate the gateway verified.
+pub fn strip_headers(req: &mut RequestHeader) {
+    req.remove_header(X_CLIENT_CERT_SUBJECT);
+    req.remove_header(X_CLIENT_CERT_SAN);
+}
+
+/// Kind, namespace and name of the object a bundle was read from
+type BundleKey = (CaSource, String, String);
+
+/// Client CA bundles of the labeled Secrets and ConfigMaps (see
+/// [`CLIENT_CA_LABEL`]), kept current by
+/// [`crate::watcher::ClientCaWatcher`].
+#[derive(Default)]
+pub struct ClientCaStore {
+    verifiers: RwLock<HashMap<BundleKey, Arc<dyn ClientCertVerifier>>>,
+}
+
+impl ClientCaStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept certificates chaining to the PEM `bundle` for the devboxes of
+    /// `namespace` referencing `name`. Returns the number of CA
+    /// certificates loaded; an invalid bundle leaves the previous one.
+    pub fn load(
+        &self,
+        source: CaSource,
+        namespace: &str,
+        name: &str,
+        bundle: &[u8],
+    ) -> Result<usize, String> {
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut &bundle[..]) {
+            let cert = cert.map_err(|e| format!("invalid certificate PEM: {e}"))?;
+            roots
+                .add(cert)
+                .map_err(|e| format!("invalid CA certificate: {e}"))?;
+        }
+        let count = roots.len();
+        if count == 0 {
+            return Err("no certificate found".to_string());
+        }
+        let verifier = WebPkiClientVerifier::builder_with_provider(
+            Arc::new(roots),
+            Arc::new(ring::default_provider()),
+        )
+        .build()
+        .map_err(|e| e.to_string())?;
+        self.verifiers
+            .write()
+            .unwrap()
+            .insert((source, namespace.to_string(), name.to_string()), verifier);
+        Ok(count)
+    }
+
+    /// Stop accepting the bundle of `name` in `namespace`.
+    pub fn remove(&self, source: CaSource, namespace: &str, name: &str) {
+        self.verifiers
+            .write()
+            .unwrap()
+            .remove(&(source, namespace.to_string(), name.to_string()));
+    }
+
+    /// Drop the bundles from `source` in `namespace` (every namespace if
+    /// `None`) other than those of `listed`, after a relist.
+    pub fn retain(
+        &self,
+        source: CaSource,
+        namespace: Option<&str>,
+        listed: &HashSet<(String, String)>,
+    ) {
+        self.verifiers.write().unwrap().retain(|(s, ns, name), _| {
+            *s != source
+                || namespace.is_some_and(|namespace| namespace != ns)
+                || listed.contains(&(ns.clone(), name.clone()))
+        });
+    }
+
+    /// Whether a bundle is loaded for `ca` in `namespace`.
+    pub fn contains(&self, namespace: &str, ca: &ClientCaRef) -> bool {
+        self.verifier(namespace, ca).is_some()
+    }
+
+    /// Verify `chain` (leaf first) against the bundle `ca` of a devbox in
+    /// `namespace`.
+    pub fn verify(
+        &self,
+        namespace: &str,
+        ca: &ClientCaRef,
+        chain: Option<&[CertificateDer<'_>]>,
+    ) -> Result<ClientCertInfo, ClientCertError> {
+        let (leaf, intermediates) = chain
+            .and_then(<[_]>::split_first)
+            .ok_or(ClientCertError::Missing)?;
+        let verifier = self
+            .verifier(namespace, ca)
+            .ok_or(ClientCertError::NoBundle)?;
+        verifier
+            .verify_client_cert(leaf, intermediates, UnixTime::now())
+            .map_err(|e| ClientCertError::Invalid(e.to_string()))?;
+        Ok(ClientCertInfo::from_cert(leaf))
+    }
+
+    fn verifier(&self, namespace: &str, ca: &ClientCaRef) -> Option<Arc<dyn ClientCertVerifier>> {
+        self.verifiers
+            .read()
+            .unwrap()
+            .get(&(ca.source, namespace.to_string(), ca.name.clone()))
+            .cloned()
+    }
+}
+
+impl fmt::Debug for ClientCaStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verifiers = self.verifiers.read().unwrap();
+        f.debug_struct("ClientCaStore")
+            .field("bundles", &verifiers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// RFC 4514 string of the content of a DER `Name`: its attributes from the
+/// last RDN to the first, with the short names of the common ones.
+fn format_name(mut name: &[u8]) -> Option<String> {
+    let mut rdns = Vec::new();
+    while !name.is_empty() {
+        let (mut rdn, after) = der_element(name, 0x31)?;
+        name = after;
+        let mut attributes = Vec::new();
+        while !rdn.is_empty() {
+            let (attribute, after) = der_element(rdn, 0x30)?;
+            rdn = after;
+            let (oid, value) = der_element(attribute, 0x06)?;
+            // Any string type; its content is the text
+            let (value, _) = der_element(value, *value.first()?)?;
+            attributes.push(format!("{}={}", attribute_name(oid), escape(value, true)));
+        }
+        rdns.push(attributes.join("+"));
+    }
+    rdns.reverse();
+    Some(rdns.join(","))
+}
+
+/// Short name of an attribute type, or its dotted OID
+fn attribute_name(oid: &[u8]) -> String {
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        // pkcs-9 emailAddress (1.2.840.113549.1.9.1)
+        [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01] => "emailAddress".to_string(),
+        _ => dotted_oid(oid),
+    }
+}
+
+fn dotted_oid(oid: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut arc: u64 = 0;
+    for &b in oid {
+        arc = arc << 7 | u64::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (arc / 40).min(2);
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+    arcs.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// subjectAltNames as `type:value`, comma-separated; other types than DNS
+/// names, emails, URIs and IP addresses are left out
+fn format_alt_names(names: &[(u8, &[u8])]) -> String {
+    names
+        .iter()
+        .filter_map(|&(tag, name)| match tag {
+            0x81 => Some(format!("email:{}", escape(name, false))),
+            0x82 => Some(format!("DNS:{}", escape(name, false))),
+            0x86 => Some(format!("URI:{}", escape(name, false))),
+            0x87 => {
+                let ip = match name.len() {
+                    4 => Ipv4Addr::from(<[u8; 4]>::try_from(name).ok()?).to_string(),
+                    16 => Ipv6Addr::from(<[u8; 16]>::try_from(name).ok()?).to_string(),
+                    _ => return None,
+                };
+                Some(format!("IP:{ip}"))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `value` as header-safe text: bytes outside printable ASCII as `\XX`, and
+/// in distinguished names the characters RFC 4514 escapes with a backslash
+fn escape(value: &[u8], dn: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for (i, &b) in value.iter().enumerate() {
+        let special = dn
+            && (matches!(b, b',' | b'+' | b'"' | b'\\' | b'<' | b'>' | b';' | b'=')
+                || (i == 0 && matches!(b, b'#' | b' '))
+                || (i == value.len() - 1 && b == b' '));
+        if special {
+            escaped.push('\\');
+            escaped.push(char::from(b));
+        } else if b.is_ascii_graphic() || b == b' ' {
+            escaped.push(char::from(b));
+        } else {
+            escaped.push_str(&format!("\\{b:02X}"));
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CA: &[u8] = include_bytes!("../tests/fixtures/client-ca.crt");
+    const CLIENT: &[u8] = include_bytes!("../tests/fixtures/client.crt");
+    const OTHER_CLIENT: &[u8] = include_bytes!("../tests/fixtures/other-client.crt");
+
+    fn chain(pem: &[u8]) -> Vec<CertificateDer<'static>> {
+        rustls_pemfile::certs(&mut &pem[..])
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn store() -> ClientCaStore {
+        let store = ClientCaStore::new();
+        assert_eq!(store.load(CaSource::Secret, "ns-a", "client-ca", CA), Ok(1));
+        store
+    }
+
+    #[test]
+    fn test_parse_ref() {
+        for (value, expected) in [
+            ("client-ca", Some((CaSource::Secret, "client-ca"))),
+            (" secret/client-ca ", Some((CaSource::Secret, "client-ca"))),
+            (
+                "configmap/ca.bundle",
+                Some((CaSource::ConfigMap, "ca.bundle")),
+            ),
+            ("pod/client-ca", None),
+            ("secret/", None),
+            ("", None),
+            ("Client_CA", None),
+        ] {
+            let parsed = ClientCaRef::parse(value).ok();
+            let expected = expected.map(|(source, name)| ClientCaRef {
+                source,
+                name: name.to_string(),
+            });
+            assert_eq!(parsed, expected, "{value:?}");
+        }
+        assert_eq!(
+            ClientCaRef::parse("configmap/ca").unwrap().to_string(),
+            "configmap/ca"
+        );
+    }
+
+    #[test]
+    fn test_verify_accepts_chain_to_bundle() {
+        let store = store();
+        let ca = ClientCaRef::parse("client-ca").unwrap();
+        let info = store.verify("ns-a", &ca, Some(&chain(CLIENT))).unwrap();
+        assert_eq!(info.subject, "CN=alice,O=Example");
+        assert_eq!(info.san, "DNS:alice.example.com, email:alice@example.com");
+    }
+
+    #[test]
+    fn test_verify_rejects() {
+        let store = store();
+        let ca = ClientCaRef::parse("client-ca").unwrap();
+
+        // Issued by another CA
+        let e = store.verify("ns-a", &ca, Some(&chain(OTHER_CLIENT)));
+        assert!(matches!(e, Err(ClientCertError::Invalid(_))), "{e:?}");
+
+        // No certificate
+        assert_eq!(
+            store.verify("ns-a", &ca, None),
+            Err(ClientCertError::Missing)
+        );
+        assert_eq!(
+            store.verify("ns-a", &ca, Some(&[])),
+            Err(ClientCertError::Missing)
+        );
+
+        // The bundle of another namespace, kind or name isn't used
+        let client = chain(CLIENT);
+        for (namespace, value) in [
+            ("ns-b", "client-ca"),
+            ("ns-a", "configmap/client-ca"),
+            ("ns-a", "other-ca"),
+        ] {
+            let ca = ClientCaRef::parse(value).unwrap();
+            assert_eq!(
+                store.verify(namespace, &ca, Some(&client)),
+                Err(ClientCertError::NoBundle),
+                "{namespace} {value}"
+            );
+        }
+        assert_eq!(
+            store.verify("ns-a", &ClientCaRef::deny_all(), Some(&client)),
+            Err(ClientCertError::NoBundle)
+        );
+    }
+
+    #[test]
+    fn test_bundle_reload() {
+        let store = store();
+        let ca = ClientCaRef::parse("client-ca").unwrap();
+
+        // An invalid bundle keeps the previous one
+        assert!(store
+            .load(CaSource::Secret, "ns-a", "client-ca", b"not a bundle")
+            .is_err());
+        assert!(store.contains("ns-a", &ca));
+
+        // Bundles not seen in a relist of their namespace are dropped
+        store
+            .load(CaSource::Secret, "ns-b", "client-ca", CA)
+            .unwrap();
+        store.retain(CaSource::Secret, Some("ns-a"), &HashSet::new());
+        assert!(!store.contains("ns-a", &ca));
+        assert!(store.contains("ns-b", &ca));
+        store.retain(CaSource::ConfigMap, None, &HashSet::new());
+        assert!(store.contains("ns-b", &ca));
+
+        store.remove(CaSource::Secret, "ns-b", "client-ca");
+        assert!(!store.contains("ns-b", &ca));
+    }
+
+    #[test]
+    fn test_format_name() {
+        let cert = &chain(CLIENT)[0];
+        assert_eq!(
+            tls_reload::subject(cert).and_then(format_name).as_deref(),
+            Some("CN=alice,O=Example")
+        );
+        assert_eq!(escape(b"a,b=c", true), "a\\,b\\=c");
+        assert_eq!(escape(b" #x ", true), "\\ #x\\ ");
+        assert_eq!(escape("é\n".as_bytes(), false), "\\C3\\A9\\0A");
+        assert_eq!(
+            dotted_oid(&[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d]),
+            "1.2.840.113549"
+        );
+    }
+
+    #[test]
+    fn test_apply_headers() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header(X_CLIENT_CERT_SUBJECT, "CN=admin")
+            .unwrap();
+        req.insert_header(X_CLIENT_CERT_SAN, "DNS:admin").unwrap();
+        strip_headers(&mut req);
+        assert!(req.headers.get(X_CLIENT_CERT_SUBJECT).is_none());
+        assert!(req.headers.get(X_CLIENT_CERT_SAN).is_none());
+
+        let info = ClientCertInfo::from_cert(&chain(CLIENT)[0]);
+        req.insert_header(X_CLIENT_CERT_SUBJECT, "CN=admin")
+            .unwrap();
+        info.apply(&mut req).unwrap();
+        assert_eq!(req.headers[X_CLIENT_CERT_SUBJECT], "CN=alice,O=Example");
+        assert_eq!(
+            req.headers[X_CLIENT_CERT_SAN],
+            "DNS:alice.example.com, email:alice@example.com"
+        );
+    }
+}
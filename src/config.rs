@@ -1,35 +1,2469 @@
-use std::net::SocketAddr;
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
 
-#[derive(Debug, Clone)]
+use http::HeaderName;
+use pingora_core::server::configuration::{Opt, ServerConf};
+use regex::Regex;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::cidr::Cidr;
+use crate::deadline;
+use crate::identity::IdentityHeaders;
+use crate::legacy_host::LegacyHosts;
+use crate::locale;
+use crate::registry::{self, DEFAULT_CLUSTER};
+use crate::response_limit;
+use crate::routing;
+
+/// How `Expect: 100-continue` requests are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExpectContinueMode {
+    /// Forward the `Expect` header and relay the backend's interim response
+    #[default]
+    Relay,
+    /// Send `100 Continue` from the gateway once the backend is resolved
+    Gateway,
+}
+
+impl FromStr for ExpectContinueMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "relay" => Ok(Self::Relay),
+            "gateway" => Ok(Self::Gateway),
+            other => Err(format!("unknown expect-continue mode: {other}")),
+        }
+    }
+}
+
+/// Which type Devbox watch events are deserialized into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DevboxWatchMode {
+    /// The whole [`crate::crd::Devbox`]
+    #[default]
+    Full,
+    /// Only the fields routing reads ([`crate::crd::SlimDevbox`]), unless
+    /// `POD_IP_STATUS_FIELD` needs the rest of the status
+    Slim,
+}
+
+impl FromStr for DevboxWatchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Self::Full),
+            "slim" => Ok(Self::Slim),
+            other => Err(format!("unknown devbox watch mode: {other}")),
+        }
+    }
+}
+
+/// What watchers do with registry entries imported through the admin API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImportedEntries {
+    /// Replace and remove them like their own, so the clusters win once
+    /// they're reachable
+    #[default]
+    Overwrite,
+    /// Leave them alone until another import replaces them
+    Respect,
+}
+
+impl FromStr for ImportedEntries {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "overwrite" => Ok(Self::Overwrite),
+            "respect" => Ok(Self::Respect),
+            other => Err(format!("unknown imported entries mode: {other}")),
+        }
+    }
+}
+
+/// Default interval of the pod IP consistency sweep
+const DEFAULT_POD_IP_GC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Default age after which pod IP entries are re-verified
+const DEFAULT_POD_IP_VERIFY_TTL: Duration = Duration::from_secs(600);
+
+/// Which requests are retried after an upstream connection failure
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RetryPolicy {
+    /// Only idempotent methods (GET, HEAD, PUT, DELETE, OPTIONS)
+    #[default]
+    Idempotent,
+    /// Idempotent methods and requests carrying an `Idempotency-Key` header
+    IdempotencyKey,
+    /// Every request
+    All,
+}
+
+impl FromStr for RetryPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "idempotent" => Ok(Self::Idempotent),
+            "idempotency-key" => Ok(Self::IdempotencyKey),
+            "all" => Ok(Self::All),
+            other => Err(format!("unknown retry policy: {other}")),
+        }
+    }
+}
+
+/// Default bound on deadlines set with `X-Request-Timeout-Ms`
+const DEFAULT_MAX_REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Default number of retries after an upstream connection failure
+const DEFAULT_UPSTREAM_CONNECT_RETRIES: usize = 1;
+
+/// Default time after the request arrived within which a failed GET or
+/// HEAD may still fail over to the devbox's replacement Pod
+const DEFAULT_UPSTREAM_FAILOVER_BUDGET: Duration = Duration::from_secs(5);
+
+/// Default methods allowed in CORS preflight responses
+const DEFAULT_CORS_ALLOWED_METHODS: &str = "GET, POST, PUT, PATCH, DELETE, OPTIONS";
+
+/// Default lifetime of cached CORS preflight responses
+const DEFAULT_CORS_MAX_AGE: Duration = Duration::from_secs(600);
+
+/// Where devbox activity is reported besides the admin API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ActivityReporting {
+    /// Only through `GET /activity` on the admin API
+    #[default]
+    Off,
+    /// Also as an annotation on the Devbox resource
+    Crd,
+}
+
+impl FromStr for ActivityReporting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "crd" => Ok(Self::Crd),
+            other => Err(format!("unknown activity reporting mode: {other}")),
+        }
+    }
+}
+
+/// Body format of gateway-generated error responses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorFormat {
+    /// `text/plain` message
+    #[default]
+    Plain,
+    /// `application/json` object with a machine-readable error code
+    Json,
+    /// `text/html` page
+    Html,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(Self::Plain),
+            "json" => Ok(Self::Json),
+            "html" => Ok(Self::Html),
+            other => Err(format!("unknown error format: {other}")),
+        }
+    }
+}
+
+/// Services besides the proxy listeners that expect a PROXY protocol
+/// header, independently of `PROXY_PROTOCOL`.
+///
+/// Parsed from a comma-separated list of service names, e.g. `metrics,admin`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ServiceProxyProtocol {
+    /// The Prometheus metrics endpoint (`METRICS_ADDR`)
+    pub metrics: bool,
+    /// The admin API (`ADMIN_ADDR`)
+    pub admin: bool,
+}
+
+impl FromStr for ServiceProxyProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut services = Self::default();
+        for name in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match name {
+                "metrics" => services.metrics = true,
+                "admin" => services.admin = true,
+                other => return Err(format!("unknown service: {other}")),
+            }
+        }
+        Ok(services)
+    }
+}
+
+/// Default minimum interval between activity patches of one devbox
+const DEFAULT_ACTIVITY_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Default time without requests after which a devbox is reported idle to
+/// `IDLE_WEBHOOK_URL`
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Default minimum interval between Kubernetes Events of one reason on one
+/// devbox
+const DEFAULT_K8S_EVENTS_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Default number of hot backends remembered for the startup warm-up
+const DEFAULT_WARMUP_BACKENDS: usize = 50;
+
+/// Default number of concurrent warm-up connections
+const DEFAULT_WARMUP_CONCURRENCY: usize = 8;
+
+/// Default time limit of the startup warm-up
+const DEFAULT_WARMUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default size limit of one cached response
+const DEFAULT_CACHE_MAX_OBJECT_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Default largest request body mirrored
+const DEFAULT_MIRROR_MAX_BODY_BYTES: u64 = 64 * 1024;
+
+/// Default time a mirrored request may take
+const DEFAULT_MIRROR_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default delay before the headers of a tarpitted response
+const DEFAULT_TARPIT_HEADER_DELAY: Duration = Duration::from_secs(10);
+
+/// Default interval between the body bytes of a tarpitted response
+const DEFAULT_TARPIT_BODY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Default body size of a tarpitted response
+const DEFAULT_TARPIT_BODY_BYTES: usize = 30;
+
+/// Default number of requests held in the tarpit at once
+const DEFAULT_TARPIT_MAX_CONNECTIONS: usize = 256;
+
+/// Default status of blocked requests
+const DEFAULT_BLOCKED_STATUS: u16 = 403;
+
+/// Default body of blocked requests
+const DEFAULT_BLOCKED_MESSAGE: &str = "access to this devbox has been blocked";
+
+/// Default largest `Retry-After` of 503s for devboxes that are not running
+const DEFAULT_NOT_RUNNING_RETRY_AFTER_MAX: Duration = Duration::from_secs(60);
+
+/// Headers whose values are never logged when `SENSITIVE_HEADERS` is not set
+const DEFAULT_SENSITIVE_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-api-key",
+];
+
+fn default_sensitive_headers() -> Vec<String> {
+    DEFAULT_SENSITIVE_HEADERS.map(String::from).to_vec()
+}
+
+/// Default size of a request header block over which it counts as a
+/// protocol anomaly
+const DEFAULT_MAX_REQUEST_HEADER_BYTES: usize = 32 * 1024;
+
+/// Default time after the watchers' initial lists during which unknown
+/// hosts get a 503 rather than a 404
+const DEFAULT_REGISTRY_SYNC_GRACE: Duration = Duration::from_secs(10);
+
+/// Default time a signed route override is accepted for
+const DEFAULT_ROUTE_OVERRIDE_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// Default ACME directory (Let's Encrypt production)
+const DEFAULT_ACME_DIRECTORY_URL: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// Default directory the ACME account and certificates are kept in
+const DEFAULT_ACME_CACHE_DIR: &str = "/var/lib/httpgate/acme";
+
+/// Default time before expiry ACME certificates are renewed
+const DEFAULT_ACME_RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// Default content types exempt from `MAX_RESPONSE_BODY_BYTES`
+const DEFAULT_RESPONSE_LIMIT_EXEMPT_TYPES: &str = response_limit::EVENT_STREAM;
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Config {
     /// Address to listen on (e.g., "0.0.0.0:8080")
     pub listen_addr: SocketAddr,
 
     /// Log level (e.g., "info", "debug", "warn")
     pub log_level: String,
+
+    /// Regex extracting the uniqueID and port (its first two groups) from
+    /// hosts without the `devbox-`/`devboxgrpc-` prefix, for host schemes
+    /// other than `<uniqueID>-<port>.<domain>` (that scheme if unset)
+    #[serde(serialize_with = "serialize_regex")]
+    pub host_pattern: Option<Regex>,
+
+    /// Trim whitespace around `Host` and keep its first comma-separated
+    /// value before routing, for clients behind proxies that pad or repeat
+    /// it (see [`crate::routing::normalize_host`])
+    pub normalize_host: bool,
+
+    /// Hostname formats of the past redirected to the current scheme with a
+    /// 308 (see [`LegacyHosts`])
+    pub legacy_hosts: LegacyHosts,
+
+    /// PEM CA bundle used to verify TLS backends (system roots if unset)
+    pub upstream_ca_file: Option<String>,
+
+    /// SNI sent to TLS backends, unless set by the devbox's `tls-sni`
+    /// annotation (the Pod IP if unset)
+    pub upstream_sni: Option<String>,
+
+    /// Requests slower than this are logged as slow (disabled if unset)
+    #[serde(serialize_with = "serialize_opt_secs")]
+    pub slow_request_threshold: Option<Duration>,
+
+    /// Upgraded (WebSocket) connections are closed once no data has crossed
+    /// them in either direction for this long (never if unset)
+    #[serde(serialize_with = "serialize_opt_secs")]
+    pub websocket_idle_timeout: Option<Duration>,
+
+    /// Share of successful (1xx-3xx) requests whose access records are
+    /// logged, from 0 to 1; failed requests are always logged
+    pub access_log_sample: f64,
+
+    /// Log the request headers of requests that fail to route (404 and 503),
+    /// with the values of `sensitive_headers` redacted
+    pub log_headers_on_error: bool,
+
+    /// Lowercase names of the headers whose values are never logged
+    pub sensitive_headers: Vec<String>,
+
+    /// Longest deadline a client can set with `X-Request-Timeout-Ms`
+    /// (the header is ignored if unset)
+    #[serde(serialize_with = "serialize_opt_secs")]
+    pub max_request_timeout: Option<Duration>,
+
+    /// Deadline of every request but upgrades, unless the client sets an
+    /// earlier one; answered with 504 once it passes (none if unset)
+    #[serde(serialize_with = "serialize_opt_secs")]
+    pub request_timeout: Option<Duration>,
+
+    /// Header telling the backend how much of the request's deadline is
+    /// left, in milliseconds (in gRPC's syntax if `grpc-timeout`)
+    #[serde(serialize_with = "serialize_header_name")]
+    pub deadline_header: HeaderName,
+
+    /// How `Expect: 100-continue` is handled ("relay" or "gateway")
+    pub expect_continue: ExpectContinueMode,
+
+    /// Maximum request body size in bytes (unlimited if unset)
+    pub max_request_body_bytes: Option<u64>,
+
+    /// Maximum devbox response body size in bytes (unlimited if unset)
+    pub max_response_body_bytes: Option<u64>,
+
+    /// Content type prefixes of responses exempt from
+    /// `max_response_body_bytes`, like long-lived event streams
+    pub response_limit_exempt_types: Vec<String>,
+
+    /// Maximum length of the request target (path and query) in bytes;
+    /// longer requests get 414 (unlimited if unset)
+    pub max_uri_length: Option<usize>,
+
+    /// Reject requests with a protocol anomaly (conflicting framing, a
+    /// foreign absolute-form target, TRACE, CONNECT, an oversized header
+    /// block or an invalid Host) with a 400, rather than only counting them
+    pub strict_protocol: bool,
+
+    /// Size of a request's header block over which it counts as a protocol
+    /// anomaly, in bytes
+    pub max_request_header_bytes: usize,
+
+    /// Collapse duplicate slashes and resolve dot segments in request paths,
+    /// rejecting paths that escape the root, unless the devbox opts out with
+    /// its `skip-path-normalization` annotation
+    pub normalize_paths: bool,
+
+    /// Maximum concurrently active requests across all listeners (unlimited if unset)
+    pub max_global_inflight: Option<usize>,
+
+    /// Maximum concurrently active requests per client IP (unlimited if unset)
+    pub max_per_client_inflight: Option<usize>,
+
+    /// Maximum concurrently active requests per namespace (unlimited if unset)
+    pub namespace_max_inflight: Option<usize>,
+
+    /// Requests per second per namespace (unlimited if unset)
+    pub namespace_rate_limit: Option<u32>,
+
+    /// Burst allowance of the namespace rate limit (defaults to the rate)
+    pub namespace_rate_burst: Option<u32>,
+
+    /// ConfigMap with per-namespace limit overrides, as `namespace/name`
+    pub limits_configmap: Option<String>,
+
+    /// `kubernetes.io/tls` Secret served by listeners with `tls_secret=true`,
+    /// as `namespace/name`
+    pub tls_secret_ref: Option<String>,
+
+    /// ACME directory certificates of listeners with `acme=true` are
+    /// ordered from
+    pub acme_directory_url: String,
+
+    /// Contact email of the ACME account
+    pub acme_email: Option<String>,
+
+    /// Names the ACME certificate covers (defaults to the domain suffixes
+    /// of listeners with `acme=true`)
+    pub acme_domains: Vec<String>,
+
+    /// Directory the ACME account key and certificate are cached in, so
+    /// restarts don't order new ones
+    pub acme_cache_dir: String,
+
+    /// How long before expiry the ACME certificate is renewed
+    #[serde(serialize_with = "serialize_secs")]
+    pub acme_renew_before: Duration,
+
+    /// Address of the Prometheus metrics endpoint (disabled if unset)
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Record the header sizes of requests and of devbox responses in
+    /// histograms
+    pub header_size_metrics: bool,
+
+    /// Rewrite forwarded request header names to canonical casing
+    /// (`Content-Type`), for backends that mishandle other casings
+    pub canonicalize_header_case: bool,
+
+    /// Tell devboxes which uniqueID, namespace and host each request came
+    /// through in identity headers, by default on every listener
+    pub identity_headers: bool,
+
+    /// Names of the identity headers
+    pub identity_header_names: IdentityHeaders,
+
+    /// Retries after an upstream connection failure (0 disables retries)
+    pub upstream_connect_retries: usize,
+
+    /// Which requests may be retried ("idempotent", "idempotency-key" or "all")
+    pub upstream_retry_policy: RetryPolicy,
+
+    /// How long after the request arrived a GET or HEAD whose devbox failed
+    /// before sending response headers may be retried once against the
+    /// devbox's replacement Pod (0 disables failover)
+    #[serde(serialize_with = "serialize_opt_secs")]
+    pub upstream_failover_budget: Option<Duration>,
+
+    /// Origins allowed by gateway-level CORS ("*" for any); CORS is disabled
+    /// unless set here or by the devbox's `cors-allowed-origins` annotation
+    pub cors_allowed_origins: Vec<String>,
+
+    /// `Access-Control-Allow-Methods` of preflight responses
+    pub cors_allowed_methods: String,
+
+    /// `Access-Control-Allow-Headers` of preflight responses (the requested
+    /// headers are echoed if unset)
+    pub cors_allowed_headers: Option<String>,
+
+    /// `Access-Control-Max-Age` of preflight responses
+    #[serde(serialize_with = "serialize_secs")]
+    pub cors_max_age: Duration,
+
+    /// Send `Access-Control-Allow-Credentials: true`
+    pub cors_allow_credentials: bool,
+
+    /// Key signing preview tokens (preview links are disabled if unset)
+    #[serde(serialize_with = "serialize_redacted_opt")]
+    pub signing_key: Option<String>,
+
+    /// Key signing `X-HG-Route` overrides (overrides are ignored if unset)
+    #[serde(serialize_with = "serialize_redacted_opt")]
+    pub route_override_key: Option<String>,
+
+    /// How far a route override's timestamp may be from now
+    #[serde(serialize_with = "serialize_secs")]
+    pub route_override_max_age: Duration,
+
+    /// Where devbox activity is reported besides the admin API ("off" or "crd")
+    pub activity_reporting: ActivityReporting,
+
+    /// Minimum interval between activity patches of one devbox
+    #[serde(serialize_with = "serialize_secs")]
+    pub activity_report_interval: Duration,
+
+    /// Webhook POSTed to when a devbox goes idle and when it is requested
+    /// again after that (disabled if unset); redacted as it may carry a token
+    #[serde(serialize_with = "serialize_redacted_opt")]
+    pub idle_webhook_url: Option<String>,
+
+    /// Time without requests after which a devbox is reported idle
+    #[serde(serialize_with = "serialize_secs")]
+    pub idle_timeout: Duration,
+
+    /// Publish Kubernetes Events on Devboxes with repeated routing anomalies
+    pub k8s_events: bool,
+
+    /// Minimum interval between Events of one reason on one devbox
+    #[serde(serialize_with = "serialize_secs")]
+    pub k8s_events_interval: Duration,
+
+    /// Expect a PROXY protocol (v1 or v2) header on every proxy connection
+    pub proxy_protocol: bool,
+
+    /// Expect a PROXY protocol header on the metrics and admin listeners
+    /// (from `PROXY_PROTOCOL_SERVICES`)
+    pub service_proxy_protocol: ServiceProxyProtocol,
+
+    /// Source ranges of internal (in-cluster) clients, whose requests are
+    /// forwarded without proxy headers and logged less verbosely
+    pub internal_cidrs: Vec<Cidr>,
+
+    /// IPs of the gateway besides loopback (e.g. its Pod IP); devboxes
+    /// routed to a gateway address are rejected instead of looping
+    pub self_addrs: Vec<IpAddr>,
+
+    /// Accept loopback Pod IPs, for testing with backends on the gateway's
+    /// host; other non-routable addresses are always rejected
+    pub allow_loopback_backends: bool,
+
+    /// Devboxes registered at most (any number if 0); further Devboxes are
+    /// not routed until some are deleted
+    pub max_registry_entries: usize,
+
+    /// Whether watchers replace registry entries imported through the admin
+    /// API or leave them alone
+    pub imported_entries: ImportedEntries,
+
+    /// File the registry entries and watch bookmarks are saved to, and
+    /// restored from on startup so the watches resume instead of listing
+    /// everything again (disabled if unset)
+    pub registry_snapshot_file: Option<String>,
+
+    /// Connect to recently used backends on startup (requires
+    /// `warmup_state_file`)
+    pub warmup: bool,
+
+    /// File the recently used backends are kept in across restarts (the
+    /// warm-up is disabled if unset)
+    pub warmup_state_file: Option<String>,
+
+    /// Number of recently used backends to remember and warm up
+    pub warmup_backends: usize,
+
+    /// Maximum concurrent warm-up connections
+    pub warmup_concurrency: usize,
+
+    /// Time limit of the whole warm-up, including waiting for the registry
+    #[serde(serialize_with = "serialize_secs")]
+    pub warmup_timeout: Duration,
+
+    /// Address of the TCP passthrough service routing TLS connections by
+    /// SNI (disabled if unset)
+    pub tcp_passthrough_addr: Option<SocketAddr>,
+
+    /// Domain suffixes served by the TCP passthrough service (any domain if
+    /// empty)
+    pub tcp_passthrough_domains: Vec<String>,
+
+    /// Address of the admin API (disabled if unset)
+    pub admin_addr: Option<SocketAddr>,
+
+    /// Bearer token required by the admin API's debug endpoints
+    #[serde(serialize_with = "serialize_redacted_opt")]
+    pub admin_token: Option<String>,
+
+    /// Serve CPU profiles, heap and runtime stats under `/debug/` on the
+    /// admin API (needs the `debug-endpoints` build feature and `ADMIN_TOKEN`)
+    pub debug_endpoints: bool,
+
+    /// Address of the gRPC health service over h2c (disabled if unset)
+    pub grpc_health_addr: Option<SocketAddr>,
+
+    /// Devbox uniqueIDs whose requests are blocked
+    pub blocked_unique_ids: Vec<String>,
+
+    /// Namespaces whose devboxes' requests are blocked
+    pub blocked_namespaces: Vec<String>,
+
+    /// File with additional blocklist entries, re-read on SIGHUP or via the admin API
+    pub blocklist_file: Option<String>,
+
+    /// Status returned for blocked requests (403 or 451)
+    pub blocked_status: u16,
+
+    /// Body returned for blocked requests
+    pub blocked_message: String,
+
+    /// Answer requests matching the abuse heuristics below slowly instead
+    /// of serving them
+    pub tarpit: bool,
+
+    /// Clients always tarpitted
+    pub tarpit_cidrs: Vec<Cidr>,
+
+    /// Path prefixes only scanners ask for (e.g. "/.env")
+    pub tarpit_paths: Vec<String>,
+
+    /// Clients asking for this many unknown devboxes within a minute are
+    /// tarpitted (not tracked if unset)
+    pub tarpit_scan_threshold: Option<u32>,
+
+    /// Delay before the headers of a tarpitted response
+    #[serde(serialize_with = "serialize_secs")]
+    pub tarpit_header_delay: Duration,
+
+    /// Interval between the body bytes of a tarpitted response
+    #[serde(serialize_with = "serialize_secs")]
+    pub tarpit_body_interval: Duration,
+
+    /// Body size of a tarpitted response, sent a byte at a time
+    pub tarpit_body_bytes: usize,
+
+    /// Requests held in the tarpit at once; above this, matching requests
+    /// get an instant 403
+    pub tarpit_max_connections: usize,
+
+    /// Serve browsers an HTML page reloading itself after this long instead
+    /// of the plain 503 for devboxes that are not running yet (disabled if
+    /// unset)
+    #[serde(serialize_with = "serialize_opt_secs")]
+    pub starting_page_refresh: Option<Duration>,
+
+    /// Largest `Retry-After` of 503s for devboxes that are not running; the
+    /// delay doubles from a second as the devbox stays down
+    #[serde(serialize_with = "serialize_secs")]
+    pub not_running_retry_after_max: Duration,
+
+    /// Answer unknown hosts with a 503 and `Retry-After` rather than a 404
+    /// while a watcher (re)lists and for this long after, as the devbox may
+    /// just not be registered yet (disabled if unset)
+    #[serde(serialize_with = "serialize_opt_secs")]
+    pub registry_sync_grace: Option<Duration>,
+
+    /// Suggest similar uniqueIDs on the HTML 404 page of a mistyped host.
+    /// Only devboxes in the namespace of the devbox a valid preview token
+    /// was minted for are suggested, so this needs `SIGNING_KEY`.
+    pub suggest_on_404: bool,
+
+    /// Status of each kind of failure reaching a devbox
+    pub failure_statuses: FailureStatuses,
+
+    /// Body format of gateway-generated errors ("plain", "json" or "html")
+    /// for clients whose `Accept` header doesn't prefer one
+    pub error_format: ErrorFormat,
+
+    /// Directory of per-locale translations of gateway-generated pages (see
+    /// [`crate::locale::Locales`]; English only if unset)
+    pub locales_dir: Option<String>,
+
+    /// Language of gateway-generated pages for clients whose
+    /// `Accept-Language` header matches no locale
+    pub default_locale: String,
+
+    /// Memory for cached static assets of devboxes, in bytes (caching is
+    /// disabled if unset)
+    pub cache_max_bytes: Option<u64>,
+
+    /// Largest response body cached, in bytes (at most `cache_max_bytes`)
+    pub cache_max_object_bytes: u64,
+
+    /// Only cache responses of devboxes with the `response-cache` annotation
+    /// set to "true" (otherwise, of all devboxes not setting it to "false")
+    pub cache_require_annotation: bool,
+
+    /// Largest request body copied to a devbox's mirror port, in bytes
+    pub mirror_max_body_bytes: u64,
+
+    /// Time a mirrored request may take before it is abandoned
+    #[serde(serialize_with = "serialize_secs")]
+    pub mirror_timeout: Duration,
+
+    /// Interval of the pod IP consistency sweep (disabled if unset)
+    #[serde(serialize_with = "serialize_opt_secs")]
+    pub pod_ip_gc_interval: Option<Duration>,
+
+    /// Age after which pod IP entries are re-verified against the API server
+    /// during the sweep (disabled if unset)
+    #[serde(serialize_with = "serialize_opt_secs")]
+    pub pod_ip_verify_ttl: Option<Duration>,
+
+    /// Dot-separated path of a Devbox status field holding the pod IP (e.g.
+    /// `network.podIP`). If set, pod IPs are read from the Devbox resources
+    /// and Pods are not watched.
+    pub pod_ip_status_field: Option<String>,
+
+    /// How much of each Devbox the watch deserializes
+    pub devbox_watch_mode: DevboxWatchMode,
+
+    /// Only route to a Pod once its `Ready` condition is true, rather than
+    /// as soon as it has an IP (ignored with `pod_ip_status_field`)
+    pub require_pod_ready: bool,
+
+    /// Namespaces whose Devboxes and Pods are watched, one watch each, for
+    /// deployments without cluster-wide RBAC (all namespaces if empty)
+    pub watch_namespaces: Vec<String>,
+
+    /// Proxy listeners, each with its own policy (from `LISTENERS`, or a
+    /// single "default" listener on `listen_addr`)
+    pub listeners: Vec<ListenerConfig>,
+
+    /// Clusters whose devboxes are served (from `CLUSTERS`, or the cluster
+    /// the gateway runs in)
+    pub clusters: Vec<ClusterConfig>,
+
+    /// Pingora server settings (from the `SERVER_*` variables)
+    pub server: ServerTuning,
+
+    /// Runtime of the Kubernetes watchers (from the `WATCHER_*` variables)
+    pub watcher_runtime: WatcherRuntime,
+
+    /// User the gateway serves as and where its listening sockets come from
+    /// (from `RUN_AS_USER`, `RUN_AS_GROUP` and `SOCKET_ACTIVATION`)
+    pub privileges: Privileges,
 }
 
 impl Config {
     pub fn from_env() -> Self {
-        let listen_addr = std::env::var("LISTEN_ADDR")
-            .unwrap_or_else(|_| "0.0.0.0:8080".to_string())
-            .parse()
-            .expect("Invalid LISTEN_ADDR format");
+        let listen_addr =
+            env_parse("LISTEN_ADDR").unwrap_or_else(|| "0.0.0.0:8080".parse().unwrap());
 
-        let log_level = std::env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string());
+        let log_level = env_var("LOG_LEVEL").unwrap_or_else(|| "info".to_string());
 
-        Self {
+        let host_pattern = env_parse("HOST_PATTERN");
+        let normalize_host = env_parse("NORMALIZE_HOST").unwrap_or(true);
+        let legacy_hosts = env_parse("LEGACY_HOST_REDIRECTS").unwrap_or_default();
+
+        let upstream_ca_file = env_var("UPSTREAM_CA_FILE");
+
+        let upstream_sni = env_var("UPSTREAM_SNI");
+
+        let slow_request_threshold =
+            env_duration("SLOW_REQUEST_THRESHOLD").filter(|d| !d.is_zero());
+
+        let websocket_idle_timeout =
+            env_duration("WEBSOCKET_IDLE_TIMEOUT").filter(|d| !d.is_zero());
+
+        let access_log_sample = env_parse("ACCESS_LOG_SAMPLE").unwrap_or(1.0);
+        assert!(
+            (0.0..=1.0).contains(&access_log_sample),
+            "Invalid ACCESS_LOG_SAMPLE format: must be between 0 and 1"
+        );
+        let log_headers_on_error = env_parse("LOG_HEADERS_ON_ERROR").unwrap_or(false);
+        let sensitive_headers: Vec<String> = env_list("SENSITIVE_HEADERS")
+            .into_iter()
+            .map(|name| name.to_ascii_lowercase())
+            .collect();
+        let sensitive_headers = if sensitive_headers.is_empty() {
+            default_sensitive_headers()
+        } else {
+            sensitive_headers
+        };
+
+        let max_request_timeout =
+            Some(env_duration("MAX_REQUEST_TIMEOUT").unwrap_or(DEFAULT_MAX_REQUEST_TIMEOUT))
+                .filter(|d| !d.is_zero());
+
+        let request_timeout = env_duration("REQUEST_TIMEOUT").filter(|d| !d.is_zero());
+        let deadline_header = env_parse("DEADLINE_HEADER")
+            .unwrap_or(HeaderName::from_static(deadline::X_REQUEST_TIMEOUT_MS));
+
+        let expect_continue = env_parse("EXPECT_CONTINUE").unwrap_or_default();
+
+        let max_request_body_bytes = env_parse("MAX_REQUEST_BODY_BYTES").filter(|&n: &u64| n > 0);
+        let max_response_body_bytes = env_parse("MAX_RESPONSE_BODY_BYTES").filter(|&n: &u64| n > 0);
+        let response_limit_exempt_types = if env_var("RESPONSE_LIMIT_EXEMPT_TYPES").is_some() {
+            env_list("RESPONSE_LIMIT_EXEMPT_TYPES")
+        } else {
+            vec![DEFAULT_RESPONSE_LIMIT_EXEMPT_TYPES.to_string()]
+        }
+        .into_iter()
+        .map(|t| t.to_ascii_lowercase())
+        .collect();
+
+        let max_uri_length = env_parse("MAX_URI_LENGTH").filter(|&n: &usize| n > 0);
+        let strict_protocol = env_parse("STRICT_PROTOCOL").unwrap_or(false);
+        let max_request_header_bytes = env_parse("MAX_REQUEST_HEADER_BYTES")
+            .filter(|&n: &usize| n > 0)
+            .unwrap_or(DEFAULT_MAX_REQUEST_HEADER_BYTES);
+        let normalize_paths = env_parse("NORMALIZE_PATHS").unwrap_or(false);
+
+        let max_global_inflight = env_parse("MAX_GLOBAL_INFLIGHT").filter(|&n: &usize| n > 0);
+
+        let max_per_client_inflight =
+            env_parse("MAX_PER_CLIENT_INFLIGHT").filter(|&n: &usize| n > 0);
+        let namespace_max_inflight = env_parse("NAMESPACE_MAX_INFLIGHT").filter(|&n: &usize| n > 0);
+        let namespace_rate_limit = env_parse("NAMESPACE_RATE_LIMIT").filter(|&n: &u32| n > 0);
+        let namespace_rate_burst = env_parse("NAMESPACE_RATE_BURST").filter(|&n: &u32| n > 0);
+        let limits_configmap = env_var("LIMITS_CONFIGMAP");
+        assert!(
+            limits_configmap
+                .as_deref()
+                .is_none_or(|s| matches!(s.split_once('/'), Some((ns, name)) if !ns.is_empty() && !name.is_empty())),
+            "Invalid LIMITS_CONFIGMAP format: expected namespace/name"
+        );
+        let tls_secret_ref = env_var("TLS_SECRET_REF");
+        assert!(
+            tls_secret_ref
+                .as_deref()
+                .is_none_or(|s| matches!(s.split_once('/'), Some((ns, name)) if !ns.is_empty() && !name.is_empty())),
+            "Invalid TLS_SECRET_REF format: expected namespace/name"
+        );
+
+        let acme_directory_url =
+            env_var("ACME_DIRECTORY_URL").unwrap_or_else(|| DEFAULT_ACME_DIRECTORY_URL.to_string());
+        let acme_email = env_var("ACME_EMAIL");
+        let acme_domains = env_list("ACME_DOMAINS")
+            .into_iter()
+            .map(|d| d.trim_start_matches('.').to_ascii_lowercase())
+            .collect();
+        let acme_cache_dir =
+            env_var("ACME_CACHE_DIR").unwrap_or_else(|| DEFAULT_ACME_CACHE_DIR.to_string());
+        let acme_renew_before =
+            env_duration("ACME_RENEW_BEFORE").unwrap_or(DEFAULT_ACME_RENEW_BEFORE);
+
+        let metrics_addr = env_parse("METRICS_ADDR");
+        let header_size_metrics = env_parse("HEADER_SIZE_METRICS").unwrap_or(true);
+
+        let canonicalize_header_case = env_parse("CANONICALIZE_HEADER_CASE").unwrap_or(false);
+
+        let identity_headers = env_parse("IDENTITY_HEADERS").unwrap_or(false);
+        let default_names = IdentityHeaders::default();
+        let identity_header_names = IdentityHeaders {
+            id: env_parse("IDENTITY_HEADER_ID").unwrap_or(default_names.id),
+            namespace: env_parse("IDENTITY_HEADER_NAMESPACE").unwrap_or(default_names.namespace),
+            original_host: env_parse("IDENTITY_HEADER_ORIGINAL_HOST")
+                .unwrap_or(default_names.original_host),
+        };
+
+        let upstream_connect_retries =
+            env_parse("UPSTREAM_CONNECT_RETRIES").unwrap_or(DEFAULT_UPSTREAM_CONNECT_RETRIES);
+        let upstream_retry_policy = env_parse("UPSTREAM_RETRY_POLICY").unwrap_or_default();
+        let upstream_failover_budget = Some(
+            env_duration("UPSTREAM_FAILOVER_BUDGET").unwrap_or(DEFAULT_UPSTREAM_FAILOVER_BUDGET),
+        )
+        .filter(|d| !d.is_zero());
+
+        let cors_allowed_origins = env_list("CORS_ALLOWED_ORIGINS");
+        let cors_allowed_methods = env_var("CORS_ALLOWED_METHODS")
+            .unwrap_or_else(|| DEFAULT_CORS_ALLOWED_METHODS.to_string());
+        let cors_allowed_headers = env_var("CORS_ALLOWED_HEADERS");
+        let cors_max_age = env_duration("CORS_MAX_AGE").unwrap_or(DEFAULT_CORS_MAX_AGE);
+        let cors_allow_credentials = env_parse("CORS_ALLOW_CREDENTIALS").unwrap_or(false);
+
+        let signing_key = env_var("SIGNING_KEY");
+        let route_override_key = env_var("ROUTE_OVERRIDE_KEY");
+        let route_override_max_age =
+            env_duration("ROUTE_OVERRIDE_MAX_AGE").unwrap_or(DEFAULT_ROUTE_OVERRIDE_MAX_AGE);
+
+        let activity_reporting = env_parse("ACTIVITY_REPORTING").unwrap_or_default();
+        let activity_report_interval = env_duration("ACTIVITY_REPORT_INTERVAL")
+            .filter(|d| !d.is_zero())
+            .unwrap_or(DEFAULT_ACTIVITY_REPORT_INTERVAL);
+        let idle_webhook_url = env_var("IDLE_WEBHOOK_URL");
+        let idle_timeout = env_duration("IDLE_TIMEOUT")
+            .filter(|d| !d.is_zero())
+            .unwrap_or(DEFAULT_IDLE_TIMEOUT);
+
+        let k8s_events = env_parse("K8S_EVENTS").unwrap_or(true);
+        let k8s_events_interval = env_duration("K8S_EVENTS_INTERVAL")
+            .filter(|d| !d.is_zero())
+            .unwrap_or(DEFAULT_K8S_EVENTS_INTERVAL);
+
+        let proxy_protocol = env_parse("PROXY_PROTOCOL").unwrap_or(false);
+        let service_proxy_protocol = env_parse("PROXY_PROTOCOL_SERVICES").unwrap_or_default();
+
+        let internal_cidrs = env_list("INTERNAL_CIDRS")
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<_, String>>()
+            .unwrap_or_else(|e| panic!("Invalid INTERNAL_CIDRS format: {e}"));
+
+        let self_addrs = env_list("SELF_ADDRS")
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|e| panic!("Invalid SELF_ADDRS format: {e}"));
+        let allow_loopback_backends = env_parse("ALLOW_LOOPBACK_BACKENDS").unwrap_or(false);
+        let max_registry_entries =
+            env_parse("MAX_REGISTRY_ENTRIES").unwrap_or(registry::DEFAULT_MAX_ENTRIES);
+        let imported_entries = env_parse("IMPORTED_ENTRIES").unwrap_or_default();
+
+        let registry_snapshot_file = env_var("REGISTRY_SNAPSHOT_FILE");
+        let warmup = env_parse("WARMUP").unwrap_or(true);
+        let warmup_state_file = env_var("WARMUP_STATE_FILE");
+        let warmup_backends = env_parse("WARMUP_BACKENDS").unwrap_or(DEFAULT_WARMUP_BACKENDS);
+        let warmup_concurrency = env_parse("WARMUP_CONCURRENCY")
+            .filter(|&n: &usize| n > 0)
+            .unwrap_or(DEFAULT_WARMUP_CONCURRENCY);
+        let warmup_timeout = env_duration("WARMUP_TIMEOUT").unwrap_or(DEFAULT_WARMUP_TIMEOUT);
+
+        let tcp_passthrough_addr = env_parse("TCP_PASSTHROUGH_ADDR");
+        let tcp_passthrough_domains = env_list("TCP_PASSTHROUGH_DOMAINS")
+            .iter()
+            .map(|d| d.trim_start_matches('.').to_ascii_lowercase())
+            .collect();
+
+        let admin_addr = env_parse("ADMIN_ADDR");
+        let admin_token = env_var("ADMIN_TOKEN");
+        let debug_endpoints = env_parse("DEBUG_ENDPOINTS").unwrap_or(false);
+        let grpc_health_addr = env_parse("GRPC_HEALTH_ADDR");
+
+        let blocked_unique_ids = env_list("BLOCKED_UNIQUE_IDS");
+        let blocked_namespaces = env_list("BLOCKED_NAMESPACES");
+        let blocklist_file = env_var("BLOCKLIST_FILE");
+        let blocked_status = env_parse("BLOCKED_STATUS").unwrap_or(DEFAULT_BLOCKED_STATUS);
+        assert!(
+            matches!(blocked_status, 403 | 451),
+            "Invalid BLOCKED_STATUS format: must be 403 or 451"
+        );
+        let blocked_message =
+            env_var("BLOCKED_MESSAGE").unwrap_or_else(|| DEFAULT_BLOCKED_MESSAGE.to_string());
+
+        let tarpit = env_parse("TARPIT").unwrap_or(false);
+        let tarpit_cidrs = env_list("TARPIT_CIDRS")
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<_, String>>()
+            .unwrap_or_else(|e| panic!("Invalid TARPIT_CIDRS format: {e}"));
+        let tarpit_paths = env_list("TARPIT_PATHS");
+        assert!(
+            tarpit_paths.iter().all(|p| p.starts_with('/')),
+            "Invalid TARPIT_PATHS format: paths must start with /"
+        );
+        let tarpit_scan_threshold = env_parse("TARPIT_SCAN_THRESHOLD").filter(|&n: &u32| n > 0);
+        let tarpit_header_delay =
+            env_duration("TARPIT_HEADER_DELAY").unwrap_or(DEFAULT_TARPIT_HEADER_DELAY);
+        let tarpit_body_interval =
+            env_duration("TARPIT_BODY_INTERVAL").unwrap_or(DEFAULT_TARPIT_BODY_INTERVAL);
+        let tarpit_body_bytes = env_parse("TARPIT_BODY_BYTES").unwrap_or(DEFAULT_TARPIT_BODY_BYTES);
+        let tarpit_max_connections =
+            env_parse("TARPIT_MAX_CONNECTIONS").unwrap_or(DEFAULT_TARPIT_MAX_CONNECTIONS);
+
+        let starting_page_refresh = env_duration("STARTING_PAGE_REFRESH").filter(|d| !d.is_zero());
+        let not_running_retry_after_max = env_duration("NOT_RUNNING_RETRY_AFTER_MAX")
+            .filter(|d| !d.is_zero())
+            .unwrap_or(DEFAULT_NOT_RUNNING_RETRY_AFTER_MAX);
+        let registry_sync_grace =
+            Some(env_duration("REGISTRY_SYNC_GRACE").unwrap_or(DEFAULT_REGISTRY_SYNC_GRACE))
+                .filter(|d| !d.is_zero());
+
+        let suggest_on_404 = env_parse("SUGGEST_ON_404").unwrap_or(false);
+
+        let defaults = FailureStatuses::default();
+        let failure_statuses = FailureStatuses {
+            unavailable: env_parse("UPSTREAM_UNAVAILABLE_STATUS").unwrap_or(defaults.unavailable),
+            refused: env_parse("UPSTREAM_REFUSED_STATUS").unwrap_or(defaults.refused),
+            timeout: env_parse("UPSTREAM_TIMEOUT_STATUS").unwrap_or(defaults.timeout),
+            invalid_response: env_parse("UPSTREAM_INVALID_RESPONSE_STATUS")
+                .unwrap_or(defaults.invalid_response),
+        };
+        failure_statuses
+            .validate()
+            .unwrap_or_else(|e| panic!("Invalid upstream failure statuses: {e}"));
+
+        let error_format = env_parse("ERROR_FORMAT").unwrap_or_default();
+
+        let locales_dir = env_var("LOCALES_DIR");
+        let default_locale =
+            env_var("DEFAULT_LOCALE").unwrap_or_else(|| locale::BUILTIN_LOCALE.to_string());
+        assert!(
+            locale::is_valid_tag(&default_locale),
+            "Invalid DEFAULT_LOCALE format: expected a language tag"
+        );
+
+        let cache_max_bytes = env_parse("CACHE_MAX_BYTES").filter(|&n: &u64| n > 0);
+        let cache_max_object_bytes = env_parse("CACHE_MAX_OBJECT_BYTES")
+            .filter(|&n: &u64| n > 0)
+            .unwrap_or(DEFAULT_CACHE_MAX_OBJECT_BYTES);
+        let cache_require_annotation = env_parse("CACHE_REQUIRE_ANNOTATION").unwrap_or(false);
+
+        let mirror_max_body_bytes =
+            env_parse("MIRROR_MAX_BODY_BYTES").unwrap_or(DEFAULT_MIRROR_MAX_BODY_BYTES);
+        let mirror_timeout = env_duration("MIRROR_TIMEOUT")
+            .filter(|d| !d.is_zero())
+            .unwrap_or(DEFAULT_MIRROR_TIMEOUT);
+
+        let pod_ip_gc_interval =
+            Some(env_duration("POD_IP_GC_INTERVAL").unwrap_or(DEFAULT_POD_IP_GC_INTERVAL))
+                .filter(|d| !d.is_zero());
+
+        let pod_ip_verify_ttl =
+            Some(env_duration("POD_IP_VERIFY_TTL").unwrap_or(DEFAULT_POD_IP_VERIFY_TTL))
+                .filter(|d| !d.is_zero());
+
+        let watch_namespaces = env_list("WATCH_NAMESPACES");
+        let require_pod_ready = env_parse("REQUIRE_POD_READY").unwrap_or(false);
+        let devbox_watch_mode = env_parse("DEVBOX_WATCH_MODE").unwrap_or_default();
+
+        let pod_ip_status_field = env_var("POD_IP_STATUS_FIELD");
+        if let Some(path) = &pod_ip_status_field {
+            assert!(
+                path.split('.').all(|key| !key.is_empty()),
+                "Invalid POD_IP_STATUS_FIELD: {path}"
+            );
+        }
+
+        let server = ServerTuning {
+            threads: env_parse("SERVER_THREADS").filter(|&n: &usize| n > 0),
+            work_stealing: env_parse("SERVER_WORK_STEALING").unwrap_or(true),
+            upgrade: env_parse("SERVER_UPGRADE").unwrap_or(false),
+            upgrade_sock: env_var("SERVER_UPGRADE_SOCK"),
+            daemon: env_parse("SERVER_DAEMON").unwrap_or(false),
+            pid_file: env_var("SERVER_PID_FILE"),
+        };
+        server
+            .validate()
+            .unwrap_or_else(|e| panic!("Invalid server tuning: {e}"));
+
+        let watcher_runtime = WatcherRuntime {
+            threads: env_parse("WATCHER_THREADS"),
+            current_thread: env_parse("WATCHER_CURRENT_THREAD").unwrap_or(false),
+        };
+        watcher_runtime
+            .validate()
+            .unwrap_or_else(|e| panic!("Invalid watcher runtime: {e}"));
+
+        let privileges = Privileges {
+            run_as_user: env_var("RUN_AS_USER"),
+            run_as_group: env_var("RUN_AS_GROUP"),
+            socket_activation: env_parse("SOCKET_ACTIVATION").unwrap_or(false),
+        };
+        privileges
+            .validate()
+            .unwrap_or_else(|e| panic!("Invalid privileges: {e}"));
+
+        let mut config = Self {
             listen_addr,
             log_level,
+            host_pattern,
+            normalize_host,
+            legacy_hosts,
+            upstream_ca_file,
+            upstream_sni,
+            slow_request_threshold,
+            websocket_idle_timeout,
+            access_log_sample,
+            log_headers_on_error,
+            sensitive_headers,
+            max_request_timeout,
+            request_timeout,
+            deadline_header,
+            expect_continue,
+            max_request_body_bytes,
+            max_response_body_bytes,
+            response_limit_exempt_types,
+            max_uri_length,
+            strict_protocol,
+            max_request_header_bytes,
+            normalize_paths,
+            max_global_inflight,
+            max_per_client_inflight,
+            namespace_max_inflight,
+            namespace_rate_limit,
+            namespace_rate_burst,
+            limits_configmap,
+            tls_secret_ref,
+            acme_directory_url,
+            acme_email,
+            acme_domains,
+            acme_cache_dir,
+            acme_renew_before,
+            metrics_addr,
+            header_size_metrics,
+            canonicalize_header_case,
+            identity_headers,
+            identity_header_names,
+            upstream_connect_retries,
+            upstream_retry_policy,
+            upstream_failover_budget,
+            cors_allowed_origins,
+            cors_allowed_methods,
+            cors_allowed_headers,
+            cors_max_age,
+            cors_allow_credentials,
+            signing_key,
+            route_override_key,
+            route_override_max_age,
+            activity_reporting,
+            activity_report_interval,
+            idle_webhook_url,
+            idle_timeout,
+            k8s_events,
+            k8s_events_interval,
+            proxy_protocol,
+            service_proxy_protocol,
+            internal_cidrs,
+            self_addrs,
+            allow_loopback_backends,
+            max_registry_entries,
+            imported_entries,
+            registry_snapshot_file,
+            warmup,
+            warmup_state_file,
+            warmup_backends,
+            warmup_concurrency,
+            warmup_timeout,
+            tcp_passthrough_addr,
+            tcp_passthrough_domains,
+            admin_addr,
+            admin_token,
+            debug_endpoints,
+            grpc_health_addr,
+            blocked_unique_ids,
+            blocked_namespaces,
+            blocklist_file,
+            blocked_status,
+            blocked_message,
+            tarpit,
+            tarpit_cidrs,
+            tarpit_paths,
+            tarpit_scan_threshold,
+            tarpit_header_delay,
+            tarpit_body_interval,
+            tarpit_body_bytes,
+            tarpit_max_connections,
+            starting_page_refresh,
+            not_running_retry_after_max,
+            registry_sync_grace,
+            suggest_on_404,
+            failure_statuses,
+            error_format,
+            locales_dir,
+            default_locale,
+            cache_max_bytes,
+            cache_max_object_bytes,
+            cache_require_annotation,
+            mirror_max_body_bytes,
+            mirror_timeout,
+            pod_ip_gc_interval,
+            pod_ip_verify_ttl,
+            pod_ip_status_field,
+            devbox_watch_mode,
+            require_pod_ready,
+            watch_namespaces,
+            listeners: Vec::new(),
+            clusters: Vec::new(),
+            server,
+            watcher_runtime,
+            privileges,
+        };
+
+        config.listeners = match env_var("LISTENERS") {
+            Some(spec) => parse_listeners(&spec, &config).expect("Invalid LISTENERS format"),
+            None => vec![ListenerConfig::from_config(&config)],
+        };
+
+        config.clusters = match env_var("CLUSTERS") {
+            Some(spec) => parse_clusters(&spec).expect("Invalid CLUSTERS format"),
+            None => vec![ClusterConfig::default()],
+        };
+
+        config
+    }
+}
+
+/// Read a non-empty environment variable.
+/// Shown in place of secrets when the configuration is serialized
+pub const REDACTED: &str = "[redacted]";
+
+fn serialize_redacted<S: Serializer>(_: &str, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(REDACTED)
+}
+
+fn serialize_redacted_opt<S: Serializer>(value: &Option<String>, s: S) -> Result<S::Ok, S::Error> {
+    value.as_ref().map(|_| REDACTED).serialize(s)
+}
+
+fn serialize_regex<S: Serializer>(value: &Option<Regex>, s: S) -> Result<S::Ok, S::Error> {
+    value.as_ref().map(Regex::as_str).serialize(s)
+}
+
+/// Durations are shown in seconds, like everywhere else in the admin API
+fn serialize_secs<S: Serializer>(value: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(value.as_secs_f64())
+}
+
+fn serialize_opt_secs<S: Serializer>(value: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+    value.map(|d| d.as_secs_f64()).serialize(s)
+}
+
+fn serialize_header_name<S: Serializer>(value: &HeaderName, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(value.as_str())
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|s| !s.is_empty())
+}
+
+/// Read and parse a non-empty environment variable, panicking on invalid values.
+fn env_parse<T>(name: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    env_var(name).map(|s| {
+        s.parse()
+            .unwrap_or_else(|e| panic!("Invalid {name} format: {e}"))
+    })
+}
+
+/// Read a comma-separated list environment variable, skipping empty items.
+fn env_list(name: &str) -> Vec<String> {
+    env_var(name)
+        .map(|s| {
+            s.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Read a duration environment variable (see [`parse_duration`]).
+fn env_duration(name: &str) -> Option<Duration> {
+    env_var(name).map(|s| parse_duration(&s).unwrap_or_else(|| panic!("Invalid {name} format")))
+}
+
+/// Per-listener routing policy.
+///
+/// Each listener gets its own `DevboxProxy` carrying one of these, while all
+/// listeners share the same registry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListenerPolicy {
+    /// Listener name used to label metrics and access logs
+    pub name: String,
+    /// Domain suffixes accepted on this listener (any domain if empty)
+    pub domain_suffixes: Vec<String>,
+    /// Maximum request body size in bytes (unlimited if unset)
+    pub max_request_body_bytes: Option<u64>,
+    /// Whether the listener terminates TLS. Hosts outside its domains then
+    /// get 421 Misdirected Request, since they arrive on coalesced HTTP/2
+    /// connections rather than being unknown.
+    pub tls: bool,
+    /// Whether requests carry identity headers (see [`IdentityHeaders`])
+    pub identity_headers: bool,
+}
+
+impl ListenerPolicy {
+    /// Whether `host` (without port) is served by this listener.
+    ///
+    /// The host must be exactly one label below one of the domain suffixes,
+    /// e.g. `devbox-my-app-8080.devbox.sealos.io` for `devbox.sealos.io`.
+    pub fn matches_host(&self, host: &str) -> bool {
+        routing::matches_domain(&self.domain_suffixes, host)
+    }
+}
+
+/// Certificate and key of a TLS listener.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListenerTls {
+    /// PEM certificate chain
+    pub cert_path: String,
+    /// PEM private key
+    #[serde(serialize_with = "serialize_redacted")]
+    pub key_path: String,
+}
+
+/// A listener: an address plus the policy applied to requests it accepts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListenerConfig {
+    pub listen_addr: SocketAddr,
+    /// Terminate TLS (negotiating h2 or http/1.1 via ALPN) instead of cleartext
+    pub tls: Option<ListenerTls>,
+    /// Terminate TLS with the certificate of `TLS_SECRET_REF`, reloaded
+    /// when the Secret changes
+    pub tls_secret: bool,
+    /// Terminate TLS with the certificates of this directory, picked by SNI
+    pub tls_cert_dir: Option<String>,
+    /// Terminate TLS with a certificate ordered from `ACME_DIRECTORY_URL`
+    pub acme: bool,
+    /// Ask TLS clients for a certificate, which devboxes with a `client-ca`
+    /// annotation require (see [`crate::client_cert`])
+    pub client_cert: bool,
+    /// Read the client address from a PROXY protocol header (cleartext only)
+    pub proxy_protocol: bool,
+    pub policy: ListenerPolicy,
+}
+
+impl ListenerConfig {
+    /// The single listener used when `LISTENERS` is not set.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            listen_addr: config.listen_addr,
+            tls: None,
+            tls_secret: false,
+            tls_cert_dir: None,
+            acme: false,
+            client_cert: false,
+            proxy_protocol: config.proxy_protocol,
+            policy: ListenerPolicy {
+                name: "default".to_string(),
+                domain_suffixes: Vec::new(),
+                max_request_body_bytes: config.max_request_body_bytes,
+                tls: false,
+                identity_headers: config.identity_headers,
+            },
         }
     }
 }
 
-impl Default for Config {
+/// Parse the `LISTENERS` specification.
+///
+/// Listeners are separated by `;`, fields by `,` and list values by `|`:
+///
+/// ```text
+/// name=public,addr=0.0.0.0:8080,domains=devbox.sealos.io;
+/// name=internal,addr=0.0.0.0:8081,domains=devbox.svc|devbox.internal,max_request_body_bytes=0;
+/// name=secure,addr=0.0.0.0:8443,tls_cert=/tls/tls.crt,tls_key=/tls/tls.key
+/// name=rotated,addr=0.0.0.0:9443,tls_secret=true
+/// name=domains,addr=0.0.0.0:10443,tls_cert_dir=/tls/certs
+/// name=acme,addr=0.0.0.0:11443,domains=devbox.example.com,acme=true
+/// name=mtls,addr=0.0.0.0:12443,tls_secret=true,client_cert=true
+/// ```
+///
+/// Fields not set on a listener inherit the global configuration, and
+/// `max_request_body_bytes=0` disables the limit for that listener.
+/// Listeners with `tls_cert` and `tls_key`, `tls_secret=true` (which
+/// requires `TLS_SECRET_REF`) or `tls_cert_dir` (certificates picked by SNI,
+/// see [`crate::sni::SniCerts::load_dir`]) or `acme=true` (a certificate
+/// ordered for `ACME_DOMAINS`, else the listener's domains, see
+/// [`crate::acme`]) terminate TLS, those of the last three kinds can ask
+/// clients for a certificate with `client_cert=true`, and
+/// `proxy_protocol=true|false` overrides `PROXY_PROTOCOL` for cleartext
+/// listeners and `identity_headers=true|false` overrides `IDENTITY_HEADERS`.
+pub fn parse_listeners(spec: &str, defaults: &Config) -> Result<Vec<ListenerConfig>, String> {
+    let mut listeners: Vec<ListenerConfig> = Vec::new();
+
+    for entry in spec.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+        let mut name = None;
+        let mut listen_addr = None;
+        let mut domain_suffixes = Vec::new();
+        let mut max_request_body_bytes = defaults.max_request_body_bytes;
+        let mut tls_cert = None;
+        let mut tls_key = None;
+        let mut tls_secret = false;
+        let mut tls_cert_dir = None;
+        let mut acme = false;
+        let mut client_cert = false;
+        let mut proxy_protocol = None;
+        let mut identity_headers = defaults.identity_headers;
+
+        for field in entry.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {field:?}"))?;
+            let value = value.trim();
+            match key.trim() {
+                "name" => name = Some(value.to_string()),
+                "addr" => {
+                    listen_addr = Some(
+                        value
+                            .parse()
+                            .map_err(|e| format!("invalid addr {value:?}: {e}"))?,
+                    );
+                }
+                "domains" => {
+                    domain_suffixes = value
+                        .split('|')
+                        .map(|d| d.trim().trim_start_matches('.').to_ascii_lowercase())
+                        .filter(|d| !d.is_empty())
+                        .collect();
+                }
+                "max_request_body_bytes" => {
+                    let n: u64 = value
+                        .parse()
+                        .map_err(|e| format!("invalid max_request_body_bytes {value:?}: {e}"))?;
+                    max_request_body_bytes = (n > 0).then_some(n);
+                }
+                "tls_cert" => tls_cert = Some(value.to_string()),
+                "tls_key" => tls_key = Some(value.to_string()),
+                "tls_cert_dir" => tls_cert_dir = Some(value.to_string()),
+                "tls_secret" => {
+                    tls_secret = value
+                        .parse()
+                        .map_err(|e| format!("invalid tls_secret {value:?}: {e}"))?;
+                }
+                "acme" => {
+                    acme = value
+                        .parse()
+                        .map_err(|e| format!("invalid acme {value:?}: {e}"))?;
+                }
+                "client_cert" => {
+                    client_cert = value
+                        .parse()
+                        .map_err(|e| format!("invalid client_cert {value:?}: {e}"))?;
+                }
+                "proxy_protocol" => {
+                    proxy_protocol = Some(
+                        value
+                            .parse()
+                            .map_err(|e| format!("invalid proxy_protocol {value:?}: {e}"))?,
+                    );
+                }
+                "identity_headers" => {
+                    identity_headers = value
+                        .parse()
+                        .map_err(|e| format!("invalid identity_headers {value:?}: {e}"))?;
+                }
+                other => return Err(format!("unknown listener field {other:?}")),
+            }
+        }
+
+        let name = name.ok_or_else(|| format!("listener {entry:?} has no name"))?;
+        let listen_addr = listen_addr.ok_or_else(|| format!("listener {name:?} has no addr"))?;
+        if listeners.iter().any(|l| l.policy.name == name) {
+            return Err(format!("duplicate listener name {name:?}"));
+        }
+        if listeners.iter().any(|l| l.listen_addr == listen_addr) {
+            return Err(format!("duplicate listener addr {listen_addr}"));
+        }
+        let tls = match (tls_cert, tls_key) {
+            (Some(cert_path), Some(key_path)) => Some(ListenerTls {
+                cert_path,
+                key_path,
+            }),
+            (None, None) => None,
+            _ => return Err(format!("listener {name:?} needs both tls_cert and tls_key")),
+        };
+        if tls_secret {
+            if tls.is_some() {
+                return Err(format!(
+                    "listener {name:?}: tls_secret can't be combined with tls_cert and tls_key"
+                ));
+            }
+            if defaults.tls_secret_ref.is_none() {
+                return Err(format!(
+                    "listener {name:?}: tls_secret requires TLS_SECRET_REF"
+                ));
+            }
+        }
+        if tls_cert_dir.is_some() && (tls.is_some() || tls_secret) {
+            return Err(format!(
+                "listener {name:?}: tls_cert_dir can't be combined with tls_cert, tls_key or tls_secret"
+            ));
+        }
+        if acme {
+            if tls.is_some() || tls_secret || tls_cert_dir.is_some() {
+                return Err(format!(
+                    "listener {name:?}: acme can't be combined with tls_cert, tls_key, tls_secret or tls_cert_dir"
+                ));
+            }
+            if domain_suffixes.is_empty() && defaults.acme_domains.is_empty() {
+                return Err(format!(
+                    "listener {name:?}: acme requires domains or ACME_DOMAINS"
+                ));
+            }
+        }
+        let terminates_tls = tls.is_some() || tls_secret || tls_cert_dir.is_some() || acme;
+        // Certificates loaded by Pingora at startup are served without
+        // asking clients for theirs
+        if client_cert && !(tls_secret || tls_cert_dir.is_some() || acme) {
+            return Err(format!(
+                "listener {name:?}: client_cert requires tls_secret, tls_cert_dir or acme"
+            ));
+        }
+
+        // The PROXY header precedes the TLS handshake, which Pingora performs
+        // before the connection reaches the application, so TLS listeners
+        // don't inherit PROXY_PROTOCOL and can't enable it
+        let proxy_protocol = match (terminates_tls, proxy_protocol) {
+            (true, Some(true)) => {
+                return Err(format!(
+                    "listener {name:?}: proxy_protocol is not supported with TLS"
+                ))
+            }
+            (true, _) => false,
+            (false, explicit) => explicit.unwrap_or(defaults.proxy_protocol),
+        };
+
+        listeners.push(ListenerConfig {
+            listen_addr,
+            proxy_protocol,
+            policy: ListenerPolicy {
+                name,
+                domain_suffixes,
+                max_request_body_bytes,
+                tls: terminates_tls,
+                identity_headers,
+            },
+            tls,
+            tls_secret,
+            tls_cert_dir,
+            acme,
+            client_cert,
+        });
+    }
+
+    if listeners.is_empty() {
+        return Err("no listeners configured".to_string());
+    }
+    Ok(listeners)
+}
+
+/// A Kubernetes cluster whose Devboxes and Pods are watched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterConfig {
+    /// Name used in the registry, metrics and the admin API
+    pub name: String,
+    /// Kubeconfig context (the current context if unset)
+    #[serde(default)]
+    pub context: Option<String>,
+    /// Kubeconfig file (`KUBECONFIG` or the default kubeconfig if unset)
+    #[serde(default)]
+    pub kubeconfig: Option<String>,
+}
+
+impl ClusterConfig {
+    /// Cluster reached through the kubeconfig context `context`.
+    pub fn from_context(context: &str) -> Self {
+        Self {
+            name: context.to_string(),
+            context: Some(context.to_string()),
+            kubeconfig: None,
+        }
+    }
+}
+
+impl Default for ClusterConfig {
+    /// The cluster the gateway runs in (see [`crate::watcher::create_client`]).
     fn default() -> Self {
         Self {
+            name: DEFAULT_CLUSTER.to_string(),
+            context: None,
+            kubeconfig: None,
+        }
+    }
+}
+
+/// Entry of the YAML form of `CLUSTERS`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ClusterEntry {
+    Context(String),
+    Cluster(ClusterConfig),
+}
+
+/// Parse the `CLUSTERS` specification.
+///
+/// Either a comma-separated list of kubeconfig contexts, each naming its
+/// cluster, or a YAML list whose entries are contexts or maps with a
+/// `name` and optional `context` and `kubeconfig`:
+///
+/// ```text
+/// CLUSTERS=hzh,bja
+/// CLUSTERS='[{name: hzh, kubeconfig: /kube/hzh.yaml}, {name: bja, context: bja-admin}]'
+/// ```
+pub fn parse_clusters(spec: &str) -> Result<Vec<ClusterConfig>, String> {
+    let spec = spec.trim();
+    let clusters: Vec<ClusterConfig> = if spec.starts_with('[') || spec.starts_with('-') {
+        let entries: Vec<ClusterEntry> =
+            serde_yaml::from_str(spec).map_err(|e| format!("invalid cluster list: {e}"))?;
+        entries
+            .into_iter()
+            .map(|entry| match entry {
+                ClusterEntry::Context(context) => ClusterConfig::from_context(context.trim()),
+                ClusterEntry::Cluster(cluster) => cluster,
+            })
+            .collect()
+    } else {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(ClusterConfig::from_context)
+            .collect()
+    };
+
+    if clusters.is_empty() {
+        return Err("no clusters configured".to_string());
+    }
+    for (i, cluster) in clusters.iter().enumerate() {
+        if cluster.name.is_empty() {
+            return Err("cluster without a name".to_string());
+        }
+        if clusters[..i].iter().any(|c| c.name == cluster.name) {
+            return Err(format!("duplicate cluster name: {}", cluster.name));
+        }
+    }
+    Ok(clusters)
+}
+
+/// Status answering each kind of failure reaching a devbox.
+///
+/// Failures the gateway cannot tell apart keep Pingora's 502.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FailureStatuses {
+    /// `UPSTREAM_UNAVAILABLE_STATUS`: the devbox has no usable address, or
+    /// none can be routed to (default 503)
+    pub unavailable: u16,
+    /// `UPSTREAM_REFUSED_STATUS`: the devbox refused the connection
+    /// (default 502)
+    pub refused: u16,
+    /// `UPSTREAM_TIMEOUT_STATUS`: connecting to the devbox timed out
+    /// (default 504)
+    pub timeout: u16,
+    /// `UPSTREAM_INVALID_RESPONSE_STATUS`: the devbox answered with
+    /// something that isn't HTTP (default 502)
+    pub invalid_response: u16,
+}
+
+impl Default for FailureStatuses {
+    fn default() -> Self {
+        Self {
+            unavailable: 503,
+            refused: 502,
+            timeout: 504,
+            invalid_response: 502,
+        }
+    }
+}
+
+impl FailureStatuses {
+    /// Only server errors describe a failure of the gateway's upstream.
+    pub fn validate(&self) -> Result<(), String> {
+        for status in [
+            self.unavailable,
+            self.refused,
+            self.timeout,
+            self.invalid_response,
+        ] {
+            if !(500..=599).contains(&status) {
+                return Err(format!("{status} is not a 5xx status"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Pingora server settings.
+///
+/// Each field comes from one environment variable and maps to one field of
+/// Pingora's [`Opt`] (command line options) or [`ServerConf`] (server
+/// configuration); unset fields keep Pingora's defaults.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServerTuning {
+    /// `SERVER_THREADS` → [`ServerConf::threads`]: worker threads of each
+    /// service, i.e. of every listener, the admin API and metrics
+    pub threads: Option<usize>,
+    /// `SERVER_WORK_STEALING` → [`ServerConf::work_stealing`]: let the
+    /// threads of a service steal each other's tasks (default `true`)
+    pub work_stealing: bool,
+    /// `SERVER_UPGRADE` → [`Opt::upgrade`]: take over the listening sockets
+    /// of a running instance through the upgrade socket instead of binding
+    /// them, for zero-downtime restarts
+    pub upgrade: bool,
+    /// `SERVER_UPGRADE_SOCK` → [`ServerConf::upgrade_sock`]: Unix socket the
+    /// listening sockets are handed over on (required with `upgrade`)
+    pub upgrade_sock: Option<String>,
+    /// `SERVER_DAEMON` → [`Opt::daemon`]: detach into the background
+    pub daemon: bool,
+    /// `SERVER_PID_FILE` → [`ServerConf::pid_file`]: where the daemon writes
+    /// its pid
+    pub pid_file: Option<String>,
+}
+
+impl Default for ServerTuning {
+    fn default() -> Self {
+        Self {
+            threads: None,
+            work_stealing: true,
+            upgrade: false,
+            upgrade_sock: None,
+            daemon: false,
+            pid_file: None,
+        }
+    }
+}
+
+impl ServerTuning {
+    /// Reject combinations Pingora would only trip over once running.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.threads == Some(0) {
+            return Err("threads must be positive".to_string());
+        }
+        if self.upgrade && self.upgrade_sock.is_none() {
+            return Err("upgrade needs an upgrade socket path".to_string());
+        }
+        Ok(())
+    }
+
+    /// Pingora's command line options.
+    pub fn opt(&self) -> Opt {
+        Opt {
+            upgrade: self.upgrade,
+            daemon: self.daemon,
+            ..Opt::default()
+        }
+    }
+
+    /// Apply the settings to Pingora's server configuration.
+    pub fn apply(&self, conf: &mut ServerConf) {
+        if let Some(threads) = self.threads {
+            conf.threads = threads;
+        }
+        conf.work_stealing = self.work_stealing;
+        if let Some(upgrade_sock) = &self.upgrade_sock {
+            conf.upgrade_sock.clone_from(upgrade_sock);
+        }
+        if let Some(pid_file) = &self.pid_file {
+            conf.pid_file.clone_from(pid_file);
+        }
+    }
+}
+
+/// Tokio runtime the Kubernetes watchers, reporters and reloaders run on.
+///
+/// The watchers are light, so small deployments can do with a runtime
+/// much smaller than the default of one worker per core.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct WatcherRuntime {
+    /// `WATCHER_THREADS`: worker threads of the multi-thread runtime (one
+    /// per core if unset)
+    pub threads: Option<usize>,
+    /// `WATCHER_CURRENT_THREAD`: run everything on a single thread instead
+    pub current_thread: bool,
+}
+
+impl WatcherRuntime {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.threads {
+            Some(0) => Err("threads must be positive".to_string()),
+            Some(_) if self.current_thread => {
+                Err("threads cannot be set for a current-thread runtime".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Builder of the runtime.
+    pub fn builder(&self) -> tokio::runtime::Builder {
+        let mut builder = if self.current_thread {
+            tokio::runtime::Builder::new_current_thread()
+        } else {
+            tokio::runtime::Builder::new_multi_thread()
+        };
+        if let Some(threads) = self.threads {
+            builder.worker_threads(threads);
+        }
+        builder.thread_name("httpgate-watcher").enable_all();
+        builder
+    }
+}
+
+/// User the gateway serves as and where its listening sockets come from.
+///
+/// With any of these set the listeners are bound at startup, before the
+/// services start, so that ports below 1024 can be bound as root and
+/// served as an unprivileged user.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Privileges {
+    /// `RUN_AS_USER`: user (name or uid) to switch to once the listeners
+    /// are bound
+    pub run_as_user: Option<String>,
+    /// `RUN_AS_GROUP`: group (name or gid) to switch to, instead of the
+    /// primary group of `run_as_user`
+    pub run_as_group: Option<String>,
+    /// `SOCKET_ACTIVATION`: serve on the listening sockets passed by systemd
+    /// (`LISTEN_FDS`) instead of binding their addresses (Linux only)
+    pub socket_activation: bool,
+}
+
+impl Privileges {
+    /// Reject the settings the platform has no API for.
+    pub fn validate(&self) -> Result<(), String> {
+        if !cfg!(unix) && (self.run_as_user.is_some() || self.run_as_group.is_some()) {
+            return Err("switching user is only supported on Unix".to_string());
+        }
+        if !cfg!(target_os = "linux") && self.socket_activation {
+            return Err("socket activation is only supported on Linux".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether the listeners are bound before the services start.
+    pub fn prebind(&self) -> bool {
+        self.run_as_user.is_some() || self.run_as_group.is_some() || self.socket_activation
+    }
+}
+
+/// Parse a duration such as "500ms", "5s", "2m" or "1h".
+///
+/// A bare number is interpreted as seconds.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: u64 = value.parse().ok()?;
+
+    match unit {
+        "ms" => Some(Duration::from_millis(value)),
+        "" | "s" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_secs(value.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(value.checked_mul(3600)?)),
+        _ => None,
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut config = Self {
             listen_addr: "0.0.0.0:8080".parse().unwrap(),
             log_level: "info".to_string(),
+            host_pattern: None,
+            normalize_host: true,
+            legacy_hosts: LegacyHosts::default(),
+            upstream_ca_file: None,
+            upstream_sni: None,
+            slow_request_threshold: None,
+            websocket_idle_timeout: None,
+            access_log_sample: 1.0,
+            log_headers_on_error: false,
+            sensitive_headers: default_sensitive_headers(),
+            max_request_timeout: Some(DEFAULT_MAX_REQUEST_TIMEOUT),
+            request_timeout: None,
+            deadline_header: HeaderName::from_static(deadline::X_REQUEST_TIMEOUT_MS),
+            expect_continue: ExpectContinueMode::default(),
+            max_request_body_bytes: None,
+            max_response_body_bytes: None,
+            response_limit_exempt_types: vec![DEFAULT_RESPONSE_LIMIT_EXEMPT_TYPES.to_string()],
+            max_uri_length: None,
+            strict_protocol: false,
+            max_request_header_bytes: DEFAULT_MAX_REQUEST_HEADER_BYTES,
+            normalize_paths: false,
+            max_global_inflight: None,
+            max_per_client_inflight: None,
+            namespace_max_inflight: None,
+            namespace_rate_limit: None,
+            namespace_rate_burst: None,
+            limits_configmap: None,
+            tls_secret_ref: None,
+            acme_directory_url: DEFAULT_ACME_DIRECTORY_URL.to_string(),
+            acme_email: None,
+            acme_domains: Vec::new(),
+            acme_cache_dir: DEFAULT_ACME_CACHE_DIR.to_string(),
+            acme_renew_before: DEFAULT_ACME_RENEW_BEFORE,
+            metrics_addr: None,
+            header_size_metrics: true,
+            canonicalize_header_case: false,
+            identity_headers: false,
+            identity_header_names: IdentityHeaders::default(),
+            upstream_connect_retries: DEFAULT_UPSTREAM_CONNECT_RETRIES,
+            upstream_retry_policy: RetryPolicy::default(),
+            upstream_failover_budget: Some(DEFAULT_UPSTREAM_FAILOVER_BUDGET),
+            cors_allowed_origins: Vec::new(),
+            cors_allowed_methods: DEFAULT_CORS_ALLOWED_METHODS.to_string(),
+            cors_allowed_headers: None,
+            cors_max_age: DEFAULT_CORS_MAX_AGE,
+            cors_allow_credentials: false,
+            signing_key: None,
+            route_override_key: None,
+            route_override_max_age: DEFAULT_ROUTE_OVERRIDE_MAX_AGE,
+            activity_reporting: ActivityReporting::default(),
+            activity_report_interval: DEFAULT_ACTIVITY_REPORT_INTERVAL,
+            idle_webhook_url: None,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            k8s_events: true,
+            k8s_events_interval: DEFAULT_K8S_EVENTS_INTERVAL,
+            proxy_protocol: false,
+            service_proxy_protocol: ServiceProxyProtocol::default(),
+            internal_cidrs: Vec::new(),
+            self_addrs: Vec::new(),
+            allow_loopback_backends: false,
+            max_registry_entries: registry::DEFAULT_MAX_ENTRIES,
+            imported_entries: ImportedEntries::Overwrite,
+            registry_snapshot_file: None,
+            warmup: true,
+            warmup_state_file: None,
+            warmup_backends: DEFAULT_WARMUP_BACKENDS,
+            warmup_concurrency: DEFAULT_WARMUP_CONCURRENCY,
+            warmup_timeout: DEFAULT_WARMUP_TIMEOUT,
+            tcp_passthrough_addr: None,
+            tcp_passthrough_domains: Vec::new(),
+            admin_addr: None,
+            admin_token: None,
+            debug_endpoints: false,
+            grpc_health_addr: None,
+            blocked_unique_ids: Vec::new(),
+            blocked_namespaces: Vec::new(),
+            blocklist_file: None,
+            blocked_status: DEFAULT_BLOCKED_STATUS,
+            blocked_message: DEFAULT_BLOCKED_MESSAGE.to_string(),
+            tarpit: false,
+            tarpit_cidrs: Vec::new(),
+            tarpit_paths: Vec::new(),
+            tarpit_scan_threshold: None,
+            tarpit_header_delay: DEFAULT_TARPIT_HEADER_DELAY,
+            tarpit_body_interval: DEFAULT_TARPIT_BODY_INTERVAL,
+            tarpit_body_bytes: DEFAULT_TARPIT_BODY_BYTES,
+            tarpit_max_connections: DEFAULT_TARPIT_MAX_CONNECTIONS,
+            starting_page_refresh: None,
+            not_running_retry_after_max: DEFAULT_NOT_RUNNING_RETRY_AFTER_MAX,
+            registry_sync_grace: Some(DEFAULT_REGISTRY_SYNC_GRACE),
+            suggest_on_404: false,
+            failure_statuses: FailureStatuses::default(),
+            error_format: ErrorFormat::default(),
+            locales_dir: None,
+            default_locale: locale::BUILTIN_LOCALE.to_string(),
+            cache_max_bytes: None,
+            cache_max_object_bytes: DEFAULT_CACHE_MAX_OBJECT_BYTES,
+            cache_require_annotation: false,
+            mirror_max_body_bytes: DEFAULT_MIRROR_MAX_BODY_BYTES,
+            mirror_timeout: DEFAULT_MIRROR_TIMEOUT,
+            pod_ip_gc_interval: Some(DEFAULT_POD_IP_GC_INTERVAL),
+            pod_ip_verify_ttl: Some(DEFAULT_POD_IP_VERIFY_TTL),
+            pod_ip_status_field: None,
+            devbox_watch_mode: DevboxWatchMode::Full,
+            require_pod_ready: false,
+            watch_namespaces: Vec::new(),
+            listeners: Vec::new(),
+            clusters: vec![ClusterConfig::default()],
+            server: ServerTuning::default(),
+            watcher_runtime: WatcherRuntime::default(),
+            privileges: Privileges::default(),
+        };
+        config.listeners = vec![ListenerConfig::from_config(&config)];
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_listener() {
+        let config = Config::default();
+        assert_eq!(config.listeners.len(), 1);
+        assert_eq!(config.listeners[0].policy.name, "default");
+        assert_eq!(config.listeners[0].listen_addr, config.listen_addr);
+        assert!(config.listeners[0].policy.domain_suffixes.is_empty());
+    }
+
+    #[test]
+    fn test_parse_listeners() {
+        let defaults = Config {
+            max_request_body_bytes: Some(1024),
+            ..Default::default()
+        };
+        let listeners = parse_listeners(
+            "name=public,addr=0.0.0.0:8080,domains=devbox.sealos.io; \
+             name=internal,addr=127.0.0.1:8081,domains=.Devbox.svc|devbox.internal,max_request_body_bytes=0",
+            &defaults,
+        )
+        .unwrap();
+
+        assert_eq!(listeners.len(), 2);
+        assert_eq!(listeners[0].policy.name, "public");
+        assert_eq!(listeners[0].listen_addr, "0.0.0.0:8080".parse().unwrap());
+        assert_eq!(
+            listeners[0].policy.domain_suffixes,
+            vec!["devbox.sealos.io"]
+        );
+        assert_eq!(listeners[0].policy.max_request_body_bytes, Some(1024));
+
+        assert_eq!(listeners[1].policy.name, "internal");
+        assert_eq!(
+            listeners[1].policy.domain_suffixes,
+            vec!["devbox.svc", "devbox.internal"]
+        );
+        assert_eq!(listeners[1].policy.max_request_body_bytes, None);
+        assert!(listeners.iter().all(|l| l.tls.is_none()));
+    }
+
+    #[test]
+    fn test_parse_listeners_tls() {
+        let listeners = parse_listeners(
+            "name=secure,addr=0.0.0.0:8443,tls_cert=/tls/tls.crt,tls_key=/tls/tls.key",
+            &Config::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            listeners[0].tls,
+            Some(ListenerTls {
+                cert_path: "/tls/tls.crt".to_string(),
+                key_path: "/tls/tls.key".to_string(),
+            })
+        );
+
+        let defaults = Config::default();
+        assert!(parse_listeners("name=a,addr=0.0.0.0:8443,tls_cert=/c", &defaults).is_err());
+        assert!(parse_listeners("name=a,addr=0.0.0.0:8443,tls_key=/k", &defaults).is_err());
+        assert!(parse_listeners(
+            "name=a,addr=0.0.0.0:8443,tls_cert=/c,tls_key=/k,proxy_protocol=true",
+            &defaults
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_listeners_tls_secret() {
+        let defaults = Config {
+            tls_secret_ref: Some("ingress/wildcard-tls".to_string()),
+            ..Default::default()
+        };
+        let listeners =
+            parse_listeners("name=rotated,addr=0.0.0.0:9443,tls_secret=true", &defaults).unwrap();
+        assert!(listeners[0].tls_secret);
+        assert!(listeners[0].policy.tls);
+        assert_eq!(listeners[0].tls, None);
+
+        for spec in [
+            // Files and Secret at once
+            "name=a,addr=0.0.0.0:9443,tls_secret=true,tls_cert=/c,tls_key=/k",
+            "name=a,addr=0.0.0.0:9443,tls_secret=true,proxy_protocol=true",
+            "name=a,addr=0.0.0.0:9443,tls_secret=yes",
+        ] {
+            assert!(parse_listeners(spec, &defaults).is_err(), "{spec}");
+        }
+        // No Secret to serve
+        assert!(parse_listeners(
+            "name=a,addr=0.0.0.0:9443,tls_secret=true",
+            &Config::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_listeners_tls_cert_dir() {
+        let defaults = Config {
+            tls_secret_ref: Some("ingress/wildcard-tls".to_string()),
+            ..Default::default()
+        };
+        let listeners = parse_listeners(
+            "name=domains,addr=0.0.0.0:10443,tls_cert_dir=/tls/certs",
+            &defaults,
+        )
+        .unwrap();
+        assert_eq!(listeners[0].tls_cert_dir.as_deref(), Some("/tls/certs"));
+        assert!(listeners[0].policy.tls);
+        assert!(!listeners[0].proxy_protocol);
+        assert_eq!(listeners[0].tls, None);
+
+        for spec in [
+            "name=a,addr=0.0.0.0:9443,tls_cert_dir=/d,tls_cert=/c,tls_key=/k",
+            "name=a,addr=0.0.0.0:9443,tls_cert_dir=/d,tls_secret=true",
+            "name=a,addr=0.0.0.0:9443,tls_cert_dir=/d,proxy_protocol=true",
+        ] {
+            assert!(parse_listeners(spec, &defaults).is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn test_parse_listeners_acme() {
+        let defaults = Config::default();
+        let listeners = parse_listeners(
+            "name=acme,addr=0.0.0.0:11443,domains=devbox.example.com,acme=true",
+            &defaults,
+        )
+        .unwrap();
+        assert!(listeners[0].acme);
+        assert!(listeners[0].policy.tls);
+
+        for spec in [
+            // Nothing to order a certificate for
+            "name=a,addr=0.0.0.0:9443,acme=true",
+            "name=a,addr=0.0.0.0:9443,domains=a.com,acme=true,tls_cert_dir=/d",
+            "name=a,addr=0.0.0.0:9443,domains=a.com,acme=true,proxy_protocol=true",
+        ] {
+            assert!(parse_listeners(spec, &defaults).is_err(), "{spec}");
         }
+
+        let defaults = Config {
+            acme_domains: vec!["devbox.example.com".to_string()],
+            ..Default::default()
+        };
+        assert!(parse_listeners("name=a,addr=0.0.0.0:9443,acme=true", &defaults).is_ok());
+    }
+
+    #[test]
+    fn test_parse_listeners_client_cert() {
+        let defaults = Config {
+            tls_secret_ref: Some("ingress/wildcard-tls".to_string()),
+            ..Default::default()
+        };
+        let listeners = parse_listeners(
+            "name=mtls,addr=0.0.0.0:12443,tls_secret=true,client_cert=true;\
+             name=open,addr=0.0.0.0:13443,tls_cert_dir=/d",
+            &defaults,
+        )
+        .unwrap();
+        assert!(listeners[0].client_cert);
+        assert!(!listeners[1].client_cert);
+        assert!(parse_listeners(
+            "name=a,addr=0.0.0.0:9443,tls_cert_dir=/d,client_cert=true",
+            &defaults
+        )
+        .is_ok());
+
+        for spec in [
+            "name=a,addr=0.0.0.0:8080,client_cert=true",
+            "name=a,addr=0.0.0.0:8443,tls_cert=/c,tls_key=/k,client_cert=true",
+            "name=a,addr=0.0.0.0:9443,tls_secret=true,client_cert=yes",
+        ] {
+            assert!(parse_listeners(spec, &defaults).is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn test_parse_listeners_proxy_protocol() {
+        let defaults = Config {
+            proxy_protocol: true,
+            ..Default::default()
+        };
+        let listeners = parse_listeners(
+            "name=lb,addr=0.0.0.0:8080;name=direct,addr=0.0.0.0:8081,proxy_protocol=false",
+            &defaults,
+        )
+        .unwrap();
+        assert!(listeners[0].proxy_protocol);
+        assert!(!listeners[1].proxy_protocol);
+
+        let listeners = parse_listeners(
+            "name=secure,addr=0.0.0.0:8443,tls_cert=/c,tls_key=/k",
+            &defaults,
+        )
+        .unwrap();
+        assert!(!listeners[0].proxy_protocol);
+
+        assert!(parse_listeners("name=a,addr=0.0.0.0:8080,proxy_protocol=yes", &defaults).is_err());
+    }
+
+    #[test]
+    fn test_parse_listeners_identity_headers() {
+        let defaults = Config {
+            identity_headers: true,
+            ..Default::default()
+        };
+        let listeners = parse_listeners(
+            "name=public,addr=0.0.0.0:8080;name=raw,addr=0.0.0.0:8081,identity_headers=false",
+            &defaults,
+        )
+        .unwrap();
+        assert!(listeners[0].policy.identity_headers);
+        assert!(!listeners[1].policy.identity_headers);
+        assert!(
+            ListenerConfig::from_config(&defaults)
+                .policy
+                .identity_headers
+        );
+        assert!(
+            !ListenerConfig::from_config(&Config::default())
+                .policy
+                .identity_headers
+        );
+
+        assert!(
+            parse_listeners("name=a,addr=0.0.0.0:8080,identity_headers=on", &defaults).is_err()
+        );
+    }
+
+    #[test]
+    fn test_service_proxy_protocol() {
+        assert_eq!(
+            "".parse::<ServiceProxyProtocol>().unwrap(),
+            ServiceProxyProtocol::default()
+        );
+        assert_eq!(
+            "metrics".parse::<ServiceProxyProtocol>().unwrap(),
+            ServiceProxyProtocol {
+                metrics: true,
+                admin: false,
+            }
+        );
+        assert_eq!(
+            " admin , metrics,".parse::<ServiceProxyProtocol>().unwrap(),
+            ServiceProxyProtocol {
+                metrics: true,
+                admin: true,
+            }
+        );
+        assert!("metrics,proxy".parse::<ServiceProxyProtocol>().is_err());
+
+        // Independent of the proxy listeners' setting
+        let config = Config {
+            proxy_protocol: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.service_proxy_protocol,
+            ServiceProxyProtocol::default()
+        );
+        assert!(ListenerConfig::from_config(&config).proxy_protocol);
+    }
+
+    #[test]
+    fn test_parse_listeners_invalid() {
+        let defaults = Config::default();
+        assert!(parse_listeners("", &defaults).is_err());
+        assert!(parse_listeners("name=a", &defaults).is_err());
+        assert!(parse_listeners("addr=0.0.0.0:8080", &defaults).is_err());
+        assert!(parse_listeners("name=a,addr=invalid", &defaults).is_err());
+        assert!(parse_listeners("name=a,addr=0.0.0.0:8080,bogus=1", &defaults).is_err());
+        assert!(parse_listeners(
+            "name=a,addr=0.0.0.0:8080;name=a,addr=0.0.0.0:8081",
+            &defaults
+        )
+        .is_err());
+        assert!(parse_listeners(
+            "name=a,addr=0.0.0.0:8080;name=b,addr=0.0.0.0:8080",
+            &defaults
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_listener_matches_host() {
+        let policy = ListenerPolicy {
+            name: "public".to_string(),
+            domain_suffixes: vec!["devbox.sealos.io".to_string()],
+            max_request_body_bytes: None,
+            tls: false,
+            identity_headers: false,
+        };
+        assert!(policy.matches_host("devbox-my-app-8080.devbox.sealos.io"));
+        assert!(!policy.matches_host("devbox-my-app-8080.other.io"));
+        assert!(!policy.matches_host("devbox-my-app-8080.evil.devbox.sealos.io"));
+        assert!(!policy.matches_host("devbox.sealos.io"));
+        assert!(!policy.matches_host("devbox-my-app-8080xdevbox.sealos.io"));
+
+        let any = ListenerPolicy {
+            domain_suffixes: Vec::new(),
+            ..policy
+        };
+        assert!(any.matches_host("devbox-my-app-8080.other.io"));
+    }
+
+    #[test]
+    fn test_parse_clusters() {
+        assert_eq!(
+            parse_clusters("hzh, bja,").unwrap(),
+            vec![
+                ClusterConfig::from_context("hzh"),
+                ClusterConfig::from_context("bja")
+            ]
+        );
+
+        let clusters = parse_clusters(
+            "[{name: hzh, kubeconfig: /kube/hzh.yaml}, {name: bja, context: bja-admin}, gzg]",
+        )
+        .unwrap();
+        assert_eq!(
+            clusters,
+            vec![
+                ClusterConfig {
+                    name: "hzh".to_string(),
+                    context: None,
+                    kubeconfig: Some("/kube/hzh.yaml".to_string()),
+                },
+                ClusterConfig {
+                    name: "bja".to_string(),
+                    context: Some("bja-admin".to_string()),
+                    kubeconfig: None,
+                },
+                ClusterConfig::from_context("gzg"),
+            ]
+        );
+
+        // Block style, as mounted from a ConfigMap
+        let clusters = parse_clusters("- name: hzh\n  context: hzh-admin\n- bja\n").unwrap();
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].context.as_deref(), Some("hzh-admin"));
+        assert_eq!(clusters[1].name, "bja");
+    }
+
+    #[test]
+    fn test_parse_clusters_invalid() {
+        assert!(parse_clusters("").is_err());
+        assert!(parse_clusters(" , ").is_err());
+        assert!(parse_clusters("[]").is_err());
+        assert!(parse_clusters("hzh,hzh").is_err());
+        assert!(parse_clusters("[hzh, {name: hzh, context: other}]").is_err());
+        assert!(parse_clusters("[{name: ''}]").is_err());
+        assert!(parse_clusters("[{context: hzh}]").is_err());
+        assert!(parse_clusters("[{name: hzh, bogus: 1}]").is_err());
+        assert!(parse_clusters("[hzh").is_err());
+    }
+
+    #[test]
+    fn test_server_tuning_defaults() {
+        let tuning = ServerTuning::default();
+        assert!(tuning.validate().is_ok());
+
+        let opt = tuning.opt();
+        assert!(!opt.upgrade);
+        assert!(!opt.daemon);
+
+        let mut conf = ServerConf::default();
+        tuning.apply(&mut conf);
+        let defaults = ServerConf::default();
+        assert_eq!(conf.threads, defaults.threads);
+        assert_eq!(conf.work_stealing, defaults.work_stealing);
+        assert_eq!(conf.upgrade_sock, defaults.upgrade_sock);
+        assert_eq!(conf.pid_file, defaults.pid_file);
+    }
+
+    #[test]
+    fn test_server_tuning_mapping() {
+        let tuning = ServerTuning {
+            threads: Some(8),
+            work_stealing: false,
+            upgrade: true,
+            upgrade_sock: Some("/run/httpgate/upgrade.sock".to_string()),
+            daemon: true,
+            pid_file: Some("/run/httpgate/httpgate.pid".to_string()),
+        };
+        assert!(tuning.validate().is_ok());
+
+        let opt = tuning.opt();
+        assert!(opt.upgrade);
+        assert!(opt.daemon);
+        assert_eq!(opt.conf, None);
+
+        let mut conf = ServerConf::default();
+        tuning.apply(&mut conf);
+        assert_eq!(conf.threads, 8);
+        assert!(!conf.work_stealing);
+        assert_eq!(conf.upgrade_sock, "/run/httpgate/upgrade.sock");
+        assert_eq!(conf.pid_file, "/run/httpgate/httpgate.pid");
+    }
+
+    #[test]
+    fn test_server_tuning_invalid() {
+        let upgrade_without_sock = ServerTuning {
+            upgrade: true,
+            ..Default::default()
+        };
+        assert!(upgrade_without_sock.validate().is_err());
+
+        let no_threads = ServerTuning {
+            threads: Some(0),
+            ..Default::default()
+        };
+        assert!(no_threads.validate().is_err());
+
+        // The socket alone is fine: a later instance may upgrade from it
+        let sock_only = ServerTuning {
+            upgrade_sock: Some("/run/httpgate/upgrade.sock".to_string()),
+            ..Default::default()
+        };
+        assert!(sock_only.validate().is_ok());
+    }
+
+    #[test]
+    fn test_privileges() {
+        let privileges = Privileges::default();
+        assert!(privileges.validate().is_ok());
+        assert!(!privileges.prebind());
+
+        let run_as = Privileges {
+            run_as_user: Some("httpgate".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(run_as.validate().is_ok(), cfg!(unix));
+        assert!(run_as.prebind());
+
+        let activated = Privileges {
+            socket_activation: true,
+            ..Default::default()
+        };
+        assert_eq!(activated.validate().is_ok(), cfg!(target_os = "linux"));
+        assert!(activated.prebind());
+    }
+
+    #[test]
+    fn test_watcher_runtime() {
+        use tokio::runtime::RuntimeFlavor;
+
+        let runtime = WatcherRuntime::default();
+        assert!(runtime.validate().is_ok());
+        let built = runtime.builder().build().unwrap();
+        assert_eq!(built.handle().runtime_flavor(), RuntimeFlavor::MultiThread);
+
+        let runtime = WatcherRuntime {
+            threads: Some(2),
+            current_thread: false,
+        };
+        assert!(runtime.validate().is_ok());
+        let built = runtime.builder().build().unwrap();
+        assert_eq!(built.metrics().num_workers(), 2);
+
+        let runtime = WatcherRuntime {
+            threads: None,
+            current_thread: true,
+        };
+        assert!(runtime.validate().is_ok());
+        let built = runtime.builder().build().unwrap();
+        assert_eq!(
+            built.handle().runtime_flavor(),
+            RuntimeFlavor::CurrentThread
+        );
+        assert_eq!(built.block_on(async { 1 + 1 }), 2);
+    }
+
+    #[test]
+    fn test_watcher_runtime_invalid() {
+        let no_threads = WatcherRuntime {
+            threads: Some(0),
+            current_thread: false,
+        };
+        assert!(no_threads.validate().is_err());
+        let contradictory = WatcherRuntime {
+            threads: Some(4),
+            current_thread: true,
+        };
+        assert!(contradictory.validate().is_err());
+    }
+
+    #[test]
+    fn test_failure_statuses_invalid() {
+        assert!(FailureStatuses::default().validate().is_ok());
+        let all_503 = FailureStatuses {
+            unavailable: 503,
+            refused: 503,
+            timeout: 503,
+            invalid_response: 503,
+        };
+        assert!(all_503.validate().is_ok());
+
+        let not_found = FailureStatuses {
+            unavailable: 404,
+            ..Default::default()
+        };
+        assert!(not_found.validate().is_err());
+        let out_of_range = FailureStatuses {
+            timeout: 600,
+            ..Default::default()
+        };
+        assert!(out_of_range.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("5s"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_duration("5"), Some(Duration::from_secs(5)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("ms"), None);
+        assert_eq!(parse_duration("5d"), None);
+        assert_eq!(parse_duration("-5s"), None);
     }
 }
@@ -0,0 +1,433 @@
+use http::header::{
+    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, CONTENT_LENGTH, ORIGIN, VARY,
+};
+use http::{HeaderValue, Method};
+use pingora_core::Result;
+use pingora_http::{RequestHeader, ResponseHeader};
+
+use crate::config::Config;
+use crate::policy::DevboxPolicy;
+
+/// Origin wildcard
+const ANY_ORIGIN: &str = "*";
+
+/// Gateway-level CORS handling.
+///
+/// Enabled per devbox by its `cors` annotation, or when either
+/// `CORS_ALLOWED_ORIGINS` or the devbox's `cors-allowed-origins` annotation
+/// lists origins. Preflight requests are then answered by the gateway, and
+/// responses to allowed origins get the `Access-Control-Allow-Origin` header.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    allowed_origins: Vec<String>,
+    allowed_methods: String,
+    allowed_headers: Option<String>,
+    max_age_secs: u64,
+    allow_credentials: bool,
+}
+
+impl Cors {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            allowed_origins: config.cors_allowed_origins.clone(),
+            allowed_methods: config.cors_allowed_methods.clone(),
+            allowed_headers: config.cors_allowed_headers.clone(),
+            max_age_secs: config.cors_max_age.as_secs(),
+            allow_credentials: config.cors_allow_credentials,
+        }
+    }
+
+    /// Settings for a devbox: its `cors` annotation over the global
+    /// configuration, or else the global configuration with the origins of
+    /// its `cors-allowed-origins` annotation.
+    fn settings<'a>(&'a self, policy: &'a DevboxPolicy) -> Settings<'a> {
+        if let Some(cors) = &policy.cors {
+            return Settings {
+                origins: &cors.origins,
+                methods: cors.methods.as_deref().unwrap_or(&self.allowed_methods),
+                headers: cors.headers.as_deref().or(self.allowed_headers.as_deref()),
+                max_age_secs: cors.max_age_secs.unwrap_or(self.max_age_secs),
+                allow_credentials: cors.allow_credentials,
+            };
+        }
+
+        let origins = if policy.cors_allowed_origins.is_empty() {
+            &self.allowed_origins
+        } else {
+            &policy.cors_allowed_origins
+        };
+        Settings {
+            origins,
+            methods: &self.allowed_methods,
+            headers: self.allowed_headers.as_deref(),
+            max_age_secs: self.max_age_secs,
+            allow_credentials: self.allow_credentials,
+        }
+    }
+
+    /// Whether the gateway handles CORS for a devbox.
+    pub fn enabled_for(&self, policy: &DevboxPolicy) -> bool {
+        !self.settings(policy).origins.is_empty()
+    }
+
+    /// `Access-Control-Allow-Origin` value for the request's `Origin`, if it
+    /// is allowed for the devbox.
+    ///
+    /// A wildcard echoes the origin when credentials are allowed, since
+    /// browsers reject `*` on credentialed requests.
+    pub fn allow_origin(&self, policy: &DevboxPolicy, req: &RequestHeader) -> Option<HeaderValue> {
+        let origin = req.headers.get(ORIGIN)?;
+        let settings = self.settings(policy);
+        let origins = settings.origins;
+        if origins.iter().any(|o| o == ANY_ORIGIN) {
+            return Some(if settings.allow_credentials {
+                origin.clone()
+            } else {
+                HeaderValue::from_static(ANY_ORIGIN)
+            });
+        }
+        let origin_str = origin.to_str().ok()?;
+        origins
+            .iter()
+            .any(|o| o == origin_str)
+            .then(|| origin.clone())
+    }
+
+    /// Whether a request is a CORS preflight.
+    pub fn is_preflight(req: &RequestHeader) -> bool {
+        req.method == Method::OPTIONS
+            && req.headers.contains_key(ORIGIN)
+            && req.headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// Response to a preflight request.
+    ///
+    /// Disallowed origins get a bare 204, which the browser treats as a
+    /// failed preflight.
+    pub fn preflight_response(
+        &self,
+        policy: &DevboxPolicy,
+        req: &RequestHeader,
+        allow_origin: Option<HeaderValue>,
+    ) -> Result<ResponseHeader> {
+        let mut resp = ResponseHeader::build(204, None)?;
+        resp.insert_header(CONTENT_LENGTH, 0)?;
+        resp.insert_header(VARY, "Origin")?;
+
+        let Some(allow_origin) = allow_origin else {
+            return Ok(resp);
+        };
+        let settings = self.settings(policy);
+        resp.insert_header(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)?;
+        resp.insert_header(ACCESS_CONTROL_ALLOW_METHODS, settings.methods)?;
+        match (
+            settings.headers,
+            req.headers.get(ACCESS_CONTROL_REQUEST_HEADERS),
+        ) {
+            (Some(headers), _) => {
+                resp.insert_header(ACCESS_CONTROL_ALLOW_HEADERS, headers)?;
+            }
+            (None, Some(requested)) => {
+                resp.insert_header(ACCESS_CONTROL_ALLOW_HEADERS, requested.clone())?;
+            }
+            (None, None) => {}
+        }
+        resp.insert_header(ACCESS_CONTROL_MAX_AGE, settings.max_age_secs)?;
+        if settings.allow_credentials {
+            resp.insert_header(ACCESS_CONTROL_ALLOW_CREDENTIALS, "true")?;
+        }
+        Ok(resp)
+    }
+
+    /// Add the CORS headers for an allowed origin to a backend response.
+    ///
+    /// Headers the backend set itself are replaced rather than duplicated,
+    /// and `Vary: Origin` is only added if the backend doesn't vary on the
+    /// origin already.
+    pub fn apply(
+        &self,
+        policy: &DevboxPolicy,
+        resp: &mut ResponseHeader,
+        allow_origin: HeaderValue,
+    ) -> Result<()> {
+        if allow_origin != ANY_ORIGIN && !varies_on_origin(resp) {
+            resp.append_header(VARY, "Origin")?;
+        }
+        resp.insert_header(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)?;
+        if self.settings(policy).allow_credentials {
+            resp.insert_header(ACCESS_CONTROL_ALLOW_CREDENTIALS, "true")?;
+        } else {
+            resp.remove_header(&ACCESS_CONTROL_ALLOW_CREDENTIALS);
+        }
+        Ok(())
+    }
+}
+
+/// CORS settings in effect for one devbox
+struct Settings<'a> {
+    origins: &'a [String],
+    methods: &'a str,
+    headers: Option<&'a str>,
+    max_age_secs: u64,
+    allow_credentials: bool,
+}
+
+/// Whether a response's `Vary` already covers the `Origin` header.
+fn varies_on_origin(resp: &ResponseHeader) -> bool {
+    resp.headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .any(|v| v == "*" || v.eq_ignore_ascii_case("origin"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::CorsPolicy;
+
+    const APP_ORIGIN: &str = "https://app.example.com";
+
+    fn cors(origins: &[&str], allow_credentials: bool) -> Cors {
+        Cors::from_config(&Config {
+            cors_allowed_origins: origins.iter().map(ToString::to_string).collect(),
+            cors_allow_credentials: allow_credentials,
+            ..Default::default()
+        })
+    }
+
+    fn request(method: &str, headers: &[(&'static str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build(method, b"/api", None).unwrap();
+        for (name, value) in headers {
+            req.insert_header(*name, *value).unwrap();
+        }
+        req
+    }
+
+    fn preflight(origin: &str) -> RequestHeader {
+        request(
+            "OPTIONS",
+            &[
+                ("origin", origin),
+                ("access-control-request-method", "PUT"),
+                ("access-control-request-headers", "content-type, x-token"),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_is_preflight() {
+        assert!(Cors::is_preflight(&preflight(APP_ORIGIN)));
+        assert!(!Cors::is_preflight(&request("OPTIONS", &[])));
+        assert!(!Cors::is_preflight(&request(
+            "OPTIONS",
+            &[("origin", APP_ORIGIN)]
+        )));
+        assert!(!Cors::is_preflight(&request(
+            "GET",
+            &[
+                ("origin", APP_ORIGIN),
+                ("access-control-request-method", "GET")
+            ]
+        )));
+    }
+
+    #[test]
+    fn test_allow_origin() {
+        let policy = DevboxPolicy::default();
+        let req = request("GET", &[("origin", APP_ORIGIN)]);
+
+        assert!(!cors(&[], false).enabled_for(&policy));
+        assert_eq!(cors(&[], false).allow_origin(&policy, &req), None);
+
+        let listed = cors(&[APP_ORIGIN], false);
+        assert_eq!(listed.allow_origin(&policy, &req).unwrap(), APP_ORIGIN);
+        let other = request("GET", &[("origin", "https://evil.example")]);
+        assert_eq!(listed.allow_origin(&policy, &other), None);
+        assert_eq!(listed.allow_origin(&policy, &request("GET", &[])), None);
+
+        assert_eq!(
+            cors(&["*"], false).allow_origin(&policy, &req).unwrap(),
+            "*"
+        );
+        assert_eq!(
+            cors(&["*"], true).allow_origin(&policy, &req).unwrap(),
+            APP_ORIGIN
+        );
+    }
+
+    #[test]
+    fn test_annotation_overrides_global_origins() {
+        let policy = DevboxPolicy {
+            cors_allowed_origins: vec![APP_ORIGIN.to_string()],
+            ..Default::default()
+        };
+        let req = request("GET", &[("origin", APP_ORIGIN)]);
+        let global = cors(&["https://other.example"], false);
+        assert_eq!(global.allow_origin(&policy, &req).unwrap(), APP_ORIGIN);
+
+        // Enabled by the annotation alone
+        let disabled = cors(&[], false);
+        assert!(disabled.enabled_for(&policy));
+        assert!(!disabled.enabled_for(&DevboxPolicy::default()));
+    }
+
+    #[test]
+    fn test_preflight_response() {
+        let cors = cors(&[APP_ORIGIN], true);
+        let req = preflight(APP_ORIGIN);
+        let allow_origin = cors.allow_origin(&DevboxPolicy::default(), &req);
+
+        let resp = cors
+            .preflight_response(&DevboxPolicy::default(), &req, allow_origin)
+            .unwrap();
+        assert_eq!(resp.status, 204);
+        let header = |name| resp.headers.get(name).unwrap().to_str().unwrap();
+        assert_eq!(header(ACCESS_CONTROL_ALLOW_ORIGIN), APP_ORIGIN);
+        assert_eq!(
+            header(ACCESS_CONTROL_ALLOW_METHODS),
+            "GET, POST, PUT, PATCH, DELETE, OPTIONS"
+        );
+        // Requested headers are echoed unless configured
+        assert_eq!(
+            header(ACCESS_CONTROL_ALLOW_HEADERS),
+            "content-type, x-token"
+        );
+        assert_eq!(header(ACCESS_CONTROL_MAX_AGE), "600");
+        assert_eq!(header(ACCESS_CONTROL_ALLOW_CREDENTIALS), "true");
+        assert_eq!(header(CONTENT_LENGTH), "0");
+    }
+
+    #[test]
+    fn test_preflight_response_disallowed_origin() {
+        let cors = cors(&[APP_ORIGIN], false);
+        let resp = cors
+            .preflight_response(
+                &DevboxPolicy::default(),
+                &preflight("https://evil.example"),
+                None,
+            )
+            .unwrap();
+        assert_eq!(resp.status, 204);
+        assert!(resp.headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        assert!(resp.headers.get(ACCESS_CONTROL_ALLOW_METHODS).is_none());
+    }
+
+    #[test]
+    fn test_apply() {
+        let cors = cors(&[APP_ORIGIN], false);
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Access-Control-Allow-Origin", "*")
+            .unwrap();
+        resp.insert_header("Vary", "Accept-Encoding").unwrap();
+
+        cors.apply(
+            &DevboxPolicy::default(),
+            &mut resp,
+            HeaderValue::from_static(APP_ORIGIN),
+        )
+        .unwrap();
+        assert_eq!(
+            resp.headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            APP_ORIGIN
+        );
+        let vary: Vec<_> = resp.headers.get_all(VARY).iter().collect();
+        assert_eq!(vary, vec!["Accept-Encoding", "Origin"]);
+        assert!(resp.headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+    }
+
+    fn annotated(cors: CorsPolicy) -> DevboxPolicy {
+        DevboxPolicy {
+            cors: Some(cors),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cors_annotation_overrides_global_config() {
+        let global = cors(&["https://other.example"], false);
+        let policy = annotated(CorsPolicy {
+            origins: vec![APP_ORIGIN.to_string()],
+            methods: Some("GET, POST".to_string()),
+            max_age_secs: Some(60),
+            allow_credentials: true,
+            ..Default::default()
+        });
+        assert!(global.enabled_for(&policy));
+        assert!(cors(&[], false).enabled_for(&policy));
+
+        let req = preflight(APP_ORIGIN);
+        let allow_origin = global.allow_origin(&policy, &req);
+        assert_eq!(allow_origin.as_ref().unwrap(), APP_ORIGIN);
+        assert_eq!(
+            global.allow_origin(&policy, &preflight("https://other.example")),
+            None
+        );
+
+        let resp = global
+            .preflight_response(&policy, &req, allow_origin)
+            .unwrap();
+        let header = |name| resp.headers.get(name).unwrap().to_str().unwrap();
+        assert_eq!(header(ACCESS_CONTROL_ALLOW_METHODS), "GET, POST");
+        assert_eq!(header(ACCESS_CONTROL_MAX_AGE), "60");
+        assert_eq!(header(ACCESS_CONTROL_ALLOW_CREDENTIALS), "true");
+        // Not set by the annotation: falls back to echoing the request
+        assert_eq!(
+            header(ACCESS_CONTROL_ALLOW_HEADERS),
+            "content-type, x-token"
+        );
+    }
+
+    #[test]
+    fn test_cors_annotation_credentials_not_inherited() {
+        let global = cors(&["*"], true);
+        let policy = annotated(CorsPolicy {
+            origins: vec!["*".to_string()],
+            ..Default::default()
+        });
+        let req = request("GET", &[("origin", APP_ORIGIN)]);
+        assert_eq!(global.allow_origin(&policy, &req).unwrap(), "*");
+
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Access-Control-Allow-Credentials", "true")
+            .unwrap();
+        global
+            .apply(&policy, &mut resp, HeaderValue::from_static("*"))
+            .unwrap();
+        assert!(resp.headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+        assert!(resp.headers.get(VARY).is_none());
+    }
+
+    #[test]
+    fn test_apply_does_not_duplicate_backend_headers() {
+        let cors = cors(&[APP_ORIGIN], true);
+        let policy = DevboxPolicy::default();
+        for vary in ["Origin", "accept-encoding, origin", "*"] {
+            let mut resp = ResponseHeader::build(200, None).unwrap();
+            resp.insert_header("Vary", vary).unwrap();
+            resp.insert_header("Access-Control-Allow-Origin", APP_ORIGIN)
+                .unwrap();
+            resp.insert_header("Access-Control-Allow-Credentials", "true")
+                .unwrap();
+
+            cors.apply(&policy, &mut resp, HeaderValue::from_static(APP_ORIGIN))
+                .unwrap();
+            for name in [
+                VARY,
+                ACCESS_CONTROL_ALLOW_ORIGIN,
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            ] {
+                assert_eq!(
+                    resp.headers.get_all(&name).iter().count(),
+                    1,
+                    "{vary}: {name}"
+                );
+            }
+            assert_eq!(resp.headers.get(VARY).unwrap(), vary);
+        }
+    }
+}
@@ -1,12 +1,18 @@
-use kube::CustomResource;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::NamespaceResourceScope;
+use kube::{CustomResource, Resource};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 /// Devbox Custom Resource Definition
 ///
 /// This struct represents the Devbox CRD from sealos.io.
 /// We only define the fields we need for routing purposes.
-#[derive(CustomResource, Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[derive(CustomResource, Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
 #[kube(
     group = "devbox.sealos.io",
     version = "v1alpha2",
@@ -18,6 +24,22 @@ use serde::{Deserialize, Serialize};
 pub struct DevboxSpec {
     #[serde(default)]
     pub state: Option<String>,
+    #[serde(default)]
+    pub config: Option<DevboxConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DevboxConfig {
+    /// Ports the devbox serves its application on
+    #[serde(default)]
+    pub app_ports: Vec<DevboxAppPort>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DevboxAppPort {
+    pub port: u16,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
@@ -25,6 +47,9 @@ pub struct DevboxSpec {
 pub struct DevboxStatus {
     #[serde(default)]
     pub network: Option<DevboxNetwork>,
+    /// Remaining status fields, kept for [`Devbox::status_field`]
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
@@ -34,6 +59,9 @@ pub struct DevboxNetwork {
     /// Note: JSON field is "uniqueID" (uppercase ID), not "uniqueId"
     #[serde(default, rename = "uniqueID")]
     pub unique_id: Option<String>,
+    /// Remaining network fields, kept for [`Devbox::status_field`]
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
 }
 
 impl Devbox {
@@ -41,6 +69,131 @@ impl Devbox {
     pub fn unique_id(&self) -> Option<&str> {
         self.status.as_ref()?.network.as_ref()?.unique_id.as_deref()
     }
+
+    /// The app port portless hosts are routed to: the spec's only
+    /// `config.appPorts` entry.
+    ///
+    /// Returns `None` if the spec declares no app port, or several.
+    pub fn app_port(&self) -> Option<u16> {
+        match self.spec.config.as_ref()?.app_ports.as_slice() {
+            [app_port] => Some(app_port.port),
+            _ => None,
+        }
+    }
+
+    /// Read the string at the dot-separated `path` of the status (e.g.
+    /// `network.podIP`).
+    ///
+    /// Returns `None` if the status has no such field or it is not a string.
+    pub fn status_field(&self, path: &str) -> Option<String> {
+        let status = serde_json::to_value(self.status.as_ref()?).ok()?;
+        path.split('.')
+            .try_fold(&status, |value, key| value.get(key))?
+            .as_str()
+            .map(ToString::to_string)
+    }
+}
+
+/// Reduced variant of [`Devbox`] with only what routing reads, for
+/// `DEVBOX_WATCH_MODE=slim`.
+///
+/// The API server still sends whole objects, but deserializing skips
+/// `managedFields`, labels, owner references and all of the status but the
+/// uniqueID, so far less of each object is allocated. Without the rest of
+/// the status, [`Devbox::status_field`] finds nothing.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SlimDevbox {
+    #[serde(deserialize_with = "deserialize_slim_metadata")]
+    pub metadata: ObjectMeta,
+    #[serde(default)]
+    pub spec: DevboxSpec,
+    #[serde(default)]
+    pub status: Option<SlimDevboxStatus>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SlimDevboxStatus {
+    #[serde(default)]
+    pub network: Option<SlimDevboxNetwork>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SlimDevboxNetwork {
+    #[serde(default, rename = "uniqueID")]
+    pub unique_id: Option<String>,
+}
+
+/// The metadata fields routing reads, plus the `resourceVersion` the watch
+/// resumes from
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SlimMetadata {
+    name: Option<String>,
+    namespace: Option<String>,
+    uid: Option<String>,
+    resource_version: Option<String>,
+    annotations: Option<BTreeMap<String, String>>,
+}
+
+fn deserialize_slim_metadata<'de, D>(deserializer: D) -> Result<ObjectMeta, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let meta = SlimMetadata::deserialize(deserializer)?;
+    Ok(ObjectMeta {
+        name: meta.name,
+        namespace: meta.namespace,
+        uid: meta.uid,
+        resource_version: meta.resource_version,
+        annotations: meta.annotations,
+        ..Default::default()
+    })
+}
+
+impl Resource for SlimDevbox {
+    type DynamicType = ();
+    type Scope = NamespaceResourceScope;
+
+    fn kind(dt: &()) -> Cow<'_, str> {
+        Devbox::kind(dt)
+    }
+
+    fn group(dt: &()) -> Cow<'_, str> {
+        Devbox::group(dt)
+    }
+
+    fn version(dt: &()) -> Cow<'_, str> {
+        Devbox::version(dt)
+    }
+
+    fn plural(dt: &()) -> Cow<'_, str> {
+        Devbox::plural(dt)
+    }
+
+    fn meta(&self) -> &ObjectMeta {
+        &self.metadata
+    }
+
+    fn meta_mut(&mut self) -> &mut ObjectMeta {
+        &mut self.metadata
+    }
+}
+
+impl From<SlimDevbox> for Devbox {
+    fn from(slim: SlimDevbox) -> Self {
+        let unique_id = slim.status.and_then(|s| s.network?.unique_id);
+        Self {
+            metadata: slim.metadata,
+            spec: slim.spec,
+            status: unique_id.map(|unique_id| DevboxStatus {
+                network: Some(DevboxNetwork {
+                    unique_id: Some(unique_id),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -51,11 +204,13 @@ mod tests {
     fn test_devbox_unique_id() {
         let devbox = Devbox {
             metadata: Default::default(),
-            spec: DevboxSpec { state: None },
+            spec: DevboxSpec::default(),
             status: Some(DevboxStatus {
                 network: Some(DevboxNetwork {
                     unique_id: Some("outdoor-before-78648".to_string()),
+                    ..Default::default()
                 }),
+                ..Default::default()
             }),
         };
 
@@ -66,10 +221,103 @@ mod tests {
     fn test_devbox_unique_id_missing() {
         let devbox = Devbox {
             metadata: Default::default(),
-            spec: DevboxSpec { state: None },
+            spec: DevboxSpec::default(),
             status: None,
         };
 
         assert_eq!(devbox.unique_id(), None);
     }
+
+    #[test]
+    fn test_devbox_app_port() {
+        let devbox = |config: serde_json::Value| -> Devbox {
+            serde_json::from_value(serde_json::json!({
+                "apiVersion": "devbox.sealos.io/v1alpha2",
+                "kind": "Devbox",
+                "metadata": {"name": "my-app", "namespace": "ns-test"},
+                "spec": {"state": "Running", "config": config},
+            }))
+            .unwrap()
+        };
+
+        let one = devbox(serde_json::json!({
+            "appPorts": [{"name": "app", "port": 3000, "protocol": "TCP"}]
+        }));
+        assert_eq!(one.app_port(), Some(3000));
+
+        let several = devbox(serde_json::json!({
+            "appPorts": [{"port": 3000}, {"port": 8080}]
+        }));
+        assert_eq!(several.app_port(), None);
+        assert_eq!(devbox(serde_json::json!({"appPorts": []})).app_port(), None);
+        assert_eq!(devbox(serde_json::json!({})).app_port(), None);
+        assert_eq!(devbox(serde_json::Value::Null).app_port(), None);
+    }
+
+    #[test]
+    fn test_devbox_status_field() {
+        let devbox: Devbox = serde_json::from_value(serde_json::json!({
+            "apiVersion": "devbox.sealos.io/v1alpha2",
+            "kind": "Devbox",
+            "metadata": {"name": "my-app", "namespace": "ns-test"},
+            "spec": {},
+            "status": {
+                "network": {"uniqueID": "outdoor-before-78648", "podIP": "10.0.0.5"},
+                "serving": {"ip": "10.0.0.6", "ready": true},
+                "phase": "Running"
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(devbox.unique_id(), Some("outdoor-before-78648"));
+        assert_eq!(
+            devbox.status_field("network.podIP").as_deref(),
+            Some("10.0.0.5")
+        );
+        assert_eq!(
+            devbox.status_field("serving.ip").as_deref(),
+            Some("10.0.0.6")
+        );
+        assert_eq!(devbox.status_field("phase").as_deref(), Some("Running"));
+        // Missing fields and non-string values
+        assert_eq!(devbox.status_field("network.hostIP"), None);
+        assert_eq!(devbox.status_field("serving.ready"), None);
+        assert_eq!(devbox.status_field("serving"), None);
+        assert_eq!(devbox.status_field("phase.ip"), None);
+    }
+
+    #[test]
+    fn test_slim_devbox() {
+        let object = serde_json::json!({
+            "apiVersion": "devbox.sealos.io/v1alpha2",
+            "kind": "Devbox",
+            "metadata": {
+                "name": "my-app",
+                "namespace": "ns-test",
+                "uid": "3f6c",
+                "resourceVersion": "42",
+                "labels": {"app": "my-app"},
+                "annotations": {"devbox.sealos.io/response-cache": "true"},
+                "managedFields": [{"manager": "controller", "operation": "Update"}]
+            },
+            "spec": {"state": "Running", "config": {"appPorts": [{"port": 3000}]}},
+            "status": {
+                "network": {"uniqueID": "outdoor-before-78648", "podIP": "10.0.0.5"},
+                "phase": "Running"
+            }
+        });
+        let slim: SlimDevbox = serde_json::from_value(object.clone()).unwrap();
+        assert_eq!(slim.meta().resource_version.as_deref(), Some("42"));
+        assert!(slim.metadata.managed_fields.is_none());
+        assert!(slim.metadata.labels.is_none());
+        assert_eq!(SlimDevbox::plural(&()), Devbox::plural(&()));
+
+        let full: Devbox = serde_json::from_value(object).unwrap();
+        let from_slim = Devbox::from(slim);
+        assert_eq!(from_slim.unique_id(), full.unique_id());
+        assert_eq!(from_slim.app_port(), full.app_port());
+        assert_eq!(from_slim.metadata.annotations, full.metadata.annotations);
+        // The rest of the status is left out
+        assert_eq!(from_slim.status_field("network.podIP"), None);
+    }
 }
@@ -0,0 +1,173 @@
+use std::time::{Duration, Instant};
+
+use http::{HeaderMap, HeaderName};
+
+/// Header carrying the client's time budget for a request, in milliseconds.
+/// The gateway forwards what is left of it to the backend.
+pub const X_REQUEST_TIMEOUT_MS: &str = "x-request-timeout-ms";
+
+/// gRPC's deadline header, whose values carry their own unit (e.g. `250m`)
+pub const GRPC_TIMEOUT: &str = "grpc-timeout";
+
+/// Most digits of a `grpc-timeout` value
+const GRPC_TIMEOUT_MAX_DIGITS: u128 = 99_999_999;
+
+/// Header on a 504 telling which hop ran out of time
+pub const X_TIMEOUT_SOURCE: &str = "x-timeout-source";
+
+/// Deadline of a request that arrived at `start`, from its
+/// `X-Request-Timeout-Ms` header bounded by `max`.
+///
+/// Missing, malformed and zero budgets are ignored, so the request runs
+/// without a deadline.
+pub fn from_headers(headers: &HeaderMap, start: Instant, max: Duration) -> Option<Instant> {
+    budget(headers, max).map(|budget| start + budget)
+}
+
+/// Time budget requested by the client, at most `max`
+fn budget(headers: &HeaderMap, max: Duration) -> Option<Duration> {
+    let ms: u64 = headers
+        .get(X_REQUEST_TIMEOUT_MS)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    (ms > 0).then(|| Duration::from_millis(ms).min(max))
+}
+
+/// Time left until `deadline`, or `None` once it has passed.
+pub fn remaining(deadline: Instant, now: Instant) -> Option<Duration> {
+    deadline
+        .checked_duration_since(now)
+        .filter(|left| !left.is_zero())
+}
+
+/// Earlier of two optional deadlines.
+pub fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Value of header `name` forwarding `remaining` to the backend: whole
+/// milliseconds, or gRPC's timeout syntax for [`GRPC_TIMEOUT`].
+///
+/// Rounded down, so the backend never believes it has more time than the
+/// gateway will wait; a sub-millisecond remainder still leaves it 1ms.
+pub fn header_value(name: &HeaderName, remaining: Duration) -> String {
+    let ms = remaining.as_millis().max(1);
+    if name != GRPC_TIMEOUT {
+        return ms.to_string();
+    }
+    // Coarser units for budgets too long for eight digits of milliseconds
+    if ms <= GRPC_TIMEOUT_MAX_DIGITS {
+        format!("{ms}m")
+    } else if ms / 1000 <= GRPC_TIMEOUT_MAX_DIGITS {
+        format!("{}S", ms / 1000)
+    } else {
+        format!("{}H", (ms / 3_600_000).min(GRPC_TIMEOUT_MAX_DIGITS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_REQUEST_TIMEOUT_MS, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_budget_clamped() {
+        let max = Duration::from_secs(30);
+        for (value, expected) in [
+            ("1500", Some(1500)),
+            (" 250 ", Some(250)),
+            ("30000", Some(30_000)),
+            ("30001", Some(30_000)),
+            ("18446744073709551615", Some(30_000)),
+            ("0", None),
+            ("-5", None),
+            ("1.5", None),
+            ("soon", None),
+            ("18446744073709551616", None),
+        ] {
+            assert_eq!(
+                budget(&headers(value), max),
+                expected.map(Duration::from_millis),
+                "{value:?}"
+            );
+        }
+        assert_eq!(budget(&HeaderMap::new(), max), None);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let start = Instant::now();
+        let max = Duration::from_secs(30);
+        assert_eq!(
+            from_headers(&headers("2000"), start, max),
+            Some(start + Duration::from_secs(2))
+        );
+        assert_eq!(
+            from_headers(&headers("60000"), start, max),
+            Some(start + max)
+        );
+        assert_eq!(from_headers(&HeaderMap::new(), start, max), None);
+    }
+
+    #[test]
+    fn test_remaining_budget() {
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(2000);
+
+        // Time spent in the gateway is taken off the backend's budget
+        let now = start + Duration::from_millis(350);
+        let left = remaining(deadline, now).unwrap();
+        assert_eq!(left, Duration::from_millis(1650));
+        let name = HeaderName::from_static(X_REQUEST_TIMEOUT_MS);
+        assert_eq!(header_value(&name, left), "1650");
+
+        assert_eq!(
+            header_value(&name, Duration::from_micros(1_999_900)),
+            "1999",
+            "rounded down"
+        );
+        assert_eq!(header_value(&name, Duration::from_micros(400)), "1");
+
+        assert_eq!(remaining(deadline, deadline), None);
+        assert_eq!(
+            remaining(deadline, deadline + Duration::from_millis(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_grpc_timeout_value() {
+        let name = HeaderName::from_static(GRPC_TIMEOUT);
+        for (remaining, expected) in [
+            (Duration::from_micros(1_999_900), "1999m"),
+            (Duration::from_micros(400), "1m"),
+            (Duration::from_millis(99_999_999), "99999999m"),
+            (Duration::from_millis(100_000_000), "100000S"),
+            (Duration::from_secs(100_000_000), "27777H"),
+            (Duration::MAX, "99999999H"),
+        ] {
+            assert_eq!(header_value(&name, remaining), expected, "{remaining:?}");
+        }
+    }
+
+    #[test]
+    fn test_earliest() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(1);
+        assert_eq!(earliest(Some(later), Some(now)), Some(now));
+        assert_eq!(earliest(None, Some(later)), Some(later));
+        assert_eq!(earliest(Some(now), None), Some(now));
+        assert_eq!(earliest(None, None), None);
+    }
+}
@@ -0,0 +1,225 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use http::{header, HeaderMap};
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+
+/// Length of a CPU profile when `seconds` is not given
+pub const DEFAULT_PROFILE_SECS: u64 = 30;
+
+/// Longest CPU profile that can be requested
+pub const MAX_PROFILE_SECS: u64 = 120;
+
+/// Samples per second taken while profiling
+const PROFILE_FREQUENCY: i32 = 99;
+
+/// Libraries whose frames are skipped while sampling, as unwinding through
+/// them can deadlock
+const PROFILE_BLOCKLIST: &[&str] = &["libc", "libgcc", "pthread", "vdso"];
+
+/// Whether a CPU profile is being taken; the profiler is process-wide
+static PROFILING: AtomicBool = AtomicBool::new(false);
+
+/// Whether `headers` carry `Authorization: Bearer <token>`.
+pub fn authorized(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| bool::from(given.as_bytes().ct_eq(token.as_bytes())))
+}
+
+/// Encoding of a CPU profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileFormat {
+    /// Flamegraph SVG
+    Flamegraph,
+    /// pprof protobuf, for `go tool pprof`
+    Pprof,
+}
+
+impl ProfileFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "flamegraph" | "svg" => Some(Self::Flamegraph),
+            "pprof" | "proto" => Some(Self::Pprof),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Flamegraph => "image/svg+xml",
+            Self::Pprof => "application/octet-stream",
+        }
+    }
+}
+
+/// Why a CPU profile could not be taken.
+#[derive(Debug)]
+pub enum ProfileError {
+    /// Another profile is being taken
+    Busy,
+    Profiler(String),
+}
+
+impl std::fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Busy => write!(f, "a profile is already being taken"),
+            Self::Profiler(e) => write!(f, "profiler failed: {e}"),
+        }
+    }
+}
+
+/// Sample the whole process's CPU for `duration`.
+pub async fn cpu_profile(
+    duration: Duration,
+    format: ProfileFormat,
+) -> Result<Vec<u8>, ProfileError> {
+    if PROFILING.swap(true, Ordering::AcqRel) {
+        return Err(ProfileError::Busy);
+    }
+    // The profiler guard isn't Send; sample on a blocking thread
+    let result = tokio::task::spawn_blocking(move || sample(duration, format))
+        .await
+        .unwrap_or_else(|e| Err(ProfileError::Profiler(e.to_string())));
+    PROFILING.store(false, Ordering::Release);
+    result
+}
+
+fn sample(duration: Duration, format: ProfileFormat) -> Result<Vec<u8>, ProfileError> {
+    use pprof::protos::Message;
+
+    let profiler_error = |e: pprof::Error| ProfileError::Profiler(e.to_string());
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(PROFILE_FREQUENCY)
+        .blocklist(PROFILE_BLOCKLIST)
+        .build()
+        .map_err(profiler_error)?;
+    std::thread::sleep(duration);
+    let report = guard.report().build().map_err(profiler_error)?;
+
+    let mut body = Vec::new();
+    match format {
+        ProfileFormat::Flamegraph => report.flamegraph(&mut body).map_err(profiler_error)?,
+        ProfileFormat::Pprof => report
+            .pprof()
+            .map_err(profiler_error)?
+            .encode(&mut body)
+            .map_err(|e| ProfileError::Profiler(e.to_string()))?,
+    }
+    Ok(body)
+}
+
+/// Allocator statistics from jemalloc, in bytes.
+#[cfg(feature = "jemalloc")]
+#[derive(Debug, Clone, Serialize)]
+pub struct HeapStats {
+    /// Allocated by the application
+    pub allocated: usize,
+    /// In active pages
+    pub active: usize,
+    /// Allocator metadata
+    pub metadata: usize,
+    /// In physically resident pages
+    pub resident: usize,
+    /// In mapped chunks
+    pub mapped: usize,
+    /// Retained for reuse rather than returned to the OS
+    pub retained: usize,
+}
+
+#[cfg(feature = "jemalloc")]
+impl HeapStats {
+    /// Read the statistics, refreshing jemalloc's cached copy first.
+    pub fn read() -> Result<Self, String> {
+        use tikv_jemalloc_ctl::{epoch, stats};
+
+        let read = || -> Result<Self, tikv_jemalloc_ctl::Error> {
+            epoch::advance()?;
+            Ok(Self {
+                allocated: stats::allocated::read()?,
+                active: stats::active::read()?,
+                metadata: stats::metadata::read()?,
+                resident: stats::resident::read()?,
+                mapped: stats::mapped::read()?,
+                retained: stats::retained::read()?,
+            })
+        };
+        read().map_err(|e| e.to_string())
+    }
+}
+
+/// Counters of the Tokio runtime serving the request.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeStats {
+    pub workers: usize,
+    /// Tasks spawned and not yet finished
+    pub alive_tasks: usize,
+    /// Tasks waiting in the runtime's shared queue
+    pub global_queue_depth: usize,
+    /// Time each worker has spent running tasks
+    pub worker_busy_secs: Vec<f64>,
+    /// Times each worker has parked for lack of work
+    pub worker_park_counts: Vec<u64>,
+}
+
+impl RuntimeStats {
+    /// Collect the counters of the current runtime, if there is one.
+    pub fn collect() -> Option<Self> {
+        let metrics = tokio::runtime::Handle::try_current().ok()?.metrics();
+        let workers = metrics.num_workers();
+        Some(Self {
+            workers,
+            alive_tasks: metrics.num_alive_tasks(),
+            global_queue_depth: metrics.global_queue_depth(),
+            worker_busy_secs: (0..workers)
+                .map(|worker| metrics.worker_total_busy_duration(worker).as_secs_f64())
+                .collect(),
+            worker_park_counts: (0..workers)
+                .map(|worker| metrics.worker_park_count(worker))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[test]
+    fn test_authorized() {
+        let mut headers = HeaderMap::new();
+        assert!(!authorized(&headers, "s3cret"));
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer s3cret"),
+        );
+        assert!(authorized(&headers, "s3cret"));
+        assert!(!authorized(&headers, "s3cre"));
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Basic s3cret"),
+        );
+        assert!(!authorized(&headers, "s3cret"));
+    }
+
+    #[test]
+    fn test_profile_format() {
+        assert_eq!(ProfileFormat::parse("svg"), Some(ProfileFormat::Flamegraph));
+        assert_eq!(ProfileFormat::parse("pprof"), Some(ProfileFormat::Pprof));
+        assert_eq!(ProfileFormat::parse("json"), None);
+    }
+
+    #[tokio::test]
+    async fn test_runtime_stats() {
+        let stats = RuntimeStats::collect().unwrap();
+        assert_eq!(stats.workers, 1);
+        assert_eq!(stats.worker_busy_secs.len(), 1);
+    }
+}
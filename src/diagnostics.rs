@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::config::{ClusterConfig, Config};
+use crate::status::{COMMIT, VERSION};
+
+/// How the gateway authenticates to a cluster's Kubernetes API, following
+/// the order of [`crate::watcher::create_client`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KubeAuthMode {
+    /// A kubeconfig file: `KUBECONFIG`, or the cluster's own file or context
+    Kubeconfig,
+    /// The Pod's service account
+    InCluster,
+    /// `~/.kube/config`
+    DefaultKubeconfig,
+}
+
+impl KubeAuthMode {
+    /// Mode used for `cluster`, given whether `KUBECONFIG` is set and
+    /// whether the gateway runs in a Pod.
+    pub fn detect(cluster: &ClusterConfig, kubeconfig_env: bool, in_cluster: bool) -> Self {
+        if kubeconfig_env || cluster.kubeconfig.is_some() || cluster.context.is_some() {
+            Self::Kubeconfig
+        } else if in_cluster {
+            Self::InCluster
+        } else {
+            Self::DefaultKubeconfig
+        }
+    }
+
+    /// Mode used for `cluster` in this process's environment.
+    pub fn from_env(cluster: &ClusterConfig) -> Self {
+        let kubeconfig_env = std::env::var_os("KUBECONFIG").is_some();
+        // What `kube::Config::incluster` requires
+        let in_cluster = std::env::var_os("KUBERNETES_SERVICE_HOST").is_some()
+            && std::env::var_os("KUBERNETES_SERVICE_PORT").is_some();
+        Self::detect(cluster, kubeconfig_env, in_cluster)
+    }
+}
+
+/// Optional features the configuration enables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Features {
+    /// Any listener terminates TLS
+    pub tls: bool,
+    pub acme: bool,
+    pub metrics: bool,
+    pub admin: bool,
+    pub grpc_health: bool,
+    pub tcp_passthrough: bool,
+    pub response_cache: bool,
+    pub idle_webhook: bool,
+}
+
+impl Features {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            tls: config
+                .listeners
+                .iter()
+                .any(|l| l.tls.is_some() || l.tls_secret || l.tls_cert_dir.is_some() || l.acme),
+            acme: config.listeners.iter().any(|l| l.acme),
+            metrics: config.metrics_addr.is_some(),
+            admin: config.admin_addr.is_some(),
+            grpc_health: config.grpc_health_addr.is_some(),
+            tcp_passthrough: config.tcp_passthrough_addr.is_some(),
+            response_cache: config.cache_max_bytes.is_some(),
+            idle_webhook: config.idle_webhook_url.is_some(),
+        }
+    }
+}
+
+/// Everything logged in the single `startup` event.
+#[derive(Debug, Clone, Serialize)]
+pub struct StartupDiagnostics {
+    pub version: &'static str,
+    pub commit: &'static str,
+    /// Kubernetes auth mode by cluster
+    pub kube_auth: BTreeMap<String, KubeAuthMode>,
+    pub features: Features,
+    /// Effective configuration, with secrets redacted as `/config` serves it
+    pub config: serde_json::Value,
+}
+
+impl StartupDiagnostics {
+    /// Assemble the diagnostics of `config`, detecting each cluster's auth
+    /// mode with `kube_auth`.
+    pub fn new(config: &Config, kube_auth: impl Fn(&ClusterConfig) -> KubeAuthMode) -> Self {
+        Self {
+            version: VERSION,
+            commit: COMMIT,
+            kube_auth: config
+                .clusters
+                .iter()
+                .map(|cluster| (cluster.name.clone(), kube_auth(cluster)))
+                .collect(),
+            features: Features::new(config),
+            config: serde_json::to_value(config).expect("configuration serializes to JSON"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ListenerConfig;
+
+    #[test]
+    fn test_kube_auth_mode() {
+        let default = ClusterConfig::default();
+        assert_eq!(
+            KubeAuthMode::detect(&default, true, true),
+            KubeAuthMode::Kubeconfig
+        );
+        assert_eq!(
+            KubeAuthMode::detect(&default, false, true),
+            KubeAuthMode::InCluster
+        );
+        assert_eq!(
+            KubeAuthMode::detect(&default, false, false),
+            KubeAuthMode::DefaultKubeconfig
+        );
+
+        // A cluster with its own context always reads a kubeconfig
+        let remote = ClusterConfig::from_context("remote");
+        assert_eq!(
+            KubeAuthMode::detect(&remote, false, true),
+            KubeAuthMode::Kubeconfig
+        );
+    }
+
+    #[test]
+    fn test_startup_diagnostics() {
+        let mut config = Config {
+            metrics_addr: Some("0.0.0.0:9090".parse().unwrap()),
+            signing_key: Some("hunter2".to_string()),
+            clusters: vec![
+                ClusterConfig::default(),
+                ClusterConfig::from_context("remote"),
+            ],
+            ..Default::default()
+        };
+        let mut listener = ListenerConfig::from_config(&config);
+        listener.acme = true;
+        config.listeners = vec![listener];
+
+        let diagnostics = StartupDiagnostics::new(&config, |cluster| {
+            KubeAuthMode::detect(cluster, false, true)
+        });
+        assert_eq!(diagnostics.version, VERSION);
+        assert_eq!(
+            diagnostics.features,
+            Features {
+                tls: true,
+                acme: true,
+                metrics: true,
+                admin: false,
+                grpc_health: false,
+                tcp_passthrough: false,
+                response_cache: false,
+                idle_webhook: false,
+            }
+        );
+
+        let json = serde_json::to_value(&diagnostics).unwrap();
+        let default_cluster = &config.clusters[0].name;
+        assert_eq!(json["kube_auth"][default_cluster], "in-cluster");
+        assert_eq!(json["kube_auth"]["remote"], "kubeconfig");
+        assert_eq!(json["config"]["metrics_addr"], "0.0.0.0:9090");
+        assert!(!json["config"].to_string().contains("hunter2"));
+    }
+}
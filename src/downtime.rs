@@ -0,0 +1,107 @@
+use dashmap::DashMap;
+
+use crate::preview::unix_now;
+
+/// When devboxes were first seen not running, to make clients back off
+/// more the longer a devbox stays down.
+#[derive(Debug)]
+pub struct DowntimeTracker {
+    /// Unix seconds a request first found the devbox not running, by uniqueID
+    down_since: DashMap<String, u64>,
+    /// Largest `Retry-After` sent, in seconds
+    max_retry_after: u64,
+}
+
+impl DowntimeTracker {
+    pub fn new(max_retry_after: u64) -> Self {
+        Self {
+            down_since: DashMap::new(),
+            max_retry_after: max_retry_after.max(1),
+        }
+    }
+
+    /// Record a request finding `unique_id` not running at `now`, returning
+    /// the `Retry-After` seconds to answer it with.
+    ///
+    /// The delay is the time the devbox has been down, rounded up to a power
+    /// of two: 1s at first, then 2s, 4s, 8s... up to the maximum. Clients
+    /// retrying as told thus back off exponentially.
+    pub fn retry_after_at(&self, unique_id: &str, now: u64) -> u64 {
+        let since = *self.down_since.entry(unique_id.to_string()).or_insert(now);
+        now.saturating_sub(since)
+            .max(1)
+            .checked_next_power_of_two()
+            .map_or(self.max_retry_after, |secs| secs.min(self.max_retry_after))
+    }
+
+    /// Record a request finding `unique_id` not running.
+    pub fn retry_after(&self, unique_id: &str) -> u64 {
+        self.retry_after_at(unique_id, unix_now())
+    }
+
+    /// Forget the downtime of `unique_id` once it is resolvable again (or
+    /// gone).
+    pub fn clear(&self, unique_id: &str) {
+        // Most requests are for running devboxes; don't take the write lock
+        if self.down_since.contains_key(unique_id) {
+            self.down_since.remove(unique_id);
+        }
+    }
+
+    /// Number of devboxes currently tracked as down
+    pub fn len(&self) -> usize {
+        self.down_since.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.down_since.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn test_retry_after_escalates() {
+        let tracker = DowntimeTracker::new(60);
+        let delays: Vec<u64> = [0, 1, 2, 3, 5, 9, 20, 40, 3600]
+            .into_iter()
+            .map(|elapsed| tracker.retry_after_at("my-app", NOW + elapsed))
+            .collect();
+        assert_eq!(delays, [1, 1, 2, 4, 8, 16, 32, 60, 60]);
+
+        // Other devboxes have their own downtime
+        assert_eq!(tracker.retry_after_at("other-app", NOW + 3600), 1);
+        assert_eq!(tracker.len(), 2);
+    }
+
+    #[test]
+    fn test_clear_resets() {
+        let tracker = DowntimeTracker::new(60);
+        tracker.retry_after_at("my-app", NOW);
+        assert_eq!(tracker.retry_after_at("my-app", NOW + 10), 16);
+
+        tracker.clear("my-app");
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.retry_after_at("my-app", NOW + 20), 1);
+        assert_eq!(tracker.retry_after_at("my-app", NOW + 22), 2);
+
+        // Clearing an unknown devbox is a no-op
+        tracker.clear("other-app");
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn test_max_retry_after() {
+        let tracker = DowntimeTracker::new(0);
+        assert_eq!(tracker.retry_after_at("my-app", NOW), 1);
+        assert_eq!(tracker.retry_after_at("my-app", NOW + 100), 1);
+
+        let tracker = DowntimeTracker::new(u64::MAX);
+        tracker.retry_after_at("my-app", 0);
+        assert_eq!(tracker.retry_after_at("my-app", u64::MAX), u64::MAX);
+    }
+}
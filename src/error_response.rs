@@ -0,0 +1,460 @@
+use std::borrow::Cow;
+
+use bytes::Bytes;
+use http::header::ACCEPT;
+use http::{HeaderMap, StatusCode};
+use pingora_core::{Error, ErrorSource, ErrorType};
+
+use crate::config::{ErrorFormat, FailureStatuses};
+use crate::locale::Locales;
+use crate::suggest;
+
+/// Media types of each error format, most common first
+const FORMATS: [(ErrorFormat, &[&str]); 3] = [
+    (ErrorFormat::Json, &["application/json"]),
+    (ErrorFormat::Html, &["text/html", "application/xhtml+xml"]),
+    (ErrorFormat::Plain, &["text/plain"]),
+];
+
+/// An error response generated by the gateway rather than a devbox.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GatewayError {
+    pub status: u16,
+    /// Machine-readable error code (e.g. "devbox_not_found")
+    pub code: Cow<'static, str>,
+    /// Human-readable description
+    pub message: Cow<'static, str>,
+    /// uniqueID of the devbox the request was for, if known
+    pub unique_id: Option<String>,
+    /// Title of the HTML page (the status's reason phrase if unset)
+    pub title: Option<String>,
+    /// Language of the message, if translated
+    pub lang: Option<String>,
+}
+
+impl GatewayError {
+    pub const fn new(status: u16, code: &'static str, message: &'static str) -> Self {
+        Self {
+            status,
+            code: Cow::Borrowed(code),
+            message: Cow::Borrowed(message),
+            unique_id: None,
+            title: None,
+            lang: None,
+        }
+    }
+
+    /// Error for `status` with its reason phrase as code and message, for
+    /// failures the gateway has nothing more specific to say about.
+    pub fn from_status(status: u16) -> Self {
+        let reason = StatusCode::from_u16(status)
+            .ok()
+            .and_then(|s| s.canonical_reason())
+            .unwrap_or("Error");
+        Self {
+            status,
+            code: Cow::Owned(reason.to_ascii_lowercase().replace([' ', '-'], "_")),
+            message: Cow::Owned(reason.to_ascii_lowercase()),
+            unique_id: None,
+            title: None,
+            lang: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    #[must_use]
+    pub fn with_message(mut self, message: impl Into<Cow<'static, str>>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    #[must_use]
+    pub fn with_unique_id(mut self, unique_id: &str) -> Self {
+        self.unique_id = Some(unique_id.to_string());
+        self
+    }
+
+    /// Translate the message and title into `lang`, keeping the built-in
+    /// English of those without a translation.
+    #[must_use]
+    pub fn localized(mut self, locales: &Locales, lang: &str) -> Self {
+        let status = self.status.to_string();
+        let vars = [
+            ("status", status.as_str()),
+            ("unique_id", self.unique_id.as_deref().unwrap_or_default()),
+        ];
+        let message = locales.translate(lang, &self.code, &vars);
+        let title = locales.translate(lang, &format!("status.{status}"), &vars);
+        if let Some(title) = title {
+            self.title = Some(title.text);
+        }
+        if let Some(message) = message {
+            self.lang = Some(message.lang.to_string());
+            self.message = Cow::Owned(message.text);
+        }
+        self
+    }
+
+    /// Content type and body of the error in `format`
+    pub fn render(&self, format: ErrorFormat) -> (&'static str, Bytes) {
+        match format {
+            ErrorFormat::Plain => ("text/plain", Bytes::from(self.message.to_string())),
+            ErrorFormat::Json => {
+                let mut body = serde_json::json!({
+                    "error": self.code,
+                    "message": self.message,
+                });
+                if let Some(unique_id) = &self.unique_id {
+                    body["unique_id"] = unique_id.as_str().into();
+                }
+                ("application/json", Bytes::from(body.to_string()))
+            }
+            ErrorFormat::Html => {
+                let title = self.title.as_deref().map_or_else(
+                    || {
+                        StatusCode::from_u16(self.status)
+                            .ok()
+                            .and_then(|s| s.canonical_reason())
+                            .unwrap_or("Error")
+                            .to_string()
+                    },
+                    suggest::html_escape,
+                );
+                let message = suggest::html_escape(&self.message);
+                let body = format!(
+                    "<!DOCTYPE html>\n\
+                     <html{lang}>\n\
+                     <head>\n\
+                     <meta charset=\"utf-8\">\n\
+                     <title>{status} {title}</title>\n\
+                     </head>\n\
+                     <body>\n\
+                     <h1>{status} {title}</h1>\n\
+                     <p>{message}</p>\n\
+                     </body>\n\
+                     </html>\n",
+                    status = self.status,
+                    lang = html_lang(self.lang.as_deref()),
+                );
+                ("text/html; charset=utf-8", Bytes::from(body))
+            }
+        }
+    }
+}
+
+/// Failures reaching a devbox the gateway can tell apart, each answered
+/// with its own status (see [`FailureStatuses`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamFailure {
+    /// No route to the devbox's address
+    Unavailable,
+    /// The devbox refused the connection
+    Refused,
+    /// Connecting to the devbox timed out
+    TimedOut,
+    /// The devbox's response couldn't be parsed
+    InvalidResponse,
+}
+
+impl UpstreamFailure {
+    /// Kind of the upstream failure `e`, if it is one of those told apart.
+    pub fn classify(e: &Error) -> Option<Self> {
+        if *e.esource() != ErrorSource::Upstream {
+            return None;
+        }
+        match e.etype() {
+            ErrorType::ConnectNoRoute => Some(Self::Unavailable),
+            ErrorType::ConnectRefused => Some(Self::Refused),
+            ErrorType::ConnectTimedout | ErrorType::TLSHandshakeTimedout => Some(Self::TimedOut),
+            ErrorType::InvalidHTTPHeader
+            | ErrorType::H1Error
+            | ErrorType::H2Error
+            | ErrorType::InvalidH2 => Some(Self::InvalidResponse),
+            _ => None,
+        }
+    }
+
+    pub const fn status(self, statuses: &FailureStatuses) -> u16 {
+        match self {
+            Self::Unavailable => statuses.unavailable,
+            Self::Refused => statuses.refused,
+            Self::TimedOut => statuses.timeout,
+            Self::InvalidResponse => statuses.invalid_response,
+        }
+    }
+
+    /// Error answering the failure with `status`
+    pub const fn error(self, status: u16) -> GatewayError {
+        match self {
+            Self::Unavailable => {
+                GatewayError::new(status, "devbox_unreachable", "no route to devbox")
+            }
+            Self::Refused => GatewayError::new(
+                status,
+                "connection_refused",
+                "devbox refused the connection",
+            ),
+            Self::TimedOut => {
+                GatewayError::new(status, "connect_timeout", "timed out connecting to devbox")
+            }
+            Self::InvalidResponse => GatewayError::new(
+                status,
+                "invalid_upstream_response",
+                "devbox sent an invalid HTTP response",
+            ),
+        }
+    }
+}
+
+/// `lang` attribute of an HTML page in `lang`, if known
+pub fn html_lang(lang: Option<&str>) -> String {
+    lang.map(|lang| format!(" lang=\"{}\"", suggest::html_escape(lang)))
+        .unwrap_or_default()
+}
+
+/// Pick the error format the client prefers by its `Accept` header.
+///
+/// Each format gets the quality of the most specific media range matching
+/// it. The best one wins, with `default` winning ties, so clients without
+/// a preference (no `Accept`, or `*/*`) get `default`.
+pub fn negotiate(headers: &HeaderMap, default: ErrorFormat) -> ErrorFormat {
+    let ranges: Vec<(&str, f32)> = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(parse_range)
+        .collect();
+    if ranges.is_empty() {
+        return default;
+    }
+
+    let quality = |format: ErrorFormat| {
+        FORMATS
+            .iter()
+            .filter(|(f, _)| *f == format)
+            .flat_map(|(_, types)| types.iter())
+            .filter_map(|media_type| {
+                ranges
+                    .iter()
+                    .filter_map(|&(range, q)| Some((specificity(range, media_type)?, q)))
+                    .max_by_key(|&(specificity, _)| specificity)
+                    .map(|(_, q)| q)
+            })
+            .fold(0.0, f32::max)
+    };
+
+    let mut best = (default, quality(default));
+    for (format, _) in FORMATS {
+        let q = quality(format);
+        if q > best.1 {
+            best = (format, q);
+        }
+    }
+    if best.1 > 0.0 {
+        best.0
+    } else {
+        default
+    }
+}
+
+/// Media range and quality of one `Accept` element
+fn parse_range(element: &str) -> Option<(&str, f32)> {
+    let mut params = element.split(';').map(str::trim);
+    let range = params.next().filter(|r| r.contains('/'))?;
+    let q = params
+        .filter_map(|p| p.strip_prefix("q="))
+        .find_map(|q| q.parse::<f32>().ok())
+        .unwrap_or(1.0)
+        .clamp(0.0, 1.0);
+    Some((range, q))
+}
+
+/// How specifically `range` matches `media_type`: 2 for the type itself,
+/// 1 for `type/*`, 0 for `*/*`
+fn specificity(range: &str, media_type: &str) -> Option<u8> {
+    if range.eq_ignore_ascii_case(media_type) {
+        return Some(2);
+    }
+    let (kind, subtype) = range.split_once('/')?;
+    match (kind, subtype) {
+        ("*", "*") => Some(0),
+        (kind, "*") if media_type.split('/').next()?.eq_ignore_ascii_case(kind) => Some(1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(ACCEPT, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_negotiate() {
+        use ErrorFormat::{Html, Json, Plain};
+
+        for (values, default, expected) in [
+            (&[][..], Plain, Plain),
+            (&[][..], Json, Json),
+            (&["*/*"][..], Plain, Plain),
+            (&["*/*"][..], Html, Html),
+            (&["application/json"][..], Plain, Json),
+            (&["Application/JSON"][..], Plain, Json),
+            (&["text/plain"][..], Json, Plain),
+            // Browsers
+            (
+                &["text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"][..],
+                Json,
+                Html,
+            ),
+            (&["application/json, text/plain;q=0.5"][..], Html, Json),
+            (&["application/json;q=0.5, text/*"][..], Json, Html),
+            // Ties go to the default
+            (&["application/json, text/html"][..], Html, Html),
+            (&["application/json, text/html"][..], Plain, Json),
+            // Split across headers
+            (&["text/plain;q=0.1", "application/json"][..], Plain, Json),
+            // Excluded formats, and nothing acceptable
+            (&["*/*, application/json;q=0"][..], Json, Html),
+            (&["image/png"][..], Json, Json),
+            (&["application/json;q=0"][..], Json, Json),
+            (&["garbage"][..], Html, Html),
+        ] {
+            assert_eq!(
+                negotiate(&accept(values), default),
+                expected,
+                "{values:?} {default:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let error = GatewayError::new(404, "devbox_not_found", "devbox not found")
+            .with_unique_id("outdoor-before-78648");
+
+        let (content_type, body) = error.render(ErrorFormat::Plain);
+        assert_eq!(content_type, "text/plain");
+        assert_eq!(body, "devbox not found");
+
+        let (content_type, body) = error.render(ErrorFormat::Json);
+        assert_eq!(content_type, "application/json");
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "error": "devbox_not_found",
+                "message": "devbox not found",
+                "unique_id": "outdoor-before-78648",
+            })
+        );
+
+        let (content_type, body) = error.render(ErrorFormat::Html);
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("<title>404 Not Found</title>"));
+        assert!(body.contains("<p>devbox not found</p>"));
+
+        // Messages are escaped, and the uniqueID is only sent when known
+        let error = GatewayError::new(403, "blocked", "").with_message("<b>blocked</b>");
+        let (_, body) = error.render(ErrorFormat::Html);
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains("<p>&lt;b&gt;blocked&lt;/b&gt;</p>"));
+        let (_, body) = error.render(ErrorFormat::Json);
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(json.get("unique_id").is_none());
+    }
+
+    #[test]
+    fn test_localized() {
+        let mut locales = Locales::default();
+        locales
+            .add(
+                "zh-CN",
+                "devbox_not_found: \"找不到 devbox {unique_id}\"\nstatus.404: 未找到\n",
+            )
+            .unwrap();
+        let error = GatewayError::new(404, "devbox_not_found", "devbox not found")
+            .with_unique_id("outdoor-before-78648")
+            .localized(&locales, "zh-CN");
+        assert_eq!(error.code, "devbox_not_found");
+        assert_eq!(error.message, "找不到 devbox outdoor-before-78648");
+
+        let (_, body) = error.render(ErrorFormat::Html);
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("<html lang=\"zh-CN\">"));
+        assert!(body.contains("<title>404 未找到</title>"));
+
+        // Errors without a translation keep their English
+        let error =
+            GatewayError::new(503, "overloaded", "gateway overloaded").localized(&locales, "zh-CN");
+        assert_eq!(error.message, "gateway overloaded");
+        let (_, body) = error.render(ErrorFormat::Html);
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("<html>"));
+        assert!(body.contains("<title>503 Service Unavailable</title>"));
+    }
+
+    #[test]
+    fn test_from_status() {
+        let error = GatewayError::from_status(502);
+        assert_eq!(error.code, "bad_gateway");
+        assert_eq!(error.message, "bad gateway");
+        assert_eq!(GatewayError::from_status(504).code, "gateway_timeout");
+        assert_eq!(GatewayError::from_status(599).code, "error");
+    }
+
+    #[test]
+    fn test_classify_upstream_failure() {
+        use UpstreamFailure::{InvalidResponse, Refused, TimedOut, Unavailable};
+
+        for (etype, expected) in [
+            (ErrorType::ConnectNoRoute, Some(Unavailable)),
+            (ErrorType::ConnectRefused, Some(Refused)),
+            (ErrorType::ConnectTimedout, Some(TimedOut)),
+            (ErrorType::TLSHandshakeTimedout, Some(TimedOut)),
+            (ErrorType::InvalidHTTPHeader, Some(InvalidResponse)),
+            (ErrorType::H1Error, Some(InvalidResponse)),
+            (ErrorType::InvalidH2, Some(InvalidResponse)),
+            (ErrorType::ReadTimedout, None),
+            (ErrorType::ConnectionClosed, None),
+        ] {
+            assert_eq!(
+                UpstreamFailure::classify(&Error::new_up(etype.clone())),
+                expected,
+                "{etype:?}"
+            );
+        }
+        // Only the devbox's failures
+        assert_eq!(
+            UpstreamFailure::classify(&Error::new_down(ErrorType::InvalidHTTPHeader)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_upstream_failure_status() {
+        let statuses = FailureStatuses::default();
+        assert_eq!(UpstreamFailure::Unavailable.status(&statuses), 503);
+        assert_eq!(UpstreamFailure::Refused.status(&statuses), 502);
+        assert_eq!(UpstreamFailure::TimedOut.status(&statuses), 504);
+        assert_eq!(UpstreamFailure::InvalidResponse.status(&statuses), 502);
+
+        let error = UpstreamFailure::InvalidResponse.error(502);
+        assert_eq!(error.status, 502);
+        assert_eq!(error.code, "invalid_upstream_response");
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::runtime::events::{Event, EventType, Recorder, Reporter};
+use kube::{Client, Resource};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::crd::Devbox;
+use crate::error::{Error, Result};
+use crate::registry::DevboxInfo;
+
+/// Occurrences of an anomaly within an interval before it is published
+const REPEAT_THRESHOLD: u32 = 3;
+
+/// Events waiting to be published; more are dropped
+const QUEUE_CAPACITY: usize = 256;
+
+/// Tracked (devbox, anomaly) pairs above which stale ones are pruned
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// Routing anomaly reported to the tenant as an Event on their Devbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Anomaly {
+    /// Requests arrived while the devbox had no running Pod
+    PodNotReady,
+    /// The gateway could not connect to the devbox
+    BackendUnreachable,
+    /// The devbox routes back to the gateway
+    RoutingLoop,
+}
+
+impl Anomaly {
+    /// Event reason
+    pub const fn reason(self) -> &'static str {
+        match self {
+            Self::PodNotReady => "PodNotReady",
+            Self::BackendUnreachable => "BackendUnreachable",
+            Self::RoutingLoop => "RoutingLoop",
+        }
+    }
+}
+
+/// An Event to publish on a Devbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevboxEvent {
+    pub cluster: Arc<str>,
+    pub namespace: String,
+    pub devbox_name: String,
+    pub anomaly: Anomaly,
+    /// Human-readable description
+    pub note: String,
+}
+
+/// Publishes Events on Devboxes.
+#[async_trait]
+pub trait EventPublisher: Send + Sync {
+    async fn publish(&self, event: &DevboxEvent) -> Result<()>;
+}
+
+/// [`EventPublisher`] creating Warning Events through the API server of the
+/// devbox's cluster.
+pub struct ApiEventPublisher {
+    /// Recorder of each cluster, by name
+    recorders: HashMap<String, Recorder>,
+}
+
+impl ApiEventPublisher {
+    pub fn new(clients: HashMap<String, Client>) -> Self {
+        let reporter = Reporter {
+            controller: "httpgate".to_string(),
+            instance: std::env::var("HOSTNAME").ok(),
+        };
+        let recorders = clients
+            .into_iter()
+            .map(|(cluster, client)| (cluster, Recorder::new(client, reporter.clone())))
+            .collect();
+        Self { recorders }
+    }
+}
+
+#[async_trait]
+impl EventPublisher for ApiEventPublisher {
+    async fn publish(&self, event: &DevboxEvent) -> Result<()> {
+        let recorder = self
+            .recorders
+            .get(&*event.cluster)
+            .ok_or_else(|| Error::Config(format!("no client for cluster {:?}", event.cluster)))?;
+        let reference = ObjectReference {
+            api_version: Some(Devbox::api_version(&()).into_owned()),
+            kind: Some(Devbox::kind(&()).into_owned()),
+            namespace: Some(event.namespace.clone()),
+            name: Some(event.devbox_name.clone()),
+            ..Default::default()
+        };
+        recorder
+            .publish(
+                &Event {
+                    type_: EventType::Warning,
+                    reason: event.anomaly.reason().to_string(),
+                    note: Some(event.note.clone()),
+                    action: "Route".to_string(),
+                    secondary: None,
+                },
+                &reference,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// Cluster, namespace and name of a devbox, and an anomaly on it
+type OccurrenceKey = (Arc<str>, String, String, Anomaly);
+
+/// Occurrences of an anomaly on a devbox
+#[derive(Debug, Clone, Copy)]
+struct Occurrences {
+    /// Occurrences since `since`, the start of the current interval or the
+    /// last Event
+    count: u32,
+    since: Instant,
+    /// When an Event was last queued
+    published: Option<Instant>,
+}
+
+/// Turns routing anomalies into Devbox Events, so tenants can see gateway-side
+/// problems without asking an operator.
+///
+/// An anomaly is published once it occurred [`REPEAT_THRESHOLD`] times within
+/// `interval`, and at most once per `interval` for each devbox and reason.
+/// Recording never waits for the API server: Events are queued for an
+/// [`EventQueue`] to publish, and dropped while the queue is full.
+pub struct EventRecorder {
+    interval: Duration,
+    occurrences: Mutex<HashMap<OccurrenceKey, Occurrences>>,
+    queue: mpsc::Sender<DevboxEvent>,
+}
+
+impl EventRecorder {
+    /// A recorder and the queue its Events are published from.
+    pub fn new(interval: Duration) -> (Self, EventQueue) {
+        let (queue, events) = mpsc::channel(QUEUE_CAPACITY);
+        let recorder = Self {
+            interval,
+            occurrences: Mutex::new(HashMap::new()),
+            queue,
+        };
+        (recorder, EventQueue(events))
+    }
+
+    /// Record an occurrence of `anomaly` on the devbox, described by `note`.
+    ///
+    /// Returns whether an Event was queued.
+    pub fn record(&self, devbox: &DevboxInfo, anomaly: Anomaly, note: String) -> bool {
+        self.record_at(devbox, anomaly, note, Instant::now())
+    }
+
+    fn record_at(&self, devbox: &DevboxInfo, anomaly: Anomaly, note: String, now: Instant) -> bool {
+        let mut occurrences = self.occurrences.lock().unwrap();
+        if occurrences.len() >= PRUNE_THRESHOLD {
+            occurrences.retain(|_, o| now.duration_since(o.since) < self.interval);
+        }
+
+        let key = (
+            Arc::clone(&devbox.cluster),
+            devbox.namespace.clone(),
+            devbox.devbox_name.clone(),
+            anomaly,
+        );
+        let entry = occurrences.entry(key).or_insert(Occurrences {
+            count: 0,
+            since: now,
+            published: None,
+        });
+        if now.duration_since(entry.since) >= self.interval {
+            entry.count = 0;
+            entry.since = now;
+        }
+        entry.count += 1;
+        if entry.count < REPEAT_THRESHOLD
+            || entry
+                .published
+                .is_some_and(|p| now.duration_since(p) < self.interval)
+        {
+            return false;
+        }
+        entry.count = 0;
+        entry.since = now;
+        entry.published = Some(now);
+        drop(occurrences);
+
+        let event = DevboxEvent {
+            cluster: Arc::clone(&devbox.cluster),
+            namespace: devbox.namespace.clone(),
+            devbox_name: devbox.devbox_name.clone(),
+            anomaly,
+            note,
+        };
+        if let Err(e) = self.queue.try_send(event) {
+            debug!(error = %e, "Event queue full, dropping event");
+            return false;
+        }
+        true
+    }
+}
+
+/// Events queued by an [`EventRecorder`].
+pub struct EventQueue(mpsc::Receiver<DevboxEvent>);
+
+impl EventQueue {
+    /// Publish queued Events until the recorder is dropped.
+    pub async fn run(mut self, publisher: Box<dyn EventPublisher>) {
+        info!("Publishing routing anomalies as Kubernetes Events");
+        while let Some(event) = self.0.recv().await {
+            match publisher.publish(&event).await {
+                Ok(()) => debug!(
+                    cluster = %event.cluster,
+                    namespace = %event.namespace,
+                    devbox_name = %event.devbox_name,
+                    reason = event.anomaly.reason(),
+                    "Published devbox event"
+                ),
+                Err(e) => warn!(
+                    cluster = %event.cluster,
+                    namespace = %event.namespace,
+                    devbox_name = %event.devbox_name,
+                    reason = event.anomaly.reason(),
+                    error = %e,
+                    "Failed to publish devbox event"
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fake API server recording Events.
+    #[derive(Default)]
+    struct FakePublisher {
+        events: Mutex<Vec<(String, &'static str)>>,
+    }
+
+    #[async_trait]
+    impl EventPublisher for Arc<FakePublisher> {
+        async fn publish(&self, event: &DevboxEvent) -> Result<()> {
+            self.events
+                .lock()
+                .unwrap()
+                .push((event.devbox_name.clone(), event.anomaly.reason()));
+            Ok(())
+        }
+    }
+
+    fn devbox(name: &str) -> DevboxInfo {
+        DevboxInfo::new("ns".to_string(), name.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_events_deduplicated_per_devbox_and_reason() {
+        let interval = Duration::from_secs(300);
+        let (recorder, queue) = EventRecorder::new(interval);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let record = |name: &str, anomaly, secs| {
+            recorder.record_at(&devbox(name), anomaly, "note".to_string(), at(secs))
+        };
+
+        // Published on the third occurrence, then not again for an interval
+        assert!(!record("devbox-a", Anomaly::PodNotReady, 0));
+        assert!(!record("devbox-a", Anomaly::PodNotReady, 1));
+        assert!(record("devbox-a", Anomaly::PodNotReady, 2));
+        for secs in 3..100 {
+            assert!(!record("devbox-a", Anomaly::PodNotReady, secs));
+        }
+        // Other reasons and devboxes are limited separately
+        for secs in 0..3 {
+            record("devbox-a", Anomaly::BackendUnreachable, secs);
+            record("devbox-b", Anomaly::PodNotReady, secs);
+        }
+        // Once the interval is over, it takes repeats again
+        assert!(!record("devbox-a", Anomaly::PodNotReady, 302));
+        assert!(!record("devbox-a", Anomaly::PodNotReady, 303));
+        assert!(record("devbox-a", Anomaly::PodNotReady, 304));
+
+        // Occurrences spread over more than an interval are no repeats
+        assert!(!record("devbox-c", Anomaly::RoutingLoop, 0));
+        assert!(!record("devbox-c", Anomaly::RoutingLoop, 200));
+        assert!(!record("devbox-c", Anomaly::RoutingLoop, 400));
+        assert!(!record("devbox-c", Anomaly::RoutingLoop, 500));
+        assert!(record("devbox-c", Anomaly::RoutingLoop, 600));
+
+        let publisher = Arc::new(FakePublisher::default());
+        drop(recorder);
+        queue.run(Box::new(Arc::clone(&publisher))).await;
+        assert_eq!(
+            *publisher.events.lock().unwrap(),
+            vec![
+                ("devbox-a".to_string(), "PodNotReady"),
+                ("devbox-a".to_string(), "BackendUnreachable"),
+                ("devbox-b".to_string(), "PodNotReady"),
+                ("devbox-a".to_string(), "PodNotReady"),
+                ("devbox-c".to_string(), "RoutingLoop"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use http::header::{CONTENT_LENGTH, EXPECT};
+use pingora_http::RequestHeader;
+
+use crate::config::ExpectContinueMode;
+
+/// What to do with a request before its body is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectAction {
+    /// Forward the request as-is (the backend sees any `Expect` header)
+    Proceed,
+    /// Answer `100 Continue` at the gateway and strip `Expect` upstream
+    SendContinue,
+    /// Reject the request with the given status before reading the body
+    Reject(u16),
+}
+
+/// Decide how to handle `Expect` and the declared body size of a request.
+///
+/// - A declared `Content-Length` above `max_body_bytes` is rejected with 413
+///   before any `100 Continue` is sent, so the client never uploads the body.
+/// - Expectations other than `100-continue` are rejected with 417.
+/// - `100-continue` is answered by the gateway or relayed to the backend
+///   depending on `mode`.
+pub fn evaluate(
+    req: &RequestHeader,
+    mode: ExpectContinueMode,
+    max_body_bytes: Option<u64>,
+) -> ExpectAction {
+    let content_length = req
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+
+    if let (Some(length), Some(max)) = (content_length, max_body_bytes) {
+        if length > max {
+            return ExpectAction::Reject(413);
+        }
+    }
+
+    let Some(expect) = req.headers.get(EXPECT) else {
+        return ExpectAction::Proceed;
+    };
+
+    let is_continue = expect
+        .to_str()
+        .is_ok_and(|v| v.trim().eq_ignore_ascii_case("100-continue"));
+    if !is_continue {
+        return ExpectAction::Reject(417);
+    }
+
+    match mode {
+        ExpectContinueMode::Gateway => ExpectAction::SendContinue,
+        ExpectContinueMode::Relay => ExpectAction::Proceed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&'static str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build("POST", b"/upload", None).unwrap();
+        for (name, value) in headers {
+            req.insert_header(*name, *value).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn test_no_expect() {
+        let req = request(&[("content-length", "10")]);
+        assert_eq!(
+            evaluate(&req, ExpectContinueMode::Gateway, None),
+            ExpectAction::Proceed
+        );
+    }
+
+    #[test]
+    fn test_expect_continue_modes() {
+        let req = request(&[("expect", "100-continue"), ("content-length", "10")]);
+        assert_eq!(
+            evaluate(&req, ExpectContinueMode::Gateway, Some(100)),
+            ExpectAction::SendContinue
+        );
+        assert_eq!(
+            evaluate(&req, ExpectContinueMode::Relay, Some(100)),
+            ExpectAction::Proceed
+        );
+
+        // Header value is case-insensitive
+        let req = request(&[("expect", "100-Continue")]);
+        assert_eq!(
+            evaluate(&req, ExpectContinueMode::Gateway, None),
+            ExpectAction::SendContinue
+        );
+    }
+
+    #[test]
+    fn test_expect_oversized_body_rejected_before_continue() {
+        let req = request(&[("expect", "100-continue"), ("content-length", "101")]);
+        assert_eq!(
+            evaluate(&req, ExpectContinueMode::Gateway, Some(100)),
+            ExpectAction::Reject(413)
+        );
+        assert_eq!(
+            evaluate(&req, ExpectContinueMode::Relay, Some(100)),
+            ExpectAction::Reject(413)
+        );
+    }
+
+    #[test]
+    fn test_oversized_body_without_expect() {
+        let req = request(&[("content-length", "101")]);
+        assert_eq!(
+            evaluate(&req, ExpectContinueMode::Relay, Some(100)),
+            ExpectAction::Reject(413)
+        );
+    }
+
+    #[test]
+    fn test_unsupported_expectation() {
+        let req = request(&[("expect", "something-else")]);
+        assert_eq!(
+            evaluate(&req, ExpectContinueMode::Gateway, None),
+            ExpectAction::Reject(417)
+        );
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::Pod;
+use kube::{Api, Client};
+use tracing::{debug, info, warn};
+
+use crate::{
+    error::{Error, Result},
+    metrics,
+    registry::{DevboxRegistry, PodIpRecord, PodRef},
+};
+
+/// Upper bound on API server lookups per sweep, so a sweep takes bounded time
+/// even when many entries are due for verification.
+const MAX_VERIFICATIONS_PER_SWEEP: usize = 100;
+
+/// Checks whether a Pod still exists.
+#[async_trait]
+pub trait PodLiveness: Send + Sync {
+    /// Whether the Pod `pod.name` exists in `namespace` of `cluster` with
+    /// UID `pod.uid`.
+    async fn is_live(&self, cluster: &str, namespace: &str, pod: &PodRef) -> Result<bool>;
+}
+
+/// [`PodLiveness`] backed by the Kubernetes API servers of the clusters.
+pub struct ApiPodLiveness {
+    /// Client of each cluster, by name
+    clients: HashMap<String, Client>,
+}
+
+impl ApiPodLiveness {
+    pub const fn new(clients: HashMap<String, Client>) -> Self {
+        Self { clients }
+    }
+}
+
+#[async_trait]
+impl PodLiveness for ApiPodLiveness {
+    async fn is_live(&self, cluster: &str, namespace: &str, pod: &PodRef) -> Result<bool> {
+        let client = self
+            .clients
+            .get(cluster)
+            .ok_or_else(|| Error::Config(format!("no client for cluster {cluster:?}")))?;
+        let pods: Api<Pod> = Api::namespaced(client.clone(), namespace);
+        let current = pods.get_opt(&pod.name).await?;
+        Ok(current.is_some_and(|p| p.metadata.uid.as_deref() == Some(pod.uid.as_str())))
+    }
+}
+
+/// Entries removed by one sweep.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SweepReport {
+    /// Entries whose devbox is no longer registered
+    pub orphaned: usize,
+    /// Entries whose Pod no longer exists on the API server
+    pub stale: usize,
+}
+
+/// Periodic consistency sweep of the Pod index.
+///
+/// If Pod delete events are missed, entries for devboxes that are long gone
+/// stay in the registry and could route traffic to an IP that has since been
+/// recycled for an unrelated Pod. Each sweep:
+///
+/// - removes entries with no registered devbox. An entry must be seen orphaned
+///   with the same endpoint generation on two consecutive sweeps, so a devbox
+///   relist in progress (or a registration racing the sweep) is not mistaken
+///   for an orphan;
+/// - re-verifies entries with a known Pod that are older than `verify_ttl`
+///   against the API server and removes those whose Pod is gone.
+pub struct PodIpSweeper {
+    registry: Arc<DevboxRegistry>,
+    liveness: Option<Box<dyn PodLiveness>>,
+    verify_ttl: Option<Duration>,
+    /// Orphans seen on the previous sweep: `cluster/namespace/devbox_name` -> generation
+    suspects: HashMap<String, u64>,
+}
+
+impl PodIpSweeper {
+    pub fn new(registry: Arc<DevboxRegistry>) -> Self {
+        Self {
+            registry,
+            liveness: None,
+            verify_ttl: None,
+            suspects: HashMap::new(),
+        }
+    }
+
+    /// Re-verify entries older than `ttl` with `liveness`.
+    #[must_use]
+    pub fn with_verification(mut self, liveness: Box<dyn PodLiveness>, ttl: Duration) -> Self {
+        self.liveness = Some(liveness);
+        self.verify_ttl = Some(ttl);
+        self
+    }
+
+    /// Run sweeps every `interval` forever.
+    pub async fn run(mut self, interval: Duration) {
+        info!(interval = ?interval, "Starting pod IP consistency sweep");
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            self.sweep().await;
+        }
+    }
+
+    /// Run one sweep over a snapshot of the Pod index.
+    pub async fn sweep(&mut self) -> SweepReport {
+        let report = SweepReport {
+            orphaned: self.sweep_orphans(),
+            stale: self.sweep_stale().await,
+        };
+
+        if report.orphaned > 0 || report.stale > 0 {
+            info!(
+                orphaned = report.orphaned,
+                stale = report.stale,
+                "Removed inconsistent pod IP entries"
+            );
+        }
+        report
+    }
+
+    fn sweep_orphans(&mut self) -> usize {
+        let orphans = self.registry.orphaned_pod_ips();
+        let mut suspects = HashMap::with_capacity(orphans.len());
+        let mut removed = 0;
+
+        for record in orphans {
+            let key = format!(
+                "{}/{}/{}",
+                record.cluster, record.namespace, record.devbox_name
+            );
+            if self.suspects.get(&key) == Some(&record.generation) {
+                if self.remove(&record, "orphaned") {
+                    removed += 1;
+                }
+            } else {
+                suspects.insert(key, record.generation);
+            }
+        }
+
+        self.suspects = suspects;
+        removed
+    }
+
+    async fn sweep_stale(&self) -> usize {
+        let (Some(liveness), Some(ttl)) = (self.liveness.as_ref(), self.verify_ttl) else {
+            return 0;
+        };
+
+        let mut removed = 0;
+        let records = self.registry.pod_ips_unverified_for(ttl);
+        for record in records.into_iter().take(MAX_VERIFICATIONS_PER_SWEEP) {
+            let Some(pod) = record.pod.as_ref() else {
+                continue;
+            };
+
+            match liveness
+                .is_live(&record.cluster, &record.namespace, pod)
+                .await
+            {
+                Ok(true) => {
+                    self.registry.mark_pod_ip_verified(
+                        &record.cluster,
+                        &record.namespace,
+                        &record.devbox_name,
+                        record.generation,
+                    );
+                }
+                Ok(false) => {
+                    if self.remove(&record, "stale") {
+                        removed += 1;
+                    }
+                }
+                Err(e) => {
+                    // Keep the entry; it is retried on the next sweep
+                    warn!(
+                        cluster = %record.cluster,
+                        namespace = %record.namespace,
+                        pod_name = %pod.name,
+                        error = %e,
+                        "Failed to verify pod liveness"
+                    );
+                }
+            }
+        }
+        removed
+    }
+
+    fn remove(&self, record: &PodIpRecord, reason: &str) -> bool {
+        let removed = self.registry.remove_pod_ip_if_generation(
+            &record.cluster,
+            &record.namespace,
+            &record.devbox_name,
+            record.generation,
+        );
+
+        if removed {
+            warn!(
+                cluster = %record.cluster,
+                namespace = %record.namespace,
+                devbox_name = %record.devbox_name,
+                reason = reason,
+                "Removed pod IP entry missed by the Pod watcher"
+            );
+            metrics::POD_IPS_SWEPT_TOTAL
+                .with_label_values(&[record.cluster.as_str(), reason])
+                .inc();
+        } else {
+            debug!(
+                cluster = %record.cluster,
+                namespace = %record.namespace,
+                devbox_name = %record.devbox_name,
+                "Pod IP entry changed during sweep, keeping it"
+            );
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use crate::registry::DEFAULT_CLUSTER;
+
+    /// Fake API server: Pods are live unless listed as deleted.
+    #[derive(Default)]
+    struct FakeLiveness {
+        deleted: Mutex<Vec<String>>,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl PodLiveness for Arc<FakeLiveness> {
+        async fn is_live(&self, _cluster: &str, _namespace: &str, pod: &PodRef) -> Result<bool> {
+            if self.fail {
+                return Err(Error::Config("api server unavailable".to_string()));
+            }
+            Ok(!self.deleted.lock().unwrap().contains(&pod.uid))
+        }
+    }
+
+    fn pod(uid: &str) -> Option<PodRef> {
+        Some(PodRef {
+            name: format!("pod-{uid}"),
+            uid: uid.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_missed_devbox_delete_removed_after_two_sweeps() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "id-1".to_string(),
+            "ns-1".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        // Devbox deleted, but the Pod delete event was missed
+        registry
+            .update_pod_ip("ns-2", "gone", "10.0.0.2".to_string())
+            .unwrap();
+
+        let mut sweeper = PodIpSweeper::new(Arc::clone(&registry));
+
+        // First sighting only marks the entry as suspect
+        assert_eq!(sweeper.sweep().await, SweepReport::default());
+        assert!(registry.get_pod_ip("ns-2", "gone").is_some());
+
+        let report = sweeper.sweep().await;
+        assert_eq!(report.orphaned, 1);
+        assert!(registry.get_pod_ip("ns-2", "gone").is_none());
+        assert!(registry.get_pod_ip("ns-1", "devbox1").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_orphan_in_other_cluster() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "id-1".to_string(),
+            "ns-1".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        // Same namespace and name, but no such devbox in the other cluster
+        registry
+            .update_pod_endpoint("west", "ns-1", "devbox1", "10.1.0.1".to_string(), None)
+            .unwrap();
+
+        let mut sweeper = PodIpSweeper::new(Arc::clone(&registry));
+        sweeper.sweep().await;
+        assert_eq!(sweeper.sweep().await.orphaned, 1);
+        assert!(registry
+            .get_pod_endpoint("west", "ns-1", "devbox1")
+            .is_none());
+        assert!(registry.get_pod_ip("ns-1", "devbox1").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_orphan_registered_between_sweeps_is_kept() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+
+        let mut sweeper = PodIpSweeper::new(Arc::clone(&registry));
+        sweeper.sweep().await;
+
+        // Devbox watcher catches up (e.g., after a relist)
+        registry.register_devbox(
+            "id-1".to_string(),
+            "ns-1".to_string(),
+            "devbox1".to_string(),
+        );
+        assert_eq!(sweeper.sweep().await.orphaned, 0);
+        assert!(registry.get_pod_ip("ns-1", "devbox1").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_orphan_with_new_endpoint_is_kept() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+
+        let mut sweeper = PodIpSweeper::new(Arc::clone(&registry));
+        sweeper.sweep().await;
+
+        // The entry changed since it was first suspected: start over
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.2".to_string())
+            .unwrap();
+        assert_eq!(sweeper.sweep().await.orphaned, 0);
+        assert_eq!(sweeper.sweep().await.orphaned, 1);
+    }
+
+    #[tokio::test]
+    async fn test_missed_pod_delete_removed_by_verification() {
+        let registry = Arc::new(DevboxRegistry::new());
+        for (id, name, uid) in [("id-1", "devbox1", "uid-1"), ("id-2", "devbox2", "uid-2")] {
+            registry.register_devbox(id.to_string(), "ns".to_string(), name.to_string());
+            registry
+                .update_pod_endpoint(
+                    DEFAULT_CLUSTER,
+                    "ns",
+                    name,
+                    format!("10.0.0.{}", &uid[4..]),
+                    pod(uid),
+                )
+                .unwrap();
+        }
+
+        let liveness = Arc::new(FakeLiveness::default());
+        liveness.deleted.lock().unwrap().push("uid-2".to_string());
+
+        let mut sweeper = PodIpSweeper::new(Arc::clone(&registry))
+            .with_verification(Box::new(Arc::clone(&liveness)), Duration::ZERO);
+
+        let report = sweeper.sweep().await;
+        assert_eq!(report.stale, 1);
+        assert!(registry.get_pod_ip("ns", "devbox1").is_some());
+        assert!(registry.get_pod_ip("ns", "devbox2").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_recent_entries_not_verified() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox("id-1".to_string(), "ns".to_string(), "devbox1".to_string());
+        registry
+            .update_pod_endpoint(
+                DEFAULT_CLUSTER,
+                "ns",
+                "devbox1",
+                "10.0.0.1".to_string(),
+                pod("uid-1"),
+            )
+            .unwrap();
+
+        let liveness = Arc::new(FakeLiveness::default());
+        liveness.deleted.lock().unwrap().push("uid-1".to_string());
+
+        let mut sweeper = PodIpSweeper::new(Arc::clone(&registry))
+            .with_verification(Box::new(liveness), Duration::from_secs(600));
+
+        assert_eq!(sweeper.sweep().await.stale, 0);
+        assert!(registry.get_pod_ip("ns", "devbox1").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_verification_errors_keep_entries() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox("id-1".to_string(), "ns".to_string(), "devbox1".to_string());
+        registry
+            .update_pod_endpoint(
+                DEFAULT_CLUSTER,
+                "ns",
+                "devbox1",
+                "10.0.0.1".to_string(),
+                pod("uid-1"),
+            )
+            .unwrap();
+
+        let liveness = Arc::new(FakeLiveness {
+            fail: true,
+            ..Default::default()
+        });
+        let mut sweeper = PodIpSweeper::new(Arc::clone(&registry))
+            .with_verification(Box::new(liveness), Duration::ZERO);
+
+        assert_eq!(sweeper.sweep().await.stale, 0);
+        assert!(registry.get_pod_ip("ns", "devbox1").is_some());
+    }
+}
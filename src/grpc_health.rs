@@ -0,0 +1,328 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use h2::server::SendResponse;
+use h2::RecvStream;
+use http::{HeaderMap, HeaderValue, Request, Response, StatusCode};
+use pingora_core::apps::ServerApp;
+use pingora_core::protocols::Stream;
+use pingora_core::server::ShutdownWatch;
+use pingora_core::services::listening::Service;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::debug;
+
+use crate::registry::DevboxRegistry;
+
+/// Path of the unary health check
+const CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+/// Services the health check answers for: the whole server, and the gateway
+const SERVICES: [&str; 2] = ["", "httpgate"];
+
+/// Largest request message read
+const MAX_MESSAGE_BYTES: usize = 4096;
+
+/// `ServingStatus` of a `HealthCheckResponse`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServingStatus {
+    Serving = 1,
+    NotServing = 2,
+}
+
+/// gRPC status codes of failed checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GrpcError {
+    InvalidArgument = 3,
+    NotFound = 5,
+    Unimplemented = 12,
+}
+
+impl GrpcError {
+    const fn message(self) -> &'static str {
+        match self {
+            Self::InvalidArgument => "malformed HealthCheckRequest",
+            Self::NotFound => "unknown service",
+            Self::Unimplemented => "only grpc.health.v1.Health/Check is implemented",
+        }
+    }
+}
+
+/// The `grpc.health.v1.Health` service over h2c, served on `GRPC_HEALTH_ADDR`
+/// for service meshes that probe gRPC rather than HTTP.
+///
+/// The gateway is `SERVING` once the watchers of every cluster have
+/// completed their initial list, like the admin API's `/healthz`.
+/// Only the unary `Check` method is implemented.
+pub struct GrpcHealthApp {
+    registry: Arc<DevboxRegistry>,
+}
+
+impl GrpcHealthApp {
+    pub const fn new(registry: Arc<DevboxRegistry>) -> Self {
+        Self { registry }
+    }
+
+    pub fn into_service(self) -> Service<Self> {
+        Service::new("httpgate-grpc-health".to_string(), self)
+    }
+
+    /// Status of `service`
+    fn check(&self, service: &str) -> Result<ServingStatus, GrpcError> {
+        if !SERVICES.contains(&service) {
+            return Err(GrpcError::NotFound);
+        }
+        Ok(if self.registry.is_synced() {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        })
+    }
+
+    /// Serve the health checks of one HTTP/2 connection.
+    pub async fn serve<S>(self: &Arc<Self>, io: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut connection = match h2::server::handshake(io).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                debug!(error = %e, "gRPC health handshake failed");
+                return;
+            }
+        };
+        while let Some(request) = connection.accept().await {
+            let (request, respond) = match request {
+                Ok(request) => request,
+                Err(e) => {
+                    debug!(error = %e, "gRPC health connection failed");
+                    return;
+                }
+            };
+            // The connection must keep being polled while the request body
+            // is read, so requests are answered concurrently
+            let app = Arc::clone(self);
+            tokio::spawn(async move {
+                let result = if request.uri().path() == CHECK_PATH {
+                    read_message(request)
+                        .await
+                        .and_then(|message| decode_service(&message))
+                        .ok_or(GrpcError::InvalidArgument)
+                        .and_then(|service| app.check(&service))
+                } else {
+                    Err(GrpcError::Unimplemented)
+                };
+                if let Err(e) = respond_to(respond, result) {
+                    debug!(error = %e, "Failed to answer gRPC health check");
+                }
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl ServerApp for GrpcHealthApp {
+    async fn process_new(
+        self: &Arc<Self>,
+        stream: Stream,
+        _shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        self.serve(stream).await;
+        None
+    }
+}
+
+/// The request message, without its gRPC frame header, if it is a single
+/// uncompressed message of a reasonable size.
+async fn read_message(request: Request<RecvStream>) -> Option<Bytes> {
+    let mut body = request.into_body();
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.ok()?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        buf.extend_from_slice(&chunk);
+        if buf.len() > MAX_MESSAGE_BYTES + 5 {
+            return None;
+        }
+    }
+    let (&[compressed, ref len @ ..], message) = buf.split_first_chunk::<5>()?;
+    let len = u32::from_be_bytes(*len) as usize;
+    (compressed == 0 && message.len() == len).then(|| Bytes::copy_from_slice(message))
+}
+
+/// The `service` field of an encoded `HealthCheckRequest`.
+fn decode_service(mut message: &[u8]) -> Option<String> {
+    let mut service = String::new();
+    while !message.is_empty() {
+        let key = decode_varint(&mut message)?;
+        match key & 0x7 {
+            // varint
+            0 => {
+                decode_varint(&mut message)?;
+            }
+            // 64-bit
+            1 => message = message.get(8..)?,
+            // length-delimited
+            2 => {
+                let len = usize::try_from(decode_varint(&mut message)?).ok()?;
+                let value = message.get(..len)?;
+                message = &message[len..];
+                if key >> 3 == 1 {
+                    service = String::from_utf8(value.to_vec()).ok()?;
+                }
+            }
+            // 32-bit
+            5 => message = message.get(4..)?,
+            _ => return None,
+        }
+    }
+    Some(service)
+}
+
+fn decode_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// A framed `HealthCheckResponse` carrying `status`.
+fn encode_response(status: ServingStatus) -> Bytes {
+    let mut buf = BytesMut::with_capacity(7);
+    buf.put_u8(0);
+    buf.put_u32(2);
+    // Field 1, varint
+    buf.put_u8(0x08);
+    buf.put_u8(status as u8);
+    buf.freeze()
+}
+
+/// Send the check's response, or a trailers-only error response.
+fn respond_to(
+    mut respond: SendResponse<Bytes>,
+    result: Result<ServingStatus, GrpcError>,
+) -> Result<(), h2::Error> {
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, "application/grpc")
+        .body(())
+        .unwrap();
+    match result {
+        Ok(status) => {
+            let mut stream = respond.send_response(response, false)?;
+            stream.send_data(encode_response(status), false)?;
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", HeaderValue::from_static("0"));
+            stream.send_trailers(trailers)
+        }
+        Err(error) => {
+            let headers = response.headers_mut();
+            headers.insert("grpc-status", HeaderValue::from(error as u16));
+            headers.insert("grpc-message", HeaderValue::from_static(error.message()));
+            respond.send_response(response, true).map(drop)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::{WatchKind, DEFAULT_CLUSTER};
+
+    /// Framed `HealthCheckRequest` for `service`
+    fn request(service: &str) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_u8(0);
+        buf.put_u32(u32::try_from(service.len() + 2).unwrap());
+        buf.put_u8(0x0a);
+        buf.put_u8(u8::try_from(service.len()).unwrap());
+        buf.put_slice(service.as_bytes());
+        buf.freeze()
+    }
+
+    /// Call `path` on `app` over an in-memory HTTP/2 connection, returning
+    /// the `grpc-status` and the response message.
+    async fn call(app: Arc<GrpcHealthApp>, path: &str, body: Bytes) -> (String, Bytes) {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move { app.serve(server_io).await });
+        let (mut client, connection) = h2::client::handshake(client_io).await.unwrap();
+        tokio::spawn(connection);
+
+        let request = Request::post(format!("http://localhost{path}"))
+            .header(http::header::CONTENT_TYPE, "application/grpc")
+            .header("te", "trailers")
+            .body(())
+            .unwrap();
+        let (response, mut send) = client.send_request(request, false).unwrap();
+        send.send_data(body, true).unwrap();
+        let response = response.await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        if let Some(status) = response.headers().get("grpc-status") {
+            return (status.to_str().unwrap().to_string(), Bytes::new());
+        }
+
+        let mut body = response.into_body();
+        let mut message = BytesMut::new();
+        while let Some(chunk) = body.data().await {
+            message.extend_from_slice(&chunk.unwrap());
+        }
+        let trailers = body.trailers().await.unwrap().unwrap();
+        let status = trailers["grpc-status"].to_str().unwrap().to_string();
+        (status, message.freeze())
+    }
+
+    #[tokio::test]
+    async fn test_health_follows_readiness() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.add_cluster(DEFAULT_CLUSTER);
+        let app = || Arc::new(GrpcHealthApp::new(Arc::clone(&registry)));
+
+        let (status, message) = call(app(), CHECK_PATH, request("")).await;
+        assert_eq!(status, "0");
+        assert_eq!(message, encode_response(ServingStatus::NotServing));
+
+        registry.record_watch_synced(DEFAULT_CLUSTER, WatchKind::Devboxes);
+        registry.record_watch_synced(DEFAULT_CLUSTER, WatchKind::Pods);
+        assert!(registry.is_synced());
+        for service in SERVICES {
+            let (status, message) = call(app(), CHECK_PATH, request(service)).await;
+            assert_eq!(status, "0");
+            assert_eq!(message, encode_response(ServingStatus::Serving));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_errors() {
+        let registry = Arc::new(DevboxRegistry::new());
+        let app = || Arc::new(GrpcHealthApp::new(Arc::clone(&registry)));
+
+        let (status, _) = call(app(), CHECK_PATH, request("other")).await;
+        assert_eq!(status, "5");
+        let (status, _) = call(app(), CHECK_PATH, Bytes::from_static(b"\0\0\0")).await;
+        assert_eq!(status, "3");
+        let (status, _) = call(app(), "/grpc.health.v1.Health/Watch", request("")).await;
+        assert_eq!(status, "12");
+    }
+
+    #[test]
+    fn test_decode_service() {
+        assert_eq!(
+            decode_service(&request("httpgate")[5..]).unwrap(),
+            "httpgate"
+        );
+        assert_eq!(decode_service(&[]).unwrap(), "");
+        // Unknown fields are skipped
+        assert_eq!(
+            decode_service(&[0x10, 0x96, 0x01, 0x0a, 0x01, b'x']).unwrap(),
+            "x"
+        );
+        assert_eq!(decode_service(&[0x0a, 0x05, b'x']), None);
+        assert_eq!(decode_service(&[0x0a, 0x01, 0xff]), None);
+    }
+}
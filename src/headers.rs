@@ -0,0 +1,212 @@
+use std::fmt;
+
+use http::header::{CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING};
+use http::HeaderMap;
+
+/// Message framing that cannot be forwarded safely.
+///
+/// Each of these lets the gateway and the other hop disagree on where a
+/// message ends, which is the basis of request smuggling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramingError {
+    /// A `Content-Length` value is not a decimal number
+    InvalidContentLength,
+    /// Several `Content-Length` values that differ
+    ConflictingContentLength,
+    /// Both `Content-Length` and `Transfer-Encoding`
+    ContentLengthWithTransferEncoding,
+}
+
+impl fmt::Display for FramingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::InvalidContentLength => "invalid Content-Length",
+            Self::ConflictingContentLength => "conflicting Content-Length values",
+            Self::ContentLengthWithTransferEncoding => {
+                "both Content-Length and Transfer-Encoding present"
+            }
+        })
+    }
+}
+
+/// Check the framing headers of a request or response.
+///
+/// Returns the length to keep when `Content-Length` was repeated (as
+/// separate headers or a comma-separated list) with identical values; the
+/// caller should replace the duplicates with a single header.
+pub fn check_framing(headers: &HeaderMap) -> Result<Option<u64>, FramingError> {
+    let mut lengths = Vec::new();
+    for value in headers.get_all(CONTENT_LENGTH) {
+        let value = value
+            .to_str()
+            .map_err(|_| FramingError::InvalidContentLength)?;
+        for part in value.split(',').map(str::trim) {
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(FramingError::InvalidContentLength);
+            }
+            let length = part
+                .parse::<u64>()
+                .map_err(|_| FramingError::InvalidContentLength)?;
+            lengths.push(length);
+        }
+    }
+
+    let Some(&first) = lengths.first() else {
+        return Ok(None);
+    };
+    if headers.contains_key(TRANSFER_ENCODING) {
+        return Err(FramingError::ContentLengthWithTransferEncoding);
+    }
+    if lengths.iter().any(|&l| l != first) {
+        return Err(FramingError::ConflictingContentLength);
+    }
+    Ok((lengths.len() > 1).then_some(first))
+}
+
+/// Whether the `Connection` headers list `token` (case-insensitively).
+pub fn has_connection_token(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+/// Canonical casing of a header name: each dash-separated word capitalized,
+/// e.g. `x-forwarded-for` -> `X-Forwarded-For`.
+pub fn canonical_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut word_start = true;
+    for c in name.chars() {
+        out.push(if word_start {
+            c.to_ascii_uppercase()
+        } else {
+            c.to_ascii_lowercase()
+        });
+        word_start = c == '-';
+    }
+    out
+}
+
+/// Size of a header block as the sum of name and value lengths.
+///
+/// Leaves out the `: ` and CRLF framing each header has on the wire, and
+/// the start line, so it is cheap to compute for every message.
+pub fn header_bytes(headers: &HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    type Case = (
+        &'static [(&'static str, &'static str)],
+        Result<Option<u64>, FramingError>,
+    );
+
+    #[test]
+    fn test_check_framing() {
+        use FramingError::*;
+
+        let cases: &[Case] = &[
+            (&[], Ok(None)),
+            (&[("content-length", "5")], Ok(None)),
+            (&[("transfer-encoding", "chunked")], Ok(None)),
+            // Benign duplicates are collapsed
+            (
+                &[("content-length", "5"), ("content-length", "5")],
+                Ok(Some(5)),
+            ),
+            (&[("content-length", "5, 5")], Ok(Some(5))),
+            (
+                &[("content-length", "0"), ("content-length", "00")],
+                Ok(Some(0)),
+            ),
+            // Smuggling vectors are rejected
+            (
+                &[("content-length", "5"), ("content-length", "6")],
+                Err(ConflictingContentLength),
+            ),
+            (&[("content-length", "5, 6")], Err(ConflictingContentLength)),
+            (
+                &[("content-length", "5"), ("transfer-encoding", "chunked")],
+                Err(ContentLengthWithTransferEncoding),
+            ),
+            (
+                &[
+                    ("transfer-encoding", "chunked"),
+                    ("content-length", "5"),
+                    ("content-length", "5"),
+                ],
+                Err(ContentLengthWithTransferEncoding),
+            ),
+            (&[("content-length", "")], Err(InvalidContentLength)),
+            (&[("content-length", "+5")], Err(InvalidContentLength)),
+            (&[("content-length", "-1")], Err(InvalidContentLength)),
+            (&[("content-length", "0x10")], Err(InvalidContentLength)),
+            (&[("content-length", "5,")], Err(InvalidContentLength)),
+            (
+                &[("content-length", "99999999999999999999999")],
+                Err(InvalidContentLength),
+            ),
+        ];
+
+        for (pairs, expected) in cases {
+            assert_eq!(check_framing(&headers(pairs)), *expected, "{pairs:?}");
+        }
+    }
+
+    #[test]
+    fn test_has_connection_token() {
+        let map = headers(&[("connection", "Upgrade, Keep-Alive"), ("connection", "te")]);
+        assert!(has_connection_token(&map, "keep-alive"));
+        assert!(has_connection_token(&map, "upgrade"));
+        assert!(has_connection_token(&map, "TE"));
+        assert!(!has_connection_token(&map, "close"));
+        assert!(!has_connection_token(&headers(&[]), "close"));
+        assert!(!has_connection_token(
+            &headers(&[("connection", "closed")]),
+            "close"
+        ));
+    }
+
+    #[test]
+    fn test_canonical_case() {
+        for (name, expected) in [
+            ("content-type", "Content-Type"),
+            ("x-forwarded-for", "X-Forwarded-For"),
+            ("X-REQUEST-ID", "X-Request-Id"),
+            ("te", "Te"),
+            ("x--double", "X--Double"),
+        ] {
+            assert_eq!(canonical_case(name), expected);
+        }
+    }
+
+    #[test]
+    fn test_header_bytes() {
+        assert_eq!(header_bytes(&headers(&[])), 0);
+        assert_eq!(header_bytes(&headers(&[("host", "example.com")])), 15);
+        // Repeated headers count once per value
+        let map = headers(&[
+            ("host", "example.com"),
+            ("cookie", "a=1"),
+            ("cookie", "b=22"),
+            ("content-length", "0"),
+        ]);
+        assert_eq!(header_bytes(&map), 15 + 9 + 10 + 15);
+    }
+}
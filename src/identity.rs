@@ -0,0 +1,126 @@
+use http::HeaderName;
+use pingora_core::Result;
+use pingora_http::RequestHeader;
+use serde::{Serialize, Serializer};
+
+/// Default header carrying the devbox's uniqueID
+pub const DEFAULT_ID_HEADER: HeaderName = HeaderName::from_static("x-devbox-id");
+
+/// Default header carrying the devbox's namespace
+pub const DEFAULT_NAMESPACE_HEADER: HeaderName = HeaderName::from_static("x-devbox-namespace");
+
+/// Default header carrying the host the client requested
+pub const DEFAULT_ORIGINAL_HOST_HEADER: HeaderName = HeaderName::from_static("x-original-host");
+
+/// Names of the headers telling a devbox which public hostname and uniqueID
+/// a request arrived through, e.g. for apps serving several ports that build
+/// absolute URLs.
+///
+/// On listeners with `identity_headers` enabled, the gateway replaces any
+/// such headers the client sent, so backends can trust them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IdentityHeaders {
+    #[serde(serialize_with = "serialize_name")]
+    pub id: HeaderName,
+    #[serde(serialize_with = "serialize_name")]
+    pub namespace: HeaderName,
+    #[serde(serialize_with = "serialize_name")]
+    pub original_host: HeaderName,
+}
+
+impl Default for IdentityHeaders {
+    fn default() -> Self {
+        Self {
+            id: DEFAULT_ID_HEADER,
+            namespace: DEFAULT_NAMESPACE_HEADER,
+            original_host: DEFAULT_ORIGINAL_HOST_HEADER,
+        }
+    }
+}
+
+impl IdentityHeaders {
+    /// Remove the identity headers from `req`, whoever set them.
+    pub fn strip(&self, req: &mut RequestHeader) {
+        req.remove_header(&self.id);
+        req.remove_header(&self.namespace);
+        req.remove_header(&self.original_host);
+    }
+
+    /// Replace the identity headers of `req` with those of a request for
+    /// `host` routed to devbox `unique_id` of `namespace`.
+    pub fn apply(
+        &self,
+        req: &mut RequestHeader,
+        unique_id: &str,
+        namespace: &str,
+        host: &str,
+    ) -> Result<()> {
+        self.strip(req);
+        req.insert_header(self.id.clone(), unique_id)?;
+        req.insert_header(self.namespace.clone(), namespace)?;
+        if !host.is_empty() {
+            req.insert_header(self.original_host.clone(), host)?;
+        }
+        Ok(())
+    }
+}
+
+fn serialize_name<S: Serializer>(
+    name: &HeaderName,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_replaces_client_headers() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("X-Devbox-Id", "someone-else").unwrap();
+        req.append_header("x-devbox-namespace", "ns-a").unwrap();
+        req.append_header("x-devbox-namespace", "ns-b").unwrap();
+        req.insert_header("X-Original-Host", "evil.example.com")
+            .unwrap();
+
+        let headers = IdentityHeaders::default();
+        headers
+            .apply(
+                &mut req,
+                "my-app",
+                "ns-test",
+                "devbox-my-app-8080.example.com",
+            )
+            .unwrap();
+        assert_eq!(req.headers.get("x-devbox-id").unwrap(), "my-app");
+        let namespaces: Vec<_> = req.headers.get_all("x-devbox-namespace").iter().collect();
+        assert_eq!(namespaces, ["ns-test"]);
+        assert_eq!(
+            req.headers.get("x-original-host").unwrap(),
+            "devbox-my-app-8080.example.com"
+        );
+    }
+
+    #[test]
+    fn test_custom_names() {
+        let headers = IdentityHeaders {
+            id: HeaderName::from_static("x-app"),
+            ..Default::default()
+        };
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("X-App", "spoofed").unwrap();
+        req.insert_header("X-Devbox-Id", "untouched").unwrap();
+
+        headers.apply(&mut req, "my-app", "ns-test", "").unwrap();
+        assert_eq!(req.headers.get("x-app").unwrap(), "my-app");
+        // Only the configured names are the gateway's
+        assert_eq!(req.headers.get("x-devbox-id").unwrap(), "untouched");
+        assert!(req.headers.get("x-original-host").is_none());
+
+        headers.strip(&mut req);
+        assert!(req.headers.get("x-app").is_none());
+        assert!(req.headers.get("x-devbox-namespace").is_none());
+    }
+}
@@ -0,0 +1,432 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::header::{CONTENT_TYPE, HOST, USER_AGENT};
+use http::{Method, Request, Uri};
+use http_body_util::Full;
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+use tracing::{debug, info, warn};
+
+use crate::activity::ActivityTracker;
+use crate::error::{Error, Result};
+use crate::metrics;
+use crate::preview::unix_now;
+use crate::registry::{DevboxInfo, DevboxRegistry};
+use crate::tls;
+
+/// Time between scans for devboxes that went idle
+const SCAN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time allowed for one webhook request
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Transition of a devbox reported to the control plane
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleEvent {
+    /// No requests for `IDLE_TIMEOUT`: the devbox may be scaled to zero
+    Idle,
+    /// First request after the devbox was reported idle
+    Wake,
+}
+
+impl IdleEvent {
+    /// Metric label of the event
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Wake => "wake",
+        }
+    }
+}
+
+/// Body of a webhook request
+#[derive(Debug, Serialize)]
+pub struct IdleNotification<'a> {
+    pub event: IdleEvent,
+    pub unique_id: &'a str,
+    pub cluster: &'a str,
+    pub namespace: &'a str,
+    pub devbox_name: &'a str,
+    /// Unix seconds of the devbox's last request
+    pub last_activity: u64,
+}
+
+/// Tells the control plane about idle and wake transitions.
+#[async_trait]
+pub trait IdleHook: Send + Sync {
+    async fn notify(&self, notification: &IdleNotification<'_>) -> Result<()>;
+}
+
+/// [`IdleHook`] POSTing the notification as JSON to `IDLE_WEBHOOK_URL`;
+/// any 2xx response counts as delivered.
+pub struct WebhookIdleHook {
+    url: Uri,
+    tls: TlsConnector,
+}
+
+impl WebhookIdleHook {
+    pub fn new(url: &str) -> Result<Self> {
+        let url: Uri = url
+            .parse()
+            .map_err(|e| Error::Config(format!("Invalid IDLE_WEBHOOK_URL {url:?}: {e}")))?;
+        if !matches!(url.scheme_str(), Some("http" | "https")) || url.host().is_none() {
+            return Err(Error::Config(format!(
+                "Invalid IDLE_WEBHOOK_URL {url}: must be an http(s) URL"
+            )));
+        }
+        Ok(Self {
+            url,
+            tls: tls::client_connector(),
+        })
+    }
+
+    async fn post(&self, body: Vec<u8>) -> Result<u16> {
+        let host = self.url.host().unwrap_or_default();
+        let https = self.url.scheme_str() == Some("https");
+        let port = self.url.port_u16().unwrap_or(if https { 443 } else { 80 });
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.path_and_query().map_or("/", |p| p.as_str()))
+            .header(HOST, self.url.authority().map_or(host, |a| a.as_str()))
+            .header(USER_AGENT, concat!("httpgate/", env!("CARGO_PKG_VERSION")))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| Error::Proxy(e.to_string()))?;
+
+        let tcp = TcpStream::connect((host, port))
+            .await
+            .map_err(|e| Error::Proxy(format!("Failed to connect to {host}:{port}: {e}")))?;
+        if https {
+            let server_name = ServerName::try_from(host.to_string())
+                .map_err(|e| Error::Proxy(format!("invalid server name {host:?}: {e}")))?;
+            let tls = self
+                .tls
+                .connect(server_name, tcp)
+                .await
+                .map_err(|e| Error::Proxy(format!("TLS handshake with {host} failed: {e}")))?;
+            send_request(tls, request).await
+        } else {
+            send_request(tcp, request).await
+        }
+    }
+}
+
+#[async_trait]
+impl IdleHook for WebhookIdleHook {
+    async fn notify(&self, notification: &IdleNotification<'_>) -> Result<()> {
+        let body = serde_json::to_vec(notification).map_err(|e| Error::Proxy(e.to_string()))?;
+        let status = tokio::time::timeout(WEBHOOK_TIMEOUT, self.post(body))
+            .await
+            .map_err(|_| Error::Proxy("idle webhook timed out".to_string()))??;
+        if !(200..300).contains(&status) {
+            return Err(Error::Proxy(format!("idle webhook returned {status}")));
+        }
+        Ok(())
+    }
+}
+
+/// Send `request` over `io`, returning the response status.
+async fn send_request<S>(io: S, request: Request<Full<Bytes>>) -> Result<u16>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(io))
+        .await
+        .map_err(|e| Error::Proxy(e.to_string()))?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!(error = %e, "Idle webhook connection failed");
+        }
+    });
+    let response = sender
+        .send_request(request)
+        .await
+        .map_err(|e| Error::Proxy(e.to_string()))?;
+    Ok(response.status().as_u16())
+}
+
+/// Reports devboxes to the control plane when they go idle, so they can be
+/// scaled to zero, and again on their first request after that, so they
+/// can be woken.
+///
+/// Only devboxes with requests since the gateway started are tracked.
+/// Failed idle reports are retried on the next scan; failed wakes are only
+/// logged.
+pub struct IdleNotifier {
+    tracker: Arc<ActivityTracker>,
+    registry: Arc<DevboxRegistry>,
+    hook: Box<dyn IdleHook>,
+    idle_after: Duration,
+    wakes: mpsc::UnboundedReceiver<String>,
+}
+
+impl IdleNotifier {
+    /// `wakes` receives what `tracker` sends (see
+    /// [`ActivityTracker::with_wakes`]).
+    pub fn new(
+        tracker: Arc<ActivityTracker>,
+        registry: Arc<DevboxRegistry>,
+        hook: Box<dyn IdleHook>,
+        idle_after: Duration,
+        wakes: mpsc::UnboundedReceiver<String>,
+    ) -> Self {
+        Self {
+            tracker,
+            registry,
+            hook,
+            idle_after,
+            wakes,
+        }
+    }
+
+    /// Report wakes as they happen and scan for idle devboxes forever.
+    pub async fn run(mut self) {
+        info!(idle_after = ?self.idle_after, "Starting idle devbox notifications");
+        let mut ticker = tokio::time::interval(SCAN_INTERVAL.min(self.idle_after));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    self.scan(unix_now()).await;
+                }
+                Some(unique_id) = self.wakes.recv() => {
+                    self.wake(&unique_id, unix_now()).await;
+                }
+            }
+        }
+    }
+
+    /// Report the devboxes that went idle by `now`, returning how many were
+    /// reported successfully.
+    pub async fn scan(&mut self, now: u64) -> usize {
+        let idle_after = self.idle_after.as_secs();
+        let mut reported = 0;
+        for (unique_id, last_activity) in self.tracker.snapshot(now) {
+            if !self.tracker.mark_idle(&unique_id, now, idle_after) {
+                continue;
+            }
+            let Some(info) = self.registry.get_devbox(&unique_id) else {
+                self.tracker.clear_idle(&unique_id);
+                continue;
+            };
+            if self
+                .notify(IdleEvent::Idle, &unique_id, &info, last_activity)
+                .await
+            {
+                reported += 1;
+            } else {
+                self.tracker.clear_idle(&unique_id);
+            }
+        }
+        reported
+    }
+
+    /// Report that `unique_id` was requested at `now` after being reported
+    /// idle, returning whether the report was delivered.
+    pub async fn wake(&mut self, unique_id: &str, now: u64) -> bool {
+        let Some(info) = self.registry.get_devbox(unique_id) else {
+            return false;
+        };
+        self.notify(IdleEvent::Wake, unique_id, &info, now).await
+    }
+
+    async fn notify(
+        &self,
+        event: IdleEvent,
+        unique_id: &str,
+        info: &DevboxInfo,
+        last_activity: u64,
+    ) -> bool {
+        let notification = IdleNotification {
+            event,
+            unique_id,
+            cluster: &info.cluster,
+            namespace: &info.namespace,
+            devbox_name: &info.devbox_name,
+            last_activity,
+        };
+        let result = self.hook.notify(&notification).await;
+        metrics::IDLE_NOTIFICATIONS_TOTAL
+            .with_label_values(&[event.as_str(), if result.is_ok() { "ok" } else { "failed" }])
+            .inc();
+        match result {
+            Ok(()) => {
+                info!(unique_id = %unique_id, event = event.as_str(), "Notified devbox idle transition");
+                true
+            }
+            Err(e) => {
+                warn!(
+                    unique_id = %unique_id,
+                    event = event.as_str(),
+                    error = %e,
+                    "Failed to notify devbox idle transition"
+                );
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const NOW: u64 = 1_700_000_000;
+    const IDLE_AFTER: Duration = Duration::from_secs(600);
+
+    /// Fake control plane recording notifications.
+    #[derive(Default)]
+    struct FakeHook {
+        events: Mutex<Vec<(IdleEvent, String)>>,
+        fail: Mutex<bool>,
+    }
+
+    #[async_trait]
+    impl IdleHook for Arc<FakeHook> {
+        async fn notify(&self, notification: &IdleNotification<'_>) -> Result<()> {
+            if *self.fail.lock().unwrap() {
+                return Err(Error::Proxy("control plane unavailable".to_string()));
+            }
+            self.events
+                .lock()
+                .unwrap()
+                .push((notification.event, notification.devbox_name.to_string()));
+            Ok(())
+        }
+    }
+
+    fn notifier() -> (IdleNotifier, Arc<ActivityTracker>, Arc<FakeHook>) {
+        let registry = Arc::new(DevboxRegistry::new());
+        for (id, name) in [("app-a", "devbox-a"), ("app-b", "devbox-b")] {
+            registry.register_devbox(id.to_string(), "ns".to_string(), name.to_string());
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        let tracker = Arc::new(ActivityTracker::new().with_wakes(tx));
+        let hook = Arc::new(FakeHook::default());
+        let notifier = IdleNotifier::new(
+            Arc::clone(&tracker),
+            registry,
+            Box::new(Arc::clone(&hook)),
+            IDLE_AFTER,
+            rx,
+        );
+        (notifier, tracker, hook)
+    }
+
+    #[tokio::test]
+    async fn test_idle_reported_once() {
+        let (mut notifier, tracker, hook) = notifier();
+        let websocket = tracker.begin_at("app-a", 8080, NOW);
+
+        // Open connections keep a devbox active however long they last
+        assert_eq!(notifier.scan(NOW + 3600).await, 0);
+        drop(websocket);
+        let seen = tracker.last_seen("app-a", NOW).unwrap();
+
+        assert_eq!(notifier.scan(seen + 599).await, 0);
+        assert_eq!(notifier.scan(seen + 600).await, 1);
+        assert_eq!(notifier.scan(seen + 1200).await, 0);
+        assert_eq!(
+            *hook.events.lock().unwrap(),
+            vec![(IdleEvent::Idle, "devbox-a".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wake_after_idle() {
+        let (mut notifier, tracker, hook) = notifier();
+        drop(tracker.begin_at("app-a", 8080, NOW));
+        let seen = tracker.last_seen("app-a", NOW).unwrap();
+
+        // Requests before the devbox is reported idle are no wakes
+        drop(tracker.begin_at("app-a", 8080, seen));
+        assert!(notifier.wakes.try_recv().is_err());
+
+        let seen = tracker.last_seen("app-a", NOW).unwrap();
+        assert_eq!(notifier.scan(seen + 600).await, 1);
+
+        // The first request after that is, once
+        drop(tracker.begin_at("app-a", 8080, seen + 700));
+        assert_eq!(notifier.wakes.try_recv().unwrap(), "app-a");
+        assert!(notifier.wakes.try_recv().is_err());
+        assert!(notifier.wake("app-a", seen + 700).await);
+
+        assert_eq!(
+            *hook.events.lock().unwrap(),
+            vec![
+                (IdleEvent::Idle, "devbox-a".to_string()),
+                (IdleEvent::Wake, "devbox-a".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_idle_report_retried() {
+        let (mut notifier, tracker, hook) = notifier();
+        drop(tracker.begin_at("app-a", 8080, NOW));
+        let seen = tracker.last_seen("app-a", NOW).unwrap();
+
+        *hook.fail.lock().unwrap() = true;
+        assert_eq!(notifier.scan(seen + 600).await, 0);
+        // Not reported idle, so requests now are no wakes
+        tracker.wake("app-a");
+        assert!(notifier.wakes.try_recv().is_err());
+
+        *hook.fail.lock().unwrap() = false;
+        assert_eq!(notifier.scan(seen + 630).await, 1);
+        // Requests finding no running Pod wake the devbox too
+        tracker.wake("app-a");
+        assert_eq!(notifier.wakes.try_recv().unwrap(), "app-a");
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_devboxes_skipped() {
+        let (mut notifier, tracker, hook) = notifier();
+        drop(tracker.begin_at("unknown-app", 8080, NOW));
+        let seen = tracker.last_seen("unknown-app", NOW).unwrap();
+        assert_eq!(notifier.scan(seen + 600).await, 0);
+        assert!(!notifier.wake("unknown-app", seen + 700).await);
+        assert!(hook.events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_webhook_url() {
+        assert!(WebhookIdleHook::new("http://control-plane.svc/idle").is_ok());
+        assert!(WebhookIdleHook::new("https://example.com/hooks/idle?token=x").is_ok());
+        assert!(WebhookIdleHook::new("ftp://example.com/").is_err());
+        assert!(WebhookIdleHook::new("/idle").is_err());
+    }
+
+    #[test]
+    fn test_notification_body() {
+        let notification = IdleNotification {
+            event: IdleEvent::Wake,
+            unique_id: "app-a",
+            cluster: "default",
+            namespace: "ns",
+            devbox_name: "devbox-a",
+            last_activity: NOW,
+        };
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({
+                "event": "wake",
+                "unique_id": "app-a",
+                "cluster": "default",
+                "namespace": "ns",
+                "devbox_name": "devbox-a",
+                "last_activity": NOW,
+            })
+        );
+    }
+}
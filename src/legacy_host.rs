@@ -0,0 +1,265 @@
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use tracing::{debug, warn};
+
+use crate::metrics;
+
+/// A legacy host pattern and the current host it is redirected to.
+#[derive(Debug, Clone)]
+struct LegacyHost {
+    pattern: Regex,
+    /// Replacement of the whole host, with `$1` or `${name}` for capture groups
+    template: String,
+}
+
+/// Outcome of matching a host against the legacy patterns
+#[derive(Debug, PartialEq, Eq)]
+pub enum LegacyRedirect {
+    /// Redirect to this host
+    Redirect(String),
+    /// The host matches a legacy pattern, but so would the host it rewrites
+    /// to (a redirect loop)
+    Loop(String),
+}
+
+/// Redirects from hostname formats of the past to the current scheme, from
+/// `LEGACY_HOST_REDIRECTS`.
+///
+/// Rules are separated by `;` and map a host regex to a rewrite template:
+///
+/// ```text
+/// ^(\d+)-([a-z0-9-]+)\.old-devbox\.sealos\.io$ => $2-$1.devbox.sealos.io
+/// ```
+///
+/// Hosts are matched without their port, and the first matching rule wins.
+#[derive(Debug, Clone, Default)]
+pub struct LegacyHosts {
+    rules: Vec<LegacyHost>,
+}
+
+impl LegacyHosts {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Rewrite `host` by the first legacy pattern it matches.
+    ///
+    /// Redirects are refused if the rewritten host matches a legacy pattern
+    /// too, which would send clients around in circles.
+    pub fn rewrite(&self, host: &str) -> Option<LegacyRedirect> {
+        let host = host.split(':').next().unwrap_or(host).to_ascii_lowercase();
+        let (rule, captures) = self
+            .rules
+            .iter()
+            .find_map(|rule| Some((rule, rule.pattern.captures(&host)?)))?;
+        let mut rewritten = String::new();
+        captures.expand(&rule.template, &mut rewritten);
+
+        let result = if self.rules.iter().any(|r| r.pattern.is_match(&rewritten)) {
+            warn!(
+                host = %host,
+                rewritten = %rewritten,
+                pattern = %rule.pattern,
+                "Refusing to redirect legacy host to another legacy host"
+            );
+            LegacyRedirect::Loop(rewritten)
+        } else {
+            debug!(host = %host, rewritten = %rewritten, "Redirecting legacy host");
+            LegacyRedirect::Redirect(rewritten)
+        };
+        let label = match result {
+            LegacyRedirect::Redirect(_) => "redirected",
+            LegacyRedirect::Loop(_) => "loop",
+        };
+        metrics::LEGACY_HOST_REDIRECTS_TOTAL
+            .with_label_values(&[rule.pattern.as_str(), label])
+            .inc();
+        Some(result)
+    }
+}
+
+impl FromStr for LegacyHosts {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+        for entry in spec.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            let (pattern, template) = entry
+                .split_once("=>")
+                .ok_or_else(|| format!("expected <pattern> => <host>, got {entry:?}"))?;
+            let pattern = Regex::new(pattern.trim())
+                .map_err(|e| format!("invalid pattern {:?}: {e}", pattern.trim()))?;
+            let template = template.trim();
+            validate_template(&pattern, template)?;
+            rules.push(LegacyHost {
+                pattern,
+                template: template.to_string(),
+            });
+        }
+        Ok(Self { rules })
+    }
+}
+
+/// Check that `template` is a host and only refers to groups `pattern` has.
+fn validate_template(pattern: &Regex, template: &str) -> Result<(), String> {
+    if template.is_empty()
+        || template
+            .bytes()
+            .any(|b| matches!(b, b'/' | b'?' | b'#' | b'@' | b'\\') || b.is_ascii_whitespace())
+    {
+        return Err(format!("invalid host template {template:?}"));
+    }
+
+    let mut rest = template;
+    while let Some(i) = rest.find('$') {
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            rest = after;
+            continue;
+        }
+        // Same reference syntax as `Captures::expand`
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| format!("unclosed group reference in {template:?}"))?;
+            (&braced[..end], &braced[end + 1..])
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        let exists = match name.parse::<usize>() {
+            Ok(index) => index < pattern.captures_len(),
+            Err(_) => pattern.capture_names().flatten().any(|n| n == name),
+        };
+        if !exists {
+            return Err(format!(
+                "{template:?} refers to group {name:?}, which {:?} does not have",
+                pattern.as_str()
+            ));
+        }
+        rest = after;
+    }
+    Ok(())
+}
+
+impl Serialize for LegacyHosts {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        struct Rule<'a>(&'a LegacyHost);
+
+        impl Serialize for Rule<'_> {
+            fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                let mut map = s.serialize_map(Some(2))?;
+                map.serialize_entry("pattern", self.0.pattern.as_str())?;
+                map.serialize_entry("template", &self.0.template)?;
+                map.end()
+            }
+        }
+
+        let mut seq = s.serialize_seq(Some(self.rules.len()))?;
+        for rule in &self.rules {
+            seq.serialize_element(&Rule(rule))?;
+        }
+        seq.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_SCHEME: &str = r"^(?P<port>\d+)-(?P<id>[a-z0-9-]+)\.old-devbox\.sealos\.io$ => ${id}-${port}.devbox.sealos.io";
+
+    #[test]
+    fn test_rewrite() {
+        let hosts: LegacyHosts = OLD_SCHEME.parse().unwrap();
+        assert_eq!(
+            hosts.rewrite("8080-my-app.old-devbox.sealos.io"),
+            Some(LegacyRedirect::Redirect(
+                "my-app-8080.devbox.sealos.io".to_string()
+            ))
+        );
+        // Ports and case are ignored
+        assert_eq!(
+            hosts.rewrite("3000-My-App.OLD-devbox.sealos.io:443"),
+            Some(LegacyRedirect::Redirect(
+                "my-app-3000.devbox.sealos.io".to_string()
+            ))
+        );
+        assert_eq!(hosts.rewrite("my-app-8080.devbox.sealos.io"), None);
+        assert_eq!(hosts.rewrite("my-app.old-devbox.sealos.io"), None);
+
+        // Numbered groups, and the first matching rule wins
+        let hosts: LegacyHosts = r"^(\d+)\.([a-z0-9-]+)\.v1\.example\.com$ => $2-$1.example.com;
+                                   ^([a-z0-9-]+)\.v1\.example\.com$ => $1-80.example.com"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            hosts.rewrite("8080.my-app.v1.example.com"),
+            Some(LegacyRedirect::Redirect(
+                "my-app-8080.example.com".to_string()
+            ))
+        );
+        assert_eq!(
+            hosts.rewrite("my-app.v1.example.com"),
+            Some(LegacyRedirect::Redirect(
+                "my-app-80.example.com".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_rewrite_loop() {
+        // The rewritten host is legacy as well
+        let hosts: LegacyHosts = r"^([a-z0-9-]+)\.a\.example\.com$ => $1.b.example.com;
+                                   ^([a-z0-9-]+)\.b\.example\.com$ => $1.a.example.com"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            hosts.rewrite("my-app.a.example.com"),
+            Some(LegacyRedirect::Loop("my-app.b.example.com".to_string()))
+        );
+
+        // Or matches its own pattern
+        let hosts: LegacyHosts = r"\.example\.com$ => www.example.com".parse().unwrap();
+        assert_eq!(
+            hosts.rewrite("old.example.com"),
+            Some(LegacyRedirect::Loop("www.example.com".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!("".parse::<LegacyHosts>().unwrap().is_empty());
+        for spec in [
+            r"^(\d+)\.old\.example\.com$",
+            r"^(\d+\.old\.example\.com$ => $1.example.com",
+            r"^(\d+)\.old\.example\.com$ => ",
+            r"^(\d+)\.old\.example\.com$ => example.com/$1",
+            r"^(\d+)\.old\.example\.com$ => $2.example.com",
+            r"^(\d+)\.old\.example\.com$ => ${port}.example.com",
+            r"^(\d+)\.old\.example\.com$ => ${1.example.com",
+            // `$1x` reads as the group named "1x"
+            r"^(\d+)\.old\.example\.com$ => $1x.example.com",
+        ] {
+            assert!(spec.parse::<LegacyHosts>().is_err(), "{spec}");
+        }
+        assert!(r"^(\d+)\.old\.example\.com$ => $$$1.example.com"
+            .parse::<LegacyHosts>()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let hosts: LegacyHosts = OLD_SCHEME.parse().unwrap();
+        let json = serde_json::to_value(&hosts).unwrap();
+        assert_eq!(json[0]["template"], "${id}-${port}.devbox.sealos.io");
+        assert!(json[0]["pattern"]
+            .as_str()
+            .unwrap()
+            .starts_with("^(?P<port>"));
+    }
+}
@@ -1,6 +1,58 @@
+pub mod access_log;
+pub mod acme;
+pub mod activity;
+pub mod admin;
+pub mod base_href;
+pub mod basic_auth;
+pub mod blocklist;
+pub mod bloom;
+pub mod cache;
+pub mod cidr;
+pub mod client_cert;
 pub mod config;
+pub mod cors;
 pub mod crd;
+pub mod deadline;
+#[cfg(feature = "debug-endpoints")]
+pub mod debug;
+pub mod diagnostics;
+pub mod downtime;
 pub mod error;
+pub mod error_response;
+pub mod events;
+pub mod expect;
+pub mod gc;
+pub mod grpc_health;
+pub mod headers;
+pub mod identity;
+pub mod idle;
+pub mod legacy_host;
+pub mod limits;
+pub mod locale;
+pub mod metrics;
+pub mod mirror;
+pub mod passthrough;
+pub mod path;
+pub mod policy;
+pub mod preview;
+#[cfg(unix)]
+pub mod privileges;
+pub mod protocol_guard;
 pub mod proxy;
+pub mod proxy_protocol;
 pub mod registry;
+pub mod response_limit;
+pub mod retry;
+pub mod route_override;
+pub mod routing;
+pub mod self_addrs;
+pub mod snapshot;
+pub mod sni;
+pub mod status;
+pub mod suggest;
+pub mod tarpit;
+pub mod tls;
+pub mod tls_reload;
+pub mod warmup;
 pub mod watcher;
+pub mod ws_origin;
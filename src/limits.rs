@@ -0,0 +1,638 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, RwLock,
+};
+use std::time::Instant;
+
+use dashmap::DashMap;
+use tracing::warn;
+
+use crate::config::Config;
+
+/// Caps the number of concurrently active requests.
+///
+/// A slot is held by an [`InflightGuard`] for the lifetime of a request and
+/// released when the guard is dropped, whichever way the request ends.
+#[derive(Debug)]
+pub struct InflightLimiter {
+    current: AtomicUsize,
+    /// Maximum concurrent requests (unlimited if `None`)
+    max: Option<usize>,
+}
+
+impl InflightLimiter {
+    pub const fn new(max: Option<usize>) -> Self {
+        Self {
+            current: AtomicUsize::new(0),
+            max,
+        }
+    }
+
+    /// Try to take a slot, returning `None` if the limit is reached.
+    pub fn try_acquire(self: &Arc<Self>) -> Option<InflightGuard> {
+        let acquired = match self.max {
+            Some(max) => self
+                .current
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                    (n < max).then_some(n + 1)
+                })
+                .is_ok(),
+            None => {
+                self.current.fetch_add(1, Ordering::AcqRel);
+                true
+            }
+        };
+
+        acquired.then(|| InflightGuard(Arc::clone(self)))
+    }
+
+    /// Number of requests currently holding a slot.
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::Acquire)
+    }
+}
+
+/// A held in-flight slot, released on drop.
+#[derive(Debug)]
+pub struct InflightGuard(Arc<InflightLimiter>);
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.0.current.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Caps the number of concurrently active requests of each client IP.
+///
+/// Entries are removed as soon as a client has no requests in flight, so
+/// the map only holds active clients.
+#[derive(Debug)]
+pub struct ClientLimiter {
+    counts: DashMap<IpAddr, AtomicUsize>,
+    /// Maximum concurrent requests per client (unlimited if `None`)
+    max: Option<usize>,
+}
+
+impl ClientLimiter {
+    pub fn new(max: Option<usize>) -> Self {
+        Self {
+            counts: DashMap::new(),
+            max,
+        }
+    }
+
+    /// Try to take a slot for `client`, returning `None` if it has reached
+    /// the limit. Without a limit no slots are tracked.
+    pub fn try_acquire(self: &Arc<Self>, client: IpAddr) -> Option<ClientGuard> {
+        let Some(max) = self.max else {
+            return Some(ClientGuard(None));
+        };
+        // Increment under the shard lock, so a release can't remove the
+        // entry in between
+        let acquired = self
+            .counts
+            .entry(client)
+            .or_default()
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .is_ok();
+        if !acquired {
+            self.counts
+                .remove_if(&client, |_, n| n.load(Ordering::Acquire) == 0);
+            return None;
+        }
+        Some(ClientGuard(Some((Arc::clone(self), client))))
+    }
+
+    /// Number of requests of `client` currently holding a slot.
+    pub fn current(&self, client: IpAddr) -> usize {
+        self.counts
+            .get(&client)
+            .map_or(0, |n| n.load(Ordering::Acquire))
+    }
+
+    /// Number of clients with requests in flight.
+    pub fn clients(&self) -> usize {
+        self.counts.len()
+    }
+
+    fn release(&self, client: IpAddr) {
+        if let Some(n) = self.counts.get(&client) {
+            n.fetch_sub(1, Ordering::AcqRel);
+        }
+        self.counts
+            .remove_if(&client, |_, n| n.load(Ordering::Acquire) == 0);
+    }
+}
+
+/// A held per-client slot, released on drop.
+#[derive(Debug)]
+pub struct ClientGuard(Option<(Arc<ClientLimiter>, IpAddr)>);
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        if let Some((limiter, client)) = &self.0 {
+            limiter.release(*client);
+        }
+    }
+}
+
+/// Sustained request rate with a burst allowance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Requests per second
+    pub per_second: u32,
+    /// Requests admitted at once after an idle period
+    pub burst: u32,
+}
+
+/// Limits applied to all devboxes of one namespace together.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NamespaceLimit {
+    /// Maximum concurrently active requests (unlimited if `None`)
+    pub max_inflight: Option<usize>,
+    /// Request rate (unlimited if `None`)
+    pub rate: Option<RateLimit>,
+    /// Share of successful requests whose access records are logged
+    /// (`ACCESS_LOG_SAMPLE` if `None`), so busy namespaces can be sampled
+    /// down while quiet ones are logged in full
+    pub log_sample: Option<f64>,
+}
+
+impl NamespaceLimit {
+    /// Defaults for namespaces without an override.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_inflight: config.namespace_max_inflight,
+            rate: config.namespace_rate_limit.map(|per_second| RateLimit {
+                per_second,
+                burst: config.namespace_rate_burst.unwrap_or(per_second),
+            }),
+            log_sample: None,
+        }
+    }
+}
+
+impl FromStr for NamespaceLimit {
+    type Err = String;
+
+    /// Parse `max-inflight=<n>;rate=<per second>;burst=<n>;log-sample=<ratio>`.
+    /// Omitted keys are unlimited; `burst` defaults to `rate`, and
+    /// `log-sample` to `ACCESS_LOG_SAMPLE`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limit = Self::default();
+        let mut burst = None;
+        for part in s.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {part:?}"))?;
+            let number = |value: &str| {
+                value
+                    .trim()
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid {key} {value:?}"))
+            };
+            match key.trim() {
+                "max-inflight" => limit.max_inflight = Some(number(value)? as usize),
+                "rate" => {
+                    limit.rate = Some(RateLimit {
+                        per_second: number(value)?,
+                        burst: 0,
+                    });
+                }
+                "burst" => burst = Some(number(value)?),
+                "log-sample" => {
+                    let ratio = value
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|ratio| (0.0..=1.0).contains(ratio))
+                        .ok_or_else(|| format!("invalid {key} {value:?}"))?;
+                    limit.log_sample = Some(ratio);
+                }
+                other => return Err(format!("unknown key {other:?}")),
+            }
+        }
+
+        match (&mut limit.rate, burst) {
+            (Some(rate), burst) => rate.burst = burst.unwrap_or(rate.per_second),
+            (None, Some(_)) => return Err("burst requires rate".to_string()),
+            (None, None) => {}
+        }
+        Ok(limit)
+    }
+}
+
+/// Parse per-namespace overrides from ConfigMap data (namespace -> limit),
+/// skipping invalid entries.
+pub fn parse_namespace_limits(data: &BTreeMap<String, String>) -> HashMap<String, NamespaceLimit> {
+    data.iter()
+        .filter_map(|(namespace, value)| match value.parse() {
+            Ok(limit) => Some((namespace.clone(), limit)),
+            Err(e) => {
+                warn!(namespace = %namespace, value = %value, error = %e, "Invalid namespace limit, ignoring");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Which namespace limit a request exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Concurrency,
+    Rate,
+}
+
+impl LimitExceeded {
+    /// Metric label
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Concurrency => "concurrency",
+            Self::Rate => "rate",
+        }
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Token bucket of one namespace
+#[derive(Debug)]
+struct TokenBucket {
+    rate: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: RateLimit, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: f64::from(rate.burst),
+            updated: now,
+        }
+    }
+
+    fn try_take(&mut self, rate: RateLimit, now: Instant) -> bool {
+        // Start over when the limit was changed
+        if self.rate != rate {
+            *self = Self::new(rate, now);
+        }
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * f64::from(rate.per_second)).min(f64::from(rate.burst));
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Per-namespace concurrency and rate limits.
+///
+/// Every namespace gets the defaults unless an override is set for it;
+/// overrides are replaced as a whole whenever the limits ConfigMap changes.
+#[derive(Debug)]
+pub struct NamespaceLimiter {
+    defaults: NamespaceLimit,
+    overrides: RwLock<Arc<HashMap<String, NamespaceLimit>>>,
+    inflight: DashMap<String, Arc<AtomicUsize>>,
+    buckets: DashMap<String, TokenBucket>,
+}
+
+impl NamespaceLimiter {
+    pub fn new(defaults: NamespaceLimit) -> Self {
+        Self {
+            defaults,
+            overrides: RwLock::default(),
+            inflight: DashMap::new(),
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Replace the per-namespace overrides.
+    pub fn set_overrides(&self, overrides: HashMap<String, NamespaceLimit>) {
+        *self.overrides.write().unwrap() = Arc::new(overrides);
+    }
+
+    /// Limit in effect for `namespace`.
+    pub fn limit_for(&self, namespace: &str) -> NamespaceLimit {
+        self.overrides
+            .read()
+            .unwrap()
+            .get(namespace)
+            .copied()
+            .unwrap_or(self.defaults)
+    }
+
+    /// Admit a request to `namespace`, holding a concurrency slot until the
+    /// returned guard is dropped.
+    pub fn try_acquire(&self, namespace: &str) -> Result<NamespaceGuard, LimitExceeded> {
+        self.try_acquire_at(namespace, Instant::now())
+    }
+
+    fn try_acquire_at(
+        &self,
+        namespace: &str,
+        now: Instant,
+    ) -> Result<NamespaceGuard, LimitExceeded> {
+        let limit = self.limit_for(namespace);
+
+        let current = match self.inflight.get(namespace) {
+            Some(current) => Arc::clone(&current),
+            None => Arc::clone(&self.inflight.entry(namespace.to_string()).or_default()),
+        };
+        let acquired = current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                limit
+                    .max_inflight
+                    .is_none_or(|max| n < max)
+                    .then_some(n + 1)
+            })
+            .is_ok();
+        if !acquired {
+            return Err(LimitExceeded::Concurrency);
+        }
+        let guard = NamespaceGuard(current);
+
+        if let Some(rate) = limit.rate {
+            let admitted = match self.buckets.get_mut(namespace) {
+                Some(mut bucket) => bucket.try_take(rate, now),
+                None => self
+                    .buckets
+                    .entry(namespace.to_string())
+                    .or_insert_with(|| TokenBucket::new(rate, now))
+                    .try_take(rate, now),
+            };
+            if !admitted {
+                return Err(LimitExceeded::Rate);
+            }
+        }
+        Ok(guard)
+    }
+
+    /// Requests to `namespace` currently holding a slot.
+    pub fn current(&self, namespace: &str) -> usize {
+        self.inflight
+            .get(namespace)
+            .map_or(0, |current| current.load(Ordering::Acquire))
+    }
+}
+
+/// A held namespace concurrency slot, released on drop.
+#[derive(Debug)]
+pub struct NamespaceGuard(Arc<AtomicUsize>);
+
+impl Drop for NamespaceGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_client_limit() {
+        let limiter = Arc::new(ClientLimiter::new(Some(2)));
+        let a: IpAddr = "203.0.113.1".parse().unwrap();
+        let b: IpAddr = "203.0.113.2".parse().unwrap();
+
+        let first = limiter.try_acquire(a).unwrap();
+        let _second = limiter.try_acquire(a).unwrap();
+        assert!(limiter.try_acquire(a).is_none());
+        assert_eq!(limiter.current(a), 2);
+
+        // Other clients have their own slots
+        let other = limiter.try_acquire(b).unwrap();
+        assert_eq!(limiter.current(b), 1);
+
+        drop(first);
+        let _third = limiter.try_acquire(a).unwrap();
+        assert!(limiter.try_acquire(a).is_none());
+
+        // Idle clients are dropped from the map
+        drop(other);
+        assert_eq!(limiter.current(b), 0);
+        assert_eq!(limiter.clients(), 1);
+    }
+
+    #[test]
+    fn test_client_limit_cleanup() {
+        let limiter = Arc::new(ClientLimiter::new(Some(4)));
+        let handles: Vec<_> = (0..8u8)
+            .map(|i| {
+                let limiter = Arc::clone(&limiter);
+                thread::spawn(move || {
+                    let client = IpAddr::from([10, 0, 0, i % 2]);
+                    for _ in 0..1000 {
+                        let _guard = limiter.try_acquire(client);
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(limiter.clients(), 0);
+
+        // Without a limit nothing is tracked
+        let unlimited = Arc::new(ClientLimiter::new(None));
+        let client: IpAddr = "203.0.113.1".parse().unwrap();
+        let guards: Vec<_> = (0..100).map(|_| unlimited.try_acquire(client)).collect();
+        assert!(guards.iter().all(Option::is_some));
+        assert_eq!(unlimited.clients(), 0);
+    }
+
+    #[test]
+    fn test_limit_boundary() {
+        let limiter = Arc::new(InflightLimiter::new(Some(2)));
+
+        let first = limiter.try_acquire().unwrap();
+        let second = limiter.try_acquire().unwrap();
+        assert_eq!(limiter.current(), 2);
+
+        // At the limit: shed
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.current(), 2);
+
+        // Releasing a slot admits the next request
+        drop(first);
+        let third = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_none());
+
+        drop(second);
+        drop(third);
+        assert_eq!(limiter.current(), 0);
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let limiter = Arc::new(InflightLimiter::new(None));
+        let guards: Vec<_> = (0..1000).map(|_| limiter.try_acquire().unwrap()).collect();
+        assert_eq!(limiter.current(), 1000);
+        drop(guards);
+        assert_eq!(limiter.current(), 0);
+    }
+
+    #[test]
+    fn test_concurrent_acquire_never_exceeds_limit() {
+        let limiter = Arc::new(InflightLimiter::new(Some(10)));
+        let handles: Vec<_> = (0..100)
+            .map(|_| {
+                let limiter = Arc::clone(&limiter);
+                thread::spawn(move || {
+                    let guard = limiter.try_acquire();
+                    assert!(limiter.current() <= 10);
+                    guard.is_some()
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(limiter.current(), 0);
+    }
+
+    fn limiter(defaults: &str, overrides: &[(&str, &str)]) -> NamespaceLimiter {
+        let limiter = NamespaceLimiter::new(defaults.parse().unwrap());
+        let data = overrides
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect();
+        limiter.set_overrides(parse_namespace_limits(&data));
+        limiter
+    }
+
+    #[test]
+    fn test_parse_namespace_limit() {
+        assert_eq!(
+            "max-inflight=10; rate=5; burst=20".parse(),
+            Ok(NamespaceLimit {
+                max_inflight: Some(10),
+                rate: Some(RateLimit {
+                    per_second: 5,
+                    burst: 20
+                }),
+                log_sample: None,
+            })
+        );
+        assert_eq!(
+            "rate=5; log-sample=0.01"
+                .parse::<NamespaceLimit>()
+                .unwrap()
+                .log_sample,
+            Some(0.01)
+        );
+        assert_eq!(
+            "rate=5".parse::<NamespaceLimit>().unwrap().rate,
+            Some(RateLimit {
+                per_second: 5,
+                burst: 5
+            })
+        );
+        assert_eq!("".parse(), Ok(NamespaceLimit::default()));
+        for invalid in [
+            "rate=0",
+            "rate=x",
+            "burst=5",
+            "max-inflight",
+            "cpu=1",
+            "log-sample=2",
+            "log-sample=-0.5",
+            "log-sample=x",
+        ] {
+            assert!(invalid.parse::<NamespaceLimit>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_overrides_skipped() {
+        let limiter = limiter(
+            "max-inflight=1",
+            &[("ns-a", "max-inflight=5"), ("ns-b", "rate=x")],
+        );
+        assert_eq!(limiter.limit_for("ns-a").max_inflight, Some(5));
+        assert_eq!(limiter.limit_for("ns-b").max_inflight, Some(1));
+    }
+
+    #[test]
+    fn test_namespace_concurrency_override() {
+        let limiter = limiter("max-inflight=1", &[("ns-big", "max-inflight=3")]);
+
+        // Default namespace: one at a time
+        let held = limiter.try_acquire("ns-small").unwrap();
+        assert_eq!(
+            limiter.try_acquire("ns-small").unwrap_err(),
+            LimitExceeded::Concurrency
+        );
+        // Namespaces don't share slots
+        let other = limiter.try_acquire("ns-other").unwrap();
+
+        let big: Vec<_> = (0..3)
+            .map(|_| limiter.try_acquire("ns-big").unwrap())
+            .collect();
+        assert!(limiter.try_acquire("ns-big").is_err());
+        assert_eq!(limiter.current("ns-big"), 3);
+
+        drop((held, other, big));
+        assert_eq!(limiter.current("ns-small"), 0);
+        assert!(limiter.try_acquire("ns-small").is_ok());
+
+        // Removing the override falls back to the default
+        limiter.set_overrides(HashMap::new());
+        let _held = limiter.try_acquire("ns-big").unwrap();
+        assert!(limiter.try_acquire("ns-big").is_err());
+    }
+
+    #[test]
+    fn test_namespace_rate_override() {
+        let limiter = limiter("rate=1", &[("ns-fast", "rate=10;burst=2")]);
+        let start = Instant::now();
+        let at = |ms| start + std::time::Duration::from_millis(ms);
+
+        // Burst, then one token per 100ms
+        assert!(limiter.try_acquire_at("ns-fast", at(0)).is_ok());
+        assert!(limiter.try_acquire_at("ns-fast", at(0)).is_ok());
+        assert_eq!(
+            limiter.try_acquire_at("ns-fast", at(50)).unwrap_err(),
+            LimitExceeded::Rate
+        );
+        assert!(limiter.try_acquire_at("ns-fast", at(100)).is_ok());
+
+        // Default: one per second
+        assert!(limiter.try_acquire_at("ns-slow", at(0)).is_ok());
+        assert!(limiter.try_acquire_at("ns-slow", at(500)).is_err());
+        assert!(limiter.try_acquire_at("ns-slow", at(1000)).is_ok());
+
+        // A rejected request doesn't keep its concurrency slot
+        assert_eq!(limiter.current("ns-slow"), 0);
+    }
+
+    #[test]
+    fn test_unlimited_by_default() {
+        let limiter = NamespaceLimiter::new(NamespaceLimit::default());
+        let guards: Vec<_> = (0..1000)
+            .map(|_| limiter.try_acquire("ns").unwrap())
+            .collect();
+        assert_eq!(limiter.current("ns"), 1000);
+        drop(guards);
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use http::header::ACCEPT_LANGUAGE;
+use http::HeaderMap;
+use tracing::info;
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+
+/// Language of the messages built into the gateway
+pub const BUILTIN_LOCALE: &str = "en";
+
+/// Placeholders templates may use, substituted per response
+const PLACEHOLDERS: [&str; 3] = ["status", "unique_id", "secs"];
+
+/// Translations of one locale
+#[derive(Debug)]
+struct Locale {
+    /// Language tag as the file names it (e.g. `zh-CN`)
+    tag: String,
+    templates: HashMap<String, String>,
+}
+
+/// A template rendered in the language it was found in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Translation<'a> {
+    pub lang: &'a str,
+    pub text: String,
+}
+
+/// Translations of gateway-generated pages, loaded from `LOCALES_DIR`.
+///
+/// The directory holds a `<language tag>.yaml` file per locale (e.g.
+/// `zh-CN.yaml`), mapping keys to templates:
+/// - `<error code>` (e.g. `devbox_not_found`): message of that error
+/// - `status.<status>` (e.g. `status.503`): title of HTML error pages
+/// - `devbox_starting.title`, `devbox_starting`: the page shown while a
+///   devbox starts
+/// - `devbox_suggestions.title`, `devbox_suggestions`: the 404 page
+///   suggesting similar devboxes
+///
+/// Templates may use `{status}`, `{unique_id}` and `{secs}`. Keys missing
+/// in the client's locale come from `DEFAULT_LOCALE`, then from the
+/// built-in English.
+#[derive(Debug)]
+pub struct Locales {
+    default: String,
+    /// Locales by lowercase language tag
+    locales: HashMap<String, Locale>,
+}
+
+impl Default for Locales {
+    /// Only the built-in English
+    fn default() -> Self {
+        Self {
+            default: BUILTIN_LOCALE.to_string(),
+            locales: HashMap::new(),
+        }
+    }
+}
+
+impl Locales {
+    /// Load the locales of `LOCALES_DIR`, if set.
+    ///
+    /// Every file must parse and every template must be well-formed, so a
+    /// broken translation fails the startup rather than a response.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut locales = Self {
+            default: config.default_locale.clone(),
+            locales: HashMap::new(),
+        };
+        if let Some(dir) = &config.locales_dir {
+            locales.load_dir(Path::new(dir))?;
+        }
+        if !locales.is_supported(&locales.default) {
+            return Err(Error::Config(format!(
+                "no translations for the default locale {}",
+                locales.default
+            )));
+        }
+        Ok(locales)
+    }
+
+    fn load_dir(&mut self, dir: &Path) -> Result<()> {
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| Error::Config(format!("Failed to read locales {}: {e}", dir.display())))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|e| {
+                    Error::Config(format!("Failed to read locales {}: {e}", dir.display()))
+                })?
+                .path();
+            // Skip the hidden entries of mounted ConfigMaps and other files
+            let Some(tag) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .filter(|name| !name.starts_with('.'))
+                .and_then(|name| name.strip_suffix(".yaml"))
+            else {
+                continue;
+            };
+            if !path.is_file() {
+                continue;
+            }
+            let content = std::fs::read_to_string(&path).map_err(|e| {
+                Error::Config(format!("Failed to read locale {}: {e}", path.display()))
+            })?;
+            self.add(tag, &content).map_err(|msg| {
+                Error::Config(format!("Invalid locale {}: {msg}", path.display()))
+            })?;
+        }
+        info!(
+            locales = ?self.locales.values().map(|l| l.tag.as_str()).collect::<Vec<_>>(),
+            default = %self.default,
+            "Locales loaded"
+        );
+        Ok(())
+    }
+
+    /// Add the locale `tag` from the YAML map of templates in `content`.
+    pub fn add(&mut self, tag: &str, content: &str) -> std::result::Result<(), String> {
+        if !is_valid_tag(tag) {
+            return Err(format!("invalid language tag: {tag}"));
+        }
+        let templates: HashMap<String, String> = serde_yaml::from_str(content)
+            .map_err(|e| format!("expected a map of templates: {e}"))?;
+        for (key, template) in &templates {
+            validate_template(template).map_err(|msg| format!("{key}: {msg}"))?;
+        }
+        self.locales.insert(
+            tag.to_ascii_lowercase(),
+            Locale {
+                tag: tag.to_string(),
+                templates,
+            },
+        );
+        Ok(())
+    }
+
+    /// Tag of the locale the client prefers by its `Accept-Language`
+    /// header, or of the default locale if it accepts none of ours.
+    ///
+    /// Ranges are tried by descending quality. Each matches a locale with
+    /// the same tag or one it is a prefix of (`zh` matches `zh-CN`), then
+    /// the same for its own prefixes (`en-US` matches `en`).
+    pub fn negotiate(&self, headers: &HeaderMap) -> &str {
+        let mut ranges: Vec<(&str, f32)> = headers
+            .get_all(ACCEPT_LANGUAGE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(parse_language_range)
+            .filter(|&(_, q)| q > 0.0)
+            .collect();
+        // Stable, so ranges of equal quality keep the client's order
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges
+            .iter()
+            .find_map(|&(range, _)| self.lookup(range))
+            .unwrap_or(&self.default)
+    }
+
+    /// Tag of the locale matching the language range `range`
+    fn lookup(&self, range: &str) -> Option<&str> {
+        if range == "*" {
+            return Some(&self.default);
+        }
+        let range = range.to_ascii_lowercase();
+        let mut prefix = range.as_str();
+        loop {
+            if let Some(tag) = self.supported(prefix).or_else(|| self.extending(prefix)) {
+                return Some(tag);
+            }
+            prefix = prefix.rsplit_once('-')?.0;
+        }
+    }
+
+    /// Tag of the first locale `prefix` (lowercase) is a prefix of
+    fn extending(&self, prefix: &str) -> Option<&str> {
+        self.locales
+            .iter()
+            .filter(|(tag, _)| {
+                tag.strip_prefix(prefix)
+                    .is_some_and(|rest| rest.starts_with('-'))
+            })
+            .map(|(_, locale)| locale.tag.as_str())
+            .min()
+    }
+
+    /// Tag of the locale `tag` (lowercase), if there are translations for it
+    fn supported(&self, tag: &str) -> Option<&str> {
+        match self.locales.get(tag) {
+            Some(locale) => Some(&locale.tag),
+            None => (tag == BUILTIN_LOCALE).then_some(BUILTIN_LOCALE),
+        }
+    }
+
+    fn is_supported(&self, tag: &str) -> bool {
+        self.supported(&tag.to_ascii_lowercase()).is_some()
+    }
+
+    /// The template of `key` in `lang`, falling back to the default
+    /// locale, with `vars` substituted; `None` to use the built-in text.
+    pub fn translate(
+        &self,
+        lang: &str,
+        key: &str,
+        vars: &[(&str, &str)],
+    ) -> Option<Translation<'_>> {
+        let locale = self.locales.get(&lang.to_ascii_lowercase());
+        // The built-in English is complete, so English isn't completed from
+        // another default
+        if locale.is_none() && lang.eq_ignore_ascii_case(BUILTIN_LOCALE) {
+            return None;
+        }
+        let (locale, template) = [locale, self.locales.get(&self.default.to_ascii_lowercase())]
+            .into_iter()
+            .flatten()
+            .find_map(|locale| Some((locale, locale.templates.get(key)?)))?;
+        Some(Translation {
+            lang: &locale.tag,
+            text: substitute(template, vars),
+        })
+    }
+}
+
+/// Language range and quality of one `Accept-Language` element
+fn parse_language_range(element: &str) -> Option<(&str, f32)> {
+    let mut params = element.split(';').map(str::trim);
+    let range = params.next().filter(|r| *r == "*" || is_valid_tag(r))?;
+    let q = params
+        .filter_map(|p| p.strip_prefix("q="))
+        .find_map(|q| q.parse::<f32>().ok())
+        .unwrap_or(1.0)
+        .clamp(0.0, 1.0);
+    Some((range, q))
+}
+
+/// Whether `tag` looks like a language tag: a primary language of 2-8
+/// letters, then subtags of 1-8 letters or digits
+pub fn is_valid_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    (2..=8).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags
+            .all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Check that every `{` of `template` opens a known placeholder
+fn validate_template(template: &str) -> std::result::Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            return Err("unmatched '}'".to_string());
+        }
+        let Some(len) = rest[start..].find('}') else {
+            return Err("unclosed '{'".to_string());
+        };
+        let name = &rest[start + 1..start + len];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format!("unknown placeholder {{{name}}}"));
+        }
+        rest = &rest[start + len + 1..];
+    }
+    Ok(())
+}
+
+/// Replace the `{name}` placeholders of a validated template
+fn substitute(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut text = template.to_string();
+    for (name, value) in vars {
+        text = text.replace(&format!("{{{name}}}"), value);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_default(default: &str) -> Locales {
+        let mut locales = Locales {
+            default: default.to_string(),
+            locales: HashMap::new(),
+        };
+        locales
+            .add(
+                "zh-CN",
+                "devbox_not_found: \"找不到 devbox {unique_id}\"\nstatus.404: 未找到\n",
+            )
+            .unwrap();
+        locales
+            .add("zh-TW", "devbox_not_found: 找不到開發環境\n")
+            .unwrap();
+        locales
+            .add("fr", "overloaded: passerelle surchargée\n")
+            .unwrap();
+        locales
+    }
+
+    fn accept_language(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_LANGUAGE, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let locales = with_default("en");
+        for (value, expected) in [
+            ("", "en"),
+            ("zh-CN", "zh-CN"),
+            ("ZH-cn", "zh-CN"),
+            ("zh-CN,zh;q=0.9,en;q=0.8", "zh-CN"),
+            ("fr-CA", "fr"),
+            // The first of the locales a range is a prefix of
+            ("zh", "zh-CN"),
+            ("zh-HK", "zh-CN"),
+            // By quality, not order
+            ("en;q=0.5, fr", "fr"),
+            ("fr;q=0.1, zh-TW;q=0.9", "zh-TW"),
+            ("en-US,en;q=0.9", "en"),
+            // Unsupported or excluded locales are skipped
+            ("de, ja;q=0.8, fr;q=0.1", "fr"),
+            ("de", "en"),
+            ("fr;q=0", "en"),
+            ("*", "en"),
+            ("garbage!", "en"),
+        ] {
+            assert_eq!(
+                locales.negotiate(&accept_language(value)),
+                expected,
+                "{value}"
+            );
+        }
+        let locales = with_default("zh-CN");
+        assert_eq!(locales.negotiate(&HeaderMap::new()), "zh-CN");
+        assert_eq!(locales.negotiate(&accept_language("de, *;q=0.1")), "zh-CN");
+        assert_eq!(locales.negotiate(&accept_language("en-GB")), "en");
+    }
+
+    #[test]
+    fn test_translate() {
+        let locales = with_default("zh-CN");
+        let vars = [("unique_id", "my-app"), ("status", "404")];
+        assert_eq!(
+            locales.translate("zh-CN", "devbox_not_found", &vars),
+            Some(Translation {
+                lang: "zh-CN",
+                text: "找不到 devbox my-app".to_string()
+            })
+        );
+        assert_eq!(
+            locales
+                .translate("zh-TW", "devbox_not_found", &vars)
+                .unwrap()
+                .text,
+            "找不到開發環境"
+        );
+
+        // Missing keys come from the default locale, then the built-in text
+        let title = locales.translate("zh-TW", "status.404", &vars).unwrap();
+        assert_eq!((title.lang, title.text.as_str()), ("zh-CN", "未找到"));
+        assert_eq!(
+            locales.translate("fr", "overloaded", &[]).unwrap().lang,
+            "fr"
+        );
+        assert_eq!(locales.translate("zh-CN", "overloaded", &[]), None);
+
+        // English is built in, and never completed from another locale
+        assert_eq!(locales.translate("en", "devbox_not_found", &vars), None);
+        assert_eq!(Locales::default().translate("en", "status.404", &[]), None);
+    }
+
+    #[test]
+    fn test_add_invalid() {
+        let mut locales = Locales::default();
+        for (tag, content) in [
+            ("zh_CN", "a: b"),
+            ("x", "a: b"),
+            ("fr", "- not a map"),
+            ("fr", "a: {status"),
+            ("fr", "a: \"status}\""),
+            ("fr", "a: \"{name}\""),
+        ] {
+            assert!(locales.add(tag, content).is_err(), "{tag} {content}");
+        }
+        assert!(locales
+            .add("fr", "a: \"{status} {unique_id} {secs}\"")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_from_config() {
+        let dir = std::env::temp_dir().join(format!("httpgate-locales-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("..data")).unwrap();
+        std::fs::write(dir.join("zh-CN.yaml"), "overloaded: 网关过载\n").unwrap();
+        std::fs::write(dir.join("README.md"), "not a locale").unwrap();
+        let config = |default: &str| Config {
+            locales_dir: Some(dir.to_str().unwrap().to_string()),
+            default_locale: default.to_string(),
+            ..Default::default()
+        };
+
+        let locales = Locales::from_config(&config("zh-CN")).unwrap();
+        assert_eq!(
+            locales.translate("de", "overloaded", &[]).unwrap().text,
+            "网关过载"
+        );
+        assert!(Locales::from_config(&config("en")).is_ok());
+        assert!(Locales::from_config(&config("fr")).is_err());
+
+        // A malformed template refuses the whole directory
+        std::fs::write(dir.join("fr.yaml"), "overloaded: \"{oops}\"\n").unwrap();
+        assert!(Locales::from_config(&config("en")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,16 +1,49 @@
-use std::{sync::Arc, time::Duration};
+use std::{path::Path, sync::Arc, time::Duration};
 
 use pingora_core::{
-    apps::HttpServerOptions,
-    server::{configuration::Opt, Server},
+    apps::{
+        http_app::HttpServer, prometheus_http_app::PrometheusHttpApp, HttpServerOptions, ServerApp,
+    },
+    listeners::tls::TlsSettings,
+    server::{configuration::ServerConf, Server},
+    services::{listening::Service, Service as PingoraService},
 };
-use tracing::{error, info};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
 
 use httpgate::{
-    config::Config,
-    proxy::DevboxProxy,
+    acme::{AcmeManager, Challenges},
+    activity::{ActivityReporter, ActivityTracker, ApiActivityPatcher},
+    admin::AdminApp,
+    blocklist::Blocklist,
+    cache::ResponseCache,
+    client_cert::{ClientCaStore, ClientCerts},
+    config::{ActivityReporting, Config, ImportedEntries, ListenerConfig},
+    diagnostics::{KubeAuthMode, StartupDiagnostics},
+    downtime::DowntimeTracker,
+    events::{ApiEventPublisher, EventRecorder},
+    gc::{ApiPodLiveness, PodIpSweeper},
+    grpc_health::GrpcHealthApp,
+    idle::{IdleNotifier, WebhookIdleHook},
+    limits::{ClientLimiter, InflightLimiter, NamespaceLimit, NamespaceLimiter},
+    locale::Locales,
+    mirror::Mirror,
+    passthrough::PassthroughApp,
+    preview::PreviewSigner,
+    privileges::{self, BoundListeners, Prebound},
+    proxy::{DevboxProxy, HostParser},
+    proxy_protocol::{ProxiedClients, ProxyProtocolApp},
     registry::DevboxRegistry,
-    watcher::{DevboxWatcher, PodWatcher},
+    snapshot,
+    sni::SniCerts,
+    tarpit::Tarpit,
+    tls,
+    tls_reload::{CertStore, TlsReloadApp},
+    warmup::{HotSet, TcpWarmupConnector, Warmer},
+    watcher::{
+        self, ClientCaWatcher, LimitsWatcher, ResyncSignal, TlsSecretWatcher, WatcherSupervisor,
+    },
 };
 
 fn init_logging(log_level: &str) {
@@ -23,6 +56,79 @@ fn init_logging(log_level: &str) {
         .init();
 }
 
+/// Add a service to the server, serving on the socket bound for `addr`
+/// at startup if there is one.
+fn add_bound_service<S>(server: &mut Server, bound: &mut BoundListeners, addr: &str, service: S)
+where
+    S: PingoraService + 'static,
+{
+    match bound.take(addr) {
+        Some(fd) => server.add_service(Prebound::new(service, addr, fd)),
+        None => server.add_service(service),
+    }
+}
+
+/// Bind a proxy service to its listener address and add it to the server.
+fn add_listener_service<A>(
+    server: &mut Server,
+    bound: &mut BoundListeners,
+    mut service: Service<A>,
+    listener: &ListenerConfig,
+) where
+    A: ServerApp + Send + Sync + 'static,
+{
+    let listen_addr = listener.listen_addr.to_string();
+    match &listener.tls {
+        Some(tls) => {
+            // Offer h2 alongside http/1.1 so browsers can multiplex over TLS
+            let mut settings = match TlsSettings::intermediate(&tls.cert_path, &tls.key_path) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    error!(
+                        listener = %listener.policy.name,
+                        error = %e,
+                        "Failed to load listener TLS certificate"
+                    );
+                    std::process::exit(1);
+                }
+            };
+            settings.enable_h2();
+            service.add_tls_with_settings(&listen_addr, None, settings);
+        }
+        None => service.add_tcp(&listen_addr),
+    }
+
+    add_bound_service(server, bound, &listen_addr, service);
+}
+
+/// Bind a metrics or admin service to `addr` and add it to the server,
+/// reading a PROXY protocol header first if `proxy_protocol`.
+fn add_service<A>(
+    server: &mut Server,
+    bound: &mut BoundListeners,
+    name: &str,
+    app: A,
+    addr: &str,
+    proxy_protocol: bool,
+) where
+    A: ServerApp + Send + Sync + 'static,
+{
+    if proxy_protocol {
+        let app = ProxyProtocolApp::new(app, Arc::new(ProxiedClients::new()));
+        let mut service = Service::new(name.to_string(), app);
+        service.add_tcp(addr);
+        add_bound_service(server, bound, addr, service);
+    } else {
+        let mut service = Service::new(name.to_string(), app);
+        service.add_tcp(addr);
+        add_bound_service(server, bound, addr, service);
+    }
+}
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
 fn main() {
     // Load configuration
     let config = Config::from_env();
@@ -30,61 +136,545 @@ fn main() {
     // Initialize logging
     init_logging(&config.log_level);
 
-    info!(listen_addr = %config.listen_addr, "Starting httpgate");
+    let diagnostics = StartupDiagnostics::new(&config, KubeAuthMode::from_env);
+    info!(
+        event = "startup",
+        version = diagnostics.version,
+        commit = diagnostics.commit,
+        kube_auth = %serde_json::to_string(&diagnostics.kube_auth).unwrap_or_default(),
+        features = %serde_json::to_string(&diagnostics.features).unwrap_or_default(),
+        config = %diagnostics.config,
+        "Starting httpgate"
+    );
 
     // Create shared registry
-    let registry = Arc::new(DevboxRegistry::new());
+    let registry = Arc::new(
+        DevboxRegistry::new()
+            .with_loopback_backends(config.allow_loopback_backends)
+            .with_max_entries(config.max_registry_entries)
+            .with_respect_imported(config.imported_entries == ImportedEntries::Respect),
+    );
+
+    // Restore the entries and watch bookmarks saved before the restart, so
+    // the watchers resume rather than list every Devbox and Pod again
+    if let Some(path) = &config.registry_snapshot_file {
+        match snapshot::load_file(path) {
+            Ok(Some(export)) => {
+                let report = snapshot::restore(&registry, &export);
+                for e in &report.errors {
+                    warn!(entry = %e.entry, error = %e.error, "Skipped registry snapshot entry");
+                }
+            }
+            Ok(None) => info!(path = %path, "No registry snapshot yet, listing everything"),
+            Err(e) => warn!(error = %e, "Failed to load registry snapshot, listing everything"),
+        }
+    }
 
-    // Create Pingora server
-    let opt = Opt::default();
-    let mut server = Server::new(Some(opt)).unwrap();
+    // Load the backend CA bundle once at startup; Pingora's connectors use it
+    // to verify TLS backends instead of the system roots
+    let mut server_conf = ServerConf::default();
+    if let Some(ca_file) = &config.upstream_ca_file {
+        match tls::load_ca_bundle(ca_file) {
+            Ok(count) => info!(path = %ca_file, certificates = count, "Loaded upstream CA bundle"),
+            Err(e) => {
+                error!(error = %e, "Failed to load upstream CA bundle");
+                std::process::exit(1);
+            }
+        }
+        server_conf.ca_file = Some(ca_file.clone());
+    }
+
+    // Load the blocklist; a broken file at startup is a configuration error
+    let blocklist = Arc::new(Blocklist::from_config(&config));
+    if let Err(e) = blocklist.reload() {
+        error!(error = %e, "Failed to load blocklist");
+        std::process::exit(1);
+    }
+
+    // Load the translations of gateway pages, refusing malformed ones
+    let locales = match Locales::from_config(&config) {
+        Ok(locales) => Arc::new(locales),
+        Err(e) => {
+            error!(error = %e, "Failed to load locales");
+            std::process::exit(1);
+        }
+    };
+
+    // Create Pingora server, tuned by the SERVER_* settings
+    config.server.apply(&mut server_conf);
+    let opt = config.server.opt();
+    let mut server = Server::new_with_opt_and_conf(Some(opt), server_conf);
     server.bootstrap();
 
-    // Create and configure proxy service
-    let proxy = DevboxProxy::new(Arc::clone(&registry));
-    let mut proxy_service = pingora_proxy::http_proxy_service(&server.configuration, proxy);
-    // Enable h2c (HTTP/2 over cleartext) to support gRPC
-    if let Some(app) = proxy_service.app_logic_mut() {
+    // Bind every listener while still privileged (or adopt the sockets passed
+    // by systemd), then switch to RUN_AS_USER/RUN_AS_GROUP before serving
+    let mut bound = if config.privileges.prebind() {
+        match privileges::bind_and_drop(&config) {
+            Ok(bound) => bound,
+            Err(e) => {
+                error!(error = %e, "Failed to bind listeners and drop privileges");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        BoundListeners::default()
+    };
+
+    // Create and configure one proxy service per listener, sharing the registry
+    // the global and per-client in-flight limits, the namespace limits, the
+    // blocklist, the activity and downtime trackers and the response cache
+    let shared_config = Arc::new(config.clone());
+    let inflight = Arc::new(InflightLimiter::new(config.max_global_inflight));
+    let client_limits = Arc::new(ClientLimiter::new(config.max_per_client_inflight));
+    let namespace_limits = Arc::new(NamespaceLimiter::new(NamespaceLimit::from_config(&config)));
+    // Requests to devboxes reported idle are handed to the idle notifier
+    let (idle_hook, idle_wakes) = match &config.idle_webhook_url {
+        Some(url) => match WebhookIdleHook::new(url) {
+            Ok(hook) => {
+                let (tx, rx) = mpsc::unbounded_channel();
+                (Some((hook, rx)), Some(tx))
+            }
+            Err(e) => {
+                error!(error = %e, "Failed to set up idle webhook");
+                std::process::exit(1);
+            }
+        },
+        None => (None, None),
+    };
+    let mut activity = ActivityTracker::new();
+    if let Some(wakes) = idle_wakes {
+        activity = activity.with_wakes(wakes);
+    }
+    let activity = Arc::new(activity);
+    let downtime = Arc::new(DowntimeTracker::new(
+        config.not_running_retry_after_max.as_secs(),
+    ));
+    // Client certificates of `client_cert` listeners, checked against the
+    // CA bundles of the devboxes requiring one
+    let client_certs = Arc::new(ClientCerts::new());
+    let client_cas = Arc::new(ClientCaStore::new());
+    // Cached responses of a devbox are purged once it is deleted or its Pod
+    // endpoint changes
+    let cache = ResponseCache::from_config(&config).map(Arc::new);
+    if let Some(cache) = &cache {
+        registry.add_observer(Arc::clone(cache) as _);
+        info!(
+            max_bytes = config.cache_max_bytes,
+            max_object_bytes = config.cache_max_object_bytes,
+            "Response cache enabled"
+        );
+    }
+    // Repeated routing anomalies are published as Events on the Devboxes
+    let (events, event_queue) = if config.k8s_events {
+        let (recorder, queue) = EventRecorder::new(config.k8s_events_interval);
+        (Some(Arc::new(recorder)), Some(queue))
+    } else {
+        (None, None)
+    };
+    let mirror = Arc::new(Mirror::from_config(&config));
+    let tarpit = Arc::new(Tarpit::from_config(&config));
+    // Certificate of `tls_secret` listeners, loaded by the TLS Secret watcher
+    let certs = Arc::new(CertStore::new());
+    // Certificate of `acme` listeners, ordered once their challenges can be
+    // answered by every listener
+    let acme = config.listeners.iter().any(|l| l.acme).then(|| {
+        let challenges = Arc::new(Challenges::new());
+        let acme_certs = Arc::new(CertStore::new());
+        let manager =
+            AcmeManager::from_config(&config, Arc::clone(&challenges), Arc::clone(&acme_certs));
+        (challenges, acme_certs, manager)
+    });
+    for listener in &config.listeners {
+        // Each PROXY protocol listener keeps the clients of its own
+        // connections, so peers of other listeners can't match them
+        let proxied_clients = listener
+            .proxy_protocol
+            .then(|| Arc::new(ProxiedClients::new()));
+        let proxy = DevboxProxy::with_listener(
+            Arc::clone(&registry),
+            Arc::clone(&shared_config),
+            listener.policy.clone(),
+        )
+        .with_inflight_limiter(Arc::clone(&inflight))
+        .with_client_limiter(Arc::clone(&client_limits))
+        .with_namespace_limiter(Arc::clone(&namespace_limits))
+        .with_blocklist(Arc::clone(&blocklist))
+        .with_activity_tracker(Arc::clone(&activity))
+        .with_downtime_tracker(Arc::clone(&downtime))
+        .with_client_certs(Arc::clone(&client_certs))
+        .with_client_cas(Arc::clone(&client_cas))
+        .with_mirror(Arc::clone(&mirror))
+        .with_tarpit(Arc::clone(&tarpit))
+        .with_locales(Arc::clone(&locales));
+        let proxy = match &cache {
+            Some(cache) => proxy.with_response_cache(Arc::clone(cache)),
+            None => proxy,
+        };
+        let proxy = match &events {
+            Some(events) => proxy.with_event_recorder(Arc::clone(events)),
+            None => proxy,
+        };
+        let proxy = match &proxied_clients {
+            Some(clients) => proxy.with_proxied_clients(Arc::clone(clients)),
+            None => proxy,
+        };
+        let proxy = match &acme {
+            Some((challenges, _, _)) => proxy.with_acme_challenges(Arc::clone(challenges)),
+            None => proxy,
+        };
+        let mut proxy_app = pingora_proxy::http_proxy(&server.configuration, proxy);
+        // Enable h2c (HTTP/2 over cleartext) to support gRPC
         let mut opts = HttpServerOptions::default();
         opts.h2c = true;
-        app.server_options = Some(opts);
+        proxy_app.server_options = Some(opts);
+
+        let name = format!("httpgate-{}", listener.policy.name);
+        let request_client_cert = |app: TlsReloadApp<_>| {
+            if listener.client_cert {
+                app.with_client_certs(Arc::clone(&client_certs))
+            } else {
+                app
+            }
+        };
+        if listener.tls_secret {
+            let app = request_client_cert(TlsReloadApp::new(proxy_app, &certs));
+            add_listener_service(&mut server, &mut bound, Service::new(name, app), listener);
+        } else if let Some((_, acme_certs, _)) = acme.as_ref().filter(|_| listener.acme) {
+            let app = request_client_cert(TlsReloadApp::new(proxy_app, acme_certs));
+            add_listener_service(&mut server, &mut bound, Service::new(name, app), listener);
+        } else if let Some(dir) = &listener.tls_cert_dir {
+            let sni_certs = match SniCerts::load_dir(Path::new(dir)) {
+                Ok(sni_certs) => sni_certs,
+                Err(e) => {
+                    error!(
+                        listener = %listener.policy.name,
+                        error = %e,
+                        "Failed to load listener TLS certificates"
+                    );
+                    std::process::exit(1);
+                }
+            };
+            let app =
+                request_client_cert(TlsReloadApp::with_resolver(proxy_app, Arc::new(sni_certs)));
+            add_listener_service(&mut server, &mut bound, Service::new(name, app), listener);
+        } else if let Some(clients) = proxied_clients {
+            let app = ProxyProtocolApp::new(proxy_app, clients);
+            add_listener_service(&mut server, &mut bound, Service::new(name, app), listener);
+        } else {
+            add_listener_service(
+                &mut server,
+                &mut bound,
+                Service::new(name, proxy_app),
+                listener,
+            );
+        }
+    }
+
+    // Expose Prometheus metrics
+    if let Some(metrics_addr) = config.metrics_addr {
+        let proxy_protocol = config.service_proxy_protocol.metrics;
+        add_service(
+            &mut server,
+            &mut bound,
+            "Prometheus metric HTTP",
+            HttpServer::new_app(PrometheusHttpApp),
+            &metrics_addr.to_string(),
+            proxy_protocol,
+        );
+        info!(
+            metrics_addr = %metrics_addr,
+            proxy_protocol = proxy_protocol,
+            "Metrics endpoint enabled"
+        );
+    }
+
+    // Relay TLS connections to non-HTTP devbox ports by SNI
+    if let Some(passthrough_addr) = config.tcp_passthrough_addr {
+        let app = PassthroughApp::new(
+            Arc::clone(&registry),
+            Arc::clone(&blocklist),
+            config.tcp_passthrough_domains.clone(),
+        )
+        .with_host_parser(HostParser::from_config(&config));
+        let mut passthrough_service = app.into_service();
+        let passthrough_addr = passthrough_addr.to_string();
+        passthrough_service.add_tcp(&passthrough_addr);
+        add_bound_service(
+            &mut server,
+            &mut bound,
+            &passthrough_addr,
+            passthrough_service,
+        );
+        info!(passthrough_addr = %passthrough_addr, "TCP passthrough enabled");
     }
-    proxy_service.add_tcp(&config.listen_addr.to_string());
 
-    server.add_service(proxy_service);
+    // Raised from the admin API to relist every watch
+    let resync = ResyncSignal::default();
 
-    // Spawn Kubernetes watchers in background
-    let runtime = tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
+    // Expose the admin API
+    if let Some(admin_addr) = config.admin_addr {
+        let mut admin = AdminApp::new(Arc::clone(&registry), Arc::clone(&blocklist))
+            .with_activity_tracker(Arc::clone(&activity))
+            .with_config(Arc::clone(&shared_config))
+            .with_resync(resync.clone());
+        if let Some(key) = config.signing_key.as_deref() {
+            admin = admin.with_preview_signer(PreviewSigner::new(key));
+        }
+        if let Some(cache) = &cache {
+            admin = admin.with_response_cache(Arc::clone(cache));
+        }
+        if config.debug_endpoints {
+            #[cfg(feature = "debug-endpoints")]
+            match config.admin_token.clone() {
+                Some(token) => {
+                    admin = admin.with_debug_endpoints(token);
+                    info!("Debug endpoints enabled on the admin API");
+                }
+                None => {
+                    error!("DEBUG_ENDPOINTS requires ADMIN_TOKEN");
+                    std::process::exit(1);
+                }
+            }
+            #[cfg(not(feature = "debug-endpoints"))]
+            warn!("DEBUG_ENDPOINTS is ignored: built without the debug-endpoints feature");
+        }
+        let proxy_protocol = config.service_proxy_protocol.admin;
+        add_service(
+            &mut server,
+            &mut bound,
+            "httpgate-admin",
+            HttpServer::new_app(admin),
+            &admin_addr.to_string(),
+            proxy_protocol,
+        );
+        info!(
+            admin_addr = %admin_addr,
+            proxy_protocol = proxy_protocol,
+            "Admin API enabled"
+        );
+    }
+
+    // Serve gRPC health checks for service meshes
+    if let Some(grpc_health_addr) = config.grpc_health_addr {
+        let mut grpc_health_service = GrpcHealthApp::new(Arc::clone(&registry)).into_service();
+        let grpc_health_addr = grpc_health_addr.to_string();
+        grpc_health_service.add_tcp(&grpc_health_addr);
+        add_bound_service(
+            &mut server,
+            &mut bound,
+            &grpc_health_addr,
+            grpc_health_service,
+        );
+        info!(grpc_health_addr = %grpc_health_addr, "gRPC health service enabled");
+    }
+
+    // Spawn Kubernetes watchers in background, on a runtime driven by a
+    // thread of its own (a current-thread runtime only runs while blocked on)
+    let watcher_runtime = config
+        .watcher_runtime
+        .builder()
         .build()
         .expect("Failed to create Tokio runtime");
+    let runtime = watcher_runtime.handle().clone();
+    std::thread::Builder::new()
+        .name("httpgate-watchers".to_string())
+        .spawn(move || watcher_runtime.block_on(std::future::pending::<()>()))
+        .expect("Failed to start the watcher runtime");
+
+    // Spawn the Devbox and Pod watchers of every cluster
+    let clusters: Vec<&str> = config.clusters.iter().map(|c| c.name.as_str()).collect();
+    info!(clusters = ?clusters, "Watching clusters");
+    let mut supervisor = WatcherSupervisor::new(Arc::clone(&registry), config.clusters.clone())
+        .with_devbox_watch_mode(config.devbox_watch_mode)
+        .with_resync(resync);
+    if let Some(path) = config.pod_ip_status_field.clone() {
+        info!(field = %path, "Reading pod IPs from the Devbox status instead of watching Pods");
+        supervisor = supervisor.with_pod_ip_status_field(path);
+    }
+    if config.require_pod_ready {
+        if config.pod_ip_status_field.is_some() {
+            warn!("REQUIRE_POD_READY has no effect with POD_IP_STATUS_FIELD");
+        } else {
+            info!("Routing to Pods only once they are ready");
+        }
+        supervisor = supervisor.with_require_pod_ready(true);
+    }
+    if !config.watch_namespaces.is_empty() {
+        info!(namespaces = ?config.watch_namespaces, "Watching only these namespaces");
+        supervisor = supervisor.with_namespaces(config.watch_namespaces.clone());
+    }
+    runtime.spawn(supervisor.run());
+    if let Some(path) = config.registry_snapshot_file.clone() {
+        runtime.spawn(snapshot::persist(Arc::clone(&registry), path));
+    }
 
-    // Spawn independent watchers - they operate on separate indices
-    let devbox_watcher_registry = Arc::clone(&registry);
-    let pod_watcher_registry = Arc::clone(&registry);
+    // Spawn namespace limits watcher
+    if let Some((namespace, name)) = config
+        .limits_configmap
+        .as_deref()
+        .and_then(|s| s.split_once('/'))
+    {
+        let limits_watcher =
+            LimitsWatcher::new(namespace_limits, namespace.to_string(), name.to_string());
+        runtime.spawn(async move {
+            loop {
+                if let Err(e) = limits_watcher.run().await {
+                    error!(error = %e, "Namespace limits watcher failed, restarting in 5s");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        });
+    }
 
-    // Spawn Devbox watcher
-    runtime.spawn(async move {
-        let devbox_watcher = DevboxWatcher::new(devbox_watcher_registry);
-        loop {
-            if let Err(e) = devbox_watcher.run().await {
-                error!(error = %e, "Devbox watcher failed, restarting in 5s");
-                tokio::time::sleep(Duration::from_secs(5)).await;
+    // Spawn TLS Secret watcher
+    if let Some((namespace, name)) = config
+        .tls_secret_ref
+        .as_deref()
+        .and_then(|s| s.split_once('/'))
+    {
+        let secret_watcher = TlsSecretWatcher::new(certs, namespace.to_string(), name.to_string());
+        runtime.spawn(async move {
+            loop {
+                if let Err(e) = secret_watcher.run().await {
+                    error!(error = %e, "TLS Secret watcher failed, restarting in 5s");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
             }
-        }
-    });
+        });
+    }
 
-    // Spawn Pod watcher
+    // Spawn client CA watcher, for devboxes reached through `client_cert`
+    // listeners
+    if config.listeners.iter().any(|l| l.client_cert) {
+        let ca_watcher = ClientCaWatcher::new(client_cas, &config.watch_namespaces);
+        runtime.spawn(async move {
+            loop {
+                if let Err(e) = ca_watcher.run().await {
+                    error!(error = %e, "Client CA watcher failed, restarting in 5s");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        });
+    }
+
+    // Order the certificate of ACME listeners and keep it renewed
+    if let Some((_, _, manager)) = acme {
+        info!(
+            directory = %config.acme_directory_url,
+            domains = ?AcmeManager::domains(&config),
+            "ACME certificates enabled"
+        );
+        runtime.spawn(manager.run());
+    }
+
+    // Reload the blocklist file on SIGHUP
+    let reload_blocklist = Arc::clone(&blocklist);
     runtime.spawn(async move {
-        let pod_watcher = PodWatcher::new(pod_watcher_registry);
-        loop {
-            if let Err(e) = pod_watcher.run().await {
-                error!(error = %e, "Pod watcher failed, restarting in 5s");
-                tokio::time::sleep(Duration::from_secs(5)).await;
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                error!(error = %e, "Failed to install SIGHUP handler");
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading blocklist");
+            if let Err(e) = reload_blocklist.reload() {
+                error!(error = %e, "Blocklist reload failed, keeping previous entries");
             }
         }
     });
 
+    // Spawn pod IP consistency sweep to clean up after missed delete events
+    if let Some(interval) = config.pod_ip_gc_interval {
+        let sweeper_registry = Arc::clone(&registry);
+        let verify_ttl = config.pod_ip_verify_ttl;
+        let clusters = config.clusters.clone();
+        runtime.spawn(async move {
+            let mut sweeper = PodIpSweeper::new(sweeper_registry);
+            if let Some(ttl) = verify_ttl {
+                match watcher::create_cluster_clients(&clusters).await {
+                    Ok(clients) => {
+                        sweeper =
+                            sweeper.with_verification(Box::new(ApiPodLiveness::new(clients)), ttl);
+                    }
+                    Err(e) => {
+                        error!(error = %e, "Failed to create client, pod liveness verification disabled");
+                    }
+                }
+            }
+            sweeper.run(interval).await;
+        });
+    }
+
+    // Connect to the backends that were hot before the restart, then keep
+    // the hot set up to date for the next one
+    if let Some(path) = config.warmup_state_file.clone().filter(|_| config.warmup) {
+        let hot = match HotSet::load(&path, config.warmup_backends) {
+            Ok(hot) => hot,
+            Err(e) => {
+                warn!(error = %e, "Failed to load warm-up state, starting cold");
+                HotSet::new(config.warmup_backends)
+            }
+        };
+        let warmer = Warmer::new(
+            Arc::clone(&registry),
+            Arc::clone(&blocklist),
+            Box::new(TcpWarmupConnector),
+            config.warmup_concurrency,
+            config.warmup_timeout,
+        );
+        let tracker = Arc::clone(&activity);
+        runtime.spawn(async move {
+            warmer.run(&hot).await;
+            hot.record(tracker, path).await;
+        });
+    }
+
+    // Tell the control plane when devboxes go idle and wake up again
+    if let Some((hook, wakes)) = idle_hook {
+        let notifier = IdleNotifier::new(
+            Arc::clone(&activity),
+            Arc::clone(&registry),
+            Box::new(hook),
+            config.idle_timeout,
+            wakes,
+        );
+        runtime.spawn(notifier.run());
+    }
+
+    // Push devbox activity to the Devbox resources for auto-hibernation
+    if config.activity_reporting == ActivityReporting::Crd {
+        let reporter_registry = Arc::clone(&registry);
+        let interval = config.activity_report_interval;
+        let clusters = config.clusters.clone();
+        runtime.spawn(async move {
+            match watcher::create_cluster_clients(&clusters).await {
+                Ok(clients) => {
+                    let patcher = Box::new(ApiActivityPatcher::new(clients));
+                    ActivityReporter::new(activity, reporter_registry, patcher, interval)
+                        .run()
+                        .await;
+                }
+                Err(e) => {
+                    error!(error = %e, "Failed to create client, activity reporting disabled");
+                }
+            }
+        });
+    }
+
+    if let Some(queue) = event_queue {
+        let clusters = config.clusters.clone();
+        runtime.spawn(async move {
+            match watcher::create_cluster_clients(&clusters).await {
+                Ok(clients) => queue.run(Box::new(ApiEventPublisher::new(clients))).await,
+                Err(e) => {
+                    error!(error = %e, "Failed to create client, devbox events disabled");
+                }
+            }
+        });
+    }
+
     info!("Proxy server starting");
 
     // Run server (blocking)
@@ -0,0 +1,433 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    exponential_buckets, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+};
+
+/// Requests handled by the proxy, by listener, response status and downstream HTTP version
+pub static REQUESTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_requests_total",
+        "Requests handled by the proxy",
+        &["listener", "status", "version"]
+    )
+    .unwrap()
+});
+
+/// Access records of successful requests dropped by sampling, by listener
+pub static ACCESS_LOGS_SUPPRESSED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_access_logs_suppressed_total",
+        "Access records of successful requests dropped by sampling",
+        &["listener"]
+    )
+    .unwrap()
+});
+
+/// Requests rejected because the global in-flight limit was reached
+pub static REQUESTS_SHED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_requests_shed_total",
+        "Requests rejected because the global in-flight limit was reached",
+        &["listener"]
+    )
+    .unwrap()
+});
+
+/// Requests for hosts outside a TLS listener's domains, answered with 421, by listener
+pub static MISDIRECTED_REQUESTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_misdirected_requests_total",
+        "Requests for hosts outside a TLS listener's domains",
+        &["listener"]
+    )
+    .unwrap()
+});
+
+/// Requests rejected for their request target, by reason (e.g. "traversal" or "uri_too_long")
+pub static REJECTED_PATHS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_rejected_paths_total",
+        "Requests rejected for their request target",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+/// Requests rejected because their client reached the per-client in-flight limit
+pub static CLIENT_LIMITED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_client_limited_total",
+        "Requests rejected because their client reached the per-client in-flight limit",
+        &["listener"]
+    )
+    .unwrap()
+});
+
+/// Connections accepted by the TCP passthrough service, by result (e.g. "proxied" or "not_tls")
+pub static PASSTHROUGH_CONNECTIONS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_passthrough_connections_total",
+        "Connections accepted by the TCP passthrough service",
+        &["result"]
+    )
+    .unwrap()
+});
+
+/// Bytes relayed by the TCP passthrough service, by direction ("upstream" or "downstream")
+pub static PASSTHROUGH_BYTES_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_passthrough_bytes_total",
+        "Bytes relayed by the TCP passthrough service",
+        &["direction"]
+    )
+    .unwrap()
+});
+
+/// Pod IP entries removed by the consistency sweep, by cluster and reason ("orphaned" or "stale")
+pub static POD_IPS_SWEPT_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_pod_ips_swept_total",
+        "Pod IP entries removed by the consistency sweep",
+        &["cluster", "reason"]
+    )
+    .unwrap()
+});
+
+/// Devbox registrations refused because the registry is full, by cluster
+pub static REGISTRY_REFUSED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_registry_refused_total",
+        "Devbox registrations refused because MAX_REGISTRY_ENTRIES was reached",
+        &["cluster"]
+    )
+    .unwrap()
+});
+
+/// 1 while the registry is refusing registrations
+pub static REGISTRY_FULL: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "httpgate_registry_full",
+        "Whether Devbox registrations are being refused because the registry is full"
+    )
+    .unwrap()
+});
+
+/// Devbox registrations ignored because another Devbox holds their uniqueID, by cluster
+pub static DEVBOX_CONFLICTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_devbox_conflicts_total",
+        "Devbox registrations ignored because another Devbox holds their uniqueID",
+        &["cluster"]
+    )
+    .unwrap()
+});
+
+/// Watch errors, by cluster and watch ("devboxes" or "pods")
+pub static WATCH_ERRORS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_watch_errors_total",
+        "Watch errors",
+        &["cluster", "watch"]
+    )
+    .unwrap()
+});
+
+/// Watcher restarts after failures, by cluster and watch ("devboxes" or "pods")
+pub static WATCHER_RESTARTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_watcher_restarts_total",
+        "Watcher restarts after failures",
+        &["cluster", "watch"]
+    )
+    .unwrap()
+});
+
+/// Requests rejected by the blocklist, by matching entry (e.g. "namespace:ns-spam")
+pub static BLOCKED_REQUESTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_blocked_requests_total",
+        "Requests rejected by the blocklist",
+        &["entry"]
+    )
+    .unwrap()
+});
+
+/// Requests answered by the tarpit, by matching heuristic ("client", "path" or "scanner")
+pub static TARPITTED_REQUESTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_tarpitted_requests_total",
+        "Requests answered by the tarpit",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+/// Requests held in the tarpit
+pub static TARPIT_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("httpgate_tarpit_connections", "Requests held in the tarpit").unwrap()
+});
+
+/// Requests matching the tarpit's heuristics answered with an instant 403
+/// because the tarpit was full
+pub static TARPIT_OVERFLOW_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "httpgate_tarpit_overflow_total",
+        "Requests turned away because the tarpit was full"
+    )
+    .unwrap()
+});
+
+/// Warm-up connections to hot backends after startup, by result ("established" or "failed")
+pub static WARMUP_CONNECTIONS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_warmup_connections_total",
+        "Warm-up connections to hot backends after startup",
+        &["result"]
+    )
+    .unwrap()
+});
+
+/// Requests rejected by a namespace limit, by namespace and limit ("concurrency" or "rate")
+pub static NAMESPACE_LIMITED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_namespace_limited_total",
+        "Requests rejected by a namespace limit",
+        &["namespace", "limit"]
+    )
+    .unwrap()
+});
+
+/// Response cache lookups, by result ("hit" or "miss")
+pub static CACHE_LOOKUPS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_cache_lookups_total",
+        "Response cache lookups",
+        &["result"]
+    )
+    .unwrap()
+});
+
+/// Response body bytes served from the cache
+pub static CACHE_HIT_BYTES_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "httpgate_cache_hit_bytes_total",
+        "Response body bytes served from the cache"
+    )
+    .unwrap()
+});
+
+/// Memory held by cached responses
+pub static CACHE_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("httpgate_cache_bytes", "Memory held by cached responses").unwrap()
+});
+
+/// Cached responses removed, by reason ("evicted", "expired" or "purged")
+pub static CACHE_REMOVALS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_cache_removals_total",
+        "Cached responses removed",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+/// Requests copied to a devbox's mirror port, by result ("2xx" to "5xx", or "error")
+pub static MIRROR_REQUESTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_mirror_requests_total",
+        "Requests copied to a devbox's mirror port",
+        &["result"]
+    )
+    .unwrap()
+});
+
+/// Sampled requests not mirrored, by reason ("body_too_large" or "disabled")
+pub static MIRROR_SKIPPED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_mirror_skipped_total",
+        "Sampled requests not mirrored",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+/// Expiry of the certificate served from `TLS_SECRET_REF`, as a Unix timestamp
+pub static TLS_CERT_NOT_AFTER_SECONDS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "httpgate_tls_cert_not_after_seconds",
+        "Expiry of the certificate served from TLS_SECRET_REF, as a Unix timestamp"
+    )
+    .unwrap()
+});
+
+/// Requests carrying an `X-HG-Route` override, by outcome ("accepted",
+/// "unsigned", "malformed", "bad_signature", "expired" or "refused")
+pub static ROUTE_OVERRIDES_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_route_overrides_total",
+        "Requests carrying an X-HG-Route override",
+        &["result"]
+    )
+    .unwrap()
+});
+
+/// Requests to legacy hosts, by pattern and outcome ("redirected", or "loop"
+/// if the rewritten host was legacy too)
+pub static LEGACY_HOST_REDIRECTS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_legacy_host_redirects_total",
+        "Requests to legacy hosts",
+        &["pattern", "result"]
+    )
+    .unwrap()
+});
+
+/// Devbox responses cut off for exceeding `MAX_RESPONSE_BODY_BYTES`, by
+/// devbox and when ("headers" if the declared length was over, else "body")
+pub static RESPONSES_TOO_LARGE_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_responses_too_large_total",
+        "Devbox responses cut off for exceeding MAX_RESPONSE_BODY_BYTES",
+        &["namespace", "unique_id", "phase"]
+    )
+    .unwrap()
+});
+
+/// ACME certificate orders, by outcome ("issued" or "failed")
+pub static ACME_ORDERS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_acme_orders_total",
+        "ACME certificate orders",
+        &["result"]
+    )
+    .unwrap()
+});
+
+/// Devbox failures after the connection was established, by outcome:
+/// "recovered" or "failed" after a GET or HEAD was retried against the
+/// replacement Pod, "no_candidate" if there was none to retry against, or
+/// "truncated" if the response had already started and was cut short
+pub static UPSTREAM_FAILOVERS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_upstream_failovers_total",
+        "Devbox failures after the connection was established",
+        &["result"]
+    )
+    .unwrap()
+});
+
+/// Buckets of the header size histograms, from 256 bytes to 64 KiB
+fn header_size_buckets() -> Vec<f64> {
+    exponential_buckets(256.0, 2.0, 9).unwrap()
+}
+
+/// Header bytes of requests (names and values), by listener
+pub static REQUEST_HEADER_BYTES: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "httpgate_request_header_bytes",
+        "Header bytes of requests (names and values)",
+        &["listener"],
+        header_size_buckets()
+    )
+    .unwrap()
+});
+
+/// Header bytes of devbox responses as sent to the client (names and
+/// values), by listener
+pub static RESPONSE_HEADER_BYTES: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "httpgate_response_header_bytes",
+        "Header bytes of devbox responses as sent to the client (names and values)",
+        &["listener"],
+        header_size_buckets()
+    )
+    .unwrap()
+});
+
+/// Idle and wake notifications sent to `IDLE_WEBHOOK_URL`, by event and
+/// result ("ok" or "failed")
+pub static IDLE_NOTIFICATIONS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_idle_notifications_total",
+        "Idle and wake notifications sent to the control plane",
+        &["event", "result"]
+    )
+    .unwrap()
+});
+
+/// WebSocket requests rejected for an origin not in the devbox's
+/// `ws-allowed-origins` annotation, by namespace
+pub static WS_ORIGINS_REJECTED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_ws_origins_rejected_total",
+        "WebSocket requests rejected for their origin",
+        &["namespace"]
+    )
+    .unwrap()
+});
+
+/// Requests rejected for a client address outside the devbox's
+/// `allowed-cidrs` annotation, by namespace
+pub static CLIENTS_NOT_ALLOWED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_clients_not_allowed_total",
+        "Requests rejected for a client address outside the devbox's allowed ranges",
+        &["namespace"]
+    )
+    .unwrap()
+});
+
+/// Requests rejected for a missing or invalid client certificate on a
+/// devbox with a `client-ca` annotation, by namespace and reason
+pub static CLIENT_CERTS_REJECTED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_client_certs_rejected_total",
+        "Requests rejected for a missing or invalid client certificate",
+        &["namespace", "reason"]
+    )
+    .unwrap()
+});
+
+/// Requests not sent because the devbox was unregistered or moved to another
+/// endpoint between routing and connecting
+pub static ENDPOINTS_CHANGED_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "httpgate_endpoints_changed_total",
+        "Requests not sent because the devbox's endpoint changed before connecting"
+    )
+    .unwrap()
+});
+
+/// Upgraded connections closed after `WEBSOCKET_IDLE_TIMEOUT` without
+/// data, by namespace
+pub static WEBSOCKETS_IDLE_CLOSED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_websockets_idle_closed_total",
+        "Upgraded connections closed for carrying no data for too long",
+        &["namespace"]
+    )
+    .unwrap()
+});
+
+/// Requests for unknown devboxes answered with a 503 rather than a 404,
+/// as a watcher was (re)listing or had just done so
+pub static NOT_FOUND_WHILE_SYNCING_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "httpgate_not_found_while_syncing_total",
+        "Requests for unknown devboxes answered with a 503 while the registry synced"
+    )
+    .unwrap()
+});
+
+/// Requests with a protocol anomaly, by anomaly (e.g. "trace" or
+/// "conflicting_framing") and whether they were rejected for it
+pub static PROTOCOL_ANOMALIES_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "httpgate_protocol_anomalies_total",
+        "Requests with a protocol anomaly",
+        &["anomaly", "rejected"]
+    )
+    .unwrap()
+});
@@ -0,0 +1,554 @@
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING, UPGRADE};
+use http::{HeaderMap, Method};
+use pingora_http::RequestHeader;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::headers;
+use crate::metrics;
+use crate::policy::MirrorPolicy;
+
+/// Header telling the mirror backend which port the request was copied from
+pub const X_MIRRORED_FROM: &str = "x-mirrored-from";
+
+/// Mirrored requests of a devbox its error rate is judged over
+const WINDOW: u32 = 50;
+
+/// Error rate (percent) at which mirroring of a devbox is suspended
+const MAX_ERROR_PERCENT: u32 = 90;
+
+/// How long mirroring stays suspended
+const SUSPEND_FOR: Duration = Duration::from_secs(300);
+
+/// Longest status line read from the mirror backend
+const MAX_STATUS_LINE: usize = 1024;
+
+/// Headers describing the connection rather than the request, never copied
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "content-length",
+    "expect",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Why a sampled request is not mirrored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Skip {
+    /// The body is larger than `MIRROR_MAX_BODY_BYTES`
+    BodyTooLarge,
+    /// Mirroring of the devbox is suspended after too many errors
+    Disabled,
+}
+
+impl Skip {
+    const fn label(self) -> &'static str {
+        match self {
+            Self::BodyTooLarge => "body_too_large",
+            Self::Disabled => "disabled",
+        }
+    }
+
+    pub fn count(self) {
+        metrics::MIRROR_SKIPPED_TOTAL
+            .with_label_values(&[self.label()])
+            .inc();
+    }
+}
+
+/// Mirroring state of a devbox
+#[derive(Debug, Default)]
+struct DevboxMirror {
+    /// Requests to the mirrored port, for sampling
+    seen: u64,
+    /// Mirrored requests in the current window, and how many failed
+    sent: u32,
+    failed: u32,
+    /// Mirroring is suspended until then
+    disabled_until: Option<Instant>,
+}
+
+/// Copies requests of devboxes with a [`MirrorPolicy`] to their mirror port,
+/// or to a port of their shadow devbox.
+///
+/// Mirrored requests are sent once the client's request has been answered,
+/// from a task of their own, and their responses are only counted, so the
+/// mirror backend can neither change nor slow down the response. Requests
+/// that would have to be streamed (upgrades, bodies over the size cap) are
+/// not mirrored, and a devbox whose mirror fails nearly every request is
+/// left alone for a while.
+#[derive(Debug)]
+pub struct Mirror {
+    max_body_bytes: u64,
+    timeout: Duration,
+    devboxes: DashMap<String, DevboxMirror>,
+}
+
+/// A request being buffered for its mirror.
+#[derive(Debug)]
+pub struct PendingMirror {
+    unique_id: String,
+    ip: String,
+    /// Port the request was sent to, and the port it is copied to
+    port: u16,
+    mirror_port: u16,
+    max_body_bytes: u64,
+    /// Method, target and headers of the upstream request, once built
+    request: Option<(Method, String, HeaderMap)>,
+    body: BytesMut,
+    /// Whether the whole body has been received
+    complete: bool,
+}
+
+impl Mirror {
+    pub fn new(max_body_bytes: u64, timeout: Duration) -> Self {
+        Self {
+            max_body_bytes,
+            timeout,
+            devboxes: DashMap::new(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.mirror_max_body_bytes, config.mirror_timeout)
+    }
+
+    /// Start mirroring `req`, sent to `ip:port` of `unique_id`, if the
+    /// devbox's `policy` mirrors the port and the request is sampled.
+    pub fn begin(
+        &self,
+        unique_id: &str,
+        ip: &str,
+        port: u16,
+        policy: &MirrorPolicy,
+        req: &RequestHeader,
+    ) -> Option<PendingMirror> {
+        self.begin_at(unique_id, ip, port, policy, req, Instant::now())
+    }
+
+    fn begin_at(
+        &self,
+        unique_id: &str,
+        ip: &str,
+        port: u16,
+        policy: &MirrorPolicy,
+        req: &RequestHeader,
+        now: Instant,
+    ) -> Option<PendingMirror> {
+        if port != policy.port
+            || req.headers.contains_key(UPGRADE)
+            || headers::has_connection_token(&req.headers, "upgrade")
+        {
+            return None;
+        }
+
+        let mut devbox = self.devboxes.entry(unique_id.to_string()).or_default();
+        // Spread the sampled requests evenly: the n-th request is mirrored
+        // when it brings the mirrored count to n * percent / 100
+        devbox.seen += 1;
+        let percent = u64::from(policy.percent);
+        if devbox.seen * percent / 100 == (devbox.seen - 1) * percent / 100 {
+            return None;
+        }
+        let skip = if devbox.disabled_until.is_some_and(|until| now < until) {
+            Some(Skip::Disabled)
+        } else if content_length(&req.headers).is_some_and(|len| len > self.max_body_bytes) {
+            Some(Skip::BodyTooLarge)
+        } else {
+            None
+        };
+        if let Some(skip) = skip {
+            skip.count();
+            return None;
+        }
+
+        let has_body = req.headers.contains_key(TRANSFER_ENCODING)
+            || content_length(&req.headers).is_some_and(|len| len > 0);
+        Some(PendingMirror {
+            unique_id: unique_id.to_string(),
+            ip: ip.to_string(),
+            port,
+            mirror_port: policy.mirror_port,
+            max_body_bytes: self.max_body_bytes,
+            request: None,
+            body: BytesMut::new(),
+            complete: !has_body,
+        })
+    }
+
+    /// Send `pending` in the background, if its request was received whole.
+    pub fn dispatch(self: &Arc<Self>, pending: PendingMirror) {
+        let Some((method, target, headers)) = pending.request.filter(|_| pending.complete) else {
+            return;
+        };
+        let request = encode_request(&method, &target, &headers, pending.port, &pending.body);
+        let mirror = Arc::clone(self);
+        tokio::spawn(async move {
+            let addr = (pending.ip.as_str(), pending.mirror_port);
+            let result = tokio::time::timeout(mirror.timeout, send(addr, &request))
+                .await
+                .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()));
+            let label = match &result {
+                Ok(status) => match status / 100 {
+                    2 => "2xx",
+                    3 => "3xx",
+                    4 => "4xx",
+                    5 => "5xx",
+                    _ => "error",
+                },
+                Err(_) => "error",
+            };
+            metrics::MIRROR_REQUESTS_TOTAL
+                .with_label_values(&[label])
+                .inc();
+            if let Err(e) = &result {
+                debug!(
+                    unique_id = %pending.unique_id,
+                    mirror_port = pending.mirror_port,
+                    error = %e,
+                    "Mirrored request failed"
+                );
+            }
+            let failed = label == "5xx" || label == "error";
+            mirror.record_at(&pending.unique_id, failed, Instant::now());
+        });
+    }
+
+    /// Record the result of a mirrored request, suspending mirroring of the
+    /// devbox once a window of them failed at an extreme rate.
+    fn record_at(&self, unique_id: &str, failed: bool, now: Instant) {
+        let Some(mut devbox) = self.devboxes.get_mut(unique_id) else {
+            return;
+        };
+        devbox.sent += 1;
+        devbox.failed += u32::from(failed);
+        if devbox.sent < WINDOW {
+            return;
+        }
+        if devbox.failed * 100 >= devbox.sent * MAX_ERROR_PERCENT {
+            warn!(
+                unique_id = %unique_id,
+                failed = devbox.failed,
+                sent = devbox.sent,
+                suspend_for = ?SUSPEND_FOR,
+                "Mirrored requests keep failing, suspending mirroring"
+            );
+            devbox.disabled_until = Some(now + SUSPEND_FOR);
+        }
+        devbox.sent = 0;
+        devbox.failed = 0;
+    }
+}
+
+impl PendingMirror {
+    /// Keep the method, target and headers of the upstream request, as
+    /// built for the primary backend.
+    pub fn set_request(&mut self, req: &RequestHeader) {
+        let target = req
+            .uri
+            .path_and_query()
+            .map_or("/", |target| target.as_str())
+            .to_string();
+        self.request = Some((req.method.clone(), target, req.headers.clone()));
+    }
+
+    /// Buffer a chunk of the request body.
+    pub fn push_body(&mut self, chunk: Option<&[u8]>, end_of_stream: bool) -> Result<(), Skip> {
+        if let Some(chunk) = chunk {
+            if (self.body.len() + chunk.len()) as u64 > self.max_body_bytes {
+                return Err(Skip::BodyTooLarge);
+            }
+            self.body.extend_from_slice(chunk);
+        }
+        self.complete |= end_of_stream;
+        Ok(())
+    }
+}
+
+/// The request's `Content-Length`, if it has a valid one
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// An HTTP/1.1 request with the end-to-end `headers` and the buffered body.
+fn encode_request(
+    method: &Method,
+    target: &str,
+    headers: &HeaderMap,
+    from_port: u16,
+    body: &[u8],
+) -> Bytes {
+    let mut buf = BytesMut::with_capacity(256 + body.len());
+    buf.extend_from_slice(format!("{method} {target} HTTP/1.1\r\n").as_bytes());
+    for (name, value) in headers {
+        if HOP_BY_HOP.contains(&name.as_str()) || name == X_MIRRORED_FROM {
+            continue;
+        }
+        buf.extend_from_slice(name.as_str().as_bytes());
+        buf.extend_from_slice(b": ");
+        buf.extend_from_slice(value.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf.extend_from_slice(
+        format!(
+            "{X_MIRRORED_FROM}: {from_port}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            body.len()
+        )
+        .as_bytes(),
+    );
+    buf.extend_from_slice(body);
+    buf.freeze()
+}
+
+/// Send `request` to `addr`, returning the response status.
+async fn send(addr: (&str, u16), request: &[u8]) -> io::Result<u16> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(request).await?;
+
+    // Only the status line is of interest
+    let mut line = Vec::with_capacity(64);
+    let mut buf = [0u8; 256];
+    while !line.windows(2).any(|w| w == b"\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || line.len() > MAX_STATUS_LINE {
+            break;
+        }
+        line.extend_from_slice(&buf[..n]);
+    }
+    parse_status(&line).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad status line"))
+}
+
+/// Status code of an `HTTP/1.x NNN ...` status line
+fn parse_status(line: &[u8]) -> Option<u16> {
+    let rest = line.strip_prefix(b"HTTP/1.")?;
+    let code = rest.get(2..5)?;
+    if rest.get(1) != Some(&b' ') || !code.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(code).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const POLICY: MirrorPolicy = MirrorPolicy {
+        port: 8080,
+        shadow: None,
+        mirror_port: 8081,
+        percent: 10,
+    };
+
+    fn request(headers: &[(&str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build("POST", b"/api/items?x=1", None).unwrap();
+        for (name, value) in headers {
+            req.insert_header(name.to_string(), *value).unwrap();
+        }
+        req
+    }
+
+    fn begin(mirror: &Mirror, policy: &MirrorPolicy, req: &RequestHeader) -> Option<PendingMirror> {
+        mirror.begin("my-app", "127.0.0.1", 8080, policy, req)
+    }
+
+    #[test]
+    fn test_sampling() {
+        let mirror = Mirror::new(1024, Duration::from_secs(1));
+        let req = request(&[]);
+        let sampled = (0..100)
+            .filter(|_| begin(&mirror, &POLICY, &req).is_some())
+            .count();
+        assert_eq!(sampled, 10);
+
+        let all = MirrorPolicy {
+            percent: 100,
+            ..POLICY
+        };
+        assert!((0..10).all(|_| begin(&mirror, &all, &req).is_some()));
+
+        // Other ports and upgrades are never mirrored
+        let other_port = MirrorPolicy {
+            port: 3000,
+            ..all.clone()
+        };
+        assert!(begin(&mirror, &other_port, &req).is_none());
+        let websocket = request(&[("connection", "Upgrade"), ("upgrade", "websocket")]);
+        assert!(begin(&mirror, &all, &websocket).is_none());
+    }
+
+    #[test]
+    fn test_body_cap() {
+        let mirror = Mirror::new(8, Duration::from_secs(1));
+        let all = MirrorPolicy {
+            percent: 100,
+            ..POLICY
+        };
+
+        // Known to be too large up front
+        assert!(begin(&mirror, &all, &request(&[("content-length", "9")])).is_none());
+
+        let mut pending = begin(&mirror, &all, &request(&[("content-length", "8")])).unwrap();
+        assert!(!pending.complete);
+        pending.push_body(Some(b"1234"), false).unwrap();
+        pending.push_body(Some(b"5678"), true).unwrap();
+        assert!(pending.complete);
+
+        // Streamed bodies are dropped once they outgrow the cap
+        let mut pending =
+            begin(&mirror, &all, &request(&[("transfer-encoding", "chunked")])).unwrap();
+        pending.push_body(Some(b"12345"), false).unwrap();
+        assert_eq!(
+            pending.push_body(Some(b"6789"), false),
+            Err(Skip::BodyTooLarge)
+        );
+
+        // Bodyless requests are complete from the start
+        assert!(begin(&mirror, &all, &request(&[])).unwrap().complete);
+    }
+
+    #[test]
+    fn test_suspended_on_extreme_error_rate() {
+        let mirror = Mirror::new(1024, Duration::from_secs(1));
+        let all = MirrorPolicy {
+            percent: 100,
+            ..POLICY
+        };
+        let req = request(&[]);
+        let now = Instant::now();
+        let begin_at = |at| mirror.begin_at("my-app", "127.0.0.1", 8080, &all, &req, at);
+
+        // A mostly working mirror stays on
+        for i in 0..WINDOW {
+            assert!(begin_at(now).is_some());
+            mirror.record_at("my-app", i % 2 == 0, now);
+        }
+        assert!(begin_at(now).is_some());
+
+        for _ in 0..WINDOW {
+            mirror.record_at("my-app", true, now);
+        }
+        assert!(begin_at(now).is_none());
+        assert!(begin_at(now + SUSPEND_FOR - Duration::from_secs(1)).is_none());
+        assert!(begin_at(now + SUSPEND_FOR).is_some());
+    }
+
+    #[test]
+    fn test_encode_request() {
+        let mut req = request(&[
+            ("host", "devbox-my-app-8080.example.com"),
+            ("transfer-encoding", "chunked"),
+            ("connection", "keep-alive"),
+            ("x-custom", "1"),
+        ]);
+        req.append_header("x-custom", "2").unwrap();
+        let encoded = encode_request(&req.method, "/api/items?x=1", &req.headers, 8080, b"hello");
+        let encoded = std::str::from_utf8(&encoded).unwrap();
+
+        assert!(encoded.starts_with("POST /api/items?x=1 HTTP/1.1\r\n"));
+        assert!(encoded.contains("host: devbox-my-app-8080.example.com\r\n"));
+        assert!(encoded.contains("x-custom: 1\r\nx-custom: 2\r\n"));
+        assert!(encoded.contains("x-mirrored-from: 8080\r\n"));
+        assert!(encoded.contains("content-length: 5\r\n"));
+        assert!(!encoded.contains("chunked"));
+        assert!(!encoded.contains("keep-alive"));
+        assert!(encoded.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(parse_status(b"HTTP/1.1 201 Created\r\n"), Some(201));
+        assert_eq!(parse_status(b"HTTP/1.0 503\r\n"), Some(503));
+        assert_eq!(parse_status(b"HTTP/2 200\r\n"), None);
+        assert_eq!(parse_status(b"HTTP/1.1 2x0 OK\r\n"), None);
+        assert_eq!(parse_status(b""), None);
+    }
+
+    /// Mirror backend accepting one connection, returning what it received
+    async fn mirror_backend(respond: bool) -> (u16, tokio::sync::oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let n = stream.read(&mut buf).await.unwrap();
+            let _ = tx.send(String::from_utf8_lossy(&buf[..n]).to_string());
+            if respond {
+                let _ = stream.write_all(b"HTTP/1.1 500 Oops\r\n\r\n").await;
+            } else {
+                // Hold the connection open without answering
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        });
+        (port, rx)
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_isolated_from_primary() {
+        let mirror = Arc::new(Mirror::new(1024, Duration::from_millis(200)));
+
+        for respond in [true, false] {
+            let (mirror_port, received) = mirror_backend(respond).await;
+            let policy = MirrorPolicy {
+                mirror_port,
+                percent: 100,
+                ..POLICY
+            };
+            let req = request(&[("content-length", "5")]);
+            let mut pending = begin(&mirror, &policy, &req).unwrap();
+            pending.set_request(&req);
+            pending.push_body(Some(b"hello"), true).unwrap();
+
+            // Dispatching never waits for the mirror backend
+            let start = Instant::now();
+            mirror.dispatch(pending);
+            assert!(start.elapsed() < Duration::from_millis(50));
+
+            let received = received.await.unwrap();
+            assert!(received.starts_with("POST /api/items?x=1 HTTP/1.1\r\n"));
+            assert!(received.ends_with("\r\n\r\nhello"));
+        }
+
+        // Both the 500 and the unanswered request count as failures
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let devbox = mirror.devboxes.get("my-app").unwrap();
+        assert_eq!((devbox.sent, devbox.failed), (2, 2));
+    }
+
+    #[test]
+    fn test_incomplete_requests_not_dispatched() {
+        let mirror = Arc::new(Mirror::new(1024, Duration::from_secs(1)));
+        let all = MirrorPolicy {
+            percent: 100,
+            ..POLICY
+        };
+        // Neither a request without headers nor one cut short is sent (no
+        // runtime is needed, as nothing is spawned)
+        let req = request(&[("content-length", "5")]);
+        let pending = begin(&mirror, &all, &req).unwrap();
+        mirror.dispatch(pending);
+        let mut pending = begin(&mirror, &all, &req).unwrap();
+        pending.set_request(&req);
+        pending.push_body(Some(b"he"), false).unwrap();
+        mirror.dispatch(pending);
+    }
+}
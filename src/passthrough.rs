@@ -0,0 +1,472 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use pingora_core::apps::ServerApp;
+use pingora_core::protocols::Stream;
+use pingora_core::server::ShutdownWatch;
+use pingora_core::services::listening::Service;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, warn};
+
+use crate::blocklist::Blocklist;
+use crate::config::ListenerPolicy;
+use crate::metrics;
+use crate::preview::unix_now;
+use crate::registry::DevboxRegistry;
+use crate::routing::{resolve_backend, BackendResult, HostParser};
+
+/// TLS record content type of handshake messages
+const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+
+/// Handshake message type of a ClientHello
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+
+/// Extension type of the server name indication
+const EXTENSION_SERVER_NAME: u16 = 0;
+
+/// Name type of a DNS host name in the server name list
+const NAME_TYPE_HOST_NAME: u8 = 0;
+
+/// Largest TLS record body (a plaintext record plus the allowed expansion)
+const MAX_RECORD_LEN: usize = 16384 + 2048;
+
+/// Time allowed for the client to send its ClientHello
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Timeout of the connection to the backend
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why the server name of a connection could not be read.
+#[derive(Debug)]
+pub enum HelloError {
+    /// The connection does not start with a TLS handshake
+    NotTls,
+    /// The record is not a well-formed ClientHello (or is split across
+    /// several records, which is not supported)
+    Malformed,
+    /// The ClientHello carries no host name
+    NoServerName,
+    /// Reading from the connection failed
+    Io(io::Error),
+}
+
+impl HelloError {
+    /// Result label of connections failing with this error
+    const fn result(&self) -> &'static str {
+        match self {
+            Self::NotTls => "not_tls",
+            Self::Malformed => "malformed",
+            Self::NoServerName => "no_server_name",
+            Self::Io(_) => "io_error",
+        }
+    }
+}
+
+impl fmt::Display for HelloError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotTls => f.write_str("not a TLS connection"),
+            Self::Malformed => f.write_str("malformed ClientHello"),
+            Self::NoServerName => f.write_str("ClientHello has no server name"),
+            Self::Io(e) => write!(f, "failed to read ClientHello: {e}"),
+        }
+    }
+}
+
+/// Read the first TLS record of a connection, which carries the ClientHello.
+///
+/// Connections not starting with a handshake record are rejected after the
+/// first byte, so plaintext clients aren't kept waiting for a record body.
+pub async fn read_client_hello<R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<Vec<u8>, HelloError> {
+    let mut record = vec![0u8; 5];
+    stream
+        .read_exact(&mut record[..1])
+        .await
+        .map_err(HelloError::Io)?;
+    if record[0] != CONTENT_TYPE_HANDSHAKE {
+        return Err(HelloError::NotTls);
+    }
+    stream
+        .read_exact(&mut record[1..])
+        .await
+        .map_err(HelloError::Io)?;
+
+    let len = u16::from_be_bytes([record[3], record[4]]) as usize;
+    if len > MAX_RECORD_LEN {
+        return Err(HelloError::Malformed);
+    }
+    record.resize(5 + len, 0);
+    stream
+        .read_exact(&mut record[5..])
+        .await
+        .map_err(HelloError::Io)?;
+    Ok(record)
+}
+
+/// Cursor over TLS wire encoding.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// A vector with a `len_bytes`-byte length prefix
+    fn vec(&mut self, len_bytes: usize) -> Option<Self> {
+        let len = self
+            .take(len_bytes)?
+            .iter()
+            .fold(0usize, |len, &b| (len << 8) | usize::from(b));
+        self.take(len).map(Reader)
+    }
+}
+
+/// Host name from the server name extension of a ClientHello record, in
+/// lowercase.
+pub fn server_name(record: &[u8]) -> Result<String, HelloError> {
+    let mut record = Reader(record);
+    if record.u8() != Some(CONTENT_TYPE_HANDSHAKE) {
+        return Err(HelloError::NotTls);
+    }
+
+    let name = (|| {
+        record.take(2)?; // legacy record version
+        let mut handshake = record.vec(2)?;
+        if handshake.u8()? != HANDSHAKE_CLIENT_HELLO {
+            return None;
+        }
+        let mut hello = handshake.vec(3)?;
+        hello.take(2 + 32)?; // legacy version, random
+        hello.vec(1)?; // session ID
+        hello.vec(2)?; // cipher suites
+        hello.vec(1)?; // compression methods
+        if hello.0.is_empty() {
+            // No extensions at all
+            return Some(None);
+        }
+
+        let mut extensions = hello.vec(2)?;
+        while !extensions.0.is_empty() {
+            let extension_type = extensions.u16()?;
+            let mut data = extensions.vec(2)?;
+            if extension_type != EXTENSION_SERVER_NAME {
+                continue;
+            }
+            let mut names = data.vec(2)?;
+            while !names.0.is_empty() {
+                let name_type = names.u8()?;
+                let name = names.vec(2)?;
+                if name_type == NAME_TYPE_HOST_NAME {
+                    return Some(Some(name.0));
+                }
+            }
+        }
+        Some(None)
+    })()
+    .ok_or(HelloError::Malformed)?
+    .ok_or(HelloError::NoServerName)?;
+
+    let name = std::str::from_utf8(name).map_err(|_| HelloError::Malformed)?;
+    Ok(name.to_ascii_lowercase())
+}
+
+/// TCP proxy routing TLS connections by SNI, without terminating them.
+///
+/// The server name follows the HTTP host convention
+/// (`devbox-<uniqueID>-<port>.<domain>`), so TLS services on non-HTTP devbox
+/// ports (databases, SSH over TLS) are reached with the same names. The
+/// ClientHello is replayed to the backend and the rest of the connection is
+/// relayed unchanged; connections without a usable ClientHello are closed.
+pub struct PassthroughApp {
+    registry: Arc<DevboxRegistry>,
+    blocklist: Arc<Blocklist>,
+    /// Domains served (matched like an HTTP listener's)
+    listener: ListenerPolicy,
+    host_parser: HostParser,
+}
+
+impl PassthroughApp {
+    pub fn new(
+        registry: Arc<DevboxRegistry>,
+        blocklist: Arc<Blocklist>,
+        domain_suffixes: Vec<String>,
+    ) -> Self {
+        Self {
+            registry,
+            blocklist,
+            listener: ListenerPolicy {
+                name: "passthrough".to_string(),
+                domain_suffixes,
+                max_request_body_bytes: None,
+                tls: true,
+                identity_headers: false,
+            },
+            host_parser: HostParser::Default,
+        }
+    }
+
+    /// Parse server names with `host_parser` instead of the default scheme.
+    #[must_use]
+    pub fn with_host_parser(mut self, host_parser: HostParser) -> Self {
+        self.host_parser = host_parser;
+        self
+    }
+
+    /// Wrap the app in a listening service; add addresses with `add_tcp`.
+    pub fn into_service(self) -> Service<Self> {
+        Service::new("httpgate-passthrough".to_string(), self)
+    }
+
+    /// Relay one client connection to the backend named by its SNI,
+    /// returning the result label it is counted under.
+    pub async fn serve<S>(&self, mut client: S, peer: Option<SocketAddr>) -> &'static str
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let hello = match tokio::time::timeout(HELLO_TIMEOUT, read_client_hello(&mut client)).await
+        {
+            Ok(Ok(hello)) => hello,
+            Ok(Err(e)) => {
+                warn!(peer = ?peer, error = %e, "Dropping passthrough connection");
+                return e.result();
+            }
+            Err(_) => {
+                warn!(peer = ?peer, "Timed out waiting for ClientHello");
+                return "timeout";
+            }
+        };
+        let server_name = match server_name(&hello) {
+            Ok(name) => name,
+            Err(e) => {
+                warn!(peer = ?peer, error = %e, "Dropping passthrough connection");
+                return e.result();
+            }
+        };
+
+        let (ip, port) = match self.resolve(&server_name) {
+            Ok(backend) => backend,
+            Err(result) => {
+                warn!(
+                    peer = ?peer,
+                    server_name = %server_name,
+                    result = result,
+                    "Passthrough connection not routed"
+                );
+                return result;
+            }
+        };
+
+        let mut upstream = match tokio::time::timeout(
+            CONNECT_TIMEOUT,
+            TcpStream::connect((ip.as_str(), port)),
+        )
+        .await
+        {
+            Ok(Ok(upstream)) => upstream,
+            Ok(Err(e)) => {
+                warn!(server_name = %server_name, pod_ip = %ip, port = port, error = %e, "Passthrough connect failed");
+                return "connect_failed";
+            }
+            Err(_) => {
+                warn!(server_name = %server_name, pod_ip = %ip, port = port, "Passthrough connect timed out");
+                return "connect_failed";
+            }
+        };
+        if let Err(e) = upstream.write_all(&hello).await {
+            warn!(server_name = %server_name, error = %e, "Failed to replay ClientHello");
+            return "connect_failed";
+        }
+
+        debug!(peer = ?peer, server_name = %server_name, pod_ip = %ip, port = port, "Relaying passthrough connection");
+        match tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+            Ok((to_backend, to_client)) => {
+                metrics::PASSTHROUGH_BYTES_TOTAL
+                    .with_label_values(&["upstream"])
+                    .inc_by(to_backend + hello.len() as u64);
+                metrics::PASSTHROUGH_BYTES_TOTAL
+                    .with_label_values(&["downstream"])
+                    .inc_by(to_client);
+            }
+            Err(e) => {
+                debug!(server_name = %server_name, error = %e, "Passthrough connection ended with error");
+            }
+        }
+        "proxied"
+    }
+
+    /// Backend address of `server_name`, or the result label of why it
+    /// can't be routed.
+    fn resolve(&self, server_name: &str) -> Result<(String, u16), &'static str> {
+        if !self.listener.matches_host(server_name) {
+            return Err("misdirected");
+        }
+        let Some((_, unique_id, port)) = self.host_parser.parse(server_name) else {
+            return Err("not_found");
+        };
+        match resolve_backend(&self.registry, &self.blocklist, &unique_id, port) {
+            BackendResult::Ok(endpoint, port, info) => {
+                info.touch(unix_now());
+                Ok((endpoint.ip, port))
+            }
+            BackendResult::NotFound => Err("not_found"),
+            BackendResult::NotRunning => Err("not_running"),
+            BackendResult::Blocked(_) => Err("blocked"),
+            BackendResult::InvalidAddress(..) => Err("invalid_address"),
+        }
+    }
+}
+
+#[async_trait]
+impl ServerApp for PassthroughApp {
+    async fn process_new(
+        self: &Arc<Self>,
+        stream: Stream,
+        _shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        let peer = stream
+            .get_socket_digest()
+            .and_then(|d| d.peer_addr().and_then(|a| a.as_inet().copied()));
+        let result = self.serve(stream, peer).await;
+        metrics::PASSTHROUGH_CONNECTIONS_TOTAL
+            .with_label_values(&[result])
+            .inc();
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ClientHello sent by `openssl s_client -tls1_3 -servername
+    /// devbox-my-app-5432.devbox.sealos.io`
+    const CLIENT_HELLO: &[u8] = include_bytes!("../tests/fixtures/client_hello.bin");
+
+    #[test]
+    fn test_server_name() {
+        assert_eq!(
+            server_name(CLIENT_HELLO).unwrap(),
+            "devbox-my-app-5432.devbox.sealos.io"
+        );
+    }
+
+    #[test]
+    fn test_server_name_invalid() {
+        assert!(matches!(
+            server_name(b"GET / HTTP/1.1\r\n"),
+            Err(HelloError::NotTls)
+        ));
+
+        // Every truncation is malformed, never a panic
+        for len in 1..CLIENT_HELLO.len() {
+            assert!(
+                matches!(
+                    server_name(&CLIENT_HELLO[..len]),
+                    Err(HelloError::Malformed)
+                ),
+                "{len}"
+            );
+        }
+
+        // Not a ClientHello
+        let mut server_hello = CLIENT_HELLO.to_vec();
+        server_hello[5] = 2;
+        assert!(matches!(
+            server_name(&server_hello),
+            Err(HelloError::Malformed)
+        ));
+
+        // Server name extension renamed to an unknown type
+        let mut without_sni = CLIENT_HELLO.to_vec();
+        let at = CLIENT_HELLO
+            .windows(b"devbox-my-app".len())
+            .position(|w| w == b"devbox-my-app")
+            .unwrap();
+        // type (2), extension length (2), list length (2), name type (1), name length (2)
+        without_sni[at - 9] = 0xfa;
+        assert!(matches!(
+            server_name(&without_sni),
+            Err(HelloError::NoServerName)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_client_hello() {
+        // Followed by application data, which stays unread
+        let mut input = CLIENT_HELLO.to_vec();
+        input.extend_from_slice(b"more");
+        let mut reader = &input[..];
+        assert_eq!(read_client_hello(&mut reader).await.unwrap(), CLIENT_HELLO);
+        assert_eq!(reader, b"more");
+
+        let mut plaintext = &b"SSH-2.0-OpenSSH_9.6\r\n"[..];
+        assert!(matches!(
+            read_client_hello(&mut plaintext).await,
+            Err(HelloError::NotTls)
+        ));
+
+        let mut truncated = &CLIENT_HELLO[..100];
+        assert!(matches!(
+            read_client_hello(&mut truncated).await,
+            Err(HelloError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox("my-app".to_string(), "ns".to_string(), "db".to_string());
+        registry
+            .update_pod_ip("ns", "db", "10.0.0.5".to_string())
+            .unwrap();
+        registry.register_devbox(
+            "stopped".to_string(),
+            "ns".to_string(),
+            "stopped".to_string(),
+        );
+        let blocklist = Arc::new(Blocklist::from_config(&crate::config::Config::default()));
+        let app = PassthroughApp::new(registry, blocklist, vec!["devbox.sealos.io".to_string()]);
+
+        assert_eq!(
+            app.resolve("devbox-my-app-5432.devbox.sealos.io"),
+            Ok(("10.0.0.5".to_string(), 5432))
+        );
+        assert_eq!(
+            app.resolve("devbox-my-app-5432.other.io"),
+            Err("misdirected")
+        );
+        assert_eq!(
+            app.resolve("devbox-unknown-5432.devbox.sealos.io"),
+            Err("not_found")
+        );
+        assert_eq!(
+            app.resolve("devbox-stopped-5432.devbox.sealos.io"),
+            Err("not_running")
+        );
+
+        // Relayed connections count as accesses
+        let last_access = |id| app.registry.get_devbox(id).unwrap().last_access();
+        assert!(last_access("my-app").is_some());
+        assert_eq!(last_access("stopped"), None);
+    }
+}
@@ -0,0 +1,461 @@
+use std::borrow::Cow;
+use std::fmt;
+
+/// Percent-decoding rounds applied when looking for traversal, so that
+/// double and triple encodings (`%252e`) are caught as well
+const DECODE_ROUNDS: usize = 3;
+
+/// Request path that is not forwarded, even after normalization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// `..` segments climb above the root
+    Traversal,
+    /// Overlong UTF-8, e.g. `%c0%ae` for `.`, which some decoders accept
+    OverlongEncoding,
+    /// An encoded NUL byte, which truncates paths in some backends
+    NulByte,
+}
+
+impl PathError {
+    /// Metric label of the error
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Traversal => "traversal",
+            Self::OverlongEncoding => "overlong_encoding",
+            Self::NulByte => "nul_byte",
+        }
+    }
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Traversal => "path escapes the root",
+            Self::OverlongEncoding => "overlong UTF-8 in path",
+            Self::NulByte => "NUL byte in path",
+        })
+    }
+}
+
+/// Normalize a request path for backends that don't resolve paths safely.
+///
+/// Duplicate slashes are collapsed and `.` and `..` segments resolved, also
+/// when percent-encoded (`%2e%2e`). Other encodings, including `%2F`, are
+/// forwarded as they are, as is a trailing slash.
+///
+/// The path is rejected if `..` climbs above the root, either as written or
+/// once decoded the way a naive backend might: repeatedly, with `%2F` and
+/// `\` as separators and `;` parameters dropped (`/..;/`). Encoded NUL
+/// bytes and overlong UTF-8 are rejected too.
+///
+/// Returns `None` if the path needs no changes. Paths not starting with `/`
+/// (e.g. `*`) are left alone.
+pub fn normalize(path: &str) -> Result<Option<String>, PathError> {
+    if !path.starts_with('/') {
+        return Ok(None);
+    }
+    check_decoded(path.as_bytes())?;
+
+    let mut segments = Vec::new();
+    let mut trailing_slash = false;
+    for segment in path[1..].split('/') {
+        trailing_slash = true;
+        match dot_segment(segment) {
+            Some(Dot::Current) => {}
+            Some(Dot::Parent) => {
+                segments.pop().ok_or(PathError::Traversal)?;
+            }
+            None if segment.is_empty() => {}
+            None => {
+                segments.push(segment);
+                trailing_slash = false;
+            }
+        }
+    }
+
+    let mut normalized = String::with_capacity(path.len());
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if trailing_slash {
+        normalized.push('/');
+    }
+    Ok((normalized != path).then_some(normalized))
+}
+
+/// Path of a request to a devbox whose app lives under another prefix than
+/// its clients see: `strip` is removed from the front of the path (if it
+/// is there) and `add` put in front of it.
+///
+/// Prefixes have a leading slash and no trailing one, and only match whole
+/// segments: `/app` strips `/app` and `/app/x` but not `/apple`. The rest of
+/// the path is copied as it is, percent-encoding included.
+///
+/// Returns `None` if the path is forwarded unchanged.
+pub fn rewrite_prefix(path: &str, strip: Option<&str>, add: Option<&str>) -> Option<String> {
+    let stripped = strip.and_then(|strip| strip_prefix(path, strip));
+    match (stripped, add) {
+        (stripped, Some(add)) => Some(format!("{add}{}", stripped.unwrap_or(path))),
+        (Some(stripped), None) => Some(stripped.to_string()),
+        (None, None) => None,
+    }
+}
+
+/// `Location` of a devbox's redirect as its clients see it, undoing
+/// [`rewrite_prefix`] so the redirect stays under the stripped
+/// prefix.
+///
+/// Only redirects into the rewritten space are changed: absolute paths, and
+/// absolute URLs of `host` (the request's `Host`), whose path is under
+/// `add` if it is set. Relative references resolve against the path the
+/// client sent, so they are left alone, as are other hosts' URLs.
+pub fn restore_prefix(
+    location: &str,
+    host: &str,
+    strip: Option<&str>,
+    add: Option<&str>,
+) -> Option<String> {
+    if strip.is_none() && add.is_none() {
+        return None;
+    }
+    let origin_len = if location.starts_with('/') && !location.starts_with("//") {
+        0
+    } else {
+        let (scheme, rest) = location.split_once("://")?;
+        if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+            return None;
+        }
+        let authority_len = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        if !rest[..authority_len].eq_ignore_ascii_case(host) {
+            return None;
+        }
+        scheme.len() + "://".len() + authority_len
+    };
+
+    let (origin, rest) = location.split_at(origin_len);
+    let (path, tail) = rest.split_at(rest.find(['?', '#']).unwrap_or(rest.len()));
+    let path = if path.is_empty() { "/" } else { path };
+    let path = match add {
+        Some(add) => strip_prefix(path, add)?,
+        None => path,
+    };
+    let path = match strip {
+        Some(strip) => format!("{strip}{path}"),
+        None => path.to_string(),
+    };
+    Some(format!("{origin}{path}{tail}"))
+}
+
+/// `path` without `prefix`, if it starts with the whole prefix segments;
+/// the prefix itself, with or without a trailing slash, becomes `/`.
+fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    match path.strip_prefix(prefix)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+enum Dot {
+    Current,
+    Parent,
+}
+
+/// Whether `segment` is `.` or `..`, with any dot possibly written `%2e`.
+fn dot_segment(segment: &str) -> Option<Dot> {
+    let mut rest = segment.as_bytes();
+    let mut dots = 0;
+    while !rest.is_empty() {
+        if rest[0] == b'.' {
+            rest = &rest[1..];
+        } else if rest.len() >= 3 && rest[..3].eq_ignore_ascii_case(b"%2e") {
+            rest = &rest[3..];
+        } else {
+            return None;
+        }
+        dots += 1;
+    }
+    match dots {
+        1 => Some(Dot::Current),
+        2 => Some(Dot::Parent),
+        _ => None,
+    }
+}
+
+/// Reject `path` if any of its decodings escapes the root or hides bytes
+/// that backends mishandle.
+fn check_decoded(path: &[u8]) -> Result<(), PathError> {
+    let mut decoded = Cow::Borrowed(path);
+    for round in 0..=DECODE_ROUNDS {
+        if round > 0 {
+            match percent_decode(&decoded) {
+                Cow::Borrowed(_) => break,
+                Cow::Owned(next) => decoded = Cow::Owned(next),
+            }
+        }
+        if decoded.contains(&0) {
+            return Err(PathError::NulByte);
+        }
+        if has_overlong_utf8(&decoded) {
+            return Err(PathError::OverlongEncoding);
+        }
+        if escapes_root(&decoded) {
+            return Err(PathError::Traversal);
+        }
+    }
+    Ok(())
+}
+
+/// Decode `%XX` escapes, leaving malformed ones as they are.
+fn percent_decode(input: &[u8]) -> Cow<'_, [u8]> {
+    if !input.contains(&b'%') {
+        return Cow::Borrowed(input);
+    }
+    let hex = |b: u8| char::from(b).to_digit(16).map(|d| d as u8);
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%' && i + 2 < input.len() {
+            if let (Some(hi), Some(lo)) = (hex(input[i + 1]), hex(input[i + 2])) {
+                out.push((hi << 4) | lo);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(input[i]);
+        i += 1;
+    }
+    if out == input {
+        Cow::Borrowed(input)
+    } else {
+        Cow::Owned(out)
+    }
+}
+
+/// Whether the path climbs above the root, treating `\` as a separator and
+/// ignoring `;` parameters as some servers do.
+fn escapes_root(path: &[u8]) -> bool {
+    let mut depth = 0usize;
+    for segment in path.split(|&b| b == b'/' || b == b'\\') {
+        let segment = segment.split(|&b| b == b';').next().unwrap_or_default();
+        match segment {
+            b"" | b"." => {}
+            b".." => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return true,
+            },
+            _ => depth += 1,
+        }
+    }
+    false
+}
+
+/// Whether `bytes` contain UTF-8 lead bytes that only start overlong
+/// encodings (or the obsolete 5- and 6-byte forms).
+fn has_overlong_utf8(bytes: &[u8]) -> bool {
+    bytes.iter().enumerate().any(|(i, &b)| {
+        let next = bytes.get(i + 1).copied().unwrap_or(0);
+        matches!(b, 0xc0 | 0xc1 | 0xf8..=0xff)
+            || (b == 0xe0 && (0x80..0xa0).contains(&next))
+            || (b == 0xf0 && (0x80..0x90).contains(&next))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        for (path, expected) in [
+            // Already normal
+            ("/", None),
+            ("/index.html", None),
+            ("/a/b/", None),
+            ("*", None),
+            ("/a/...", None),
+            ("/a/..b/.c", None),
+            ("/caf%C3%A9", None),
+            // Latin-1 is invalid UTF-8 but not overlong
+            ("/latin%E9", None),
+            // Encoded slashes are kept for frameworks that route on them
+            ("/files/a%2Fb", None),
+            ("/files/a%2f..%2fb", None),
+            // Malformed escapes are forwarded untouched
+            ("/a%zz/b%", None),
+            ("/a/%2", None),
+            // Traversal that stays below the root once decoded
+            ("/a/..;/b", None),
+            ("/a/%252e%252e/b", None),
+            ("/a/..%5cb", None),
+            // Duplicate slashes
+            ("//", Some("/")),
+            ("//a///b", Some("/a/b")),
+            ("/a/b//", Some("/a/b/")),
+            // Dot segments
+            ("/.", Some("/")),
+            ("/a/./b", Some("/a/b")),
+            ("/a/b/..", Some("/a/")),
+            ("/a/b/../c", Some("/a/c")),
+            ("/a/b/./", Some("/a/b/")),
+            ("/api/v1/../v2/users", Some("/api/v2/users")),
+            ("/a/b/../../c", Some("/c")),
+            ("/a//..//b", Some("/b")),
+            // Encoded dots, in any case
+            ("/a/%2e/b", Some("/a/b")),
+            ("/a/%2E%2e/b", Some("/b")),
+            ("/a/.%2e/b", Some("/b")),
+            ("/a/%2e./b", Some("/b")),
+            ("/a/b/%2E", Some("/a/b/")),
+        ] {
+            assert_eq!(normalize(path), Ok(expected.map(String::from)), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_normalize_rejects() {
+        for (path, expected) in [
+            // Plain traversal
+            ("/..", PathError::Traversal),
+            ("/../etc/passwd", PathError::Traversal),
+            ("/a/../../etc/passwd", PathError::Traversal),
+            ("/a/./../..", PathError::Traversal),
+            ("//..//etc", PathError::Traversal),
+            // Encoded dots
+            ("/%2e%2e/etc/passwd", PathError::Traversal),
+            ("/%2E./etc/passwd", PathError::Traversal),
+            ("/.%2E/etc/passwd", PathError::Traversal),
+            // Encoded separators
+            ("/..%2fetc%2fpasswd", PathError::Traversal),
+            ("/a/%2e%2e%2f%2e%2e%2fetc", PathError::Traversal),
+            ("/..%2F..%2Fetc", PathError::Traversal),
+            // Backslashes, raw and encoded
+            ("/..\\windows", PathError::Traversal),
+            ("/..%5c..%5cwindows", PathError::Traversal),
+            ("/a\\..\\..\\b", PathError::Traversal),
+            // Path parameters
+            ("/..;/admin", PathError::Traversal),
+            ("/a/..;x=1/..;/etc", PathError::Traversal),
+            // Mixed and repeated encodings
+            ("/%252e%252e/etc", PathError::Traversal),
+            ("/%252e%252e%252fetc", PathError::Traversal),
+            ("/%25252e%25252e/etc", PathError::Traversal),
+            ("/.%252e/etc", PathError::Traversal),
+            ("/%2e%252e%255c/etc", PathError::Traversal),
+            // Overlong UTF-8 for `.`, `/` and `\`
+            ("/%c0%ae%c0%ae/etc", PathError::OverlongEncoding),
+            ("/..%c0%afetc", PathError::OverlongEncoding),
+            ("/..%c1%9cwindows", PathError::OverlongEncoding),
+            ("/%e0%80%ae%e0%80%ae/etc", PathError::OverlongEncoding),
+            ("/%f0%80%80%ae/etc", PathError::OverlongEncoding),
+            ("/%f8%80%80%80%ae/etc", PathError::OverlongEncoding),
+            ("/%25c0%25ae%25c0%25ae/etc", PathError::OverlongEncoding),
+            ("/a/%C0%AE", PathError::OverlongEncoding),
+            // NUL bytes
+            ("/file%00.txt", PathError::NulByte),
+            ("/file%2500.txt", PathError::NulByte),
+        ] {
+            assert_eq!(normalize(path), Err(expected), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_rewrite_prefix() {
+        let strip = Some("/app");
+        let add = Some("/base");
+        for (path, strip, add, expected) in [
+            ("/app", strip, None, Some("/")),
+            ("/app/", strip, None, Some("/")),
+            ("/app/x/y", strip, None, Some("/x/y")),
+            ("/app//x", strip, None, Some("//x")),
+            // Whole segments only
+            ("/apple", strip, None, None),
+            ("/other/app", strip, None, None),
+            ("/", strip, None, None),
+            // Encoded characters are copied, not decoded
+            (
+                "/app/caf%C3%A9/a%2Fb",
+                strip,
+                None,
+                Some("/caf%C3%A9/a%2Fb"),
+            ),
+            ("/%61pp/x", strip, None, None),
+            ("/", None, add, Some("/base/")),
+            ("/x%20y", None, add, Some("/base/x%20y")),
+            ("/app/x", strip, add, Some("/base/x")),
+            ("/app", strip, add, Some("/base/")),
+            ("/other", strip, add, Some("/base/other")),
+            ("/x", None, None, None),
+        ] {
+            assert_eq!(
+                rewrite_prefix(path, strip, add).as_deref(),
+                expected,
+                "{path} {strip:?} {add:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_restore_prefix() {
+        let host = "devbox-app-8080.devbox.local";
+        let strip = Some("/app");
+        let add = Some("/base");
+        for (location, strip, add, expected) in [
+            ("/login", strip, None, Some("/app/login")),
+            ("/", strip, None, Some("/app/")),
+            (
+                "/login?next=%2Fhome#top",
+                strip,
+                None,
+                Some("/app/login?next=%2Fhome#top"),
+            ),
+            (
+                "https://devbox-app-8080.devbox.local/login?x=1",
+                strip,
+                None,
+                Some("https://devbox-app-8080.devbox.local/app/login?x=1"),
+            ),
+            (
+                "http://Devbox-App-8080.devbox.local",
+                strip,
+                None,
+                Some("http://Devbox-App-8080.devbox.local/app/"),
+            ),
+            ("/base/login", None, add, Some("/login")),
+            ("/base", None, add, Some("/")),
+            ("/base/login", strip, add, Some("/app/login")),
+            ("/base/a%2Fb?q=%20", strip, add, Some("/app/a%2Fb?q=%20")),
+            // Outside the added prefix, so not the devbox's app
+            ("/elsewhere", None, add, None),
+            ("/basement", strip, add, None),
+            // Relative references, other hosts and schemes
+            ("login", strip, None, None),
+            ("../login", strip, None, None),
+            ("//evil.example.com/login", strip, None, None),
+            ("https://example.com/login", strip, None, None),
+            (
+                "https://devbox-app-8080.devbox.local.evil.com/",
+                strip,
+                None,
+                None,
+            ),
+            ("ftp://devbox-app-8080.devbox.local/x", strip, None, None),
+            ("/login", None, None, None),
+        ] {
+            assert_eq!(
+                restore_prefix(location, host, strip, add).as_deref(),
+                expected,
+                "{location} {strip:?} {add:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert!(matches!(percent_decode(b"/plain"), Cow::Borrowed(_)));
+        assert!(matches!(percent_decode(b"/bad%zz%"), Cow::Borrowed(_)));
+        assert_eq!(&*percent_decode(b"/a%2Fb%2e"), b"/a/b.");
+        assert_eq!(&*percent_decode(b"%25%32%65"), b"%2e");
+    }
+}
@@ -0,0 +1,900 @@
+use std::collections::BTreeMap;
+
+use http::{HeaderName, HeaderValue};
+use tracing::warn;
+
+use crate::basic_auth::BasicAuth;
+use crate::cidr::CidrSet;
+use crate::client_cert::ClientCaRef;
+use crate::routing::is_valid_unique_id;
+use crate::ws_origin::WsOrigins;
+
+/// Annotation listing backend ports that speak TLS (e.g., "8443,9443")
+pub const ANNOTATION_TLS_PORTS: &str = "devbox.sealos.io/tls-ports";
+
+/// Annotation disabling backend certificate verification (e.g., "true")
+pub const ANNOTATION_TLS_SKIP_VERIFY: &str = "devbox.sealos.io/tls-skip-verify";
+
+/// Annotation setting the SNI of TLS backends, for backends behind a shared
+/// ingress that routes on it (e.g., "app.internal.example.com")
+pub const ANNOTATION_TLS_SNI: &str = "devbox.sealos.io/tls-sni";
+
+/// Annotation listing request headers never forwarded to the devbox
+/// (e.g., "x-internal-auth,x-user-token")
+pub const ANNOTATION_DENY_REQUEST_HEADERS: &str = "devbox.sealos.io/deny-request-headers";
+
+/// Annotation restricting a devbox to requests with a valid preview token
+/// (e.g., "true")
+pub const ANNOTATION_AUTH_REQUIRED: &str = "devbox.sealos.io/auth-required";
+
+/// Annotation putting a devbox behind HTTP Basic auth, with htpasswd lines
+/// of bcrypt, apr1 or SHA-crypt hashes (e.g., "alice:$apr1$...", see
+/// [`BasicAuth`]). Named under `devbox.sealos.io/` rather than
+/// `httpgate.io/` like every other devbox annotation, so one prefix covers
+/// them all.
+pub const ANNOTATION_BASIC_AUTH: &str = "devbox.sealos.io/basic-auth";
+
+/// Annotation exempting a devbox from `NORMALIZE_PATHS`, for frameworks
+/// relying on paths exactly as sent (e.g., "true")
+pub const ANNOTATION_SKIP_PATH_NORMALIZATION: &str = "devbox.sealos.io/skip-path-normalization";
+
+/// Annotation listing origins allowed by gateway-level CORS, overriding
+/// `CORS_ALLOWED_ORIGINS` (e.g., "https://app.example.com,https://example.com")
+pub const ANNOTATION_CORS_ALLOWED_ORIGINS: &str = "devbox.sealos.io/cors-allowed-origins";
+
+/// Annotation restricting which origins may open WebSocket connections to a
+/// devbox: hosts, wildcard subdomains or `same-host` for the devbox's own
+/// hostname (e.g., "app.example.com,*.example.com" or "same-host")
+pub const ANNOTATION_WS_ALLOWED_ORIGINS: &str = "devbox.sealos.io/ws-allowed-origins";
+
+/// Annotation with the complete CORS policy of a devbox, overriding the global
+/// CORS configuration (e.g.,
+/// "origins=https://app.example.com;methods=GET,POST;credentials=true").
+///
+/// Keys: `origins` (required), `methods`, `headers`, `max-age` (seconds) and
+/// `credentials`. Unset methods, headers and max-age fall back to the global
+/// configuration; credentials default to `false`.
+pub const ANNOTATION_CORS: &str = "devbox.sealos.io/cors";
+
+/// Annotation restricting a devbox to clients in these address ranges
+/// (e.g., "10.8.0.0/16,203.0.113.0/24,2001:db8::/32")
+pub const ANNOTATION_ALLOWED_CIDRS: &str = "devbox.sealos.io/allowed-cidrs";
+
+/// Annotation turning the response cache on or off for a devbox, overriding
+/// `CACHE_REQUIRE_ANNOTATION` (e.g., "true")
+pub const ANNOTATION_RESPONSE_CACHE: &str = "devbox.sealos.io/response-cache";
+
+/// Annotation mirroring requests to one port to another port of the same Pod,
+/// or to a port of a shadow devbox in the same namespace, whose responses
+/// are discarded (e.g., "8080->8081@10%" or "8080->shadow-app-1234:8080@10%";
+/// without a percentage every request is mirrored)
+pub const ANNOTATION_MIRROR: &str = "devbox.sealos.io/mirror";
+
+/// Annotation injecting `<base href="...">` after the `<head>` tag of the
+/// devbox's HTML responses, for single-page apps served from a subpath
+/// (e.g., "/app/")
+pub const ANNOTATION_BASE_HREF: &str = "devbox.sealos.io/base-href";
+
+/// Annotation removing a path prefix from requests before they reach the
+/// devbox, for apps served from `/` behind a prefix (e.g., "/app")
+pub const ANNOTATION_STRIP_PREFIX: &str = "devbox.sealos.io/strip-prefix";
+
+/// Annotation putting a path prefix in front of the requests the devbox
+/// gets, after any stripped one is removed, for apps that expect to live
+/// under it (e.g., "/app")
+pub const ANNOTATION_ADD_PREFIX: &str = "devbox.sealos.io/add-prefix";
+
+/// Annotation requiring clients of a devbox to present a certificate
+/// chaining to the `ca.crt` bundle of a Secret or ConfigMap in its namespace
+/// (e.g., "client-ca", "secret/client-ca" or "configmap/client-ca"; see
+/// [`ClientCaRef`])
+pub const ANNOTATION_CLIENT_CA: &str = "devbox.sealos.io/client-ca";
+
+/// Annotations a [`DevboxPolicy`] is built from
+pub const ANNOTATIONS: [&str; 17] = [
+    ANNOTATION_TLS_PORTS,
+    ANNOTATION_TLS_SKIP_VERIFY,
+    ANNOTATION_TLS_SNI,
+    ANNOTATION_DENY_REQUEST_HEADERS,
+    ANNOTATION_AUTH_REQUIRED,
+    ANNOTATION_BASIC_AUTH,
+    ANNOTATION_SKIP_PATH_NORMALIZATION,
+    ANNOTATION_CORS_ALLOWED_ORIGINS,
+    ANNOTATION_WS_ALLOWED_ORIGINS,
+    ANNOTATION_CORS,
+    ANNOTATION_ALLOWED_CIDRS,
+    ANNOTATION_RESPONSE_CACHE,
+    ANNOTATION_MIRROR,
+    ANNOTATION_BASE_HREF,
+    ANNOTATION_STRIP_PREFIX,
+    ANNOTATION_ADD_PREFIX,
+    ANNOTATION_CLIENT_CA,
+];
+
+/// CORS policy of a devbox, from the [`ANNOTATION_CORS`] annotation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorsPolicy {
+    /// Allowed origins ("*" for any)
+    pub origins: Vec<String>,
+    /// `Access-Control-Allow-Methods` of preflight responses
+    pub methods: Option<String>,
+    /// `Access-Control-Allow-Headers` of preflight responses
+    pub headers: Option<String>,
+    /// `Access-Control-Max-Age` of preflight responses
+    pub max_age_secs: Option<u64>,
+    /// Send `Access-Control-Allow-Credentials: true`
+    pub allow_credentials: bool,
+}
+
+/// Request mirroring of a devbox, from the [`ANNOTATION_MIRROR`] annotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorPolicy {
+    /// Port whose requests are mirrored
+    pub port: u16,
+    /// uniqueID of the devbox the copies are sent to (the devbox itself if
+    /// `None`)
+    pub shadow: Option<String>,
+    /// Port the copies are sent to
+    pub mirror_port: u16,
+    /// Share of the requests mirrored, in percent (1-100)
+    pub percent: u8,
+}
+
+/// Per-devbox routing policy parsed from Devbox annotations.
+///
+/// Invalid annotation values are logged and ignored so that a typo never
+/// makes a devbox unreachable, except for the basic-auth gate and the client
+/// CA, which then let nobody through rather than exposing the devbox.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DevboxPolicy {
+    /// Backend ports that are proxied over TLS instead of cleartext
+    pub tls_ports: Vec<u16>,
+    /// Skip backend certificate verification (for known self-signed services)
+    pub tls_skip_verify: bool,
+    /// SNI of TLS backends, in place of `UPSTREAM_SNI` or the Pod IP
+    pub tls_sni: Option<String>,
+    /// Request headers stripped before the request is forwarded
+    pub deny_request_headers: Vec<HeaderName>,
+    /// CORS origins for this devbox (the global list applies if empty)
+    pub cors_allowed_origins: Vec<String>,
+    /// Complete CORS policy, taking precedence over `cors_allowed_origins`
+    pub cors: Option<CorsPolicy>,
+    /// Only serve requests carrying a valid preview token
+    pub auth_required: bool,
+    /// Only serve requests with the credentials of one of these users
+    pub basic_auth: Option<BasicAuth>,
+    /// Forward request paths without normalizing them
+    pub skip_path_normalization: bool,
+    /// Copy requests to a second port, for trying out a new backend
+    pub mirror: Option<MirrorPolicy>,
+    /// Origins allowed to open WebSocket connections (any if `None`)
+    pub ws_allowed_origins: Option<WsOrigins>,
+    /// Client address ranges the devbox is reachable from (any if `None`)
+    pub allowed_cidrs: Option<CidrSet>,
+    /// Whether responses are cached (`CACHE_REQUIRE_ANNOTATION` decides if
+    /// `None`)
+    pub response_cache: Option<bool>,
+    /// `href` of the `<base>` tag injected into HTML responses
+    pub base_href: Option<String>,
+    /// Path prefix removed from requests, without a trailing slash
+    pub strip_prefix: Option<String>,
+    /// Path prefix added to requests, without a trailing slash
+    pub add_prefix: Option<String>,
+    /// Bundle the client certificate must chain to (any client if `None`)
+    pub client_ca: Option<ClientCaRef>,
+    /// The annotations of [`ANNOTATIONS`] the policy was built from, so it
+    /// can be exported and built again
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl DevboxPolicy {
+    /// Build the policy from the annotations of a Devbox.
+    pub fn from_annotations(annotations: &BTreeMap<String, String>) -> Self {
+        let tls_ports = annotations
+            .get(ANNOTATION_TLS_PORTS)
+            .map(|value| parse_ports(ANNOTATION_TLS_PORTS, value))
+            .unwrap_or_default();
+
+        let tls_skip_verify = annotations
+            .get(ANNOTATION_TLS_SKIP_VERIFY)
+            .is_some_and(|value| parse_bool(ANNOTATION_TLS_SKIP_VERIFY, value));
+
+        let tls_sni = annotations
+            .get(ANNOTATION_TLS_SNI)
+            .and_then(|value| parse_server_name(ANNOTATION_TLS_SNI, value));
+
+        let deny_request_headers = annotations
+            .get(ANNOTATION_DENY_REQUEST_HEADERS)
+            .map(|value| parse_header_names(ANNOTATION_DENY_REQUEST_HEADERS, value))
+            .unwrap_or_default();
+
+        let cors_allowed_origins = annotations
+            .get(ANNOTATION_CORS_ALLOWED_ORIGINS)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let cors = annotations
+            .get(ANNOTATION_CORS)
+            .and_then(|value| match parse_cors(value) {
+                Ok(cors) => Some(cors),
+                Err(e) => {
+                    warn!(annotation = %ANNOTATION_CORS, value = %value, error = %e, "Invalid CORS policy in annotation, ignoring");
+                    None
+                }
+            });
+
+        let auth_required = annotations
+            .get(ANNOTATION_AUTH_REQUIRED)
+            .is_some_and(|value| parse_bool(ANNOTATION_AUTH_REQUIRED, value));
+
+        let basic_auth = annotations.get(ANNOTATION_BASIC_AUTH).map(|value| {
+            BasicAuth::parse(value).unwrap_or_else(|e| {
+                warn!(annotation = %ANNOTATION_BASIC_AUTH, error = %e, "Invalid basic auth in annotation, denying all requests");
+                BasicAuth::deny_all()
+            })
+        });
+
+        let skip_path_normalization = annotations
+            .get(ANNOTATION_SKIP_PATH_NORMALIZATION)
+            .is_some_and(|value| parse_bool(ANNOTATION_SKIP_PATH_NORMALIZATION, value));
+
+        let mirror = annotations
+            .get(ANNOTATION_MIRROR)
+            .and_then(|value| match parse_mirror(value) {
+                Ok(mirror) => Some(mirror),
+                Err(e) => {
+                    warn!(annotation = %ANNOTATION_MIRROR, value = %value, error = %e, "Invalid mirror in annotation, ignoring");
+                    None
+                }
+            });
+
+        // Invalid entries are dropped rather than the whole list, which would
+        // let every origin through
+        let ws_allowed_origins = annotations.get(ANNOTATION_WS_ALLOWED_ORIGINS).map(|value| {
+            let (origins, invalid) = WsOrigins::parse(value);
+            if let Some(e) = invalid {
+                warn!(annotation = %ANNOTATION_WS_ALLOWED_ORIGINS, value = %value, error = %e, "Invalid WebSocket origins in annotation, ignoring them");
+            }
+            origins
+        });
+
+        // Unlike the basic-auth gate, a typo leaves the devbox open rather
+        // than locking out the tenant's own users
+        let allowed_cidrs = annotations
+            .get(ANNOTATION_ALLOWED_CIDRS)
+            .and_then(|value| match CidrSet::parse(value) {
+                Ok(cidrs) => (!cidrs.is_empty()).then_some(cidrs),
+                Err(e) => {
+                    warn!(annotation = %ANNOTATION_ALLOWED_CIDRS, value = %value, error = %e, "Invalid CIDRs in annotation, not restricting clients");
+                    None
+                }
+            });
+
+        let response_cache = annotations
+            .get(ANNOTATION_RESPONSE_CACHE)
+            .map(|value| parse_bool(ANNOTATION_RESPONSE_CACHE, value));
+
+        let base_href = annotations
+            .get(ANNOTATION_BASE_HREF)
+            .and_then(|value| parse_base_href(ANNOTATION_BASE_HREF, value));
+
+        let strip_prefix = annotations
+            .get(ANNOTATION_STRIP_PREFIX)
+            .and_then(|value| parse_path_prefix(ANNOTATION_STRIP_PREFIX, value));
+        let add_prefix = annotations
+            .get(ANNOTATION_ADD_PREFIX)
+            .and_then(|value| parse_path_prefix(ANNOTATION_ADD_PREFIX, value));
+
+        let client_ca = annotations.get(ANNOTATION_CLIENT_CA).map(|value| {
+            ClientCaRef::parse(value).unwrap_or_else(|e| {
+                warn!(annotation = %ANNOTATION_CLIENT_CA, value = %value, error = %e, "Invalid client CA in annotation, denying all requests");
+                ClientCaRef::deny_all()
+            })
+        });
+
+        Self {
+            tls_ports,
+            tls_skip_verify,
+            tls_sni,
+            deny_request_headers,
+            cors_allowed_origins,
+            cors,
+            auth_required,
+            basic_auth,
+            skip_path_normalization,
+            mirror,
+            ws_allowed_origins,
+            allowed_cidrs,
+            response_cache,
+            base_href,
+            strip_prefix,
+            add_prefix,
+            client_ca,
+            annotations: annotations
+                .iter()
+                .filter(|(key, _)| ANNOTATIONS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
+    /// Whether the backend on `port` expects TLS.
+    pub fn uses_tls(&self, port: u16) -> bool {
+        self.tls_ports.contains(&port)
+    }
+}
+
+/// Parse a comma-separated port list, skipping invalid entries.
+fn parse_ports(key: &str, value: &str) -> Vec<u16> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| match s.parse() {
+            Ok(port) => Some(port),
+            Err(_) => {
+                warn!(annotation = %key, value = %s, "Invalid port in annotation, ignoring");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Parse a TLS server name, rejecting values that can't be sent as SNI.
+fn parse_server_name(key: &str, value: &str) -> Option<String> {
+    let value = value.trim();
+    let valid = !value.is_empty()
+        && value.len() <= 253
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_'))
+        && !value.starts_with('.')
+        && !value.ends_with('.');
+    if !valid {
+        warn!(annotation = %key, value = %value, "Invalid server name in annotation, ignoring");
+        return None;
+    }
+    Some(value.to_ascii_lowercase())
+}
+
+/// Parse the `href` of a `<base>` tag, rejecting values that would end its
+/// attribute or the tag.
+fn parse_base_href(key: &str, value: &str) -> Option<String> {
+    let value = value.trim();
+    let valid = !value.is_empty()
+        && value.len() <= 2048
+        && !value
+            .chars()
+            .any(|c| c.is_control() || c.is_whitespace() || matches!(c, '"' | '\'' | '<' | '>'));
+    if !valid {
+        warn!(annotation = %key, value = %value, "Invalid base href in annotation, ignoring");
+        return None;
+    }
+    Some(value.to_string())
+}
+
+/// Parse a path prefix, dropping its trailing slash. `/` is no prefix.
+fn parse_path_prefix(key: &str, value: &str) -> Option<String> {
+    let value = value.trim();
+    let valid = value.starts_with('/')
+        && !value.starts_with("//")
+        && !value.contains(['?', '#'])
+        && value.parse::<http::uri::PathAndQuery>().is_ok();
+    if !valid {
+        warn!(annotation = %key, value = %value, "Invalid path prefix in annotation, ignoring");
+        return None;
+    }
+    let prefix = value.trim_end_matches('/');
+    (!prefix.is_empty()).then(|| prefix.to_string())
+}
+
+/// Parse a comma-separated header name list, skipping invalid entries.
+fn parse_header_names(key: &str, value: &str) -> Vec<HeaderName> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| match HeaderName::from_bytes(s.as_bytes()) {
+            Ok(name) => Some(name),
+            Err(_) => {
+                warn!(annotation = %key, value = %s, "Invalid header name in annotation, ignoring");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Parse a `key=value;...` CORS policy.
+///
+/// Any invalid part rejects the whole policy, as does a wildcard origin with
+/// credentials, which browsers refuse.
+fn parse_cors(value: &str) -> Result<CorsPolicy, String> {
+    let mut cors = CorsPolicy::default();
+    for part in value.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+        let (key, value) = part
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, got {part:?}"))?;
+        let value = value.trim();
+        let header_value = |value: &str| {
+            HeaderValue::from_str(value)
+                .map(|_| value.to_string())
+                .map_err(|_| format!("invalid {key} value {value:?}"))
+        };
+        match key.trim() {
+            "origins" => {
+                cors.origins = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect();
+            }
+            "methods" => cors.methods = Some(header_value(value)?),
+            "headers" => cors.headers = Some(header_value(value)?),
+            "max-age" => {
+                cors.max_age_secs = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid max-age {value:?}"))?,
+                );
+            }
+            "credentials" => {
+                cors.allow_credentials = match value {
+                    "true" => true,
+                    "false" => false,
+                    other => return Err(format!("invalid credentials {other:?}")),
+                };
+            }
+            other => return Err(format!("unknown key {other:?}")),
+        }
+    }
+
+    if cors.origins.is_empty() {
+        return Err("no origins".to_string());
+    }
+    if cors.allow_credentials && cors.origins.iter().any(|o| o == "*") {
+        return Err("wildcard origin cannot be used with credentials".to_string());
+    }
+    Ok(cors)
+}
+
+/// Parse a `<port>->[<uniqueID>:]<mirror port>[@<percent>%]` mirror.
+fn parse_mirror(value: &str) -> Result<MirrorPolicy, String> {
+    let (ports, percent) = match value.trim().split_once('@') {
+        Some((ports, percent)) => {
+            let percent = percent
+                .trim()
+                .strip_suffix('%')
+                .and_then(|p| p.trim().parse::<u8>().ok())
+                .filter(|p| (1..=100).contains(p))
+                .ok_or_else(|| format!("invalid percentage {percent:?}"))?;
+            (ports, percent)
+        }
+        None => (value, 100),
+    };
+    let (port, mirror_port) = ports
+        .split_once("->")
+        .ok_or_else(|| format!("expected <port>-><mirror port>, got {ports:?}"))?;
+    let parse_port = |port: &str| {
+        port.trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|&p| p != 0)
+            .ok_or_else(|| format!("invalid port {port:?}"))
+    };
+    let (shadow, mirror_port) = match mirror_port.split_once(':') {
+        Some((shadow, mirror_port)) => {
+            let shadow = shadow.trim();
+            if !is_valid_unique_id(shadow) {
+                return Err(format!("invalid uniqueID {shadow:?}"));
+            }
+            (Some(shadow.to_string()), mirror_port)
+        }
+        None => (None, mirror_port),
+    };
+    let (port, mirror_port) = (parse_port(port)?, parse_port(mirror_port)?);
+    if shadow.is_none() && port == mirror_port {
+        return Err("mirror port must differ from the port".to_string());
+    }
+    Ok(MirrorPolicy {
+        port,
+        shadow,
+        mirror_port,
+        percent,
+    })
+}
+
+/// Parse a boolean annotation value, treating invalid values as `false`.
+fn parse_bool(key: &str, value: &str) -> bool {
+    match value.trim() {
+        "true" | "1" => true,
+        "false" | "0" | "" => false,
+        other => {
+            warn!(annotation = %key, value = %other, "Invalid boolean in annotation, ignoring");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotations(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_policy_defaults() {
+        let policy = DevboxPolicy::from_annotations(&BTreeMap::new());
+        assert_eq!(policy, DevboxPolicy::default());
+        assert!(!policy.uses_tls(8443));
+    }
+
+    #[test]
+    fn test_policy_tls_ports() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[(
+            ANNOTATION_TLS_PORTS,
+            "8443, 9443,invalid,",
+        )]));
+        assert_eq!(policy.tls_ports, vec![8443, 9443]);
+        assert!(policy.uses_tls(8443));
+        assert!(policy.uses_tls(9443));
+        assert!(!policy.uses_tls(8080));
+    }
+
+    #[test]
+    fn test_policy_tls_skip_verify() {
+        let policy =
+            DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_TLS_SKIP_VERIFY, "true")]));
+        assert!(policy.tls_skip_verify);
+
+        let policy =
+            DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_TLS_SKIP_VERIFY, "yes")]));
+        assert!(!policy.tls_skip_verify);
+    }
+
+    #[test]
+    fn test_policy_tls_sni() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[(
+            ANNOTATION_TLS_SNI,
+            " App.Internal.example.com ",
+        )]));
+        assert_eq!(policy.tls_sni.as_deref(), Some("app.internal.example.com"));
+
+        for value in [
+            "",
+            "bad host",
+            "app.example.com.",
+            "app/path",
+            "ünicode.example",
+        ] {
+            let policy =
+                DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_TLS_SNI, value)]));
+            assert_eq!(policy.tls_sni, None, "{value}");
+        }
+    }
+
+    #[test]
+    fn test_policy_auth_required() {
+        let policy =
+            DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_AUTH_REQUIRED, "true")]));
+        assert!(policy.auth_required);
+    }
+
+    #[test]
+    fn test_policy_basic_auth() {
+        let hash = "$5$Jd8KH1n2QJbNQzWt$ktOmKQgKg2aBmNJksRjKOIUBcgDyUITocPevjz9gqn5";
+        let policy = DevboxPolicy::from_annotations(&annotations(&[(
+            ANNOTATION_BASIC_AUTH,
+            &format!("alice:{hash}"),
+        )]));
+        assert_eq!(
+            policy.basic_auth,
+            Some(BasicAuth::parse(&format!("alice:{hash}")).unwrap())
+        );
+
+        // An invalid or too costly gate denies everyone instead of
+        // disappearing
+        for value in [
+            "alice:$apr1$salt$hash",
+            "alice:$5$rounds=999999999$Jd8KH1n2QJbNQzWt$ktOmKQgKg2aBmNJksRjKOIUBcgDyUITocPevjz9gqn5",
+        ] {
+            let policy =
+                DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_BASIC_AUTH, value)]));
+            assert_eq!(policy.basic_auth, Some(BasicAuth::deny_all()), "{value}");
+        }
+    }
+
+    #[test]
+    fn test_policy_skip_path_normalization() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[(
+            ANNOTATION_SKIP_PATH_NORMALIZATION,
+            "true",
+        )]));
+        assert!(policy.skip_path_normalization);
+    }
+
+    #[test]
+    fn test_policy_allowed_cidrs() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[]));
+        assert_eq!(policy.allowed_cidrs, None);
+        let policy =
+            DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_ALLOWED_CIDRS, " ")]));
+        assert_eq!(policy.allowed_cidrs, None);
+
+        let policy = DevboxPolicy::from_annotations(&annotations(&[(
+            ANNOTATION_ALLOWED_CIDRS,
+            "10.8.0.0/16,fd00::/8",
+        )]));
+        let cidrs = policy.allowed_cidrs.unwrap();
+        assert!(cidrs.contains("10.8.3.4".parse().unwrap()));
+        assert!(cidrs.contains("fd12::1".parse().unwrap()));
+        assert!(!cidrs.contains("10.9.0.1".parse().unwrap()));
+
+        // One malformed entry disables the restriction instead of blocking
+        // everyone
+        let policy = DevboxPolicy::from_annotations(&annotations(&[(
+            ANNOTATION_ALLOWED_CIDRS,
+            "10.8.0.0/16,10.8.0.0/99",
+        )]));
+        assert_eq!(policy.allowed_cidrs, None);
+    }
+
+    #[test]
+    fn test_policy_response_cache() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[]));
+        assert_eq!(policy.response_cache, None);
+
+        for (value, expected) in [("true", Some(true)), ("false", Some(false))] {
+            let policy =
+                DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_RESPONSE_CACHE, value)]));
+            assert_eq!(policy.response_cache, expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn test_policy_base_href() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[]));
+        assert_eq!(policy.base_href, None);
+
+        for (value, expected) in [
+            ("/app/", Some("/app/")),
+            (
+                " https://example.com/app/ ",
+                Some("https://example.com/app/"),
+            ),
+            ("/a?b=1&c=2", Some("/a?b=1&c=2")),
+            ("", None),
+            ("/app/\"><script>", None),
+            ("/my app/", None),
+        ] {
+            let policy =
+                DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_BASE_HREF, value)]));
+            assert_eq!(policy.base_href.as_deref(), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn test_policy_path_prefixes() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[]));
+        assert_eq!((policy.strip_prefix, policy.add_prefix), (None, None));
+
+        for (value, expected) in [
+            ("/app", Some("/app")),
+            (" /app/ ", Some("/app")),
+            ("/a/b//", Some("/a/b")),
+            ("/my%20app", Some("/my%20app")),
+            ("/", None),
+            ("", None),
+            ("app", None),
+            ("//evil.example.com", None),
+            ("/app?x=1", None),
+            ("/app#top", None),
+            ("/my app", None),
+        ] {
+            let policy = DevboxPolicy::from_annotations(&annotations(&[
+                (ANNOTATION_STRIP_PREFIX, value),
+                (ANNOTATION_ADD_PREFIX, value),
+            ]));
+            assert_eq!(policy.strip_prefix.as_deref(), expected, "{value}");
+            assert_eq!(policy.add_prefix.as_deref(), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn test_policy_client_ca() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[]));
+        assert_eq!(policy.client_ca, None);
+
+        let policy =
+            DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_CLIENT_CA, "configmap/ca")]));
+        assert_eq!(policy.client_ca, ClientCaRef::parse("configmap/ca").ok());
+
+        // A typo must not open the devbox to every client
+        let policy =
+            DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_CLIENT_CA, "secrets/ca")]));
+        assert_eq!(policy.client_ca, Some(ClientCaRef::deny_all()));
+    }
+
+    #[test]
+    fn test_policy_ws_allowed_origins() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[]));
+        assert_eq!(policy.ws_allowed_origins, None);
+
+        let policy = DevboxPolicy::from_annotations(&annotations(&[(
+            ANNOTATION_WS_ALLOWED_ORIGINS,
+            "same-host, *.example.com, not a host",
+        )]));
+        let origins = policy.ws_allowed_origins.unwrap();
+        assert!(origins.allows(Some("https://app.example.com"), "devbox-a-1-80.local"));
+        assert!(origins.allows(Some("https://devbox-a-1-80.local"), "devbox-a-1-80.local"));
+        assert!(!origins.allows(Some("https://evil.net"), "devbox-a-1-80.local"));
+    }
+
+    #[test]
+    fn test_policy_cors_allowed_origins() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[(
+            ANNOTATION_CORS_ALLOWED_ORIGINS,
+            "https://app.example.com, https://example.com,",
+        )]));
+        assert_eq!(
+            policy.cors_allowed_origins,
+            vec!["https://app.example.com", "https://example.com"]
+        );
+    }
+
+    #[test]
+    fn test_policy_cors() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[(
+            ANNOTATION_CORS,
+            "origins=https://a.example, https://b.example; methods=GET,POST; \
+             headers=content-type; max-age=60; credentials=true",
+        )]));
+        assert_eq!(
+            policy.cors,
+            Some(CorsPolicy {
+                origins: vec!["https://a.example".into(), "https://b.example".into()],
+                methods: Some("GET,POST".into()),
+                headers: Some("content-type".into()),
+                max_age_secs: Some(60),
+                allow_credentials: true,
+            })
+        );
+
+        // Only origins are required
+        let policy =
+            DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_CORS, "origins=*")]));
+        assert_eq!(
+            policy.cors,
+            Some(CorsPolicy {
+                origins: vec!["*".into()],
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_policy_cors_invalid() {
+        for value in [
+            // Browsers reject credentialed responses with a wildcard origin
+            "origins=*;credentials=true",
+            "origins=https://a.example,*;credentials=true",
+            "",
+            "methods=GET",
+            "origins=",
+            "origins=*;credentials=yes",
+            "origins=*;max-age=-1",
+            "origins=*;methods=GET\nPOST",
+            "origins=*;colour=blue",
+            "origins",
+        ] {
+            let policy = DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_CORS, value)]));
+            assert_eq!(policy.cors, None, "{value}");
+        }
+
+        // Without credentials a wildcard is fine
+        assert!(parse_cors("origins=*;credentials=false").is_ok());
+    }
+
+    #[test]
+    fn test_policy_deny_request_headers() {
+        let policy = DevboxPolicy::from_annotations(&annotations(&[(
+            ANNOTATION_DENY_REQUEST_HEADERS,
+            "X-Internal-Auth, x-user-token,bad header,",
+        )]));
+        assert_eq!(
+            policy.deny_request_headers,
+            vec![
+                HeaderName::from_static("x-internal-auth"),
+                HeaderName::from_static("x-user-token"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_policy_mirror() {
+        for (value, expected) in [
+            ("8080->8081@10%", Some((8080, 8081, 10))),
+            (" 8080 -> 8081 @ 25 % ", Some((8080, 8081, 25))),
+            ("3000->3001", Some((3000, 3001, 100))),
+            ("8080->8081@100%", Some((8080, 8081, 100))),
+            ("8080->8081@0%", None),
+            ("8080->8081@101%", None),
+            ("8080->8081@10", None),
+            ("8080->8081@2.5%", None),
+            ("8080->8080", None),
+            ("8080-8081", None),
+            ("8080->", None),
+            ("0->8081", None),
+            ("8080->70000", None),
+        ] {
+            let policy =
+                DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_MIRROR, value)]));
+            assert_eq!(
+                policy.mirror,
+                expected.map(|(port, mirror_port, percent)| MirrorPolicy {
+                    port,
+                    shadow: None,
+                    mirror_port,
+                    percent,
+                }),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_policy_mirror_shadow() {
+        for (value, expected) in [
+            ("8080->shadow-app-1234:8080@10%", Some((8080, 10))),
+            (" 8080 -> shadow-app-1234 : 9090 ", Some((9090, 100))),
+            ("8080->Shadow:8080", None),
+            ("8080->-shadow:8080", None),
+            ("8080->:8080", None),
+            ("8080->shadow-app-1234:", None),
+            ("8080->shadow-app-1234:0", None),
+        ] {
+            let policy =
+                DevboxPolicy::from_annotations(&annotations(&[(ANNOTATION_MIRROR, value)]));
+            assert_eq!(
+                policy.mirror,
+                expected.map(|(mirror_port, percent)| MirrorPolicy {
+                    port: 8080,
+                    shadow: Some("shadow-app-1234".to_string()),
+                    mirror_port,
+                    percent,
+                }),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_policy_keeps_its_annotations() {
+        let source = annotations(&[
+            (ANNOTATION_TLS_PORTS, "8443"),
+            (ANNOTATION_CORS, "origins=https://app.example.com"),
+            ("devbox.sealos.io/template", "ubuntu"),
+        ]);
+        let policy = DevboxPolicy::from_annotations(&source);
+        assert_eq!(
+            policy.annotations,
+            annotations(&[
+                (ANNOTATION_TLS_PORTS, "8443"),
+                (ANNOTATION_CORS, "origins=https://app.example.com"),
+            ])
+        );
+        assert_eq!(DevboxPolicy::from_annotations(&policy.annotations), policy);
+    }
+}
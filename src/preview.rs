@@ -0,0 +1,281 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use http::header::COOKIE;
+use http::uri::{PathAndQuery, Uri};
+use pingora_http::RequestHeader;
+use sha2::Sha256;
+
+/// Query parameter carrying a preview token on the first visit
+pub const TOKEN_QUERY_PARAM: &str = "hg_token";
+
+/// Cookie the token is exchanged for, so later requests (assets, XHR) are
+/// authorized without the query parameter
+pub const TOKEN_COOKIE: &str = "hg_token";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Why a preview token was not accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    /// No token in the query or cookies (or signing is disabled)
+    Missing,
+    /// Not of the form `<expiry>.<signature>`
+    Malformed,
+    /// The expiry has passed
+    Expired,
+    /// The signature does not match this devbox and port
+    BadSignature,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Missing => "preview token required",
+            Self::Malformed => "malformed preview token",
+            Self::Expired => "preview token expired",
+            Self::BadSignature => "invalid preview token",
+        })
+    }
+}
+
+/// Mints and verifies time-limited preview tokens.
+///
+/// A token is `<expiry>.<hex HMAC-SHA256>` over the devbox uniqueID, port
+/// and expiry (Unix seconds), keyed with `SIGNING_KEY`. It is only valid
+/// for the exact devbox and port it was minted for.
+pub struct PreviewSigner {
+    key: Vec<u8>,
+}
+
+impl PreviewSigner {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+
+    fn mac(&self, unique_id: &str, port: u16, expires_at: u64) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(format!("{unique_id}\n{port}\n{expires_at}").as_bytes());
+        mac
+    }
+
+    /// Mint a token for `unique_id` and `port` valid until `expires_at`.
+    pub fn mint(&self, unique_id: &str, port: u16, expires_at: u64) -> String {
+        let signature = self
+            .mac(unique_id, port, expires_at)
+            .finalize()
+            .into_bytes();
+        format!("{expires_at}.{}", hex::encode(signature))
+    }
+
+    /// Verify a token for `unique_id` and `port`, returning its expiry.
+    pub fn verify(
+        &self,
+        token: &str,
+        unique_id: &str,
+        port: u16,
+        now: u64,
+    ) -> Result<u64, TokenError> {
+        let (expires_at, signature) = token.split_once('.').ok_or(TokenError::Malformed)?;
+        let expires_at: u64 = expires_at.parse().map_err(|_| TokenError::Malformed)?;
+        let signature = hex::decode(signature).map_err(|_| TokenError::Malformed)?;
+
+        // Check the signature first so an expired token can't be told apart
+        // from a forged one by its expiry alone
+        self.mac(unique_id, port, expires_at)
+            .verify_slice(&signature)
+            .map_err(|_| TokenError::BadSignature)?;
+        if expires_at <= now {
+            return Err(TokenError::Expired);
+        }
+        Ok(expires_at)
+    }
+}
+
+/// Current Unix time in seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Token from the query string of `uri`, if any.
+pub fn query_token(uri: &Uri) -> Option<&str> {
+    uri.query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(TOKEN_QUERY_PARAM)?.strip_prefix('='))
+}
+
+/// Token from the request cookies, if any.
+pub fn cookie_token(req: &RequestHeader) -> Option<&str> {
+    req.headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|c| c.trim().strip_prefix(TOKEN_COOKIE)?.strip_prefix('='))
+}
+
+/// Remove the token query parameter and cookie so they never reach the
+/// backend.
+pub fn strip_token(req: &mut RequestHeader) {
+    if query_token(&req.uri).is_some() {
+        if let Some(uri) = strip_query_param(&req.uri) {
+            req.set_uri(uri);
+        }
+    }
+
+    if cookie_token(req).is_some() {
+        let cookies: Vec<String> = req
+            .headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .map(str::trim)
+            .filter(|c| !c.is_empty() && c.split('=').next() != Some(TOKEN_COOKIE))
+            .map(String::from)
+            .collect();
+        req.remove_header(&COOKIE);
+        if !cookies.is_empty() {
+            // Values come from a valid header, so they are still valid
+            let _ = req.insert_header(COOKIE, cookies.join("; "));
+        }
+    }
+}
+
+fn strip_query_param(uri: &Uri) -> Option<Uri> {
+    let query: Vec<&str> = uri
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some(TOKEN_QUERY_PARAM))
+        .collect();
+    let path_and_query = if query.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{}", uri.path(), query.join("&"))
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// `Set-Cookie` value storing a token for the rest of its lifetime.
+pub fn token_cookie(token: &str, max_age_secs: u64) -> String {
+    format!(
+        "{TOKEN_COOKIE}={token}; Max-Age={max_age_secs}; Path=/; HttpOnly; Secure; SameSite=Lax"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn signer() -> PreviewSigner {
+        PreviewSigner::new("test-signing-key")
+    }
+
+    #[test]
+    fn test_mint_and_verify() {
+        let token = signer().mint("my-app", 8080, NOW + 3600);
+        assert_eq!(signer().verify(&token, "my-app", 8080, NOW), Ok(NOW + 3600));
+    }
+
+    #[test]
+    fn test_verify_expired() {
+        let token = signer().mint("my-app", 8080, NOW + 3600);
+        assert_eq!(
+            signer().verify(&token, "my-app", 8080, NOW + 3600),
+            Err(TokenError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_verify_wrong_devbox_or_port() {
+        let token = signer().mint("my-app", 8080, NOW + 3600);
+        assert_eq!(
+            signer().verify(&token, "other-app", 8080, NOW),
+            Err(TokenError::BadSignature)
+        );
+        assert_eq!(
+            signer().verify(&token, "my-app", 3000, NOW),
+            Err(TokenError::BadSignature)
+        );
+    }
+
+    #[test]
+    fn test_verify_tampered() {
+        let token = signer().mint("my-app", 8080, NOW + 3600);
+        let (_, signature) = token.split_once('.').unwrap();
+
+        // Extended expiry
+        let extended = format!("{}.{signature}", NOW + 86400);
+        assert_eq!(
+            signer().verify(&extended, "my-app", 8080, NOW),
+            Err(TokenError::BadSignature)
+        );
+
+        // Flipped signature bit
+        let mut bytes = hex::decode(signature).unwrap();
+        bytes[0] ^= 1;
+        let flipped = format!("{}.{}", NOW + 3600, hex::encode(bytes));
+        assert_eq!(
+            signer().verify(&flipped, "my-app", 8080, NOW),
+            Err(TokenError::BadSignature)
+        );
+
+        // Different key
+        let other = PreviewSigner::new("other-key").mint("my-app", 8080, NOW + 3600);
+        assert_eq!(
+            signer().verify(&other, "my-app", 8080, NOW),
+            Err(TokenError::BadSignature)
+        );
+
+        for malformed in ["", "abc", "123", "x.00", "123.zz"] {
+            assert_eq!(
+                signer().verify(malformed, "my-app", 8080, NOW),
+                Err(TokenError::Malformed),
+                "{malformed}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_tokens_from_request() {
+        let mut req = RequestHeader::build("GET", b"/app?a=1&hg_token=abc&b=2", None).unwrap();
+        assert_eq!(query_token(&req.uri), Some("abc"));
+        assert_eq!(cookie_token(&req), None);
+
+        req.insert_header("Cookie", "session=x; hg_token=def; theme=dark")
+            .unwrap();
+        assert_eq!(cookie_token(&req), Some("def"));
+    }
+
+    #[test]
+    fn test_strip_token() {
+        let mut req = RequestHeader::build("GET", b"/app?a=1&hg_token=abc&b=2", None).unwrap();
+        req.insert_header("Cookie", "session=x; hg_token=def; theme=dark")
+            .unwrap();
+        strip_token(&mut req);
+        assert_eq!(req.uri.to_string(), "/app?a=1&b=2");
+        assert_eq!(req.headers.get("cookie").unwrap(), "session=x; theme=dark");
+
+        // Nothing left
+        let mut req = RequestHeader::build("GET", b"/?hg_token=abc", None).unwrap();
+        req.insert_header("Cookie", "hg_token=def").unwrap();
+        strip_token(&mut req);
+        assert_eq!(req.uri.to_string(), "/");
+        assert!(req.headers.get("cookie").is_none());
+
+        // Similar names are kept
+        let mut req = RequestHeader::build("GET", b"/?hg_token_x=1", None).unwrap();
+        strip_token(&mut req);
+        assert_eq!(req.uri.to_string(), "/?hg_token_x=1");
+    }
+}
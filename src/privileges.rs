@@ -0,0 +1,664 @@
+//! Binding the listeners before dropping root privileges.
+//!
+//! Ports below 1024 can only be bound as root. With `RUN_AS_USER` or
+//! `RUN_AS_GROUP` the gateway binds the address of every service at
+//! startup, switches to the configured user and group, checks that the
+//! files it keeps reading and writing are still accessible, and only then
+//! lets Pingora serve on the sockets bound for it. With `SOCKET_ACTIVATION`
+//! the listening sockets passed by systemd are adopted instead of bound.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::net::{SocketAddr, TcpListener};
+use std::os::fd::{IntoRawFd, OwnedFd};
+use std::path::Path;
+
+use async_trait::async_trait;
+use pingora_core::server::{ListenFds, ShutdownWatch};
+use pingora_core::services::Service;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+
+/// User and group the gateway switches to once its listeners are bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunAs {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+impl RunAs {
+    /// Resolve `RUN_AS_USER` and `RUN_AS_GROUP`, each a name or a numeric id.
+    ///
+    /// Without a group the user's primary group is used; without a user only
+    /// the group changes. A uid missing from the user database needs an
+    /// explicit group.
+    pub fn resolve(user: Option<&str>, group: Option<&str>) -> Result<Self> {
+        let (uid, primary_gid) = match user {
+            Some(user) => lookup_user(user)?,
+            // SAFETY: getting the effective ids can't fail
+            None => unsafe { (libc::geteuid(), Some(libc::getegid())) },
+        };
+        let gid = match (group, primary_gid) {
+            (Some(group), _) => lookup_group(group)?,
+            (None, Some(gid)) => gid,
+            (None, None) => {
+                return Err(Error::Config(format!(
+                    "User {uid} has no primary group, set RUN_AS_GROUP"
+                )))
+            }
+        };
+        Ok(Self { uid, gid })
+    }
+
+    /// Switch to the user and group, leaving every supplementary group.
+    ///
+    /// Fails if root can be regained afterwards.
+    pub fn apply(&self) -> Result<()> {
+        // The groups go first, only root can change them
+        // SAFETY: plain system calls on ids and a one-element array
+        unsafe {
+            if libc::geteuid() == 0 && libc::setgroups(1, [self.gid].as_ptr()) != 0 {
+                return Err(os_error("set supplementary groups"));
+            }
+            if libc::setgid(self.gid) != 0 {
+                return Err(os_error(&format!("switch to group {}", self.gid)));
+            }
+            if libc::setuid(self.uid) != 0 {
+                return Err(os_error(&format!("switch to user {}", self.uid)));
+            }
+            if self.uid != 0 && libc::setuid(0) == 0 {
+                return Err(Error::Config(
+                    "Root privileges can be regained after switching user".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error of the system call that just failed.
+fn os_error(action: &str) -> Error {
+    Error::Config(format!(
+        "Failed to {action}: {}",
+        std::io::Error::last_os_error()
+    ))
+}
+
+/// Size of the buffer the user and group database entries are read into.
+const DATABASE_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Uid and primary gid of a user name or uid.
+fn lookup_user(user: &str) -> Result<(libc::uid_t, Option<libc::gid_t>)> {
+    let id = user.parse::<libc::uid_t>().ok();
+    let name = CString::new(user).map_err(|_| Error::Config(format!("Invalid user {user:?}")))?;
+    let mut buf = vec![0; DATABASE_BUFFER_SIZE];
+    // SAFETY: all-zero is a valid passwd, only read once filled in
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: the buffers outlive the calls and their sizes are passed along
+    let rc = unsafe {
+        match id {
+            Some(uid) => libc::getpwuid_r(uid, &mut entry, buf.as_mut_ptr(), buf.len(), &mut found),
+            None => libc::getpwnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            ),
+        }
+    };
+    if rc != 0 {
+        return Err(Error::Config(format!(
+            "Failed to look up user {user}: {}",
+            std::io::Error::from_raw_os_error(rc)
+        )));
+    }
+    match (found.is_null(), id) {
+        (false, _) => Ok((entry.pw_uid, Some(entry.pw_gid))),
+        (true, Some(uid)) => Ok((uid, None)),
+        (true, None) => Err(Error::Config(format!("Unknown user {user}"))),
+    }
+}
+
+/// Gid of a group name or gid.
+fn lookup_group(group: &str) -> Result<libc::gid_t> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name =
+        CString::new(group).map_err(|_| Error::Config(format!("Invalid group {group:?}")))?;
+    let mut buf = vec![0; DATABASE_BUFFER_SIZE];
+    // SAFETY: all-zero is a valid group, only read once filled in
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: the buffers outlive the call and their sizes are passed along
+    let rc = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 {
+        return Err(Error::Config(format!(
+            "Failed to look up group {group}: {}",
+            std::io::Error::from_raw_os_error(rc)
+        )));
+    }
+    if found.is_null() {
+        return Err(Error::Config(format!("Unknown group {group}")));
+    }
+    Ok(entry.gr_gid)
+}
+
+/// How a file is used once the privileges are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// The file is read
+    Read,
+    /// The directory is listed and its files read
+    List,
+    /// Files are created in the directory
+    Create,
+}
+
+/// File the gateway keeps using after dropping its privileges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequiredPath {
+    /// What the file is, for error messages
+    pub purpose: String,
+    pub path: String,
+    pub access: Access,
+}
+
+impl RequiredPath {
+    fn new(purpose: impl Into<String>, path: impl Into<String>, access: Access) -> Self {
+        Self {
+            purpose: purpose.into(),
+            path: path.into(),
+            access,
+        }
+    }
+
+    /// Check the file can still be used as needed.
+    fn check(&self) -> std::io::Result<()> {
+        match self.access {
+            Access::Read => File::open(&self.path).map(drop),
+            Access::List => std::fs::read_dir(&self.path).map(drop),
+            Access::Create => {
+                let probe =
+                    Path::new(&self.path).join(format!(".httpgate-access-{}", std::process::id()));
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&probe)?;
+                std::fs::remove_file(&probe)
+            }
+        }
+    }
+}
+
+/// Files of the configuration read or written after startup.
+///
+/// Files only written elsewhere and then renamed over (registry snapshot,
+/// warm-up state, pid file) need their directory to be writable.
+pub fn required_paths(config: &Config) -> Vec<RequiredPath> {
+    let mut paths = Vec::new();
+    let parent = |path: &str| match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_string_lossy().into_owned(),
+        _ => ".".to_string(),
+    };
+    if let Some(path) = &config.upstream_ca_file {
+        paths.push(RequiredPath::new("upstream CA bundle", path, Access::Read));
+    }
+    for listener in &config.listeners {
+        let name = &listener.policy.name;
+        if let Some(tls) = &listener.tls {
+            let certificate = format!("certificate of listener {name}");
+            paths.push(RequiredPath::new(certificate, &tls.cert_path, Access::Read));
+            let key = format!("key of listener {name}");
+            paths.push(RequiredPath::new(key, &tls.key_path, Access::Read));
+        }
+        if let Some(dir) = &listener.tls_cert_dir {
+            let purpose = format!("certificate directory of listener {name}");
+            paths.push(RequiredPath::new(purpose, dir, Access::List));
+        }
+    }
+    if config.listeners.iter().any(|l| l.acme) {
+        paths.push(RequiredPath::new(
+            "ACME cache",
+            &config.acme_cache_dir,
+            Access::Create,
+        ));
+    }
+    if let Some(path) = &config.blocklist_file {
+        paths.push(RequiredPath::new("blocklist", path, Access::Read));
+    }
+    if let Some(dir) = &config.locales_dir {
+        paths.push(RequiredPath::new("locales", dir, Access::List));
+    }
+    if let Some(path) = &config.registry_snapshot_file {
+        let dir = parent(path);
+        paths.push(RequiredPath::new("registry snapshot", dir, Access::Create));
+    }
+    if let Some(path) = &config.warmup_state_file {
+        let dir = parent(path);
+        paths.push(RequiredPath::new("warm-up state", dir, Access::Create));
+    }
+    if let Some(path) = config
+        .server
+        .pid_file
+        .as_ref()
+        .filter(|_| config.server.daemon)
+    {
+        paths.push(RequiredPath::new("pid file", parent(path), Access::Create));
+    }
+    paths
+}
+
+/// Check every file can still be used, reporting all that can't at once.
+pub fn check_access(paths: &[RequiredPath]) -> Result<()> {
+    let errors: Vec<_> = paths
+        .iter()
+        .filter_map(|required| {
+            let e = required.check().err()?;
+            Some(format!("{} {}: {e}", required.purpose, required.path))
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Config(format!(
+            "Inaccessible after dropping privileges: {}",
+            errors.join("; ")
+        )))
+    }
+}
+
+/// First file descriptor passed by systemd.
+#[cfg(target_os = "linux")]
+const LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+/// Listening sockets passed by systemd socket activation.
+///
+/// The variables are unset afterwards so that no child adopts them too.
+#[cfg(target_os = "linux")]
+pub fn listen_fds() -> Result<Vec<TcpListener>> {
+    let fds = activated_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    )?;
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(name);
+    }
+    fds.map(adopt_fd).collect()
+}
+
+/// File descriptors passed by systemd, given `LISTEN_PID` and `LISTEN_FDS`.
+#[cfg(target_os = "linux")]
+fn activated_fds(
+    pid: Option<&str>,
+    count: Option<&str>,
+    own_pid: u32,
+) -> Result<std::ops::Range<std::os::fd::RawFd>> {
+    let (Some(pid), Some(count)) = (pid, count) else {
+        return Err(Error::Config(
+            "SOCKET_ACTIVATION is set but no sockets were passed (LISTEN_PID and LISTEN_FDS are unset)"
+                .to_string(),
+        ));
+    };
+    if pid.parse::<u32>().ok() != Some(own_pid) {
+        return Err(Error::Config(format!(
+            "Sockets were passed to process {pid}, not to this one ({own_pid})"
+        )));
+    }
+    let count: std::os::fd::RawFd = count
+        .parse()
+        .map_err(|e| Error::Config(format!("Invalid LISTEN_FDS {count:?}: {e}")))?;
+    Ok(LISTEN_FDS_START..LISTEN_FDS_START + count)
+}
+
+/// Take over a listening TCP socket passed by systemd.
+#[cfg(target_os = "linux")]
+fn adopt_fd(fd: std::os::fd::RawFd) -> Result<TcpListener> {
+    use std::os::fd::FromRawFd;
+
+    let mut listening: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the option is read into an int of the size passed along
+    let rc = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            (&mut listening as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(os_error(&format!("adopt passed socket {fd}")));
+    }
+    if listening == 0 {
+        return Err(Error::Config(format!(
+            "Passed socket {fd} is not listening"
+        )));
+    }
+    // SAFETY: the descriptor is a listening socket nothing else owns
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    // Not inherited by anything the gateway spawns
+    // SAFETY: setting a flag on a descriptor that is owned here
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+        return Err(os_error(&format!("adopt passed socket {fd}")));
+    }
+    let addr = listener
+        .local_addr()
+        .map_err(|e| Error::Config(format!("Passed socket {fd} is not TCP: {e}")))?;
+    info!(fd = fd, addr = %addr, "Adopted socket-activated listener");
+    Ok(listener)
+}
+
+/// Listening sockets bound before the privileges are dropped, by address.
+#[derive(Debug, Default)]
+pub struct BoundListeners {
+    listeners: HashMap<String, TcpListener>,
+}
+
+impl BoundListeners {
+    /// Bind every address, adopting the socket-activated listener of an
+    /// address instead where one was passed.
+    ///
+    /// A passed socket no service listens on is an error, as it would
+    /// otherwise silently accept connections nothing answers.
+    pub fn bind(
+        addrs: impl IntoIterator<Item = SocketAddr>,
+        activated: Vec<TcpListener>,
+    ) -> Result<Self> {
+        let mut activated: HashMap<SocketAddr, TcpListener> = activated
+            .into_iter()
+            .map(|listener| Ok((listener.local_addr()?, listener)))
+            .collect::<std::io::Result<_>>()
+            .map_err(|e| Error::Config(format!("Invalid socket-activated listener: {e}")))?;
+        let mut listeners = HashMap::new();
+        for addr in addrs {
+            let listener = match activated.remove(&addr) {
+                Some(listener) => listener,
+                None => TcpListener::bind(addr)
+                    .map_err(|e| Error::Config(format!("Failed to bind {addr}: {e}")))?,
+            };
+            listener
+                .set_nonblocking(true)
+                .map_err(|e| Error::Config(format!("Failed to set up listener {addr}: {e}")))?;
+            listeners.insert(addr.to_string(), listener);
+        }
+        if let Some(addr) = activated.keys().next() {
+            return Err(Error::Config(format!(
+                "Socket-activated listener {addr} matches no configured address"
+            )));
+        }
+        Ok(Self { listeners })
+    }
+
+    /// Take the socket bound for `addr`, as written in the service.
+    pub fn take(&mut self, addr: &str) -> Option<OwnedFd> {
+        self.listeners.remove(addr).map(OwnedFd::from)
+    }
+
+    pub fn len(&self) -> usize {
+        self.listeners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+}
+
+/// Addresses of every service of the configuration.
+pub fn listen_addrs(config: &Config) -> Vec<SocketAddr> {
+    let mut addrs: Vec<_> = config.listeners.iter().map(|l| l.listen_addr).collect();
+    addrs.extend(config.metrics_addr);
+    addrs.extend(config.tcp_passthrough_addr);
+    addrs.extend(config.admin_addr);
+    addrs.extend(config.grpc_health_addr);
+    addrs
+}
+
+/// Bind the listeners of every service (or adopt the sockets passed by
+/// systemd), then switch to the configured user and group.
+pub fn bind_and_drop(config: &Config) -> Result<BoundListeners> {
+    let privileges = &config.privileges;
+    #[cfg(target_os = "linux")]
+    let activated = if privileges.socket_activation {
+        listen_fds()?
+    } else {
+        Vec::new()
+    };
+    // Rejected by the configuration elsewhere
+    #[cfg(not(target_os = "linux"))]
+    let activated = Vec::new();
+
+    let bound = BoundListeners::bind(listen_addrs(config), activated)?;
+    info!(listeners = bound.len(), "Bound listeners before serving");
+
+    if privileges.run_as_user.is_some() || privileges.run_as_group.is_some() {
+        let run_as = RunAs::resolve(
+            privileges.run_as_user.as_deref(),
+            privileges.run_as_group.as_deref(),
+        )?;
+        run_as.apply()?;
+        check_access(&required_paths(config))?;
+        info!(uid = run_as.uid, gid = run_as.gid, "Dropped privileges");
+    }
+    Ok(bound)
+}
+
+/// Pingora service listening on a socket bound before it starts.
+///
+/// Pingora binds the address of a service when the service starts, unless
+/// its table of listening sockets (filled on upgrades) already has one for
+/// the address; the socket is put in that table first.
+pub struct Prebound<S> {
+    service: S,
+    addr: String,
+    fd: Option<OwnedFd>,
+}
+
+impl<S> Prebound<S> {
+    pub fn new(service: S, addr: impl Into<String>, fd: OwnedFd) -> Self {
+        Self {
+            service,
+            addr: addr.into(),
+            fd: Some(fd),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Service> Service for Prebound<S> {
+    async fn start_service(
+        &mut self,
+        fds: Option<ListenFds>,
+        shutdown: ShutdownWatch,
+        listeners_per_fd: usize,
+    ) {
+        match (&fds, self.fd.take()) {
+            (Some(table), Some(fd)) => {
+                let mut table = table.lock().await;
+                // A socket taken over from the upgraded instance wins
+                if table.get(&self.addr).is_none() {
+                    table.add(self.addr.clone(), fd.into_raw_fd());
+                }
+            }
+            (None, Some(_)) => warn!(
+                addr = %self.addr,
+                "No listening socket table, binding the address again"
+            ),
+            (_, None) => {}
+        }
+        self.service
+            .start_service(fds, shutdown, listeners_per_fd)
+            .await;
+    }
+
+    fn name(&self) -> &str {
+        self.service.name()
+    }
+
+    fn threads(&self) -> Option<usize> {
+        self.service.threads()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("httpgate-privileges-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_resolve_run_as() {
+        let root = RunAs::resolve(Some("root"), None).unwrap();
+        assert_eq!(root, RunAs { uid: 0, gid: 0 });
+        assert_eq!(RunAs::resolve(Some("0"), Some("0")).unwrap(), root);
+
+        // Numeric ids need no database entry, but then the group is required
+        let nobody = RunAs::resolve(Some("65533"), Some("65533")).unwrap();
+        assert_eq!(
+            nobody,
+            RunAs {
+                uid: 65533,
+                gid: 65533
+            }
+        );
+        assert!(RunAs::resolve(Some("65533"), None).is_err());
+
+        assert!(RunAs::resolve(Some("no-such-user"), None).is_err());
+        assert!(RunAs::resolve(Some("root"), Some("no-such-group")).is_err());
+        assert!(RunAs::resolve(Some("ro\0ot"), None).is_err());
+    }
+
+    #[test]
+    fn test_check_access() {
+        let dir = temp_dir("access");
+        let file = dir.join("ca.crt");
+        std::fs::write(&file, "").unwrap();
+        let file = file.to_string_lossy().into_owned();
+        let dir = dir.to_string_lossy().into_owned();
+        let missing = format!("{dir}/missing");
+
+        let accessible = [
+            RequiredPath::new("upstream CA bundle", &file, Access::Read),
+            RequiredPath::new("locales", &dir, Access::List),
+            RequiredPath::new("registry snapshot", &dir, Access::Create),
+        ];
+        assert!(check_access(&accessible).is_ok());
+        // The probe file is cleaned up
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let inaccessible = [
+            RequiredPath::new("blocklist", &missing, Access::Read),
+            RequiredPath::new("locales", &file, Access::List),
+            RequiredPath::new("warm-up state", &missing, Access::Create),
+        ];
+        let message = check_access(&inaccessible).unwrap_err().to_string();
+        assert!(
+            message.contains(&format!("blocklist {missing}")),
+            "{message}"
+        );
+        assert!(message.contains(&format!("locales {file}")), "{message}");
+        assert!(message.contains("warm-up state"), "{message}");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_required_paths() {
+        let mut config = Config {
+            registry_snapshot_file: Some("/var/lib/httpgate/registry.json".to_string()),
+            warmup_state_file: Some("warmup.json".to_string()),
+            blocklist_file: Some("/etc/httpgate/blocklist".to_string()),
+            ..Config::default()
+        };
+        config.listeners[0].tls_cert_dir = Some("/tls/certs".to_string());
+        config.server.pid_file = Some("/run/httpgate.pid".to_string());
+
+        let paths = required_paths(&config);
+        let find = |purpose: &str| {
+            let required = paths.iter().find(|p| p.purpose == purpose).unwrap();
+            (required.path.as_str(), required.access)
+        };
+        assert_eq!(
+            find("registry snapshot"),
+            ("/var/lib/httpgate", Access::Create)
+        );
+        assert_eq!(find("warm-up state"), (".", Access::Create));
+        assert_eq!(find("blocklist"), ("/etc/httpgate/blocklist", Access::Read));
+        assert_eq!(
+            find("certificate directory of listener default"),
+            ("/tls/certs", Access::List)
+        );
+        // The pid file is only written by a daemon
+        assert!(!paths.iter().any(|p| p.purpose == "pid file"));
+        assert!(!paths.iter().any(|p| p.purpose == "ACME cache"));
+    }
+
+    #[test]
+    fn test_bound_listeners() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let taken_addr = taken.local_addr().unwrap();
+        let activated = TcpListener::bind("127.0.0.1:0").unwrap();
+        let activated_addr = activated.local_addr().unwrap();
+
+        // An address already in use fails to bind
+        assert!(BoundListeners::bind([taken_addr], Vec::new()).is_err());
+
+        let mut bound = BoundListeners::bind([activated_addr], vec![activated]).unwrap();
+        assert_eq!(bound.len(), 1);
+        let fd = bound.take(&activated_addr.to_string()).unwrap();
+        assert_eq!(TcpListener::from(fd).local_addr().unwrap(), activated_addr);
+        assert!(bound.is_empty());
+
+        let unused = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert!(BoundListeners::bind(Vec::new(), vec![unused]).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_activated_fds() {
+        assert_eq!(activated_fds(Some("42"), Some("2"), 42).unwrap(), 3..5);
+        assert_eq!(activated_fds(Some("42"), Some("0"), 42).unwrap(), 3..3);
+        // Passed to the parent, which then started this process
+        assert!(activated_fds(Some("41"), Some("2"), 42).is_err());
+        assert!(activated_fds(None, None, 42).is_err());
+        assert!(activated_fds(Some("42"), Some("two"), 42).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_adopt_fd() {
+        use std::os::fd::AsRawFd;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let adopted = adopt_fd(listener.into_raw_fd()).unwrap();
+        assert_eq!(adopted.local_addr().unwrap(), addr);
+        // SAFETY: reading the flags of a descriptor owned by the test
+        let flags = unsafe { libc::fcntl(adopted.as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+
+        // Sockets that don't accept connections are refused
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(adopt_fd(socket.as_raw_fd()).is_err());
+    }
+}
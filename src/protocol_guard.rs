@@ -0,0 +1,360 @@
+use std::fmt;
+
+use http::header::HOST;
+use http::{Method, Version};
+use pingora_http::RequestHeader;
+
+use crate::config::Config;
+use crate::headers::{self, FramingError};
+
+/// A request that is valid enough to parse but unusual for a client of the
+/// gateway, and often a sign of probing or request smuggling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolAnomaly {
+    /// Framing headers the backend may read differently
+    ConflictingFraming(FramingError),
+    /// An absolute-form request target naming another host than `Host`,
+    /// which servers following RFC 9112 route by instead
+    ForeignAbsoluteUri,
+    /// A TRACE request, which reflects the request back
+    Trace,
+    /// A CONNECT request, which asks for a tunnel
+    Connect,
+    /// A header block over `MAX_REQUEST_HEADER_BYTES`
+    OversizedHeaders,
+    /// Several `Host` headers, or one with characters no host name has
+    InvalidHost,
+}
+
+impl ProtocolAnomaly {
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::ConflictingFraming(_) => "conflicting_framing",
+            Self::ForeignAbsoluteUri => "foreign_absolute_uri",
+            Self::Trace => "trace",
+            Self::Connect => "connect",
+            Self::OversizedHeaders => "oversized_headers",
+            Self::InvalidHost => "invalid_host",
+        }
+    }
+}
+
+impl fmt::Display for ProtocolAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConflictingFraming(e) => e.fmt(f),
+            Self::ForeignAbsoluteUri => f.write_str("request target names another host"),
+            Self::Trace => f.write_str("TRACE is not supported"),
+            Self::Connect => f.write_str("CONNECT is not supported"),
+            Self::OversizedHeaders => f.write_str("request headers too large"),
+            Self::InvalidHost => f.write_str("invalid Host header"),
+        }
+    }
+}
+
+/// Checks requests for [`ProtocolAnomaly`]s.
+///
+/// In strict mode (`STRICT_PROTOCOL`), requests with an anomaly are
+/// rejected; otherwise they are only counted and logged, and go on to the
+/// checks that reject some of them anyway (e.g. CONNECT).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolGuard {
+    strict: bool,
+    max_header_bytes: usize,
+}
+
+impl ProtocolGuard {
+    pub const fn new(strict: bool, max_header_bytes: usize) -> Self {
+        Self {
+            strict,
+            max_header_bytes,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.strict_protocol, config.max_request_header_bytes)
+    }
+
+    /// Anomalies of `req`, and whether it is rejected for them.
+    pub fn inspect(&self, req: &RequestHeader) -> (Vec<ProtocolAnomaly>, bool) {
+        let anomalies = check(req, self.max_header_bytes);
+        let reject = self.strict && !anomalies.is_empty();
+        (anomalies, reject)
+    }
+}
+
+/// Every anomaly of `req`, allowing header blocks of `max_header_bytes`.
+pub fn check(req: &RequestHeader, max_header_bytes: usize) -> Vec<ProtocolAnomaly> {
+    let mut anomalies = Vec::new();
+    if let Err(e) = headers::check_framing(&req.headers) {
+        anomalies.push(ProtocolAnomaly::ConflictingFraming(e));
+    }
+    if foreign_absolute_uri(req) {
+        anomalies.push(ProtocolAnomaly::ForeignAbsoluteUri);
+    }
+    if req.method == Method::TRACE {
+        anomalies.push(ProtocolAnomaly::Trace);
+    }
+    if req.method == Method::CONNECT {
+        anomalies.push(ProtocolAnomaly::Connect);
+    }
+    if headers::header_bytes(&req.headers) > max_header_bytes {
+        anomalies.push(ProtocolAnomaly::OversizedHeaders);
+    }
+    if invalid_host(req) {
+        anomalies.push(ProtocolAnomaly::InvalidHost);
+    }
+    anomalies
+}
+
+/// Whether an HTTP/1 request in absolute form names another host than its
+/// `Host` header (ports aside). HTTP/2 requests always carry their
+/// authority in the URI.
+fn foreign_absolute_uri(req: &RequestHeader) -> bool {
+    if req.version == Version::HTTP_2 {
+        return false;
+    }
+    let (Some(authority), Some(host)) = (req.uri.authority(), req.headers.get(HOST)) else {
+        return false;
+    };
+    let (name, _) = split_port(host.as_bytes());
+    !name.eq_ignore_ascii_case(authority.host().as_bytes())
+}
+
+/// Whether the `Host` headers can't name a single host: there are several,
+/// or the value isn't a host name or IP literal with an optional port.
+fn invalid_host(req: &RequestHeader) -> bool {
+    let mut hosts = req.headers.get_all(HOST).iter();
+    let (Some(host), None) = (hosts.next(), hosts.next()) else {
+        return req.headers.contains_key(HOST);
+    };
+    let (name, port) = split_port(host.as_bytes());
+    let valid_name = match name {
+        [b'[', literal @ .., b']'] => {
+            !literal.is_empty()
+                && literal
+                    .iter()
+                    .all(|&b| b.is_ascii_hexdigit() || matches!(b, b':' | b'.'))
+        }
+        _ => {
+            !name.is_empty()
+                && name
+                    .iter()
+                    .all(|&b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_'))
+        }
+    };
+    let valid_port = port.is_none_or(|port| {
+        !port.is_empty() && port.len() <= 5 && port.iter().all(u8::is_ascii_digit)
+    });
+    !(valid_name && valid_port)
+}
+
+/// Split a `Host` value into the host and the port after its last colon,
+/// which isn't one of an IPv6 literal's.
+fn split_port(host: &[u8]) -> (&[u8], Option<&[u8]>) {
+    match host.iter().rposition(|&b| b == b':') {
+        Some(i) if !host.ends_with(b"]") => (&host[..i], Some(&host[i + 1..])),
+        _ => (host, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: usize = 1024;
+
+    /// Method, request target, version and headers of a request, and its
+    /// anomalies
+    type Case<'a> = (
+        &'a str,
+        &'a str,
+        Version,
+        &'a [(&'static str, &'a str)],
+        &'a [ProtocolAnomaly],
+    );
+
+    fn request(
+        method: &str,
+        target: &str,
+        version: Version,
+        headers: &[(&'static str, &str)],
+    ) -> RequestHeader {
+        let mut req = RequestHeader::build(method, target.as_bytes(), None).unwrap();
+        req.set_version(version);
+        for (name, value) in headers {
+            req.append_header(*name, *value).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn test_check() {
+        let host = ("host", "devbox-app-8080.devbox.local");
+        let long = "x".repeat(MAX);
+        let cases: &[Case] = &[
+            ("GET", "/", Version::HTTP_11, &[host], &[]),
+            (
+                "POST",
+                "/",
+                Version::HTTP_11,
+                &[host, ("content-length", "5"), ("content-length", "5")],
+                &[],
+            ),
+            (
+                "POST",
+                "/",
+                Version::HTTP_11,
+                &[
+                    host,
+                    ("content-length", "5"),
+                    ("transfer-encoding", "chunked"),
+                ],
+                &[ProtocolAnomaly::ConflictingFraming(
+                    FramingError::ContentLengthWithTransferEncoding,
+                )],
+            ),
+            (
+                "POST",
+                "/",
+                Version::HTTP_11,
+                &[host, ("content-length", "5"), ("content-length", "6")],
+                &[ProtocolAnomaly::ConflictingFraming(
+                    FramingError::ConflictingContentLength,
+                )],
+            ),
+            (
+                "GET",
+                "http://devbox-app-8080.devbox.local/",
+                Version::HTTP_11,
+                &[host],
+                &[],
+            ),
+            (
+                "GET",
+                "http://devbox-app-8080.devbox.local:80/",
+                Version::HTTP_11,
+                &[("host", "Devbox-App-8080.devbox.local")],
+                &[],
+            ),
+            (
+                "GET",
+                "http://internal.example.com/admin",
+                Version::HTTP_11,
+                &[host],
+                &[ProtocolAnomaly::ForeignAbsoluteUri],
+            ),
+            // HTTP/2 carries `:authority` in the URI
+            (
+                "GET",
+                "https://internal.example.com/",
+                Version::HTTP_2,
+                &[],
+                &[],
+            ),
+            (
+                "TRACE",
+                "/",
+                Version::HTTP_11,
+                &[host],
+                &[ProtocolAnomaly::Trace],
+            ),
+            (
+                "CONNECT",
+                "devbox-app-8080.devbox.local:443",
+                Version::HTTP_11,
+                &[("host", "devbox-app-8080.devbox.local:443")],
+                &[ProtocolAnomaly::Connect],
+            ),
+            (
+                "GET",
+                "/",
+                Version::HTTP_11,
+                &[host, ("x-padding", &long)],
+                &[ProtocolAnomaly::OversizedHeaders],
+            ),
+            (
+                "GET",
+                "/",
+                Version::HTTP_11,
+                &[host, ("host", "other.example.com")],
+                &[ProtocolAnomaly::InvalidHost],
+            ),
+            (
+                "TRACE",
+                "http://other.example.com/",
+                Version::HTTP_11,
+                &[("host", "a b")],
+                &[
+                    ProtocolAnomaly::ForeignAbsoluteUri,
+                    ProtocolAnomaly::Trace,
+                    ProtocolAnomaly::InvalidHost,
+                ],
+            ),
+        ];
+        for (method, target, version, headers, expected) in cases {
+            let req = request(method, target, *version, headers);
+            assert_eq!(
+                check(&req, MAX),
+                *expected,
+                "{method} {target} {version:?} {headers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_host() {
+        for (host, invalid) in [
+            ("devbox-app-8080.devbox.local", false),
+            ("Devbox-App-8080.Devbox.Local:8443", false),
+            ("127.0.0.1:80", false),
+            ("[::1]", false),
+            ("[2001:db8::1]:8443", false),
+            ("app_1.local", false),
+            ("", true),
+            ("a b", true),
+            ("evil.com/path", true),
+            ("evil.com@devbox.local", true),
+            ("devbox.local:", true),
+            ("devbox.local:99999x", true),
+            ("devbox.local:123456", true),
+            ("[]", true),
+            ("[::1", true),
+            ("host\"<script>", true),
+        ] {
+            let req = request("GET", "/", Version::HTTP_11, &[("host", host)]);
+            assert_eq!(invalid_host(&req), invalid, "{host:?}");
+        }
+
+        // A missing Host is left to routing
+        let req = request("GET", "/", Version::HTTP_11, &[]);
+        assert!(!invalid_host(&req));
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        let trace = request(
+            "TRACE",
+            "/",
+            Version::HTTP_11,
+            &[("host", "devbox-app-8080.devbox.local")],
+        );
+        let get = request(
+            "GET",
+            "/",
+            Version::HTTP_11,
+            &[("host", "devbox-app-8080.devbox.local")],
+        );
+
+        let lenient = ProtocolGuard::new(false, MAX);
+        assert_eq!(
+            lenient.inspect(&trace),
+            (vec![ProtocolAnomaly::Trace], false)
+        );
+        assert_eq!(lenient.inspect(&get), (Vec::new(), false));
+
+        let strict = ProtocolGuard::new(true, MAX);
+        assert_eq!(strict.inspect(&trace), (vec![ProtocolAnomaly::Trace], true));
+        assert_eq!(strict.inspect(&get), (Vec::new(), false));
+    }
+}
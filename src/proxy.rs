@@ -1,64 +1,220 @@
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
+use bytes::Bytes;
+use http::header::{
+    ACCEPT, AGE, AUTHORIZATION, CONNECTION, CONTENT_LENGTH, EXPECT, HOST, LOCATION, ORIGIN,
+    SET_COOKIE, TRANSFER_ENCODING, WWW_AUTHENTICATE,
+};
+use http::{HeaderName, HeaderValue, Method, StatusCode, Uri, Version};
 use pingora_core::upstreams::peer::{HttpPeer, ALPN};
-use pingora_core::Result;
+use pingora_core::{Error, ErrorSource, ErrorType, ErrorType::HTTPStatus, Result};
 use pingora_http::{RequestHeader, ResponseHeader};
-use pingora_proxy::{ProxyHttp, Session};
-use regex::Regex;
+use pingora_proxy::{FailToProxy, ProxyHttp, Session};
 use tracing::{debug, info, warn};
 
-use crate::registry::DevboxRegistry;
+use crate::access_log;
+use crate::acme::Challenges;
+use crate::activity::{ActivityGuard, ActivityTracker};
+use crate::base_href::{self, BaseHrefInjector};
+use crate::basic_auth;
+use crate::blocklist::Blocklist;
+use crate::cache::{self, CacheFill, CacheKey, CachedResponse, ResponseCache};
+use crate::client_cert::{
+    self, ClientCaRef, ClientCaStore, ClientCertError, ClientCertInfo, ClientCerts,
+};
+use crate::config::{Config, ErrorFormat, FailureStatuses, ListenerConfig, ListenerPolicy};
+use crate::cors::Cors;
+use crate::deadline;
+use crate::downtime::DowntimeTracker;
+use crate::error_response::{self, GatewayError, UpstreamFailure};
+use crate::events::{Anomaly, EventRecorder};
+use crate::expect::{self, ExpectAction};
+use crate::headers::{self, FramingError};
+use crate::legacy_host::LegacyRedirect;
+use crate::limits::{
+    ClientGuard, ClientLimiter, InflightGuard, InflightLimiter, NamespaceGuard, NamespaceLimit,
+    NamespaceLimiter,
+};
+use crate::locale::Locales;
+use crate::metrics;
+use crate::mirror::{Mirror, PendingMirror};
+use crate::path;
+use crate::policy::{DevboxPolicy, MirrorPolicy};
+use crate::preview::{self, PreviewSigner, TokenError};
+use crate::protocol_guard::{ProtocolAnomaly, ProtocolGuard};
+use crate::proxy_protocol::ProxiedClients;
+use crate::registry::{DevboxInfo, DevboxRegistry, PodEndpoint};
+use crate::response_limit::{self, ResponseLimit};
+use crate::retry;
+use crate::route_override::{self, RouteSigner};
+use crate::routing::{self, HostRoute, Router};
+use crate::suggest;
+use crate::tarpit::{self, Tarpit};
 
-/// Upstream protocol type based on host prefix
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum UpstreamProtocol {
-    /// HTTP/1.1 over cleartext (prefix: devbox-)
-    Http,
-    /// gRPC over HTTP/2 cleartext (prefix: devboxgrpc-)
-    Grpc,
-}
+pub use crate::routing::{
+    resolve_backend, BackendResult, HostParser, UpstreamProtocol, DEFAULT_HOST_PATTERN,
+};
 
-/// Result of backend resolution
-enum BackendResult {
-    /// Backend resolved successfully with Pod IP
-    Ok(String, u16),
-    /// Devbox not registered (uniqueID not found)
-    NotFound,
-    /// Devbox registered but Pod is not running (no Pod IP)
-    NotRunning,
-}
+/// Header carrying the chain of client addresses
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
 
-/// Error response bodies
-const BODY_NOT_FOUND: &[u8] = b"devbox not found";
-const BODY_NOT_RUNNING: &[u8] = b"devbox not running";
+/// Log target for slow request records, so they can be filtered independently
+const SLOW_REQUEST_TARGET: &str = "httpgate::slow_request";
 
-/// Regex to parse host header: <uniqueID>-<port>.xxx
-///
-/// Pattern: ^(<uniqueID>)-(<port>)\.
-/// - uniqueID: lowercase alphanumeric with hyphens, cannot start/end with hyphen
-/// - port: numeric
-///
-/// Note: Prefix (e.g., "devbox-", "devboxgrpc-") should be stripped before matching.
-///
-/// Examples (after prefix stripped):
-///   - "outdoor-before-78648-8080.devbox.xxx" -> ("outdoor-before-78648", 8080)
-///   - "my-app-8080.devbox.xxx" -> ("my-app", 8080)
-static HOST_REGEX: std::sync::LazyLock<Regex> = std::sync::LazyLock::new(|| {
-    // [a-z\d] - starts with alphanumeric
-    // (?:[-a-z\d]*[a-z\d])? - optionally more chars, must end with alphanumeric
-    // -(\d+)\. - port suffix
-    Regex::new(r"^([a-z\d](?:[-a-z\d]*[a-z\d])?)-(\d+)\.").unwrap()
-});
-
-/// Context passed between proxy request phases
+/// Log target for per-request access records
+const ACCESS_LOG_TARGET: &str = "httpgate::access";
+
+/// Gateway-generated errors
+const NOT_FOUND: GatewayError = GatewayError::new(404, "devbox_not_found", "devbox not found");
+const MISDIRECTED: GatewayError = GatewayError::new(
+    421,
+    "misdirected_request",
+    "host not served on this connection",
+);
+const NOT_RUNNING: GatewayError =
+    GatewayError::new(503, "devbox_not_running", "devbox not running");
+const TOO_LARGE: GatewayError =
+    GatewayError::new(413, "request_body_too_large", "request body too large");
+const RESPONSE_TOO_LARGE: GatewayError =
+    GatewayError::new(502, "response_too_large", "response too large");
+const URI_TOO_LONG: GatewayError = GatewayError::new(414, "uri_too_long", "request URI too long");
+const INVALID_PATH: GatewayError = GatewayError::new(400, "invalid_path", "invalid request path");
+const EXPECTATION_FAILED: GatewayError =
+    GatewayError::new(417, "expectation_failed", "expectation not supported");
+const OVERLOADED: GatewayError = GatewayError::new(503, "overloaded", "gateway overloaded");
+const NAMESPACE_LIMITED: GatewayError =
+    GatewayError::new(429, "namespace_limited", "namespace request limit reached");
+const CLIENT_LIMITED: GatewayError = GatewayError::new(
+    429,
+    "client_limited",
+    "too many concurrent requests from this client",
+);
+const CONNECT_NOT_SUPPORTED: GatewayError = GatewayError::new(
+    501,
+    "connect_not_supported",
+    "CONNECT is not supported (including WebSocket over HTTP/2); use WebSocket over HTTP/1.1",
+);
+const UNAUTHORIZED: GatewayError = GatewayError::new(401, "unauthorized", "unauthorized");
+const CREDENTIALS_REQUIRED: GatewayError =
+    GatewayError::new(401, "credentials_required", "valid credentials required");
+const BLOCKED: GatewayError = GatewayError::new(403, "blocked", "blocked");
+const CLIENT_NOT_ALLOWED: GatewayError =
+    GatewayError::new(403, "client_not_allowed", "not reachable from this address");
+const CLIENT_CERT_REQUIRED: GatewayError = GatewayError::new(
+    403,
+    "client_cert_required",
+    "a valid client certificate is required",
+);
+const ORIGIN_NOT_ALLOWED: GatewayError = GatewayError::new(
+    403,
+    "origin_not_allowed",
+    "WebSocket connections from this origin are not allowed",
+);
+const BACKEND_ADDRESS_INVALID: GatewayError =
+    GatewayError::new(503, "backend_address_invalid", "backend address invalid");
+const LOOP_DETECTED: GatewayError =
+    GatewayError::new(508, "loop_detected", "devbox routed to the gateway itself");
+const ENDPOINT_CHANGED: GatewayError = GatewayError::new(
+    503,
+    "endpoint_changed",
+    "devbox moved or stopped while the request was routed",
+);
+const PROTOCOL_VIOLATION: GatewayError =
+    GatewayError::new(400, "protocol_violation", "malformed request");
+const DEADLINE_EXCEEDED: GatewayError =
+    GatewayError::new(504, "deadline_exceeded", "request deadline exceeded");
+const REGISTRY_SYNCING: GatewayError = GatewayError::new(
+    503,
+    "registry_syncing",
+    "devbox not found yet; routes are being reloaded",
+);
+
+/// `Retry-After` seconds sent when shedding load
+const OVERLOAD_RETRY_AFTER_SECS: &str = "1";
+
+/// Per-request state passed between proxy phases
+pub struct RequestCtx {
+    /// When the request was received
+    pub start: Instant,
+    /// Routing result, set in `request_filter` once the backend is resolved
+    pub route: Option<ProxyCtx>,
+    /// Whether the gateway answered `Expect: 100-continue` itself
+    pub continue_sent: bool,
+    /// Request body bytes received so far
+    pub request_body_bytes: u64,
+    /// Limit on the devbox's response body, unless it is exempt
+    pub response_body_limit: Option<u64>,
+    /// Response body bytes received from the devbox so far
+    pub response_body_bytes: u64,
+    /// Whether the devbox's response was cut off for exceeding the limit
+    pub response_too_large: bool,
+    /// Whether the request failed to route: its devbox is unknown or not
+    /// running
+    pub route_failed: bool,
+    /// Whether the routed endpoint was gone by the time of connecting
+    pub endpoint_changed: bool,
+    /// Normalized path forwarded instead of the client's, if it differs
+    pub upstream_path: Option<String>,
+    /// Global in-flight slot, released when the request context is dropped
+    pub inflight: Option<InflightGuard>,
+    /// Per-client in-flight slot, released when the request context is dropped
+    pub client_slot: Option<ClientGuard>,
+    /// Namespace in-flight slot, released when the request context is dropped
+    pub namespace_slot: Option<NamespaceGuard>,
+    /// Upstream connection attempts that failed so far
+    pub connect_failures: usize,
+    /// `Access-Control-Allow-Origin` to add to the response, if the gateway
+    /// handles CORS for the devbox and the origin is allowed
+    pub cors_allow_origin: Option<HeaderValue>,
+    /// `Set-Cookie` exchanging a preview token from the query for a cookie
+    pub preview_cookie: Option<String>,
+    /// Keeps the devbox active until the request (or upgraded connection) ends
+    pub activity: Option<ActivityGuard>,
+    /// Whether the client is in `INTERNAL_CIDRS`
+    pub internal: bool,
+    /// Key to store the response under, for cacheable requests that missed
+    pub cache_key: Option<CacheKey>,
+    /// Cacheable response being received, stored once complete
+    pub cache_fill: Option<CacheFill>,
+    /// Injects the devbox's `<base href>` into the HTML response body
+    pub base_href: Option<BaseHrefInjector>,
+    /// When the budget the client set with `X-Request-Timeout-Ms` runs out
+    pub deadline: Option<Instant>,
+    /// Key the access record is sampled by, once the namespace's ratio is
+    /// known (see [`access_log::sample_key`]); unsampled records are logged
+    pub log_sample_key: Option<u64>,
+    /// Copy of the request for the devbox's mirror port, if it is mirrored
+    pub mirror: Option<PendingMirror>,
+    /// Time without data after which the upgraded connection is closed
+    pub ws_idle_timeout: Option<Duration>,
+    /// Client certificate verified for a devbox with a `client-ca`
+    /// annotation, forwarded in the `X-Client-Cert-*` headers
+    pub client_cert: Option<ClientCertInfo>,
+    /// When data last crossed the connection, in either direction
+    pub last_activity: Instant,
+}
+
+/// Routing context of a request resolved to a backend
 pub struct ProxyCtx {
+    /// Devbox uniqueID parsed from the host
+    pub unique_id: String,
     /// Backend Pod IP address
     pub backend_ip: String,
     /// Backend port
     pub backend_port: u16,
+    /// Generation of the Pod endpoint, changes whenever the Pod IP changes
+    /// (0 for endpoints named by a route override rather than the registry)
+    pub backend_generation: u64,
+    /// Generations of endpoints that failed mid-request, which the request
+    /// doesn't fail over to again
+    pub failed_generations: Vec<u64>,
     /// Upstream protocol type
     pub protocol: UpstreamProtocol,
+    /// Registry entry of the resolved devbox
+    pub devbox: DevboxInfo,
 }
 
 /// Pingora-based HTTP proxy for routing requests to devbox pods.
@@ -66,366 +222,4189 @@ pub struct ProxyCtx {
 /// Routes requests based on the Host header pattern:
 /// - `devbox-<uniqueID>-<port>.xxx` -> HTTP/1.1 to `<pod_ip>:<port>`
 /// - `devboxgrpc-<uniqueID>-<port>.xxx` -> gRPCs to `<pod_ip>:<port>`
+///
+/// One instance is created per listener; all instances share the registry,
+/// the global and per-client in-flight limiters, the namespace limiter, the
+/// blocklist, the activity tracker, the response cache, the mirror state
+/// and the tarpit.
 pub struct DevboxProxy {
     registry: Arc<DevboxRegistry>,
+    config: Arc<Config>,
+    listener: ListenerPolicy,
+    inflight: Arc<InflightLimiter>,
+    client_limits: Arc<ClientLimiter>,
+    namespace_limits: Arc<NamespaceLimiter>,
+    blocklist: Arc<Blocklist>,
+    activity: Arc<ActivityTracker>,
+    /// How long devboxes have been found not running, for `Retry-After`
+    downtime: Arc<DowntimeTracker>,
+    cors: Cors,
+    /// Preview token verifier (if `SIGNING_KEY` is set)
+    preview: Option<PreviewSigner>,
+    /// `X-HG-Route` override verifier (if `ROUTE_OVERRIDE_KEY` is set)
+    route_signer: Option<RouteSigner>,
+    /// Client addresses from PROXY protocol headers (if any listener uses it)
+    proxied_clients: Option<Arc<ProxiedClients>>,
+    /// Host parsing and backend resolution, as pre-flight checks see them
+    router: Router,
+    /// Cache of static assets (if `CACHE_MAX_BYTES` is set)
+    cache: Option<Arc<ResponseCache>>,
+    /// Reports routing anomalies as Devbox Events (unless `K8S_EVENTS=false`)
+    events: Option<Arc<EventRecorder>>,
+    /// Copies requests to the mirror ports of devboxes that set one
+    mirror: Arc<Mirror>,
+    /// Holds requests matching the abuse heuristics (if `TARPIT` is set)
+    tarpit: Arc<Tarpit>,
+    /// Counts protocol anomalies, and rejects them if `STRICT_PROTOCOL` is set
+    protocol_guard: ProtocolGuard,
+    /// Translations of gateway-generated pages (English only by default)
+    locales: Arc<Locales>,
+    /// Pending ACME HTTP-01 challenges (if a listener has `acme=true`)
+    acme_challenges: Option<Arc<Challenges>>,
+    /// Certificates presented on `client_cert=true` listeners
+    client_certs: Option<Arc<ClientCerts>>,
+    /// CA bundles of devboxes with a `client-ca` annotation
+    client_cas: Arc<ClientCaStore>,
 }
 
 impl DevboxProxy {
-    pub const fn new(registry: Arc<DevboxRegistry>) -> Self {
-        Self { registry }
+    pub fn new(registry: Arc<DevboxRegistry>) -> Self {
+        Self::with_config(registry, Arc::new(Config::default()))
+    }
+
+    /// Create a proxy for the default listener derived from `config`.
+    pub fn with_config(registry: Arc<DevboxRegistry>, config: Arc<Config>) -> Self {
+        let listener = ListenerConfig::from_config(&config).policy;
+        Self::with_listener(registry, config, listener)
     }
 
-    /// Parse the Host header to extract protocol, uniqueID and port.
+    /// Create a proxy serving one listener with its own policy.
     ///
-    /// Expected formats:
-    /// - `devbox-<uniqueID>-<port>.xxx[:port]` -> HTTP
-    /// - `devboxgrpc-<uniqueID>-<port>.xxx[:port]` -> gRPCs
+    /// The proxy gets its own limiters, blocklist, activity tracker and
+    /// response cache; use [`Self::with_inflight_limiter`],
+    /// [`Self::with_client_limiter`], [`Self::with_namespace_limiter`],
+    /// [`Self::with_blocklist`], [`Self::with_activity_tracker`] and
+    /// [`Self::with_response_cache`] to share them across listeners.
+    pub fn with_listener(
+        registry: Arc<DevboxRegistry>,
+        config: Arc<Config>,
+        listener: ListenerPolicy,
+    ) -> Self {
+        let inflight = Arc::new(InflightLimiter::new(config.max_global_inflight));
+        let client_limits = Arc::new(ClientLimiter::new(config.max_per_client_inflight));
+        let namespace_limits =
+            Arc::new(NamespaceLimiter::new(NamespaceLimit::from_config(&config)));
+        let blocklist = Arc::new(Blocklist::from_config(&config));
+        let cors = Cors::from_config(&config);
+        let preview = config.signing_key.as_deref().map(PreviewSigner::new);
+        let route_signer = config
+            .route_override_key
+            .as_deref()
+            .map(|key| RouteSigner::new(key, config.route_override_max_age.as_secs()));
+        let router = Router::from_config(
+            Arc::clone(&registry),
+            &config,
+            listener.domain_suffixes.clone(),
+        )
+        .with_blocklist(Arc::clone(&blocklist));
+        let cache = ResponseCache::from_config(&config).map(Arc::new);
+        let mirror = Arc::new(Mirror::from_config(&config));
+        let tarpit = Arc::new(Tarpit::from_config(&config));
+        let protocol_guard = ProtocolGuard::from_config(&config);
+        let downtime = Arc::new(DowntimeTracker::new(
+            config.not_running_retry_after_max.as_secs(),
+        ));
+        Self {
+            registry,
+            config,
+            listener,
+            inflight,
+            client_limits,
+            namespace_limits,
+            blocklist,
+            activity: Arc::new(ActivityTracker::new()),
+            downtime,
+            cors,
+            preview,
+            route_signer,
+            proxied_clients: None,
+            router,
+            cache,
+            events: None,
+            mirror,
+            tarpit,
+            protocol_guard,
+            locales: Arc::default(),
+            acme_challenges: None,
+            client_certs: None,
+            client_cas: Arc::default(),
+        }
+    }
+
+    /// Count requests against a limiter shared with other proxies.
+    #[must_use]
+    pub fn with_inflight_limiter(mut self, inflight: Arc<InflightLimiter>) -> Self {
+        self.inflight = inflight;
+        self
+    }
+
+    /// Count client requests against a limiter shared with other proxies.
+    #[must_use]
+    pub fn with_client_limiter(mut self, client_limits: Arc<ClientLimiter>) -> Self {
+        self.client_limits = client_limits;
+        self
+    }
+
+    /// Apply namespace limits shared with other proxies.
+    #[must_use]
+    pub fn with_namespace_limiter(mut self, namespace_limits: Arc<NamespaceLimiter>) -> Self {
+        self.namespace_limits = namespace_limits;
+        self
+    }
+
+    /// Check requests against a blocklist shared with other proxies.
+    #[must_use]
+    pub fn with_blocklist(mut self, blocklist: Arc<Blocklist>) -> Self {
+        self.router = self.router.with_blocklist(Arc::clone(&blocklist));
+        self.blocklist = blocklist;
+        self
+    }
+
+    /// Record devbox activity in a tracker shared with other proxies.
+    #[must_use]
+    pub fn with_activity_tracker(mut self, activity: Arc<ActivityTracker>) -> Self {
+        self.activity = activity;
+        self
+    }
+
+    /// Track devbox downtime in a tracker shared with other proxies.
+    #[must_use]
+    pub fn with_downtime_tracker(mut self, downtime: Arc<DowntimeTracker>) -> Self {
+        self.downtime = downtime;
+        self
+    }
+
+    /// Cache responses in a cache shared with other proxies.
+    #[must_use]
+    pub fn with_response_cache(mut self, cache: Arc<ResponseCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Take client addresses from the PROXY protocol headers recorded in
+    /// `clients`, which must only hold the connections of this proxy's
+    /// listener.
+    #[must_use]
+    pub fn with_proxied_clients(mut self, clients: Arc<ProxiedClients>) -> Self {
+        self.proxied_clients = Some(clients);
+        self
+    }
+
+    /// Report routing anomalies to a recorder shared with other proxies.
+    #[must_use]
+    pub fn with_event_recorder(mut self, events: Arc<EventRecorder>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Mirror requests with state shared with other proxies.
+    #[must_use]
+    pub fn with_mirror(mut self, mirror: Arc<Mirror>) -> Self {
+        self.mirror = mirror;
+        self
+    }
+
+    /// Hold abusive requests in a tarpit shared with other proxies.
+    #[must_use]
+    pub fn with_tarpit(mut self, tarpit: Arc<Tarpit>) -> Self {
+        self.tarpit = tarpit;
+        self
+    }
+
+    /// Translate gateway-generated pages with `locales`.
+    #[must_use]
+    pub fn with_locales(mut self, locales: Arc<Locales>) -> Self {
+        self.locales = locales;
+        self
+    }
+
+    /// Answer the ACME HTTP-01 challenges pending in `challenges`.
+    #[must_use]
+    pub fn with_acme_challenges(mut self, challenges: Arc<Challenges>) -> Self {
+        self.acme_challenges = Some(challenges);
+        self
+    }
+
+    /// Take client certificates from those recorded in `client_certs` by
+    /// the TLS listener.
+    #[must_use]
+    pub fn with_client_certs(mut self, client_certs: Arc<ClientCerts>) -> Self {
+        self.client_certs = Some(client_certs);
+        self
+    }
+
+    /// Verify client certificates against CA bundles shared with other
+    /// proxies.
+    #[must_use]
+    pub fn with_client_cas(mut self, client_cas: Arc<ClientCaStore>) -> Self {
+        self.client_cas = client_cas;
+        self
+    }
+
+    /// Address the copies of `route`'s requests are sent to under `mirror`:
+    /// the devbox's own Pod, or the Pod of its shadow devbox, which must be
+    /// a running devbox of the same namespace. Mirrors get plain HTTP/1.1,
+    /// so TLS mirror ports are left out.
+    fn mirror_target(&self, route: &ProxyCtx, mirror: &MirrorPolicy) -> Option<String> {
+        let Some(shadow) = &mirror.shadow else {
+            return (!route.devbox.policy.uses_tls(mirror.mirror_port))
+                .then(|| route.backend_ip.clone());
+        };
+        let info = self.registry.get_devbox(shadow).filter(|info| {
+            info.cluster == route.devbox.cluster
+                && info.namespace == route.devbox.namespace
+                && !info.policy.uses_tls(mirror.mirror_port)
+        })?;
+        let endpoint =
+            self.registry
+                .get_pod_endpoint(&info.cluster, &info.namespace, &info.devbox_name)?;
+        self.registry.check_backend_ip(&endpoint.ip).ok()?;
+        Some(endpoint.ip)
+    }
+
+    /// Record an occurrence of `anomaly` on `devbox`, if events are enabled.
+    fn record_anomaly(&self, devbox: &DevboxInfo, anomaly: Anomaly, note: impl FnOnce() -> String) {
+        if let Some(events) = &self.events {
+            events.record(devbox, anomaly, note());
+        }
+    }
+
+    /// Address of the client: from the PROXY header of the connection if one
+    /// was received, otherwise the socket peer address.
+    fn client_addr(&self, session: &Session) -> Option<SocketAddr> {
+        let peer = *session.client_addr()?.as_inet()?;
+        let proxied = self
+            .proxied_clients
+            .as_ref()
+            .and_then(|clients| clients.get(&peer));
+        Some(proxied.unwrap_or(peer))
+    }
+
+    /// Verify the certificate presented on the connection from `peer`
+    /// against the CA bundle `ca` of a devbox in `namespace`.
+    fn verify_client_cert(
+        &self,
+        peer: Option<SocketAddr>,
+        namespace: &str,
+        ca: &ClientCaRef,
+    ) -> std::result::Result<ClientCertInfo, ClientCertError> {
+        let chain = self
+            .client_certs
+            .as_ref()
+            .zip(peer)
+            .and_then(|(client_certs, peer)| client_certs.get(&peer));
+        self.client_cas.verify(namespace, ca, chain.as_deref())
+    }
+
+    /// Whether `client` is an internal client, which is forwarded without
+    /// proxy headers and logged less verbosely.
+    fn is_internal(&self, client: SocketAddr) -> bool {
+        self.config
+            .internal_cidrs
+            .iter()
+            .any(|cidr| cidr.contains(client.ip()))
+    }
+
+    /// Check the preview token of a request to `unique_id` and `port`, from
+    /// the query string or else the cookie.
     ///
-    /// Examples:
-    /// - `devbox-outdoor-before-78648-8080.devbox.sealos.io` -> (Http, "outdoor-before-78648", 8080)
-    /// - `devboxgrpc-my-app-50051.devbox.sealos.io` -> (Grpcs, "my-app", 50051)
-    fn parse_host(host: &str) -> Option<(UpstreamProtocol, String, u16)> {
-        // Remove port suffix if present (e.g., "xxx:443" -> "xxx")
-        let host_without_port = host.split(':').next().unwrap_or(host);
-
-        // Try to strip prefixes and determine protocol
-        let (protocol, host_stripped) =
-            if let Some(stripped) = host_without_port.strip_prefix("devboxgrpc-") {
-                (UpstreamProtocol::Grpc, stripped)
-            } else if let Some(stripped) = host_without_port.strip_prefix("devbox-") {
-                (UpstreamProtocol::Http, stripped)
-            } else {
+    /// Returns the `Set-Cookie` value to send when the token came from the
+    /// query string.
+    fn authorize_preview(
+        &self,
+        req: &RequestHeader,
+        unique_id: &str,
+        port: u16,
+    ) -> std::result::Result<Option<String>, TokenError> {
+        let signer = self.preview.as_ref().ok_or(TokenError::Missing)?;
+        let now = preview::unix_now();
+        if let Some(token) = preview::query_token(&req.uri) {
+            let expires_at = signer.verify(token, unique_id, port, now)?;
+            return Ok(Some(preview::token_cookie(token, expires_at - now)));
+        }
+        let token = preview::cookie_token(req).ok_or(TokenError::Missing)?;
+        signer.verify(token, unique_id, port, now)?;
+        Ok(None)
+    }
+
+    /// Reject requests whose framing headers could be read differently by
+    /// the backend, and collapse identical duplicate `Content-Length` values.
+    fn normalize_framing(req: &mut RequestHeader) -> std::result::Result<(), FramingError> {
+        if let Some(length) = headers::check_framing(&req.headers)? {
+            // Only fails for invalid values, and a number is always valid
+            let _ = req.insert_header(CONTENT_LENGTH, length);
+        }
+        Ok(())
+    }
+
+    /// Replace the path of the forwarded request, keeping its query.
+    fn rewrite_path(req: &mut RequestHeader, path: &str) -> Result<()> {
+        let path_and_query = match req.uri.query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path.to_string(),
+        };
+        // The path only lost characters of the original, so this can't fail
+        // unless the original was invalid
+        let mut parts = req.uri.clone().into_parts();
+        let Ok(path_and_query) = path_and_query.parse() else {
+            return Error::e_explain(HTTPStatus(400), "invalid request path");
+        };
+        parts.path_and_query = Some(path_and_query);
+        let Ok(uri) = Uri::from_parts(parts) else {
+            return Error::e_explain(HTTPStatus(400), "invalid request path");
+        };
+        req.set_uri(uri);
+        Ok(())
+    }
+
+    /// Point a redirect of a devbox with path prefix annotations back under
+    /// the prefix its client used (see [`path::restore_prefix`]).
+    fn restore_location_prefix(
+        resp: &mut ResponseHeader,
+        policy: &DevboxPolicy,
+        host: &str,
+    ) -> Result<()> {
+        let Some(location) = resp
+            .headers
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+        else {
+            return Ok(());
+        };
+        if let Some(restored) = path::restore_prefix(
+            location,
+            host,
+            policy.strip_prefix.as_deref(),
+            policy.add_prefix.as_deref(),
+        ) {
+            resp.insert_header(LOCATION, restored)?;
+        }
+        Ok(())
+    }
+
+    /// Re-insert every header under its canonically cased name.
+    fn canonicalize_header_case(req: &mut RequestHeader) -> Result<()> {
+        let names: Vec<HeaderName> = req.headers.keys().cloned().collect();
+        for name in names {
+            let values: Vec<HeaderValue> = req.headers.get_all(&name).iter().cloned().collect();
+            req.remove_header(&name);
+            for value in values {
+                req.append_header(headers::canonical_case(name.as_str()), value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `X-Forwarded-For` value with `client_ip` appended to any existing chain.
+    fn forwarded_for(existing: Option<&str>, client_ip: IpAddr) -> String {
+        match existing.map(str::trim).filter(|v| !v.is_empty()) {
+            Some(chain) => format!("{chain}, {client_ip}"),
+            None => client_ip.to_string(),
+        }
+    }
+
+    /// Host of a request: the `Host` header, or the `:authority` of HTTP/2
+    /// requests that carry no `Host` header.
+    fn request_host(req: &RequestHeader) -> &str {
+        req.headers
+            .get(HOST)
+            .and_then(|h| h.to_str().ok())
+            .or_else(|| req.uri.authority().map(|a| a.as_str()))
+            .unwrap_or("")
+    }
+
+    /// Metric label for the downstream HTTP version.
+    fn version_label(version: Version) -> &'static str {
+        match version {
+            Version::HTTP_10 => "1.0",
+            Version::HTTP_11 => "1.1",
+            Version::HTTP_2 => "2",
+            _ => "other",
+        }
+    }
+
+    /// Parse the Host header to extract protocol, uniqueID and port (see
+    /// [`routing::parse_host`]).
+    ///
+    /// Hosts are parsed with the default scheme; listeners with a
+    /// `HOST_PATTERN` use their [`HostParser`].
+    pub fn parse_host(host: &str) -> Option<(UpstreamProtocol, String, u16)> {
+        routing::parse_host(host)
+    }
+
+    /// The uniqueID [`Self::parse_host`] would extract from `host`, found
+    /// without validating it. Only used to consult the registry's filter.
+    pub fn candidate_unique_id(host: &str) -> Option<&str> {
+        routing::candidate_unique_id(host)
+    }
+
+    /// Match `host` against the listener's domains, then find the devbox it
+    /// names (see [`Router`]).
+    fn route_host(&self, host: &str) -> HostRoute {
+        let route = self.router.route_host(host);
+        if route == HostRoute::Misdirected {
+            warn!(
+                listener = %self.listener.name,
+                host = %host,
+                "Host does not match listener domains"
+            );
+        }
+        route
+    }
+
+    /// Resolve the backend address from uniqueID (see [`resolve_backend`]).
+    fn resolve_backend(&self, unique_id: &str, port: u16) -> BackendResult {
+        self.router.resolve(unique_id, port)
+    }
+
+    /// Whether the registry still routes `route`'s devbox to its endpoint.
+    /// Endpoints set by route override aren't the registry's to check.
+    fn is_current_route(&self, route: &ProxyCtx) -> bool {
+        route.backend_generation == 0
+            || self
+                .registry
+                .is_current_endpoint(&route.unique_id, route.backend_generation)
+    }
+
+    /// Resolve the backend named by a signed `X-HG-Route` header, bypassing
+    /// the registry.
+    ///
+    /// Returns `None` (resolve from the registry) without the header, or if
+    /// it is unsigned or fails verification. The devbox's policy still
+    /// applies if `unique_id` is registered in the override's namespace.
+    fn resolve_override(&self, req: &RequestHeader, unique_id: &str) -> Option<BackendResult> {
+        let now = preview::unix_now();
+        let route = match RouteSigner::from_headers(self.route_signer.as_ref(), &req.headers, now)?
+        {
+            Ok(route) => route,
+            Err(e) => {
+                warn!(
+                    unique_id = %unique_id,
+                    error = %e,
+                    "Ignoring route override"
+                );
+                metrics::ROUTE_OVERRIDES_TOTAL
+                    .with_label_values(&[e.label()])
+                    .inc();
                 return None;
-            };
+            }
+        };
+
+        let info = self
+            .registry
+            .get_devbox(unique_id)
+            .filter(|info| info.namespace == route.namespace)
+            .unwrap_or_else(|| DevboxInfo::new(route.namespace.clone(), String::new()));
+        if let Some(entry) = self.blocklist.check(unique_id, Some(&route.namespace)) {
+            metrics::ROUTE_OVERRIDES_TOTAL
+                .with_label_values(&["refused"])
+                .inc();
+            return Some(BackendResult::Blocked(entry));
+        }
+        let endpoint = PodEndpoint {
+            ip: route.ip.to_string(),
+            generation: 0,
+        };
+        if let Err(e) = self.registry.check_backend_ip(&endpoint.ip) {
+            metrics::ROUTE_OVERRIDES_TOTAL
+                .with_label_values(&["refused"])
+                .inc();
+            return Some(BackendResult::InvalidAddress(endpoint, info, e));
+        }
 
-        HOST_REGEX.captures(host_stripped).and_then(|caps| {
-            let unique_id = caps.get(1)?.as_str().to_string();
-            let port: u16 = caps.get(2)?.as_str().parse().ok()?;
-            Some((protocol, unique_id, port))
-        })
+        info!(
+            unique_id = %unique_id,
+            namespace = %route.namespace,
+            pod_ip = %endpoint.ip,
+            port = route.port,
+            "Routing request by override"
+        );
+        metrics::ROUTE_OVERRIDES_TOTAL
+            .with_label_values(&["accepted"])
+            .inc();
+        Some(BackendResult::Ok(endpoint, route.port, info))
     }
 
-    /// Resolve the backend address from uniqueID.
+    /// Build the upstream peer for a resolved request.
     ///
-    /// Performs a two-step lookup:
-    /// 1. uniqueID -> DevboxInfo (namespace, devbox_name)
-    /// 2. namespace/devbox_name -> pod_ip
+    /// Ports listed in the devbox's `tls-ports` annotation are proxied over TLS,
+    /// verified against the configured CA bundle (or system roots) unless the
+    /// devbox opts out via `tls-skip-verify`.
     ///
-    /// Returns:
-    /// - `BackendResult::Ok` if uniqueID is registered and Pod IP is available
-    /// - `BackendResult::NotFound` if uniqueID is not registered
-    /// - `BackendResult::NotRunning` if uniqueID is registered but Pod IP is not available
-    fn resolve_backend(&self, unique_id: &str, port: u16) -> BackendResult {
-        // Step 1: Look up devbox info
-        let Some(info) = self.registry.get_devbox(unique_id) else {
-            return BackendResult::NotFound;
+    /// The connection always goes to the Pod IP; the SNI (and the name the
+    /// certificate is verified against) is the devbox's `tls-sni` annotation,
+    /// else `default_sni`, else the Pod IP.
+    fn build_peer(ctx: &ProxyCtx, default_sni: Option<&str>) -> HttpPeer {
+        let policy = &ctx.devbox.policy;
+        let tls = policy.uses_tls(ctx.backend_port);
+
+        // Without a configured name, use the Pod IP so backend certificates can
+        // be verified against IP SANs
+        let sni = if tls {
+            policy
+                .tls_sni
+                .as_deref()
+                .or(default_sni)
+                .unwrap_or(&ctx.backend_ip)
+                .to_string()
+        } else {
+            String::new()
+        };
+        let mut peer = HttpPeer::new((ctx.backend_ip.as_str(), ctx.backend_port), tls, sni);
+
+        // Key the connection pool by endpoint generation: once the Pod IP changes,
+        // connections pooled for the previous endpoint are never reused, even if
+        // the same IP is later handed to a different Pod
+        peer.group_key = ctx.backend_generation;
+
+        if tls && policy.tls_skip_verify {
+            peer.options.verify_cert = false;
+            peer.options.verify_hostname = false;
+        }
+
+        // Configure HTTP/2 for gRPC (h2c, or h2 over TLS)
+        if ctx.protocol == UpstreamProtocol::Grpc {
+            peer.options.alpn = ALPN::H2;
+        }
+
+        peer
+    }
+
+    /// Remove the headers the devbox must not receive from the upstream
+    /// request, including the credentials of its basic-auth gate.
+    fn strip_denied_headers(policy: &DevboxPolicy, upstream_request: &mut RequestHeader) {
+        for name in &policy.deny_request_headers {
+            upstream_request.remove_header(name);
+        }
+        if policy.basic_auth.is_some() {
+            upstream_request.remove_header(&AUTHORIZATION);
+        }
+    }
+
+    /// Set the identity headers of a request for `host` on listeners that
+    /// send them, replacing any the client sent.
+    ///
+    /// Requests without a devbox route (route overrides included) only get
+    /// the client's copies removed.
+    fn set_identity_headers(
+        &self,
+        upstream_request: &mut RequestHeader,
+        route: Option<&ProxyCtx>,
+        host: &str,
+    ) -> Result<()> {
+        if !self.listener.identity_headers {
+            return Ok(());
+        }
+        let names = &self.config.identity_header_names;
+        match route {
+            Some(route) => names.apply(
+                upstream_request,
+                &route.unique_id,
+                &route.devbox.namespace,
+                host,
+            ),
+            None => {
+                names.strip(upstream_request);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether a request that took `elapsed` exceeds the slow request threshold.
+    fn is_slow_request(&self, elapsed: Duration) -> bool {
+        self.config
+            .slow_request_threshold
+            .is_some_and(|threshold| elapsed > threshold)
+    }
+
+    /// Whether the access record of a successful request is sampled, at the
+    /// ratio of its devbox's namespace or `ACCESS_LOG_SAMPLE`.
+    fn log_sampled(&self, ctx: &RequestCtx) -> bool {
+        let Some(key) = ctx.log_sample_key else {
+            return true;
+        };
+        let ratio = ctx
+            .route
+            .as_ref()
+            .and_then(|route| {
+                self.namespace_limits
+                    .limit_for(&route.devbox.namespace)
+                    .log_sample
+            })
+            .unwrap_or(self.config.access_log_sample);
+        access_log::is_key_sampled(key, ratio)
+    }
+
+    /// Emit the slow request record (independent of any access log sampling).
+    fn log_slow_request(ctx: &ProxyCtx, elapsed: Duration, e: Option<&Error>) {
+        warn!(
+            target: SLOW_REQUEST_TARGET,
+            unique_id = %ctx.unique_id,
+            cluster = %ctx.devbox.cluster,
+            namespace = %ctx.devbox.namespace,
+            devbox_name = %ctx.devbox.devbox_name,
+            backend = %format!("{}:{}", ctx.backend_ip, ctx.backend_port),
+            duration_ms = elapsed.as_millis(),
+            error = ?e.map(ToString::to_string),
+            "Slow request"
+        );
+    }
+
+    /// Whether an upgraded connection that ended with `e` was closed for
+    /// carrying no data for its idle timeout.
+    fn closed_idle(ctx: &RequestCtx, e: Option<&Error>, now: Instant) -> bool {
+        let timed_out = e.is_some_and(|e| matches!(e.etype(), ErrorType::ReadTimedout));
+        ctx.ws_idle_timeout
+            .is_some_and(|timeout| timed_out && now.duration_since(ctx.last_activity) >= timeout)
+    }
+
+    /// Deadline of a request received at `start`: the earlier of the one
+    /// its client set and `REQUEST_TIMEOUT`, which leaves upgraded
+    /// connections alone.
+    fn request_deadline(
+        &self,
+        req: &RequestHeader,
+        upgrade: bool,
+        start: Instant,
+    ) -> Option<Instant> {
+        let requested = self
+            .config
+            .max_request_timeout
+            .and_then(|max| deadline::from_headers(&req.headers, start, max));
+        let total = self
+            .config
+            .request_timeout
+            .filter(|_| !upgrade)
+            .map(|timeout| start + timeout);
+        deadline::earliest(requested, total)
+    }
+
+    /// What is left of the request's deadline, if it has one. Fails with
+    /// 504 once the deadline has passed.
+    fn remaining_budget(ctx: &RequestCtx) -> Result<Option<Duration>> {
+        let Some(deadline) = ctx.deadline else {
+            return Ok(None);
+        };
+        match deadline::remaining(deadline, Instant::now()) {
+            Some(remaining) => Ok(Some(remaining)),
+            None => Error::e_explain(HTTPStatus(504), "request deadline exceeded"),
+        }
+    }
+
+    /// Bound connecting to the backend, and each read and write, by what is
+    /// left of the request's deadline.
+    fn apply_deadline(peer: &mut HttpPeer, remaining: Duration) {
+        let options = &mut peer.options;
+        for timeout in [
+            &mut options.total_connection_timeout,
+            &mut options.read_timeout,
+            &mut options.write_timeout,
+        ] {
+            *timeout = Some(timeout.map_or(remaining, |t| t.min(remaining)));
+        }
+    }
+
+    /// Status answering a request that failed with `e`, as Pingora would
+    /// (0 if the client is gone) except for the upstream failures told
+    /// apart, which get their configured status. Upstream failures past the
+    /// request's deadline are timeouts.
+    fn failure_status(e: &Error, deadline_exceeded: bool, statuses: &FailureStatuses) -> u16 {
+        let code = match e.etype() {
+            HTTPStatus(code) => *code,
+            _ => match e.esource() {
+                ErrorSource::Upstream if deadline_exceeded => 504,
+                ErrorSource::Upstream => {
+                    UpstreamFailure::classify(e).map_or(502, |f| f.status(statuses))
+                }
+                ErrorSource::Downstream => match e.etype() {
+                    ErrorType::WriteError | ErrorType::ReadError | ErrorType::ConnectionClosed => 0,
+                    _ => 400,
+                },
+                ErrorSource::Internal | ErrorSource::Unset => 500,
+            },
+        };
+        if deadline_exceeded && code == 502 {
+            504
+        } else {
+            code
+        }
+    }
+
+    /// Abort a devbox response over `MAX_RESPONSE_BODY_BYTES`, counting it
+    /// against the devbox. `phase` is "headers" if the declared length was
+    /// over, "body" if the streamed body was.
+    fn abort_response_too_large(ctx: &mut RequestCtx, limit: u64, phase: &str) -> Result<()> {
+        ctx.response_too_large = true;
+        if let Some(route) = &ctx.route {
+            metrics::RESPONSES_TOO_LARGE_TOTAL
+                .with_label_values(&[&route.devbox.namespace, &route.unique_id, phase])
+                .inc();
+        }
+        warn!(
+            unique_id = ?ctx.route.as_ref().map(|r| &r.unique_id),
+            limit,
+            phase,
+            "Aborting devbox response over the size limit"
+        );
+        Error::e_explain(HTTPStatus(502), "response too large")
+    }
+
+    /// Build the 504 for a request whose deadline passed in the gateway
+    fn deadline_exceeded_response(
+        error: &GatewayError,
+        format: ErrorFormat,
+    ) -> Result<(ResponseHeader, Bytes)> {
+        let (mut header, body) = Self::error_response(error, format)?;
+        header.insert_header(deadline::X_TIMEOUT_SOURCE, "gateway")?;
+        Ok((header, body))
+    }
+
+    /// Format of gateway-generated errors for `req`
+    fn error_format(&self, req: &RequestHeader) -> ErrorFormat {
+        error_response::negotiate(&req.headers, self.config.error_format)
+    }
+
+    /// Language of gateway-generated pages for `req`
+    fn locale(&self, req: &RequestHeader) -> &str {
+        self.locales.negotiate(&req.headers)
+    }
+
+    /// `error` in the language the client prefers
+    fn localize(&self, error: GatewayError, req: &RequestHeader) -> GatewayError {
+        error.localized(&self.locales, self.locale(req))
+    }
+
+    /// Build a gateway-generated error response in `format`
+    fn error_response(
+        error: &GatewayError,
+        format: ErrorFormat,
+    ) -> Result<(ResponseHeader, Bytes)> {
+        let (content_type, body) = error.render(format);
+        let mut header = ResponseHeader::build(error.status, None)?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+        header.insert_header("Content-Type", content_type)?;
+        Ok((header, body))
+    }
+
+    /// Send a gateway-generated error response, in the format the client
+    /// prefers
+    async fn send_error(&self, session: &mut Session, error: GatewayError) -> Result<bool> {
+        let format = self.error_format(session.req_header());
+        let error = self.localize(error, session.req_header());
+        let (header, body) = Self::error_response(&error, format)?;
+        Self::send_response(session, header, body).await
+    }
+
+    /// Send an error asking the client to retry after a second
+    async fn send_retry_later(&self, session: &mut Session, error: GatewayError) -> Result<bool> {
+        self.send_retry_after(session, error, OVERLOAD_RETRY_AFTER_SECS)
+            .await
+    }
+
+    /// Send an error asking the client to retry after `secs` seconds
+    async fn send_retry_after(
+        &self,
+        session: &mut Session,
+        error: GatewayError,
+        secs: &str,
+    ) -> Result<bool> {
+        let format = self.error_format(session.req_header());
+        let error = self.localize(error, session.req_header());
+        let (mut header, body) = Self::error_response(&error, format)?;
+        header.insert_header("Retry-After", secs)?;
+        Self::send_response(session, header, body).await
+    }
+
+    /// Send a 401 challenging the client for basic-auth credentials
+    async fn send_basic_auth_challenge(
+        &self,
+        session: &mut Session,
+        error: GatewayError,
+    ) -> Result<bool> {
+        let format = self.error_format(session.req_header());
+        let error = self.localize(error, session.req_header());
+        let (mut header, body) = Self::error_response(&error, format)?;
+        header.insert_header(WWW_AUTHENTICATE, basic_auth::CHALLENGE)?;
+        Self::send_response(session, header, body).await
+    }
+
+    /// Send a complete gateway-generated response.
+    ///
+    /// The response is always framed by `Content-Length`, and HTTP/1
+    /// connections are closed when the client asked for it or left a
+    /// request body unread, which would otherwise be parsed as the next
+    /// request.
+    async fn send_response(
+        session: &mut Session,
+        mut header: ResponseHeader,
+        body: impl Into<Bytes>,
+    ) -> Result<bool> {
+        let body = body.into();
+        if header.headers.get(CONTENT_LENGTH).is_none() {
+            header.insert_header(CONTENT_LENGTH, body.len().to_string())?;
+        }
+        if !session.is_http2() {
+            let body_done = session.is_body_done();
+            let keep_alive = Self::keep_alive(session.req_header(), body_done);
+            if !keep_alive {
+                session.set_keepalive(None);
+            }
+            if let Some(value) = Self::connection_header(session.req_header(), keep_alive) {
+                header.insert_header(CONNECTION, value)?;
+            }
+        }
+
+        session
+            .write_response_header(Box::new(header), false)
+            .await?;
+        session.write_response_body(Some(body), true).await?;
+        Ok(true)
+    }
+
+    /// Whether an HTTP/1 connection can serve another request after a
+    /// gateway-generated response to `req`.
+    fn keep_alive(req: &RequestHeader, body_done: bool) -> bool {
+        if !body_done || headers::has_connection_token(&req.headers, "close") {
+            return false;
+        }
+        match req.version {
+            Version::HTTP_10 => headers::has_connection_token(&req.headers, "keep-alive"),
+            Version::HTTP_11 => true,
+            _ => false,
+        }
+    }
+
+    /// `Connection` header telling the client whether the connection is kept
+    /// (HTTP/1.1 keeps it unless told otherwise)
+    fn connection_header(req: &RequestHeader, keep_alive: bool) -> Option<&'static str> {
+        match (keep_alive, req.version) {
+            (false, _) => Some("close"),
+            (true, Version::HTTP_10) => Some("keep-alive"),
+            (true, _) => None,
+        }
+    }
+
+    /// Frame a proxied response for an HTTP/1.0 client, which can't take a
+    /// chunked body: `Transfer-Encoding` is dropped, so a body without
+    /// `Content-Length` runs until the connection closes. Returns whether the
+    /// connection is kept.
+    fn frame_for_http10(req: &RequestHeader, resp: &mut ResponseHeader) -> Result<bool> {
+        resp.remove_header(&TRANSFER_ENCODING);
+        let bodyless = req.method == Method::HEAD
+            || resp.status.is_informational()
+            || matches!(
+                resp.status,
+                StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED
+            );
+        let framed = bodyless || resp.headers.contains_key(CONTENT_LENGTH);
+        let keep_alive = framed && Self::keep_alive(req, true);
+        resp.remove_header(&CONNECTION);
+        if let Some(value) = Self::connection_header(req, keep_alive) {
+            resp.insert_header(CONNECTION, value)?;
+        }
+        Ok(keep_alive)
+    }
+
+    /// Add the headers of a request's response that don't come from the
+    /// backend: CORS and the preview cookie.
+    fn decorate_response(&self, resp: &mut ResponseHeader, ctx: &mut RequestCtx) -> Result<()> {
+        if let (Some(allow_origin), Some(route)) = (ctx.cors_allow_origin.clone(), &ctx.route) {
+            self.cors.apply(&route.devbox.policy, resp, allow_origin)?;
+        }
+        if let Some(cookie) = ctx.preview_cookie.take() {
+            resp.append_header(SET_COOKIE, cookie)?;
+        }
+        Ok(())
+    }
+
+    /// Look up a cached response to the routed request. On a miss, the key
+    /// to store the backend's response under is kept in `ctx`.
+    fn cache_lookup(&self, req: &RequestHeader, ctx: &mut RequestCtx) -> Option<CachedResponse> {
+        let (cache, route) = (self.cache.as_ref()?, ctx.route.as_ref()?);
+        if !cache.enabled_for(&route.devbox.policy)
+            || !cache::is_cacheable_request(&req.method, &req.headers)
+        {
+            return None;
+        }
+        let key = CacheKey::new(&route.unique_id, route.backend_port, &req.uri);
+        let hit = if cache::bypasses_cache(&req.headers) {
+            None
+        } else {
+            cache.lookup(&key, route.backend_generation, &req.headers)
+        };
+        if hit.is_none() {
+            ctx.cache_key = Some(key);
+        }
+        hit
+    }
+
+    /// Header of a cached response, as if it came from the backend
+    fn cached_response_header(hit: &CachedResponse) -> Result<ResponseHeader> {
+        let mut header = ResponseHeader::build(hit.status, Some(hit.headers.len() + 1))?;
+        for (name, value) in &hit.headers {
+            header.append_header(name.clone(), value.clone())?;
+        }
+        header.insert_header(AGE, hit.age().to_string())?;
+        Ok(header)
+    }
+
+    /// Send a response from the cache.
+    async fn send_cached(
+        &self,
+        session: &mut Session,
+        ctx: &mut RequestCtx,
+        hit: CachedResponse,
+    ) -> Result<bool> {
+        let mut header = Self::cached_response_header(&hit)?;
+        self.decorate_response(&mut header, ctx)?;
+        metrics::CACHE_HIT_BYTES_TOTAL.inc_by(hit.body.len() as u64);
+        Self::send_response(session, header, hit.body).await
+    }
+
+    /// Send a 404 for a host naming no known devbox (`unique_id`, if the
+    /// host names one at all).
+    ///
+    /// Browsers holding a preview token for a devbox with a similar
+    /// uniqueID get a page suggesting the similar devboxes of its
+    /// namespace, if enabled.
+    async fn send_devbox_not_found(
+        &self,
+        session: &mut Session,
+        host: String,
+        unique_id: Option<&str>,
+    ) -> Result<bool> {
+        let req = session.req_header();
+        let suggestions = if self.config.suggest_on_404 && Self::accepts_html(req) {
+            self.suggest_hosts(req, &host)
+        } else {
+            Vec::new()
+        };
+        if suggestions.is_empty() {
+            let error = match unique_id {
+                Some(unique_id) => NOT_FOUND.with_unique_id(unique_id),
+                None => NOT_FOUND,
+            };
+            return self.send_error(session, error).await;
+        }
+        debug!(host = %host, suggestions = ?suggestions, "Suggesting similar devboxes");
+        let lang = self.locale(session.req_header());
+        let (header, body) = Self::not_found_page(&suggestions, &self.locales, lang)?;
+        Self::send_response(session, header, body).await
+    }
+
+    /// Hosts of the devboxes similar to the one `host` names.
+    ///
+    /// Suggestions reveal which devboxes exist, so they are limited to the
+    /// namespace of the similar devbox the request's preview token was
+    /// minted for; requests without such a token get none.
+    fn suggest_hosts(&self, req: &RequestHeader, host: &str) -> Vec<String> {
+        // Only the default scheme says where the uniqueID is in the host
+        if !matches!(self.router.host_parser(), HostParser::Default) {
+            return Vec::new();
+        }
+        let Some(signer) = &self.preview else {
+            return Vec::new();
+        };
+        let Some(token) = preview::query_token(&req.uri).or_else(|| preview::cookie_token(req))
+        else {
+            return Vec::new();
+        };
+        let Some((_, unique_id, port)) = self.router.host_parser().parse(host) else {
+            return Vec::new();
+        };
+
+        let similar = self.registry.similar_devboxes(&unique_id);
+        let now = preview::unix_now();
+        let Some((_, owner)) = similar
+            .iter()
+            .find(|(id, _)| signer.verify(token, id, port, now).is_ok())
+        else {
+            return Vec::new();
+        };
+
+        // The uniqueID follows the `devbox-` or `devboxgrpc-` prefix
+        let (prefix, rest) = host.split_at(host.find('-').map_or(0, |i| i + 1));
+        let rest = &rest[unique_id.len()..];
+        similar
+            .iter()
+            .filter(|(_, info)| info.cluster == owner.cluster && info.namespace == owner.namespace)
+            .take(suggest::MAX_SUGGESTIONS)
+            .map(|(id, _)| format!("{prefix}{id}{rest}"))
+            .collect()
+    }
+
+    /// Build the 404 page linking to `suggestions`, in `lang`
+    fn not_found_page(
+        suggestions: &[String],
+        locales: &Locales,
+        lang: &str,
+    ) -> Result<(ResponseHeader, Bytes)> {
+        let links: String = suggestions
+            .iter()
+            .map(|host| {
+                let host = suggest::html_escape(host);
+                format!("<li><a href=\"//{host}/\">{host}</a></li>\n")
+            })
+            .collect();
+        let vars = [("status", "404")];
+        let (title, _) = translate_page(
+            locales,
+            lang,
+            "devbox_suggestions.title",
+            &vars,
+            "Devbox not found",
+        );
+        let (message, lang) = translate_page(
+            locales,
+            lang,
+            "devbox_suggestions",
+            &vars,
+            "This devbox does not exist. Did you mean:",
+        );
+        let lang = error_response::html_lang(lang);
+        let body = format!(
+            "<!DOCTYPE html>\n\
+             <html{lang}>\n\
+             <head>\n\
+             <meta charset=\"utf-8\">\n\
+             <title>{title}</title>\n\
+             </head>\n\
+             <body>\n\
+             <p>{message}</p>\n\
+             <ul>\n\
+             {links}\
+             </ul>\n\
+             </body>\n\
+             </html>\n"
+        );
+
+        let mut header = ResponseHeader::build(404, None)?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+        header.insert_header("Content-Type", "text/html; charset=utf-8")?;
+        header.insert_header("Cache-Control", "no-store")?;
+        Ok((header, Bytes::from(body)))
+    }
+
+    /// Reject a host outside the listener's domains.
+    ///
+    /// TLS listeners answer 421 Misdirected Request, so clients that
+    /// coalesced the request onto our HTTP/2 connection retry it on a new
+    /// one; cleartext listeners answer 404.
+    async fn send_misdirected(&self, session: &mut Session) -> Result<bool> {
+        if !self.listener.tls {
+            return self.send_error(session, NOT_FOUND).await;
+        }
+        metrics::MISDIRECTED_REQUESTS_TOTAL
+            .with_label_values(&[self.listener.name.as_str()])
+            .inc();
+        self.send_error(session, MISDIRECTED).await
+    }
+
+    /// Answer an ACME HTTP-01 challenge with its key authorization.
+    async fn send_acme_challenge(session: &mut Session, key_authorization: String) -> Result<bool> {
+        let mut header = ResponseHeader::build(200, None)?;
+        header.insert_header("Content-Type", "application/octet-stream")?;
+        header.insert_header("Content-Length", key_authorization.len().to_string())?;
+        header.insert_header("Cache-Control", "no-store")?;
+        Self::send_response(session, header, Bytes::from(key_authorization)).await
+    }
+
+    /// Permanently redirect a request of a legacy host to `host`, keeping
+    /// the path and query.
+    ///
+    /// The location is scheme-relative, so clients stay on the scheme they
+    /// used, whatever terminated TLS in front of the gateway.
+    async fn send_legacy_redirect(session: &mut Session, host: &str) -> Result<bool> {
+        let path = session
+            .req_header()
+            .uri
+            .path_and_query()
+            .map_or("/", |p| p.as_str());
+        let location = format!("//{host}{path}");
+        let mut header = ResponseHeader::build(308, None)?;
+        header.insert_header("Location", location)?;
+        header.insert_header("Content-Length", "0")?;
+        Self::send_response(session, header, Bytes::new()).await
+    }
+
+    /// Answer a request matching the tarpit's heuristics as slowly as
+    /// possible, or with an instant 403 if the tarpit is full.
+    async fn send_tarpit(&self, session: &mut Session, reason: tarpit::Reason) -> Result<bool> {
+        let client = self.client_addr(session).map(|c| c.ip());
+        let Some(slot) = self.tarpit.try_enter(reason) else {
+            debug!(client = ?client, reason = reason.label(), "Tarpit full, rejecting request");
+            return self.send_error(session, BLOCKED).await;
+        };
+        debug!(client = ?client, reason = reason.label(), "Tarpitting request");
+        // Never let the client reuse the connection
+        if !session.is_http2() {
+            session.set_keepalive(None);
+        }
+        self.tarpit.serve(&slot, session).await?;
+        Ok(true)
+    }
+
+    /// Whether a devbox missing from the registry may be there shortly:
+    /// a watcher is (re)listing, or did less than `REGISTRY_SYNC_GRACE` ago.
+    fn registry_syncing(&self) -> bool {
+        self.config
+            .registry_sync_grace
+            .is_some_and(|grace| self.registry.possibly_incomplete(grace))
+    }
+
+    /// Count and log the protocol anomalies of `req`, returning the one it
+    /// is rejected for in strict mode.
+    fn guard_protocol(&self, req: &RequestHeader) -> Option<ProtocolAnomaly> {
+        let (anomalies, reject) = self.protocol_guard.inspect(req);
+        let rejected = if reject { "true" } else { "false" };
+        for anomaly in &anomalies {
+            warn!(
+                listener = %self.listener.name,
+                method = %req.method,
+                uri = %req.uri,
+                anomaly = anomaly.label(),
+                rejected = reject,
+                "Protocol anomaly: {anomaly}"
+            );
+            metrics::PROTOCOL_ANOMALIES_TOTAL
+                .with_label_values(&[anomaly.label(), rejected])
+                .inc();
+        }
+        anomalies.first().copied().filter(|_| reject)
+    }
+
+    /// Redacted headers of a request that failed to route, to be logged if
+    /// `LOG_HEADERS_ON_ERROR` is set.
+    fn headers_on_error(&self, req: &RequestHeader, ctx: &RequestCtx) -> Option<String> {
+        (self.config.log_headers_on_error && ctx.route_failed)
+            .then(|| access_log::redacted_headers(&req.headers, &self.config.sensitive_headers))
+    }
+
+    /// Count a request for an unknown devbox against its client, for the
+    /// tarpit's scanner heuristic.
+    fn record_unknown_host(&self, session: &Session, ctx: &RequestCtx) {
+        if let Some(client) = self.client_addr(session).filter(|_| !ctx.internal) {
+            self.tarpit.record_unknown_host(client.ip());
+        }
+    }
+
+    /// Send a 503 Service Unavailable response (devbox not running).
+    ///
+    /// Browsers get a page that reloads itself while the devbox starts, if
+    /// enabled; other clients get a `Retry-After` growing with the time the
+    /// devbox has been down.
+    async fn send_service_unavailable(
+        &self,
+        session: &mut Session,
+        unique_id: &str,
+    ) -> Result<bool> {
+        match self.config.starting_page_refresh {
+            Some(refresh) if Self::accepts_html(session.req_header()) => {
+                let lang = self.locale(session.req_header());
+                let (header, body) = Self::starting_page(refresh, &self.locales, lang)?;
+                Self::send_response(session, header, body).await
+            }
+            _ => {
+                let error = NOT_RUNNING
+                    .with_status(self.config.failure_statuses.unavailable)
+                    .with_unique_id(unique_id);
+                // Clients are asked to back off more the longer it stays down
+                let retry_after = self.downtime.retry_after(unique_id).to_string();
+                self.send_retry_after(session, error, &retry_after).await
+            }
+        }
+    }
+
+    /// Whether the client takes an HTML response, i.e. is a browser
+    fn accepts_html(req: &RequestHeader) -> bool {
+        req.headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|v| v.contains("text/html"))
+    }
+
+    /// Build the 503 page reloading itself after `refresh` (at least a
+    /// second), in `lang`
+    fn starting_page(
+        refresh: Duration,
+        locales: &Locales,
+        lang: &str,
+    ) -> Result<(ResponseHeader, Bytes)> {
+        let secs = refresh.as_secs().max(1);
+        let secs_text = secs.to_string();
+        let vars = [("status", "503"), ("secs", secs_text.as_str())];
+        let (title, _) = translate_page(
+            locales,
+            lang,
+            "devbox_starting.title",
+            &vars,
+            "Devbox starting",
+        );
+        let (message, lang) = translate_page(
+            locales,
+            lang,
+            "devbox_starting",
+            &vars,
+            &format!("This devbox is starting. The page will reload in {secs} seconds."),
+        );
+        let lang = error_response::html_lang(lang);
+        let body = format!(
+            "<!DOCTYPE html>\n\
+             <html{lang}>\n\
+             <head>\n\
+             <meta charset=\"utf-8\">\n\
+             <meta http-equiv=\"refresh\" content=\"{secs}\">\n\
+             <title>{title}</title>\n\
+             </head>\n\
+             <body>\n\
+             <p>{message}</p>\n\
+             </body>\n\
+             </html>\n"
+        );
+
+        let mut header = ResponseHeader::build(503, None)?;
+        header.insert_header("Content-Length", body.len().to_string())?;
+        header.insert_header("Content-Type", "text/html; charset=utf-8")?;
+        header.insert_header("Cache-Control", "no-store")?;
+        header.insert_header("Retry-After", secs.to_string())?;
+        Ok((header, Bytes::from(body)))
+    }
+}
+
+#[async_trait]
+impl ProxyHttp for DevboxProxy {
+    type CTX = RequestCtx;
+
+    fn new_ctx(&self) -> Self::CTX {
+        RequestCtx {
+            start: Instant::now(),
+            route: None,
+            continue_sent: false,
+            request_body_bytes: 0,
+            response_body_limit: None,
+            response_body_bytes: 0,
+            response_too_large: false,
+            route_failed: false,
+            endpoint_changed: false,
+            upstream_path: None,
+            inflight: None,
+            client_slot: None,
+            namespace_slot: None,
+            connect_failures: 0,
+            cors_allow_origin: None,
+            preview_cookie: None,
+            activity: None,
+            internal: false,
+            cache_key: None,
+            cache_fill: None,
+            base_href: None,
+            deadline: None,
+            log_sample_key: None,
+            mirror: None,
+            ws_idle_timeout: None,
+            client_cert: None,
+            last_activity: Instant::now(),
+        }
+    }
+
+    async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
+        if self.config.header_size_metrics {
+            metrics::REQUEST_HEADER_BYTES
+                .with_label_values(&[self.listener.name.as_str()])
+                .observe(headers::header_bytes(&session.req_header().headers) as f64);
+        }
+        if let Some(anomaly) = self.guard_protocol(session.req_header()) {
+            let error = PROTOCOL_VIOLATION.with_message(anomaly.to_string());
+            return self.send_error(session, error).await;
+        }
+        ctx.log_sample_key = Some(access_log::sample_key(session.req_header()));
+        ctx.deadline =
+            self.request_deadline(session.req_header(), session.is_upgrade_req(), ctx.start);
+        ctx.internal = !self.config.internal_cidrs.is_empty()
+            && self
+                .client_addr(session)
+                .is_some_and(|client| self.is_internal(client));
+
+        // The CA validating an ACME order asks for the bare domain names,
+        // which don't route to a devbox
+        if let Some(challenges) = &self.acme_challenges {
+            if let Some(key_authorization) = challenges.response(session.req_header().uri.path()) {
+                debug!(path = %session.req_header().uri.path(), "Answering ACME challenge");
+                return Self::send_acme_challenge(session, key_authorization).await;
+            }
+        }
+
+        // Tie up abusive clients before they take any of the limits' slots
+        if !ctx.internal {
+            let client = self.client_addr(session).map(|c| c.ip());
+            if let Some(reason) = self.tarpit.check(client, session.req_header().uri.path()) {
+                return self.send_tarpit(session, reason).await;
+            }
+        }
+
+        // Shed load before doing any work once the global in-flight limit is reached
+        ctx.inflight = self.inflight.try_acquire();
+        if ctx.inflight.is_none() {
+            warn!(
+                listener = %self.listener.name,
+                inflight = self.inflight.current(),
+                "Global in-flight limit reached, shedding request"
+            );
+            metrics::REQUESTS_SHED_TOTAL
+                .with_label_values(&[self.listener.name.as_str()])
+                .inc();
+            return self.send_retry_later(session, OVERLOADED).await;
+        }
+
+        // Count the real client (from the PROXY header if there is one), not
+        // the load balancer in front of us
+        if let Some(client) = self.client_addr(session) {
+            ctx.client_slot = self.client_limits.try_acquire(client.ip());
+            if ctx.client_slot.is_none() {
+                warn!(
+                    listener = %self.listener.name,
+                    client = %client.ip(),
+                    inflight = self.client_limits.current(client.ip()),
+                    "Per-client in-flight limit reached, rejecting request"
+                );
+                metrics::CLIENT_LIMITED_TOTAL
+                    .with_label_values(&[self.listener.name.as_str()])
+                    .inc();
+                return self.send_retry_later(session, CLIENT_LIMITED).await;
+            }
+        }
+
+        // CONNECT can't be routed by host; this also covers extended CONNECT
+        // (WebSocket over HTTP/2, RFC 8441), which would otherwise hang
+        if session.req_header().method == Method::CONNECT {
+            warn!(listener = %self.listener.name, "Rejecting CONNECT request");
+            return self.send_error(session, CONNECT_NOT_SUPPORTED).await;
+        }
+
+        if let Some(max) = self.config.max_uri_length {
+            let length = session.req_header().raw_path().len();
+            if length > max {
+                warn!(
+                    listener = %self.listener.name,
+                    length = length,
+                    max = max,
+                    "Rejecting request with overlong URI"
+                );
+                metrics::REJECTED_PATHS_TOTAL
+                    .with_label_values(&["uri_too_long"])
+                    .inc();
+                return self.send_error(session, URI_TOO_LONG).await;
+            }
+        }
+
+        // Extract Host header (or :authority for HTTP/2)
+        let host = Self::request_host(session.req_header());
+
+        let route = self.route_host(host);
+
+        // Bookmarks of hostname formats of the past are redirected
+        if matches!(route, HostRoute::Misdirected | HostRoute::NotFound) {
+            if let Some(LegacyRedirect::Redirect(host)) = self.config.legacy_hosts.rewrite(host) {
+                return Self::send_legacy_redirect(session, &host).await;
+            }
+        }
+
+        let (protocol, unique_id, port) = match route {
+            HostRoute::Devbox(protocol, unique_id, port) => (protocol, unique_id, port),
+            HostRoute::Misdirected => return self.send_misdirected(session).await,
+            HostRoute::NotFound => {
+                ctx.route_failed = true;
+                self.record_unknown_host(session, ctx);
+                return self
+                    .send_devbox_not_found(session, host.to_string(), None)
+                    .await;
+            }
+        };
+
+        // Resolve backend from a signed override, else from the registry
+        let resolved = self
+            .resolve_override(session.req_header(), &unique_id)
+            .unwrap_or_else(|| self.resolve_backend(&unique_id, port));
+        let (endpoint, backend_port, devbox) = match resolved {
+            BackendResult::Ok(endpoint, port, devbox) => {
+                self.downtime.clear(&unique_id);
+                (endpoint, port, devbox)
+            }
+            BackendResult::NotFound if self.registry_syncing() => {
+                // The devbox may exist, just not be listed yet
+                ctx.route_failed = true;
+                self.downtime.clear(&unique_id);
+                metrics::NOT_FOUND_WHILE_SYNCING_TOTAL.inc();
+                warn!(
+                    host = %host,
+                    unique_id = %unique_id,
+                    "Devbox not found while the registry syncs"
+                );
+                let error = REGISTRY_SYNCING.with_unique_id(&unique_id);
+                return self.send_retry_later(session, error).await;
+            }
+            BackendResult::NotFound => {
+                ctx.route_failed = true;
+                self.downtime.clear(&unique_id);
+                warn!(
+                    host = %host,
+                    unique_id = %unique_id,
+                    "Devbox not found"
+                );
+                self.record_unknown_host(session, ctx);
+                return self
+                    .send_devbox_not_found(session, host.to_string(), Some(&unique_id))
+                    .await;
+            }
+            BackendResult::NotRunning => {
+                ctx.route_failed = true;
+                // Possibly scaled to zero after going idle
+                self.activity.wake(&unique_id);
+                let devbox = self.registry.get_devbox(&unique_id);
+                warn!(
+                    host = %host,
+                    unique_id = %unique_id,
+                    devbox_name = devbox.as_ref().map_or("", |d| d.devbox_name.as_str()),
+                    "Devbox not running (no Pod IP)"
+                );
+                if let Some(devbox) = devbox {
+                    self.record_anomaly(&devbox, Anomaly::PodNotReady, || {
+                        "Requests failed: the devbox has no running Pod".to_string()
+                    });
+                }
+                return self.send_service_unavailable(session, &unique_id).await;
+            }
+            BackendResult::Blocked(entry) => {
+                warn!(
+                    host = %host,
+                    unique_id = %unique_id,
+                    entry = %entry,
+                    "Request blocked"
+                );
+                let error = GatewayError {
+                    status: self.blocklist.status,
+                    ..BLOCKED
+                };
+                let error = error
+                    .with_message(self.blocklist.message.clone())
+                    .with_unique_id(&unique_id);
+                return self.send_error(session, error).await;
+            }
+            BackendResult::InvalidAddress(endpoint, devbox, e) => {
+                warn!(
+                    host = %host,
+                    unique_id = %unique_id,
+                    namespace = %devbox.namespace,
+                    devbox_name = %devbox.devbox_name,
+                    backend_ip = %endpoint.ip,
+                    error = %e,
+                    "Devbox has an invalid backend address"
+                );
+                let error = BACKEND_ADDRESS_INVALID
+                    .with_status(self.config.failure_statuses.unavailable)
+                    .with_unique_id(&unique_id);
+                return self.send_error(session, error).await;
+            }
+        };
+
+        if let Ok(ip) = endpoint.ip.parse::<IpAddr>() {
+            if self.router.is_self(ip, backend_port) {
+                warn!(
+                    host = %host,
+                    unique_id = %unique_id,
+                    backend_ip = %endpoint.ip,
+                    backend_port = backend_port,
+                    "Devbox routes to the gateway itself, rejecting request loop"
+                );
+                self.record_anomaly(&devbox, Anomaly::RoutingLoop, || {
+                    format!(
+                        "Requests rejected: {}:{backend_port} is an address of the gateway",
+                        endpoint.ip
+                    )
+                });
+                return self
+                    .send_error(session, LOOP_DETECTED.with_unique_id(&unique_id))
+                    .await;
+            }
+        }
+
+        if ctx.internal {
+            debug!(host = %host, "Routing internal request");
+        } else {
+            debug!(
+                listener = %self.listener.name,
+                host = %host,
+                protocol = ?protocol,
+                cluster = %devbox.cluster,
+                backend = %format!("{}:{}", endpoint.ip, backend_port),
+                "Routing request"
+            );
+        }
+
+        // Devboxes restricted to client ranges (e.g., an office VPN) check the
+        // real client, from the PROXY header if there is one
+        if let Some(cidrs) = &devbox.policy.allowed_cidrs {
+            let client = self.client_addr(session).map(|c| c.ip());
+            if !client.is_some_and(|ip| cidrs.contains(ip)) {
+                warn!(
+                    host = %host,
+                    unique_id = %unique_id,
+                    client = ?client,
+                    "Client address not allowed"
+                );
+                metrics::CLIENTS_NOT_ALLOWED_TOTAL
+                    .with_label_values(&[devbox.namespace.as_str()])
+                    .inc();
+                let error = CLIENT_NOT_ALLOWED.with_unique_id(&unique_id);
+                return self.send_error(session, error).await;
+            }
+        }
+
+        // Devboxes naming a client CA only serve clients with a certificate
+        // it issued; the listener can't refuse the handshake, since the
+        // devbox is only known once the request is read
+        if let Some(ca) = &devbox.policy.client_ca {
+            let peer = session.client_addr().and_then(|a| a.as_inet()).copied();
+            match self.verify_client_cert(peer, &devbox.namespace, ca) {
+                Ok(cert) => ctx.client_cert = Some(cert),
+                Err(e) => {
+                    warn!(
+                        host = %host,
+                        unique_id = %unique_id,
+                        ca = %ca,
+                        error = %e,
+                        "Client certificate rejected"
+                    );
+                    metrics::CLIENT_CERTS_REJECTED_TOTAL
+                        .with_label_values(&[devbox.namespace.as_str(), e.label()])
+                        .inc();
+                    let error = CLIENT_CERT_REQUIRED.with_unique_id(&unique_id);
+                    return self.send_error(session, error).await;
+                }
+            }
+        }
+
+        // Toy backends often resolve paths naively, so stop traversal here
+        if self.config.normalize_paths && !devbox.policy.skip_path_normalization {
+            let path = session.req_header().uri.path();
+            match path::normalize(path) {
+                Ok(normalized) => {
+                    if let Some(normalized) = &normalized {
+                        debug!(host = %host, path = %path, normalized = %normalized, "Normalized request path");
+                    }
+                    ctx.upstream_path = normalized;
+                }
+                Err(e) => {
+                    warn!(
+                        host = %host,
+                        path = %path,
+                        error = %e,
+                        "Rejecting request path"
+                    );
+                    metrics::REJECTED_PATHS_TOTAL
+                        .with_label_values(&[e.as_str()])
+                        .inc();
+                    return self.send_error(session, INVALID_PATH).await;
+                }
+            }
+        }
+
+        // Per-namespace quotas, so one team can't starve the others
+        match self.namespace_limits.try_acquire(&devbox.namespace) {
+            Ok(slot) => ctx.namespace_slot = Some(slot),
+            Err(limit) => {
+                warn!(
+                    host = %host,
+                    namespace = %devbox.namespace,
+                    limit = %limit,
+                    "Namespace limit reached, rejecting request"
+                );
+                metrics::NAMESPACE_LIMITED_TOTAL
+                    .with_label_values(&[devbox.namespace.as_str(), limit.as_str()])
+                    .inc();
+                return self.send_retry_later(session, NAMESPACE_LIMITED).await;
+            }
+        }
+
+        // Answer CORS preflights without involving the backend, ahead of the
+        // auth gates since browsers never send credentials with them
+        if self.cors.enabled_for(&devbox.policy) {
+            let req = session.req_header();
+            ctx.cors_allow_origin = self.cors.allow_origin(&devbox.policy, req);
+            if Cors::is_preflight(req) {
+                debug!(host = %host, allowed = ctx.cors_allow_origin.is_some(), "CORS preflight");
+                let header = self.cors.preflight_response(
+                    &devbox.policy,
+                    req,
+                    ctx.cors_allow_origin.clone(),
+                )?;
+                return Self::send_response(session, header, Bytes::new()).await;
+            }
+        }
+
+        // Devboxes requiring auth are only reachable through preview links
+        let mut previewed = false;
+        if devbox.policy.auth_required {
+            match self.authorize_preview(session.req_header(), &unique_id, backend_port) {
+                Ok(cookie) => {
+                    ctx.preview_cookie = cookie;
+                    previewed = true;
+                }
+                Err(e) => {
+                    warn!(
+                        host = %host,
+                        unique_id = %unique_id,
+                        error = %e,
+                        "Preview token rejected"
+                    );
+                    let error = UNAUTHORIZED
+                        .with_message(e.to_string())
+                        .with_unique_id(&unique_id);
+                    return self.send_error(session, error).await;
+                }
+            }
+        }
+
+        // Devboxes behind a basic-auth gate need the credentials of one of
+        // its users, or a preview link, which stands in for them
+        if let Some(basic_auth) = &devbox.policy.basic_auth {
+            let previewed = previewed
+                || match self.authorize_preview(session.req_header(), &unique_id, backend_port) {
+                    Ok(cookie) => {
+                        ctx.preview_cookie = cookie;
+                        true
+                    }
+                    Err(_) => false,
+                };
+            if !previewed && !basic_auth.check(&session.req_header().headers).await {
+                debug!(host = %host, unique_id = %unique_id, "Basic auth credentials missing or invalid");
+                let error = CREDENTIALS_REQUIRED.with_unique_id(&unique_id);
+                return self.send_basic_auth_challenge(session, error).await;
+            }
+        }
+
+        // Any page can open a WebSocket to the devbox with the user's
+        // cookies, so devboxes opting in only accept listed origins
+        if let Some(origins) = &devbox.policy.ws_allowed_origins {
+            if session.is_upgrade_req() {
+                // An origin that isn't text can't match, like `null`
+                let origin = session
+                    .req_header()
+                    .headers
+                    .get(ORIGIN)
+                    .map(|v| v.to_str().unwrap_or("null"));
+                if !origins.allows(origin, host) {
+                    warn!(
+                        host = %host,
+                        unique_id = %unique_id,
+                        origin = ?origin,
+                        "WebSocket origin not allowed"
+                    );
+                    metrics::WS_ORIGINS_REJECTED_TOTAL
+                        .with_label_values(&[devbox.namespace.as_str()])
+                        .inc();
+                    let error = ORIGIN_NOT_ALLOWED.with_unique_id(&unique_id);
+                    return self.send_error(session, error).await;
+                }
+            }
+        }
+
+        // Only requests through every gate count as an access, so rejected
+        // ones (scanners included) don't keep an idle devbox active
+        devbox.touch(preview::unix_now());
+
+        // The context lives until the request ends, which for WebSocket is
+        // when the upgraded connection closes
+        ctx.activity = Some(self.activity.begin(&unique_id, port));
+
+        // Handle Expect and declared body size before the client uploads the body
+        ctx.continue_sent = match expect::evaluate(
+            session.req_header(),
+            self.config.expect_continue,
+            self.listener.max_request_body_bytes,
+        ) {
+            ExpectAction::Proceed => false,
+            ExpectAction::SendContinue => {
+                session.write_continue_response().await?;
+                true
+            }
+            ExpectAction::Reject(413) => {
+                return self.send_error(session, TOO_LARGE).await;
+            }
+            ExpectAction::Reject(status) => {
+                let error = GatewayError {
+                    status,
+                    ..EXPECTATION_FAILED
+                };
+                return self.send_error(session, error).await;
+            }
+        };
+
+        ctx.route = Some(ProxyCtx {
+            unique_id,
+            backend_ip: endpoint.ip,
+            backend_port,
+            backend_generation: endpoint.generation,
+            failed_generations: Vec::new(),
+            protocol,
+            devbox,
+        });
+
+        // Serve static assets the backend marked cacheable without involving it
+        if let Some(hit) = self.cache_lookup(session.req_header(), ctx) {
+            debug!(host = %Self::request_host(session.req_header()), "Serving cached response");
+            return self.send_cached(session, ctx, hit).await;
+        }
+
+        // Mirrors get plain HTTP/1.1, so gRPC mirrors are left out
+        if let Some(route) = ctx.route.as_ref() {
+            ctx.mirror = route
+                .devbox
+                .policy
+                .mirror
+                .as_ref()
+                .filter(|_| route.protocol == UpstreamProtocol::Http)
+                .and_then(|m| {
+                    let ip = self.mirror_target(route, m)?;
+                    self.mirror.begin(
+                        &route.unique_id,
+                        &ip,
+                        route.backend_port,
+                        m,
+                        session.req_header(),
+                    )
+                });
+        }
+
+        Ok(false) // Continue to upstream
+    }
+
+    async fn upstream_peer(
+        &self,
+        session: &mut Session,
+        ctx: &mut Self::CTX,
+    ) -> Result<Box<HttpPeer>> {
+        let route = ctx
+            .route
+            .as_ref()
+            .expect("Route should be set in request_filter");
+
+        // The devbox may have been unregistered or moved since the request
+        // was routed, and its old IP handed to another Pod. Checked again on
+        // every connect retry.
+        if !self.is_current_route(route) {
+            warn!(
+                unique_id = %route.unique_id,
+                backend = %format!("{}:{}", route.backend_ip, route.backend_port),
+                "Devbox endpoint changed before connecting"
+            );
+            metrics::ENDPOINTS_CHANGED_TOTAL.inc();
+            ctx.endpoint_changed = true;
+            return Error::e_explain(HTTPStatus(503), "devbox endpoint changed");
+        }
+
+        let mut peer = Self::build_peer(route, self.config.upstream_sni.as_deref());
+
+        // Pingora re-arms the read timeouts of both sides of an upgraded
+        // connection whenever data crosses it either way, so they only
+        // expire once it has gone idle
+        if let Some(timeout) = self.config.websocket_idle_timeout {
+            if session.is_upgrade_req() {
+                session.set_read_timeout(Some(timeout));
+                peer.options.read_timeout = Some(timeout);
+                ctx.ws_idle_timeout = Some(timeout);
+            }
+        }
+        if let Some(remaining) = Self::remaining_budget(ctx)? {
+            Self::apply_deadline(&mut peer, remaining);
+        }
+        Ok(Box::new(peer))
+    }
+
+    async fn request_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        if body.is_some() {
+            ctx.last_activity = Instant::now();
+        }
+        if let Some(mirror) = ctx.mirror.as_mut() {
+            if let Err(skip) = mirror.push_body(body.as_deref(), end_of_stream) {
+                skip.count();
+                ctx.mirror = None;
+            }
+        }
+
+        let (Some(body), Some(max)) = (body.as_ref(), self.listener.max_request_body_bytes) else {
+            return Ok(());
+        };
+
+        // Enforce the limit on streamed (e.g., chunked) bodies without Content-Length
+        ctx.request_body_bytes += body.len() as u64;
+        if ctx.request_body_bytes > max {
+            return Error::e_explain(HTTPStatus(413), "request body too large");
+        }
+
+        Ok(())
+    }
+
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        if let Err(e) = Self::normalize_framing(upstream_request) {
+            warn!(
+                host = %Self::request_host(session.req_header()),
+                error = %e,
+                "Rejecting request with ambiguous framing"
+            );
+            return Error::e_explain(HTTPStatus(400), e.to_string());
+        }
+
+        // The client was already told to continue; don't make the backend answer again
+        if ctx.continue_sent {
+            upstream_request.remove_header(&EXPECT);
+        }
+
+        if let Some(route) = ctx.route.as_ref() {
+            Self::strip_denied_headers(&route.devbox.policy, upstream_request);
+        }
+        let prefixed = ctx.route.as_ref().and_then(|route| {
+            let policy = &route.devbox.policy;
+            let path = ctx
+                .upstream_path
+                .as_deref()
+                .unwrap_or(upstream_request.uri.path());
+            path::rewrite_prefix(
+                path,
+                policy.strip_prefix.as_deref(),
+                policy.add_prefix.as_deref(),
+            )
+        });
+        if let Some(path) = prefixed.as_deref().or(ctx.upstream_path.as_deref()) {
+            Self::rewrite_path(upstream_request, path)?;
+        }
+        preview::strip_token(upstream_request);
+        route_override::strip_headers(upstream_request);
+        match &ctx.client_cert {
+            Some(cert) => cert.apply(upstream_request)?,
+            None => client_cert::strip_headers(upstream_request),
+        }
+        self.set_identity_headers(
+            upstream_request,
+            ctx.route.as_ref(),
+            Self::request_host(session.req_header()),
+        )?;
+
+        // Add standard proxy headers, except for internal clients that talk
+        // to backends as if directly
+        if let Some(client) = self.client_addr(session).filter(|_| !ctx.internal) {
+            let existing = upstream_request
+                .headers
+                .get(X_FORWARDED_FOR)
+                .and_then(|v| v.to_str().ok());
+            let forwarded_for = Self::forwarded_for(existing, client.ip());
+            upstream_request.insert_header(X_FORWARDED_FOR, forwarded_for)?;
+        }
+        // upstream_request
+        //     .insert_header("X-Forwarded-Proto", "https")
+        //     .unwrap();
+
+        // Let the backend give up when the client will have stopped waiting
+        if let Some(remaining) = Self::remaining_budget(ctx)? {
+            let name = &self.config.deadline_header;
+            upstream_request
+                .insert_header(name.clone(), deadline::header_value(name, remaining))?;
+        }
+
+        if self.config.canonicalize_header_case {
+            Self::canonicalize_header_case(upstream_request)?;
+        }
+
+        if let Some(mirror) = ctx.mirror.as_mut() {
+            mirror.set_request(upstream_request);
+        }
+
+        Ok(())
+    }
+
+    async fn response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        self.decorate_response(upstream_response, ctx)?;
+        if session.req_header().version == Version::HTTP_10
+            && upstream_response.status != StatusCode::SWITCHING_PROTOCOLS
+        {
+            let keep_alive = Self::frame_for_http10(session.req_header(), upstream_response)?;
+            if !keep_alive {
+                session.set_keepalive(None);
+            }
+        }
+        if self.config.header_size_metrics {
+            metrics::RESPONSE_HEADER_BYTES
+                .with_label_values(&[self.listener.name.as_str()])
+                .observe(headers::header_bytes(&upstream_response.headers) as f64);
+        }
+        Ok(())
+    }
+
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        match headers::check_framing(&upstream_response.headers) {
+            Ok(None) => {}
+            Ok(Some(length)) => upstream_response.insert_header(CONTENT_LENGTH, length)?,
+            Err(e) => {
+                warn!(
+                    unique_id = ?ctx.route.as_ref().map(|r| &r.unique_id),
+                    error = %e,
+                    "Rejecting upstream response with ambiguous framing"
+                );
+                return Error::e_explain(HTTPStatus(502), e.to_string());
+            }
+        }
+
+        match response_limit::evaluate(
+            upstream_response,
+            self.config.max_response_body_bytes,
+            &self.config.response_limit_exempt_types,
+        ) {
+            ResponseLimit::Unlimited => {}
+            ResponseLimit::Limit(max) => ctx.response_body_limit = Some(max),
+            ResponseLimit::TooLarge => {
+                let max = self.config.max_response_body_bytes.unwrap_or_default();
+                return Self::abort_response_too_large(ctx, max, "headers");
+            }
+        }
+
+        if let Some(route) = ctx.route.as_ref() {
+            Self::restore_location_prefix(
+                upstream_response,
+                &route.devbox.policy,
+                Self::request_host(session.req_header()),
+            )?;
+        }
+
+        // Before the cache sees the headers, so it stores the rewritten body
+        let base_href = ctx
+            .route
+            .as_ref()
+            .and_then(|route| route.devbox.policy.base_href.as_deref());
+        if let Some(href) = base_href.filter(|_| {
+            session.req_header().method != Method::HEAD && base_href::applies(upstream_response)
+        }) {
+            base_href::prepare(upstream_response)?;
+            ctx.base_href = Some(BaseHrefInjector::new(href));
+        }
+
+        // Before CORS headers and cookies are added for this client
+        if let (Some(cache), Some(key), Some(route)) =
+            (&self.cache, ctx.cache_key.take(), &ctx.route)
+        {
+            ctx.cache_fill = cache.start_fill(
+                key,
+                route.backend_generation,
+                &session.req_header().headers,
+                upstream_response.status,
+                &upstream_response.headers,
+            );
+        }
+        Ok(())
+    }
+
+    fn upstream_response_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        if body.is_some() {
+            ctx.last_activity = Instant::now();
+        }
+
+        // Stop a devbox streaming without end before it reaches the client
+        if let (Some(chunk), Some(max)) = (body.as_ref(), ctx.response_body_limit) {
+            ctx.response_body_bytes += chunk.len() as u64;
+            if ctx.response_body_bytes > max {
+                ctx.cache_fill = None;
+                return Self::abort_response_too_large(ctx, max, "body");
+            }
+        }
+
+        if let Some(injector) = ctx.base_href.as_mut() {
+            injector.filter(body, end_of_stream);
+        }
+
+        let Some(fill) = ctx.cache_fill.as_mut() else {
+            return Ok(());
+        };
+        if body.as_ref().is_some_and(|chunk| !fill.push(chunk)) {
+            ctx.cache_fill = None;
+            return Ok(());
+        }
+        if end_of_stream {
+            if let (Some(cache), Some(fill), Some(route)) =
+                (&self.cache, ctx.cache_fill.take(), &ctx.route)
+            {
+                cache.finish_fill(fill, &route.devbox);
+            }
+        }
+        Ok(())
+    }
+
+    fn fail_to_connect(
+        &self,
+        session: &mut Session,
+        _peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<Error>,
+    ) -> Box<Error> {
+        ctx.connect_failures += 1;
+        let req = session.req_header();
+        if ctx.connect_failures <= self.config.upstream_connect_retries
+            && retry::is_retryable(req, self.config.upstream_retry_policy)
+        {
+            debug!(
+                method = %req.method,
+                unique_id = ?ctx.route.as_ref().map(|r| &r.unique_id),
+                attempt = ctx.connect_failures,
+                error = %e,
+                "Retrying upstream connection"
+            );
+            e.set_retry(true);
+        } else if let Some(route) = &ctx.route {
+            self.record_anomaly(&route.devbox, Anomaly::BackendUnreachable, || {
+                format!(
+                    "Requests failed: cannot connect to port {}: {}",
+                    route.backend_port,
+                    e.etype().as_str()
+                )
+            });
+        }
+        e
+    }
+
+    /// Handle a devbox failing after the connection was established.
+    ///
+    /// Like the default implementation, the request is retried if it failed
+    /// on a reused connection the devbox may have closed. Otherwise a GET or
+    /// HEAD whose response hasn't started is retried once against the
+    /// devbox's replacement Pod, if the registry has moved on to one. Once
+    /// the response has started it can only be cut short.
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<Error> {
+        let mut e = e.more_context(format!("Peer: {peer}"));
+        let replayable = !session.retry_buffer_truncated();
+        e.retry.decide_reuse(client_reused && replayable);
+        if e.retry() {
+            return e;
+        }
+        let Some(route) = ctx.route.as_mut() else {
+            return e;
+        };
+
+        let req = session.req_header();
+        if session.response_written().is_some() {
+            warn!(
+                method = %req.method,
+                unique_id = %route.unique_id,
+                backend = %format!("{}:{}", route.backend_ip, route.backend_port),
+                error = %e,
+                "truncated_response"
+            );
+            metrics::UPSTREAM_FAILOVERS_TOTAL
+                .with_label_values(&["truncated"])
+                .inc();
+            return e;
+        }
+        // Routes set by override have no replacement in the registry
+        if !replayable
+            || route.backend_generation == 0
+            || !retry::can_fail_over(
+                &req.method,
+                route.failed_generations.len(),
+                ctx.start.elapsed(),
+                self.config.upstream_failover_budget,
+            )
+        {
+            return e;
+        }
+
+        let endpoint = match self.resolve_backend(&route.unique_id, route.backend_port) {
+            BackendResult::Ok(endpoint, _, _)
+                if endpoint.generation != route.backend_generation
+                    && !route.failed_generations.contains(&endpoint.generation) =>
+            {
+                endpoint
+            }
+            _ => {
+                metrics::UPSTREAM_FAILOVERS_TOTAL
+                    .with_label_values(&["no_candidate"])
+                    .inc();
+                return e;
+            }
+        };
+        info!(
+            method = %req.method,
+            unique_id = %route.unique_id,
+            from = %route.backend_ip,
+            to = %endpoint.ip,
+            error = %e,
+            "Failing over to replacement Pod"
+        );
+        route.failed_generations.push(route.backend_generation);
+        route.backend_ip = endpoint.ip;
+        route.backend_generation = endpoint.generation;
+        // Anything counted from the failed response starts over
+        ctx.response_body_limit = None;
+        ctx.response_body_bytes = 0;
+        ctx.cache_fill = None;
+        e.set_retry(true);
+        e
+    }
+
+    /// Answer a request that failed before or while proxying, like the
+    /// default implementation but with a body in the client's error format.
+    async fn fail_to_proxy(
+        &self,
+        session: &mut Session,
+        e: &Error,
+        ctx: &mut Self::CTX,
+    ) -> FailToProxy {
+        let deadline_exceeded = ctx.deadline.is_some_and(|d| d <= Instant::now());
+        let code = Self::failure_status(e, deadline_exceeded, &self.config.failure_statuses);
+        // Nothing can be sent once the backend's response has started; the
+        // downstream connection is closed, cutting the response short
+        if code > 0 && session.response_written().is_none() {
+            let req = session.req_header();
+            let format = self.error_format(req);
+            let response = if code == 504 && deadline_exceeded {
+                Self::deadline_exceeded_response(&self.localize(DEADLINE_EXCEEDED, req), format)
+            } else if ctx.response_too_large {
+                Self::error_response(&self.localize(RESPONSE_TOO_LARGE, req), format)
+            } else if ctx.endpoint_changed {
+                Self::error_response(&self.localize(ENDPOINT_CHANGED, req), format)
+            } else {
+                let error = UpstreamFailure::classify(e)
+                    .map_or_else(|| GatewayError::from_status(code), |f| f.error(code));
+                Self::error_response(&self.localize(error, req), format)
+            };
+            let sent = match response {
+                Ok((header, body)) => Self::send_response(session, header, body).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = sent {
+                warn!(error = %e, "Failed to send error response");
+            }
+        }
+        FailToProxy {
+            error_code: code,
+            can_reuse_downstream: false,
+        }
+    }
+
+    async fn logging(&self, session: &mut Session, e: Option<&Error>, ctx: &mut Self::CTX) {
+        let elapsed = ctx.start.elapsed();
+        let status = session
+            .response_written()
+            .map_or(0, |resp| resp.status.as_u16());
+
+        let req = session.req_header();
+        metrics::REQUESTS_TOTAL
+            .with_label_values(&[
+                self.listener.name.as_str(),
+                &status.to_string(),
+                Self::version_label(req.version),
+            ])
+            .inc();
+
+        let route = ctx.route.as_ref();
+        // Successful internal requests only get a short record at debug level
+        if ctx.internal && e.is_none() {
+            debug!(
+                target: ACCESS_LOG_TARGET,
+                method = %req.method,
+                host = %Self::request_host(req),
+                status = status,
+                duration_ms = elapsed.as_millis(),
+                "Internal access"
+            );
+        } else if access_log::should_log(status, e.is_some(), self.log_sampled(ctx)) {
+            info!(
+                target: ACCESS_LOG_TARGET,
+                listener = %self.listener.name,
+                method = %req.method,
+                host = %Self::request_host(req),
+                path = %req.uri.path(),
+                status = status,
+                unique_id = route.map_or("", |r| r.unique_id.as_str()),
+                cluster = route.map_or("", |r| &*r.devbox.cluster),
+                namespace = route.map_or("", |r| r.devbox.namespace.as_str()),
+                devbox_name = route.map_or("", |r| r.devbox.devbox_name.as_str()),
+                backend = %route.map(|r| format!("{}:{}", r.backend_ip, r.backend_port)).unwrap_or_default(),
+                client_cert_subject = ctx.client_cert.as_ref().map_or("", |c| c.subject.as_str()),
+                client_cert_san = ctx.client_cert.as_ref().map_or("", |c| c.san.as_str()),
+                duration_ms = elapsed.as_millis(),
+                error = ?e.map(ToString::to_string),
+                "Access"
+            );
+        } else {
+            metrics::ACCESS_LOGS_SUPPRESSED_TOTAL
+                .with_label_values(&[self.listener.name.as_str()])
+                .inc();
+        }
+
+        if let Some(headers) = self.headers_on_error(req, ctx) {
+            info!(
+                target: ACCESS_LOG_TARGET,
+                listener = %self.listener.name,
+                method = %req.method,
+                host = %Self::request_host(req),
+                path = %req.uri.path(),
+                status = status,
+                headers = %headers,
+                "Request failed to route"
+            );
+        }
+
+        if let Some(route) = route {
+            if !route.failed_generations.is_empty() {
+                let result = if e.is_none() { "recovered" } else { "failed" };
+                metrics::UPSTREAM_FAILOVERS_TOTAL
+                    .with_label_values(&[result])
+                    .inc();
+            }
+            if self.is_slow_request(elapsed) {
+                Self::log_slow_request(route, elapsed, e);
+            }
+            if Self::closed_idle(ctx, e, Instant::now()) {
+                debug!(
+                    unique_id = %route.unique_id,
+                    duration_ms = elapsed.as_millis(),
+                    "Closed idle WebSocket connection"
+                );
+                metrics::WEBSOCKETS_IDLE_CLOSED_TOTAL
+                    .with_label_values(&[route.devbox.namespace.as_str()])
+                    .inc();
+            }
+        }
+
+        // Only sent now, so the mirror can't hold up the response
+        if let Some(mirror) = ctx.mirror.take() {
+            self.mirror.dispatch(mirror);
+        }
+    }
+}
+
+/// HTML-escaped text of a gateway page in `lang`, or `builtin` if there
+/// is no translation, with the language it is in if translated
+fn translate_page<'a>(
+    locales: &'a Locales,
+    lang: &str,
+    key: &str,
+    vars: &[(&str, &str)],
+    builtin: &str,
+) -> (String, Option<&'a str>) {
+    match locales.translate(lang, key, vars) {
+        Some(translation) => (
+            suggest::html_escape(&translation.text),
+            Some(translation.lang),
+        ),
+        None => (builtin.to_string(), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basic_auth::BasicAuth;
+    use crate::blocklist::BlockEntry;
+    use crate::client_cert::CaSource;
+    use crate::identity::IdentityHeaders;
+    use crate::policy::ANNOTATION_DENY_REQUEST_HEADERS;
+    use crate::registry::{WatchKind, DEFAULT_CLUSTER};
+    use regex::Regex;
+    use std::collections::{BTreeMap, HashMap};
+    use tokio_rustls::rustls::pki_types::CertificateDer;
+
+    // HTTP protocol tests (devbox- prefix)
+
+    #[test]
+    fn test_parse_host_http_standard_format() {
+        let result = DevboxProxy::parse_host("devbox-outdoor-before-78648-8080.devbox.sealos.io");
+        assert_eq!(
+            result,
+            Some((
+                UpstreamProtocol::Http,
+                "outdoor-before-78648".to_string(),
+                8080
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_host_http_simple_id() {
+        let result = DevboxProxy::parse_host("devbox-my-app-8080.devbox.sealos.io");
+        assert_eq!(
+            result,
+            Some((UpstreamProtocol::Http, "my-app".to_string(), 8080))
+        );
+    }
+
+    #[test]
+    fn test_parse_host_http_single_word() {
+        let result = DevboxProxy::parse_host("devbox-myapp-443.devbox.sealos.io");
+        assert_eq!(
+            result,
+            Some((UpstreamProtocol::Http, "myapp".to_string(), 443))
+        );
+    }
+
+    #[test]
+    fn test_parse_host_http_with_numbers() {
+        let result = DevboxProxy::parse_host("devbox-app123-test456-3000.devbox.sealos.io");
+        assert_eq!(
+            result,
+            Some((UpstreamProtocol::Http, "app123-test456".to_string(), 3000))
+        );
+    }
+
+    #[test]
+    fn test_parse_host_http_with_port_suffix() {
+        let result =
+            DevboxProxy::parse_host("devbox-outdoor-before-78648-8080.devbox.sealos.io:443");
+        assert_eq!(
+            result,
+            Some((
+                UpstreamProtocol::Http,
+                "outdoor-before-78648".to_string(),
+                8080
+            ))
+        );
+    }
+
+    // gRPC protocol tests (devboxgrpc- prefix)
+
+    #[test]
+    fn test_parse_host_grpc_standard_format() {
+        let result =
+            DevboxProxy::parse_host("devboxgrpc-outdoor-before-78648-50051.devbox.sealos.io");
+        assert_eq!(
+            result,
+            Some((
+                UpstreamProtocol::Grpc,
+                "outdoor-before-78648".to_string(),
+                50051
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_host_grpc_simple_id() {
+        let result = DevboxProxy::parse_host("devboxgrpc-my-app-50051.devbox.sealos.io");
+        assert_eq!(
+            result,
+            Some((UpstreamProtocol::Grpc, "my-app".to_string(), 50051))
+        );
+    }
+
+    #[test]
+    fn test_parse_host_grpc_with_port_suffix() {
+        let result =
+            DevboxProxy::parse_host("devboxgrpc-outdoor-before-78648-50051.devbox.sealos.io:443");
+        assert_eq!(
+            result,
+            Some((
+                UpstreamProtocol::Grpc,
+                "outdoor-before-78648".to_string(),
+                50051
+            ))
+        );
+    }
+
+    #[test]
+    fn test_route_host() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "my-app".to_string(),
+            "ns".to_string(),
+            "devbox1".to_string(),
+        );
+        let config = Arc::new(Config::default());
+        let policy = ListenerPolicy {
+            domain_suffixes: vec!["devbox.sealos.io".to_string()],
+            ..ListenerConfig::from_config(&config).policy
+        };
+        let proxy = DevboxProxy::with_listener(registry, config, policy);
+
+        assert_eq!(
+            proxy.route_host("devbox-my-app-8080.devbox.sealos.io"),
+            HostRoute::Devbox(UpstreamProtocol::Http, "my-app".to_string(), 8080)
+        );
+        // Unknown devboxes and unparsable hosts of our domain are not found...
+        assert_eq!(
+            proxy.route_host("devbox-other-app-8080.devbox.sealos.io"),
+            HostRoute::NotFound
+        );
+        assert_eq!(
+            proxy.route_host("www.devbox.sealos.io"),
+            HostRoute::NotFound
+        );
+        // ...while other domains are misdirected, even for known devboxes
+        assert_eq!(
+            proxy.route_host("devbox-my-app-8080.other.io"),
+            HostRoute::Misdirected
+        );
+        assert_eq!(proxy.route_host("example.com"), HostRoute::Misdirected);
+    }
+
+    #[test]
+    fn test_route_portless_host() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox_info(
+            "outdoor-before-78648".to_string(),
+            DevboxInfo {
+                app_port: Some(3000),
+                ..DevboxInfo::new("ns".to_string(), "devbox1".to_string())
+            },
+        );
+        registry.register_devbox(
+            "my-app".to_string(),
+            "ns".to_string(),
+            "devbox2".to_string(),
+        );
+        let proxy = DevboxProxy::with_config(registry, Arc::new(Config::default()));
+
+        assert_eq!(
+            proxy.route_host("devbox-outdoor-before-78648.devbox.sealos.io"),
+            HostRoute::Devbox(
+                UpstreamProtocol::Http,
+                "outdoor-before-78648".to_string(),
+                3000
+            )
+        );
+        assert_eq!(
+            proxy.route_host("devboxgrpc-outdoor-before-78648.devbox.sealos.io:443"),
+            HostRoute::Devbox(
+                UpstreamProtocol::Grpc,
+                "outdoor-before-78648".to_string(),
+                3000
+            )
+        );
+        // The numeric suffix still picks any port
+        assert_eq!(
+            proxy.route_host("devbox-outdoor-before-78648-8080.devbox.sealos.io"),
+            HostRoute::Devbox(
+                UpstreamProtocol::Http,
+                "outdoor-before-78648".to_string(),
+                8080
+            )
+        );
+        // Devboxes declaring no app port have no portless host
+        assert_eq!(
+            proxy.route_host("devbox-my-app.devbox.sealos.io"),
+            HostRoute::NotFound
+        );
+        assert_eq!(
+            proxy.route_host("devbox-my-app-8080.devbox.sealos.io"),
+            HostRoute::Devbox(UpstreamProtocol::Http, "my-app".to_string(), 8080)
+        );
+        assert_eq!(
+            proxy.route_host("devbox-other-app.devbox.sealos.io"),
+            HostRoute::NotFound
+        );
+
+        assert_eq!(
+            HostParser::parse_portless("devbox-my-app.devbox.sealos.io"),
+            Some((UpstreamProtocol::Http, "my-app".to_string()))
+        );
+        assert_eq!(HostParser::parse_portless("devbox-My-App.x"), None);
+        assert_eq!(HostParser::parse_portless("devbox-my-app"), None);
+        assert_eq!(HostParser::parse_portless("my-app.x"), None);
+    }
+
+    #[test]
+    fn test_route_host_prefers_port_suffix() {
+        // "my-app-8080" is both a uniqueID and "my-app" on port 8080
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "my-app".to_string(),
+            "ns".to_string(),
+            "devbox1".to_string(),
+        );
+        registry.register_devbox_info(
+            "my-app-8080".to_string(),
+            DevboxInfo {
+                app_port: Some(3000),
+                ..DevboxInfo::new("ns".to_string(), "devbox2".to_string())
+            },
+        );
+        let proxy = DevboxProxy::with_config(registry, Arc::new(Config::default()));
+
+        assert_eq!(
+            proxy.route_host("devbox-my-app-8080.devbox.sealos.io"),
+            HostRoute::Devbox(UpstreamProtocol::Http, "my-app".to_string(), 8080)
+        );
+        assert_eq!(
+            proxy.route_host("devbox-my-app-8080-9000.devbox.sealos.io"),
+            HostRoute::Devbox(UpstreamProtocol::Http, "my-app-8080".to_string(), 9000)
+        );
+    }
+
+    #[test]
+    fn test_parse_host_matches_regex() {
+        let regex = HostParser::Pattern(Regex::new(DEFAULT_HOST_PATTERN).unwrap());
+        for host in [
+            "devbox-outdoor-before-78648-8080.devbox.sealos.io",
+            "devboxgrpc-my-app-50051.devbox.sealos.io:443",
+            "devbox-a-1.x",
+            "devbox-0-0.x",
+            "devbox-my-app-08080.x",
+            "devbox-a--b-80.x",
+            "devbox-my-app-65535.x",
+            "devbox-my-app-65536.x",
+            "devbox-my-app-99999999999999999999.x",
+            "devbox-my-app-8080",
+            "devbox-my-app-8080x.x",
+            "devbox-my-app-.x",
+            "devbox--8080.x",
+            "devbox-8080.x",
+            "devbox-My-App-8080.x",
+            "devbox-my_app-8080.x",
+            "devbox-my.app-8080.x",
+            "devbox-my-app-+80.x",
+            "devbox-caf\u{e9}-8080.x",
+            "devbox-my-app-8080..x",
+            "devbox-.x",
+            "devbox-",
+            "devboxgrpc-",
+            "devbox-my-app-80:80.x",
+        ] {
+            assert_eq!(HostParser::Default.parse(host), regex.parse(host), "{host}");
+        }
+    }
+
+    #[test]
+    fn test_custom_host_pattern() {
+        let config = Config {
+            host_pattern: Some(Regex::new(r"^([a-z\d-]+)\.p(\d+)\.").unwrap()),
+            ..Default::default()
+        };
+        let parser = HostParser::from_config(&config);
+        assert_eq!(
+            parser.parse("devbox-my-app.p8080.devbox.sealos.io"),
+            Some((UpstreamProtocol::Http, "my-app".to_string(), 8080))
+        );
+        assert_eq!(
+            parser.parse("devboxgrpc-my-app.p50051.devbox.sealos.io:443"),
+            Some((UpstreamProtocol::Grpc, "my-app".to_string(), 50051))
+        );
+        assert_eq!(parser.parse("devbox-my-app-8080.devbox.sealos.io"), None);
+        assert_eq!(parser.parse("my-app.p8080.devbox.sealos.io"), None);
+
+        // The uniqueID filter is skipped for custom patterns
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "my-app".to_string(),
+            "ns".to_string(),
+            "devbox1".to_string(),
+        );
+        let proxy = DevboxProxy::with_config(registry, Arc::new(config));
+        assert_eq!(
+            proxy.route_host("devbox-my-app.p8080.devbox.sealos.io"),
+            HostRoute::Devbox(UpstreamProtocol::Http, "my-app".to_string(), 8080)
+        );
+
+        // Hosts the pattern doesn't match may still be portless
+        proxy.registry.register_devbox_info(
+            "my-app".to_string(),
+            DevboxInfo {
+                app_port: Some(3000),
+                ..DevboxInfo::new("ns".to_string(), "devbox1".to_string())
+            },
+        );
+        assert_eq!(
+            proxy.route_host("devbox-my-app.devbox.sealos.io"),
+            HostRoute::Devbox(UpstreamProtocol::Http, "my-app".to_string(), 3000)
+        );
+        assert_eq!(
+            proxy.route_host("devbox-my-app.p8080.devbox.sealos.io"),
+            HostRoute::Devbox(UpstreamProtocol::Http, "my-app".to_string(), 8080)
+        );
+    }
+
+    // Invalid format tests
+
+    #[test]
+    fn test_candidate_unique_id_matches_parse_host() {
+        for host in [
+            "devbox-outdoor-before-78648-8080.devbox.sealos.io",
+            "devbox-my-app-8080.devbox.xxx:443",
+            "devboxgrpc-my-app-50051.devbox.sealos.io",
+            "devbox-app1-3000.example.com",
+        ] {
+            let (_, unique_id, _) = DevboxProxy::parse_host(host).unwrap();
+            assert_eq!(
+                DevboxProxy::candidate_unique_id(host),
+                Some(unique_id.as_str()),
+                "{host}"
+            );
+        }
+        for host in ["example.com", "devbox-nodots", "devbox-noport.example.com"] {
+            assert_eq!(DevboxProxy::candidate_unique_id(host), None, "{host}");
+        }
+    }
+
+    #[test]
+    fn test_parse_host_invalid_no_port() {
+        assert!(DevboxProxy::parse_host("devbox-outdoor-before.devbox.sealos.io").is_none());
+        assert!(DevboxProxy::parse_host("devboxgrpc-outdoor-before.devbox.sealos.io").is_none());
+    }
+
+    #[test]
+    fn test_parse_host_invalid_format() {
+        // No prefix
+        assert!(DevboxProxy::parse_host("invalid.example.com").is_none());
+        assert!(DevboxProxy::parse_host("").is_none());
+        // Missing prefix
+        assert!(DevboxProxy::parse_host("outdoor-before-78648-8080.devbox.sealos.io").is_none());
+        // Invalid uniqueID format (starts/ends with hyphen)
+        assert!(DevboxProxy::parse_host("devbox--invalid-8080.devbox.io").is_none());
+        assert!(DevboxProxy::parse_host("devbox-invalid--8080.devbox.io").is_none());
+        assert!(DevboxProxy::parse_host("devboxgrpc--invalid-50051.devbox.io").is_none());
+    }
+
+    #[test]
+    fn test_resolve_backend_with_pod_ip() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "outdoor-before-78648".to_string(),
+            "ns-admin".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-admin", "devbox1", "10.107.173.213".to_string())
+            .unwrap();
+
+        let proxy = DevboxProxy::new(registry);
+
+        let result = proxy.resolve_backend("outdoor-before-78648", 8080);
+        assert!(matches!(
+            result,
+            BackendResult::Ok(endpoint, 8080, _) if endpoint.ip == "10.107.173.213"
+        ));
+    }
+
+    #[test]
+    fn test_pod_ip_change_routes_to_new_endpoint() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "outdoor-before-78648".to_string(),
+            "ns-admin".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-admin", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        let proxy = DevboxProxy::new(Arc::clone(&registry));
+
+        let peer_for = |proxy: &DevboxProxy| {
+            let BackendResult::Ok(endpoint, port, devbox) =
+                proxy.resolve_backend("outdoor-before-78648", 8080)
+            else {
+                panic!("backend should resolve");
+            };
+            DevboxProxy::build_peer(
+                &ProxyCtx {
+                    unique_id: "outdoor-before-78648".to_string(),
+                    backend_ip: endpoint.ip,
+                    backend_port: port,
+                    backend_generation: endpoint.generation,
+                    failed_generations: Vec::new(),
+                    protocol: UpstreamProtocol::Http,
+                    devbox,
+                },
+                None,
+            )
+        };
+
+        let before = peer_for(&proxy);
+        assert_eq!(before._address.to_string(), "10.0.0.1:8080");
+
+        // Pod restarted with a new IP: the next request dials the new IP and
+        // lands in a different connection pool group
+        registry
+            .update_pod_ip("ns-admin", "devbox1", "10.0.0.2".to_string())
+            .unwrap();
+        let after = peer_for(&proxy);
+        assert_eq!(after._address.to_string(), "10.0.0.2:8080");
+        assert_ne!(after.group_key, before.group_key);
+
+        // Unchanged IP keeps reusing pooled connections
+        assert_eq!(peer_for(&proxy).group_key, after.group_key);
+    }
+
+    #[test]
+    fn test_stale_route_not_dialed() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "outdoor-before-78648".to_string(),
+            "ns-admin".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-admin", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        let proxy = DevboxProxy::new(Arc::clone(&registry));
+
+        // request_filter resolves the backend...
+        let resolve = || {
+            let BackendResult::Ok(endpoint, port, devbox) =
+                proxy.resolve_backend("outdoor-before-78648", 8080)
+            else {
+                panic!("backend should resolve");
+            };
+            ProxyCtx {
+                unique_id: "outdoor-before-78648".to_string(),
+                backend_ip: endpoint.ip,
+                backend_port: port,
+                backend_generation: endpoint.generation,
+                failed_generations: Vec::new(),
+                protocol: UpstreamProtocol::Http,
+                devbox,
+            }
+        };
+        let route = resolve();
+        assert!(proxy.is_current_route(&route));
+
+        // ...the devbox is unregistered and its Pod deleted...
+        registry.unregister_devbox("outdoor-before-78648");
+        registry
+            .update_pod_ip("ns-admin", "devbox1", String::new())
+            .unwrap();
+        // ...and upstream_peer refuses the old address
+        assert!(!proxy.is_current_route(&route));
+
+        // Even once the devbox is back on a Pod with the same IP
+        registry.register_devbox(
+            "outdoor-before-78648".to_string(),
+            "ns-admin".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-admin", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        assert!(!proxy.is_current_route(&route));
+
+        // A request routed now connects, until the Pod is replaced before a
+        // connect retry
+        let route = resolve();
+        assert!(proxy.is_current_route(&route));
+        registry
+            .update_pod_ip("ns-admin", "devbox1", "10.0.0.2".to_string())
+            .unwrap();
+        assert!(!proxy.is_current_route(&route));
+
+        // Route overrides aren't checked against the registry
+        let overridden = ProxyCtx {
+            backend_generation: 0,
+            ..route
+        };
+        assert!(proxy.is_current_route(&overridden));
+    }
+
+    #[test]
+    fn test_resolve_backend_no_pod_ip() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "outdoor-before-78648".to_string(),
+            "ns-admin".to_string(),
+            "devbox1".to_string(),
+        );
+        // Pod IP not set
+
+        let proxy = DevboxProxy::new(registry);
+
+        let result = proxy.resolve_backend("outdoor-before-78648", 8080);
+        assert!(matches!(result, BackendResult::NotRunning));
+    }
+
+    #[test]
+    fn test_resolve_backend_blocked() {
+        let registry = Arc::new(DevboxRegistry::new());
+        let config = Arc::new(Config {
+            blocked_unique_ids: vec!["blocked-app".to_string()],
+            blocked_namespaces: vec!["ns-spam".to_string()],
+            ..Default::default()
+        });
+        let proxy = DevboxProxy::with_config(Arc::clone(&registry), config);
+
+        // Unique ID blocks apply even to unknown devboxes
+        assert!(matches!(
+            proxy.resolve_backend("blocked-app", 8080),
+            BackendResult::Blocked(BlockEntry::UniqueId(_))
+        ));
+
+        // Namespace blocks apply to devboxes registered after the block,
+        // before the running check
+        registry.register_devbox(
+            "new-app".to_string(),
+            "ns-spam".to_string(),
+            "devbox1".to_string(),
+        );
+        assert!(matches!(
+            proxy.resolve_backend("new-app", 8080),
+            BackendResult::Blocked(BlockEntry::Namespace(_))
+        ));
+        registry
+            .update_pod_ip("ns-spam", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        assert!(matches!(
+            proxy.resolve_backend("new-app", 8080),
+            BackendResult::Blocked(BlockEntry::Namespace(_))
+        ));
+
+        // Other devboxes are unaffected
+        assert!(matches!(
+            proxy.resolve_backend("unknown-app", 8080),
+            BackendResult::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_resolve_backend_not_found() {
+        let registry = Arc::new(DevboxRegistry::new());
+        let proxy = DevboxProxy::new(registry);
+
+        let result = proxy.resolve_backend("unknown-id-123", 8080);
+        assert!(matches!(result, BackendResult::NotFound));
+    }
+
+    fn ctx_with_policy(port: u16, protocol: UpstreamProtocol, policy: DevboxPolicy) -> ProxyCtx {
+        let mut devbox = DevboxInfo::new("ns-admin".to_string(), "devbox1".to_string());
+        devbox.policy = Arc::new(policy);
+        ProxyCtx {
+            unique_id: "outdoor-before-78648".to_string(),
+            backend_ip: "10.107.173.213".to_string(),
+            backend_port: port,
+            backend_generation: 1,
+            failed_generations: Vec::new(),
+            protocol,
+            devbox,
+        }
+    }
+
+    #[test]
+    fn test_mirror_target() {
+        let registry = Arc::new(DevboxRegistry::new());
+        for (unique_id, namespace, name, ip) in [
+            ("shadow-app", "ns-admin", "shadow", Some("10.0.0.9")),
+            ("stopped-app", "ns-admin", "stopped", None),
+            ("other-app", "ns-other", "other", Some("10.0.1.9")),
+        ] {
+            registry.register_devbox(
+                unique_id.to_string(),
+                namespace.to_string(),
+                name.to_string(),
+            );
+            if let Some(ip) = ip {
+                registry
+                    .update_pod_ip(namespace, name, ip.to_string())
+                    .unwrap();
+            }
+        }
+        let proxy = DevboxProxy::new(registry);
+        let mirror = |shadow: Option<&str>| MirrorPolicy {
+            port: 8080,
+            shadow: shadow.map(String::from),
+            mirror_port: 8081,
+            percent: 100,
+        };
+
+        let route = ctx_with_policy(8080, UpstreamProtocol::Http, DevboxPolicy::default());
+        assert_eq!(
+            proxy.mirror_target(&route, &mirror(None)).as_deref(),
+            Some("10.107.173.213")
+        );
+        assert_eq!(
+            proxy
+                .mirror_target(&route, &mirror(Some("shadow-app")))
+                .as_deref(),
+            Some("10.0.0.9")
+        );
+        // Only running devboxes of the same namespace
+        for shadow in ["stopped-app", "other-app", "missing-app"] {
+            assert_eq!(proxy.mirror_target(&route, &mirror(Some(shadow))), None);
+        }
+
+        // TLS mirror ports are left out
+        let policy = DevboxPolicy {
+            tls_ports: vec![8081],
+            ..Default::default()
+        };
+        let route = ctx_with_policy(8080, UpstreamProtocol::Http, policy);
+        assert_eq!(proxy.mirror_target(&route, &mirror(None)), None);
+    }
+
+    #[test]
+    fn test_build_peer_cleartext() {
+        let ctx = ctx_with_policy(8080, UpstreamProtocol::Http, DevboxPolicy::default());
+        let peer = DevboxProxy::build_peer(&ctx, None);
+        assert!(!peer.is_tls());
+        assert_eq!(peer.options.alpn, ALPN::H1);
+    }
+
+    #[test]
+    fn test_build_peer_tls_verified() {
+        let policy = DevboxPolicy {
+            tls_ports: vec![8443],
+            ..Default::default()
+        };
+        let ctx = ctx_with_policy(8443, UpstreamProtocol::Http, policy);
+        let peer = DevboxProxy::build_peer(&ctx, None);
+        assert!(peer.is_tls());
+        assert_eq!(peer.sni, "10.107.173.213");
+        assert!(peer.options.verify_cert);
+        assert!(peer.options.verify_hostname);
+    }
+
+    #[test]
+    fn test_build_peer_tls_sni() {
+        let policy = DevboxPolicy {
+            tls_ports: vec![8443],
+            ..Default::default()
+        };
+        let ctx = ctx_with_policy(8443, UpstreamProtocol::Http, policy);
+
+        // The configured SNI is sent while still dialing the Pod IP
+        let peer = DevboxProxy::build_peer(&ctx, Some("ingress.internal"));
+        assert_eq!(peer.sni, "ingress.internal");
+        assert_eq!(peer._address.to_string(), "10.107.173.213:8443");
+        assert!(peer.options.verify_hostname);
+
+        // The devbox's annotation takes precedence
+        let policy = DevboxPolicy {
+            tls_ports: vec![8443],
+            tls_sni: Some("app.internal".to_string()),
+            ..Default::default()
+        };
+        let ctx = ctx_with_policy(8443, UpstreamProtocol::Http, policy);
+        let peer = DevboxProxy::build_peer(&ctx, Some("ingress.internal"));
+        assert_eq!(peer.sni, "app.internal");
+        assert_eq!(peer._address.to_string(), "10.107.173.213:8443");
+
+        // Cleartext ports send no SNI
+        let ctx = ctx_with_policy(8080, UpstreamProtocol::Http, (*ctx.devbox.policy).clone());
+        let peer = DevboxProxy::build_peer(&ctx, Some("ingress.internal"));
+        assert!(!peer.is_tls());
+        assert_eq!(peer.sni, "");
+    }
+
+    #[test]
+    fn test_build_peer_tls_skip_verify() {
+        let policy = DevboxPolicy {
+            tls_ports: vec![8443],
+            tls_skip_verify: true,
+            ..Default::default()
+        };
+        let ctx = ctx_with_policy(8443, UpstreamProtocol::Grpc, policy.clone());
+        let peer = DevboxProxy::build_peer(&ctx, None);
+        assert!(peer.is_tls());
+        assert!(!peer.options.verify_cert);
+        assert!(!peer.options.verify_hostname);
+        assert_eq!(peer.options.alpn, ALPN::H2);
+
+        // Skip-verify only applies to TLS ports
+        let ctx = ctx_with_policy(8080, UpstreamProtocol::Http, policy);
+        let peer = DevboxProxy::build_peer(&ctx, None);
+        assert!(!peer.is_tls());
+    }
+
+    #[test]
+    fn test_path_prefixes() {
+        let policy = DevboxPolicy {
+            strip_prefix: Some("/app".to_string()),
+            ..Default::default()
+        };
+        let host = "devbox-my-app-8080.devbox.local";
+
+        // The request path loses the prefix; the query and encoded
+        // characters are forwarded as sent
+        let mut req = RequestHeader::build("GET", b"/app/a%2Fb?q=1%202&r=/app", None).unwrap();
+        let path = path::rewrite_prefix(req.uri.path(), policy.strip_prefix.as_deref(), None);
+        DevboxProxy::rewrite_path(&mut req, path.as_deref().unwrap()).unwrap();
+        assert_eq!(req.uri.to_string(), "/a%2Fb?q=1%202&r=/app");
+
+        // Redirects into the app come back under the prefix
+        for (location, expected) in [
+            ("/login?next=%2Fhome", "/app/login?next=%2Fhome"),
+            (
+                "https://devbox-my-app-8080.devbox.local/",
+                "https://devbox-my-app-8080.devbox.local/app/",
+            ),
+            ("https://example.com/login", "https://example.com/login"),
+            ("login", "login"),
+        ] {
+            let mut resp = ResponseHeader::build(302, None).unwrap();
+            resp.insert_header(LOCATION, location).unwrap();
+            DevboxProxy::restore_location_prefix(&mut resp, &policy, host).unwrap();
+            assert_eq!(resp.headers[LOCATION], expected, "{location}");
+        }
+
+        // Without the annotations, redirects pass as they are
+        let mut resp = ResponseHeader::build(302, None).unwrap();
+        resp.insert_header(LOCATION, "/login").unwrap();
+        DevboxProxy::restore_location_prefix(&mut resp, &DevboxPolicy::default(), host).unwrap();
+        assert_eq!(resp.headers[LOCATION], "/login");
+    }
+
+    #[test]
+    fn test_strip_denied_headers() {
+        let annotations = BTreeMap::from([(
+            ANNOTATION_DENY_REQUEST_HEADERS.to_string(),
+            "X-Internal-Auth,x-user-token".to_string(),
+        )]);
+        let policy = DevboxPolicy::from_annotations(&annotations);
+
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("X-Internal-Auth", "secret").unwrap();
+        req.append_header("x-user-token", "a").unwrap();
+        req.append_header("x-user-token", "b").unwrap();
+        req.insert_header("Accept", "*/*").unwrap();
+
+        req.insert_header("Authorization", "Basic YWxpY2U6czNjcmV0")
+            .unwrap();
+
+        DevboxProxy::strip_denied_headers(&policy, &mut req);
+        assert!(req.headers.get("x-internal-auth").is_none());
+        assert!(req.headers.get("x-user-token").is_none());
+        assert_eq!(req.headers.get("accept").unwrap(), "*/*");
+        assert!(req.headers.get("authorization").is_some());
+
+        // The credentials of a basic-auth gate stay at the gateway
+        let policy = DevboxPolicy {
+            basic_auth: Some(BasicAuth::deny_all()),
+            ..Default::default()
+        };
+        DevboxProxy::strip_denied_headers(&policy, &mut req);
+        assert!(req.headers.get("authorization").is_none());
+    }
+
+    #[test]
+    fn test_verify_client_cert() {
+        fn chain(pem: &[u8]) -> Vec<CertificateDer<'static>> {
+            rustls_pemfile::certs(&mut &pem[..])
+                .collect::<std::result::Result<_, _>>()
+                .unwrap()
+        }
+
+        let client_certs = Arc::new(ClientCerts::new());
+        let client_cas = Arc::new(ClientCaStore::new());
+        let ca = include_bytes!("../tests/fixtures/client-ca.crt");
+        client_cas
+            .load(CaSource::Secret, "ns-test", "client-ca", ca)
+            .unwrap();
+        let proxy = DevboxProxy::new(Arc::new(DevboxRegistry::new()))
+            .with_client_certs(Arc::clone(&client_certs))
+            .with_client_cas(client_cas);
+        let ca = ClientCaRef::parse("client-ca").unwrap();
+
+        let alice: SocketAddr = "203.0.113.1:40000".parse().unwrap();
+        let mallory: SocketAddr = "203.0.113.2:40000".parse().unwrap();
+        let anonymous: SocketAddr = "203.0.113.3:40000".parse().unwrap();
+        client_certs.insert(alice, chain(include_bytes!("../tests/fixtures/client.crt")));
+        client_certs.insert(
+            mallory,
+            chain(include_bytes!("../tests/fixtures/other-client.crt")),
+        );
+
+        let cert = proxy
+            .verify_client_cert(Some(alice), "ns-test", &ca)
+            .unwrap();
+        assert_eq!(cert.subject, "CN=alice,O=Example");
+        // Each devbox only trusts the bundle of its own namespace
+        assert_eq!(
+            proxy.verify_client_cert(Some(alice), "ns-other", &ca),
+            Err(ClientCertError::NoBundle)
+        );
+        assert!(matches!(
+            proxy.verify_client_cert(Some(mallory), "ns-test", &ca),
+            Err(ClientCertError::Invalid(_))
+        ));
+        assert_eq!(
+            proxy.verify_client_cert(Some(anonymous), "ns-test", &ca),
+            Err(ClientCertError::Missing)
+        );
+
+        // Closed connections take their certificate with them
+        client_certs.remove(&alice);
+        assert_eq!(
+            proxy.verify_client_cert(Some(alice), "ns-test", &ca),
+            Err(ClientCertError::Missing)
+        );
+    }
+
+    #[test]
+    fn test_authorize_preview() {
+        let config = Arc::new(Config {
+            signing_key: Some("proxy-key".to_string()),
+            ..Default::default()
+        });
+        let proxy = DevboxProxy::with_config(Arc::new(DevboxRegistry::new()), config);
+        let signer = PreviewSigner::new("proxy-key");
+        let token = signer.mint("my-app", 8080, preview::unix_now() + 3600);
+
+        // Query tokens are exchanged for a cookie
+        let path = format!("/index.html?hg_token={token}");
+        let req = RequestHeader::build("GET", path.as_bytes(), None).unwrap();
+        let cookie = proxy.authorize_preview(&req, "my-app", 8080).unwrap();
+        assert!(cookie
+            .unwrap()
+            .starts_with(&format!("hg_token={token}; Max-Age=")));
+        assert_eq!(
+            proxy.authorize_preview(&req, "my-app", 3000),
+            Err(TokenError::BadSignature)
+        );
+
+        let mut req = RequestHeader::build("GET", b"/app.js", None).unwrap();
+        assert_eq!(
+            proxy.authorize_preview(&req, "my-app", 8080),
+            Err(TokenError::Missing)
+        );
+        req.insert_header("Cookie", format!("hg_token={token}"))
+            .unwrap();
+        assert_eq!(proxy.authorize_preview(&req, "my-app", 8080), Ok(None));
+        assert_eq!(
+            proxy.authorize_preview(&req, "other-app", 8080),
+            Err(TokenError::BadSignature)
+        );
+
+        let expired = signer.mint("my-app", 8080, preview::unix_now() - 1);
+        req.insert_header("Cookie", format!("hg_token={expired}"))
+            .unwrap();
+        assert_eq!(
+            proxy.authorize_preview(&req, "my-app", 8080),
+            Err(TokenError::Expired)
+        );
+
+        // Without a signing key no token is accepted
+        let proxy = DevboxProxy::new(Arc::new(DevboxRegistry::new()));
+        let req = RequestHeader::build("GET", path.as_bytes(), None).unwrap();
+        assert_eq!(
+            proxy.authorize_preview(&req, "my-app", 8080),
+            Err(TokenError::Missing)
+        );
+    }
+
+    #[test]
+    fn test_resolve_override() {
+        let config = Arc::new(Config {
+            route_override_key: Some("route-key".to_string()),
+            ..Default::default()
+        });
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "my-app".to_string(),
+            "ns-admin".to_string(),
+            "app".to_string(),
+        );
+        let proxy = DevboxProxy::with_config(registry, config);
+        let signer = RouteSigner::new("route-key", 30);
+        let now = preview::unix_now();
+
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        assert!(proxy.resolve_override(&req, "my-app").is_none());
+
+        let route = "ns-admin/10.0.0.7:8080";
+        req.insert_header(route_override::ROUTE_HEADER, route)
+            .unwrap();
+        req.insert_header(route_override::SIGNATURE_HEADER, signer.sign(route, now))
+            .unwrap();
+        let Some(BackendResult::Ok(endpoint, port, devbox)) =
+            proxy.resolve_override(&req, "my-app")
+        else {
+            panic!("override not accepted");
+        };
+        assert_eq!((endpoint.ip.as_str(), port), ("10.0.0.7", 8080));
+        assert_eq!(devbox.devbox_name, "app");
+
+        // Devboxes of other namespaces aren't matched
+        let route = "ns-other/10.0.0.7:8080";
+        req.insert_header(route_override::ROUTE_HEADER, route)
+            .unwrap();
+        req.insert_header(route_override::SIGNATURE_HEADER, signer.sign(route, now))
+            .unwrap();
+        let Some(BackendResult::Ok(_, _, devbox)) = proxy.resolve_override(&req, "my-app") else {
+            panic!("override not accepted");
+        };
+        assert_eq!(
+            (devbox.namespace.as_str(), devbox.devbox_name.as_str()),
+            ("ns-other", "")
+        );
+
+        // Expired or tampered overrides fall back to the registry
+        req.insert_header(
+            route_override::SIGNATURE_HEADER,
+            signer.sign(route, now - 60),
+        )
+        .unwrap();
+        assert!(proxy.resolve_override(&req, "my-app").is_none());
+        req.insert_header(route_override::ROUTE_HEADER, "ns-other/10.0.0.8:8080")
+            .unwrap();
+        req.insert_header(route_override::SIGNATURE_HEADER, signer.sign(route, now))
+            .unwrap();
+        assert!(proxy.resolve_override(&req, "my-app").is_none());
+
+        // The address is still checked
+        let route = "ns-admin/0.0.0.0:8080";
+        req.insert_header(route_override::ROUTE_HEADER, route)
+            .unwrap();
+        req.insert_header(route_override::SIGNATURE_HEADER, signer.sign(route, now))
+            .unwrap();
+        assert!(matches!(
+            proxy.resolve_override(&req, "my-app"),
+            Some(BackendResult::InvalidAddress(..))
+        ));
+    }
+
+    #[test]
+    fn test_normalize_framing() {
+        let mut req = RequestHeader::build("POST", b"/", None).unwrap();
+        req.append_header("Content-Length", "5").unwrap();
+        req.append_header("content-length", "5").unwrap();
+        DevboxProxy::normalize_framing(&mut req).unwrap();
+        let lengths: Vec<_> = req.headers.get_all(CONTENT_LENGTH).iter().collect();
+        assert_eq!(lengths, vec!["5"]);
+
+        req.append_header("Transfer-Encoding", "chunked").unwrap();
+        assert_eq!(
+            DevboxProxy::normalize_framing(&mut req),
+            Err(FramingError::ContentLengthWithTransferEncoding)
+        );
+    }
+
+    #[test]
+    fn test_canonicalize_header_case_keeps_values() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("x-request-id", "abc").unwrap();
+        req.append_header("accept", "text/html").unwrap();
+        req.append_header("accept", "*/*").unwrap();
+
+        DevboxProxy::canonicalize_header_case(&mut req).unwrap();
+        assert_eq!(req.headers.len(), 3);
+        assert_eq!(req.headers.get("x-request-id").unwrap(), "abc");
+        let accept: Vec<_> = req.headers.get_all("accept").iter().collect();
+        assert_eq!(accept, vec!["text/html", "*/*"]);
+    }
+
+    #[test]
+    fn test_rewrite_path() {
+        let mut req = RequestHeader::build("GET", b"/a/../b?x=/../y", None).unwrap();
+        DevboxProxy::rewrite_path(&mut req, "/b").unwrap();
+        assert_eq!(req.uri, "/b?x=/../y");
+
+        // Absolute-form (HTTP/2) keeps scheme and authority
+        let mut req = RequestHeader::build(
+            "GET",
+            b"https://devbox-my-app-8080.devbox.sealos.io//index.html",
+            None,
+        )
+        .unwrap();
+        DevboxProxy::rewrite_path(&mut req, "/index.html").unwrap();
+        assert_eq!(
+            req.uri,
+            "https://devbox-my-app-8080.devbox.sealos.io/index.html"
+        );
+    }
+
+    #[test]
+    fn test_request_host_falls_back_to_authority() {
+        let mut req = RequestHeader::build(
+            "GET",
+            b"https://devbox-my-app-8080.devbox.sealos.io/index.html",
+            None,
+        )
+        .unwrap();
+        req.set_version(Version::HTTP_2);
+        assert_eq!(
+            DevboxProxy::request_host(&req),
+            "devbox-my-app-8080.devbox.sealos.io"
+        );
+
+        req.insert_header("Host", "devbox-other-8080.devbox.sealos.io")
+            .unwrap();
+        assert_eq!(
+            DevboxProxy::request_host(&req),
+            "devbox-other-8080.devbox.sealos.io"
+        );
+
+        let req = RequestHeader::build("GET", b"/", None).unwrap();
+        assert_eq!(DevboxProxy::request_host(&req), "");
+    }
+
+    #[test]
+    fn test_is_internal() {
+        let config = Config {
+            internal_cidrs: vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()],
+            ..Default::default()
+        };
+        let proxy = DevboxProxy::with_config(Arc::new(DevboxRegistry::new()), Arc::new(config));
+        for (client, internal) in [
+            ("10.1.2.3:5000", true),
+            ("[fd00::1]:5000", true),
+            ("[::ffff:10.0.0.1]:5000", true),
+            ("192.168.1.1:5000", false),
+            ("[2001:db8::1]:5000", false),
+        ] {
+            assert_eq!(
+                proxy.is_internal(client.parse().unwrap()),
+                internal,
+                "{client}"
+            );
+        }
+
+        // Nobody is internal by default
+        let proxy = DevboxProxy::new(Arc::new(DevboxRegistry::new()));
+        assert!(!proxy.is_internal("10.1.2.3:5000".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_forwarded_for() {
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        assert_eq!(DevboxProxy::forwarded_for(None, ip), "203.0.113.7");
+        assert_eq!(DevboxProxy::forwarded_for(Some(" "), ip), "203.0.113.7");
+        assert_eq!(
+            DevboxProxy::forwarded_for(Some("198.51.100.1"), ip),
+            "198.51.100.1, 203.0.113.7"
+        );
+    }
+
+    #[test]
+    fn test_slow_request_threshold() {
+        let registry = Arc::new(DevboxRegistry::new());
+        let config = Config {
+            slow_request_threshold: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        let proxy = DevboxProxy::with_config(registry, Arc::new(config));
+
+        assert!(!proxy.is_slow_request(Duration::from_millis(100)));
+        assert!(!proxy.is_slow_request(Duration::from_millis(500)));
+        assert!(proxy.is_slow_request(Duration::from_millis(501)));
+    }
+
+    #[test]
+    fn test_log_sample_per_namespace() {
+        let config = Arc::new(Config {
+            access_log_sample: 0.5,
+            ..Default::default()
+        });
+        let limiter = Arc::new(NamespaceLimiter::new(NamespaceLimit::default()));
+        limiter.set_overrides(HashMap::from([
+            ("ns-noisy".to_string(), "log-sample=0.01".parse().unwrap()),
+            ("ns-quiet".to_string(), "log-sample=1".parse().unwrap()),
+            // Only a limit: sampled at ACCESS_LOG_SAMPLE
+            ("ns-limited".to_string(), "rate=5".parse().unwrap()),
+        ]));
+        let proxy = DevboxProxy::with_config(Arc::new(DevboxRegistry::new()), config)
+            .with_namespace_limiter(limiter);
+
+        let sampled = |namespace: Option<&str>| {
+            (0..10_000)
+                .filter(|&i| {
+                    let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+                    req.insert_header("x-request-id", format!("{i:08x}"))
+                        .unwrap();
+                    let mut ctx = proxy.new_ctx();
+                    ctx.log_sample_key = Some(access_log::sample_key(&req));
+                    ctx.route = namespace.map(|namespace| {
+                        let mut route =
+                            ctx_with_policy(8080, UpstreamProtocol::Http, DevboxPolicy::default());
+                        route.devbox.namespace = namespace.to_string();
+                        route
+                    });
+                    proxy.log_sampled(&ctx)
+                })
+                .count()
+        };
+        assert_eq!(sampled(Some("ns-quiet")), 10_000);
+        assert!(sampled(Some("ns-noisy")) < 300);
+        for namespace in [Some("ns-limited"), Some("ns-other"), None] {
+            let count = sampled(namespace);
+            assert!((4_000..=6_000).contains(&count), "{namespace:?}: {count}");
+        }
+
+        // Records of requests that never reached the request filter aren't
+        // sampled out
+        assert!(proxy.log_sampled(&proxy.new_ctx()));
+    }
+
+    #[test]
+    fn test_inflight_limiter_shared_across_listeners() {
+        let registry = Arc::new(DevboxRegistry::new());
+        let config = Arc::new(Config {
+            max_global_inflight: Some(1),
+            ..Default::default()
+        });
+        let limiter = Arc::new(InflightLimiter::new(config.max_global_inflight));
+
+        let listener = |name: &str| ListenerPolicy {
+            name: name.to_string(),
+            ..ListenerConfig::from_config(&config).policy
+        };
+        let public = DevboxProxy::with_listener(
+            Arc::clone(&registry),
+            Arc::clone(&config),
+            listener("public"),
+        )
+        .with_inflight_limiter(Arc::clone(&limiter));
+        let internal =
+            DevboxProxy::with_listener(registry, Arc::clone(&config), listener("internal"))
+                .with_inflight_limiter(limiter);
+
+        let guard = public.inflight.try_acquire().unwrap();
+        assert!(internal.inflight.try_acquire().is_none());
+        drop(guard);
+        assert!(internal.inflight.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_identity_headers() {
+        let config = Arc::new(Config {
+            identity_header_names: IdentityHeaders {
+                original_host: HeaderName::from_static("x-forwarded-devbox-host"),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let listener = |identity_headers| ListenerPolicy {
+            identity_headers,
+            ..ListenerConfig::from_config(&config).policy
         };
-
-        // Step 2: Look up pod IP
-        let Some(pod_ip) = self.registry.get_pod_ip(&info.namespace, &info.devbox_name) else {
-            return BackendResult::NotRunning;
+        let host = "devbox-outdoor-before-78648-8080.devbox.sealos.io";
+        let request = || {
+            let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+            req.insert_header("X-Devbox-Id", "spoofed").unwrap();
+            req.insert_header("X-Forwarded-Devbox-Host", "evil.example.com")
+                .unwrap();
+            req
         };
+        let ctx = ctx_with_policy(8080, UpstreamProtocol::Http, DevboxPolicy::default());
 
-        debug!(
-            unique_id = %unique_id,
-            namespace = %info.namespace,
-            devbox_name = %info.devbox_name,
-            pod_ip = %pod_ip,
-            port = port,
-            "Resolved backend"
+        let proxy = DevboxProxy::with_listener(
+            Arc::new(DevboxRegistry::new()),
+            Arc::clone(&config),
+            listener(true),
+        );
+        let mut req = request();
+        proxy
+            .set_identity_headers(&mut req, Some(&ctx), host)
+            .unwrap();
+        assert_eq!(
+            req.headers.get("x-devbox-id").unwrap(),
+            "outdoor-before-78648"
         );
+        assert_eq!(req.headers.get("x-devbox-namespace").unwrap(), "ns-admin");
+        assert_eq!(req.headers.get("x-forwarded-devbox-host").unwrap(), host);
 
-        BackendResult::Ok(pod_ip, port)
-    }
+        // Without a devbox route the client's copies are still removed
+        let mut req = request();
+        proxy.set_identity_headers(&mut req, None, host).unwrap();
+        assert!(req.headers.get("x-devbox-id").is_none());
+        assert!(req.headers.get("x-forwarded-devbox-host").is_none());
 
-    /// Send a 404 Not Found response
-    async fn send_not_found(session: &mut Session) -> Result<bool> {
-        let mut header = ResponseHeader::build(404, None)?;
-        header.insert_header("Content-Length", BODY_NOT_FOUND.len().to_string())?;
-        header.insert_header("Content-Type", "text/plain")?;
-        session
-            .write_response_header(Box::new(header), false)
-            .await?;
-        session
-            .write_response_body(Some(BODY_NOT_FOUND.into()), true)
-            .await?;
-        Ok(true)
+        // Listeners without identity headers leave requests alone
+        let proxy = DevboxProxy::with_listener(
+            Arc::new(DevboxRegistry::new()),
+            Arc::clone(&config),
+            listener(false),
+        );
+        let mut req = request();
+        proxy
+            .set_identity_headers(&mut req, Some(&ctx), host)
+            .unwrap();
+        assert_eq!(req.headers.get("x-devbox-id").unwrap(), "spoofed");
+        assert!(req.headers.get("x-devbox-namespace").is_none());
     }
 
-    /// Send a 503 Service Unavailable response (devbox not running)
-    async fn send_service_unavailable(session: &mut Session) -> Result<bool> {
-        let mut header = ResponseHeader::build(503, None)?;
-        header.insert_header("Content-Length", BODY_NOT_RUNNING.len().to_string())?;
-        header.insert_header("Content-Type", "text/plain")?;
-        session
-            .write_response_header(Box::new(header), false)
-            .await?;
-        session
-            .write_response_body(Some(BODY_NOT_RUNNING.into()), true)
-            .await?;
-        Ok(true)
+    #[test]
+    fn test_error_response_keep_alive() {
+        let request = |version, connection: Option<&str>| {
+            let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+            req.set_version(version);
+            if let Some(connection) = connection {
+                req.insert_header(CONNECTION, connection).unwrap();
+            }
+            req
+        };
+        for (version, connection, body_done, expected) in [
+            (Version::HTTP_11, None, true, (true, None)),
+            (Version::HTTP_11, Some("keep-alive"), true, (true, None)),
+            (
+                Version::HTTP_11,
+                Some("close"),
+                true,
+                (false, Some("close")),
+            ),
+            (
+                Version::HTTP_11,
+                Some("TE, Close"),
+                true,
+                (false, Some("close")),
+            ),
+            // An unread request body would be parsed as the next request
+            (Version::HTTP_11, None, false, (false, Some("close"))),
+            (Version::HTTP_10, None, true, (false, Some("close"))),
+            (
+                Version::HTTP_10,
+                Some("Keep-Alive"),
+                true,
+                (true, Some("keep-alive")),
+            ),
+            (
+                Version::HTTP_10,
+                Some("keep-alive"),
+                false,
+                (false, Some("close")),
+            ),
+        ] {
+            let req = request(version, connection);
+            let keep_alive = DevboxProxy::keep_alive(&req, body_done);
+            assert_eq!(
+                (keep_alive, DevboxProxy::connection_header(&req, keep_alive)),
+                expected,
+                "{version:?} {connection:?} body_done={body_done}"
+            );
+        }
     }
-}
 
-#[async_trait]
-impl ProxyHttp for DevboxProxy {
-    type CTX = Option<ProxyCtx>;
+    #[test]
+    fn test_headers_on_error() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header(HOST, "devbox-gone-8080.devbox.local")
+            .unwrap();
+        req.insert_header(AUTHORIZATION, "Bearer secret").unwrap();
+        let registry = Arc::new(DevboxRegistry::new());
+        let mut ctx = DevboxProxy::new(Arc::clone(&registry)).new_ctx();
 
-    fn new_ctx(&self) -> Self::CTX {
-        None
+        let config = Arc::new(Config {
+            log_headers_on_error: true,
+            ..Default::default()
+        });
+        let proxy = DevboxProxy::with_config(Arc::clone(&registry), config);
+        // Requests routed to a devbox, whatever it answers
+        assert_eq!(proxy.headers_on_error(&req, &ctx), None);
+
+        ctx.route_failed = true;
+        let headers = proxy.headers_on_error(&req, &ctx).unwrap();
+        assert!(headers.contains("devbox-gone-8080"), "{headers}");
+        assert!(!headers.contains("secret"), "{headers}");
+
+        // Off by default
+        let proxy = DevboxProxy::new(registry);
+        assert_eq!(proxy.headers_on_error(&req, &ctx), None);
     }
 
-    async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
-        // Extract Host header
-        let host = session
-            .req_header()
-            .headers
-            .get("host")
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("");
+    #[test]
+    fn test_guard_protocol() {
+        let mut req = RequestHeader::build("TRACE", b"/", None).unwrap();
+        req.insert_header(HOST, "devbox-app-8080.devbox.local")
+            .unwrap();
 
-        // Parse protocol, uniqueID and port from host
-        let Some((protocol, unique_id, port)) = Self::parse_host(host) else {
-            warn!(host = %host, "Failed to parse host header");
-            return Self::send_not_found(session).await;
-        };
+        let proxy = DevboxProxy::new(Arc::new(DevboxRegistry::new()));
+        assert_eq!(proxy.guard_protocol(&req), None);
 
-        // Resolve backend from registry
-        let (backend_ip, backend_port) = match self.resolve_backend(&unique_id, port) {
-            BackendResult::Ok(ip, port) => (ip, port),
-            BackendResult::NotFound => {
-                warn!(
-                    host = %host,
-                    unique_id = %unique_id,
-                    "Devbox not found"
-                );
-                return Self::send_not_found(session).await;
+        let config = Arc::new(Config {
+            strict_protocol: true,
+            ..Default::default()
+        });
+        let proxy = DevboxProxy::with_config(Arc::new(DevboxRegistry::new()), config);
+        assert_eq!(proxy.guard_protocol(&req), Some(ProtocolAnomaly::Trace));
+        let before = metrics::PROTOCOL_ANOMALIES_TOTAL
+            .with_label_values(&["trace", "true"])
+            .get();
+        proxy.guard_protocol(&req);
+        assert_eq!(
+            metrics::PROTOCOL_ANOMALIES_TOTAL
+                .with_label_values(&["trace", "true"])
+                .get(),
+            before + 1
+        );
+
+        let req = RequestHeader::build("GET", b"/", None).unwrap();
+        assert_eq!(proxy.guard_protocol(&req), None);
+    }
+
+    #[test]
+    fn test_frame_for_http10() {
+        let request = |method: &str, connection: Option<&str>| {
+            let mut req = RequestHeader::build(method, b"/", None).unwrap();
+            req.set_version(Version::HTTP_10);
+            if let Some(connection) = connection {
+                req.insert_header(CONNECTION, connection).unwrap();
             }
-            BackendResult::NotRunning => {
-                warn!(
-                    host = %host,
-                    unique_id = %unique_id,
-                    "Devbox not running (no Pod IP)"
-                );
-                return Self::send_service_unavailable(session).await;
+            req
+        };
+        let response = |status: u16, headers: &[(&'static str, &str)]| {
+            let mut resp = ResponseHeader::build(status, None).unwrap();
+            for (name, value) in headers {
+                resp.insert_header(*name, *value).unwrap();
             }
+            resp
         };
 
-        info!(
-            host = %host,
-            protocol = ?protocol,
-            backend = %format!("{}:{}", backend_ip, backend_port),
-            "Routing request"
+        // A chunked body is sent as is, up to the connection's end
+        let req = request("GET", Some("keep-alive"));
+        let mut resp = response(
+            200,
+            &[
+                ("transfer-encoding", "chunked"),
+                ("connection", "keep-alive"),
+            ],
         );
+        assert!(!DevboxProxy::frame_for_http10(&req, &mut resp).unwrap());
+        assert!(resp.headers.get(TRANSFER_ENCODING).is_none());
+        assert_eq!(resp.headers.get(CONNECTION).unwrap(), "close");
 
-        *ctx = Some(ProxyCtx {
-            backend_ip,
-            backend_port,
-            protocol,
-        });
+        // Framed responses keep connections the client asked to keep
+        let mut resp = response(200, &[("content-length", "5")]);
+        assert!(DevboxProxy::frame_for_http10(&req, &mut resp).unwrap());
+        assert_eq!(resp.headers.get(CONNECTION).unwrap(), "keep-alive");
+        assert_eq!(resp.headers.get(CONTENT_LENGTH).unwrap(), "5");
 
-        Ok(false) // Continue to upstream
-    }
+        let req = request("GET", None);
+        let mut resp = response(200, &[("content-length", "5")]);
+        assert!(!DevboxProxy::frame_for_http10(&req, &mut resp).unwrap());
+        assert_eq!(resp.headers.get(CONNECTION).unwrap(), "close");
 
-    async fn upstream_peer(
-        &self,
-        _session: &mut Session,
-        ctx: &mut Self::CTX,
-    ) -> Result<Box<HttpPeer>> {
-        let ctx = ctx
-            .as_ref()
-            .expect("Context should be set in request_filter");
+        // Responses without a body need no length
+        for (method, status) in [("HEAD", 200), ("GET", 204), ("GET", 304)] {
+            let req = request(method, Some("keep-alive"));
+            let mut resp = response(status, &[("transfer-encoding", "chunked")]);
+            assert!(
+                DevboxProxy::frame_for_http10(&req, &mut resp).unwrap(),
+                "{method} {status}"
+            );
+            assert!(resp.headers.get(TRANSFER_ENCODING).is_none());
+        }
+    }
 
-        let mut peer = HttpPeer::new(
-            (ctx.backend_ip.as_str(), ctx.backend_port),
-            false, // No TLS (cleartext)
-            String::new(),
+    #[test]
+    fn test_error_header_framing() {
+        let (header, body) = DevboxProxy::error_response(&NOT_FOUND, ErrorFormat::Plain).unwrap();
+        assert_eq!(body, "devbox not found");
+        assert_eq!(
+            header.headers.get(CONTENT_LENGTH).unwrap(),
+            body.len().to_string().as_str()
         );
+        assert_eq!(header.headers.get("content-type").unwrap(), "text/plain");
+        assert!(header.headers.get(CONNECTION).is_none());
+    }
 
-        // Configure HTTP/2 for gRPC (h2c - HTTP/2 over cleartext)
-        if ctx.protocol == UpstreamProtocol::Grpc {
-            peer.options.alpn = ALPN::H2;
-        }
-
-        Ok(Box::new(peer))
+    #[test]
+    fn test_overloaded_response_has_retry_after() {
+        let (mut header, _) = DevboxProxy::error_response(&OVERLOADED, ErrorFormat::Plain).unwrap();
+        header
+            .insert_header("Retry-After", OVERLOAD_RETRY_AFTER_SECS)
+            .unwrap();
+        assert_eq!(header.status.as_u16(), 503);
+        assert_eq!(header.headers.get("retry-after").unwrap(), "1");
     }
 
-    async fn upstream_request_filter(
-        &self,
-        _session: &mut Session,
-        _upstream_request: &mut RequestHeader,
-        _ctx: &mut Self::CTX,
-    ) -> Result<()> {
-        // Add standard proxy headers
-        // upstream_request
-        //     .insert_header("X-Forwarded-Proto", "https")
-        //     .unwrap();
+    #[test]
+    fn test_registry_syncing() {
+        let registry = Arc::new(DevboxRegistry::new());
+        let proxy = DevboxProxy::new(Arc::clone(&registry));
+        let disabled = DevboxProxy::with_config(
+            Arc::clone(&registry),
+            Arc::new(Config {
+                registry_sync_grace: None,
+                ..Default::default()
+            }),
+        );
+        assert!(!proxy.registry_syncing());
 
-        Ok(())
-    }
-}
+        registry.record_watch_init(DEFAULT_CLUSTER, WatchKind::Devboxes);
+        assert!(proxy.registry_syncing());
+        assert!(!disabled.registry_syncing());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // Misses get a 503 to retry rather than a 404
+        let (header, _) =
+            DevboxProxy::error_response(&REGISTRY_SYNCING, ErrorFormat::Plain).unwrap();
+        assert_eq!(header.status.as_u16(), 503);
 
-    // HTTP protocol tests (devbox- prefix)
+        // Within the grace period after the list
+        registry.record_watch_synced(DEFAULT_CLUSTER, WatchKind::Devboxes);
+        assert!(proxy.registry_syncing());
 
-    #[test]
-    fn test_parse_host_http_standard_format() {
-        let result = DevboxProxy::parse_host("devbox-outdoor-before-78648-8080.devbox.sealos.io");
-        assert_eq!(
-            result,
-            Some((
-                UpstreamProtocol::Http,
-                "outdoor-before-78648".to_string(),
-                8080
-            ))
+        let short = DevboxProxy::with_config(
+            Arc::clone(&registry),
+            Arc::new(Config {
+                registry_sync_grace: Some(Duration::from_millis(50)),
+                ..Default::default()
+            }),
         );
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!short.registry_syncing());
     }
 
     #[test]
-    fn test_parse_host_http_simple_id() {
-        let result = DevboxProxy::parse_host("devbox-my-app-8080.devbox.sealos.io");
+    fn test_error_format() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        let proxy = DevboxProxy::new(Arc::new(DevboxRegistry::new()));
+        assert_eq!(proxy.error_format(&req), ErrorFormat::Plain);
+
+        let config = Arc::new(Config {
+            error_format: ErrorFormat::Json,
+            ..Default::default()
+        });
+        let proxy = DevboxProxy::with_config(Arc::new(DevboxRegistry::new()), config);
+        assert_eq!(proxy.error_format(&req), ErrorFormat::Json);
+        req.insert_header("accept", "*/*").unwrap();
+        assert_eq!(proxy.error_format(&req), ErrorFormat::Json);
+        req.insert_header("accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+            .unwrap();
+        assert_eq!(proxy.error_format(&req), ErrorFormat::Html);
+        req.insert_header("accept", "text/plain").unwrap();
+        assert_eq!(proxy.error_format(&req), ErrorFormat::Plain);
+
+        // API clients get the error code and the devbox
+        let error = NOT_FOUND.with_unique_id("outdoor-before-78648");
+        let (header, body) = DevboxProxy::error_response(&error, ErrorFormat::Json).unwrap();
+        assert_eq!(header.status.as_u16(), 404);
         assert_eq!(
-            result,
-            Some((UpstreamProtocol::Http, "my-app".to_string(), 8080))
+            header.headers.get("content-type").unwrap(),
+            "application/json"
         );
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "devbox_not_found");
+        assert_eq!(body["unique_id"], "outdoor-before-78648");
     }
 
     #[test]
-    fn test_parse_host_http_single_word() {
-        let result = DevboxProxy::parse_host("devbox-myapp-443.devbox.sealos.io");
+    fn test_failure_status() {
+        let statuses = FailureStatuses::default();
+        let read_timeout = Error::new_up(ErrorType::ReadTimedout);
         assert_eq!(
-            result,
-            Some((UpstreamProtocol::Http, "myapp".to_string(), 443))
+            DevboxProxy::failure_status(&read_timeout, false, &statuses),
+            502
+        );
+        assert_eq!(
+            DevboxProxy::failure_status(&read_timeout, true, &statuses),
+            504
+        );
+        let expired = Error::explain(HTTPStatus(504), "request deadline exceeded");
+        assert_eq!(DevboxProxy::failure_status(&expired, true, &statuses), 504);
+        // Client-side failures are not the deadline's doing
+        let gone = Error::new_down(ErrorType::ConnectionClosed);
+        assert_eq!(DevboxProxy::failure_status(&gone, true, &statuses), 0);
+        let too_large = Error::explain(HTTPStatus(413), "request body too large");
+        assert_eq!(
+            DevboxProxy::failure_status(&too_large, true, &statuses),
+            413
         );
+        let internal = Error::new_in(ErrorType::InternalError);
+        assert_eq!(DevboxProxy::failure_status(&internal, true, &statuses), 500);
     }
 
     #[test]
-    fn test_parse_host_http_with_numbers() {
-        let result = DevboxProxy::parse_host("devbox-app123-test456-3000.devbox.sealos.io");
+    fn test_upstream_failure_statuses() {
+        let failures = [
+            ErrorType::ConnectNoRoute,
+            ErrorType::ConnectRefused,
+            ErrorType::ConnectTimedout,
+            ErrorType::InvalidHTTPHeader,
+            ErrorType::ConnectError,
+        ];
+        let status = |etype: &ErrorType, statuses: &FailureStatuses| {
+            DevboxProxy::failure_status(&Error::new_up(etype.clone()), false, statuses)
+        };
+
+        let defaults = FailureStatuses::default();
+        let statuses: Vec<_> = failures.iter().map(|e| status(e, &defaults)).collect();
+        assert_eq!(statuses, [503, 502, 504, 502, 502]);
+
+        let overridden = FailureStatuses {
+            unavailable: 502,
+            refused: 503,
+            timeout: 503,
+            invalid_response: 500,
+        };
+        let statuses: Vec<_> = failures.iter().map(|e| status(e, &overridden)).collect();
+        assert_eq!(statuses, [502, 503, 503, 500, 502]);
+
+        // Past the deadline, every upstream failure is a timeout
+        let refused = Error::new_up(ErrorType::ConnectRefused);
         assert_eq!(
-            result,
-            Some((UpstreamProtocol::Http, "app123-test456".to_string(), 3000))
+            DevboxProxy::failure_status(&refused, true, &overridden),
+            504
         );
+
+        // Garbage from the devbox gets its own body
+        let garbage = Error::new_up(ErrorType::InvalidHTTPHeader);
+        let error = UpstreamFailure::classify(&garbage).unwrap().error(502);
+        let (header, body) = DevboxProxy::error_response(&error, ErrorFormat::Json).unwrap();
+        assert_eq!(header.status.as_u16(), 502);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "invalid_upstream_response");
     }
 
     #[test]
-    fn test_parse_host_http_with_port_suffix() {
-        let result =
-            DevboxProxy::parse_host("devbox-outdoor-before-78648-8080.devbox.sealos.io:443");
-        assert_eq!(
-            result,
-            Some((
-                UpstreamProtocol::Http,
-                "outdoor-before-78648".to_string(),
-                8080
-            ))
-        );
+    fn test_deadline_exceeded_response() {
+        let (header, body) =
+            DevboxProxy::deadline_exceeded_response(&DEADLINE_EXCEEDED, ErrorFormat::Json).unwrap();
+        assert_eq!(header.status.as_u16(), 504);
+        assert_eq!(header.headers.get("x-timeout-source").unwrap(), "gateway");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "deadline_exceeded");
     }
 
-    // gRPC protocol tests (devboxgrpc- prefix)
+    #[test]
+    fn test_closed_idle() {
+        let proxy = DevboxProxy::new(Arc::new(DevboxRegistry::new()));
+        let mut ctx = proxy.new_ctx();
+        let timed_out = Error::new_up(ErrorType::ReadTimedout);
+        let later = ctx.last_activity + Duration::from_secs(60);
+        // Only upgraded connections with an idle timeout go idle
+        assert!(!DevboxProxy::closed_idle(&ctx, Some(&timed_out), later));
+
+        ctx.ws_idle_timeout = Some(Duration::from_secs(60));
+        assert!(DevboxProxy::closed_idle(&ctx, Some(&timed_out), later));
+        assert!(!DevboxProxy::closed_idle(&ctx, None, later));
+        let reset = Error::new_up(ErrorType::ConnectionClosed);
+        assert!(!DevboxProxy::closed_idle(&ctx, Some(&reset), later));
+
+        // A read timing out soon after data crossed wasn't the idle timeout
+        let soon = ctx.last_activity + Duration::from_secs(5);
+        assert!(!DevboxProxy::closed_idle(&ctx, Some(&timed_out), soon));
+    }
 
     #[test]
-    fn test_parse_host_grpc_standard_format() {
-        let result =
-            DevboxProxy::parse_host("devboxgrpc-outdoor-before-78648-50051.devbox.sealos.io");
-        assert_eq!(
-            result,
-            Some((
-                UpstreamProtocol::Grpc,
-                "outdoor-before-78648".to_string(),
-                50051
-            ))
+    fn test_request_deadline() {
+        let proxy = DevboxProxy::new(Arc::new(DevboxRegistry::new()));
+        let mut ctx = proxy.new_ctx();
+        assert_eq!(DevboxProxy::remaining_budget(&ctx).unwrap(), None);
+
+        ctx.deadline = Some(Instant::now() + Duration::from_secs(60));
+        let remaining = DevboxProxy::remaining_budget(&ctx).unwrap().unwrap();
+        assert!(remaining <= Duration::from_secs(60));
+        assert!(remaining > Duration::from_secs(50));
+
+        // The budget bounds the upstream timeouts
+        let mut peer = DevboxProxy::build_peer(
+            &ctx_with_policy(8080, UpstreamProtocol::Http, DevboxPolicy::default()),
+            None,
         );
+        peer.options.read_timeout = Some(Duration::from_secs(1));
+        DevboxProxy::apply_deadline(&mut peer, remaining);
+        assert_eq!(peer.options.total_connection_timeout, Some(remaining));
+        assert_eq!(peer.options.write_timeout, Some(remaining));
+        assert_eq!(peer.options.read_timeout, Some(Duration::from_secs(1)));
+
+        ctx.deadline = Some(Instant::now());
+        let e = DevboxProxy::remaining_budget(&ctx).unwrap_err();
+        let statuses = FailureStatuses::default();
+        assert_eq!(DevboxProxy::failure_status(&e, true, &statuses), 504);
     }
 
     #[test]
-    fn test_parse_host_grpc_simple_id() {
-        let result = DevboxProxy::parse_host("devboxgrpc-my-app-50051.devbox.sealos.io");
+    fn test_request_timeout_deadline() {
+        let config = Config {
+            request_timeout: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let proxy = DevboxProxy::with_config(Arc::new(DevboxRegistry::new()), Arc::new(config));
+        let start = Instant::now();
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
         assert_eq!(
-            result,
-            Some((UpstreamProtocol::Grpc, "my-app".to_string(), 50051))
+            proxy.request_deadline(&req, false, start),
+            Some(start + Duration::from_secs(10))
         );
-    }
+        // Upgraded connections outlive any request timeout
+        assert_eq!(proxy.request_deadline(&req, true, start), None);
 
-    #[test]
-    fn test_parse_host_grpc_with_port_suffix() {
-        let result =
-            DevboxProxy::parse_host("devboxgrpc-outdoor-before-78648-50051.devbox.sealos.io:443");
+        // Clients can only shorten it
+        req.insert_header(deadline::X_REQUEST_TIMEOUT_MS, "2000")
+            .unwrap();
         assert_eq!(
-            result,
-            Some((
-                UpstreamProtocol::Grpc,
-                "outdoor-before-78648".to_string(),
-                50051
-            ))
+            proxy.request_deadline(&req, false, start),
+            Some(start + Duration::from_secs(2))
+        );
+        req.insert_header(deadline::X_REQUEST_TIMEOUT_MS, "60000")
+            .unwrap();
+        assert_eq!(
+            proxy.request_deadline(&req, false, start),
+            Some(start + Duration::from_secs(10))
+        );
+        assert_eq!(
+            proxy.request_deadline(&req, true, start),
+            Some(start + Duration::from_secs(60))
         );
     }
 
-    // Invalid format tests
-
     #[test]
-    fn test_parse_host_invalid_no_port() {
-        assert!(DevboxProxy::parse_host("devbox-outdoor-before.devbox.sealos.io").is_none());
-        assert!(DevboxProxy::parse_host("devboxgrpc-outdoor-before.devbox.sealos.io").is_none());
+    fn test_starting_page() {
+        let locales = Locales::default();
+        let (header, body) =
+            DevboxProxy::starting_page(Duration::from_secs(7), &locales, "en").unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(r#"<meta http-equiv="refresh" content="7">"#));
+        assert_eq!(header.status.as_u16(), 503);
+        assert_eq!(header.headers.get("retry-after").unwrap(), "7");
+        assert_eq!(
+            header.headers.get("content-type").unwrap(),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            header.headers.get("content-length").unwrap(),
+            &body.len().to_string()
+        );
+
+        // Sub-second intervals still wait a second
+        let (_, body) =
+            DevboxProxy::starting_page(Duration::from_millis(200), &locales, "en").unwrap();
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains(r#"content="1""#));
     }
 
     #[test]
-    fn test_parse_host_invalid_format() {
-        // No prefix
-        assert!(DevboxProxy::parse_host("invalid.example.com").is_none());
-        assert!(DevboxProxy::parse_host("").is_none());
-        // Missing prefix
-        assert!(DevboxProxy::parse_host("outdoor-before-78648-8080.devbox.sealos.io").is_none());
-        // Invalid uniqueID format (starts/ends with hyphen)
-        assert!(DevboxProxy::parse_host("devbox--invalid-8080.devbox.io").is_none());
-        assert!(DevboxProxy::parse_host("devbox-invalid--8080.devbox.io").is_none());
-        assert!(DevboxProxy::parse_host("devboxgrpc--invalid-50051.devbox.io").is_none());
+    fn test_localized_pages() {
+        let mut locales = Locales::default();
+        locales
+            .add(
+                "zh-CN",
+                "devbox_starting.title: 正在启动\n\
+                 devbox_starting: \"devbox 正在启动，{secs} 秒后刷新。\"\n\
+                 devbox_suggestions: \"<你是否要找>\"\n",
+            )
+            .unwrap();
+
+        let (_, body) =
+            DevboxProxy::starting_page(Duration::from_secs(5), &locales, "zh-CN").unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(r#"<html lang="zh-CN">"#));
+        assert!(body.contains("<title>正在启动</title>"));
+        assert!(body.contains("<p>devbox 正在启动，5 秒后刷新。</p>"));
+
+        // Translations are escaped, and missing ones stay English
+        let (_, body) = DevboxProxy::not_found_page(
+            &["devbox-a-8080.example.com".to_string()],
+            &locales,
+            "zh-CN",
+        )
+        .unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("<title>Devbox not found</title>"));
+        assert!(body.contains("<p>&lt;你是否要找&gt;</p>"));
     }
 
     #[test]
-    fn test_resolve_backend_with_pod_ip() {
+    fn test_suggest_hosts() {
         let registry = Arc::new(DevboxRegistry::new());
-        registry.register_devbox(
-            "outdoor-before-78648".to_string(),
-            "ns-admin".to_string(),
-            "devbox1".to_string(),
+        for (id, namespace) in [
+            ("my-app-7x9k2", "ns-1"),
+            ("my-app-7x9k3", "ns-2"),
+            ("my-app-7x9", "ns-1"),
+            ("other-app", "ns-1"),
+        ] {
+            registry.register_devbox(id.to_string(), namespace.to_string(), id.to_string());
+        }
+        let config = Config {
+            signing_key: Some("test-key".to_string()),
+            suggest_on_404: true,
+            ..Default::default()
+        };
+        let proxy = DevboxProxy::with_config(Arc::clone(&registry), Arc::new(config.clone()));
+        let signer = PreviewSigner::new("test-key");
+        let host = "devboxgrpc-my-app-7x9k-8080.devbox.sealos.io:443";
+        let request = |token: &str| {
+            let path = format!("/?{}={token}", preview::TOKEN_QUERY_PARAM);
+            RequestHeader::build("GET", path.as_bytes(), None).unwrap()
+        };
+        let expires_at = preview::unix_now() + 3600;
+
+        // Only the token owner's namespace is suggested
+        let req = request(&signer.mint("my-app-7x9k2", 8080, expires_at));
+        assert_eq!(
+            proxy.suggest_hosts(&req, host),
+            vec![
+                "devboxgrpc-my-app-7x9-8080.devbox.sealos.io:443",
+                "devboxgrpc-my-app-7x9k2-8080.devbox.sealos.io:443",
+            ]
+        );
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header(
+            "Cookie",
+            format!(
+                "{}={}",
+                preview::TOKEN_COOKIE,
+                signer.mint("my-app-7x9k3", 8080, expires_at)
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            proxy.suggest_hosts(&req, host),
+            vec!["devboxgrpc-my-app-7x9k3-8080.devbox.sealos.io:443"]
         );
-        registry.update_pod_ip("ns-admin", "devbox1", "10.107.173.213".to_string());
 
-        let proxy = DevboxProxy::new(registry);
+        // No token, or one for another port, a devbox that isn't similar or
+        // one that expired
+        for req in [
+            RequestHeader::build("GET", b"/", None).unwrap(),
+            request(&signer.mint("my-app-7x9k2", 3000, expires_at)),
+            request(&signer.mint("other-app", 8080, expires_at)),
+            request(&signer.mint("my-app-7x9k2", 8080, preview::unix_now() - 1)),
+            request("garbage"),
+        ] {
+            assert!(proxy.suggest_hosts(&req, host).is_empty());
+        }
 
-        let result = proxy.resolve_backend("outdoor-before-78648", 8080);
-        assert!(matches!(
-            result,
-            BackendResult::Ok(ip, 8080) if ip == "10.107.173.213"
-        ));
+        // The uniqueID's place in custom host patterns is unknown
+        let custom = DevboxProxy::with_config(
+            registry,
+            Arc::new(Config {
+                host_pattern: Some(Regex::new(DEFAULT_HOST_PATTERN).unwrap()),
+                ..config
+            }),
+        );
+        let req = request(&signer.mint("my-app-7x9k2", 8080, expires_at));
+        assert!(custom.suggest_hosts(&req, host).is_empty());
     }
 
     #[test]
-    fn test_resolve_backend_no_pod_ip() {
-        let registry = Arc::new(DevboxRegistry::new());
-        registry.register_devbox(
-            "outdoor-before-78648".to_string(),
-            "ns-admin".to_string(),
-            "devbox1".to_string(),
+    fn test_not_found_page() {
+        let (header, body) = DevboxProxy::not_found_page(
+            &[
+                "devbox-my-app-8080.devbox.sealos.io".to_string(),
+                "devbox-my-app-8080.<evil>".to_string(),
+            ],
+            &Locales::default(),
+            "en",
+        )
+        .unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert_eq!(header.status.as_u16(), 404);
+        assert!(body.contains(
+            r#"<a href="//devbox-my-app-8080.devbox.sealos.io/">devbox-my-app-8080.devbox.sealos.io</a>"#
+        ));
+        assert!(body.contains("devbox-my-app-8080.&lt;evil&gt;"));
+        assert!(!body.contains("<evil>"));
+        assert_eq!(
+            header.headers.get("content-length").unwrap(),
+            &body.len().to_string()
         );
-        // Pod IP not set
+    }
 
-        let proxy = DevboxProxy::new(registry);
+    #[test]
+    fn test_accepts_html() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        assert!(!DevboxProxy::accepts_html(&req));
+        req.insert_header("accept", "application/json").unwrap();
+        assert!(!DevboxProxy::accepts_html(&req));
+        req.insert_header("accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+            .unwrap();
+        assert!(DevboxProxy::accepts_html(&req));
+    }
 
-        let result = proxy.resolve_backend("outdoor-before-78648", 8080);
-        assert!(matches!(result, BackendResult::NotRunning));
+    #[test]
+    fn test_slow_request_disabled() {
+        let proxy = DevboxProxy::new(Arc::new(DevboxRegistry::new()));
+        assert!(!proxy.is_slow_request(Duration::from_secs(3600)));
     }
 
     #[test]
-    fn test_resolve_backend_not_found() {
-        let registry = Arc::new(DevboxRegistry::new());
-        let proxy = DevboxProxy::new(registry);
+    fn test_cache_lookup() {
+        let config = Arc::new(Config {
+            cache_max_bytes: Some(1024 * 1024),
+            ..Default::default()
+        });
+        let proxy = DevboxProxy::with_config(Arc::new(DevboxRegistry::new()), config);
+        let cache = proxy.cache.clone().unwrap();
+        let mut ctx = proxy.new_ctx();
+        ctx.route = Some(ctx_with_policy(
+            3000,
+            UpstreamProtocol::Http,
+            DevboxPolicy::default(),
+        ));
+        let req = RequestHeader::build("GET", b"/_next/static/app.js", None).unwrap();
 
-        let result = proxy.resolve_backend("unknown-id-123", 8080);
-        assert!(matches!(result, BackendResult::NotFound));
+        // A miss keeps the key to store the backend's response under
+        assert!(proxy.cache_lookup(&req, &mut ctx).is_none());
+        let key = ctx.cache_key.take().unwrap();
+        assert_eq!(key, CacheKey::new("outdoor-before-78648", 3000, &req.uri));
+
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Cache-Control", "public, max-age=60")
+            .unwrap();
+        resp.insert_header("Content-Type", "application/javascript")
+            .unwrap();
+        let mut fill = cache
+            .start_fill(key, 1, &req.headers, resp.status, &resp.headers)
+            .unwrap();
+        assert!(fill.push(b"app"));
+        cache.finish_fill(fill, &ctx.route.as_ref().unwrap().devbox);
+
+        let hit = proxy.cache_lookup(&req, &mut ctx).unwrap();
+        assert!(ctx.cache_key.is_none());
+        let header = DevboxProxy::cached_response_header(&hit).unwrap();
+        assert_eq!(header.status, 200);
+        assert_eq!(header.headers["content-type"], "application/javascript");
+        assert_eq!(header.headers["content-length"], "3");
+        assert_eq!(header.headers["age"], "0");
+
+        // Other requests are neither looked up nor stored
+        let post = RequestHeader::build("POST", b"/_next/static/app.js", None).unwrap();
+        assert!(proxy.cache_lookup(&post, &mut ctx).is_none());
+        assert!(ctx.cache_key.is_none());
+
+        // Hard reloads go to the backend, refreshing the cache
+        let mut reload = RequestHeader::build("GET", b"/_next/static/app.js", None).unwrap();
+        reload.insert_header("Cache-Control", "no-cache").unwrap();
+        assert!(proxy.cache_lookup(&reload, &mut ctx).is_none());
+        assert!(ctx.cache_key.is_some());
+    }
+
+    #[test]
+    fn test_cache_disabled_by_default() {
+        let proxy = DevboxProxy::new(Arc::new(DevboxRegistry::new()));
+        let mut ctx = proxy.new_ctx();
+        ctx.route = Some(ctx_with_policy(
+            3000,
+            UpstreamProtocol::Http,
+            DevboxPolicy::default(),
+        ));
+        let req = RequestHeader::build("GET", b"/_next/static/app.js", None).unwrap();
+        assert!(proxy.cache_lookup(&req, &mut ctx).is_none());
+        assert!(ctx.cache_key.is_none());
     }
 }
@@ -0,0 +1,326 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use pingora_core::apps::ServerApp;
+use pingora_core::protocols::Stream;
+use pingora_core::server::ShutdownWatch;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::{debug, warn};
+
+/// PROXY protocol v2 signature
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Maximum length of a v1 header including the trailing CRLF
+const V1_MAX_LEN: usize = 107;
+
+/// Time allowed for the load balancer to send the header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Decoded PROXY protocol header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyHeader {
+    /// Proxied connection from the given client address
+    Proxied(SocketAddr),
+    /// Connection from the load balancer itself (v2 LOCAL, v1 UNKNOWN, or a
+    /// non-IP address family); the socket peer address applies
+    Local,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid PROXY header: {msg}"),
+    )
+}
+
+/// Read a v1 or v2 PROXY header from the start of a connection.
+///
+/// Consumes exactly the header, leaving the application data unread.
+pub async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<ProxyHeader> {
+    // Both versions are at least 12 bytes long (the shortest v1 header is
+    // "PROXY UNKNOWN\r\n")
+    let mut prefix = [0u8; 12];
+    stream.read_exact(&mut prefix).await?;
+
+    if &prefix == V2_SIGNATURE {
+        let mut fixed = [0u8; 4];
+        stream.read_exact(&mut fixed).await?;
+        let len = u16::from_be_bytes([fixed[2], fixed[3]]) as usize;
+        let mut body = vec![0u8; len];
+        stream.read_exact(&mut body).await?;
+        return parse_v2(fixed[0], fixed[1], &body);
+    }
+
+    if !prefix.starts_with(b"PROXY ") {
+        return Err(invalid("missing signature"));
+    }
+
+    let mut line = prefix.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("v1 header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    parse_v1(&line)
+}
+
+/// Parse a v1 header line such as `PROXY TCP4 1.2.3.4 10.0.0.1 56324 443\r\n`.
+pub fn parse_v1(line: &[u8]) -> io::Result<ProxyHeader> {
+    let line = std::str::from_utf8(line)
+        .ok()
+        .and_then(|l| l.strip_suffix("\r\n"))
+        .ok_or_else(|| invalid("v1 header is not a CRLF-terminated line"))?;
+    let mut fields = line.split(' ');
+
+    if fields.next() != Some("PROXY") {
+        return Err(invalid("missing signature"));
+    }
+    match fields.next() {
+        Some("UNKNOWN") => return Ok(ProxyHeader::Local),
+        Some("TCP4" | "TCP6") => {}
+        _ => return Err(invalid("unsupported v1 protocol")),
+    }
+
+    let fields: Vec<&str> = fields.collect();
+    let [src_ip, _dst_ip, src_port, _dst_port] = fields[..] else {
+        return Err(invalid("wrong number of v1 fields"));
+    };
+    let ip: IpAddr = src_ip
+        .parse()
+        .map_err(|_| invalid("bad v1 source address"))?;
+    let port: u16 = src_port
+        .parse()
+        .map_err(|_| invalid("bad v1 source port"))?;
+    Ok(ProxyHeader::Proxied(SocketAddr::new(ip, port)))
+}
+
+/// Parse a v2 header from its version/command byte, family byte and body.
+pub fn parse_v2(version_command: u8, family: u8, body: &[u8]) -> io::Result<ProxyHeader> {
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported v2 version"));
+    }
+    match version_command & 0x0f {
+        0 => return Ok(ProxyHeader::Local),
+        1 => {}
+        _ => return Err(invalid("unsupported v2 command")),
+    }
+
+    let source = match family >> 4 {
+        // AF_INET: src addr, dst addr, src port, dst port
+        1 => {
+            let addr: [u8; 12] = body
+                .get(..12)
+                .and_then(|b| b.try_into().ok())
+                .ok_or_else(|| invalid("short v2 IPv4 address block"))?;
+            let ip = Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]);
+            SocketAddr::new(ip.into(), u16::from_be_bytes([addr[8], addr[9]]))
+        }
+        // AF_INET6
+        2 => {
+            let addr: [u8; 36] = body
+                .get(..36)
+                .and_then(|b| b.try_into().ok())
+                .ok_or_else(|| invalid("short v2 IPv6 address block"))?;
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addr[..16]);
+            let ip = Ipv6Addr::from(octets);
+            SocketAddr::new(ip.into(), u16::from_be_bytes([addr[32], addr[33]]))
+        }
+        // AF_UNSPEC / AF_UNIX carry no client IP
+        _ => return Ok(ProxyHeader::Local),
+    };
+    Ok(ProxyHeader::Proxied(source))
+}
+
+/// Client addresses announced via PROXY protocol, keyed by the socket peer
+/// address (the load balancer side) of each live connection.
+#[derive(Debug, Default)]
+pub struct ProxiedClients {
+    by_peer: DashMap<SocketAddr, SocketAddr>,
+}
+
+impl ProxiedClients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Client address of the connection from `peer`, if it sent a PROXY header.
+    pub fn get(&self, peer: &SocketAddr) -> Option<SocketAddr> {
+        self.by_peer.get(peer).map(|r| *r.value())
+    }
+}
+
+/// Server app that reads a PROXY header before handing the connection to `A`.
+///
+/// Connections without a valid header are closed. The header is sent once per
+/// connection, so all requests on a connection are served from here instead
+/// of returning the stream to the listener for reuse.
+pub struct ProxyProtocolApp<A> {
+    inner: Arc<A>,
+    clients: Arc<ProxiedClients>,
+}
+
+impl<A> ProxyProtocolApp<A> {
+    pub fn new(inner: A, clients: Arc<ProxiedClients>) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            clients,
+        }
+    }
+}
+
+#[async_trait]
+impl<A> ServerApp for ProxyProtocolApp<A>
+where
+    A: ServerApp + Send + Sync + 'static,
+{
+    async fn process_new(
+        self: &Arc<Self>,
+        mut stream: Stream,
+        shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        let peer = stream
+            .get_socket_digest()
+            .and_then(|d| d.peer_addr().and_then(|a| a.as_inet().copied()));
+
+        let header = match tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream)).await {
+            Ok(Ok(header)) => header,
+            Ok(Err(e)) => {
+                warn!(peer = ?peer, error = %e, "Rejecting connection without valid PROXY header");
+                return None;
+            }
+            Err(_) => {
+                warn!(peer = ?peer, "Timed out waiting for PROXY header");
+                return None;
+            }
+        };
+
+        let registered = match (header, peer) {
+            (ProxyHeader::Proxied(client), Some(peer)) => {
+                debug!(peer = %peer, client = %client, "PROXY header received");
+                self.clients.by_peer.insert(peer, client);
+                Some(peer)
+            }
+            _ => None,
+        };
+
+        let mut next = Some(stream);
+        while let Some(stream) = next {
+            next = self.inner.process_new(stream, shutdown).await;
+        }
+
+        if let Some(peer) = registered {
+            self.clients.by_peer.remove(&peer);
+        }
+        None
+    }
+
+    async fn cleanup(&self) {
+        self.inner.cleanup().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2(command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        header.extend_from_slice(body);
+        header
+    }
+
+    #[tokio::test]
+    async fn test_read_v1_tcp4() {
+        let mut input: &[u8] = b"PROXY TCP4 203.0.113.7 10.0.0.1 56324 443\r\nGET / HTTP/1.1\r\n";
+        let header = read_header(&mut input).await.unwrap();
+        assert_eq!(
+            header,
+            ProxyHeader::Proxied("203.0.113.7:56324".parse().unwrap())
+        );
+        // The request is left for the HTTP server
+        assert_eq!(input, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_read_v1_tcp6_and_unknown() {
+        let mut input: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n";
+        assert_eq!(
+            read_header(&mut input).await.unwrap(),
+            ProxyHeader::Proxied("[2001:db8::1]:4000".parse().unwrap())
+        );
+
+        let mut input: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut input).await.unwrap(), ProxyHeader::Local);
+    }
+
+    #[tokio::test]
+    async fn test_read_v2_inet() {
+        let body = [203, 0, 113, 7, 10, 0, 0, 1, 0xdc, 0x04, 0x01, 0xbb];
+        let mut input = v2(1, 0x11, &body);
+        input.extend_from_slice(b"GET /");
+
+        let mut reader = input.as_slice();
+        let header = read_header(&mut reader).await.unwrap();
+        assert_eq!(
+            header,
+            ProxyHeader::Proxied("203.0.113.7:56324".parse().unwrap())
+        );
+        assert_eq!(reader, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn test_read_v2_inet6_with_tlvs() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        body.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        body.extend_from_slice(&4000u16.to_be_bytes());
+        body.extend_from_slice(&443u16.to_be_bytes());
+        // Trailing TLV (PP2_TYPE_AUTHORITY) is skipped
+        body.extend_from_slice(&[0x02, 0x00, 0x03, b'a', b'b', b'c']);
+
+        let input = v2(1, 0x21, &body);
+        let mut reader = input.as_slice();
+        assert_eq!(
+            read_header(&mut reader).await.unwrap(),
+            ProxyHeader::Proxied("[2001:db8::1]:4000".parse().unwrap())
+        );
+        assert!(reader.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_v2_local() {
+        let input = v2(0, 0x00, &[]);
+        let mut reader = input.as_slice();
+        assert_eq!(read_header(&mut reader).await.unwrap(), ProxyHeader::Local);
+    }
+
+    #[tokio::test]
+    async fn test_read_invalid() {
+        for input in [
+            &b"GET / HTTP/1.1\r\nHost: x\r\n\r\n"[..],
+            b"PROXY TCP4 not-an-ip 10.0.0.1 1 2\r\n",
+            b"PROXY TCP4 1.2.3.4 10.0.0.1 1\r\n",
+            b"PROXY SCTP 1.2.3.4 10.0.0.1 1 2\r\n",
+        ] {
+            let mut reader = input;
+            assert!(read_header(&mut reader).await.is_err(), "{input:?}");
+        }
+
+        // Unterminated v1 header
+        let long = format!("PROXY TCP4 {}", "1".repeat(200));
+        assert!(read_header(&mut long.as_bytes()).await.is_err());
+
+        // Truncated v2 address block
+        let input = v2(1, 0x11, &[1, 2, 3]);
+        assert!(read_header(&mut input.as_slice()).await.is_err());
+    }
+}
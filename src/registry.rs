@@ -1,42 +1,644 @@
-use dashmap::DashMap;
-use tracing::{debug, info};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex, RwLock,
+};
+use std::time::{Duration, Instant};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+
+use crate::bloom::{BloomFilter, MIN_CAPACITY};
+use crate::metrics;
+use crate::policy::DevboxPolicy;
+use crate::preview::unix_now;
+use crate::suggest;
+
+/// Cluster of the devboxes when `CLUSTERS` is not set
+pub const DEFAULT_CLUSTER: &str = "default";
+
+/// Devboxes registered at most when `MAX_REGISTRY_ENTRIES` is not set
+pub const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+/// Where a registry entry came from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntrySource {
+    /// A watcher, from the cluster
+    #[default]
+    Watch,
+    /// An import through the admin API (see [`crate::snapshot`])
+    Import,
+}
 
 /// Information about a registered devbox (from Devbox CRD)
 #[derive(Debug, Clone)]
 pub struct DevboxInfo {
+    /// Cluster the Devbox was seen in
+    pub cluster: Arc<str>,
+    pub namespace: String,
+    pub devbox_name: String,
+    /// Routing policy from Devbox annotations (shared to keep clones cheap)
+    pub policy: Arc<DevboxPolicy>,
+    /// Port portless hosts are routed to, declared in the Devbox spec
+    pub app_port: Option<u16>,
+    pub source: EntrySource,
+    /// When the devbox was last routed to (Unix seconds, 0 if never). Shared
+    /// by the clones of the entry, so touching a resolved clone updates the
+    /// registered entry
+    pub last_access: Arc<AtomicU64>,
+}
+
+impl DevboxInfo {
+    /// A devbox of the [`DEFAULT_CLUSTER`].
+    pub fn new(namespace: String, devbox_name: String) -> Self {
+        Self::in_cluster(Arc::from(DEFAULT_CLUSTER), namespace, devbox_name)
+    }
+
+    pub fn in_cluster(cluster: Arc<str>, namespace: String, devbox_name: String) -> Self {
+        Self {
+            cluster,
+            namespace,
+            devbox_name,
+            policy: Arc::default(),
+            app_port: None,
+            source: EntrySource::Watch,
+            last_access: Arc::default(),
+        }
+    }
+
+    /// Record that the devbox was routed to at `now` (Unix seconds).
+    ///
+    /// Written at most once a second, so devboxes busy on many threads don't
+    /// contend on the timestamp.
+    pub fn touch(&self, now: u64) {
+        if self.last_access.load(Ordering::Relaxed) != now {
+            self.last_access.store(now, Ordering::Relaxed);
+        }
+    }
+
+    /// When the devbox was last routed to (Unix seconds), if ever.
+    pub fn last_access(&self) -> Option<u64> {
+        Some(self.last_access.load(Ordering::Relaxed)).filter(|&t| t != 0)
+    }
+
+    /// Whether both refer to the same Devbox resource.
+    pub fn is_same_devbox(&self, other: &Self) -> bool {
+        self.cluster == other.cluster
+            && self.namespace == other.namespace
+            && self.devbox_name == other.devbox_name
+    }
+}
+
+/// Watch stream of a cluster
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchKind {
+    Devboxes,
+    Pods,
+}
+
+impl WatchKind {
+    /// Metric label of the watch
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Devboxes => "devboxes",
+            Self::Pods => "pods",
+        }
+    }
+}
+
+/// Where one watch stream got to: the `resourceVersion` it can be resumed
+/// from after a restart, without listing its scope again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub cluster: String,
+    pub kind: WatchKind,
+    /// Namespace of a namespaced watch, `None` for a cluster-wide one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    pub resource_version: String,
+}
+
+/// Key of a bookmark
+fn bookmark_key(cluster: &str, kind: WatchKind, namespace: Option<&str>) -> String {
+    format!("{cluster}/{}/{}", kind.as_str(), namespace.unwrap_or(""))
+}
+
+/// Health of one watch stream of a cluster.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct WatchStatus {
+    /// Whether the current initial list completed
+    pub synced: bool,
+    /// Whether the last watch event was an error. Entries are kept, but may
+    /// be stale until the watch recovers.
+    pub failing: bool,
+    /// Watch errors so far
+    pub errors: u64,
+    /// Times the watcher was restarted
+    pub restarts: u64,
+    /// Most recent watch error
+    pub last_error: Option<String>,
+    /// When the watch last completed an initial list or delivered an event
+    /// (Unix seconds)
+    pub last_event_at: Option<u64>,
+    /// When the most recent watch error happened (Unix seconds)
+    pub last_error_at: Option<u64>,
+    /// When the running initial list started
+    #[serde(skip)]
+    pub syncing_since: Option<Instant>,
+    /// When the most recent initial list completed
+    #[serde(skip)]
+    pub synced_at: Option<Instant>,
+}
+
+/// Whether the registry holds everything the watchers listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncState {
+    /// Every watch completed its initial list
+    Synced,
+    /// A watch is (re)listing, since the earliest of their starts; entries
+    /// that exist in the cluster may be missing meanwhile
+    Syncing { since: Instant },
+}
+
+/// Entries and watch health of one cluster.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ClusterStatus {
+    /// Registered devboxes
+    pub devboxes: usize,
+    /// Pod index entries
+    pub pod_ips: usize,
+    pub devbox_watch: WatchStatus,
+    pub pod_watch: WatchStatus,
+}
+
+/// Pod IP of a devbox together with its endpoint generation.
+///
+/// The generation changes whenever the IP changes, so connections pooled for
+/// an earlier endpoint are never reused for the current one (see
+/// `DevboxProxy::build_peer`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PodEndpoint {
+    pub ip: String,
+    pub generation: u64,
+}
+
+/// Identity of the Pod an endpoint was learned from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PodRef {
+    pub name: String,
+    pub uid: String,
+}
+
+/// Pod index entry: the routed endpoint plus bookkeeping for the orphan sweep.
+#[derive(Debug, Clone)]
+struct PodEntry {
+    endpoint: PodEndpoint,
+    /// Pod the IP belongs to, if known
+    pod: Option<PodRef>,
+    /// When the endpoint was last set or verified against the API server
+    verified_at: Instant,
+    source: EntrySource,
+}
+
+/// Snapshot of a Pod index entry, taken without holding any locks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PodIpRecord {
+    pub cluster: String,
     pub namespace: String,
     pub devbox_name: String,
+    pub generation: u64,
+    pub pod: Option<PodRef>,
+}
+
+/// A Pod index entry with its routed IP, for exports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PodIpEntry {
+    pub cluster: String,
+    pub namespace: String,
+    pub devbox_name: String,
+    pub ip: String,
+    pub source: EntrySource,
+}
+
+/// Point-in-time counts over both registry indices.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegistryStats {
+    /// Registered devboxes
+    pub total_devboxes: usize,
+    /// Registered devboxes whose Pod has an IP
+    pub devboxes_with_pod_ip: usize,
+    /// Namespaces with at least one registered devbox
+    pub distinct_namespaces: usize,
+    /// Pod index entries, including those without a registered devbox
+    pub total_pod_ips: usize,
+}
+
+/// Size of the devbox index against its cap, for the readiness payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RegistryUsage {
+    /// Registered devboxes
+    pub entries: usize,
+    /// Cap of `entries`, 0 if there is none
+    pub max_entries: usize,
+    /// Whether registrations are being refused
+    pub full: bool,
+    /// Rough estimate of the memory held by both indices
+    pub approx_memory_bytes: usize,
+}
+
+/// Why an address can't be a devbox's backend.
+///
+/// Such addresses show up in Pod termination races, and connecting to them
+/// would reach the gateway's own host rather than a devbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidBackendAddr {
+    NotAnIp,
+    Loopback,
+    Unspecified,
+    LinkLocal,
+    Multicast,
+}
+
+impl fmt::Display for InvalidBackendAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NotAnIp => "not an IP address",
+            Self::Loopback => "loopback address",
+            Self::Unspecified => "unspecified address",
+            Self::LinkLocal => "link-local address",
+            Self::Multicast => "multicast address",
+        })
+    }
+}
+
+impl std::error::Error for InvalidBackendAddr {}
+
+/// Check that `ip` can be a devbox's backend address.
+///
+/// Loopback addresses are only accepted with `allow_loopback`, for
+/// backends running next to the gateway.
+pub fn check_backend_ip(ip: &str, allow_loopback: bool) -> Result<(), InvalidBackendAddr> {
+    let ip = ip
+        .parse::<IpAddr>()
+        .map_err(|_| InvalidBackendAddr::NotAnIp)?
+        .to_canonical();
+    let link_local = match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_unicast_link_local(),
+    };
+    if ip.is_unspecified() {
+        Err(InvalidBackendAddr::Unspecified)
+    } else if ip.is_loopback() && !allow_loopback {
+        Err(InvalidBackendAddr::Loopback)
+    } else if link_local {
+        Err(InvalidBackendAddr::LinkLocal)
+    } else if ip.is_multicast() || ip == IpAddr::from([255, 255, 255, 255]) {
+        Err(InvalidBackendAddr::Multicast)
+    } else {
+        Ok(())
+    }
+}
+
+/// Notified of registry changes that invalidate state derived from a
+/// devbox, such as its cached responses.
+///
+/// Called synchronously by the watchers, so implementations must be cheap
+/// and must not call back into the registry.
+pub trait RegistryObserver: Send + Sync {
+    /// `unique_id` is no longer registered
+    fn devbox_unregistered(&self, unique_id: &str);
+
+    /// The Pod endpoint of a devbox changed or was removed
+    fn endpoint_changed(&self, cluster: &str, namespace: &str, devbox_name: &str);
 }
 
 /// Thread-safe registry for devbox routing information.
 ///
 /// Maintains two independent indices:
 /// - `uniqueID -> DevboxInfo` (managed by Devbox watcher)
-/// - `namespace/devbox_name -> pod_ip` (managed by Pod watcher)
+/// - `cluster/namespace/devbox_name -> pod_ip` (managed by Pod watcher)
 ///
 /// The two watchers are completely isolated and can operate independently.
+/// Each watched cluster has its own pair of watchers, and each entry records
+/// its cluster, so one cluster's relist never touches another's entries.
+///
+/// A Bloom filter over the registered uniqueIDs lets the proxy reject hosts
+/// of unknown devboxes without touching the devbox index.
 pub struct DevboxRegistry {
     /// Devbox index: uniqueID -> `DevboxInfo` (namespace, devbox_name)
     by_unique_id: DashMap<String, DevboxInfo>,
-    /// Pod index: `namespace/devbox_name` -> pod_ip
-    pod_ips: DashMap<String, String>,
+    /// Filter over the keys of `by_unique_id`. Writers update the index
+    /// before the filter, and rebuilds hold the write lock while reading the
+    /// index, so a registered uniqueID is never missing from the filter.
+    unique_id_filter: RwLock<BloomFilter>,
+    /// Pod index: `cluster/namespace/devbox_name` -> pod_ip
+    pod_ips: DashMap<String, PodEntry>,
+    /// Source of endpoint generations (monotonic across all devboxes)
+    next_generation: AtomicU64,
+    /// Watch health of each cluster: (Devbox watch, Pod watch)
+    watches: Mutex<BTreeMap<String, (WatchStatus, WatchStatus)>>,
+    /// Where each watch stream got to, kept in the registry snapshot
+    bookmarks: Mutex<BTreeMap<String, Bookmark>>,
+    /// Notified of unregistered devboxes and changed endpoints
+    observers: RwLock<Vec<Arc<dyn RegistryObserver>>>,
+    /// Whether Pod IPs may be loopback addresses (`ALLOW_LOOPBACK_BACKENDS`)
+    loopback_backends: bool,
+    /// Devboxes registered at most (`MAX_REGISTRY_ENTRIES`), 0 for no cap
+    max_entries: usize,
+    /// Entries of `by_unique_id`, counted apart from the map so the cap
+    /// doesn't depend on how concurrent inserts spread over its shards
+    entries: AtomicUsize,
+    /// Whether a registration was refused since the index last had room
+    full: AtomicBool,
+    /// Whether watchers leave imported entries alone (`IMPORTED_ENTRIES`)
+    respect_imported: bool,
+}
+
+/// Key of a Pod index entry. Namespaces and names can't contain `/`, so the
+/// cluster is everything before the last two.
+fn pod_key(cluster: &str, namespace: &str, devbox_name: &str) -> String {
+    format!("{cluster}/{namespace}/{devbox_name}")
+}
+
+/// Split a Pod index key into cluster, namespace and devbox name.
+fn split_pod_key(key: &str) -> Option<(&str, &str, &str)> {
+    let (rest, devbox_name) = key.rsplit_once('/')?;
+    let (cluster, namespace) = rest.rsplit_once('/')?;
+    Some((cluster, namespace, devbox_name))
 }
 
 impl DevboxRegistry {
     pub fn new() -> Self {
         Self {
             by_unique_id: DashMap::new(),
+            unique_id_filter: RwLock::new(BloomFilter::new(MIN_CAPACITY)),
             pod_ips: DashMap::new(),
+            next_generation: AtomicU64::new(1),
+            watches: Mutex::new(BTreeMap::new()),
+            bookmarks: Mutex::new(BTreeMap::new()),
+            observers: RwLock::new(Vec::new()),
+            loopback_backends: false,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            entries: AtomicUsize::new(0),
+            full: AtomicBool::new(false),
+            respect_imported: false,
+        }
+    }
+
+    /// Register at most `max` devboxes (any number if 0); further
+    /// registrations are refused until some are unregistered.
+    #[must_use]
+    pub const fn with_max_entries(mut self, max: usize) -> Self {
+        self.max_entries = max;
+        self
+    }
+
+    /// Accept loopback Pod IPs, for backends on the gateway's host.
+    #[must_use]
+    pub const fn with_loopback_backends(mut self, allow: bool) -> Self {
+        self.loopback_backends = allow;
+        self
+    }
+
+    /// Have watchers neither replace nor remove imported entries, rather
+    /// than treating them as their own.
+    #[must_use]
+    pub const fn with_respect_imported(mut self, respect: bool) -> Self {
+        self.respect_imported = respect;
+        self
+    }
+
+    /// Whether watchers must leave an entry from `source` alone.
+    fn keeps_from_watch(&self, source: EntrySource) -> bool {
+        self.respect_imported && source == EntrySource::Import
+    }
+
+    /// Check that `ip` can be a devbox's backend address.
+    pub fn check_backend_ip(&self, ip: &str) -> Result<(), InvalidBackendAddr> {
+        check_backend_ip(ip, self.loopback_backends)
+    }
+
+    /// Notify `observer` of later changes.
+    pub fn add_observer(&self, observer: Arc<dyn RegistryObserver>) {
+        self.observers.write().unwrap().push(observer);
+    }
+
+    fn notify_unregistered(&self, unique_id: &str) {
+        for observer in self.observers.read().unwrap().iter() {
+            observer.devbox_unregistered(unique_id);
+        }
+    }
+
+    fn notify_endpoint_changed(&self, cluster: &str, namespace: &str, devbox_name: &str) {
+        for observer in self.observers.read().unwrap().iter() {
+            observer.endpoint_changed(cluster, namespace, devbox_name);
+        }
+    }
+
+    // ========================================================================
+    // Watch health (used by the watchers and their supervisor)
+    // ========================================================================
+
+    /// Expect watchers for `cluster`, so it counts as unsynced until they
+    /// complete their initial lists.
+    pub fn add_cluster(&self, cluster: &str) {
+        self.watches
+            .lock()
+            .unwrap()
+            .entry(cluster.to_string())
+            .or_default();
+    }
+
+    fn update_watch(&self, cluster: &str, kind: WatchKind, update: impl FnOnce(&mut WatchStatus)) {
+        let mut watches = self.watches.lock().unwrap();
+        let (devboxes, pods) = watches.entry(cluster.to_string()).or_default();
+        update(match kind {
+            WatchKind::Devboxes => devboxes,
+            WatchKind::Pods => pods,
+        });
+    }
+
+    /// Record that a watch of `cluster` started an initial list.
+    pub fn record_watch_init(&self, cluster: &str, kind: WatchKind) {
+        self.update_watch(cluster, kind, |watch| {
+            // A list restarted before completing keeps its first start
+            watch.syncing_since.get_or_insert_with(Instant::now);
+            watch.synced = false;
+            watch.failing = false;
+        });
+    }
+
+    /// Record that a watch of `cluster` completed an initial list.
+    pub fn record_watch_synced(&self, cluster: &str, kind: WatchKind) {
+        self.update_watch(cluster, kind, |watch| {
+            watch.synced = true;
+            watch.failing = false;
+            watch.last_event_at = Some(unix_now());
+            watch.syncing_since = None;
+            watch.synced_at = Some(Instant::now());
+        });
+    }
+
+    /// Record that a watch of `cluster` delivered an event.
+    pub fn record_watch_event(&self, cluster: &str, kind: WatchKind) {
+        self.update_watch(cluster, kind, |watch| {
+            watch.failing = false;
+            watch.last_event_at = Some(unix_now());
+        });
+    }
+
+    /// Record a watch error of `cluster`; its entries are kept.
+    pub fn record_watch_error(&self, cluster: &str, kind: WatchKind, error: &str) {
+        metrics::WATCH_ERRORS_TOTAL
+            .with_label_values(&[cluster, kind.as_str()])
+            .inc();
+        self.update_watch(cluster, kind, |watch| {
+            watch.failing = true;
+            watch.errors += 1;
+            watch.last_error = Some(error.to_string());
+            watch.last_error_at = Some(unix_now());
+        });
+    }
+
+    /// Record that a watcher of `cluster` is being restarted.
+    pub fn record_watcher_restart(&self, cluster: &str, kind: WatchKind) {
+        metrics::WATCHER_RESTARTS_TOTAL
+            .with_label_values(&[cluster, kind.as_str()])
+            .inc();
+        self.update_watch(cluster, kind, |watch| watch.restarts += 1);
+    }
+
+    /// Whether both indices of every cluster have been populated from an
+    /// initial list.
+    pub fn is_synced(&self) -> bool {
+        let watches = self.watches.lock().unwrap();
+        !watches.is_empty()
+            && watches
+                .values()
+                .all(|(devboxes, pods)| devboxes.synced && pods.synced)
+    }
+
+    /// Whether a watch is running an initial list, and since when.
+    ///
+    /// Watches that haven't started one yet don't count, so a registry
+    /// without watchers is always synced.
+    pub fn sync_state(&self) -> SyncState {
+        let watches = self.watches.lock().unwrap();
+        let since = watches
+            .values()
+            .flat_map(|(devboxes, pods)| [devboxes, pods])
+            .filter(|watch| !watch.synced)
+            .filter_map(|watch| watch.syncing_since)
+            .min();
+        since.map_or(SyncState::Synced, |since| SyncState::Syncing { since })
+    }
+
+    /// Whether a lookup miss may be due to entries not listed yet: a watch
+    /// is running an initial list, or one completed less than `grace` ago.
+    pub fn possibly_incomplete(&self, grace: Duration) -> bool {
+        self.possibly_incomplete_at(grace, Instant::now())
+    }
+
+    fn possibly_incomplete_at(&self, grace: Duration, now: Instant) -> bool {
+        if matches!(self.sync_state(), SyncState::Syncing { .. }) {
+            return true;
         }
+        let watches = self.watches.lock().unwrap();
+        let recent = watches
+            .values()
+            .flat_map(|(devboxes, pods)| [devboxes, pods])
+            .filter_map(|watch| watch.synced_at)
+            .any(|at| now.saturating_duration_since(at) < grace);
+        recent
+    }
+
+    /// Record that a watch of `cluster` in `namespace` (or the whole cluster
+    /// if `None`) got to `resource_version`.
+    pub fn record_bookmark(
+        &self,
+        cluster: &str,
+        kind: WatchKind,
+        namespace: Option<&str>,
+        resource_version: &str,
+    ) {
+        let bookmark = Bookmark {
+            cluster: cluster.to_string(),
+            kind,
+            namespace: namespace.map(ToString::to_string),
+            resource_version: resource_version.to_string(),
+        };
+        self.bookmarks
+            .lock()
+            .unwrap()
+            .insert(bookmark_key(cluster, kind, namespace), bookmark);
+    }
+
+    /// Forget where a watch got to, as it lists its scope again.
+    pub fn forget_bookmark(&self, cluster: &str, kind: WatchKind, namespace: Option<&str>) {
+        self.bookmarks
+            .lock()
+            .unwrap()
+            .remove(&bookmark_key(cluster, kind, namespace));
+    }
+
+    /// The `resourceVersion` a watch of `cluster` in `namespace` can resume
+    /// from, if any.
+    pub fn bookmark(
+        &self,
+        cluster: &str,
+        kind: WatchKind,
+        namespace: Option<&str>,
+    ) -> Option<String> {
+        self.bookmarks
+            .lock()
+            .unwrap()
+            .get(&bookmark_key(cluster, kind, namespace))
+            .map(|bookmark| bookmark.resource_version.clone())
+    }
+
+    /// Every bookmark, sorted by cluster, kind and namespace.
+    pub fn bookmarks(&self) -> Vec<Bookmark> {
+        self.bookmarks.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Entry counts and watch health of each cluster.
+    pub fn clusters(&self) -> BTreeMap<String, ClusterStatus> {
+        let mut clusters: BTreeMap<String, ClusterStatus> = self
+            .watches
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(cluster, (devbox_watch, pod_watch))| {
+                let status = ClusterStatus {
+                    devbox_watch: devbox_watch.clone(),
+                    pod_watch: pod_watch.clone(),
+                    ..Default::default()
+                };
+                (cluster.clone(), status)
+            })
+            .collect();
+        for r in &self.by_unique_id {
+            clusters.entry(r.cluster.to_string()).or_default().devboxes += 1;
+        }
+        for r in &self.pod_ips {
+            if let Some((cluster, _, _)) = split_pod_key(r.key()) {
+                clusters.entry(cluster.to_string()).or_default().pod_ips += 1;
+            }
+        }
+        clusters
     }
 
     // ========================================================================
     // Devbox CRD operations (used by DevboxWatcher)
     // ========================================================================
 
-    /// Register a devbox from Devbox CRD.
+    /// Register a devbox of the [`DEFAULT_CLUSTER`] from Devbox CRD.
     ///
-    /// Called by Devbox CRD watcher when a Devbox is created/updated.
     /// Returns `true` if this is a new entry.
     pub fn register_devbox(
         &self,
@@ -44,147 +646,1321 @@ impl DevboxRegistry {
         namespace: String,
         devbox_name: String,
     ) -> bool {
-        let is_new = !self.by_unique_id.contains_key(&unique_id);
+        self.register_devbox_info(unique_id, DevboxInfo::new(namespace, devbox_name))
+    }
 
-        self.by_unique_id.insert(
-            unique_id,
-            DevboxInfo {
-                namespace,
-                devbox_name,
-            },
+    /// Register a devbox with a fully populated `DevboxInfo` (including policy).
+    ///
+    /// Called by Devbox CRD watcher when a Devbox is created/updated.
+    /// Returns `true` if this is a new entry.
+    ///
+    /// A uniqueID already registered by a different Devbox, in the same or
+    /// another cluster, is a conflict: the registered devbox keeps the
+    /// uniqueID, so requests never flip between clusters, and the conflict
+    /// is logged and counted. The other Devbox is registered by its next
+    /// update after the registered one is deleted.
+    ///
+    /// Imported entries are replaced like the watcher's own, whichever
+    /// Devbox they name, unless imported entries are respected.
+    pub fn register_devbox_info(&self, unique_id: String, mut info: DevboxInfo) -> bool {
+        let mut replaced_other = false;
+        let is_new = match self.by_unique_id.entry(unique_id.clone()) {
+            Entry::Occupied(entry) if self.keeps_from_watch(entry.get().source) => {
+                debug!(unique_id = %unique_id, "Keeping imported devbox entry");
+                return false;
+            }
+            Entry::Occupied(entry)
+                if !entry.get().is_same_devbox(&info)
+                    && entry.get().source == EntrySource::Watch =>
+            {
+                let registered = entry.get();
+                warn!(
+                    unique_id = %unique_id,
+                    cluster = %info.cluster,
+                    namespace = %info.namespace,
+                    devbox_name = %info.devbox_name,
+                    registered_cluster = %registered.cluster,
+                    registered_namespace = %registered.namespace,
+                    registered_devbox_name = %registered.devbox_name,
+                    "Devbox uniqueID conflicts with a registered devbox, ignoring"
+                );
+                metrics::DEVBOX_CONFLICTS_TOTAL
+                    .with_label_values(&[&info.cluster])
+                    .inc();
+                return false;
+            }
+            Entry::Occupied(mut entry) => {
+                replaced_other = !entry.get().is_same_devbox(&info);
+                if !replaced_other {
+                    info.last_access = Arc::clone(&entry.get().last_access);
+                }
+                entry.insert(info);
+                false
+            }
+            Entry::Vacant(entry) => {
+                if !self.reserve_entry() {
+                    error!(
+                        unique_id = %unique_id,
+                        cluster = %info.cluster,
+                        namespace = %info.namespace,
+                        devbox_name = %info.devbox_name,
+                        max_entries = self.max_entries,
+                        "Devbox registry is full, refusing to register devbox"
+                    );
+                    metrics::REGISTRY_REFUSED_TOTAL
+                        .with_label_values(&[&info.cluster])
+                        .inc();
+                    return false;
+                }
+                entry.insert(info);
+                true
+            }
+        };
+        if is_new {
+            self.note_registered(&unique_id);
+        }
+        if replaced_other {
+            self.notify_unregistered(&unique_id);
+        }
+        is_new
+    }
+
+    /// Register a devbox from an import, replacing any entry of the
+    /// uniqueID. Returns `false` if the registry is full.
+    pub fn import_devbox(&self, unique_id: String, mut info: DevboxInfo) -> bool {
+        info.source = EntrySource::Import;
+        self.restore_devbox(unique_id, info)
+    }
+
+    /// Register a devbox from a snapshot, keeping the source it records and
+    /// replacing any entry of the uniqueID. Returns `false` if the registry
+    /// is full.
+    pub fn restore_devbox(&self, unique_id: String, mut info: DevboxInfo) -> bool {
+        let (is_new, replaced_other) = match self.by_unique_id.entry(unique_id.clone()) {
+            Entry::Occupied(mut entry) => {
+                let replaced_other = !entry.get().is_same_devbox(&info);
+                if !replaced_other {
+                    info.last_access = Arc::clone(&entry.get().last_access);
+                }
+                entry.insert(info);
+                (false, replaced_other)
+            }
+            Entry::Vacant(entry) => {
+                if !self.reserve_entry() {
+                    return false;
+                }
+                entry.insert(info);
+                (true, false)
+            }
+        };
+        if is_new {
+            self.note_registered(&unique_id);
+        }
+        if replaced_other {
+            self.notify_unregistered(&unique_id);
+        }
+        true
+    }
+
+    fn note_registered(&self, unique_id: &str) {
+        let mut filter = self.unique_id_filter.write().unwrap();
+        filter.insert(unique_id);
+        if filter.needs_rebuild() {
+            self.rebuild_filter(&mut filter);
+        }
+    }
+
+    /// Unregister a devbox by its `unique_id`, whichever Devbox registered it.
+    pub fn unregister_devbox(&self, unique_id: &str) -> bool {
+        let removed = self.by_unique_id.remove(unique_id).is_some();
+        if removed {
+            self.release_entries(1);
+            self.note_unregistered(unique_id);
+        }
+        removed
+    }
+
+    /// Unregister `unique_id` if it is registered by the Devbox of `info`.
+    ///
+    /// Called by Devbox CRD watcher when a Devbox is deleted, so deleting
+    /// the losing side of a conflict leaves the registered devbox alone.
+    pub fn unregister_devbox_info(&self, unique_id: &str, info: &DevboxInfo) -> bool {
+        let removed = self
+            .by_unique_id
+            .remove_if(unique_id, |_, registered| {
+                registered.is_same_devbox(info) && !self.keeps_from_watch(registered.source)
+            })
+            .is_some();
+        if removed {
+            self.release_entries(1);
+            self.note_unregistered(unique_id);
+        }
+        removed
+    }
+
+    /// Count a new entry against the cap, unless the index is full.
+    ///
+    /// Refusals only depend on the count, so whichever registration comes
+    /// after the cap is reached is refused.
+    fn reserve_entry(&self) -> bool {
+        let reserved = self
+            .entries
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (self.max_entries == 0 || n < self.max_entries).then_some(n + 1)
+            })
+            .is_ok();
+        if !reserved && !self.full.swap(true, Ordering::AcqRel) {
+            metrics::REGISTRY_FULL.set(1);
+        }
+        reserved
+    }
+
+    fn release_entries(&self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let before = self.entries.fetch_sub(count, Ordering::AcqRel);
+        if before - count < self.max_entries && self.full.swap(false, Ordering::AcqRel) {
+            metrics::REGISTRY_FULL.set(0);
+        }
+    }
+
+    /// Whether registrations were refused since the index last had room.
+    pub fn is_full(&self) -> bool {
+        self.full.load(Ordering::Acquire)
+    }
+
+    /// Size of the devbox index against its cap.
+    pub fn usage(&self) -> RegistryUsage {
+        RegistryUsage {
+            entries: self.entries.load(Ordering::Acquire),
+            max_entries: self.max_entries,
+            full: self.is_full(),
+            approx_memory_bytes: self.approx_memory_bytes(),
+        }
+    }
+
+    /// Rough estimate of the memory held by both indices and the uniqueID
+    /// filter: the entries and the strings they own, ignoring the maps' own
+    /// overhead and the shared policies.
+    pub fn approx_memory_bytes(&self) -> usize {
+        let devboxes: usize = self
+            .by_unique_id
+            .iter()
+            .map(|r| {
+                size_of::<(String, DevboxInfo)>()
+                    + r.key().len()
+                    + r.namespace.len()
+                    + r.devbox_name.len()
+            })
+            .sum();
+        let pod_ips: usize = self
+            .pod_ips
+            .iter()
+            .map(|r| {
+                let pod = r
+                    .pod
+                    .as_ref()
+                    .map_or(0, |pod| pod.name.len() + pod.uid.len());
+                size_of::<(String, PodEntry)>() + r.key().len() + r.endpoint.ip.len() + pod
+            })
+            .sum();
+        devboxes + pod_ips + self.unique_id_filter.read().unwrap().memory_bytes()
+    }
+
+    fn note_unregistered(&self, unique_id: &str) {
+        {
+            let mut filter = self.unique_id_filter.write().unwrap();
+            filter.note_removed();
+            if filter.needs_rebuild() {
+                self.rebuild_filter(&mut filter);
+            }
+        }
+        self.notify_unregistered(unique_id);
+    }
+
+    /// Clear the devbox entries of `cluster` (used during Devbox watcher
+    /// re-initialization).
+    pub fn clear_devboxes(&self, cluster: &str) {
+        self.clear_devboxes_in(cluster, None);
+    }
+
+    /// Clear the devboxes of `cluster` in `namespace` (used during
+    /// re-initialization of a namespaced Devbox watcher), or in every
+    /// namespace if `None`.
+    pub fn clear_devboxes_in(&self, cluster: &str, namespace: Option<&str>) {
+        self.remove_devboxes(|info| {
+            &*info.cluster == cluster
+                && namespace.is_none_or(|namespace| info.namespace == namespace)
+                && !self.keeps_from_watch(info.source)
+        });
+        debug!(cluster = %cluster, namespace = ?namespace, "Devbox registry cleared");
+    }
+
+    /// Clear both indices of every cluster, imported entries included.
+    pub fn clear_all(&self) {
+        self.remove_devboxes(|_| true);
+        self.remove_pod_ips(|_, _| true);
+        debug!("Registry cleared");
+    }
+
+    fn remove_devboxes(&self, remove: impl Fn(&DevboxInfo) -> bool) {
+        // Hold the filter lock so registrations racing the clear are added
+        // to the new filter
+        let mut filter = self.unique_id_filter.write().unwrap();
+        let mut removed = Vec::new();
+        self.by_unique_id.retain(|unique_id, info| {
+            let keep = !remove(info);
+            if !keep {
+                removed.push(unique_id.clone());
+            }
+            keep
+        });
+        self.release_entries(removed.len());
+        // Keep the capacity, since the cluster is about to be relisted
+        let mut rebuilt = BloomFilter::new(filter.capacity());
+        for r in &self.by_unique_id {
+            rebuilt.insert(r.key());
+        }
+        *filter = rebuilt;
+        drop(filter);
+        for unique_id in &removed {
+            self.notify_unregistered(unique_id);
+        }
+    }
+
+    /// Whether `unique_id` may be registered. `false` is definite, so
+    /// callers can reject unknown devboxes without a lookup.
+    pub fn may_contain_devbox(&self, unique_id: &str) -> bool {
+        self.unique_id_filter.read().unwrap().may_contain(unique_id)
+    }
+
+    /// Replace the filter with one built from the current devbox index.
+    fn rebuild_filter(&self, filter: &mut BloomFilter) {
+        let keys: Vec<String> = self.by_unique_id.iter().map(|r| r.key().clone()).collect();
+        *filter = BloomFilter::from_items(keys.iter().map(String::as_str));
+        debug!(
+            devboxes = keys.len(),
+            capacity = filter.capacity(),
+            "Rebuilt uniqueID filter"
         );
+    }
 
-        is_new
+    /// Look up a devbox by `unique_id`.
+    ///
+    /// Returns a clone of the `DevboxInfo` to avoid holding any locks.
+    pub fn get_devbox(&self, unique_id: &str) -> Option<DevboxInfo> {
+        self.by_unique_id.get(unique_id).map(|r| r.value().clone())
     }
 
-    /// Unregister a devbox by its `unique_id`.
+    /// Get the current number of registered devboxes.
+    pub fn devbox_count(&self) -> usize {
+        self.by_unique_id.len()
+    }
+
+    /// Devboxes whose uniqueIDs are within [`suggest::MAX_DISTANCE`] edits
+    /// of `unique_id` (excluding it), nearest first.
     ///
-    /// Called by Devbox CRD watcher when a Devbox is deleted.
-    pub fn unregister_devbox(&self, unique_id: &str) -> bool {
-        self.by_unique_id.remove(unique_id).is_some()
+    /// Registries of more than [`suggest::MAX_DEVBOXES`] devboxes are not
+    /// searched, so this stays cheap enough to run on missed lookups.
+    pub fn similar_devboxes(&self, unique_id: &str) -> Vec<(String, DevboxInfo)> {
+        if self.devbox_count() > suggest::MAX_DEVBOXES {
+            return Vec::new();
+        }
+        let mut similar: Vec<(usize, String, DevboxInfo)> = self
+            .by_unique_id
+            .iter()
+            .filter(|r| r.key() != unique_id)
+            .filter_map(|r| {
+                let distance = suggest::edit_distance(unique_id, r.key(), suggest::MAX_DISTANCE)?;
+                Some((distance, r.key().clone(), r.value().clone()))
+            })
+            .collect();
+        similar.sort_unstable_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        similar
+            .into_iter()
+            .map(|(_, unique_id, info)| (unique_id, info))
+            .collect()
+    }
+
+    // ========================================================================
+    // Pod operations (used by PodWatcher)
+    // ========================================================================
+
+    /// Update Pod IP for a devbox of the [`DEFAULT_CLUSTER`].
+    ///
+    /// If `pod_ip` is empty, the entry is removed.
+    pub fn update_pod_ip(
+        &self,
+        namespace: &str,
+        devbox_name: &str,
+        pod_ip: String,
+    ) -> Result<(), InvalidBackendAddr> {
+        self.update_pod_endpoint(DEFAULT_CLUSTER, namespace, devbox_name, pod_ip, None)
+    }
+
+    /// Update Pod IP for a devbox, recording the Pod it belongs to.
+    ///
+    /// Called by Pod watcher when a Pod is created/updated.
+    /// If `pod_ip` is empty, the entry is removed.
+    ///
+    /// Knowing the Pod lets the orphan sweep re-verify old entries against
+    /// the API server. A different Pod with the same IP is a new endpoint.
+    ///
+    /// IPs that can't be a backend's (see [`check_backend_ip`]) are refused,
+    /// leaving the entry as it was, as are imported entries if respected.
+    pub fn update_pod_endpoint(
+        &self,
+        cluster: &str,
+        namespace: &str,
+        devbox_name: &str,
+        pod_ip: String,
+        pod: Option<PodRef>,
+    ) -> Result<(), InvalidBackendAddr> {
+        if pod_ip.is_empty() {
+            self.clear_pod_ip(cluster, namespace, devbox_name);
+            return Ok(());
+        }
+        self.check_backend_ip(&pod_ip)?;
+
+        let devbox_key = pod_key(cluster, namespace, devbox_name);
+        let (changed, replaced) = match self.pod_ips.entry(devbox_key) {
+            Entry::Occupied(entry) if self.keeps_from_watch(entry.get().source) => (false, false),
+            Entry::Occupied(entry)
+                if entry.get().endpoint.ip == pod_ip
+                    && (pod.is_none() || entry.get().pod == pod)
+                    && entry.get().source == EntrySource::Watch =>
+            {
+                (false, false)
+            }
+            Entry::Occupied(mut entry) => {
+                entry.insert(self.new_entry(pod_ip.clone(), pod, EntrySource::Watch));
+                (true, true)
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(self.new_entry(pod_ip.clone(), pod, EntrySource::Watch));
+                (true, false)
+            }
+        };
+
+        if replaced {
+            self.notify_endpoint_changed(cluster, namespace, devbox_name);
+        }
+        if changed {
+            info!(
+                cluster = %cluster,
+                namespace = %namespace,
+                devbox_name = %devbox_name,
+                pod_ip = %pod_ip,
+                "Pod IP updated"
+            );
+        }
+        Ok(())
+    }
+
+    /// Set the Pod IP of a devbox from an import, replacing any entry.
+    pub fn import_pod_ip(
+        &self,
+        cluster: &str,
+        namespace: &str,
+        devbox_name: &str,
+        pod_ip: String,
+    ) -> Result<(), InvalidBackendAddr> {
+        self.restore_pod_ip(cluster, namespace, devbox_name, pod_ip, EntrySource::Import)
+    }
+
+    /// Set the Pod IP of a devbox from a snapshot, as an entry from
+    /// `source`, replacing any entry.
+    pub fn restore_pod_ip(
+        &self,
+        cluster: &str,
+        namespace: &str,
+        devbox_name: &str,
+        pod_ip: String,
+        source: EntrySource,
+    ) -> Result<(), InvalidBackendAddr> {
+        self.check_backend_ip(&pod_ip)?;
+        let devbox_key = pod_key(cluster, namespace, devbox_name);
+        let entry = self.new_entry(pod_ip, None, source);
+        if self.pod_ips.insert(devbox_key, entry).is_some() {
+            self.notify_endpoint_changed(cluster, namespace, devbox_name);
+        }
+        Ok(())
+    }
+
+    /// Create a Pod index entry with a fresh endpoint generation.
+    fn new_entry(&self, ip: String, pod: Option<PodRef>, source: EntrySource) -> PodEntry {
+        PodEntry {
+            endpoint: PodEndpoint {
+                ip,
+                generation: self.next_generation.fetch_add(1, Ordering::Relaxed),
+            },
+            pod,
+            verified_at: Instant::now(),
+            source,
+        }
+    }
+
+    /// Clear Pod IP for a devbox.
+    ///
+    /// Called by Pod watcher when a Pod is deleted.
+    pub fn clear_pod_ip(&self, cluster: &str, namespace: &str, devbox_name: &str) {
+        let devbox_key = pod_key(cluster, namespace, devbox_name);
+        let removed = self
+            .pod_ips
+            .remove_if(&devbox_key, |_, entry| !self.keeps_from_watch(entry.source));
+        if removed.is_some() {
+            self.notify_endpoint_changed(cluster, namespace, devbox_name);
+            info!(
+                cluster = %cluster,
+                namespace = %namespace,
+                devbox_name = %devbox_name,
+                "Pod IP cleared"
+            );
+        }
+    }
+
+    /// Clear the pod IP entries of `cluster` (used during Pod watcher
+    /// re-initialization).
+    pub fn clear_pod_ips(&self, cluster: &str) {
+        self.clear_pod_ips_in(cluster, None);
+    }
+
+    /// Clear the pod IP entries of `cluster` in `namespace` (used during
+    /// re-initialization of a namespaced Pod watcher), or in every namespace
+    /// if `None`.
+    pub fn clear_pod_ips_in(&self, cluster: &str, namespace: Option<&str>) {
+        self.remove_pod_ips(|key, entry| {
+            split_pod_key(key).is_some_and(|(c, ns, _)| {
+                c == cluster && namespace.is_none_or(|namespace| ns == namespace)
+            }) && !self.keeps_from_watch(entry.source)
+        });
+        debug!(cluster = %cluster, namespace = ?namespace, "Pod IP registry cleared");
+    }
+
+    fn remove_pod_ips(&self, remove: impl Fn(&str, &PodEntry) -> bool) {
+        let mut removed = Vec::new();
+        self.pod_ips.retain(|key, entry| {
+            let keep = !remove(key, entry);
+            if !keep {
+                removed.push(key.clone());
+            }
+            keep
+        });
+        for key in &removed {
+            if let Some((cluster, namespace, devbox_name)) = split_pod_key(key) {
+                self.notify_endpoint_changed(cluster, namespace, devbox_name);
+            }
+        }
+    }
+
+    /// Get Pod IP for a devbox of the [`DEFAULT_CLUSTER`].
+    pub fn get_pod_ip(&self, namespace: &str, devbox_name: &str) -> Option<String> {
+        self.get_pod_endpoint(DEFAULT_CLUSTER, namespace, devbox_name)
+            .map(|endpoint| endpoint.ip)
+    }
+
+    /// Get Pod IP and endpoint generation for a devbox.
+    pub fn get_pod_endpoint(
+        &self,
+        cluster: &str,
+        namespace: &str,
+        devbox_name: &str,
+    ) -> Option<PodEndpoint> {
+        let devbox_key = pod_key(cluster, namespace, devbox_name);
+        self.pod_ips.get(&devbox_key).map(|r| r.endpoint.clone())
+    }
+
+    /// Whether `unique_id` is still routed to the endpoint of `generation`.
+    ///
+    /// False once the devbox is unregistered, its Pod IP is cleared or a new
+    /// IP or Pod replaces the endpoint, so a request routed before any of
+    /// these never dials an address that may belong to another Pod by now.
+    pub fn is_current_endpoint(&self, unique_id: &str, generation: u64) -> bool {
+        self.get_devbox(unique_id)
+            .and_then(|info| {
+                self.get_pod_endpoint(&info.cluster, &info.namespace, &info.devbox_name)
+            })
+            .is_some_and(|endpoint| endpoint.generation == generation)
+    }
+
+    /// Get the current number of registered pod IPs.
+    pub fn pod_ip_count(&self) -> usize {
+        self.pod_ips.len()
+    }
+
+    /// Count devboxes, namespaces and Pod IPs in a single pass over the
+    /// devbox index.
+    ///
+    /// The indices change concurrently, so the counts are only consistent
+    /// with each other when no watcher is applying events.
+    pub fn stats(&self) -> RegistryStats {
+        let mut namespaces = HashSet::new();
+        let mut stats = RegistryStats::default();
+        for r in &self.by_unique_id {
+            stats.total_devboxes += 1;
+            let devbox_key = pod_key(&r.cluster, &r.namespace, &r.devbox_name);
+            if self.pod_ips.contains_key(&devbox_key) {
+                stats.devboxes_with_pod_ip += 1;
+            }
+            if !namespaces.contains(r.namespace.as_str()) {
+                namespaces.insert(r.namespace.clone());
+            }
+        }
+        stats.distinct_namespaces = namespaces.len();
+        stats.total_pod_ips = self.pod_ips.len();
+        stats
+    }
+
+    /// Snapshot the devbox index, sorted by uniqueID.
+    pub fn devbox_entries(&self) -> Vec<(String, DevboxInfo)> {
+        let mut entries: Vec<_> = self
+            .by_unique_id
+            .iter()
+            .map(|r| (r.key().clone(), r.value().clone()))
+            .collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    /// Snapshot the Pod index, sorted by cluster, namespace and devbox name.
+    pub fn pod_ip_entries(&self) -> Vec<PodIpEntry> {
+        let mut entries: Vec<_> = self
+            .pod_ips
+            .iter()
+            .filter_map(|r| {
+                let (cluster, namespace, devbox_name) = split_pod_key(r.key())?;
+                Some(PodIpEntry {
+                    cluster: cluster.to_string(),
+                    namespace: namespace.to_string(),
+                    devbox_name: devbox_name.to_string(),
+                    ip: r.endpoint.ip.clone(),
+                    source: r.source,
+                })
+            })
+            .collect();
+        entries.sort_unstable_by(|a, b| {
+            (&a.cluster, &a.namespace, &a.devbox_name).cmp(&(
+                &b.cluster,
+                &b.namespace,
+                &b.devbox_name,
+            ))
+        });
+        entries
+    }
+
+    // ========================================================================
+    // Consistency sweep (used by PodIpSweeper)
+    // ========================================================================
+
+    /// Snapshot the Pod index entries whose devbox is not registered.
+    ///
+    /// Both indices are copied shard by shard, so no lock is held for longer
+    /// than it takes to clone one shard's keys.
+    pub fn orphaned_pod_ips(&self) -> Vec<PodIpRecord> {
+        let registered: HashSet<String> = self
+            .by_unique_id
+            .iter()
+            .map(|r| pod_key(&r.cluster, &r.namespace, &r.devbox_name))
+            .collect();
+
+        self.pod_ip_records(|key, _| !registered.contains(key))
+    }
+
+    /// Snapshot the Pod index entries with a known Pod that were last set or
+    /// verified more than `ttl` ago.
+    pub fn pod_ips_unverified_for(&self, ttl: Duration) -> Vec<PodIpRecord> {
+        self.pod_ip_records(|_, entry| entry.pod.is_some() && entry.verified_at.elapsed() > ttl)
+    }
+
+    fn pod_ip_records(&self, filter: impl Fn(&str, &PodEntry) -> bool) -> Vec<PodIpRecord> {
+        self.pod_ips
+            .iter()
+            .filter(|r| filter(r.key(), r.value()))
+            .filter_map(|r| {
+                let (cluster, namespace, devbox_name) = split_pod_key(r.key())?;
+                Some(PodIpRecord {
+                    cluster: cluster.to_string(),
+                    namespace: namespace.to_string(),
+                    devbox_name: devbox_name.to_string(),
+                    generation: r.endpoint.generation,
+                    pod: r.pod.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Remove a Pod index entry, unless it changed since `generation` was read.
+    ///
+    /// Returns `true` if the entry was removed.
+    pub fn remove_pod_ip_if_generation(
+        &self,
+        cluster: &str,
+        namespace: &str,
+        devbox_name: &str,
+        generation: u64,
+    ) -> bool {
+        let devbox_key = pod_key(cluster, namespace, devbox_name);
+        let removed = self
+            .pod_ips
+            .remove_if(&devbox_key, |_, entry| {
+                entry.endpoint.generation == generation
+            })
+            .is_some();
+        if removed {
+            self.notify_endpoint_changed(cluster, namespace, devbox_name);
+        }
+        removed
+    }
+
+    /// Record that a Pod index entry was confirmed against the API server.
+    pub fn mark_pod_ip_verified(
+        &self,
+        cluster: &str,
+        namespace: &str,
+        devbox_name: &str,
+        generation: u64,
+    ) {
+        let devbox_key = pod_key(cluster, namespace, devbox_name);
+        if let Some(mut entry) = self.pod_ips.get_mut(&devbox_key) {
+            if entry.endpoint.generation == generation {
+                entry.verified_at = Instant::now();
+            }
+        }
+    }
+}
+
+impl Default for DevboxRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_register_and_get_devbox() {
+        let registry = DevboxRegistry::new();
+        registry.register_devbox(
+            "unique-123".to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+
+        let info = registry.get_devbox("unique-123").unwrap();
+        assert_eq!(info.namespace, "ns-test");
+        assert_eq!(info.devbox_name, "devbox1");
+    }
+
+    #[test]
+    fn test_similar_devboxes() {
+        let registry = DevboxRegistry::new();
+        for (id, namespace) in [
+            ("my-app-7x9k2", "ns-1"),
+            ("my-app-7x9k3", "ns-2"),
+            ("my-app-7x9", "ns-1"),
+            ("my-app-8x9k2x", "ns-1"),
+            ("my-app-7x9k23", "ns-1"),
+            ("other-app", "ns-1"),
+        ] {
+            registry.register_devbox(id.to_string(), namespace.to_string(), id.to_string());
+        }
+
+        let similar: Vec<(String, String)> = registry
+            .similar_devboxes("my-app-7x9k")
+            .into_iter()
+            .map(|(id, info)| (id, info.namespace))
+            .collect();
+        assert_eq!(
+            similar,
+            vec![
+                ("my-app-7x9".to_string(), "ns-1".to_string()),
+                ("my-app-7x9k2".to_string(), "ns-1".to_string()),
+                ("my-app-7x9k3".to_string(), "ns-2".to_string()),
+                // Further away, so ranked last
+                ("my-app-7x9k23".to_string(), "ns-1".to_string()),
+            ]
+        );
+        // The uniqueID itself is not similar
+        assert!(registry.similar_devboxes("other-app").is_empty());
+        assert!(registry.similar_devboxes("unrelated").is_empty());
+    }
+
+    #[test]
+    fn test_similar_devboxes_bounded() {
+        let registry = DevboxRegistry::new();
+        for i in 0..suggest::MAX_DEVBOXES {
+            registry.register_devbox(
+                format!("app-{i:05}-{:x}", i * 7919),
+                "ns".to_string(),
+                format!("devbox{i}"),
+            );
+        }
+
+        let start = Instant::now();
+        for target in ["app-00042-5133", "app-zzzzz-ffff", "x"] {
+            registry.similar_devboxes(target);
+        }
+        // Generous for unoptimized builds; comparing every full uniqueID
+        // pair would take several times longer
+        assert!(
+            start.elapsed() < Duration::from_secs(1),
+            "{:?}",
+            start.elapsed()
+        );
+        assert_eq!(
+            registry.similar_devboxes("app-00042-5133")[0].0,
+            "app-00042-51336"
+        );
+
+        // Larger registries are not searched at all
+        registry.register_devbox(
+            "app-extra".to_string(),
+            "ns".to_string(),
+            "extra".to_string(),
+        );
+        assert!(registry.similar_devboxes("app-00042-5133").is_empty());
+    }
+
+    #[test]
+    fn test_register_devbox_info_replaces_policy() {
+        let registry = DevboxRegistry::new();
+        assert!(registry.register_devbox(
+            "unique-123".to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        ));
+        assert!(registry
+            .get_devbox("unique-123")
+            .unwrap()
+            .policy
+            .tls_ports
+            .is_empty());
+
+        let mut info = DevboxInfo::new("ns-test".to_string(), "devbox1".to_string());
+        info.policy = Arc::new(DevboxPolicy {
+            tls_ports: vec![8443],
+            ..Default::default()
+        });
+        assert!(!registry.register_devbox_info("unique-123".to_string(), info));
+
+        let info = registry.get_devbox("unique-123").unwrap();
+        assert_eq!(info.policy.tls_ports, vec![8443]);
+    }
+
+    #[test]
+    fn test_last_access() {
+        let registry = DevboxRegistry::new();
+        registry.register_devbox(
+            "unique-123".to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+        assert_eq!(
+            registry.get_devbox("unique-123").unwrap().last_access(),
+            None
+        );
+
+        // Touching a clone updates the registered entry
+        registry
+            .get_devbox("unique-123")
+            .unwrap()
+            .touch(1_700_000_000);
+        let last_access = || registry.get_devbox("unique-123").unwrap().last_access();
+        assert_eq!(last_access(), Some(1_700_000_000));
+
+        // Updates of the Devbox keep it, another Devbox taking over doesn't
+        registry.register_devbox(
+            "unique-123".to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+        assert_eq!(last_access(), Some(1_700_000_000));
+        registry.import_devbox(
+            "unique-123".to_string(),
+            DevboxInfo::new("ns-other".to_string(), "devbox2".to_string()),
+        );
+        assert_eq!(last_access(), None);
     }
 
-    /// Clear all devbox entries (used during Devbox watcher re-initialization).
-    pub fn clear_devboxes(&self) {
-        self.by_unique_id.clear();
-        debug!("Devbox registry cleared");
-    }
+    #[test]
+    fn test_update_pod_ip() {
+        let registry = DevboxRegistry::new();
 
-    /// Look up a devbox by `unique_id`.
-    ///
-    /// Returns a clone of the `DevboxInfo` to avoid holding any locks.
-    pub fn get_devbox(&self, unique_id: &str) -> Option<DevboxInfo> {
-        self.by_unique_id.get(unique_id).map(|r| r.value().clone())
-    }
+        // Pod IP can be set independently of devbox registration
+        registry
+            .update_pod_ip("ns-test", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
 
-    /// Get the current number of registered devboxes.
-    pub fn devbox_count(&self) -> usize {
-        self.by_unique_id.len()
+        let pod_ip = registry.get_pod_ip("ns-test", "devbox1");
+        assert_eq!(pod_ip, Some("10.0.0.1".to_string()));
     }
 
-    // ========================================================================
-    // Pod operations (used by PodWatcher)
-    // ========================================================================
-
-    /// Update Pod IP for a devbox.
-    ///
-    /// Called by Pod watcher when a Pod is created/updated.
-    /// If `pod_ip` is empty, the entry is removed.
-    pub fn update_pod_ip(&self, namespace: &str, devbox_name: &str, pod_ip: String) {
-        if pod_ip.is_empty() {
-            self.clear_pod_ip(namespace, devbox_name);
-            return;
+    #[test]
+    fn test_check_backend_ip() {
+        for (ip, expected) in [
+            ("10.0.0.1", Ok(())),
+            ("fd00::1", Ok(())),
+            ("", Err(InvalidBackendAddr::NotAnIp)),
+            ("pod-ip", Err(InvalidBackendAddr::NotAnIp)),
+            ("127.0.0.1", Err(InvalidBackendAddr::Loopback)),
+            ("127.1.2.3", Err(InvalidBackendAddr::Loopback)),
+            ("::1", Err(InvalidBackendAddr::Loopback)),
+            ("::ffff:127.0.0.1", Err(InvalidBackendAddr::Loopback)),
+            ("0.0.0.0", Err(InvalidBackendAddr::Unspecified)),
+            ("::", Err(InvalidBackendAddr::Unspecified)),
+            ("169.254.169.254", Err(InvalidBackendAddr::LinkLocal)),
+            ("fe80::1", Err(InvalidBackendAddr::LinkLocal)),
+            ("224.0.0.1", Err(InvalidBackendAddr::Multicast)),
+            ("255.255.255.255", Err(InvalidBackendAddr::Multicast)),
+            ("ff02::1", Err(InvalidBackendAddr::Multicast)),
+        ] {
+            assert_eq!(check_backend_ip(ip, false), expected, "{ip}");
         }
 
-        let devbox_key = format!("{namespace}/{devbox_name}");
-        let old_ip = self.pod_ips.insert(devbox_key, pod_ip.clone());
+        // Only loopback is allowed by the escape hatch
+        assert_eq!(check_backend_ip("127.0.0.1", true), Ok(()));
+        assert_eq!(check_backend_ip("::1", true), Ok(()));
+        assert_eq!(
+            check_backend_ip("0.0.0.0", true),
+            Err(InvalidBackendAddr::Unspecified)
+        );
+        assert_eq!(
+            check_backend_ip("169.254.1.1", true),
+            Err(InvalidBackendAddr::LinkLocal)
+        );
+    }
+
+    #[test]
+    fn test_update_pod_ip_rejects_invalid() {
+        let registry = DevboxRegistry::new();
+        registry
+            .update_pod_ip("ns-test", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
 
-        if old_ip.as_ref() != Some(&pod_ip) {
-            info!(
-                namespace = %namespace,
-                devbox_name = %devbox_name,
-                pod_ip = %pod_ip,
-                "Pod IP updated"
+        // Refused IPs leave the entry as it was
+        for ip in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "169.254.0.5",
+            "fe80::1",
+            "239.1.1.1",
+            "x",
+        ] {
+            assert!(registry
+                .update_pod_ip("ns-test", "devbox1", ip.to_string())
+                .is_err());
+            assert_eq!(
+                registry.get_pod_ip("ns-test", "devbox1"),
+                Some("10.0.0.1".to_string())
             );
         }
+
+        let registry = DevboxRegistry::new().with_loopback_backends(true);
+        registry
+            .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+            .unwrap();
+        assert_eq!(
+            registry.update_pod_ip("ns-test", "devbox1", "0.0.0.0".to_string()),
+            Err(InvalidBackendAddr::Unspecified)
+        );
+        assert_eq!(
+            registry.get_pod_ip("ns-test", "devbox1"),
+            Some("127.0.0.1".to_string())
+        );
     }
 
-    /// Clear Pod IP for a devbox.
-    ///
-    /// Called by Pod watcher when a Pod is deleted.
-    pub fn clear_pod_ip(&self, namespace: &str, devbox_name: &str) {
-        let devbox_key = format!("{namespace}/{devbox_name}");
-        if self.pod_ips.remove(&devbox_key).is_some() {
-            info!(
-                namespace = %namespace,
-                devbox_name = %devbox_name,
-                "Pod IP cleared"
+    #[test]
+    fn test_invalid_pod_ip_not_routed() {
+        use crate::blocklist::Blocklist;
+        use crate::config::Config;
+        use crate::routing::{resolve_backend, BackendResult};
+
+        let registry = DevboxRegistry::new();
+        let blocklist = Blocklist::from_config(&Config::default());
+        registry.register_devbox(
+            "unique-123".to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+
+        // Entries bypassing the update check are still caught when routing
+        for ip in ["0.0.0.0", "127.0.0.1", "169.254.0.5", "ff02::1"] {
+            let key = pod_key(DEFAULT_CLUSTER, "ns-test", "devbox1");
+            registry.pod_ips.insert(
+                key,
+                registry.new_entry(ip.to_string(), None, EntrySource::Watch),
+            );
+            let result = resolve_backend(&registry, &blocklist, "unique-123", 8080);
+            assert!(
+                matches!(result, BackendResult::InvalidAddress(ref endpoint, ref info, _)
+                    if endpoint.ip == ip && info.devbox_name == "devbox1"),
+                "{ip}"
             );
         }
     }
 
-    /// Clear all pod IP entries (used during Pod watcher re-initialization).
-    pub fn clear_pod_ips(&self) {
-        self.pod_ips.clear();
-        debug!("Pod IP registry cleared");
-    }
+    #[test]
+    fn test_pod_endpoint_generation() {
+        let registry = DevboxRegistry::new();
+        registry
+            .update_pod_ip("ns-test", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        let first = registry
+            .get_pod_endpoint(DEFAULT_CLUSTER, "ns-test", "devbox1")
+            .unwrap();
 
-    /// Get Pod IP for a devbox.
-    pub fn get_pod_ip(&self, namespace: &str, devbox_name: &str) -> Option<String> {
-        let devbox_key = format!("{namespace}/{devbox_name}");
-        self.pod_ips.get(&devbox_key).map(|r| r.value().clone())
+        // Same IP keeps the generation
+        registry
+            .update_pod_ip("ns-test", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        assert_eq!(
+            registry.get_pod_endpoint(DEFAULT_CLUSTER, "ns-test", "devbox1"),
+            Some(first.clone())
+        );
+
+        // New IP gets a new generation
+        registry
+            .update_pod_ip("ns-test", "devbox1", "10.0.0.2".to_string())
+            .unwrap();
+        let second = registry
+            .get_pod_endpoint(DEFAULT_CLUSTER, "ns-test", "devbox1")
+            .unwrap();
+        assert_eq!(second.ip, "10.0.0.2");
+        assert_ne!(second.generation, first.generation);
+
+        // Returning to a previous IP is still a new endpoint
+        registry
+            .update_pod_ip("ns-test", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        let third = registry
+            .get_pod_endpoint(DEFAULT_CLUSTER, "ns-test", "devbox1")
+            .unwrap();
+        assert_eq!(third.ip, first.ip);
+        assert_ne!(third.generation, first.generation);
+        assert_ne!(third.generation, second.generation);
     }
 
-    /// Get the current number of registered pod IPs.
-    pub fn pod_ip_count(&self) -> usize {
-        self.pod_ips.len()
+    #[test]
+    fn test_pod_endpoint_new_pod_same_ip() {
+        let registry = DevboxRegistry::new();
+        let pod = |uid: &str| {
+            Some(PodRef {
+                name: "devbox1-pod".to_string(),
+                uid: uid.to_string(),
+            })
+        };
+        registry
+            .update_pod_endpoint(
+                DEFAULT_CLUSTER,
+                "ns-test",
+                "devbox1",
+                "10.0.0.1".to_string(),
+                pod("a"),
+            )
+            .unwrap();
+        let first = registry
+            .get_pod_endpoint(DEFAULT_CLUSTER, "ns-test", "devbox1")
+            .unwrap();
+
+        // Same Pod, or an update without Pod identity, keeps the endpoint
+        registry
+            .update_pod_endpoint(
+                DEFAULT_CLUSTER,
+                "ns-test",
+                "devbox1",
+                "10.0.0.1".to_string(),
+                pod("a"),
+            )
+            .unwrap();
+        registry
+            .update_pod_ip("ns-test", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        assert_eq!(
+            registry.get_pod_endpoint(DEFAULT_CLUSTER, "ns-test", "devbox1"),
+            Some(first.clone())
+        );
+
+        // A recreated Pod that got the same IP is a new endpoint
+        registry
+            .update_pod_endpoint(
+                DEFAULT_CLUSTER,
+                "ns-test",
+                "devbox1",
+                "10.0.0.1".to_string(),
+                pod("b"),
+            )
+            .unwrap();
+        let second = registry
+            .get_pod_endpoint(DEFAULT_CLUSTER, "ns-test", "devbox1")
+            .unwrap();
+        assert_ne!(second.generation, first.generation);
     }
-}
 
-impl Default for DevboxRegistry {
-    fn default() -> Self {
-        Self::new()
+    #[test]
+    fn test_orphaned_pod_ips() {
+        let registry = DevboxRegistry::new();
+        registry.register_devbox(
+            "id-1".to_string(),
+            "ns-1".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        registry
+            .update_pod_ip("ns-2", "devbox2", "10.0.0.2".to_string())
+            .unwrap();
+
+        let orphans = registry.orphaned_pod_ips();
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].namespace, "ns-2");
+        assert_eq!(orphans[0].devbox_name, "devbox2");
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::Arc;
-    use std::thread;
+    #[test]
+    fn test_remove_pod_ip_if_generation() {
+        let registry = DevboxRegistry::new();
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        let stale = registry
+            .get_pod_endpoint(DEFAULT_CLUSTER, "ns-1", "devbox1")
+            .unwrap();
+
+        // The entry changed after the snapshot: keep it
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.9".to_string())
+            .unwrap();
+        assert!(!registry.remove_pod_ip_if_generation(
+            DEFAULT_CLUSTER,
+            "ns-1",
+            "devbox1",
+            stale.generation
+        ));
+        assert_eq!(
+            registry.get_pod_ip("ns-1", "devbox1"),
+            Some("10.0.0.9".to_string())
+        );
+
+        let current = registry
+            .get_pod_endpoint(DEFAULT_CLUSTER, "ns-1", "devbox1")
+            .unwrap();
+        assert!(registry.remove_pod_ip_if_generation(
+            DEFAULT_CLUSTER,
+            "ns-1",
+            "devbox1",
+            current.generation
+        ));
+        assert!(registry.get_pod_ip("ns-1", "devbox1").is_none());
+    }
 
     #[test]
-    fn test_register_and_get_devbox() {
+    fn test_is_current_endpoint() {
         let registry = DevboxRegistry::new();
         registry.register_devbox(
-            "unique-123".to_string(),
-            "ns-test".to_string(),
+            "app-1".to_string(),
+            "ns-1".to_string(),
             "devbox1".to_string(),
         );
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        let routed = registry
+            .get_pod_endpoint(DEFAULT_CLUSTER, "ns-1", "devbox1")
+            .unwrap();
+        assert!(registry.is_current_endpoint("app-1", routed.generation));
+        assert!(!registry.is_current_endpoint("app-2", routed.generation));
 
-        let info = registry.get_devbox("unique-123").unwrap();
-        assert_eq!(info.namespace, "ns-test");
-        assert_eq!(info.devbox_name, "devbox1");
+        // Unregistered, even though the Pod entry lingers
+        registry.unregister_devbox("app-1");
+        assert!(!registry.is_current_endpoint("app-1", routed.generation));
+        // Registered again with the same Pod
+        registry.register_devbox(
+            "app-1".to_string(),
+            "ns-1".to_string(),
+            "devbox1".to_string(),
+        );
+        assert!(registry.is_current_endpoint("app-1", routed.generation));
+
+        // Pod deleted and recreated with the same IP
+        registry.clear_pod_ip(DEFAULT_CLUSTER, "ns-1", "devbox1");
+        assert!(!registry.is_current_endpoint("app-1", routed.generation));
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        assert!(!registry.is_current_endpoint("app-1", routed.generation));
     }
 
     #[test]
-    fn test_update_pod_ip() {
+    fn test_pod_ips_unverified_for() {
         let registry = DevboxRegistry::new();
+        let pod = PodRef {
+            name: "devbox1-pod".to_string(),
+            uid: "uid-1".to_string(),
+        };
+        registry
+            .update_pod_endpoint(
+                DEFAULT_CLUSTER,
+                "ns-1",
+                "devbox1",
+                "10.0.0.1".to_string(),
+                Some(pod),
+            )
+            .unwrap();
+        // Entries without a known Pod can't be verified
+        registry
+            .update_pod_ip("ns-2", "devbox2", "10.0.0.2".to_string())
+            .unwrap();
 
-        // Pod IP can be set independently of devbox registration
-        registry.update_pod_ip("ns-test", "devbox1", "10.0.0.1".to_string());
+        assert!(registry
+            .pod_ips_unverified_for(Duration::from_secs(60))
+            .is_empty());
+        let records = registry.pod_ips_unverified_for(Duration::ZERO);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].devbox_name, "devbox1");
+    }
 
-        let pod_ip = registry.get_pod_ip("ns-test", "devbox1");
-        assert_eq!(pod_ip, Some("10.0.0.1".to_string()));
+    #[test]
+    fn test_stats() {
+        let registry = DevboxRegistry::new();
+        assert_eq!(registry.stats(), RegistryStats::default());
+
+        for (id, ns, name) in [
+            ("id-1", "ns-1", "devbox1"),
+            ("id-2", "ns-1", "devbox2"),
+            ("id-3", "ns-2", "devbox1"),
+            ("id-4", "ns-3", "devbox4"),
+        ] {
+            registry.register_devbox(id.to_string(), ns.to_string(), name.to_string());
+        }
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        registry
+            .update_pod_ip("ns-2", "devbox1", "10.0.0.2".to_string())
+            .unwrap();
+        // Pod of a devbox that isn't registered (yet)
+        registry
+            .update_pod_ip("ns-4", "devbox9", "10.0.0.9".to_string())
+            .unwrap();
+
+        assert_eq!(
+            registry.stats(),
+            RegistryStats {
+                total_devboxes: 4,
+                devboxes_with_pod_ip: 2,
+                distinct_namespaces: 3,
+                total_pod_ips: 3,
+            }
+        );
+
+        registry.clear_pod_ip(DEFAULT_CLUSTER, "ns-1", "devbox1");
+        registry.unregister_devbox("id-4");
+        assert_eq!(
+            registry.stats(),
+            RegistryStats {
+                total_devboxes: 3,
+                devboxes_with_pod_ip: 1,
+                distinct_namespaces: 2,
+                total_pod_ips: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_unique_id_filter_never_misses_registered() {
+        let registry = DevboxRegistry::new();
+        let id = |i: usize| format!("app-{i}");
+        let assert_registered_pass = |registry: &DevboxRegistry| {
+            for r in &registry.by_unique_id {
+                assert!(registry.may_contain_devbox(r.key()), "{}", r.key());
+            }
+        };
+
+        // Growth past the initial capacity forces rebuilds
+        for i in 0..5 * MIN_CAPACITY {
+            registry.register_devbox(id(i), "ns".to_string(), id(i));
+        }
+        assert_registered_pass(&registry);
+
+        // Churn: deletions trigger rebuilds, which must keep the survivors
+        for i in (0..5 * MIN_CAPACITY).filter(|i| i % 3 != 0) {
+            registry.unregister_devbox(&id(i));
+        }
+        assert_registered_pass(&registry);
+        assert!(registry.may_contain_devbox(&id(0)));
+
+        // Re-registering an existing devbox keeps it in the filter
+        registry.register_devbox(id(0), "ns".to_string(), "renamed".to_string());
+        assert!(registry.may_contain_devbox(&id(0)));
+
+        registry.clear_devboxes(DEFAULT_CLUSTER);
+        registry.register_devbox(id(1), "ns".to_string(), id(1));
+        assert!(registry.may_contain_devbox(&id(1)));
+    }
+
+    #[test]
+    fn test_unique_id_filter_rejects_unknown() {
+        let registry = DevboxRegistry::new();
+        for i in 0..100 {
+            registry.register_devbox(format!("app-{i}"), "ns".to_string(), i.to_string());
+        }
+        let passed = (0..10_000)
+            .filter(|i| registry.may_contain_devbox(&format!("scan-{i}")))
+            .count();
+        assert!(passed < 100, "{passed}");
+
+        // Deleted devboxes stop passing once the filter is rebuilt
+        for i in 0..100 {
+            registry.unregister_devbox(&format!("app-{i}"));
+        }
+        let passed = (0..100)
+            .filter(|i| registry.may_contain_devbox(&format!("app-{i}")))
+            .count();
+        assert!(passed < 5, "{passed}");
+    }
+
+    #[test]
+    fn test_concurrent_registration_and_filter_rebuilds() {
+        let registry = Arc::new(DevboxRegistry::new());
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let registry = Arc::clone(&registry);
+                thread::spawn(move || {
+                    for i in 0..MIN_CAPACITY {
+                        let id = format!("t{t}-{i}");
+                        registry.register_devbox(id.clone(), "ns".to_string(), id.clone());
+                        assert!(registry.may_contain_devbox(&id));
+                        if i % 2 == 0 {
+                            registry.unregister_devbox(&id);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(registry.devbox_count(), 2 * MIN_CAPACITY);
+        for r in &registry.by_unique_id {
+            assert!(registry.may_contain_devbox(r.key()));
+        }
     }
 
     #[test]
     fn test_clear_pod_ip() {
         let registry = DevboxRegistry::new();
-        registry.update_pod_ip("ns-test", "devbox1", "10.0.0.1".to_string());
+        registry
+            .update_pod_ip("ns-test", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
 
         // Clear pod IP
-        registry.clear_pod_ip("ns-test", "devbox1");
+        registry.clear_pod_ip(DEFAULT_CLUSTER, "ns-test", "devbox1");
 
         let pod_ip = registry.get_pod_ip("ns-test", "devbox1");
         assert!(pod_ip.is_none());
@@ -216,7 +1992,9 @@ mod tests {
         );
 
         // Update pod IP (independent operation)
-        registry.update_pod_ip("ns-test", "devbox1", "10.0.0.1".to_string());
+        registry
+            .update_pod_ip("ns-test", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
 
         // Both should exist
         assert!(registry.get_devbox("unique-123").is_some());
@@ -234,7 +2012,7 @@ mod tests {
         );
 
         // Clear pod IP - devbox should still not exist
-        registry.clear_pod_ip("ns-test", "devbox1");
+        registry.clear_pod_ip(DEFAULT_CLUSTER, "ns-test", "devbox1");
         assert!(registry.get_pod_ip("ns-test", "devbox1").is_none());
     }
 
@@ -251,10 +2029,12 @@ mod tests {
             "ns-2".to_string(),
             "devbox2".to_string(),
         );
-        registry.update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string());
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
 
         assert_eq!(registry.devbox_count(), 2);
-        registry.clear_devboxes();
+        registry.clear_devboxes(DEFAULT_CLUSTER);
         assert_eq!(registry.devbox_count(), 0);
 
         // Pod IPs should be unaffected
@@ -264,6 +2044,104 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_max_entries() {
+        let registry = DevboxRegistry::new().with_max_entries(3);
+        let register = |i: usize| {
+            registry.register_devbox(format!("id-{i}"), "ns".to_string(), format!("devbox{i}"))
+        };
+        for i in 0..3 {
+            assert!(register(i));
+        }
+        assert!(!registry.is_full());
+
+        // Registrations past the cap are refused, whichever devbox they are for
+        for i in 3..10 {
+            assert!(!register(i));
+            assert!(registry.get_devbox(&format!("id-{i}")).is_none());
+        }
+        assert!(registry.is_full());
+        let usage = registry.usage();
+        assert_eq!((usage.entries, usage.max_entries, usage.full), (3, 3, true));
+        assert_eq!(registry.devbox_count(), 3);
+
+        // Registered devboxes are still served and updated
+        for i in 0..3 {
+            assert_eq!(
+                registry.get_devbox(&format!("id-{i}")).unwrap().devbox_name,
+                format!("devbox{i}")
+            );
+            assert!(registry.may_contain_devbox(&format!("id-{i}")));
+        }
+        assert!(!register(0));
+        assert!(registry.is_full());
+
+        // Room is made by unregistering
+        assert!(registry.unregister_devbox("id-0"));
+        assert!(!registry.is_full());
+        assert!(register(3));
+        assert!(!register(4));
+        registry.clear_devboxes(DEFAULT_CLUSTER);
+        assert_eq!(registry.usage().entries, 0);
+        assert!(!registry.is_full());
+        assert!(register(4));
+    }
+
+    #[test]
+    fn test_max_entries_refusals_counted() {
+        let refused = || {
+            metrics::REGISTRY_REFUSED_TOTAL
+                .with_label_values(&["capped"])
+                .get()
+        };
+        let registry = DevboxRegistry::new().with_max_entries(1);
+        let register = |i: usize| {
+            registry.register_devbox_info(
+                format!("id-{i}"),
+                DevboxInfo::in_cluster(Arc::from("capped"), "ns".to_string(), format!("devbox{i}")),
+            )
+        };
+        assert!(register(0));
+        assert_eq!(refused(), 0);
+        for i in 1..4 {
+            assert!(!register(i));
+            assert_eq!(refused(), i as u64);
+        }
+    }
+
+    #[test]
+    fn test_max_entries_zero_is_unlimited() {
+        let registry = DevboxRegistry::new().with_max_entries(0);
+        for i in 0..1_000 {
+            assert!(registry.register_devbox(
+                format!("id-{i}"),
+                "ns".to_string(),
+                format!("devbox{i}")
+            ));
+        }
+        assert!(!registry.is_full());
+        assert_eq!(registry.usage().entries, 1_000);
+        registry.clear_devboxes(DEFAULT_CLUSTER);
+        assert!(!registry.is_full());
+    }
+
+    #[test]
+    fn test_approx_memory_bytes() {
+        let registry = DevboxRegistry::new();
+        let empty = registry.approx_memory_bytes();
+        registry.register_devbox(
+            "id-1".to_string(),
+            "ns-1".to_string(),
+            "devbox1".to_string(),
+        );
+        let one = registry.approx_memory_bytes();
+        assert!(one > empty);
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        assert!(registry.approx_memory_bytes() > one);
+    }
+
     #[test]
     fn test_clear_pod_ips() {
         let registry = DevboxRegistry::new();
@@ -272,17 +2150,259 @@ mod tests {
             "ns-1".to_string(),
             "devbox1".to_string(),
         );
-        registry.update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string());
-        registry.update_pod_ip("ns-2", "devbox2", "10.0.0.2".to_string());
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        registry
+            .update_pod_ip("ns-2", "devbox2", "10.0.0.2".to_string())
+            .unwrap();
 
         assert_eq!(registry.pod_ip_count(), 2);
-        registry.clear_pod_ips();
+        registry.clear_pod_ips(DEFAULT_CLUSTER);
         assert_eq!(registry.pod_ip_count(), 0);
 
         // Devboxes should be unaffected
         assert!(registry.get_devbox("id-1").is_some());
     }
 
+    #[test]
+    fn test_imported_entries() {
+        for respect in [false, true] {
+            let registry = DevboxRegistry::new().with_respect_imported(respect);
+            let info =
+                |devbox_name: &str| DevboxInfo::new("ns".to_string(), devbox_name.to_string());
+            assert!(registry.import_devbox("id-1".to_string(), info("devbox1")));
+            registry
+                .import_pod_ip(DEFAULT_CLUSTER, "ns", "devbox1", "10.0.0.1".to_string())
+                .unwrap();
+            assert_eq!(
+                registry.get_devbox("id-1").unwrap().source,
+                EntrySource::Import
+            );
+            assert!(registry
+                .import_pod_ip(DEFAULT_CLUSTER, "ns", "devbox1", "0.0.0.0".to_string())
+                .is_err());
+
+            // The watch names another Devbox and Pod IP
+            registry.register_devbox_info("id-1".to_string(), info("devbox2"));
+            registry
+                .update_pod_ip("ns", "devbox1", "10.0.0.2".to_string())
+                .unwrap();
+            let (expected_name, expected_ip) = if respect {
+                ("devbox1", "10.0.0.1")
+            } else {
+                ("devbox2", "10.0.0.2")
+            };
+            assert_eq!(
+                registry.get_devbox("id-1").unwrap().devbox_name,
+                expected_name,
+                "respect: {respect}"
+            );
+            assert_eq!(
+                registry.get_pod_ip("ns", "devbox1").as_deref(),
+                Some(expected_ip),
+                "respect: {respect}"
+            );
+
+            // Relists only remove the watcher's entries if imports are respected
+            registry.clear_devboxes(DEFAULT_CLUSTER);
+            registry.clear_pod_ips(DEFAULT_CLUSTER);
+            assert_eq!(registry.devbox_count(), usize::from(respect));
+            assert_eq!(registry.pod_ip_count(), usize::from(respect));
+
+            registry.clear_all();
+            assert_eq!(registry.devbox_count(), 0);
+            assert_eq!(registry.pod_ip_count(), 0);
+            assert!(!registry.may_contain_devbox("id-1"));
+        }
+    }
+
+    /// Records notifications as readable strings
+    #[derive(Default)]
+    struct RecordingObserver(Mutex<Vec<String>>);
+
+    impl RegistryObserver for RecordingObserver {
+        fn devbox_unregistered(&self, unique_id: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("unregistered {unique_id}"));
+        }
+
+        fn endpoint_changed(&self, cluster: &str, namespace: &str, devbox_name: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("endpoint {cluster}/{namespace}/{devbox_name}"));
+        }
+    }
+
+    impl RecordingObserver {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    #[test]
+    fn test_sync_state() {
+        let registry = DevboxRegistry::new();
+        let grace = Duration::from_secs(10);
+        let later = |by| Instant::now() + by;
+
+        // No watchers
+        assert_eq!(registry.sync_state(), SyncState::Synced);
+        assert!(!registry.possibly_incomplete(grace));
+
+        registry.add_cluster(DEFAULT_CLUSTER);
+        assert_eq!(registry.sync_state(), SyncState::Synced);
+
+        let before = Instant::now();
+        registry.record_watch_init(DEFAULT_CLUSTER, WatchKind::Devboxes);
+        let SyncState::Syncing { since } = registry.sync_state() else {
+            panic!("not syncing");
+        };
+        assert!(since >= before);
+        assert!(registry.possibly_incomplete_at(grace, later(grace * 2)));
+
+        // Restarting the list keeps its start
+        registry.record_watch_init(DEFAULT_CLUSTER, WatchKind::Devboxes);
+        assert_eq!(registry.sync_state(), SyncState::Syncing { since });
+
+        registry.record_watch_init(DEFAULT_CLUSTER, WatchKind::Pods);
+        registry.record_watch_synced(DEFAULT_CLUSTER, WatchKind::Devboxes);
+        assert!(matches!(registry.sync_state(), SyncState::Syncing { .. }));
+
+        registry.record_watch_synced(DEFAULT_CLUSTER, WatchKind::Pods);
+        assert_eq!(registry.sync_state(), SyncState::Synced);
+        // Within the grace period after the list, then not
+        assert!(registry.possibly_incomplete(grace));
+        assert!(!registry.possibly_incomplete_at(grace, later(grace)));
+        assert!(!registry.possibly_incomplete(Duration::ZERO));
+
+        // A relist after a reconnect
+        registry.record_watch_init(DEFAULT_CLUSTER, WatchKind::Pods);
+        let SyncState::Syncing { since: relisting } = registry.sync_state() else {
+            panic!("not syncing");
+        };
+        assert!(relisting >= since);
+        registry.record_watch_synced(DEFAULT_CLUSTER, WatchKind::Pods);
+        assert!(registry.possibly_incomplete(grace));
+        assert!(!registry.possibly_incomplete_at(grace, later(grace)));
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        let registry = DevboxRegistry::new();
+        assert_eq!(registry.bookmark("hzh", WatchKind::Devboxes, None), None);
+
+        registry.record_bookmark("hzh", WatchKind::Devboxes, None, "100");
+        registry.record_bookmark("hzh", WatchKind::Pods, Some("ns-a"), "90");
+        registry.record_bookmark("hzh", WatchKind::Devboxes, None, "120");
+        assert_eq!(
+            registry
+                .bookmark("hzh", WatchKind::Devboxes, None)
+                .as_deref(),
+            Some("120")
+        );
+        // Scopes and kinds are apart
+        assert_eq!(registry.bookmark("hzh", WatchKind::Pods, None), None);
+        assert_eq!(registry.bookmark("bja", WatchKind::Devboxes, None), None);
+        assert_eq!(
+            registry.bookmarks(),
+            vec![
+                Bookmark {
+                    cluster: "hzh".to_string(),
+                    kind: WatchKind::Devboxes,
+                    namespace: None,
+                    resource_version: "120".to_string(),
+                },
+                Bookmark {
+                    cluster: "hzh".to_string(),
+                    kind: WatchKind::Pods,
+                    namespace: Some("ns-a".to_string()),
+                    resource_version: "90".to_string(),
+                },
+            ]
+        );
+
+        registry.forget_bookmark("hzh", WatchKind::Devboxes, None);
+        assert_eq!(registry.bookmark("hzh", WatchKind::Devboxes, None), None);
+        assert_eq!(registry.bookmarks().len(), 1);
+    }
+
+    #[test]
+    fn test_observer_notified() {
+        let registry = DevboxRegistry::new();
+        let observer = Arc::new(RecordingObserver::default());
+        registry.add_observer(Arc::clone(&observer) as Arc<dyn RegistryObserver>);
+
+        registry.register_devbox(
+            "id-1".to_string(),
+            "ns-1".to_string(),
+            "devbox1".to_string(),
+        );
+        registry.register_devbox(
+            "id-2".to_string(),
+            "ns-1".to_string(),
+            "devbox2".to_string(),
+        );
+        // New endpoints invalidate nothing
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        assert!(observer.take().is_empty());
+
+        registry
+            .update_pod_ip("ns-1", "devbox1", "10.0.0.2".to_string())
+            .unwrap();
+        registry.clear_pod_ip(DEFAULT_CLUSTER, "ns-1", "devbox1");
+        registry.clear_pod_ip(DEFAULT_CLUSTER, "ns-1", "devbox1");
+        assert_eq!(
+            observer.take(),
+            vec![
+                "endpoint default/ns-1/devbox1",
+                "endpoint default/ns-1/devbox1"
+            ]
+        );
+
+        registry
+            .update_pod_ip("ns-1", "devbox2", "10.0.0.3".to_string())
+            .unwrap();
+        let generation = registry
+            .get_pod_endpoint(DEFAULT_CLUSTER, "ns-1", "devbox2")
+            .unwrap()
+            .generation;
+        assert!(!registry.remove_pod_ip_if_generation(DEFAULT_CLUSTER, "ns-1", "devbox2", 0));
+        assert!(registry.remove_pod_ip_if_generation(
+            DEFAULT_CLUSTER,
+            "ns-1",
+            "devbox2",
+            generation
+        ));
+        registry
+            .update_pod_ip("ns-1", "devbox2", "10.0.0.3".to_string())
+            .unwrap();
+        registry.clear_pod_ips(DEFAULT_CLUSTER);
+        assert_eq!(
+            observer.take(),
+            vec![
+                "endpoint default/ns-1/devbox2",
+                "endpoint default/ns-1/devbox2"
+            ]
+        );
+
+        registry.unregister_devbox("id-1");
+        registry.unregister_devbox("id-1");
+        registry.clear_devboxes(DEFAULT_CLUSTER);
+        assert_eq!(
+            observer.take(),
+            vec!["unregistered id-1", "unregistered id-2"]
+        );
+    }
+
     #[test]
     fn test_concurrent_devbox_writes() {
         let registry = Arc::new(DevboxRegistry::new());
@@ -322,7 +2442,8 @@ mod tests {
                     &format!("ns-{i}"),
                     &format!("devbox-{i}"),
                     format!("10.0.0.{i}"),
-                );
+                )
+                .unwrap();
             }));
         }
 
@@ -361,7 +2482,8 @@ mod tests {
                     &format!("ns-{i}"),
                     &format!("devbox-{i}"),
                     format!("10.0.0.{i}"),
-                );
+                )
+                .unwrap();
             }));
         }
 
@@ -0,0 +1,121 @@
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::StatusCode;
+use pingora_http::ResponseHeader;
+
+/// Content type of Server-Sent Events, exempt from the limit by default
+pub const EVENT_STREAM: &str = "text/event-stream";
+
+/// How the body of a devbox response is limited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseLimit {
+    /// No limit applies to the response
+    Unlimited,
+    /// Count the streamed body against this many bytes
+    Limit(u64),
+    /// The declared `Content-Length` is already over the limit
+    TooLarge,
+}
+
+/// Decide the body limit of a devbox response.
+///
+/// Upgraded connections (WebSocket) are never limited, and neither are
+/// responses whose media type starts with one of `exempt_types`, so
+/// long-lived streams like SSE aren't cut off. Responses declaring a
+/// `Content-Length` over `max_body_bytes` are rejected before their headers
+/// are sent; others are counted as their body streams.
+pub fn evaluate(
+    resp: &ResponseHeader,
+    max_body_bytes: Option<u64>,
+    exempt_types: &[String],
+) -> ResponseLimit {
+    let Some(max) = max_body_bytes else {
+        return ResponseLimit::Unlimited;
+    };
+    if resp.status == StatusCode::SWITCHING_PROTOCOLS {
+        return ResponseLimit::Unlimited;
+    }
+
+    let media_type = resp
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or(v).trim().to_ascii_lowercase());
+    if media_type.is_some_and(|media_type| {
+        exempt_types
+            .iter()
+            .any(|exempt| media_type.starts_with(exempt.as_str()))
+    }) {
+        return ResponseLimit::Unlimited;
+    }
+
+    let content_length = resp
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > max) {
+        ResponseLimit::TooLarge
+    } else {
+        ResponseLimit::Limit(max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, headers: &[(&'static str, &str)]) -> ResponseHeader {
+        let mut resp = ResponseHeader::build(status, None).unwrap();
+        for (name, value) in headers {
+            resp.insert_header(*name, *value).unwrap();
+        }
+        resp
+    }
+
+    fn exempt() -> Vec<String> {
+        vec![EVENT_STREAM.to_string(), "video/".to_string()]
+    }
+
+    #[test]
+    fn test_limit() {
+        let resp = response(200, &[("content-type", "text/html")]);
+        assert_eq!(evaluate(&resp, None, &exempt()), ResponseLimit::Unlimited);
+        assert_eq!(
+            evaluate(&resp, Some(1024), &exempt()),
+            ResponseLimit::Limit(1024)
+        );
+
+        let resp = response(200, &[("content-length", "1024")]);
+        assert_eq!(
+            evaluate(&resp, Some(1024), &exempt()),
+            ResponseLimit::Limit(1024)
+        );
+        let resp = response(200, &[("content-length", "1025")]);
+        assert_eq!(
+            evaluate(&resp, Some(1024), &exempt()),
+            ResponseLimit::TooLarge
+        );
+    }
+
+    #[test]
+    fn test_exempt() {
+        for headers in [
+            &[("content-type", "text/event-stream")][..],
+            &[("content-type", "Text/Event-Stream; charset=utf-8")],
+            &[("content-type", "video/mp4"), ("content-length", "4096")],
+        ] {
+            let resp = response(200, headers);
+            assert_eq!(
+                evaluate(&resp, Some(1024), &exempt()),
+                ResponseLimit::Unlimited,
+                "{headers:?}"
+            );
+        }
+        let resp = response(101, &[("upgrade", "websocket")]);
+        assert_eq!(evaluate(&resp, Some(1024), &[]), ResponseLimit::Unlimited);
+
+        // Only exempt when configured
+        let resp = response(200, &[("content-type", "text/event-stream")]);
+        assert_eq!(evaluate(&resp, Some(1024), &[]), ResponseLimit::Limit(1024));
+    }
+}
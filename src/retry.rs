@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use http::Method;
+use pingora_http::RequestHeader;
+
+use crate::config::RetryPolicy;
+
+/// Header clients use to mark a request as safe to replay
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// Whether a request whose upstream connection failed may be retried.
+///
+/// Only idempotent methods are retried by default, since replaying e.g. a
+/// POST can duplicate its side effects.
+pub fn is_retryable(req: &RequestHeader, policy: RetryPolicy) -> bool {
+    match policy {
+        RetryPolicy::All => true,
+        RetryPolicy::Idempotent => is_idempotent(&req.method),
+        RetryPolicy::IdempotencyKey => {
+            is_idempotent(&req.method) || req.headers.contains_key(IDEMPOTENCY_KEY)
+        }
+    }
+}
+
+/// Whether a request whose devbox failed before sending response headers
+/// may be retried against the devbox's replacement Pod.
+///
+/// Only bodiless GET and HEAD requests fail over, at most once, and only
+/// within `budget` of the request arriving, so a client isn't left waiting
+/// on a devbox that keeps failing.
+pub fn can_fail_over(
+    method: &Method,
+    failovers: usize,
+    elapsed: Duration,
+    budget: Option<Duration>,
+) -> bool {
+    matches!(*method, Method::GET | Method::HEAD)
+        && failovers == 0
+        && budget.is_some_and(|budget| elapsed < budget)
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, headers: &[(&'static str, &str)]) -> RequestHeader {
+        let mut req = RequestHeader::build(method, b"/", None).unwrap();
+        for (name, value) in headers {
+            req.insert_header(*name, *value).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn test_default_retries_idempotent_only() {
+        let policy = RetryPolicy::default();
+        for method in ["GET", "HEAD", "PUT", "DELETE", "OPTIONS"] {
+            assert!(is_retryable(&request(method, &[]), policy), "{method}");
+        }
+        assert!(!is_retryable(&request("POST", &[]), policy));
+        assert!(!is_retryable(&request("PATCH", &[]), policy));
+
+        // The idempotency key is ignored unless configured
+        let req = request("POST", &[("idempotency-key", "abc")]);
+        assert!(!is_retryable(&req, policy));
+    }
+
+    #[test]
+    fn test_retry_all() {
+        assert!(is_retryable(&request("POST", &[]), RetryPolicy::All));
+        assert!(is_retryable(&request("PATCH", &[]), RetryPolicy::All));
+    }
+
+    #[test]
+    fn test_retry_with_idempotency_key() {
+        let policy = RetryPolicy::IdempotencyKey;
+        assert!(is_retryable(&request("GET", &[]), policy));
+        assert!(!is_retryable(&request("POST", &[]), policy));
+        assert!(is_retryable(
+            &request("POST", &[("Idempotency-Key", "abc")]),
+            policy
+        ));
+    }
+
+    #[test]
+    fn test_fail_over() {
+        let budget = Some(Duration::from_secs(5));
+        let soon = Duration::from_secs(1);
+        assert!(can_fail_over(&Method::GET, 0, soon, budget));
+        assert!(can_fail_over(&Method::HEAD, 0, soon, budget));
+
+        // Other methods may have had side effects, even idempotent ones
+        assert!(!can_fail_over(&Method::PUT, 0, soon, budget));
+        assert!(!can_fail_over(&Method::POST, 0, soon, budget));
+
+        // Once only, within the budget
+        assert!(!can_fail_over(&Method::GET, 1, soon, budget));
+        assert!(!can_fail_over(
+            &Method::GET,
+            0,
+            Duration::from_secs(5),
+            budget
+        ));
+        assert!(!can_fail_over(&Method::GET, 0, soon, None));
+    }
+}
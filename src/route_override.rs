@@ -0,0 +1,291 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use hmac::{Hmac, Mac};
+use http::{HeaderMap, HeaderName};
+use pingora_http::RequestHeader;
+use sha2::Sha256;
+
+/// Header naming the backend a request is routed to, as
+/// `<namespace>/<pod IP>:<port>` (`[<pod IP>]:<port>` for IPv6)
+pub const ROUTE_HEADER: HeaderName = HeaderName::from_static("x-hg-route");
+
+/// Header signing [`ROUTE_HEADER`], as `<timestamp>.<signature>`
+pub const SIGNATURE_HEADER: HeaderName = HeaderName::from_static("x-hg-route-signature");
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Why a route override was not accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverrideError {
+    /// No signature header (or overrides are disabled)
+    Unsigned,
+    /// Not of the form `<timestamp>.<signature>` or
+    /// `<namespace>/<pod IP>:<port>`
+    Malformed,
+    /// The signature does not match the route and timestamp
+    BadSignature,
+    /// The timestamp is further from now than the allowed age
+    Expired,
+}
+
+impl OverrideError {
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Unsigned => "unsigned",
+            Self::Malformed => "malformed",
+            Self::BadSignature => "bad_signature",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+impl fmt::Display for OverrideError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Unsigned => "unsigned route override",
+            Self::Malformed => "malformed route override",
+            Self::BadSignature => "invalid route override signature",
+            Self::Expired => "route override expired",
+        })
+    }
+}
+
+/// A backend picked by the control plane instead of the registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteOverride {
+    pub namespace: String,
+    pub ip: IpAddr,
+    pub port: u16,
+}
+
+impl RouteOverride {
+    fn parse(route: &str) -> Option<Self> {
+        let (namespace, addr) = route.split_once('/')?;
+        let valid_namespace = (1..=63).contains(&namespace.len())
+            && namespace
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+            && !namespace.starts_with('-')
+            && !namespace.ends_with('-');
+        let addr: SocketAddr = addr.parse().ok()?;
+        (valid_namespace && addr.port() != 0).then(|| Self {
+            namespace: namespace.to_string(),
+            ip: addr.ip(),
+            port: addr.port(),
+        })
+    }
+}
+
+/// Signs and verifies route overrides for platform-internal calls.
+///
+/// The signature is `<timestamp>.<hex HMAC-SHA256>` over the route and the
+/// timestamp (Unix seconds), keyed with `ROUTE_OVERRIDE_KEY`. It is only
+/// accepted within `max_age_secs` of the timestamp, so a captured override
+/// can't be replayed for long.
+pub struct RouteSigner {
+    key: Vec<u8>,
+    max_age_secs: u64,
+}
+
+impl RouteSigner {
+    pub fn new(key: impl Into<Vec<u8>>, max_age_secs: u64) -> Self {
+        Self {
+            key: key.into(),
+            max_age_secs,
+        }
+    }
+
+    fn mac(&self, route: &str, timestamp: u64) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(format!("{route}\n{timestamp}").as_bytes());
+        mac
+    }
+
+    /// Sign `route` at `timestamp`.
+    pub fn sign(&self, route: &str, timestamp: u64) -> String {
+        let signature = self.mac(route, timestamp).finalize().into_bytes();
+        format!("{timestamp}.{}", hex::encode(signature))
+    }
+
+    /// Verify a signed `route` at `now`, returning the backend it names.
+    pub fn verify(
+        &self,
+        route: &str,
+        signature: &str,
+        now: u64,
+    ) -> Result<RouteOverride, OverrideError> {
+        let (timestamp, signature) = signature.split_once('.').ok_or(OverrideError::Malformed)?;
+        let timestamp: u64 = timestamp.parse().map_err(|_| OverrideError::Malformed)?;
+        let signature = hex::decode(signature).map_err(|_| OverrideError::Malformed)?;
+
+        // Constant-time comparison, and before the timestamp so an expired
+        // signature can't be told apart from a forged one
+        self.mac(route, timestamp)
+            .verify_slice(&signature)
+            .map_err(|_| OverrideError::BadSignature)?;
+        if now.abs_diff(timestamp) > self.max_age_secs {
+            return Err(OverrideError::Expired);
+        }
+        RouteOverride::parse(route).ok_or(OverrideError::Malformed)
+    }
+
+    /// The route override of a request, if it has one.
+    pub fn from_headers(
+        signer: Option<&Self>,
+        headers: &HeaderMap,
+        now: u64,
+    ) -> Option<Result<RouteOverride, OverrideError>> {
+        let route = headers.get(ROUTE_HEADER)?;
+        let signature = headers.get(SIGNATURE_HEADER).zip(signer);
+        Some(match signature {
+            Some((signature, signer)) => match (route.to_str(), signature.to_str()) {
+                (Ok(route), Ok(signature)) => signer.verify(route, signature, now),
+                _ => Err(OverrideError::Malformed),
+            },
+            None => Err(OverrideError::Unsigned),
+        })
+    }
+}
+
+/// Remove the route override headers, valid or not, so they never reach a
+/// devbox.
+pub fn strip_headers(req: &mut RequestHeader) {
+    req.remove_header(&ROUTE_HEADER);
+    req.remove_header(&SIGNATURE_HEADER);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+    const ROUTE: &str = "ns-admin/10.0.0.7:8080";
+
+    fn signer() -> RouteSigner {
+        RouteSigner::new("route-key", 30)
+    }
+
+    fn headers(route: &str, signature: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ROUTE_HEADER, route.parse().unwrap());
+        if let Some(signature) = signature {
+            headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_verify() {
+        let signature = signer().sign(ROUTE, NOW);
+        let expected = RouteOverride {
+            namespace: "ns-admin".to_string(),
+            ip: "10.0.0.7".parse().unwrap(),
+            port: 8080,
+        };
+        assert_eq!(
+            signer().verify(ROUTE, &signature, NOW),
+            Ok(expected.clone())
+        );
+        // Within the allowed age, either way to allow for clock skew
+        assert_eq!(
+            signer().verify(ROUTE, &signature, NOW + 30),
+            Ok(expected.clone())
+        );
+        assert_eq!(signer().verify(ROUTE, &signature, NOW - 30), Ok(expected));
+
+        let route = "ns-admin/[fd00::7]:8443";
+        let parsed = signer()
+            .verify(route, &signer().sign(route, NOW), NOW)
+            .unwrap();
+        assert_eq!((parsed.ip, parsed.port), ("fd00::7".parse().unwrap(), 8443));
+    }
+
+    #[test]
+    fn test_verify_expired() {
+        let signature = signer().sign(ROUTE, NOW);
+        assert_eq!(
+            signer().verify(ROUTE, &signature, NOW + 31),
+            Err(OverrideError::Expired)
+        );
+        assert_eq!(
+            signer().verify(ROUTE, &signature, NOW - 31),
+            Err(OverrideError::Expired)
+        );
+    }
+
+    #[test]
+    fn test_verify_tampered() {
+        let signature = signer().sign(ROUTE, NOW);
+        for (route, signature) in [
+            // Another backend, or a later timestamp under the same signature
+            ("ns-admin/10.0.0.8:8080", signature.clone()),
+            (
+                ROUTE,
+                signature.replacen(&NOW.to_string(), &(NOW + 1).to_string(), 1),
+            ),
+            // Signed with another key
+            (ROUTE, RouteSigner::new("other-key", 30).sign(ROUTE, NOW)),
+        ] {
+            assert_eq!(
+                signer().verify(route, &signature, NOW),
+                Err(OverrideError::BadSignature),
+                "{route} {signature}"
+            );
+        }
+        for signature in ["", "abc", "1700000000", "1700000000.zz"] {
+            assert_eq!(
+                signer().verify(ROUTE, signature, NOW),
+                Err(OverrideError::Malformed)
+            );
+        }
+
+        // Signed but not a route
+        for route in [
+            "10.0.0.7:8080",
+            "ns/10.0.0.7",
+            "NS/10.0.0.7:80",
+            "ns/10.0.0.7:0",
+        ] {
+            let signature = signer().sign(route, NOW);
+            assert_eq!(
+                signer().verify(route, &signature, NOW),
+                Err(OverrideError::Malformed),
+                "{route}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_headers() {
+        let signer = signer();
+        let signature = signer.sign(ROUTE, NOW);
+        assert!(RouteSigner::from_headers(Some(&signer), &HeaderMap::new(), NOW).is_none());
+        assert!(matches!(
+            RouteSigner::from_headers(Some(&signer), &headers(ROUTE, Some(&signature)), NOW),
+            Some(Ok(_))
+        ));
+        assert_eq!(
+            RouteSigner::from_headers(Some(&signer), &headers(ROUTE, None), NOW),
+            Some(Err(OverrideError::Unsigned))
+        );
+        // Overrides are disabled without a key
+        assert_eq!(
+            RouteSigner::from_headers(None, &headers(ROUTE, Some(&signature)), NOW),
+            Some(Err(OverrideError::Unsigned))
+        );
+    }
+
+    #[test]
+    fn test_strip_headers() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header(ROUTE_HEADER, ROUTE).unwrap();
+        req.insert_header(SIGNATURE_HEADER, "1.ab").unwrap();
+        req.insert_header("accept", "*/*").unwrap();
+        strip_headers(&mut req);
+        assert!(req.headers.get(ROUTE_HEADER).is_none());
+        assert!(req.headers.get(SIGNATURE_HEADER).is_none());
+        assert_eq!(req.headers.get("accept").unwrap(), "*/*");
+    }
+}
@@ -0,0 +1,826 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use regex::Regex;
+use tracing::{debug, warn};
+
+use crate::blocklist::{BlockEntry, Blocklist};
+use crate::config::Config;
+use crate::registry::{DevboxInfo, DevboxRegistry, InvalidBackendAddr, PodEndpoint};
+use crate::self_addrs::SelfAddrs;
+
+/// Upstream protocol type based on host prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamProtocol {
+    /// HTTP/1.1 over cleartext (prefix: devbox-)
+    Http,
+    /// gRPC over HTTP/2 cleartext (prefix: devboxgrpc-)
+    Grpc,
+}
+
+/// Result of backend resolution
+pub enum BackendResult {
+    /// Backend resolved successfully with Pod endpoint
+    Ok(PodEndpoint, u16, DevboxInfo),
+    /// Devbox not registered (uniqueID not found)
+    NotFound,
+    /// Devbox registered but Pod is not running (no Pod IP)
+    NotRunning,
+    /// Devbox or its namespace is on the blocklist
+    Blocked(BlockEntry),
+    /// The Pod IP can't be a backend's (e.g. `0.0.0.0` during termination)
+    InvalidAddress(PodEndpoint, DevboxInfo, InvalidBackendAddr),
+}
+
+/// Result of matching a host against the router's domains and registry
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum HostRoute {
+    /// Devbox host of the domains: protocol, uniqueID and port
+    Devbox(UpstreamProtocol, String, u16),
+    /// Host outside the domains
+    Misdirected,
+    /// Host of the domains that names no known devbox
+    NotFound,
+}
+
+/// Where the gateway would send a request for a host, or why it wouldn't.
+///
+/// New variants and fields may be added in later releases; match with a
+/// wildcard arm and `..`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RouteDecision {
+    /// Proxied to `addr`
+    #[non_exhaustive]
+    Ok {
+        addr: SocketAddr,
+        devbox: RoutedDevbox,
+    },
+    /// The host names no devbox: it is outside the router's domains
+    /// (answered with 421) or not a devbox host of them (404)
+    #[non_exhaustive]
+    UnknownHost { outside_domains: bool },
+    /// The host names a devbox that isn't registered (404)
+    #[non_exhaustive]
+    NotRegistered { unique_id: String },
+    /// The devbox is registered but can't be reached (503)
+    #[non_exhaustive]
+    NotRunning {
+        unique_id: String,
+        reason: NotRunningReason,
+    },
+    /// The devbox is registered but the host's port isn't proxied: the
+    /// host is portless (`port` is `None`) and the devbox declares no app
+    /// port (404), or the backend is one of the gateway's own addresses
+    /// (508)
+    #[non_exhaustive]
+    PortNotAllowed {
+        unique_id: String,
+        port: Option<u16>,
+    },
+    /// The devbox or its namespace is on the blocklist (403 or 451)
+    #[non_exhaustive]
+    Blocked {
+        unique_id: String,
+        entry: BlockEntry,
+    },
+}
+
+/// The devbox a host is routed to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RoutedDevbox {
+    pub unique_id: String,
+    pub cluster: Arc<str>,
+    pub namespace: String,
+    pub name: String,
+    pub protocol: UpstreamProtocol,
+    /// Whether the backend port is proxied over TLS
+    pub tls: bool,
+}
+
+/// Why a registered devbox can't be reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NotRunningReason {
+    /// No running Pod, e.g. stopped or scaled to zero
+    NoPod,
+    /// The Pod IP can't be a backend's (e.g. `0.0.0.0` during termination)
+    InvalidAddress(InvalidBackendAddr),
+}
+
+impl fmt::Display for NotRunningReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoPod => f.write_str("no running Pod"),
+            Self::InvalidAddress(e) => write!(f, "invalid backend address: {e}"),
+        }
+    }
+}
+
+/// Picks the backend of request hosts, as the proxy does, without running
+/// it (e.g. for pre-flight checks in the dashboard).
+///
+/// Every [`crate::proxy::DevboxProxy`] routes through a `Router`, so a
+/// router built from the same registry, domains, blocklist and host scheme
+/// reaches the same decisions. Signed route overrides and the registry's
+/// initial sync, which depend on more than the host, are left to the proxy.
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use httpgate::registry::DevboxRegistry;
+/// use httpgate::routing::{RouteDecision, Router};
+///
+/// let registry = Arc::new(DevboxRegistry::new());
+/// registry.register_devbox("my-app".to_string(), "ns".to_string(), "devbox1".to_string());
+/// registry
+///     .update_pod_ip("ns", "devbox1", "10.0.0.5".to_string())
+///     .unwrap();
+///
+/// let router = Router::new(registry, vec!["devbox.example.com".to_string()]);
+/// match router.route("devbox-my-app-8080.devbox.example.com", "/") {
+///     RouteDecision::Ok { addr, devbox, .. } => {
+///         assert_eq!(addr.to_string(), "10.0.0.5:8080");
+///         assert_eq!(devbox.name, "devbox1");
+///     }
+///     other => panic!("unexpected decision: {other:?}"),
+/// }
+/// assert!(matches!(
+///     router.route("devbox-gone-8080.devbox.example.com", "/"),
+///     RouteDecision::NotRegistered { .. }
+/// ));
+/// assert!(matches!(
+///     router.route("devbox-my-app-8080.other.example.com", "/"),
+///     RouteDecision::UnknownHost { outside_domains: true, .. }
+/// ));
+/// ```
+pub struct Router {
+    registry: Arc<DevboxRegistry>,
+    /// Domains served; empty to serve any
+    domain_suffixes: Vec<String>,
+    blocklist: Arc<Blocklist>,
+    host_parser: HostParser,
+    /// Addresses of the gateway, never proxied to
+    self_addrs: SelfAddrs,
+    /// Route hosts as [`normalize_host`] leaves them
+    normalize_host: bool,
+}
+
+impl Router {
+    /// Route hosts one label below `domain_suffixes` (any host if empty)
+    /// under the default host scheme, with no blocklist.
+    pub fn new(registry: Arc<DevboxRegistry>, domain_suffixes: Vec<String>) -> Self {
+        Self {
+            registry,
+            domain_suffixes,
+            blocklist: Arc::new(Blocklist::from_config(&Config::default())),
+            host_parser: HostParser::Default,
+            self_addrs: SelfAddrs::default(),
+            normalize_host: true,
+        }
+    }
+
+    /// Route as a gateway running with `config` would: with its host
+    /// pattern, static blocklist entries and own addresses.
+    ///
+    /// The blocklist file isn't read; use [`Self::with_blocklist`] to share
+    /// a loaded one.
+    pub fn from_config(
+        registry: Arc<DevboxRegistry>,
+        config: &Config,
+        domain_suffixes: Vec<String>,
+    ) -> Self {
+        Self {
+            blocklist: Arc::new(Blocklist::from_config(config)),
+            host_parser: HostParser::from_config(config),
+            self_addrs: SelfAddrs::from_config(config),
+            normalize_host: config.normalize_host,
+            ..Self::new(registry, domain_suffixes)
+        }
+    }
+
+    /// Check hosts against a blocklist shared with other routers.
+    #[must_use]
+    pub fn with_blocklist(mut self, blocklist: Arc<Blocklist>) -> Self {
+        self.blocklist = blocklist;
+        self
+    }
+
+    /// Parse hosts with `host_parser` instead of the default scheme.
+    #[must_use]
+    pub fn with_host_parser(mut self, host_parser: HostParser) -> Self {
+        self.host_parser = host_parser;
+        self
+    }
+
+    /// Refuse backends at the gateway's own addresses.
+    #[must_use]
+    pub fn with_self_addrs(mut self, self_addrs: SelfAddrs) -> Self {
+        self.self_addrs = self_addrs;
+        self
+    }
+
+    pub fn host_parser(&self) -> &HostParser {
+        &self.host_parser
+    }
+
+    /// Decide where a request for `host` (with an optional port) goes.
+    ///
+    /// Backends are picked by host alone; `path` is taken so that routes by
+    /// path can be added without changing this signature. Blocked devboxes
+    /// are counted in the blocklist's metrics like blocked requests.
+    pub fn route(&self, host: &str, _path: &str) -> RouteDecision {
+        let host = self.normalized(host);
+        let (protocol, unique_id, port) = match self.route_host(host) {
+            HostRoute::Devbox(protocol, unique_id, port) => (protocol, unique_id, port),
+            HostRoute::Misdirected => {
+                return RouteDecision::UnknownHost {
+                    outside_domains: true,
+                }
+            }
+            HostRoute::NotFound => return self.explain_not_found(host),
+        };
+
+        match self.resolve(&unique_id, port) {
+            BackendResult::Ok(endpoint, port, info) => {
+                let Ok(ip) = endpoint.ip.parse::<IpAddr>() else {
+                    let reason = NotRunningReason::InvalidAddress(InvalidBackendAddr::NotAnIp);
+                    return RouteDecision::NotRunning { unique_id, reason };
+                };
+                if self.is_self(ip, port) {
+                    return RouteDecision::PortNotAllowed {
+                        unique_id,
+                        port: Some(port),
+                    };
+                }
+                let devbox = RoutedDevbox {
+                    tls: info.policy.uses_tls(port),
+                    unique_id,
+                    cluster: info.cluster,
+                    namespace: info.namespace,
+                    name: info.devbox_name,
+                    protocol,
+                };
+                RouteDecision::Ok {
+                    addr: SocketAddr::new(ip, port),
+                    devbox,
+                }
+            }
+            BackendResult::NotFound => RouteDecision::NotRegistered { unique_id },
+            BackendResult::NotRunning => RouteDecision::NotRunning {
+                unique_id,
+                reason: NotRunningReason::NoPod,
+            },
+            BackendResult::Blocked(entry) => RouteDecision::Blocked { unique_id, entry },
+            BackendResult::InvalidAddress(_, _, e) => RouteDecision::NotRunning {
+                unique_id,
+                reason: NotRunningReason::InvalidAddress(e),
+            },
+        }
+    }
+
+    /// Match `host` against the router's domains, then find the devbox it
+    /// names.
+    ///
+    /// The domain check comes first and has its own result, so requests for
+    /// other domains can be told apart from requests for unknown devboxes.
+    pub(crate) fn route_host(&self, host: &str) -> HostRoute {
+        let host = self.normalized(host);
+        // Only serve the configured domains
+        let host_without_port = host.split(':').next().unwrap_or(host);
+        if !matches_domain(&self.domain_suffixes, host_without_port) {
+            return HostRoute::Misdirected;
+        }
+
+        // Under the default scheme, uniqueIDs may end in digits
+        // ("outdoor-before-78648") that also read as a port. `<uniqueID>-<port>`
+        // hosts keep routing to their devbox when it exists, and only hosts
+        // naming no registered devbox that way are tried as portless ones.
+        let default_scheme = matches!(self.host_parser, HostParser::Default);
+        if default_scheme {
+            if let Some((protocol, unique_id, port)) = candidate_unique_id(host)
+                .filter(|candidate| self.registry.may_contain_devbox(candidate))
+                .and_then(|_| self.host_parser.parse(host))
+                .filter(|(_, unique_id, _)| self.registry.get_devbox(unique_id).is_some())
+            {
+                return HostRoute::Devbox(protocol, unique_id, port);
+            }
+            if let Some(route) = self.route_portless(host) {
+                return route;
+            }
+        }
+
+        // Reject hosts of unknown devboxes (mostly scanners) before the full
+        // parse and registry lookups. Unknown devboxes are never routed, so
+        // this skips the blocklist check, which only matters once they exist.
+        // Custom host patterns may put the uniqueID elsewhere.
+        if let (true, Some(candidate)) = (default_scheme, candidate_unique_id(host)) {
+            if !self.registry.may_contain_devbox(candidate) {
+                debug!(host = %host, "Devbox not found");
+                return HostRoute::NotFound;
+            }
+        }
+
+        // Parse protocol, uniqueID and port from host
+        match self.host_parser.parse(host) {
+            Some((protocol, unique_id, port)) => HostRoute::Devbox(protocol, unique_id, port),
+            None => {
+                if !default_scheme {
+                    if let Some(route) = self.route_portless(host) {
+                        return route;
+                    }
+                }
+                warn!(host = %host, "Failed to parse host header");
+                HostRoute::NotFound
+            }
+        }
+    }
+
+    /// `host` normalized (see [`normalize_host`]) unless `NORMALIZE_HOST`
+    /// is off or it can't be, in which case it is routed as sent.
+    fn normalized<'a>(&self, host: &'a str) -> &'a str {
+        if self.normalize_host {
+            normalize_host(host).unwrap_or(host)
+        } else {
+            host
+        }
+    }
+
+    /// Route a portless host to the app port its devbox declares.
+    ///
+    /// Returns `None` if the host's first label is no registered uniqueID,
+    /// and [`HostRoute::NotFound`] if the devbox declares no app port.
+    fn route_portless(&self, host: &str) -> Option<HostRoute> {
+        let (protocol, unique_id) = HostParser::parse_portless(host)?;
+        if !self.registry.may_contain_devbox(&unique_id) {
+            return None;
+        }
+        let info = self.registry.get_devbox(&unique_id)?;
+        Some(match info.app_port {
+            Some(port) => HostRoute::Devbox(protocol, unique_id, port),
+            None => {
+                debug!(host = %host, unique_id = %unique_id, "Devbox declares no app port");
+                HostRoute::NotFound
+            }
+        })
+    }
+
+    /// Why a host of the router's domains routes to no devbox: it is
+    /// portless and its devbox declares no app port, it names an
+    /// unregistered devbox, or it isn't a devbox host at all.
+    fn explain_not_found(&self, host: &str) -> RouteDecision {
+        if let Some((_, unique_id)) = HostParser::parse_portless(host) {
+            if self
+                .registry
+                .get_devbox(&unique_id)
+                .is_some_and(|info| info.app_port.is_none())
+            {
+                return RouteDecision::PortNotAllowed {
+                    unique_id,
+                    port: None,
+                };
+            }
+        }
+        match self.host_parser.parse(host) {
+            Some((_, unique_id, _)) => RouteDecision::NotRegistered { unique_id },
+            None => RouteDecision::UnknownHost {
+                outside_domains: false,
+            },
+        }
+    }
+
+    /// Resolve the backend of `unique_id` (see [`resolve_backend`]).
+    pub(crate) fn resolve(&self, unique_id: &str, port: u16) -> BackendResult {
+        resolve_backend(&self.registry, &self.blocklist, unique_id, port)
+    }
+
+    /// Whether connecting to `ip:port` reaches the gateway itself.
+    pub(crate) fn is_self(&self, ip: IpAddr, port: u16) -> bool {
+        self.self_addrs.is_self(ip, port)
+    }
+}
+
+/// Whether `host` (without a port) is exactly one label below one of
+/// `domain_suffixes`, e.g. `devbox-my-app-8080.devbox.sealos.io` for
+/// `devbox.sealos.io`. Any host matches if there are none.
+pub fn matches_domain(domain_suffixes: &[String], host: &str) -> bool {
+    if domain_suffixes.is_empty() {
+        return true;
+    }
+
+    domain_suffixes.iter().any(|suffix| {
+        host.strip_suffix(suffix.as_str())
+            .and_then(|h| h.strip_suffix('.'))
+            .is_some_and(|label| !label.is_empty() && !label.contains('.'))
+    })
+}
+
+/// The host a `Host` value names once whitespace around it is trimmed
+/// and only its first comma-separated value is kept, as some proxies send
+/// `id-8080.devbox.io, id-8080.devbox.io`.
+///
+/// Returns `None` if that leaves nothing, or a value with whitespace
+/// inside, which is no host at all.
+pub fn normalize_host(host: &str) -> Option<&str> {
+    let first = host.split(',').next()?.trim();
+    (!first.is_empty() && !first.contains(char::is_whitespace)).then_some(first)
+}
+
+/// Parse `host` under the default scheme, after [`normalize_host`].
+///
+/// Expected formats:
+/// - `devbox-<uniqueID>-<port>.xxx[:port]` -> HTTP
+/// - `devboxgrpc-<uniqueID>-<port>.xxx[:port]` -> gRPCs
+///
+/// Examples:
+/// - `devbox-outdoor-before-78648-8080.devbox.sealos.io` -> (Http, "outdoor-before-78648", 8080)
+/// - `devboxgrpc-my-app-50051.devbox.sealos.io` -> (Grpcs, "my-app", 50051)
+pub fn parse_host(host: &str) -> Option<(UpstreamProtocol, String, u16)> {
+    HostParser::Default.parse(normalize_host(host)?)
+}
+
+/// The uniqueID [`parse_host`] would extract from `host`, found without
+/// validating it. Only used to consult the registry's filter.
+pub fn candidate_unique_id(host: &str) -> Option<&str> {
+    let host_without_port = host.split(':').next().unwrap_or(host);
+    let stripped = host_without_port
+        .strip_prefix("devboxgrpc-")
+        .or_else(|| host_without_port.strip_prefix("devbox-"))?;
+    let (label, _) = stripped.split_once('.')?;
+    let (unique_id, _port) = label.rsplit_once('-')?;
+    Some(unique_id)
+}
+
+/// Regex equivalent of the default host scheme: <uniqueID>-<port>.xxx
+///
+/// Pattern: ^(<uniqueID>)-(<port>)\.
+/// - uniqueID: lowercase alphanumeric with hyphens, cannot start/end with hyphen
+/// - port: numeric
+///
+/// Note: Prefix (e.g., "devbox-", "devboxgrpc-") should be stripped before matching.
+///
+/// Examples (after prefix stripped):
+///   - "outdoor-before-78648-8080.devbox.xxx" -> ("outdoor-before-78648", 8080)
+///   - "my-app-8080.devbox.xxx" -> ("my-app", 8080)
+pub const DEFAULT_HOST_PATTERN: &str = r"^([a-z\d](?:[-a-z\d]*[a-z\d])?)-(\d+)\.";
+
+/// Extracts the protocol, uniqueID and port from request hosts.
+#[derive(Debug, Clone, Default)]
+pub enum HostParser {
+    /// `<uniqueID>-<port>.xxx`, parsed without a regex
+    #[default]
+    Default,
+    /// Custom regex capturing the uniqueID and port in its first two groups
+    Pattern(Regex),
+}
+
+impl HostParser {
+    /// The parser for `HOST_PATTERN`, or the default scheme if unset.
+    pub fn from_config(config: &Config) -> Self {
+        config
+            .host_pattern
+            .clone()
+            .map_or(Self::Default, Self::Pattern)
+    }
+
+    /// Parse `host` (see [`parse_host`]).
+    pub fn parse(&self, host: &str) -> Option<(UpstreamProtocol, String, u16)> {
+        let (protocol, host_stripped) = strip_protocol(host)?;
+
+        let (unique_id, port) = match self {
+            Self::Default => split_unique_id_port(host_stripped)?,
+            Self::Pattern(regex) => {
+                let caps = regex.captures(host_stripped)?;
+                (caps.get(1)?.as_str(), caps.get(2)?.as_str())
+            }
+        };
+        Some((protocol, unique_id.to_string(), port.parse().ok()?))
+    }
+
+    /// Parse a portless host, `<uniqueID>.xxx` after the protocol prefix,
+    /// into the protocol and uniqueID.
+    ///
+    /// Examples:
+    /// - `devbox-outdoor-before-78648.devbox.sealos.io` -> (Http, "outdoor-before-78648")
+    /// - `devboxgrpc-my-app.devbox.sealos.io` -> (Grpc, "my-app")
+    pub fn parse_portless(host: &str) -> Option<(UpstreamProtocol, String)> {
+        let (protocol, host_stripped) = strip_protocol(host)?;
+        let (unique_id, _) = host_stripped.split_once('.')?;
+        is_valid_unique_id(unique_id).then(|| (protocol, unique_id.to_string()))
+    }
+}
+
+/// Strip the port suffix and protocol prefix of `host` (e.g.
+/// `devboxgrpc-xxx:443` -> (Grpc, "xxx")).
+fn strip_protocol(host: &str) -> Option<(UpstreamProtocol, &str)> {
+    let host_without_port = host.split(':').next().unwrap_or(host);
+    if let Some(stripped) = host_without_port.strip_prefix("devboxgrpc-") {
+        Some((UpstreamProtocol::Grpc, stripped))
+    } else {
+        host_without_port
+            .strip_prefix("devbox-")
+            .map(|stripped| (UpstreamProtocol::Http, stripped))
+    }
+}
+
+/// Split `<uniqueID>-<port>.xxx` into uniqueID and port, matching
+/// [`DEFAULT_HOST_PATTERN`].
+///
+/// Neither part can contain a `.`, so the first label is split on its last
+/// `-`. Only ASCII digits are accepted where the regex's `\d` would also
+/// match other decimal digits, which never appear in uniqueIDs.
+fn split_unique_id_port(host: &str) -> Option<(&str, &str)> {
+    let (label, _) = host.split_once('.')?;
+    let (unique_id, port) = label.rsplit_once('-')?;
+
+    let valid_port = !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit());
+    (is_valid_unique_id(unique_id) && valid_port).then_some((unique_id, port))
+}
+
+/// Whether `unique_id` is lowercase alphanumeric with hyphens, neither
+/// starting nor ending with one.
+pub(crate) fn is_valid_unique_id(unique_id: &str) -> bool {
+    unique_id
+        .bytes()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !unique_id.is_empty()
+        && !unique_id.starts_with('-')
+        && !unique_id.ends_with('-')
+}
+
+/// Resolve the backend address from uniqueID.
+///
+/// Shared by the proxy, the admin warmup endpoint and TCP passthrough.
+///
+/// Performs a two-step lookup:
+/// 1. uniqueID -> DevboxInfo (namespace, devbox_name)
+/// 2. namespace/devbox_name -> pod_ip
+///
+/// Returns:
+/// - `BackendResult::Blocked` if uniqueID or its namespace is blocked
+///   (checked before the devbox state, so blocked devboxes look the same
+///   whether or not they are running)
+/// - `BackendResult::Ok` if uniqueID is registered and Pod IP is available
+/// - `BackendResult::NotFound` if uniqueID is not registered
+/// - `BackendResult::NotRunning` if uniqueID is registered but Pod IP is not available
+/// - `BackendResult::InvalidAddress` if the Pod IP is loopback (unless
+///   allowed), unspecified, link-local or multicast
+pub fn resolve_backend(
+    registry: &DevboxRegistry,
+    blocklist: &Blocklist,
+    unique_id: &str,
+    port: u16,
+) -> BackendResult {
+    // Step 1: Look up devbox info
+    let info = registry.get_devbox(unique_id);
+
+    // Namespace blocks are checked per request, so they also cover
+    // devboxes registered after the block was added
+    let namespace = info.as_ref().map(|info| info.namespace.as_str());
+    if let Some(entry) = blocklist.check(unique_id, namespace) {
+        return BackendResult::Blocked(entry);
+    }
+
+    let Some(info) = info else {
+        return BackendResult::NotFound;
+    };
+
+    // Step 2: Look up pod IP
+    let Some(endpoint) =
+        registry.get_pod_endpoint(&info.cluster, &info.namespace, &info.devbox_name)
+    else {
+        return BackendResult::NotRunning;
+    };
+    // Entries predating the registry's own check, or from other sources
+    if let Err(e) = registry.check_backend_ip(&endpoint.ip) {
+        return BackendResult::InvalidAddress(endpoint, info, e);
+    }
+
+    debug!(
+        unique_id = %unique_id,
+        cluster = %info.cluster,
+        namespace = %info.namespace,
+        devbox_name = %info.devbox_name,
+        pod_ip = %endpoint.ip,
+        port = port,
+        "Resolved backend"
+    );
+
+    BackendResult::Ok(endpoint, port, info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::DEFAULT_CLUSTER;
+
+    #[test]
+    fn test_route() {
+        let registry = Arc::new(DevboxRegistry::new());
+        for (unique_id, name) in [
+            ("my-app", "devbox1"),
+            ("stopped", "devbox2"),
+            ("blocked", "devbox3"),
+            ("looped", "devbox4"),
+        ] {
+            registry.register_devbox(unique_id.to_string(), "ns".to_string(), name.to_string());
+        }
+        registry
+            .update_pod_ip("ns", "devbox1", "10.0.0.5".to_string())
+            .unwrap();
+        registry
+            .update_pod_ip("ns", "devbox3", "10.0.0.6".to_string())
+            .unwrap();
+        registry
+            .update_pod_ip("ns", "devbox4", "10.0.0.7".to_string())
+            .unwrap();
+        let config = Config {
+            blocked_unique_ids: vec!["blocked".to_string()],
+            ..Default::default()
+        };
+        let router =
+            Router::from_config(registry, &config, vec!["devbox.io".to_string()]).with_self_addrs(
+                SelfAddrs::new(vec!["10.0.0.7:8080".parse().unwrap()], Vec::new()),
+            );
+
+        assert_eq!(
+            router.route("devboxgrpc-my-app-50051.devbox.io:443", "/"),
+            RouteDecision::Ok {
+                addr: "10.0.0.5:50051".parse().unwrap(),
+                devbox: RoutedDevbox {
+                    unique_id: "my-app".to_string(),
+                    cluster: Arc::from(DEFAULT_CLUSTER),
+                    namespace: "ns".to_string(),
+                    name: "devbox1".to_string(),
+                    protocol: UpstreamProtocol::Grpc,
+                    tls: false,
+                },
+            }
+        );
+        assert_eq!(
+            router.route("devbox-my-app-8080.other.io", "/"),
+            RouteDecision::UnknownHost {
+                outside_domains: true
+            }
+        );
+        assert_eq!(
+            router.route("www.devbox.io", "/"),
+            RouteDecision::UnknownHost {
+                outside_domains: false
+            }
+        );
+        assert_eq!(
+            router.route("devbox-gone-8080.devbox.io", "/"),
+            RouteDecision::NotRegistered {
+                unique_id: "gone".to_string()
+            }
+        );
+        assert_eq!(
+            router.route("devbox-stopped-8080.devbox.io", "/"),
+            RouteDecision::NotRunning {
+                unique_id: "stopped".to_string(),
+                reason: NotRunningReason::NoPod,
+            }
+        );
+        assert_eq!(
+            router.route("devbox-blocked-8080.devbox.io", "/"),
+            RouteDecision::Blocked {
+                unique_id: "blocked".to_string(),
+                entry: BlockEntry::UniqueId("blocked".to_string()),
+            }
+        );
+        // Portless hosts of devboxes declaring no app port, and the
+        // gateway's own addresses
+        assert_eq!(
+            router.route("devbox-my-app.devbox.io", "/"),
+            RouteDecision::PortNotAllowed {
+                unique_id: "my-app".to_string(),
+                port: None,
+            }
+        );
+        assert_eq!(
+            router.route("devbox-looped-8080.devbox.io", "/"),
+            RouteDecision::PortNotAllowed {
+                unique_id: "looped".to_string(),
+                port: Some(8080),
+            }
+        );
+        assert!(matches!(
+            router.route("devbox-looped-3000.devbox.io", "/"),
+            RouteDecision::Ok { .. }
+        ));
+    }
+
+    #[test]
+    fn test_route_leaves_last_access() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "my-app".to_string(),
+            "ns".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns", "devbox1", "10.0.0.5".to_string())
+            .unwrap();
+        let router = Router::new(Arc::clone(&registry), vec!["devbox.io".to_string()]);
+
+        // A pre-flight check isn't traffic
+        assert!(matches!(
+            router.route("devbox-my-app-8080.devbox.io", "/"),
+            RouteDecision::Ok { .. }
+        ));
+        assert!(matches!(
+            router.resolve("my-app", 8080),
+            BackendResult::Ok(..)
+        ));
+        assert_eq!(registry.get_devbox("my-app").unwrap().last_access(), None);
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        for (host, expected) in [
+            ("id-8080.devbox.io", Some("id-8080.devbox.io")),
+            (
+                "devbox-id-8080.devbox.io, devbox-id-8080.devbox.io",
+                Some("devbox-id-8080.devbox.io"),
+            ),
+            (
+                "devbox-id-8080.devbox.io,other.io",
+                Some("devbox-id-8080.devbox.io"),
+            ),
+            (
+                "  devbox-id-8080.devbox.io:443\t",
+                Some("devbox-id-8080.devbox.io:443"),
+            ),
+            ("", None),
+            ("  ", None),
+            (", devbox-id-8080.devbox.io", None),
+            ("devbox-id 8080.devbox.io", None),
+        ] {
+            assert_eq!(normalize_host(host), expected, "{host:?}");
+        }
+
+        assert_eq!(
+            parse_host(" devbox-my-app-8080.devbox.io , devbox-my-app-8080.devbox.io"),
+            Some((UpstreamProtocol::Http, "my-app".to_string(), 8080))
+        );
+        for host in [
+            "devbox-my app-8080.devbox.io",
+            " , ",
+            "devbox-my-app-80x.devbox.io",
+        ] {
+            assert_eq!(parse_host(host), None, "{host:?}");
+        }
+    }
+
+    #[test]
+    fn test_route_normalized_host() {
+        let registry = Arc::new(DevboxRegistry::new());
+        registry.register_devbox(
+            "my-app".to_string(),
+            "ns".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns", "devbox1", "10.0.0.5".to_string())
+            .unwrap();
+        let domains = vec!["devbox.io".to_string()];
+        let padded = "  devbox-my-app-8080.devbox.io:443 ";
+        let repeated = "devbox-my-app-8080.devbox.io, devbox-my-app-8080.devbox.io";
+
+        let router = Router::new(Arc::clone(&registry), domains.clone());
+        for host in [padded, repeated] {
+            assert!(
+                matches!(router.route(host, "/"), RouteDecision::Ok { .. }),
+                "{host:?}"
+            );
+        }
+
+        // Routed as sent when turned off
+        let config = Config {
+            normalize_host: false,
+            ..Default::default()
+        };
+        let router = Router::from_config(registry, &config, domains);
+        for host in [padded, repeated] {
+            assert!(
+                !matches!(router.route(host, "/"), RouteDecision::Ok { .. }),
+                "{host:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches_domain() {
+        let suffixes = vec!["devbox.sealos.io".to_string()];
+        assert!(matches_domain(
+            &suffixes,
+            "devbox-my-app-8080.devbox.sealos.io"
+        ));
+        assert!(!matches_domain(&suffixes, "devbox.sealos.io"));
+        assert!(!matches_domain(&suffixes, "a.b.devbox.sealos.io"));
+        assert!(matches_domain(&[], "devbox-my-app-8080.other.io"));
+    }
+}
@@ -0,0 +1,115 @@
+use std::net::{IpAddr, SocketAddr};
+
+use crate::config::Config;
+
+/// Addresses the gateway itself is reachable at.
+///
+/// A devbox whose Pod IP and port are one of them (a misconfigured endpoint,
+/// or an IP recycled for the gateway's own Pod) would have its requests
+/// proxied back to the gateway, forever.
+#[derive(Debug, Clone, Default)]
+pub struct SelfAddrs {
+    /// Addresses the gateway's services are bound to
+    listeners: Vec<SocketAddr>,
+    /// Further IPs of the gateway (e.g. its Pod IP), reaching listeners
+    /// bound to the unspecified address
+    ips: Vec<IpAddr>,
+}
+
+impl SelfAddrs {
+    pub fn new(listeners: Vec<SocketAddr>, ips: Vec<IpAddr>) -> Self {
+        Self { listeners, ips }
+    }
+
+    /// Addresses of every service `config` enables, and the IPs in
+    /// `SELF_ADDRS`.
+    pub fn from_config(config: &Config) -> Self {
+        let listeners = config
+            .listeners
+            .iter()
+            .map(|l| l.listen_addr)
+            .chain(config.metrics_addr)
+            .chain(config.admin_addr)
+            .chain(config.grpc_health_addr)
+            .chain(config.tcp_passthrough_addr)
+            .collect();
+        Self::new(listeners, config.self_addrs.clone())
+    }
+
+    /// Whether connecting to `ip:port` reaches the gateway itself.
+    ///
+    /// Listeners bound to the unspecified address are reached through any
+    /// loopback address and the configured IPs.
+    pub fn is_self(&self, ip: IpAddr, port: u16) -> bool {
+        let ip = ip.to_canonical();
+        self.listeners.iter().any(|listener| {
+            listener.port() == port
+                && if listener.ip().is_unspecified() {
+                    ip.is_loopback() || ip.is_unspecified() || self.ips.contains(&ip)
+                } else {
+                    listener.ip() == ip
+                }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_is_self() {
+        let addrs = SelfAddrs::new(
+            vec![addr("0.0.0.0:8080"), addr("10.0.0.7:9090")],
+            vec![ip("10.0.0.7")],
+        );
+
+        for (backend_ip, port, expected) in [
+            // Wildcard listener
+            ("10.0.0.7", 8080, true),
+            ("127.0.0.1", 8080, true),
+            ("127.0.0.2", 8080, true),
+            ("::1", 8080, true),
+            ("::ffff:127.0.0.1", 8080, true),
+            ("0.0.0.0", 8080, true),
+            ("10.0.0.8", 8080, false),
+            // Listener bound to one IP
+            ("10.0.0.7", 9090, true),
+            ("127.0.0.1", 9090, false),
+            // Ports the gateway doesn't listen on
+            ("10.0.0.7", 3000, false),
+            ("127.0.0.1", 3000, false),
+        ] {
+            assert_eq!(
+                addrs.is_self(ip(backend_ip), port),
+                expected,
+                "{backend_ip}:{port}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = Config {
+            admin_addr: Some(addr("127.0.0.1:9901")),
+            self_addrs: vec![ip("10.0.0.7")],
+            ..Default::default()
+        };
+        let addrs = SelfAddrs::from_config(&config);
+        assert!(addrs.is_self(ip("10.0.0.7"), 8080));
+        assert!(addrs.is_self(ip("127.0.0.1"), 9901));
+        assert!(!addrs.is_self(ip("10.0.0.7"), 9901));
+        assert!(!addrs.is_self(ip("10.0.0.7"), 3000));
+
+        // Nothing is self without listeners
+        assert!(!SelfAddrs::default().is_self(ip("127.0.0.1"), 8080));
+    }
+}
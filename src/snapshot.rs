@@ -0,0 +1,626 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::error::Error;
+use crate::policy::{DevboxPolicy, ANNOTATIONS};
+use crate::preview::unix_now;
+use crate::registry::{Bookmark, DevboxInfo, DevboxRegistry, EntrySource};
+use crate::routing::is_valid_unique_id;
+
+/// Version of the export document written, and the only one imported
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Interval between saves of `REGISTRY_SNAPSHOT_FILE`
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Registry entries as a versioned JSON document, for standing up a spare
+/// gateway with known-good routes while the clusters are unreachable.
+///
+/// Holds what the registry routes with: each devbox's policy as the
+/// annotations it was built from, and the Pod IPs. Devbox phases and Pods
+/// aren't part of the registry, so they aren't exported.
+///
+/// Saved as `REGISTRY_SNAPSHOT_FILE`, the document also holds where each
+/// watch got to, so a restarted gateway resumes its watches instead of
+/// listing every Devbox and Pod again (see [`restore`]).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryExport {
+    pub version: u32,
+    /// When the document was written (Unix seconds)
+    #[serde(default)]
+    pub exported_at: u64,
+    #[serde(default)]
+    pub devboxes: Vec<ExportedDevbox>,
+    #[serde(default)]
+    pub pod_ips: Vec<ExportedPodIp>,
+    /// Where the watches had got to when the entries were read; older than
+    /// the entries, never newer
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bookmarks: Vec<Bookmark>,
+}
+
+/// One entry of the devbox index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedDevbox {
+    pub unique_id: String,
+    pub cluster: String,
+    pub namespace: String,
+    pub devbox_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_port: Option<u16>,
+    /// Gateway annotations of the Devbox, from which its policy is built
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    /// Where the exporting gateway got the entry; imports are always
+    /// [`EntrySource::Import`]
+    #[serde(default)]
+    pub source: EntrySource,
+}
+
+/// One entry of the Pod index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedPodIp {
+    pub cluster: String,
+    pub namespace: String,
+    pub devbox_name: String,
+    pub ip: String,
+    #[serde(default)]
+    pub source: EntrySource,
+}
+
+/// How an import treats the entries already registered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Add the document's entries, replacing those with the same keys
+    #[default]
+    Merge,
+    /// Clear the registry first, so it holds only the document's entries
+    Replace,
+}
+
+impl FromStr for ImportMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(Self::Merge),
+            "replace" => Ok(Self::Replace),
+            other => Err(format!("unknown import mode: {other}")),
+        }
+    }
+}
+
+/// Why a document can't be imported at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DocumentError {
+    /// Not JSON, or not shaped like a [`RegistryExport`]
+    Malformed(String),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(e) => write!(f, "malformed export document: {e}"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported export version {version} (expected {EXPORT_VERSION})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DocumentError {}
+
+/// Outcome of an import.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ImportReport {
+    pub devboxes: usize,
+    pub pod_ips: usize,
+    /// Entries that were skipped, and why
+    pub errors: Vec<EntryError>,
+}
+
+/// A skipped entry, by its position in the document (e.g. `devboxes[3]`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EntryError {
+    pub entry: String,
+    pub error: String,
+}
+
+/// Export every entry of `registry`.
+pub fn export(registry: &DevboxRegistry) -> RegistryExport {
+    // Read before the entries: a watch resumed from a bookmark replays the
+    // changes after it, which an entry read later may already hold, but
+    // would skip those after a bookmark newer than the entries
+    let bookmarks = registry.bookmarks();
+    let devboxes = registry
+        .devbox_entries()
+        .into_iter()
+        .map(|(unique_id, info)| ExportedDevbox {
+            unique_id,
+            cluster: info.cluster.to_string(),
+            namespace: info.namespace,
+            devbox_name: info.devbox_name,
+            app_port: info.app_port,
+            annotations: info.policy.annotations.clone(),
+            source: info.source,
+        })
+        .collect();
+    let pod_ips = registry
+        .pod_ip_entries()
+        .into_iter()
+        .map(|entry| ExportedPodIp {
+            cluster: entry.cluster,
+            namespace: entry.namespace,
+            devbox_name: entry.devbox_name,
+            ip: entry.ip,
+            source: entry.source,
+        })
+        .collect();
+    RegistryExport {
+        version: EXPORT_VERSION,
+        exported_at: unix_now(),
+        devboxes,
+        pod_ips,
+        bookmarks,
+    }
+}
+
+/// Parse an export document, rejecting other versions.
+pub fn parse(document: &[u8]) -> Result<RegistryExport, DocumentError> {
+    let export: RegistryExport =
+        serde_json::from_slice(document).map_err(|e| DocumentError::Malformed(e.to_string()))?;
+    if export.version != EXPORT_VERSION {
+        return Err(DocumentError::UnsupportedVersion(export.version));
+    }
+    Ok(export)
+}
+
+/// Import the entries of `export` into `registry`, marked as imported.
+///
+/// Every entry is validated on its own: invalid ones are reported and
+/// skipped, and the rest are still imported. With [`ImportMode::Replace`]
+/// the registry is cleared first, even if no entry is valid. Bookmarks are
+/// the exporting gateway's, so they are left out.
+pub fn import(
+    registry: &DevboxRegistry,
+    export: &RegistryExport,
+    mode: ImportMode,
+) -> ImportReport {
+    if mode == ImportMode::Replace {
+        registry.clear_all();
+    }
+
+    let report = load(registry, export, false);
+    info!(
+        ?mode,
+        devboxes = report.devboxes,
+        pod_ips = report.pod_ips,
+        errors = report.errors.len(),
+        "Registry entries imported"
+    );
+    report
+}
+
+/// Restore the entries of this gateway's own snapshot, as they were before
+/// a restart, into the empty `registry`.
+///
+/// Entries keep their sources, so watchers treat those they made as their
+/// own. The bookmarks are restored only if every entry was: a watch resumed
+/// from them never brings back an entry that went missing.
+pub fn restore(registry: &DevboxRegistry, export: &RegistryExport) -> ImportReport {
+    let report = load(registry, export, true);
+    let bookmarks = if report.errors.is_empty() {
+        export.bookmarks.len()
+    } else {
+        0
+    };
+    for bookmark in &export.bookmarks[..bookmarks] {
+        registry.record_bookmark(
+            &bookmark.cluster,
+            bookmark.kind,
+            bookmark.namespace.as_deref(),
+            &bookmark.resource_version,
+        );
+    }
+    info!(
+        devboxes = report.devboxes,
+        pod_ips = report.pod_ips,
+        bookmarks,
+        errors = report.errors.len(),
+        "Registry snapshot restored"
+    );
+    report
+}
+
+/// Add the valid entries of `export` to `registry`, as imported unless
+/// `keep_sources`.
+fn load(registry: &DevboxRegistry, export: &RegistryExport, keep_sources: bool) -> ImportReport {
+    let mut report = ImportReport::default();
+    let mut fail = |entry: String, error: String| report.errors.push(EntryError { entry, error });
+    let mut devboxes = 0;
+    for (i, devbox) in export.devboxes.iter().enumerate() {
+        let entry = format!("devboxes[{i}]");
+        if let Err(e) = validate_devbox(devbox) {
+            fail(entry, e);
+            continue;
+        }
+        let mut info = DevboxInfo::in_cluster(
+            Arc::from(devbox.cluster.as_str()),
+            devbox.namespace.clone(),
+            devbox.devbox_name.clone(),
+        );
+        info.policy = Arc::new(DevboxPolicy::from_annotations(&devbox.annotations));
+        info.app_port = devbox.app_port;
+        info.source = devbox.source;
+        let added = if keep_sources {
+            registry.restore_devbox(devbox.unique_id.clone(), info)
+        } else {
+            registry.import_devbox(devbox.unique_id.clone(), info)
+        };
+        if added {
+            devboxes += 1;
+        } else {
+            fail(entry, "registry is full".to_string());
+        }
+    }
+
+    let mut pod_ips = 0;
+    for (i, pod_ip) in export.pod_ips.iter().enumerate() {
+        let entry = format!("pod_ips[{i}]");
+        let source = if keep_sources {
+            pod_ip.source
+        } else {
+            EntrySource::Import
+        };
+        let imported = validate_names(&pod_ip.cluster, &pod_ip.namespace, &pod_ip.devbox_name)
+            .and_then(|()| {
+                registry
+                    .restore_pod_ip(
+                        &pod_ip.cluster,
+                        &pod_ip.namespace,
+                        &pod_ip.devbox_name,
+                        pod_ip.ip.clone(),
+                        source,
+                    )
+                    .map_err(|e| format!("invalid ip {:?}: {e}", pod_ip.ip))
+            });
+        match imported {
+            Ok(()) => pod_ips += 1,
+            Err(e) => fail(entry, e),
+        }
+    }
+
+    report.devboxes = devboxes;
+    report.pod_ips = pod_ips;
+    report
+}
+
+/// Read the snapshot at `path`; `None` if there is none yet.
+pub fn load_file(path: &str) -> Result<Option<RegistryExport>, Error> {
+    let document = match std::fs::read(path) {
+        Ok(document) => document,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(Error::Config(format!(
+                "Failed to read registry snapshot {path}: {e}"
+            )))
+        }
+    };
+    parse(&document)
+        .map(Some)
+        .map_err(|e| Error::Config(format!("Invalid registry snapshot {path}: {e}")))
+}
+
+/// Write a snapshot of `registry` to `path`, replacing the file atomically.
+pub fn save_file(registry: &DevboxRegistry, path: &str) -> Result<(), Error> {
+    let document = serde_json::to_vec(&export(registry))
+        .map_err(|e| Error::Config(format!("Failed to encode registry snapshot: {e}")))?;
+    let tmp = format!("{path}.tmp");
+    std::fs::write(&tmp, document)
+        .and_then(|()| std::fs::rename(&tmp, path))
+        .map_err(|e| Error::Config(format!("Failed to write registry snapshot {path}: {e}")))
+}
+
+/// Save a snapshot of `registry` to `path` every [`SAVE_INTERVAL`] forever.
+pub async fn persist(registry: Arc<DevboxRegistry>, path: String) {
+    let mut ticker = tokio::time::interval(SAVE_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = save_file(&registry, &path) {
+            warn!(error = %e, "Failed to save registry snapshot");
+        }
+    }
+}
+
+fn validate_devbox(devbox: &ExportedDevbox) -> Result<(), String> {
+    if !is_valid_unique_id(&devbox.unique_id) {
+        return Err(format!("invalid unique_id {:?}", devbox.unique_id));
+    }
+    validate_names(&devbox.cluster, &devbox.namespace, &devbox.devbox_name)?;
+    if devbox.app_port == Some(0) {
+        return Err("invalid app_port 0".to_string());
+    }
+    if let Some(key) = devbox
+        .annotations
+        .keys()
+        .find(|key| !ANNOTATIONS.contains(&key.as_str()))
+    {
+        return Err(format!("unknown annotation {key:?}"));
+    }
+    Ok(())
+}
+
+/// Check the names keying an entry: a cluster, and a namespace and devbox
+/// name as Kubernetes allows them (lowercase DNS labels and subdomains).
+fn validate_names(cluster: &str, namespace: &str, devbox_name: &str) -> Result<(), String> {
+    let is_name = |name: &str| {
+        !name.is_empty()
+            && name.len() <= 253
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || matches!(b, b'-' | b'.'))
+    };
+    if cluster.is_empty() {
+        Err("empty cluster".to_string())
+    } else if !is_name(namespace) {
+        Err(format!("invalid namespace {namespace:?}"))
+    } else if !is_name(devbox_name) {
+        Err(format!("invalid devbox_name {devbox_name:?}"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{ANNOTATION_CORS, ANNOTATION_TLS_PORTS};
+    use crate::registry::{WatchKind, DEFAULT_CLUSTER};
+
+    fn registry() -> DevboxRegistry {
+        let registry = DevboxRegistry::new();
+        let annotations = BTreeMap::from([
+            (ANNOTATION_TLS_PORTS.to_string(), "8443".to_string()),
+            (
+                ANNOTATION_CORS.to_string(),
+                "origins=https://app.example.com".to_string(),
+            ),
+        ]);
+        registry.register_devbox_info(
+            "app-1".to_string(),
+            DevboxInfo {
+                policy: Arc::new(DevboxPolicy::from_annotations(&annotations)),
+                app_port: Some(3000),
+                ..DevboxInfo::new("ns-a".to_string(), "devbox1".to_string())
+            },
+        );
+        registry.register_devbox_info(
+            "app-2".to_string(),
+            DevboxInfo::in_cluster(Arc::from("west"), "ns-b".to_string(), "devbox2".to_string()),
+        );
+        registry
+            .update_pod_ip("ns-a", "devbox1", "10.0.0.1".to_string())
+            .unwrap();
+        registry
+            .update_pod_endpoint("west", "ns-b", "devbox2", "10.1.0.2".to_string(), None)
+            .unwrap();
+        // A Pod whose Devbox isn't registered yet
+        registry
+            .update_pod_ip("ns-a", "devbox3", "10.0.0.3".to_string())
+            .unwrap();
+        registry
+    }
+
+    /// What routing sees of `unique_id`
+    fn lookup(
+        registry: &DevboxRegistry,
+        unique_id: &str,
+    ) -> Option<(String, Option<u16>, DevboxPolicy, Option<String>)> {
+        let info = registry.get_devbox(unique_id)?;
+        let ip = registry
+            .get_pod_endpoint(&info.cluster, &info.namespace, &info.devbox_name)
+            .map(|endpoint| endpoint.ip);
+        Some((
+            format!("{}/{}/{}", info.cluster, info.namespace, info.devbox_name),
+            info.app_port,
+            (*info.policy).clone(),
+            ip,
+        ))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let source = registry();
+        let document = serde_json::to_vec(&export(&source)).unwrap();
+
+        let target = DevboxRegistry::new();
+        let report = import(&target, &parse(&document).unwrap(), ImportMode::Replace);
+        assert_eq!(
+            report,
+            ImportReport {
+                devboxes: 2,
+                pod_ips: 3,
+                errors: Vec::new(),
+            }
+        );
+        for unique_id in ["app-1", "app-2"] {
+            assert!(lookup(&source, unique_id).is_some());
+            assert_eq!(
+                lookup(&target, unique_id),
+                lookup(&source, unique_id),
+                "{unique_id}"
+            );
+        }
+        assert_eq!(target.pod_ip_count(), source.pod_ip_count());
+        assert_eq!(
+            target.get_devbox("app-1").unwrap().source,
+            EntrySource::Import
+        );
+
+        // Exported again, only the sources differ
+        let mut again = export(&target);
+        for devbox in &mut again.devboxes {
+            assert_eq!(devbox.source, EntrySource::Import);
+            devbox.source = EntrySource::Watch;
+        }
+        for pod_ip in &mut again.pod_ips {
+            assert_eq!(pod_ip.source, EntrySource::Import);
+            pod_ip.source = EntrySource::Watch;
+        }
+        let first = export(&source);
+        assert_eq!(again.devboxes, first.devboxes);
+        assert_eq!(again.pod_ips, first.pod_ips);
+    }
+
+    #[test]
+    fn test_merge_and_replace() {
+        let export = export(&registry());
+        let target = DevboxRegistry::new();
+        target.register_devbox(
+            "kept".to_string(),
+            "ns-c".to_string(),
+            "devbox4".to_string(),
+        );
+
+        import(&target, &export, ImportMode::Merge);
+        assert_eq!(target.devbox_count(), 3);
+        assert!(target.get_devbox("kept").is_some());
+
+        import(&target, &export, ImportMode::Replace);
+        assert_eq!(target.devbox_count(), 2);
+        assert!(target.get_devbox("kept").is_none());
+        assert_eq!(target.pod_ip_count(), 3);
+        assert!(target
+            .get_pod_endpoint(DEFAULT_CLUSTER, "ns-a", "devbox1")
+            .is_some());
+    }
+
+    #[test]
+    fn test_invalid_entries_skipped() {
+        let mut export = export(&registry());
+        export.devboxes[0].unique_id = "Not Valid".to_string();
+        export.devboxes[1]
+            .annotations
+            .insert("devbox.sealos.io/unknown".to_string(), "1".to_string());
+        export.devboxes.push(ExportedDevbox {
+            unique_id: "app-4".to_string(),
+            cluster: DEFAULT_CLUSTER.to_string(),
+            namespace: "ns/a".to_string(),
+            devbox_name: "devbox4".to_string(),
+            app_port: None,
+            annotations: BTreeMap::new(),
+            source: EntrySource::Watch,
+        });
+        export.devboxes.push(ExportedDevbox {
+            unique_id: "app-5".to_string(),
+            namespace: "ns-a".to_string(),
+            devbox_name: "devbox5".to_string(),
+            ..export.devboxes[2].clone()
+        });
+        export.pod_ips[0].ip = "127.0.0.1".to_string();
+        export.pod_ips[1].devbox_name = String::new();
+
+        let target = DevboxRegistry::new();
+        let report = import(&target, &export, ImportMode::Merge);
+        assert_eq!((report.devboxes, report.pod_ips), (1, 1));
+        let errors: Vec<_> = report
+            .errors
+            .iter()
+            .map(|e| (e.entry.as_str(), e.error.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                ("devboxes[0]", "invalid unique_id \"Not Valid\""),
+                (
+                    "devboxes[1]",
+                    "unknown annotation \"devbox.sealos.io/unknown\""
+                ),
+                ("devboxes[2]", "invalid namespace \"ns/a\""),
+                ("pod_ips[0]", "invalid ip \"127.0.0.1\": loopback address"),
+                ("pod_ips[1]", "invalid devbox_name \"\""),
+            ]
+        );
+        assert!(target.get_devbox("app-5").is_some());
+    }
+
+    #[test]
+    fn test_full_registry_reported() {
+        let target = DevboxRegistry::new().with_max_entries(1);
+        let report = import(&target, &export(&registry()), ImportMode::Merge);
+        assert_eq!(report.devboxes, 1);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].error, "registry is full");
+    }
+
+    #[test]
+    fn test_restore_keeps_sources_and_bookmarks() {
+        let source = registry();
+        source.record_bookmark(DEFAULT_CLUSTER, WatchKind::Devboxes, None, "100");
+        source.record_bookmark("west", WatchKind::Pods, Some("ns-b"), "42");
+        let saved = export(&source);
+
+        let target = DevboxRegistry::new();
+        assert!(restore(&target, &saved).errors.is_empty());
+        assert_eq!(
+            target.get_devbox("app-1").unwrap().source,
+            EntrySource::Watch
+        );
+        assert_eq!(target.bookmarks(), source.bookmarks());
+
+        // The importing gateway's watches are its own
+        let imported = DevboxRegistry::new();
+        import(&imported, &saved, ImportMode::Merge);
+        assert!(imported.bookmarks().is_empty());
+
+        // A watch resumed over a missing entry would never bring it back
+        let full = DevboxRegistry::new().with_max_entries(1);
+        assert_eq!(restore(&full, &saved).errors.len(), 1);
+        assert!(full.bookmarks().is_empty());
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_documents() {
+        for document in [
+            &b"not json"[..],
+            b"[]",
+            b"{}",
+            br#"{"version": 1, "devboxes": {}}"#,
+            br#"{"version": 1, "devboxes": [{"unique_id": "app-1"}]}"#,
+            br#"{"version": 1, "pod_ips": [{"cluster": "default", "ip": 1}]}"#,
+            br#"{"version": 1, "devboxes": [{"unique_id": "app-1", "cluster": "default",
+                "namespace": "ns", "devbox_name": "devbox1", "app_port": 70000}]}"#,
+            br#"{"version": 1, "devboxes": [{"unique_id": "app-1", "cluster": "default",
+                "namespace": "ns", "devbox_name": "devbox1", "source": "elsewhere"}]}"#,
+        ] {
+            assert!(
+                matches!(parse(document), Err(DocumentError::Malformed(_))),
+                "{}",
+                String::from_utf8_lossy(document)
+            );
+        }
+        assert_eq!(
+            parse(br#"{"version": 2}"#),
+            Err(DocumentError::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            parse(br#"{"version": 1}"#).unwrap(),
+            RegistryExport {
+                version: 1,
+                exported_at: 0,
+                devboxes: Vec::new(),
+                pod_ips: Vec::new(),
+                bookmarks: Vec::new(),
+            }
+        );
+    }
+}
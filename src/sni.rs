@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tracing::debug;
+
+use crate::error::{Error, Result};
+use crate::tls_reload::ServerCert;
+
+/// Base name of the certificate served when no other one matches
+pub const DEFAULT_CERT_NAME: &str = "default";
+
+/// Certificates of a listener terminating TLS for several domains, picked
+/// by the server name (SNI) of each handshake.
+///
+/// Certificates serve the DNS names of their subjectAltNames. Exact names
+/// take precedence over wildcards (`*.example.com`, matching exactly one
+/// label), and clients sending no or an unknown name get the default
+/// certificate.
+#[derive(Debug, Default)]
+pub struct SniCerts {
+    exact: HashMap<String, Arc<CertifiedKey>>,
+    /// Wildcard certificates, by the suffix after `*.`
+    wildcard: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl SniCerts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `cert` for its names not served by an earlier certificate. The
+    /// first certificate added is the default one.
+    pub fn add(&mut self, cert: &ServerCert) {
+        let key = cert.certified_key();
+        for name in &cert.names {
+            let (names, name) = match name.strip_prefix("*.") {
+                Some(suffix) => (&mut self.wildcard, suffix),
+                None => (&mut self.exact, name.as_str()),
+            };
+            names
+                .entry(name.to_string())
+                .or_insert_with(|| Arc::clone(&key));
+        }
+        self.default.get_or_insert(key);
+    }
+
+    /// Serve `cert` when no other certificate matches.
+    pub fn set_default(&mut self, cert: &ServerCert) {
+        self.default = Some(cert.certified_key());
+    }
+
+    /// Load the `<name>.crt` and `<name>.key` pairs of `dir`.
+    ///
+    /// Pairs are added in name order, and `default.crt` is the default
+    /// certificate (else the first one).
+    pub fn load_dir(dir: &Path) -> Result<Self> {
+        let read_error =
+            |e: std::io::Error| Error::Config(format!("Failed to read {}: {e}", dir.display()));
+        let mut stems = Vec::new();
+        for entry in std::fs::read_dir(dir).map_err(read_error)? {
+            let path = entry.map_err(read_error)?.path();
+            // Skip the hidden entries of mounted Secrets and other files
+            let stem = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .filter(|name| !name.starts_with('.'))
+                .and_then(|name| name.strip_suffix(".crt"));
+            if let Some(stem) = stem.filter(|_| path.is_file()) {
+                stems.push(stem.to_string());
+            }
+        }
+        stems.sort();
+
+        let mut certs = Self::new();
+        for stem in stems {
+            let cert_path = dir.join(format!("{stem}.crt"));
+            let key_path = dir.join(format!("{stem}.key"));
+            let read = |path: &Path| {
+                std::fs::read(path)
+                    .map_err(|e| Error::Config(format!("Failed to read {}: {e}", path.display())))
+            };
+            let cert = ServerCert::from_pem(&read(&cert_path)?, &read(&key_path)?)
+                .map_err(|e| Error::Config(format!("{}: {e}", cert_path.display())))?;
+            certs.add(&cert);
+            if stem == DEFAULT_CERT_NAME {
+                certs.set_default(&cert);
+            }
+        }
+        if certs.default.is_none() {
+            return Err(Error::Config(format!(
+                "no certificates found in {}",
+                dir.display()
+            )));
+        }
+        Ok(certs)
+    }
+
+    /// The certificate to present to a client asking for `server_name`.
+    pub fn select(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let selected = server_name.and_then(|name| {
+            let name = name.trim_end_matches('.').to_ascii_lowercase();
+            self.exact.get(&name).or_else(|| {
+                let (_, parent) = name.split_once('.')?;
+                self.wildcard.get(parent)
+            })
+        });
+        selected.or(self.default.as_ref()).map(Arc::clone)
+    }
+}
+
+impl ResolvesServerCert for SniCerts {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let server_name = client_hello.server_name();
+        let key = self.select(server_name);
+        if key.is_none() {
+            debug!(server_name = ?server_name, "No TLS certificate for server name");
+        }
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVBOX_CERT: &[u8] = include_bytes!("../tests/fixtures/tls.crt");
+    const DEVBOX_KEY: &[u8] = include_bytes!("../tests/fixtures/tls.key");
+    const EXAMPLE_CERT: &[u8] = include_bytes!("../tests/fixtures/tls-example.crt");
+    const EXAMPLE_KEY: &[u8] = include_bytes!("../tests/fixtures/tls-example.key");
+
+    /// Leaf certificate of the certificate selected for `server_name`
+    fn selected(certs: &SniCerts, server_name: Option<&str>) -> Option<Vec<u8>> {
+        Some(certs.select(server_name)?.cert[0].to_vec())
+    }
+
+    fn leaf(pem: &[u8]) -> Option<Vec<u8>> {
+        Some(
+            rustls_pemfile::certs(&mut &pem[..])
+                .next()
+                .unwrap()
+                .unwrap()
+                .to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_select() {
+        let mut certs = SniCerts::new();
+        assert_eq!(selected(&certs, Some("example.com")), None);
+
+        // *.devbox.local, devbox.local
+        certs.add(&ServerCert::from_pem(DEVBOX_CERT, DEVBOX_KEY).unwrap());
+        // example.com, *.apps.example.com
+        certs.add(&ServerCert::from_pem(EXAMPLE_CERT, EXAMPLE_KEY).unwrap());
+
+        for (name, cert) in [
+            ("devbox.local", DEVBOX_CERT),
+            ("my-app-8080.devbox.local", DEVBOX_CERT),
+            ("example.com", EXAMPLE_CERT),
+            ("Example.COM.", EXAMPLE_CERT),
+            ("my-app.apps.example.com", EXAMPLE_CERT),
+            // Wildcards match one label only, else the default is served
+            ("a.b.apps.example.com", DEVBOX_CERT),
+            ("apps.example.com", DEVBOX_CERT),
+            ("other.org", DEVBOX_CERT),
+        ] {
+            assert_eq!(selected(&certs, Some(name)), leaf(cert), "{name}");
+        }
+        assert_eq!(selected(&certs, None), leaf(DEVBOX_CERT));
+
+        certs.set_default(&ServerCert::from_pem(EXAMPLE_CERT, EXAMPLE_KEY).unwrap());
+        assert_eq!(selected(&certs, Some("other.org")), leaf(EXAMPLE_CERT));
+        assert_eq!(selected(&certs, Some("devbox.local")), leaf(DEVBOX_CERT));
+    }
+
+    #[test]
+    fn test_load_dir() {
+        let dir = std::env::temp_dir().join(format!("httpgate-sni-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(SniCerts::load_dir(&dir).is_err());
+
+        std::fs::write(dir.join("devbox.crt"), DEVBOX_CERT).unwrap();
+        std::fs::write(dir.join("devbox.key"), DEVBOX_KEY).unwrap();
+        std::fs::write(dir.join("default.crt"), EXAMPLE_CERT).unwrap();
+        std::fs::write(dir.join("default.key"), EXAMPLE_KEY).unwrap();
+        std::fs::write(dir.join(".hidden.crt"), b"ignored").unwrap();
+        let certs = SniCerts::load_dir(&dir).unwrap();
+        assert_eq!(selected(&certs, Some("devbox.local")), leaf(DEVBOX_CERT));
+        assert_eq!(selected(&certs, Some("example.com")), leaf(EXAMPLE_CERT));
+        assert_eq!(selected(&certs, None), leaf(EXAMPLE_CERT));
+
+        // Certificates need their key
+        std::fs::remove_file(dir.join("devbox.key")).unwrap();
+        assert!(SniCerts::load_dir(&dir).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(SniCerts::load_dir(&dir).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::registry::{DevboxRegistry, RegistryUsage, WatchStatus};
+
+/// Version of the gateway binary
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Git commit the gateway was built from (see `build.rs`)
+pub const COMMIT: &str = env!("HTTPGATE_COMMIT");
+
+/// Hex characters of the configuration digest kept in the fingerprint
+const FINGERPRINT_LEN: usize = 16;
+
+/// Whether the registry can be trusted for one watch stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchState {
+    /// No initial list has completed yet; nothing is served from the watch
+    Syncing,
+    /// Synced and delivering events
+    Ok,
+    /// Synced before, but failing or re-listing since: its entries are still
+    /// served, though they may be out of date
+    Stale,
+}
+
+/// Health of one watch stream, with timestamps turned into ages.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WatchHealth {
+    pub state: WatchState,
+    /// Seconds since the last initial list or event
+    pub last_event_age_secs: Option<u64>,
+    /// Most recent watch error
+    pub last_error: Option<String>,
+    /// Seconds since the most recent watch error
+    pub last_error_age_secs: Option<u64>,
+    /// Watch errors so far
+    pub errors: u64,
+    /// Times the watcher was restarted
+    pub restarts: u64,
+}
+
+impl WatchHealth {
+    pub fn new(watch: &WatchStatus, now: u64) -> Self {
+        let state = if watch.last_event_at.is_none() {
+            WatchState::Syncing
+        } else if watch.synced && !watch.failing {
+            WatchState::Ok
+        } else {
+            WatchState::Stale
+        };
+        Self {
+            state,
+            last_event_age_secs: watch.last_event_at.map(|at| now.saturating_sub(at)),
+            last_error: watch.last_error.clone(),
+            last_error_age_secs: watch.last_error_at.map(|at| now.saturating_sub(at)),
+            errors: watch.errors,
+            restarts: watch.restarts,
+        }
+    }
+}
+
+/// Health of the watches of one cluster.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClusterWatches {
+    pub devboxes: WatchHealth,
+    pub pods: WatchHealth,
+}
+
+/// Health of the gateway itself, served by the admin API's `/status`.
+///
+/// Readiness (`/healthz`) is derived from the same snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GatewayStatus {
+    /// Whether the watches of every cluster have synced
+    pub ready: bool,
+    pub version: &'static str,
+    pub commit: &'static str,
+    pub uptime_secs: u64,
+    /// Fingerprint of the effective configuration, if it is known
+    pub config_fingerprint: Option<String>,
+    pub registry: RegistryUsage,
+    /// Watch health by cluster
+    pub watchers: BTreeMap<String, ClusterWatches>,
+}
+
+impl GatewayStatus {
+    /// Take a snapshot of the gateway's health at `now` (Unix seconds).
+    pub fn collect(
+        registry: &DevboxRegistry,
+        config: Option<&Config>,
+        uptime: Duration,
+        now: u64,
+    ) -> Self {
+        let watchers = registry
+            .clusters()
+            .into_iter()
+            .map(|(cluster, status)| {
+                let watches = ClusterWatches {
+                    devboxes: WatchHealth::new(&status.devbox_watch, now),
+                    pods: WatchHealth::new(&status.pod_watch, now),
+                };
+                (cluster, watches)
+            })
+            .collect();
+        Self {
+            ready: registry.is_synced(),
+            version: VERSION,
+            commit: COMMIT,
+            uptime_secs: uptime.as_secs(),
+            config_fingerprint: config.map(config_fingerprint),
+            registry: registry.usage(),
+            watchers,
+        }
+    }
+}
+
+/// Short digest of the configuration as `/config` serves it, to tell
+/// whether gateway instances run the same configuration.
+///
+/// Secrets are redacted before hashing, so changing only a secret keeps
+/// the fingerprint.
+pub fn config_fingerprint(config: &Config) -> String {
+    let json = serde_json::to_vec(config).expect("configuration serializes to JSON");
+    let mut digest = hex::encode(Sha256::digest(json));
+    digest.truncate(FINGERPRINT_LEN);
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::{WatchKind, DEFAULT_CLUSTER};
+
+    #[test]
+    fn test_watch_health() {
+        let now = 1_000;
+        let mut watch = WatchStatus::default();
+        assert_eq!(WatchHealth::new(&watch, now).state, WatchState::Syncing);
+
+        // Failing before the first list: still nothing to serve
+        watch.failing = true;
+        watch.last_error_at = Some(990);
+        assert_eq!(WatchHealth::new(&watch, now).state, WatchState::Syncing);
+
+        watch.synced = true;
+        watch.failing = false;
+        watch.last_event_at = Some(940);
+        let health = WatchHealth::new(&watch, now);
+        assert_eq!(health.state, WatchState::Ok);
+        assert_eq!(health.last_event_age_secs, Some(60));
+        assert_eq!(health.last_error_age_secs, Some(10));
+
+        // Entries kept while the watch fails, or re-lists after a restart
+        watch.failing = true;
+        assert_eq!(WatchHealth::new(&watch, now).state, WatchState::Stale);
+        watch.failing = false;
+        watch.synced = false;
+        assert_eq!(WatchHealth::new(&watch, now).state, WatchState::Stale);
+
+        // Clock steps back
+        assert_eq!(WatchHealth::new(&watch, 900).last_event_age_secs, Some(0));
+    }
+
+    #[test]
+    fn test_status_serializes() {
+        let registry = DevboxRegistry::new();
+        registry.add_cluster(DEFAULT_CLUSTER);
+        registry.record_watch_synced(DEFAULT_CLUSTER, WatchKind::Devboxes);
+        registry.record_watch_error(DEFAULT_CLUSTER, WatchKind::Pods, "connection refused");
+
+        let config = Config::default();
+        let status = GatewayStatus::collect(
+            &registry,
+            Some(&config),
+            Duration::from_secs(90),
+            crate::preview::unix_now(),
+        );
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["ready"], false);
+        assert_eq!(json["version"], VERSION);
+        assert!(json["commit"].is_string());
+        assert_eq!(json["uptime_secs"], 90);
+        assert_eq!(json["config_fingerprint"].as_str().unwrap().len(), 16);
+        assert_eq!(json["registry"]["entries"], 0);
+
+        let watches = &json["watchers"][DEFAULT_CLUSTER];
+        assert_eq!(watches["devboxes"]["state"], "ok");
+        assert!(watches["devboxes"]["last_event_age_secs"].as_u64().unwrap() <= 1);
+        assert_eq!(watches["pods"]["state"], "syncing");
+        assert_eq!(watches["pods"]["last_error"], "connection refused");
+        assert_eq!(watches["pods"]["errors"], 1);
+    }
+
+    #[test]
+    fn test_config_fingerprint() {
+        let config = Config::default();
+        assert_eq!(config_fingerprint(&config), config_fingerprint(&config));
+
+        let changed = Config {
+            upstream_connect_retries: 3,
+            ..Default::default()
+        };
+        assert_ne!(config_fingerprint(&config), config_fingerprint(&changed));
+
+        // Secrets are redacted first
+        let secret = Config {
+            signing_key: Some("hunter2".to_string()),
+            ..Default::default()
+        };
+        let other = Config {
+            signing_key: Some("hunter3".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(config_fingerprint(&secret), config_fingerprint(&other));
+    }
+}
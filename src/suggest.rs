@@ -0,0 +1,130 @@
+/// Largest edit distance between a requested uniqueID and a suggestion
+pub const MAX_DISTANCE: usize = 2;
+
+/// Most suggestions offered for one request
+pub const MAX_SUGGESTIONS: usize = 3;
+
+/// Largest registry searched for suggestions; larger registries offer none,
+/// so a missed lookup never costs more than this many comparisons
+pub const MAX_DEVBOXES: usize = 10_000;
+
+/// Levenshtein distance between `a` and `b` if it is at most `max`.
+///
+/// Only a band of `2 * max + 1` cells per row is computed, and the
+/// comparison stops as soon as every cell of a row exceeds `max`, so
+/// dissimilar strings are rejected after a few bytes.
+pub fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    // Cells outside the band are treated as `max + 1`
+    let over = max + 1;
+    let mut prev: Vec<usize> = (0..=b.len()).map(|j| j.min(over)).collect();
+    let mut row = vec![over; b.len() + 1];
+    for (i, &ca) in a.iter().enumerate() {
+        let lo = (i + 1).saturating_sub(max);
+        let hi = (i + 1 + max).min(b.len());
+        row.fill(over);
+        row[0] = (i + 1).min(over);
+        let mut row_min = row[0];
+        for j in lo.max(1)..=hi {
+            let cost = usize::from(ca != b[j - 1]);
+            let cell = (prev[j - 1] + cost).min(prev[j] + 1).min(row[j - 1] + 1);
+            row[j] = cell.min(over);
+            row_min = row_min.min(row[j]);
+        }
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut row);
+    }
+    Some(prev[b.len()]).filter(|&d| d <= max)
+}
+
+/// Escape text for inclusion in HTML.
+pub fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unbounded reference implementation
+    fn levenshtein(a: &str, b: &str) -> usize {
+        let (a, b) = (a.as_bytes(), b.as_bytes());
+        let mut prev: Vec<usize> = (0..=b.len()).collect();
+        for (i, &ca) in a.iter().enumerate() {
+            let mut row = vec![i + 1; b.len() + 1];
+            for j in 1..=b.len() {
+                let cost = usize::from(ca != b[j - 1]);
+                row[j] = (prev[j - 1] + cost).min(prev[j] + 1).min(row[j - 1] + 1);
+            }
+            prev = row;
+        }
+        prev[b.len()]
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        for (a, b, expected) in [
+            ("my-app", "my-app", Some(0)),
+            ("my-app", "my-ap", Some(1)),
+            ("my-ap", "my-app", Some(1)),
+            ("my-app", "my-apq", Some(1)),
+            ("my-app", "ym-app", Some(2)),
+            ("my-app-7x9k2", "my-app-7x9k", Some(1)),
+            ("my-app-7x9k2", "my-app-7k2", Some(2)),
+            ("my-app", "my-a", Some(2)),
+            ("my-app", "my", None),
+            ("my-app", "other", None),
+            ("", "ab", Some(2)),
+            ("", "abc", None),
+            ("", "", Some(0)),
+        ] {
+            assert_eq!(edit_distance(a, b, MAX_DISTANCE), expected, "{a} {b}");
+        }
+    }
+
+    #[test]
+    fn test_edit_distance_matches_reference() {
+        let words = [
+            "", "a", "ab", "ba", "abc", "acb", "abcd", "my-app", "my-apq", "ym-app", "myapp",
+            "my-app-1", "app-my", "kitten", "sitting", "mitten", "devbox",
+        ];
+        for a in words {
+            for b in words {
+                let exact = levenshtein(a, b);
+                for max in 0..=3 {
+                    assert_eq!(
+                        edit_distance(a, b, max),
+                        (exact <= max).then_some(exact),
+                        "{a:?} {b:?} max={max}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_html_escape() {
+        assert_eq!(
+            html_escape(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+        assert_eq!(html_escape("devbox.sealos.io"), "devbox.sealos.io");
+    }
+}
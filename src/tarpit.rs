@@ -0,0 +1,338 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use dashmap::DashMap;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use pingora_core::Result;
+use pingora_http::ResponseHeader;
+use pingora_proxy::Session;
+
+use crate::cidr::Cidr;
+use crate::config::Config;
+use crate::metrics;
+
+/// Window a client's requests for unknown devboxes are counted over
+const SCAN_WINDOW: Duration = Duration::from_secs(60);
+
+/// Most clients whose requests for unknown devboxes are counted at once
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Status of tarpitted requests, and of those turned away above the cap
+pub const TARPIT_STATUS: u16 = 403;
+
+/// Abuse heuristic a request matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The client is in `TARPIT_CIDRS`
+    Client,
+    /// The path starts with one of `TARPIT_PATHS`
+    Path,
+    /// The client asked for too many unknown devboxes, e.g. while scanning
+    Scanner,
+}
+
+impl Reason {
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Client => "client",
+            Self::Path => "path",
+            Self::Scanner => "scanner",
+        }
+    }
+}
+
+/// Where a tarpitted response is written.
+#[async_trait]
+pub trait ResponseWriter: Send {
+    async fn write_header(&mut self, header: ResponseHeader) -> Result<()>;
+    async fn write_body(&mut self, chunk: Bytes, end_of_stream: bool) -> Result<()>;
+}
+
+#[async_trait]
+impl ResponseWriter for Session {
+    async fn write_header(&mut self, header: ResponseHeader) -> Result<()> {
+        self.write_response_header(Box::new(header), false).await
+    }
+
+    async fn write_body(&mut self, chunk: Bytes, end_of_stream: bool) -> Result<()> {
+        self.write_response_body(Some(chunk), end_of_stream).await
+    }
+}
+
+/// Answers obviously malicious requests as slowly as possible.
+///
+/// Instead of an instant 403, which scanners just move on from, requests
+/// matching one of the abuse heuristics get their response headers after
+/// `TARPIT_HEADER_DELAY` and then the body one byte every
+/// `TARPIT_BODY_INTERVAL`, tying up the client's connection for a timer on
+/// our side. At most `TARPIT_MAX_CONNECTIONS` requests are held at once;
+/// above that they get the instant 403, so the tarpit can't be turned
+/// against the gateway.
+#[derive(Debug)]
+pub struct Tarpit {
+    enabled: bool,
+    cidrs: Vec<Cidr>,
+    paths: Vec<String>,
+    /// Requests for unknown devboxes within `SCAN_WINDOW` making a client a
+    /// scanner (not tracked if `None`)
+    scan_threshold: Option<u32>,
+    header_delay: Duration,
+    body_interval: Duration,
+    body_bytes: usize,
+    max_connections: usize,
+    current: AtomicUsize,
+    /// Start of each client's window, and its requests for unknown devboxes
+    unknown_hosts: DashMap<IpAddr, (Instant, u32)>,
+}
+
+impl Tarpit {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            enabled: config.tarpit,
+            cidrs: config.tarpit_cidrs.clone(),
+            paths: config.tarpit_paths.clone(),
+            scan_threshold: config.tarpit_scan_threshold,
+            header_delay: config.tarpit_header_delay,
+            body_interval: config.tarpit_body_interval,
+            body_bytes: config.tarpit_body_bytes,
+            max_connections: config.tarpit_max_connections,
+            current: AtomicUsize::new(0),
+            unknown_hosts: DashMap::new(),
+        }
+    }
+
+    /// The heuristic a request from `client` for `path` matches, if any.
+    pub fn check(&self, client: Option<IpAddr>, path: &str) -> Option<Reason> {
+        self.check_at(client, path, Instant::now())
+    }
+
+    pub fn check_at(&self, client: Option<IpAddr>, path: &str, now: Instant) -> Option<Reason> {
+        if !self.enabled {
+            return None;
+        }
+        if let Some(client) = client {
+            if self.cidrs.iter().any(|cidr| cidr.contains(client)) {
+                return Some(Reason::Client);
+            }
+        }
+        if self.paths.iter().any(|p| path.starts_with(p.as_str())) {
+            return Some(Reason::Path);
+        }
+        let threshold = self.scan_threshold?;
+        let (start, count) = *self.unknown_hosts.get(&client?)?;
+        (now.duration_since(start) < SCAN_WINDOW && count >= threshold).then_some(Reason::Scanner)
+    }
+
+    /// Count a request from `client` for a devbox that doesn't exist.
+    pub fn record_unknown_host(&self, client: IpAddr) {
+        self.record_unknown_host_at(client, Instant::now());
+    }
+
+    pub fn record_unknown_host_at(&self, client: IpAddr, now: Instant) {
+        if !self.enabled || self.scan_threshold.is_none() {
+            return;
+        }
+        let expired = |start: Instant| now.duration_since(start) >= SCAN_WINDOW;
+        if !self.unknown_hosts.contains_key(&client)
+            && self.unknown_hosts.len() >= MAX_TRACKED_CLIENTS
+        {
+            self.unknown_hosts.retain(|_, (start, _)| !expired(*start));
+            if self.unknown_hosts.len() >= MAX_TRACKED_CLIENTS {
+                return;
+            }
+        }
+        let mut entry = self.unknown_hosts.entry(client).or_insert((now, 0));
+        if expired(entry.0) {
+            *entry = (now, 0);
+        }
+        entry.1 = entry.1.saturating_add(1);
+    }
+
+    /// Take a tarpit slot for a request matching `reason`, or `None` if
+    /// `TARPIT_MAX_CONNECTIONS` requests are already held.
+    pub fn try_enter(self: &Arc<Self>, reason: Reason) -> Option<TarpitSlot> {
+        let entered = self
+            .current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max_connections).then_some(n + 1)
+            })
+            .is_ok();
+        if !entered {
+            metrics::TARPIT_OVERFLOW_TOTAL.inc();
+            return None;
+        }
+        metrics::TARPITTED_REQUESTS_TOTAL
+            .with_label_values(&[reason.label()])
+            .inc();
+        metrics::TARPIT_CONNECTIONS.inc();
+        Some(TarpitSlot(Arc::clone(self)))
+    }
+
+    /// Number of requests currently held.
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::Acquire)
+    }
+
+    /// Dribble the tarpitted response to `writer`: the headers after the
+    /// header delay, then the body a byte at a time.
+    pub async fn serve<W: ResponseWriter>(&self, _slot: &TarpitSlot, writer: &mut W) -> Result<()> {
+        tokio::time::sleep(self.header_delay).await;
+        let mut header = ResponseHeader::build(TARPIT_STATUS, None)?;
+        header.insert_header(CONTENT_TYPE, "text/plain")?;
+        header.insert_header(CONTENT_LENGTH, self.body_bytes.to_string())?;
+        writer.write_header(header).await?;
+        if self.body_bytes == 0 {
+            return writer.write_body(Bytes::new(), true).await;
+        }
+        for sent in 1..=self.body_bytes {
+            tokio::time::sleep(self.body_interval).await;
+            writer
+                .write_body(Bytes::from_static(b" "), sent == self.body_bytes)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// A held tarpit slot, released on drop.
+#[derive(Debug)]
+pub struct TarpitSlot(Arc<Tarpit>);
+
+impl Drop for TarpitSlot {
+    fn drop(&mut self) {
+        self.0.current.fetch_sub(1, Ordering::AcqRel);
+        metrics::TARPIT_CONNECTIONS.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tarpit(max_connections: usize) -> Arc<Tarpit> {
+        Arc::new(Tarpit::from_config(&Config {
+            tarpit: true,
+            tarpit_cidrs: vec!["203.0.113.0/24".parse().unwrap()],
+            tarpit_paths: vec!["/.env".to_string(), "/wp-login.php".to_string()],
+            tarpit_scan_threshold: Some(3),
+            tarpit_header_delay: Duration::from_secs(5),
+            tarpit_body_interval: Duration::from_secs(1),
+            tarpit_body_bytes: 3,
+            tarpit_max_connections: max_connections,
+            ..Default::default()
+        }))
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_check() {
+        let tarpit = tarpit(10);
+        let client = Some(ip("198.51.100.7"));
+        assert_eq!(
+            tarpit.check(Some(ip("203.0.113.9")), "/"),
+            Some(Reason::Client)
+        );
+        assert_eq!(tarpit.check(client, "/.env"), Some(Reason::Path));
+        assert_eq!(tarpit.check(None, "/wp-login.php?x"), Some(Reason::Path));
+        assert_eq!(tarpit.check(client, "/"), None);
+        assert_eq!(tarpit.check(client, "/api/.env"), None);
+
+        // Nothing matches while disabled
+        let disabled = Tarpit::from_config(&Config {
+            tarpit_paths: vec!["/.env".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(disabled.check(client, "/.env"), None);
+    }
+
+    #[test]
+    fn test_scanner() {
+        let tarpit = tarpit(10);
+        let scanner = ip("198.51.100.7");
+        let start = Instant::now();
+
+        for i in 0..2 {
+            tarpit.record_unknown_host_at(scanner, start + Duration::from_secs(i));
+        }
+        assert_eq!(tarpit.check_at(Some(scanner), "/", start), None);
+        tarpit.record_unknown_host_at(scanner, start + Duration::from_secs(2));
+        let now = start + Duration::from_secs(3);
+        assert_eq!(
+            tarpit.check_at(Some(scanner), "/", now),
+            Some(Reason::Scanner)
+        );
+        assert_eq!(tarpit.check_at(Some(ip("198.51.100.8")), "/", now), None);
+        assert_eq!(tarpit.check_at(None, "/", now), None);
+
+        // Forgiven once the window is over, and counted afresh
+        let later = start + SCAN_WINDOW;
+        assert_eq!(tarpit.check_at(Some(scanner), "/", later), None);
+        tarpit.record_unknown_host_at(scanner, later);
+        assert_eq!(tarpit.unknown_hosts.get(&scanner).unwrap().1, 1);
+    }
+
+    #[test]
+    fn test_cap() {
+        let tarpit = tarpit(2);
+        let first = tarpit.try_enter(Reason::Path).unwrap();
+        let _second = tarpit.try_enter(Reason::Client).unwrap();
+        assert_eq!(tarpit.current(), 2);
+        assert!(tarpit.try_enter(Reason::Scanner).is_none());
+
+        drop(first);
+        assert_eq!(tarpit.current(), 1);
+        assert!(tarpit.try_enter(Reason::Scanner).is_some());
+    }
+
+    /// Records when each part of the response is written
+    struct Recorder {
+        start: tokio::time::Instant,
+        writes: Vec<(Duration, Option<u16>, Bytes, bool)>,
+    }
+
+    #[async_trait]
+    impl ResponseWriter for Recorder {
+        async fn write_header(&mut self, header: ResponseHeader) -> Result<()> {
+            let at = self.start.elapsed();
+            self.writes
+                .push((at, Some(header.status.as_u16()), Bytes::new(), false));
+            Ok(())
+        }
+
+        async fn write_body(&mut self, chunk: Bytes, end_of_stream: bool) -> Result<()> {
+            let at = self.start.elapsed();
+            self.writes.push((at, None, chunk, end_of_stream));
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_serve_timing() {
+        let tarpit = tarpit(1);
+        let slot = tarpit.try_enter(Reason::Path).unwrap();
+        let mut recorder = Recorder {
+            start: tokio::time::Instant::now(),
+            writes: Vec::new(),
+        };
+        tarpit.serve(&slot, &mut recorder).await.unwrap();
+
+        let secs = Duration::from_secs;
+        let space = Bytes::from_static(b" ");
+        assert_eq!(
+            recorder.writes,
+            [
+                (secs(5), Some(TARPIT_STATUS), Bytes::new(), false),
+                (secs(6), None, space.clone(), false),
+                (secs(7), None, space.clone(), false),
+                (secs(8), None, space, true),
+            ]
+        );
+    }
+}
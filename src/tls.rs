@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use crate::error::{Error, Result};
+
+/// Load and validate a PEM CA bundle used to verify backend certificates.
+///
+/// The bundle is read once at startup so that a missing or malformed file
+/// fails fast instead of surfacing as TLS handshake errors on live traffic.
+/// Returns the number of certificates found in the bundle.
+pub fn load_ca_bundle(path: &str) -> Result<usize> {
+    let pem = std::fs::read(path)
+        .map_err(|e| Error::Config(format!("Failed to read CA bundle {path}: {e}")))?;
+    parse_ca_bundle(&pem)
+        .map(|roots| roots.len())
+        .map_err(|msg| Error::Config(format!("Invalid CA bundle {path}: {msg}")))
+}
+
+/// Connector for the gateway's own HTTPS clients (ACME, webhooks),
+/// verifying servers against the system roots.
+pub fn client_connector() -> TlsConnector {
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("the ring provider supports the default protocol versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Parse the certificates of a PEM document into roots, failing on any
+/// that isn't a valid certificate.
+fn parse_ca_bundle(pem: &[u8]) -> std::result::Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut &pem[..]) {
+        let cert = cert.map_err(|e| format!("invalid certificate PEM: {e}"))?;
+        roots
+            .add(cert)
+            .map_err(|e| format!("invalid CA certificate: {e}"))?;
+    }
+    if roots.is_empty() {
+        return Err("no certificates found".to_string());
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CA: &[u8] = include_bytes!("../tests/fixtures/client-ca.crt");
+
+    #[test]
+    fn test_parse_ca_bundle() {
+        assert_eq!(parse_ca_bundle(CA).unwrap().len(), 1);
+        assert_eq!(parse_ca_bundle(&CA.repeat(3)).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_parse_ca_bundle_invalid() {
+        for pem in [
+            "",
+            "not a pem",
+            "-----BEGIN CERTIFICATE-----\nMIIB\n",
+            "-----END CERTIFICATE-----\n",
+            // Well-formed blocks around invalid base64, or base64 that isn't
+            // a certificate
+            "-----BEGIN CERTIFICATE-----\n!!not base64!!\n-----END CERTIFICATE-----\n",
+            "-----BEGIN CERTIFICATE-----\nMIIBAAAA\n-----END CERTIFICATE-----\n",
+        ] {
+            assert!(parse_ca_bundle(pem.as_bytes()).is_err(), "{pem}");
+        }
+    }
+
+    #[test]
+    fn test_load_ca_bundle() {
+        let path = std::env::temp_dir().join(format!("httpgate-ca-{}.pem", std::process::id()));
+        std::fs::write(&path, CA.repeat(2)).unwrap();
+
+        let result = load_ca_bundle(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[test]
+    fn test_load_ca_bundle_missing_file() {
+        let result = load_ca_bundle("/nonexistent/httpgate-ca.pem");
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+}
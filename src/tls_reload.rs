@@ -0,0 +1,826 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use pingora_core::apps::ServerApp;
+use pingora_core::protocols::l4::socket::SocketDigest;
+use pingora_core::protocols::{
+    GetProxyDigest, GetSocketDigest, GetTimingDigest, Peek, ProxyDigest, Shutdown, Ssl, Stream,
+    TimingDigest, UniqueID, UniqueIDType, ALPN,
+};
+use pingora_core::server::ShutdownWatch;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio_rustls::rustls::client::danger::HandshakeSignatureValid;
+use tokio_rustls::rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature};
+use tokio_rustls::rustls::pki_types::{CertificateDer, UnixTime};
+use tokio_rustls::rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use tokio_rustls::rustls::server::{ClientHello, NoClientAuth, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::{
+    self, DigitallySignedStruct, DistinguishedName, ServerConfig, SignatureScheme,
+};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::debug;
+
+use crate::client_cert::ClientCerts;
+use crate::metrics;
+
+/// Time allowed for the client to complete the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A certificate chain and the private key it was checked to belong to.
+#[derive(Debug, Clone)]
+pub struct ServerCert {
+    key: Arc<CertifiedKey>,
+    /// Expiry of the leaf certificate, as a Unix timestamp
+    pub not_after: i64,
+    /// DNS names of the leaf certificate (its subjectAltNames), lowercased
+    pub names: Vec<String>,
+}
+
+impl ServerCert {
+    /// Parse a PEM certificate chain (leaf first) and its PEM private key.
+    ///
+    /// Fails unless the key is the one the leaf certificate was issued for,
+    /// so a half-rotated Secret never replaces a working certificate.
+    pub fn from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<Self, String> {
+        let certs = rustls_pemfile::certs(&mut &cert_pem[..])
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid certificate PEM: {e}"))?;
+        let leaf = certs.first().ok_or("no certificate found")?;
+        let not_after = not_after(leaf).ok_or("unreadable certificate validity")?;
+        let names = dns_names(leaf);
+
+        let key = rustls_pemfile::private_key(&mut &key_pem[..])
+            .map_err(|e| format!("invalid private key PEM: {e}"))?
+            .ok_or("no private key found")?;
+        let signing_key = ring::default_provider()
+            .key_provider
+            .load_private_key(key)
+            .map_err(|e| format!("unsupported private key: {e}"))?;
+
+        let key = CertifiedKey::new(certs, signing_key);
+        key.keys_match()
+            .map_err(|e| format!("private key does not match the certificate: {e}"))?;
+        Ok(Self {
+            key: Arc::new(key),
+            not_after,
+            names,
+        })
+    }
+
+    /// The chain and key presented in handshakes.
+    pub fn certified_key(&self) -> Arc<CertifiedKey> {
+        Arc::clone(&self.key)
+    }
+}
+
+/// Server configuration offering h2 and http/1.1 with the certificates of
+/// `resolver`.
+pub fn server_config(resolver: Arc<dyn ResolvesServerCert>) -> Arc<ServerConfig> {
+    build_server_config(resolver, Arc::new(NoClientAuth))
+}
+
+/// Like [`server_config`], but also asking clients for a certificate, which
+/// they may decline (see [`RequestClientCert`]).
+pub fn client_cert_server_config(resolver: Arc<dyn ResolvesServerCert>) -> Arc<ServerConfig> {
+    build_server_config(resolver, Arc::new(RequestClientCert))
+}
+
+fn build_server_config(
+    resolver: Arc<dyn ResolvesServerCert>,
+    verifier: Arc<dyn ClientCertVerifier>,
+) -> Arc<ServerConfig> {
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("the ring provider supports the default protocol versions")
+        .with_client_cert_verifier(verifier)
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Arc::new(config)
+}
+
+/// Client certificate verifier of listeners with `client_cert=true`.
+///
+/// Which CAs a certificate must chain to depends on the devbox, which is
+/// only known once the request is read, so any chain is accepted here as
+/// long as its key signed the handshake. Devboxes with a `client-ca`
+/// annotation validate it against their bundle (see
+/// [`crate::client_cert::ClientCaStore`]); the others ignore it.
+#[derive(Debug)]
+struct RequestClientCert;
+
+impl ClientCertVerifier for RequestClientCert {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = ring::default_provider().signature_verification_algorithms;
+        verify_tls12_signature(message, cert, dss, &algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = ring::default_provider().signature_verification_algorithms;
+        verify_tls13_signature(message, cert, dss, &algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// The certificate of listeners serving `TLS_SECRET_REF`, replaced whenever
+/// the Secret is rotated.
+///
+/// Each handshake picks up the certificate current at that moment, so a
+/// swap applies to new connections while established ones keep theirs.
+/// Until a certificate is loaded, handshakes are refused.
+#[derive(Debug, Default)]
+pub struct CertStore {
+    current: RwLock<Option<ServerCert>>,
+}
+
+impl CertStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `cert` from now on.
+    pub fn swap(&self, cert: ServerCert) {
+        metrics::TLS_CERT_NOT_AFTER_SECONDS.set(cert.not_after);
+        *self.current.write().unwrap() = Some(cert);
+    }
+
+    /// Expiry of the certificate being served, if any.
+    pub fn not_after(&self) -> Option<i64> {
+        self.current.read().unwrap().as_ref().map(|c| c.not_after)
+    }
+
+    /// Server configuration offering h2 and http/1.1 with the current
+    /// certificate.
+    pub fn server_config(self: &Arc<Self>) -> Arc<ServerConfig> {
+        server_config(Arc::clone(self) as Arc<dyn ResolvesServerCert>)
+    }
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let key = self
+            .current
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|c| Arc::clone(&c.key));
+        if key.is_none() {
+            debug!("No TLS certificate loaded yet, refusing handshake");
+        }
+        key
+    }
+}
+
+/// Server app terminating TLS with the certificate of a [`CertStore`] (or
+/// any certificate resolver) before handing the connection to `A`.
+///
+/// Pingora's own TLS listeners load one certificate once at startup, so
+/// listeners whose certificate is rotated, or chosen by SNI, terminate TLS
+/// here instead. As for
+/// [`crate::proxy_protocol::ProxyProtocolApp`], all requests of a
+/// connection are served from here.
+pub struct TlsReloadApp<A> {
+    inner: Arc<A>,
+    resolver: Arc<dyn ResolvesServerCert>,
+    acceptor: TlsAcceptor,
+    /// Where the certificates clients present are kept, if they are asked
+    /// for one
+    client_certs: Option<Arc<ClientCerts>>,
+}
+
+impl<A> TlsReloadApp<A> {
+    pub fn new(inner: A, certs: &Arc<CertStore>) -> Self {
+        Self::with_resolver(inner, Arc::clone(certs) as Arc<dyn ResolvesServerCert>)
+    }
+
+    /// Terminate TLS with the certificates picked by `resolver`.
+    pub fn with_resolver(inner: A, resolver: Arc<dyn ResolvesServerCert>) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            acceptor: TlsAcceptor::from(server_config(Arc::clone(&resolver))),
+            resolver,
+            client_certs: None,
+        }
+    }
+
+    /// Ask clients for a certificate, and keep the chains they present in
+    /// `client_certs` for as long as their connection lasts.
+    #[must_use]
+    pub fn with_client_certs(mut self, client_certs: Arc<ClientCerts>) -> Self {
+        self.acceptor = TlsAcceptor::from(client_cert_server_config(Arc::clone(&self.resolver)));
+        self.client_certs = Some(client_certs);
+        self
+    }
+}
+
+#[async_trait]
+impl<A> ServerApp for TlsReloadApp<A>
+where
+    A: ServerApp + Send + Sync + 'static,
+{
+    async fn process_new(
+        self: &Arc<Self>,
+        stream: Stream,
+        shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        let peer = stream
+            .get_socket_digest()
+            .and_then(|d| d.peer_addr().and_then(|a| a.as_inet().copied()));
+
+        let tls = match tokio::time::timeout(HANDSHAKE_TIMEOUT, self.acceptor.accept(stream)).await
+        {
+            Ok(Ok(tls)) => tls,
+            Ok(Err(e)) => {
+                debug!(peer = ?peer, error = %e, "TLS handshake failed");
+                return None;
+            }
+            Err(_) => {
+                debug!(peer = ?peer, "Timed out waiting for TLS handshake");
+                return None;
+            }
+        };
+
+        let registered = match (&self.client_certs, peer) {
+            (Some(client_certs), Some(peer)) => {
+                let chain = tls
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .map(|chain| chain.iter().map(|cert| cert.clone().into_owned()).collect());
+                chain.map(|chain| {
+                    debug!(peer = %peer, "Client certificate received");
+                    client_certs.insert(peer, chain);
+                    (client_certs, peer)
+                })
+            }
+            _ => None,
+        };
+
+        let mut next = Some(Box::new(TlsConnection(tls)) as Stream);
+        while let Some(stream) = next {
+            next = self.inner.process_new(stream, shutdown).await;
+        }
+
+        if let Some((client_certs, peer)) = registered {
+            client_certs.remove(&peer);
+        }
+        None
+    }
+
+    async fn cleanup(&self) {
+        self.inner.cleanup().await;
+    }
+}
+
+/// A connection after a [`TlsReloadApp`] handshake.
+#[derive(Debug)]
+struct TlsConnection(TlsStream<Stream>);
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+#[async_trait]
+impl Shutdown for TlsConnection {
+    async fn shutdown(&mut self) {
+        // Sends close_notify before closing the socket
+        let _ = AsyncWriteExt::shutdown(&mut self.0).await;
+    }
+}
+
+impl UniqueID for TlsConnection {
+    fn id(&self) -> UniqueIDType {
+        self.0.get_ref().0.id()
+    }
+}
+
+impl Ssl for TlsConnection {
+    fn selected_alpn_proto(&self) -> Option<ALPN> {
+        match self.0.get_ref().1.alpn_protocol()? {
+            b"h2" => Some(ALPN::H2),
+            _ => Some(ALPN::H1),
+        }
+    }
+}
+
+impl GetTimingDigest for TlsConnection {
+    fn get_timing_digest(&self) -> Vec<Option<TimingDigest>> {
+        self.0.get_ref().0.get_timing_digest()
+    }
+}
+
+impl GetProxyDigest for TlsConnection {
+    fn get_proxy_digest(&self) -> Option<Arc<ProxyDigest>> {
+        self.0.get_ref().0.get_proxy_digest()
+    }
+}
+
+impl GetSocketDigest for TlsConnection {
+    fn get_socket_digest(&self) -> Option<Arc<SocketDigest>> {
+        self.0.get_ref().0.get_socket_digest()
+    }
+}
+
+#[async_trait]
+impl Peek for TlsConnection {}
+
+/// The fields of a DER certificate's `tbsCertificate` from `validity` on.
+fn tbs_from_validity<'a>(cert: &'a CertificateDer<'_>) -> Option<&'a [u8]> {
+    let (cert, _) = der_element(cert, 0x30)?;
+    let (tbs, _) = der_element(cert, 0x30)?;
+    let mut rest = tbs;
+    // version [0], optional
+    if rest.first() == Some(&0xa0) {
+        rest = der_element(rest, 0xa0)?.1;
+    }
+    // serialNumber, signature, issuer
+    rest = der_element(rest, 0x02)?.1;
+    rest = der_element(rest, 0x30)?.1;
+    rest = der_element(rest, 0x30)?.1;
+    Some(rest)
+}
+
+/// DNS names of the subjectAltName extension of a DER certificate,
+/// lowercased.
+fn dns_names(cert: &CertificateDer<'_>) -> Vec<String> {
+    subject_alt_names(cert)
+        .unwrap_or_default()
+        .into_iter()
+        // dNSName [2]
+        .filter(|(tag, _)| *tag == 0x82)
+        .filter_map(|(_, name)| Some(std::str::from_utf8(name).ok()?.to_ascii_lowercase()))
+        .collect()
+}
+
+/// The names of the subjectAltName extension of a DER certificate, as the
+/// context tag of their `GeneralName` choice and their content.
+pub(crate) fn subject_alt_names<'a>(cert: &'a CertificateDer<'_>) -> Option<Vec<(u8, &'a [u8])>> {
+    // id-ce-subjectAltName (2.5.29.17)
+    let value = extension(cert, &[0x55, 0x1d, 0x11])?;
+    let (mut names, _) = der_element(value, 0x30)?;
+    let mut general_names = Vec::new();
+    while let Some(&tag) = names.first() {
+        let (name, after) = der_element(names, tag)?;
+        names = after;
+        general_names.push((tag, name));
+    }
+    Some(general_names)
+}
+
+/// The `subject` of a DER certificate: the content of its `Name` sequence.
+pub(crate) fn subject<'a>(cert: &'a CertificateDer<'_>) -> Option<&'a [u8]> {
+    // validity, then subject
+    let (_, rest) = der_element(tbs_from_validity(cert)?, 0x30)?;
+    let (subject, _) = der_element(rest, 0x30)?;
+    Some(subject)
+}
+
+/// The `extnValue` of the extension `oid` of a DER certificate.
+fn extension<'a>(cert: &'a CertificateDer<'_>, oid: &[u8]) -> Option<&'a [u8]> {
+    // validity, subject, subjectPublicKeyInfo
+    let mut rest = tbs_from_validity(cert)?;
+    for _ in 0..3 {
+        rest = der_element(rest, 0x30)?.1;
+    }
+    // issuerUniqueID [1] and subjectUniqueID [2], optional
+    for tag in [0x81, 0x82] {
+        if rest.first() == Some(&tag) {
+            rest = der_element(rest, tag)?.1;
+        }
+    }
+    let (extensions, _) = der_element(rest, 0xa3)?;
+    let (mut extensions, _) = der_element(extensions, 0x30)?;
+
+    while !extensions.is_empty() {
+        let (extension, after) = der_element(extensions, 0x30)?;
+        extensions = after;
+        let (id, mut value) = der_element(extension, 0x06)?;
+        if id != oid {
+            continue;
+        }
+        // critical, optional
+        if value.first() == Some(&0x01) {
+            value = der_element(value, 0x01)?.1;
+        }
+        let (value, _) = der_element(value, 0x04)?;
+        return Some(value);
+    }
+    None
+}
+
+/// `notAfter` of a DER certificate, as a Unix timestamp.
+fn not_after(cert: &CertificateDer<'_>) -> Option<i64> {
+    let (validity, _) = der_element(tbs_from_validity(cert)?, 0x30)?;
+
+    // notBefore, then notAfter as UTCTime or GeneralizedTime
+    let tag = *validity.first()?;
+    let (_, validity) = der_element(validity, tag)?;
+    match validity.first()? {
+        0x17 => {
+            let (time, _) = der_element(validity, 0x17)?;
+            let year = i64::from(decimal(time.get(..2)?)?);
+            // RFC 5280: two-digit years from 50 are 19xx
+            let year = if year >= 50 { 1900 + year } else { 2000 + year };
+            unix_time(year, time.get(2..)?)
+        }
+        0x18 => {
+            let (time, _) = der_element(validity, 0x18)?;
+            unix_time(i64::from(decimal(time.get(..4)?)?), time.get(4..)?)
+        }
+        _ => None,
+    }
+}
+
+/// Content of the DER element with `tag` at the start of `buf`, and the
+/// bytes after it.
+pub(crate) fn der_element(buf: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&actual, rest) = buf.split_first()?;
+    if actual != tag {
+        return None;
+    }
+    let (&first, mut rest) = rest.split_first()?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let octets = usize::from(first & 0x7f);
+        if octets == 0 || octets > 4 {
+            return None;
+        }
+        let (len, after) = rest.split_at_checked(octets)?;
+        rest = after;
+        len.iter().fold(0, |n, &b| n << 8 | usize::from(b))
+    };
+    rest.split_at_checked(len)
+}
+
+/// Value of ASCII decimal digits
+fn decimal(digits: &[u8]) -> Option<u32> {
+    digits.iter().try_fold(0, |n, &d| {
+        d.is_ascii_digit().then(|| n * 10 + u32::from(d - b'0'))
+    })
+}
+
+/// Unix timestamp of `year` and `MMDDHHMMSSZ`.
+fn unix_time(year: i64, rest: &[u8]) -> Option<i64> {
+    if rest.len() != 11 || rest[10] != b'Z' {
+        return None;
+    }
+    let field = |i: usize| decimal(&rest[i..i + 2]).map(i64::from);
+    let (month, day) = (field(0)?, field(2)?);
+    let (hour, minute, second) = (field(4)?, field(6)?, field(8)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days since the epoch of a proleptic Gregorian date
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Some(days * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_rustls::rustls::client::danger::{ServerCertVerified, ServerCertVerifier};
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::TlsConnector;
+
+    const CERT: &[u8] = include_bytes!("../tests/fixtures/tls.crt");
+    const KEY: &[u8] = include_bytes!("../tests/fixtures/tls.key");
+    const ROTATED_CERT: &[u8] = include_bytes!("../tests/fixtures/tls-rotated.crt");
+    const ROTATED_KEY: &[u8] = include_bytes!("../tests/fixtures/tls-rotated.key");
+
+    #[test]
+    fn test_server_cert_from_pem() {
+        // GeneralizedTime (2126) and UTCTime (2036) expiries
+        assert_eq!(
+            ServerCert::from_pem(CERT, KEY).unwrap().not_after,
+            4_945_740_598
+        );
+        assert_eq!(
+            ServerCert::from_pem(ROTATED_CERT, ROTATED_KEY)
+                .unwrap()
+                .not_after,
+            2_107_507_749
+        );
+    }
+
+    #[test]
+    fn test_server_cert_names() {
+        assert_eq!(
+            ServerCert::from_pem(CERT, KEY).unwrap().names,
+            ["*.devbox.local", "devbox.local"]
+        );
+        let cert = include_bytes!("../tests/fixtures/tls-example.crt");
+        let key = include_bytes!("../tests/fixtures/tls-example.key");
+        assert_eq!(
+            ServerCert::from_pem(cert, key).unwrap().names,
+            ["example.com", "*.apps.example.com"]
+        );
+    }
+
+    #[test]
+    fn test_server_cert_rejected() {
+        for (cert, key) in [
+            (CERT, ROTATED_KEY),
+            (ROTATED_CERT, KEY),
+            (&b""[..], KEY),
+            (CERT, &b""[..]),
+            (
+                &b"-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n"[..],
+                KEY,
+            ),
+            (KEY, CERT),
+        ] {
+            assert!(ServerCert::from_pem(cert, key).is_err());
+        }
+    }
+
+    #[test]
+    fn test_unix_time() {
+        assert_eq!(unix_time(1970, b"0101000000Z"), Some(0));
+        assert_eq!(unix_time(2000, b"0229120000Z"), Some(951_825_600));
+        assert_eq!(unix_time(2036, b"1013104909Z"), Some(2_107_507_749));
+        assert_eq!(unix_time(2036, b"1013104909"), None);
+        assert_eq!(unix_time(2036, b"1313104909Z"), None);
+    }
+
+    /// Accepts any certificate: the fixtures are self-signed CAs, which
+    /// webpki refuses as end entities.
+    #[derive(Debug)]
+    struct AcceptAny;
+
+    impl ServerCertVerifier for AcceptAny {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            let algorithms = ring::default_provider().signature_verification_algorithms;
+            verify_tls12_signature(message, cert, dss, &algorithms)
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            let algorithms = ring::default_provider().signature_verification_algorithms;
+            verify_tls13_signature(message, cert, dss, &algorithms)
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            ring::default_provider()
+                .signature_verification_algorithms
+                .supported_schemes()
+        }
+    }
+
+    /// Certificate presented by `store` in a fresh handshake.
+    async fn presented_cert(store: &Arc<CertStore>) -> Option<Vec<u8>> {
+        let (cert, _) = handshake(store.server_config(), "app.devbox.local", b"").await?;
+        Some(cert)
+    }
+
+    /// Certificate presented by `config` to a client asking for
+    /// `server_name`, and what the server reads of `request` once TLS is
+    /// terminated.
+    async fn handshake(
+        config: Arc<ServerConfig>,
+        server_name: &str,
+        request: &[u8],
+    ) -> Option<(Vec<u8>, Vec<u8>)> {
+        use tokio::io::AsyncReadExt;
+
+        let client = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAny))
+            .with_no_client_auth();
+
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let acceptor = TlsAcceptor::from(config);
+        let len = request.len();
+        let server = tokio::spawn(async move {
+            let mut tls = acceptor.accept(server_io).await.ok()?;
+            let mut received = vec![0; len];
+            tls.read_exact(&mut received).await.ok()?;
+            Some(received)
+        });
+        let name = ServerName::try_from(server_name.to_string()).unwrap();
+        let mut tls = TlsConnector::from(Arc::new(client))
+            .connect(name, client_io)
+            .await
+            .ok()?;
+        tls.write_all(request).await.unwrap();
+        tls.flush().await.unwrap();
+        let received = server.await.unwrap().expect("server side of the handshake");
+        let (_, connection) = tls.get_ref();
+        Some((connection.peer_certificates()?[0].to_vec(), received))
+    }
+
+    #[tokio::test]
+    async fn test_sni_certificates() {
+        use crate::sni::SniCerts;
+
+        let example_cert = include_bytes!("../tests/fixtures/tls-example.crt");
+        let example_key = include_bytes!("../tests/fixtures/tls-example.key");
+        let mut certs = SniCerts::new();
+        certs.add(&ServerCert::from_pem(CERT, KEY).unwrap());
+        certs.add(&ServerCert::from_pem(example_cert, example_key).unwrap());
+        let config = server_config(Arc::new(certs));
+
+        let request = b"GET / HTTP/1.1\r\nHost: my-app.apps.example.com\r\n\r\n";
+        for (server_name, cert) in [
+            ("my-app-8080.devbox.local", CERT),
+            ("my-app.apps.example.com", &example_cert[..]),
+            ("example.com", &example_cert[..]),
+            ("unknown.org", CERT),
+        ] {
+            let (presented, received) = handshake(Arc::clone(&config), server_name, request)
+                .await
+                .unwrap();
+            assert_eq!(presented, der(cert), "{server_name}");
+            // The application behind the listener reads plaintext HTTP
+            assert_eq!(received, request);
+        }
+    }
+
+    fn der(pem: &[u8]) -> Vec<u8> {
+        rustls_pemfile::certs(&mut &pem[..])
+            .next()
+            .unwrap()
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_swap_applies_to_new_handshakes() {
+        let store = Arc::new(CertStore::new());
+
+        // Nothing to present before the first certificate is loaded
+        assert_eq!(presented_cert(&store).await, None);
+        assert_eq!(store.not_after(), None);
+
+        store.swap(ServerCert::from_pem(CERT, KEY).unwrap());
+        assert_eq!(presented_cert(&store).await, Some(der(CERT)));
+
+        store.swap(ServerCert::from_pem(ROTATED_CERT, ROTATED_KEY).unwrap());
+        assert_eq!(presented_cert(&store).await, Some(der(ROTATED_CERT)));
+        assert_eq!(store.not_after(), Some(2_107_507_749));
+    }
+
+    /// Chain the server side of a handshake with `config` got from a client
+    /// presenting `client_cert` (a PEM chain and key), or `None` if the
+    /// handshake failed.
+    async fn client_cert_handshake(
+        config: Arc<ServerConfig>,
+        client_cert: Option<(&[u8], &[u8])>,
+    ) -> Option<Option<Vec<Vec<u8>>>> {
+        let client = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAny));
+        let client = match client_cert {
+            Some((cert, key)) => {
+                let chain = rustls_pemfile::certs(&mut &cert[..])
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+                let key = rustls_pemfile::private_key(&mut &key[..]).unwrap().unwrap();
+                client.with_client_auth_cert(chain, key).unwrap()
+            }
+            None => client.with_no_client_auth(),
+        };
+
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let acceptor = TlsAcceptor::from(config);
+        let server = tokio::spawn(async move {
+            let tls = acceptor.accept(server_io).await.ok()?;
+            let chain = tls
+                .get_ref()
+                .1
+                .peer_certificates()
+                .map(|chain| chain.iter().map(|c| c.to_vec()).collect());
+            Some(chain)
+        });
+        let name = ServerName::try_from("app.devbox.local").unwrap();
+        let mut tls = TlsConnector::from(Arc::new(client))
+            .connect(name, client_io)
+            .await
+            .ok()?;
+        // TLS 1.3 servers check the client's certificate after the client
+        // considers the handshake done
+        tls.flush().await.unwrap();
+        server.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_client_cert_requested() {
+        let client_cert = include_bytes!("../tests/fixtures/client.crt");
+        let client_key = include_bytes!("../tests/fixtures/client.key");
+        let store = Arc::new(CertStore::new());
+        store.swap(ServerCert::from_pem(CERT, KEY).unwrap());
+        let resolver = Arc::clone(&store) as Arc<dyn ResolvesServerCert>;
+
+        // Any certificate is taken, to be checked per devbox, and clients
+        // without one still connect
+        let config = client_cert_server_config(Arc::clone(&resolver));
+        let chain = client_cert_handshake(Arc::clone(&config), Some((client_cert, client_key)))
+            .await
+            .unwrap();
+        assert_eq!(chain, Some(vec![der(client_cert)]));
+        assert_eq!(client_cert_handshake(config, None).await, Some(None));
+
+        // Other listeners don't ask for one
+        let config = server_config(resolver);
+        assert_eq!(
+            client_cert_handshake(config, Some((client_cert, client_key))).await,
+            Some(None)
+        );
+    }
+}
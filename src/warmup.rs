@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+use crate::activity::ActivityTracker;
+use crate::blocklist::Blocklist;
+use crate::error::{Error, Result};
+use crate::metrics;
+use crate::preview::unix_now;
+use crate::registry::DevboxRegistry;
+
+/// Interval at which the hot set is written to the state file
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interval at which the warm-up checks whether the registry has synced
+const SYNC_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Timeout of a single warm-up connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A devbox port with recent traffic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotBackend {
+    pub unique_id: String,
+    pub port: u16,
+    /// Unix seconds of its last activity
+    pub last_seen: u64,
+}
+
+/// The most recently used devbox ports, kept across restarts so a new
+/// gateway process can connect to them before their first request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HotSet {
+    capacity: usize,
+    /// Most recently seen first
+    backends: Vec<HotBackend>,
+}
+
+impl HotSet {
+    /// Create an empty set holding at most `capacity` backends.
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            backends: Vec::new(),
+        }
+    }
+
+    pub fn backends(&self) -> &[HotBackend] {
+        &self.backends
+    }
+
+    /// Merge `(unique_id, port, last_seen)` activity into the set, keeping
+    /// the `capacity` most recently seen backends.
+    pub fn update(&mut self, activity: impl IntoIterator<Item = (String, u16, u64)>) {
+        let mut latest: HashMap<(String, u16), u64> = self
+            .backends
+            .drain(..)
+            .map(|b| ((b.unique_id, b.port), b.last_seen))
+            .collect();
+        for (unique_id, port, last_seen) in activity {
+            let entry = latest.entry((unique_id, port)).or_default();
+            *entry = (*entry).max(last_seen);
+        }
+
+        self.backends = latest
+            .into_iter()
+            .map(|((unique_id, port), last_seen)| HotBackend {
+                unique_id,
+                port,
+                last_seen,
+            })
+            .collect();
+        self.backends.sort_by(|a, b| {
+            b.last_seen
+                .cmp(&a.last_seen)
+                .then_with(|| a.unique_id.cmp(&b.unique_id))
+                .then_with(|| a.port.cmp(&b.port))
+        });
+        self.backends.truncate(self.capacity);
+    }
+
+    /// Parse a state file: one `<unique_id> <port> <last_seen>` per line.
+    /// Malformed lines are skipped.
+    pub fn parse(content: &str, capacity: usize) -> Self {
+        let activity = content.lines().filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let unique_id = fields.next()?;
+            let port = fields.next()?.parse().ok()?;
+            let last_seen = fields.next()?.parse().ok()?;
+            Some((unique_id.to_string(), port, last_seen))
+        });
+        let mut set = Self::new(capacity);
+        set.update(activity);
+        set
+    }
+
+    /// Read the set from `path`; a missing file is an empty set.
+    pub fn load(path: &str, capacity: usize) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content, capacity)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::new(capacity)),
+            Err(e) => Err(Error::Config(format!(
+                "Failed to read warm-up state {path}: {e}"
+            ))),
+        }
+    }
+
+    /// Write the set to `path`, replacing the file atomically.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let content: String = self
+            .backends
+            .iter()
+            .map(|b| format!("{} {} {}\n", b.unique_id, b.port, b.last_seen))
+            .collect();
+        let tmp = format!("{path}.tmp");
+        std::fs::write(&tmp, content)
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .map_err(|e| Error::Config(format!("Failed to write warm-up state {path}: {e}")))
+    }
+
+    /// Merge the tracked activity into the set and save it to `path` every
+    /// [`SAVE_INTERVAL`] forever.
+    pub async fn record(mut self, tracker: Arc<ActivityTracker>, path: String) {
+        let mut ticker = tokio::time::interval(SAVE_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            self.update(
+                tracker
+                    .backends(unix_now())
+                    .into_iter()
+                    .filter(|&(_, port, _)| port != 0),
+            );
+            if let Err(e) = self.save(&path) {
+                warn!(error = %e, "Failed to save warm-up state");
+            }
+        }
+    }
+}
+
+/// Opens warm-up connections to backends.
+#[async_trait]
+pub trait WarmupConnector: Send + Sync {
+    /// Connect to `port` of `ip`, returning whether it succeeded.
+    async fn connect(&self, ip: &str, port: u16) -> bool;
+}
+
+/// [`WarmupConnector`] opening plain TCP connections.
+///
+/// Pingora's upstream pool is private to each proxy service, so these
+/// connections are closed again instead of being reused by the first
+/// request; they prime the network path to the Pod and the backend's
+/// listener.
+pub struct TcpWarmupConnector;
+
+#[async_trait]
+impl WarmupConnector for TcpWarmupConnector {
+    async fn connect(&self, ip: &str, port: u16) -> bool {
+        match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((ip, port))).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => {
+                debug!(pod_ip = %ip, port = port, error = %e, "Warm-up connect failed");
+                false
+            }
+            Err(_) => {
+                debug!(pod_ip = %ip, port = port, "Warm-up connect timed out");
+                false
+            }
+        }
+    }
+}
+
+/// Connects to the hot backends once the registry has synced after startup.
+///
+/// Strictly best effort: at most `concurrency` connections are in flight,
+/// and the whole warm-up (including waiting for the sync) is abandoned after
+/// `timeout`.
+pub struct Warmer {
+    registry: Arc<DevboxRegistry>,
+    blocklist: Arc<Blocklist>,
+    connector: Box<dyn WarmupConnector>,
+    concurrency: usize,
+    timeout: Duration,
+}
+
+impl Warmer {
+    pub fn new(
+        registry: Arc<DevboxRegistry>,
+        blocklist: Arc<Blocklist>,
+        connector: Box<dyn WarmupConnector>,
+        concurrency: usize,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            registry,
+            blocklist,
+            connector,
+            concurrency: concurrency.max(1),
+            timeout,
+        }
+    }
+
+    /// Warm up the backends of `hot`, returning how many connections were
+    /// established.
+    pub async fn run(&self, hot: &HotSet) -> usize {
+        let established = AtomicUsize::new(0);
+        let warm = async {
+            while !self.registry.is_synced() {
+                tokio::time::sleep(SYNC_POLL_INTERVAL).await;
+            }
+
+            let targets = self.targets(hot);
+            info!(
+                backends = targets.len(),
+                concurrency = self.concurrency,
+                "Warming up hot backends"
+            );
+            stream::iter(targets)
+                .for_each_concurrent(self.concurrency, |(ip, port)| {
+                    let established = &established;
+                    async move {
+                        let result = if self.connector.connect(&ip, port).await {
+                            established.fetch_add(1, Ordering::Relaxed);
+                            "established"
+                        } else {
+                            "failed"
+                        };
+                        metrics::WARMUP_CONNECTIONS_TOTAL
+                            .with_label_values(&[result])
+                            .inc();
+                    }
+                })
+                .await;
+        };
+        if tokio::time::timeout(self.timeout, warm).await.is_err() {
+            warn!(timeout = ?self.timeout, "Warm-up timed out");
+        }
+
+        let established = established.load(Ordering::Relaxed);
+        info!(established = established, "Warm-up finished");
+        established
+    }
+
+    /// Pod endpoints of the hot backends that are currently routable.
+    fn targets(&self, hot: &HotSet) -> Vec<(String, u16)> {
+        let blocked = self.blocklist.snapshot();
+        hot.backends()
+            .iter()
+            .filter_map(|backend| {
+                let info = self.registry.get_devbox(&backend.unique_id)?;
+                if blocked.unique_ids.contains(&backend.unique_id)
+                    || blocked.namespaces.contains(&info.namespace)
+                {
+                    return None;
+                }
+                let endpoint = self.registry.get_pod_endpoint(
+                    &info.cluster,
+                    &info.namespace,
+                    &info.devbox_name,
+                )?;
+                Some((endpoint.ip, backend.port))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::registry::{WatchKind, DEFAULT_CLUSTER};
+    use std::sync::Mutex;
+
+    fn activity(entries: &[(&str, u16, u64)]) -> Vec<(String, u16, u64)> {
+        entries
+            .iter()
+            .map(|&(id, port, last_seen)| (id.to_string(), port, last_seen))
+            .collect()
+    }
+
+    fn ids(set: &HotSet) -> Vec<(&str, u16)> {
+        set.backends()
+            .iter()
+            .map(|b| (b.unique_id.as_str(), b.port))
+            .collect()
+    }
+
+    #[test]
+    fn test_hot_set_selection() {
+        let mut set = HotSet::new(3);
+        set.update(activity(&[
+            ("app-a", 8080, 100),
+            ("app-b", 3000, 300),
+            ("app-c", 8080, 200),
+            ("app-d", 8080, 50),
+        ]));
+        assert_eq!(
+            ids(&set),
+            vec![("app-b", 3000), ("app-c", 8080), ("app-a", 8080)]
+        );
+
+        // Newer activity replaces older entries, older activity never
+        // moves an entry back
+        set.update(activity(&[("app-d", 8080, 400), ("app-b", 3000, 10)]));
+        assert_eq!(
+            ids(&set),
+            vec![("app-d", 8080), ("app-b", 3000), ("app-c", 8080)]
+        );
+        assert_eq!(set.backends()[1].last_seen, 300);
+
+        // Each port of a devbox is its own backend
+        set.update(activity(&[("app-d", 3000, 500)]));
+        assert_eq!(
+            ids(&set),
+            vec![("app-d", 3000), ("app-d", 8080), ("app-b", 3000)]
+        );
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let mut set = HotSet::new(10);
+        set.update(activity(&[("app-a", 8080, 100), ("app-b", 3000, 200)]));
+
+        let dir = std::env::temp_dir().join(format!("httpgate-warmup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hot").to_string_lossy().into_owned();
+        set.save(&path).unwrap();
+        assert_eq!(HotSet::load(&path, 10).unwrap(), set);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(HotSet::load(&path, 10).unwrap(), HotSet::new(10));
+        let parsed = HotSet::parse("app-a 8080 100\nbad line\napp-b x 1\n\napp-c 80", 10);
+        assert_eq!(ids(&parsed), vec![("app-a", 8080)]);
+    }
+
+    /// Records connections, holding each open for `delay`.
+    #[derive(Default)]
+    struct FakeConnector {
+        delay: Duration,
+        connected: Mutex<Vec<(String, u16)>>,
+        open: AtomicUsize,
+        max_open: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl WarmupConnector for Arc<FakeConnector> {
+        async fn connect(&self, ip: &str, port: u16) -> bool {
+            let open = self.open.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_open.fetch_max(open, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            self.open.fetch_sub(1, Ordering::SeqCst);
+            self.connected.lock().unwrap().push((ip.to_string(), port));
+            true
+        }
+    }
+
+    fn synced_registry(count: usize) -> Arc<DevboxRegistry> {
+        let registry = Arc::new(DevboxRegistry::new());
+        for i in 0..count {
+            let name = format!("devbox-{i}");
+            registry.register_devbox(format!("app-{i}"), "ns".to_string(), name.clone());
+            registry
+                .update_pod_ip("ns", &name, format!("10.0.0.{i}"))
+                .unwrap();
+        }
+        registry.record_watch_synced(DEFAULT_CLUSTER, WatchKind::Devboxes);
+        registry.record_watch_synced(DEFAULT_CLUSTER, WatchKind::Pods);
+        registry
+    }
+
+    fn hot_set(count: usize) -> HotSet {
+        let mut set = HotSet::new(count);
+        set.update((0..count).map(|i| (format!("app-{i}"), 8080, i as u64)));
+        set
+    }
+
+    fn warmer(
+        registry: Arc<DevboxRegistry>,
+        connector: &Arc<FakeConnector>,
+        concurrency: usize,
+        timeout: Duration,
+    ) -> Warmer {
+        let blocklist = Arc::new(Blocklist::from_config(&Config::default()));
+        Warmer::new(
+            registry,
+            blocklist,
+            Box::new(Arc::clone(connector)),
+            concurrency,
+            timeout,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_bound() {
+        let connector = Arc::new(FakeConnector {
+            delay: Duration::from_millis(20),
+            ..Default::default()
+        });
+        let warmer = warmer(synced_registry(10), &connector, 3, Duration::from_secs(10));
+
+        assert_eq!(warmer.run(&hot_set(10)).await, 10);
+        assert_eq!(connector.max_open.load(Ordering::SeqCst), 3);
+        assert_eq!(connector.connected.lock().unwrap().len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_skips_unroutable_backends() {
+        let registry = synced_registry(2);
+        registry.register_devbox(
+            "stopped".to_string(),
+            "ns".to_string(),
+            "stopped".to_string(),
+        );
+        let mut hot = hot_set(2);
+        hot.capacity = 10;
+        hot.update(activity(&[("stopped", 8080, 10), ("deleted", 8080, 10)]));
+
+        let connector = Arc::new(FakeConnector::default());
+        let warmer = warmer(registry, &connector, 4, Duration::from_secs(10));
+        assert_eq!(warmer.run(&hot).await, 2);
+
+        let mut connected = connector.connected.lock().unwrap().clone();
+        connected.sort();
+        assert_eq!(
+            connected,
+            vec![
+                ("10.0.0.0".to_string(), 8080),
+                ("10.0.0.1".to_string(), 8080)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        // Never synced
+        let connector = Arc::new(FakeConnector::default());
+        let unsynced = warmer(
+            Arc::new(DevboxRegistry::new()),
+            &connector,
+            4,
+            Duration::from_millis(50),
+        );
+        assert_eq!(unsynced.run(&hot_set(2)).await, 0);
+
+        // Slow connections are abandoned
+        let connector = Arc::new(FakeConnector {
+            delay: Duration::from_secs(60),
+            ..Default::default()
+        });
+        let slow = warmer(synced_registry(2), &connector, 4, Duration::from_millis(50));
+        assert_eq!(slow.run(&hot_set(2)).await, 0);
+        assert!(connector.connected.lock().unwrap().is_empty());
+    }
+}
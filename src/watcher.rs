@@ -1,16 +1,32 @@
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
 
-use futures::StreamExt;
-use k8s_openapi::api::core::v1::Pod;
+use futures::{future, stream, stream::BoxStream, Stream, StreamExt};
+use k8s_openapi::api::core::v1::{ConfigMap, Pod, Secret};
+use k8s_openapi::NamespaceResourceScope;
 use kube::{
-    api::Api,
+    api::{Api, WatchEvent, WatchParams},
     config::{KubeConfigOptions, Kubeconfig},
     runtime::{watcher, watcher::Event, WatchStreamExt},
-    Client, Config,
+    Client, Config, Resource,
 };
 use tracing::{debug, error, info, warn};
 
-use crate::{crd::Devbox, error::Result, registry::DevboxRegistry};
+use crate::{
+    client_cert::{CaSource, ClientCaStore, CLIENT_CA_KEY, CLIENT_CA_LABEL},
+    config::{ClusterConfig, DevboxWatchMode},
+    crd::{Devbox, SlimDevbox},
+    error::{Error, Result},
+    limits::{self, NamespaceLimiter},
+    policy::DevboxPolicy,
+    registry::{DevboxInfo, DevboxRegistry, PodRef, WatchKind},
+    tls_reload::{CertStore, ServerCert},
+};
 
 /// Label used to identify devbox pods
 const DEVBOX_PART_OF_LABEL: &str = "app.kubernetes.io/part-of";
@@ -19,6 +35,12 @@ const DEVBOX_PART_OF_VALUE: &str = "devbox";
 /// OwnerReference kind for devbox
 const DEVBOX_OWNER_KIND: &str = "Devbox";
 
+/// Delay before a failed watcher is restarted
+const WATCHER_RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// Interval of the warning repeated while the registry is full
+const REGISTRY_FULL_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
 /// Create a Kubernetes client.
 ///
 /// Priority:
@@ -46,21 +68,749 @@ pub async fn create_client() -> Result<Client> {
     }
 }
 
+/// Create a Kubernetes client for `cluster`.
+///
+/// Uses the cluster's kubeconfig file (or `KUBECONFIG` and the default
+/// kubeconfig) with its context. Clusters with neither fall back to
+/// [`create_client`].
+pub async fn create_cluster_client(cluster: &ClusterConfig) -> Result<Client> {
+    if cluster.context.is_none() && cluster.kubeconfig.is_none() {
+        return create_client().await;
+    }
+
+    info!(
+        cluster = %cluster.name,
+        context = ?cluster.context,
+        kubeconfig = ?cluster.kubeconfig,
+        "Using kubeconfig for cluster"
+    );
+    let options = KubeConfigOptions {
+        context: cluster.context.clone(),
+        ..Default::default()
+    };
+    let config = match cluster.kubeconfig.as_deref() {
+        Some(path) => {
+            let kubeconfig = Kubeconfig::read_from(path).map_err(|e| {
+                Error::Config(format!(
+                    "Failed to read kubeconfig of cluster {}: {e}",
+                    cluster.name
+                ))
+            })?;
+            Config::from_custom_kubeconfig(kubeconfig, &options).await
+        }
+        None => Config::from_kubeconfig(&options).await,
+    }
+    .map_err(|e| {
+        Error::Config(format!(
+            "Failed to load kubeconfig of cluster {}: {e}",
+            cluster.name
+        ))
+    })?;
+    Ok(Client::try_from(config)?)
+}
+
+/// Create a Kubernetes client for each of `clusters`, by cluster name.
+pub async fn create_cluster_clients(clusters: &[ClusterConfig]) -> Result<HashMap<String, Client>> {
+    let mut clients = HashMap::with_capacity(clusters.len());
+    for cluster in clusters {
+        clients.insert(cluster.name.clone(), create_cluster_client(cluster).await?);
+    }
+    Ok(clients)
+}
+
+// ============================================================================
+// Watcher Supervisor
+// ============================================================================
+
+/// Runs the Devbox and Pod watchers of every cluster, restarting each one
+/// after it fails.
+///
+/// The watchers of a cluster only ever touch that cluster's entries, so a
+/// cluster whose API server is unreachable keeps serving its last known
+/// devboxes while the others carry on. Watch health is recorded in the
+/// registry (see [`DevboxRegistry::clusters`]).
+pub struct WatcherSupervisor {
+    registry: Arc<DevboxRegistry>,
+    clusters: Vec<ClusterConfig>,
+    pod_ip_status_field: Option<String>,
+    devbox_watch_mode: DevboxWatchMode,
+    require_pod_ready: bool,
+    namespaces: Vec<String>,
+    resync: Option<ResyncSignal>,
+}
+
+impl WatcherSupervisor {
+    pub fn new(registry: Arc<DevboxRegistry>, clusters: Vec<ClusterConfig>) -> Self {
+        for cluster in &clusters {
+            registry.add_cluster(&cluster.name);
+        }
+        Self {
+            registry,
+            clusters,
+            pod_ip_status_field: None,
+            devbox_watch_mode: DevboxWatchMode::Full,
+            require_pod_ready: false,
+            namespaces: Vec::new(),
+            resync: None,
+        }
+    }
+
+    /// Read pod IPs from the Devbox status field at `path` instead of
+    /// watching Pods (see [`DevboxWatcher::with_pod_ip_status_field`]).
+    #[must_use]
+    pub fn with_pod_ip_status_field(mut self, path: String) -> Self {
+        self.pod_ip_status_field = Some(path);
+        self
+    }
+
+    /// Deserialize Devboxes as `mode` says (see
+    /// [`DevboxWatcher::with_watch_mode`]).
+    #[must_use]
+    pub fn with_devbox_watch_mode(mut self, mode: DevboxWatchMode) -> Self {
+        self.devbox_watch_mode = mode;
+        self
+    }
+
+    /// Have every watcher list everything again whenever `signal` is
+    /// raised (see [`ResyncSignal`]).
+    #[must_use]
+    pub fn with_resync(mut self, signal: ResyncSignal) -> Self {
+        self.resync = Some(signal);
+        self
+    }
+
+    /// Only route to ready Pods (see [`PodWatcher::with_require_ready`]).
+    #[must_use]
+    pub fn with_require_pod_ready(mut self, require: bool) -> Self {
+        self.require_pod_ready = require;
+        self
+    }
+
+    /// Only watch `namespaces` of each cluster (see
+    /// [`DevboxWatcher::with_namespaces`]).
+    #[must_use]
+    pub fn with_namespaces(mut self, namespaces: Vec<String>) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    /// Run all watchers.
+    ///
+    /// This function runs indefinitely. It should be spawned as a background
+    /// task.
+    pub async fn run(self) {
+        let watchers = self.clusters.iter().flat_map(|cluster| {
+            let mut devboxes = DevboxWatcher::new(Arc::clone(&self.registry))
+                .with_cluster(cluster.clone())
+                .with_namespaces(&self.namespaces)
+                .with_watch_mode(self.devbox_watch_mode);
+            if let Some(path) = &self.pod_ip_status_field {
+                devboxes = devboxes.with_pod_ip_status_field(path.clone());
+            }
+            if let Some(signal) = &self.resync {
+                devboxes = devboxes.with_resync(signal.clone());
+            }
+            let registry = &self.registry;
+            let pods = self.pod_ip_status_field.is_none().then(|| {
+                let mut pods = PodWatcher::new(Arc::clone(&self.registry))
+                    .with_cluster(cluster.clone())
+                    .with_namespaces(&self.namespaces)
+                    .with_require_ready(self.require_pod_ready);
+                if let Some(signal) = &self.resync {
+                    pods = pods.with_resync(signal.clone());
+                }
+                future::Either::Right(async move {
+                    supervise(registry, &cluster.name, WatchKind::Pods, || pods.run()).await;
+                })
+            });
+            std::iter::once(future::Either::Left(async move {
+                supervise(registry, &cluster.name, WatchKind::Devboxes, || {
+                    devboxes.run()
+                })
+                .await;
+            }))
+            .chain(pods)
+        });
+        future::join_all(watchers).await;
+    }
+}
+
+/// Call `run` forever, recording each failure of the watcher.
+async fn supervise<F, Fut>(registry: &DevboxRegistry, cluster: &str, kind: WatchKind, mut run: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    loop {
+        let error = match run().await {
+            Ok(()) => "watch stream ended".to_string(),
+            Err(e) => e.to_string(),
+        };
+        error!(
+            cluster = %cluster,
+            watch = kind.as_str(),
+            error = %error,
+            "Watcher failed, restarting in 5s"
+        );
+        registry.record_watch_error(cluster, kind, &error);
+        registry.record_watcher_restart(cluster, kind);
+        tokio::time::sleep(WATCHER_RESTART_DELAY).await;
+    }
+}
+
+/// Namespaces covered by one watch stream.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum WatchScope {
+    /// Every namespace, which needs cluster-wide list/watch RBAC
+    Cluster,
+    /// One namespace, which only needs a namespaced Role
+    Namespace(String),
+}
+
+impl WatchScope {
+    /// One scope per namespace of `namespaces`, or the whole cluster if
+    /// there are none.
+    pub fn for_namespaces(namespaces: &[String]) -> Vec<Self> {
+        let mut seen = HashSet::new();
+        let scopes: Vec<Self> = namespaces
+            .iter()
+            .map(|namespace| namespace.trim())
+            .filter(|namespace| !namespace.is_empty() && seen.insert(*namespace))
+            .map(|namespace| Self::Namespace(namespace.to_string()))
+            .collect();
+        if scopes.is_empty() {
+            vec![Self::Cluster]
+        } else {
+            scopes
+        }
+    }
+
+    /// The namespace, unless the scope is the whole cluster
+    pub fn namespace(&self) -> Option<&str> {
+        match self {
+            Self::Cluster => None,
+            Self::Namespace(namespace) => Some(namespace),
+        }
+    }
+
+    /// API of the resources `K` in the scope.
+    pub fn api<K>(&self, client: Client) -> Api<K>
+    where
+        K: Resource<Scope = NamespaceResourceScope>,
+        K::DynamicType: Default,
+    {
+        match self {
+            Self::Cluster => Api::all(client),
+            Self::Namespace(namespace) => Api::namespaced(client, namespace),
+        }
+    }
+}
+
+/// Scopes of a watcher that haven't completed their initial list, so the
+/// watch only counts as synced once all of them have.
+#[derive(Debug, Default)]
+struct PendingScopes(Mutex<HashSet<WatchScope>>);
+
+impl PendingScopes {
+    /// Start over with every scope of `scopes` pending, before their
+    /// streams open.
+    fn reset(&self, scopes: &[WatchScope]) {
+        *self.0.lock().unwrap() = scopes.iter().cloned().collect();
+    }
+
+    /// Record that `scope` is relisting.
+    fn relist(&self, scope: &WatchScope) {
+        self.0.lock().unwrap().insert(scope.clone());
+    }
+
+    /// Record that `scope` completed its initial list. Returns whether every
+    /// scope has.
+    fn synced(&self, scope: &WatchScope) -> bool {
+        let mut pending = self.0.lock().unwrap();
+        pending.remove(scope);
+        pending.is_empty()
+    }
+}
+
+/// HTTP status of a watch whose `resourceVersion` is too old to resume from
+const GONE: u16 = 410;
+
+/// What a watch stream of one scope yields: the events of the kube watcher,
+/// or news of its resumption from a bookmark.
+#[derive(Debug)]
+pub enum WatchItem<K> {
+    /// A watch event, as from the kube watcher
+    Event(std::result::Result<Event<K>, watcher::Error>),
+    /// The watch resumed from the scope's bookmark: the entries restored
+    /// for the scope stand, and only changes since come as events
+    Resumed,
+    /// The watch got to this `resourceVersion`
+    Bookmark(String),
+    /// The watch can't resume (410 Gone): the scope is listed again
+    Expired,
+}
+
+impl<K> WatchItem<K> {
+    /// The item with its event's object converted by `f`
+    fn map<T>(self, f: impl FnOnce(Event<K>) -> Event<T>) -> WatchItem<T> {
+        match self {
+            Self::Event(event) => WatchItem::Event(event.map(f)),
+            Self::Resumed => WatchItem::Resumed,
+            Self::Bookmark(resource_version) => WatchItem::Bookmark(resource_version),
+            Self::Expired => WatchItem::Expired,
+        }
+    }
+}
+
+/// Where a resumable watch stream is at (see [`resumable_stream`])
+enum WatchPhase<K> {
+    /// Watch from `resource_version`, after [`WATCHER_RESTART_DELAY`] if
+    /// `retry`, yielding [`WatchItem::Resumed`] once connected if `resumed`
+    Connect {
+        resource_version: String,
+        resumed: bool,
+        retry: bool,
+    },
+    /// Watching, having got to `resource_version`
+    Watching {
+        resource_version: String,
+        events: BoxStream<'static, kube::Result<WatchEvent<K>>>,
+    },
+    /// Listing the scope with the kube watcher, `newest` being the highest
+    /// `resourceVersion` listed so far
+    Listing {
+        events: BoxStream<'static, std::result::Result<Event<K>, watcher::Error>>,
+        newest: Option<u64>,
+    },
+    /// Listed up to `resourceVersion`, which is yielded before watching on
+    Listed(String),
+}
+
+/// Watch parameters matching the selectors of `config`, with bookmark
+/// events asked for so the stream has a position to save even while
+/// nothing in the scope changes.
+fn watch_params(config: &watcher::Config) -> WatchParams {
+    let mut params = WatchParams::default();
+    if let Some(labels) = &config.label_selector {
+        params = params.labels(labels);
+    }
+    if let Some(fields) = &config.field_selector {
+        params = params.fields(fields);
+    }
+    params.bookmarks = true;
+    params
+}
+
+/// Watch stream of `api` that resumes from `bookmark` if there is one, and
+/// lists with the kube watcher otherwise or once the bookmark has expired.
+///
+/// After a list, the stream watches on by itself from the newest object
+/// listed, so it sees the bookmarks the kube watcher keeps to itself.
+/// `resourceVersion`s are opaque to clients, but etcd's are increasing
+/// integers. If the scope is empty or its versions aren't integers, the
+/// kube watcher carries on alone and the scope is listed again after each
+/// restart, as without bookmarks.
+fn resumable_stream<K>(
+    api: Api<K>,
+    config: watcher::Config,
+    bookmark: Option<String>,
+) -> impl Stream<Item = WatchItem<K>>
+where
+    K: Resource + Clone + std::fmt::Debug + serde::de::DeserializeOwned + Send + 'static,
+{
+    let params = watch_params(&config);
+    let start = match bookmark {
+        Some(resource_version) => WatchPhase::Connect {
+            resource_version,
+            resumed: true,
+            retry: false,
+        },
+        None => list(&api, &config),
+    };
+    stream::unfold(start, move |phase| {
+        let api = api.clone();
+        let config = config.clone();
+        let params = params.clone();
+        async move { next_item(&api, &config, &params, phase).await }
+    })
+}
+
+/// Start listing the scope of `api`.
+fn list<K>(api: &Api<K>, config: &watcher::Config) -> WatchPhase<K>
+where
+    K: Resource + Clone + std::fmt::Debug + serde::de::DeserializeOwned + Send + 'static,
+{
+    WatchPhase::Listing {
+        events: watcher(api.clone(), config.clone())
+            .default_backoff()
+            .boxed(),
+        newest: None,
+    }
+}
+
+/// Next item of a resumable watch stream in `phase`, and the phase after it
+async fn next_item<K>(
+    api: &Api<K>,
+    config: &watcher::Config,
+    params: &WatchParams,
+    mut phase: WatchPhase<K>,
+) -> Option<(WatchItem<K>, WatchPhase<K>)>
+where
+    K: Resource + Clone + std::fmt::Debug + serde::de::DeserializeOwned + Send + 'static,
+{
+    loop {
+        phase = match phase {
+            WatchPhase::Connect {
+                resource_version,
+                resumed,
+                retry,
+            } => {
+                if retry {
+                    tokio::time::sleep(WATCHER_RESTART_DELAY).await;
+                }
+                match api.watch(params, &resource_version).await {
+                    Ok(events) => {
+                        let watching = WatchPhase::Watching {
+                            resource_version,
+                            events: events.boxed(),
+                        };
+                        if resumed {
+                            return Some((WatchItem::Resumed, watching));
+                        }
+                        watching
+                    }
+                    Err(kube::Error::Api(e)) if e.code == GONE => {
+                        return Some((WatchItem::Expired, list(api, config)));
+                    }
+                    Err(e) => {
+                        let error = watcher::Error::WatchStartFailed(e);
+                        let retry = WatchPhase::Connect {
+                            resource_version,
+                            resumed,
+                            retry: true,
+                        };
+                        return Some((WatchItem::Event(Err(error)), retry));
+                    }
+                }
+            }
+            WatchPhase::Watching {
+                resource_version,
+                mut events,
+            } => match events.next().await {
+                Some(Ok(WatchEvent::Added(object) | WatchEvent::Modified(object))) => {
+                    let resource_version = object
+                        .meta()
+                        .resource_version
+                        .clone()
+                        .unwrap_or(resource_version);
+                    let item = WatchItem::Event(Ok(Event::Apply(object)));
+                    return Some((
+                        item,
+                        WatchPhase::Watching {
+                            resource_version,
+                            events,
+                        },
+                    ));
+                }
+                Some(Ok(WatchEvent::Deleted(object))) => {
+                    let resource_version = object
+                        .meta()
+                        .resource_version
+                        .clone()
+                        .unwrap_or(resource_version);
+                    let item = WatchItem::Event(Ok(Event::Delete(object)));
+                    return Some((
+                        item,
+                        WatchPhase::Watching {
+                            resource_version,
+                            events,
+                        },
+                    ));
+                }
+                Some(Ok(WatchEvent::Bookmark(bookmark))) => {
+                    let resource_version = bookmark.metadata.resource_version;
+                    let item = WatchItem::Bookmark(resource_version.clone());
+                    return Some((
+                        item,
+                        WatchPhase::Watching {
+                            resource_version,
+                            events,
+                        },
+                    ));
+                }
+                Some(Ok(WatchEvent::Error(e))) if e.code == GONE => {
+                    return Some((WatchItem::Expired, list(api, config)));
+                }
+                Some(Ok(WatchEvent::Error(e))) => {
+                    warn!(code = e.code, error = %e.message, "Watch failed, resuming");
+                    WatchPhase::Connect {
+                        resource_version,
+                        resumed: false,
+                        retry: true,
+                    }
+                }
+                Some(Err(e)) => {
+                    let retry = WatchPhase::Connect {
+                        resource_version,
+                        resumed: false,
+                        retry: true,
+                    };
+                    return Some((WatchItem::Event(Err(watcher::Error::WatchFailed(e))), retry));
+                }
+                // The API server ends watches after a while
+                None => WatchPhase::Connect {
+                    resource_version,
+                    resumed: false,
+                    retry: false,
+                },
+            },
+            WatchPhase::Listing { mut events, newest } => {
+                let event = events.next().await?;
+                let next = match &event {
+                    Ok(Event::Init) => WatchPhase::Listing {
+                        events,
+                        newest: None,
+                    },
+                    Ok(Event::InitApply(object)) => {
+                        let listed = object
+                            .meta()
+                            .resource_version
+                            .as_deref()
+                            .and_then(|resource_version| resource_version.parse().ok());
+                        let newest = newest.max(listed);
+                        WatchPhase::Listing { events, newest }
+                    }
+                    Ok(Event::InitDone) => match newest {
+                        Some(newest) => WatchPhase::Listed(newest.to_string()),
+                        None => WatchPhase::Listing { events, newest },
+                    },
+                    _ => WatchPhase::Listing { events, newest },
+                };
+                return Some((WatchItem::Event(event), next));
+            }
+            WatchPhase::Listed(resource_version) => {
+                let item = WatchItem::Bookmark(resource_version.clone());
+                let connect = WatchPhase::Connect {
+                    resource_version,
+                    resumed: false,
+                    retry: false,
+                };
+                return Some((item, connect));
+            }
+        };
+    }
+}
+
+/// Merge one resumable watch stream per scope (see [`resumable_stream`]),
+/// tagging each item with its scope. Scopes resume from the bookmark
+/// `bookmark` returns for them.
+fn resumable_streams<K>(
+    scopes: &[WatchScope],
+    client: &Client,
+    config: &watcher::Config,
+    bookmark: impl Fn(&WatchScope) -> Option<String>,
+) -> impl Stream<Item = (WatchScope, WatchItem<K>)>
+where
+    K: Resource<Scope = NamespaceResourceScope>
+        + Clone
+        + std::fmt::Debug
+        + serde::de::DeserializeOwned
+        + Send
+        + 'static,
+    K::DynamicType: Default,
+{
+    stream::select_all(scopes.iter().map(|scope| {
+        let items = resumable_stream(
+            scope.api::<K>(client.clone()),
+            config.clone(),
+            bookmark(scope),
+        );
+        let scope = scope.clone();
+        items.map(move |item| (scope.clone(), item)).boxed()
+    }))
+}
+
+/// Requests for the watchers to list everything again, as after a restart.
+///
+/// Watchers given the signal drop their watch streams when it is raised and
+/// open new ones, whose initial list replaces the registry entries of their
+/// scopes, undoing any drift from the cluster's state.
+#[derive(Clone)]
+pub struct ResyncSignal {
+    requests: Arc<tokio::sync::watch::Sender<u64>>,
+}
+
+impl Default for ResyncSignal {
+    fn default() -> Self {
+        Self {
+            requests: Arc::new(tokio::sync::watch::Sender::new(0)),
+        }
+    }
+}
+
+impl ResyncSignal {
+    /// Ask every running watcher to resync. Returns how many are listening.
+    pub fn request(&self) -> usize {
+        self.requests.send_modify(|requests| *requests += 1);
+        self.requests.receiver_count()
+    }
+
+    /// Listen for requests made from now on.
+    pub fn subscribe(&self) -> ResyncListener {
+        ResyncListener(self.requests.subscribe())
+    }
+}
+
+/// One watcher's end of a [`ResyncSignal`].
+pub struct ResyncListener(tokio::sync::watch::Receiver<u64>);
+
+impl ResyncListener {
+    /// Wait for the next request.
+    pub async fn requested(&mut self) {
+        if self.0.changed().await.is_err() {
+            // The signal is gone, so no request will come
+            future::pending::<()>().await;
+        }
+    }
+}
+
+/// Run `watch` until it ends or `listener` hears a resync request. Returns
+/// whether a resync was requested.
+async fn until_resync(
+    listener: Option<&mut ResyncListener>,
+    watch: impl Future<Output = ()>,
+) -> bool {
+    let Some(listener) = listener else {
+        watch.await;
+        return false;
+    };
+    let watch = std::pin::pin!(watch);
+    let requested = std::pin::pin!(listener.requested());
+    matches!(
+        future::select(watch, requested).await,
+        future::Either::Right(_)
+    )
+}
+
+/// Event of a slim Devbox watch as the full type the watcher handles
+fn from_slim(event: Event<SlimDevbox>) -> Event<Devbox> {
+    match event {
+        Event::Apply(devbox) => Event::Apply(devbox.into()),
+        Event::Delete(devbox) => Event::Delete(devbox.into()),
+        Event::Init => Event::Init,
+        Event::InitApply(devbox) => Event::InitApply(devbox.into()),
+        Event::InitDone => Event::InitDone,
+    }
+}
+
 // ============================================================================
 // Devbox CRD Watcher
 // ============================================================================
 
 /// Kubernetes watcher for Devbox CRD resources.
 ///
-/// Watches all Devbox CRDs across all namespaces and maintains
-/// a registry of uniqueID -> (namespace, devbox_name) mappings.
+/// Watches all Devbox CRDs across all namespaces of one cluster (or across
+/// the configured ones) and maintains a registry of uniqueID -> (cluster,
+/// namespace, devbox_name) mappings.
 pub struct DevboxWatcher {
     registry: Arc<DevboxRegistry>,
+    cluster: ClusterConfig,
+    cluster_name: Arc<str>,
+    scopes: Vec<WatchScope>,
+    /// Scopes yet to complete their initial list
+    pending: PendingScopes,
+    /// Status field the pod IPs are read from, if Pods are not watched
+    pod_ip_status_field: Option<String>,
+    watch_mode: DevboxWatchMode,
+    resync: Option<ResyncSignal>,
+    /// When the full registry was last warned about
+    full_warned_at: Mutex<Option<Instant>>,
 }
 
 impl DevboxWatcher {
-    pub const fn new(registry: Arc<DevboxRegistry>) -> Self {
-        Self { registry }
+    /// Watcher of the cluster the gateway runs in.
+    pub fn new(registry: Arc<DevboxRegistry>) -> Self {
+        Self {
+            registry,
+            cluster: ClusterConfig::default(),
+            cluster_name: Arc::from(ClusterConfig::default().name),
+            scopes: vec![WatchScope::Cluster],
+            pending: PendingScopes::default(),
+            pod_ip_status_field: None,
+            watch_mode: DevboxWatchMode::Full,
+            resync: None,
+            full_warned_at: Mutex::new(None),
+        }
+    }
+
+    /// Watch `cluster` instead.
+    #[must_use]
+    pub fn with_cluster(mut self, cluster: ClusterConfig) -> Self {
+        self.cluster_name = Arc::from(cluster.name.as_str());
+        self.cluster = cluster;
+        self
+    }
+
+    /// Watch one namespace per entry of `namespaces` instead of the whole
+    /// cluster, for deployments without cluster-wide RBAC. An empty list
+    /// keeps the cluster-wide watch.
+    #[must_use]
+    pub fn with_namespaces(mut self, namespaces: &[String]) -> Self {
+        self.scopes = WatchScope::for_namespaces(namespaces);
+        self
+    }
+
+    /// Scopes of the watch streams [`Self::run`] opens
+    pub fn scopes(&self) -> &[WatchScope] {
+        &self.scopes
+    }
+
+    /// Maintain the Pod index too, from the status field at the
+    /// dot-separated `path` (see [`Devbox::status_field`]).
+    ///
+    /// For setups that record the serving IP in the Devbox status, so no
+    /// [`PodWatcher`] is needed. The watcher then also stands in for the
+    /// Pod watch in the registry's watch health.
+    #[must_use]
+    pub fn with_pod_ip_status_field(mut self, path: String) -> Self {
+        self.pod_ip_status_field = Some(path);
+        self
+    }
+
+    /// Deserialize watched Devboxes as `mode` says.
+    ///
+    /// [`DevboxWatchMode::Slim`] cuts the memory each Devbox of a list page
+    /// or watch event takes, in clusters with many of them. It falls back to
+    /// full objects when reading pod IPs from a status field, which the slim
+    /// type leaves out.
+    #[must_use]
+    pub fn with_watch_mode(mut self, mode: DevboxWatchMode) -> Self {
+        self.watch_mode = mode;
+        self
+    }
+
+    /// List everything again whenever `signal` is raised.
+    #[must_use]
+    pub fn with_resync(mut self, signal: ResyncSignal) -> Self {
+        self.resync = Some(signal);
+        self
+    }
+
+    /// Mode [`Self::run`] watches in
+    pub fn watch_mode(&self) -> DevboxWatchMode {
+        match self.watch_mode {
+            DevboxWatchMode::Slim if self.pod_ip_status_field.is_some() => DevboxWatchMode::Full,
+            mode => mode,
+        }
+    }
+
+    /// Watches whose health this watcher records
+    fn watch_kinds(&self) -> &'static [WatchKind] {
+        if self.pod_ip_status_field.is_some() {
+            &[WatchKind::Devboxes, WatchKind::Pods]
+        } else {
+            &[WatchKind::Devboxes]
+        }
     }
 
     /// Start watching Devbox resources.
@@ -68,46 +818,285 @@ impl DevboxWatcher {
     /// This function runs indefinitely, processing watch events.
     /// It should be spawned as a background task.
     pub async fn run(&self) -> Result<()> {
-        let client = create_client().await?;
-        let devboxes: Api<Devbox> = Api::all(client);
+        let client = create_cluster_client(&self.cluster).await?;
 
-        info!("Starting Devbox CRD watcher");
+        let mode = self.watch_mode();
+        if mode != self.watch_mode {
+            warn!(
+                cluster = %self.cluster_name,
+                "Watching full Devboxes: POD_IP_STATUS_FIELD needs fields the slim watch leaves out"
+            );
+        }
+        info!(
+            cluster = %self.cluster_name,
+            namespaces = ?self.scopes.iter().filter_map(WatchScope::namespace).collect::<Vec<_>>(),
+            mode = ?mode,
+            "Starting Devbox CRD watcher"
+        );
 
         let watcher_config = watcher::Config::default();
-        let mut stream = watcher(devboxes, watcher_config).default_backoff().boxed();
-
-        while let Some(event) = stream.next().await {
-            self.handle_event(event);
+        let bookmark = |scope: &WatchScope| {
+            self.registry
+                .bookmark(&self.cluster_name, WatchKind::Devboxes, scope.namespace())
+        };
+        match mode {
+            DevboxWatchMode::Full => {
+                self.run_with_item_streams(|| {
+                    resumable_streams::<Devbox>(&self.scopes, &client, &watcher_config, &bookmark)
+                })
+                .await;
+            }
+            DevboxWatchMode::Slim => {
+                self.run_with_item_streams(|| {
+                    resumable_streams::<SlimDevbox>(
+                        &self.scopes,
+                        &client,
+                        &watcher_config,
+                        &bookmark,
+                    )
+                    .map(|(scope, item)| (scope, item.map(from_slim)))
+                })
+                .await;
+            }
         }
 
-        warn!("Devbox CRD watcher stream ended unexpectedly");
+        warn!(cluster = %self.cluster_name, "Devbox CRD watcher stream ended unexpectedly");
         Ok(())
     }
 
-    fn handle_event(&self, event: std::result::Result<Event<Devbox>, watcher::Error>) {
+    /// Apply the watch events of the streams `open` returns to the
+    /// registry, until they end.
+    ///
+    /// With [`Self::with_resync`], the streams are dropped on each resync
+    /// request and new ones opened, whose initial list starts over.
+    pub async fn run_with_streams<F, S>(&self, mut open: F)
+    where
+        F: FnMut() -> S,
+        S: Stream<
+            Item = (
+                WatchScope,
+                std::result::Result<Event<Devbox>, watcher::Error>,
+            ),
+        >,
+    {
+        self.run_with_item_streams(|| {
+            open().map(|(scope, event)| (scope, WatchItem::Event(event)))
+        })
+        .await;
+    }
+
+    /// Apply the items of the streams `open` returns to the registry, until
+    /// they end (see [`Self::run_with_streams`]).
+    ///
+    /// The scopes' bookmarks are forgotten on each resync request, so the
+    /// new streams list rather than resume.
+    pub async fn run_with_item_streams<F, S>(&self, mut open: F)
+    where
+        F: FnMut() -> S,
+        S: Stream<Item = (WatchScope, WatchItem<Devbox>)>,
+    {
+        let mut listener = self.resync.as_ref().map(ResyncSignal::subscribe);
+        loop {
+            self.pending.reset(&self.scopes);
+            if !until_resync(listener.as_mut(), self.run_with_scoped_items(open())).await {
+                return;
+            }
+            info!(cluster = %self.cluster_name, "Relisting Devboxes on request");
+            for scope in &self.scopes {
+                self.registry.forget_bookmark(
+                    &self.cluster_name,
+                    WatchKind::Devboxes,
+                    scope.namespace(),
+                );
+            }
+        }
+    }
+
+    /// Apply the watch events of a cluster-wide watch from `stream` to the
+    /// registry until it ends.
+    ///
+    /// Tests feed this scripted event sequences.
+    pub async fn run_with_stream<S>(&self, stream: S)
+    where
+        S: Stream<Item = std::result::Result<Event<Devbox>, watcher::Error>>,
+    {
+        let stream = stream.map(|event| (WatchScope::Cluster, event));
+        self.run_with_scoped_stream(stream).await;
+    }
+
+    /// Apply watch events from `stream`, each tagged with its scope, to the
+    /// registry until it ends.
+    ///
+    /// [`Self::run`] feeds this from the Kubernetes API, after marking every
+    /// scope as not yet listed: the watch is synced once none is relisting.
+    pub async fn run_with_scoped_stream<S>(&self, stream: S)
+    where
+        S: Stream<
+            Item = (
+                WatchScope,
+                std::result::Result<Event<Devbox>, watcher::Error>,
+            ),
+        >,
+    {
+        let stream = stream.map(|(scope, event)| (scope, WatchItem::Event(event)));
+        self.run_with_scoped_items(stream).await;
+    }
+
+    /// Apply items from `stream`, each tagged with its scope, to the
+    /// registry until it ends.
+    ///
+    /// Events move the scope's bookmark to their object's
+    /// `resourceVersion` once applied, so it never runs ahead of the
+    /// registry. Tests feed this resumptions and expired bookmarks.
+    pub async fn run_with_scoped_items<S>(&self, stream: S)
+    where
+        S: Stream<Item = (WatchScope, WatchItem<Devbox>)>,
+    {
+        let mut stream = std::pin::pin!(stream);
+        while let Some((scope, item)) = stream.next().await {
+            self.handle_item(&scope, item);
+        }
+    }
+
+    fn handle_item(&self, scope: &WatchScope, item: WatchItem<Devbox>) {
+        let cluster = &*self.cluster_name;
+        let namespace = scope.namespace();
+        match item {
+            WatchItem::Event(event) => {
+                let resource_version = match &event {
+                    Ok(Event::Apply(devbox) | Event::Delete(devbox)) => {
+                        devbox.metadata.resource_version.clone()
+                    }
+                    _ => None,
+                };
+                self.handle_event(scope, event);
+                if let Some(resource_version) = resource_version {
+                    self.registry.record_bookmark(
+                        cluster,
+                        WatchKind::Devboxes,
+                        namespace,
+                        &resource_version,
+                    );
+                }
+            }
+            WatchItem::Resumed => {
+                info!(
+                    cluster = %cluster,
+                    namespace = ?namespace,
+                    "Devbox watcher resumed from bookmark, keeping restored devboxes"
+                );
+                self.scope_synced(scope);
+            }
+            WatchItem::Bookmark(resource_version) => {
+                self.registry.record_bookmark(
+                    cluster,
+                    WatchKind::Devboxes,
+                    namespace,
+                    &resource_version,
+                );
+            }
+            WatchItem::Expired => {
+                warn!(
+                    cluster = %cluster,
+                    namespace = ?namespace,
+                    "Devbox watch bookmark expired, listing again"
+                );
+                self.registry
+                    .forget_bookmark(cluster, WatchKind::Devboxes, namespace);
+            }
+        }
+    }
+
+    fn handle_event(
+        &self,
+        scope: &WatchScope,
+        event: std::result::Result<Event<Devbox>, watcher::Error>,
+    ) {
+        let cluster = &*self.cluster_name;
+        let namespace = scope.namespace();
         match event {
             Ok(Event::Apply(devbox) | Event::InitApply(devbox)) => {
+                for &kind in self.watch_kinds() {
+                    self.registry.record_watch_event(cluster, kind);
+                }
                 self.handle_apply(&devbox);
+                self.warn_if_full();
             }
             Ok(Event::Delete(devbox)) => {
+                for &kind in self.watch_kinds() {
+                    self.registry.record_watch_event(cluster, kind);
+                }
                 self.handle_delete(&devbox);
             }
             Ok(Event::Init) => {
-                info!("Devbox watcher initializing, clearing devbox registry");
-                self.registry.clear_devboxes();
-            }
-            Ok(Event::InitDone) => {
                 info!(
-                    count = self.registry.devbox_count(),
-                    "Devbox watcher initialization complete"
+                    cluster = %cluster,
+                    namespace = ?namespace,
+                    "Devbox watcher initializing, clearing devbox registry"
                 );
+                self.pending.relist(scope);
+                for &kind in self.watch_kinds() {
+                    self.registry.record_watch_init(cluster, kind);
+                }
+                self.registry
+                    .forget_bookmark(cluster, WatchKind::Devboxes, namespace);
+                self.registry.clear_devboxes_in(cluster, namespace);
+                if self.pod_ip_status_field.is_some() {
+                    self.registry.clear_pod_ips_in(cluster, namespace);
+                }
             }
+            Ok(Event::InitDone) => self.scope_synced(scope),
             Err(e) => {
-                error!(error = %e, "Devbox watcher error");
+                error!(cluster = %cluster, namespace = ?namespace, error = %e, "Devbox watcher error");
+                for &kind in self.watch_kinds() {
+                    self.registry
+                        .record_watch_error(cluster, kind, &e.to_string());
+                }
             }
         }
     }
 
+    /// Record that `scope` is listed or resumed, and the watch synced once
+    /// every scope is.
+    fn scope_synced(&self, scope: &WatchScope) {
+        let cluster = &*self.cluster_name;
+        if !self.pending.synced(scope) {
+            debug!(cluster = %cluster, namespace = ?scope.namespace(), "Devbox watcher namespace listed");
+            return;
+        }
+        for &kind in self.watch_kinds() {
+            self.registry.record_watch_synced(cluster, kind);
+        }
+        info!(
+            cluster = %cluster,
+            count = self.registry.devbox_count(),
+            "Devbox watcher initialization complete"
+        );
+        self.warn_if_full();
+    }
+
+    /// Warn, at most once per [`REGISTRY_FULL_WARNING_INTERVAL`], while the
+    /// registry refuses registrations, so the condition shows up in the logs
+    /// for as long as it lasts.
+    fn warn_if_full(&self) {
+        if !self.registry.is_full() {
+            return;
+        }
+        let now = Instant::now();
+        let mut warned_at = self.full_warned_at.lock().unwrap();
+        if warned_at.is_some_and(|at| now.duration_since(at) < REGISTRY_FULL_WARNING_INTERVAL) {
+            return;
+        }
+        *warned_at = Some(now);
+        let usage = self.registry.usage();
+        error!(
+            cluster = %self.cluster_name,
+            entries = usage.entries,
+            max_entries = usage.max_entries,
+            "Devbox registry is full: new Devboxes are not routed until some are deleted or MAX_REGISTRY_ENTRIES is raised"
+        );
+    }
+
     fn handle_apply(&self, devbox: &Devbox) {
         let Some(unique_id) = devbox.unique_id() else {
             warn!(
@@ -134,27 +1123,76 @@ impl DevboxWatcher {
             return;
         };
 
-        let is_new = self.registry.register_devbox(
-            unique_id.to_string(),
+        let mut info = DevboxInfo::in_cluster(
+            Arc::clone(&self.cluster_name),
             namespace.clone(),
             devbox_name.clone(),
         );
+        if let Some(annotations) = devbox.metadata.annotations.as_ref() {
+            info.policy = Arc::new(DevboxPolicy::from_annotations(annotations));
+        }
+        info.app_port = devbox.app_port();
+
+        let is_new = self
+            .registry
+            .register_devbox_info(unique_id.to_string(), info);
 
         if is_new {
             info!(
                 unique_id = %unique_id,
+                cluster = %self.cluster_name,
                 namespace = %namespace,
                 devbox_name = %devbox_name,
                 "Devbox registered"
             );
         }
+
+        // An empty or missing IP clears the entry, as for a Pod without one
+        if let Some(path) = &self.pod_ip_status_field {
+            let pod_ip = devbox.status_field(path).unwrap_or_default();
+            if let Err(e) = self.registry.update_pod_endpoint(
+                &self.cluster_name,
+                namespace,
+                devbox_name,
+                pod_ip.clone(),
+                None,
+            ) {
+                warn!(
+                    cluster = %self.cluster_name,
+                    namespace = %namespace,
+                    devbox_name = %devbox_name,
+                    pod_ip = %pod_ip,
+                    error = %e,
+                    "Ignoring invalid Pod IP"
+                );
+            }
+        }
     }
 
     fn handle_delete(&self, devbox: &Devbox) {
-        if let Some(unique_id) = devbox.unique_id() {
-            if self.registry.unregister_devbox(unique_id) {
-                info!(unique_id = %unique_id, "Devbox unregistered");
-            }
+        let Some(unique_id) = devbox.unique_id() else {
+            return;
+        };
+        let (Some(namespace), Some(devbox_name)) = (
+            devbox.metadata.namespace.clone(),
+            devbox.metadata.name.clone(),
+        ) else {
+            return;
+        };
+
+        if self.pod_ip_status_field.is_some() {
+            self.registry
+                .clear_pod_ip(&self.cluster_name, &namespace, &devbox_name);
+        }
+
+        // Only if the entry is this devbox, not a conflicting one elsewhere
+        let info = DevboxInfo::in_cluster(Arc::clone(&self.cluster_name), namespace, devbox_name);
+        if self.registry.unregister_devbox_info(unique_id, &info) {
+            info!(
+                unique_id = %unique_id,
+                cluster = %self.cluster_name,
+                "Devbox unregistered"
+            );
         }
     }
 }
@@ -166,14 +1204,66 @@ impl DevboxWatcher {
 /// Kubernetes watcher for Devbox Pods.
 ///
 /// Watches all Pods with label `app.kubernetes.io/part-of=devbox` across all namespaces
-/// and updates the registry with Pod IP information.
+/// of one cluster (or across the configured ones) and updates the registry
+/// with Pod IP information.
 pub struct PodWatcher {
     registry: Arc<DevboxRegistry>,
+    cluster: ClusterConfig,
+    scopes: Vec<WatchScope>,
+    /// Scopes yet to complete their initial list
+    pending: PendingScopes,
+    require_ready: bool,
+    resync: Option<ResyncSignal>,
 }
 
 impl PodWatcher {
-    pub const fn new(registry: Arc<DevboxRegistry>) -> Self {
-        Self { registry }
+    /// Watcher of the cluster the gateway runs in.
+    pub fn new(registry: Arc<DevboxRegistry>) -> Self {
+        Self {
+            registry,
+            cluster: ClusterConfig::default(),
+            scopes: vec![WatchScope::Cluster],
+            pending: PendingScopes::default(),
+            require_ready: false,
+            resync: None,
+        }
+    }
+
+    /// Only register a Pod's IP once its `Ready` condition is true.
+    ///
+    /// A Pod gets its IP before its readiness probe passes; until then the
+    /// devbox has no backend and requests wait as for a stopped devbox.
+    #[must_use]
+    pub fn with_require_ready(mut self, require: bool) -> Self {
+        self.require_ready = require;
+        self
+    }
+
+    /// Watch `cluster` instead.
+    #[must_use]
+    pub fn with_cluster(mut self, cluster: ClusterConfig) -> Self {
+        self.cluster = cluster;
+        self
+    }
+
+    /// Watch one namespace per entry of `namespaces` instead of the whole
+    /// cluster (see [`DevboxWatcher::with_namespaces`]).
+    #[must_use]
+    pub fn with_namespaces(mut self, namespaces: &[String]) -> Self {
+        self.scopes = WatchScope::for_namespaces(namespaces);
+        self
+    }
+
+    /// Scopes of the watch streams [`Self::run`] opens
+    pub fn scopes(&self) -> &[WatchScope] {
+        &self.scopes
+    }
+
+    /// List everything again whenever `signal` is raised.
+    #[must_use]
+    pub fn with_resync(mut self, signal: ResyncSignal) -> Self {
+        self.resync = Some(signal);
+        self
     }
 
     /// Start watching Devbox Pods.
@@ -181,49 +1271,212 @@ impl PodWatcher {
     /// This function runs indefinitely, processing watch events.
     /// It should be spawned as a background task.
     pub async fn run(&self) -> Result<()> {
-        let client = create_client().await?;
-        let pods: Api<Pod> = Api::all(client);
+        let client = create_cluster_client(&self.cluster).await?;
 
-        info!("Starting Pod watcher for devbox pods");
+        info!(
+            cluster = %self.cluster.name,
+            namespaces = ?self.scopes.iter().filter_map(WatchScope::namespace).collect::<Vec<_>>(),
+            "Starting Pod watcher for devbox pods"
+        );
 
         // Filter pods by label: app.kubernetes.io/part-of=devbox
         let label_selector = format!("{DEVBOX_PART_OF_LABEL}={DEVBOX_PART_OF_VALUE}");
         let watcher_config = watcher::Config::default().labels(&label_selector);
 
-        let mut stream = watcher(pods, watcher_config).default_backoff().boxed();
+        let bookmark = |scope: &WatchScope| {
+            self.registry
+                .bookmark(&self.cluster.name, WatchKind::Pods, scope.namespace())
+        };
+        self.run_with_item_streams(|| {
+            resumable_streams::<Pod>(&self.scopes, &client, &watcher_config, &bookmark)
+        })
+        .await;
 
-        while let Some(event) = stream.next().await {
-            self.handle_event(event);
+        warn!(cluster = %self.cluster.name, "Pod watcher stream ended unexpectedly");
+        Ok(())
+    }
+
+    /// Apply the watch events of the streams `open` returns to the
+    /// registry, until they end.
+    ///
+    /// See [`DevboxWatcher::run_with_streams`].
+    pub async fn run_with_streams<F, S>(&self, mut open: F)
+    where
+        F: FnMut() -> S,
+        S: Stream<Item = (WatchScope, std::result::Result<Event<Pod>, watcher::Error>)>,
+    {
+        self.run_with_item_streams(|| {
+            open().map(|(scope, event)| (scope, WatchItem::Event(event)))
+        })
+        .await;
+    }
+
+    /// Apply the items of the streams `open` returns to the registry, until
+    /// they end.
+    ///
+    /// See [`DevboxWatcher::run_with_item_streams`].
+    pub async fn run_with_item_streams<F, S>(&self, mut open: F)
+    where
+        F: FnMut() -> S,
+        S: Stream<Item = (WatchScope, WatchItem<Pod>)>,
+    {
+        let mut listener = self.resync.as_ref().map(ResyncSignal::subscribe);
+        loop {
+            self.pending.reset(&self.scopes);
+            if !until_resync(listener.as_mut(), self.run_with_scoped_items(open())).await {
+                return;
+            }
+            info!(cluster = %self.cluster.name, "Relisting Pods on request");
+            for scope in &self.scopes {
+                self.registry.forget_bookmark(
+                    &self.cluster.name,
+                    WatchKind::Pods,
+                    scope.namespace(),
+                );
+            }
         }
+    }
 
-        warn!("Pod watcher stream ended unexpectedly");
-        Ok(())
+    /// Apply the watch events of a cluster-wide watch from `stream` to the
+    /// registry until it ends.
+    ///
+    /// See [`DevboxWatcher::run_with_stream`].
+    pub async fn run_with_stream<S>(&self, stream: S)
+    where
+        S: Stream<Item = std::result::Result<Event<Pod>, watcher::Error>>,
+    {
+        let stream = stream.map(|event| (WatchScope::Cluster, event));
+        self.run_with_scoped_stream(stream).await;
+    }
+
+    /// Apply watch events from `stream`, each tagged with its scope, to the
+    /// registry until it ends.
+    ///
+    /// See [`DevboxWatcher::run_with_scoped_stream`].
+    pub async fn run_with_scoped_stream<S>(&self, stream: S)
+    where
+        S: Stream<Item = (WatchScope, std::result::Result<Event<Pod>, watcher::Error>)>,
+    {
+        let stream = stream.map(|(scope, event)| (scope, WatchItem::Event(event)));
+        self.run_with_scoped_items(stream).await;
     }
 
-    fn handle_event(&self, event: std::result::Result<Event<Pod>, watcher::Error>) {
+    /// Apply items from `stream`, each tagged with its scope, to the
+    /// registry until it ends.
+    ///
+    /// See [`DevboxWatcher::run_with_scoped_items`].
+    pub async fn run_with_scoped_items<S>(&self, stream: S)
+    where
+        S: Stream<Item = (WatchScope, WatchItem<Pod>)>,
+    {
+        let mut stream = std::pin::pin!(stream);
+        while let Some((scope, item)) = stream.next().await {
+            self.handle_item(&scope, item);
+        }
+    }
+
+    fn handle_item(&self, scope: &WatchScope, item: WatchItem<Pod>) {
+        let cluster = self.cluster.name.as_str();
+        let namespace = scope.namespace();
+        match item {
+            WatchItem::Event(event) => {
+                let resource_version = match &event {
+                    Ok(Event::Apply(pod) | Event::Delete(pod)) => {
+                        pod.metadata.resource_version.clone()
+                    }
+                    _ => None,
+                };
+                self.handle_event(scope, event);
+                if let Some(resource_version) = resource_version {
+                    self.registry.record_bookmark(
+                        cluster,
+                        WatchKind::Pods,
+                        namespace,
+                        &resource_version,
+                    );
+                }
+            }
+            WatchItem::Resumed => {
+                info!(
+                    cluster = %cluster,
+                    namespace = ?namespace,
+                    "Pod watcher resumed from bookmark, keeping restored pod IPs"
+                );
+                self.scope_synced(scope);
+            }
+            WatchItem::Bookmark(resource_version) => {
+                self.registry.record_bookmark(
+                    cluster,
+                    WatchKind::Pods,
+                    namespace,
+                    &resource_version,
+                );
+            }
+            WatchItem::Expired => {
+                warn!(
+                    cluster = %cluster,
+                    namespace = ?namespace,
+                    "Pod watch bookmark expired, listing again"
+                );
+                self.registry
+                    .forget_bookmark(cluster, WatchKind::Pods, namespace);
+            }
+        }
+    }
+
+    fn handle_event(
+        &self,
+        scope: &WatchScope,
+        event: std::result::Result<Event<Pod>, watcher::Error>,
+    ) {
+        let cluster = self.cluster.name.as_str();
+        let namespace = scope.namespace();
         match event {
             Ok(Event::Apply(pod) | Event::InitApply(pod)) => {
+                self.registry.record_watch_event(cluster, WatchKind::Pods);
                 self.handle_apply(&pod);
             }
             Ok(Event::Delete(pod)) => {
+                self.registry.record_watch_event(cluster, WatchKind::Pods);
                 self.handle_delete(&pod);
             }
             Ok(Event::Init) => {
-                info!("Pod watcher initializing, clearing pod IP registry");
-                self.registry.clear_pod_ips();
-            }
-            Ok(Event::InitDone) => {
                 info!(
-                    count = self.registry.pod_ip_count(),
-                    "Pod watcher initialization complete"
+                    cluster = %cluster,
+                    namespace = ?namespace,
+                    "Pod watcher initializing, clearing pod IP registry"
                 );
+                self.pending.relist(scope);
+                self.registry.record_watch_init(cluster, WatchKind::Pods);
+                self.registry
+                    .forget_bookmark(cluster, WatchKind::Pods, namespace);
+                self.registry.clear_pod_ips_in(cluster, namespace);
             }
+            Ok(Event::InitDone) => self.scope_synced(scope),
             Err(e) => {
-                error!(error = %e, "Pod watcher error");
+                error!(cluster = %cluster, namespace = ?namespace, error = %e, "Pod watcher error");
+                self.registry
+                    .record_watch_error(cluster, WatchKind::Pods, &e.to_string());
             }
         }
     }
 
+    /// Record that `scope` is listed or resumed, and the watch synced once
+    /// every scope is.
+    fn scope_synced(&self, scope: &WatchScope) {
+        let cluster = self.cluster.name.as_str();
+        if !self.pending.synced(scope) {
+            debug!(cluster = %cluster, namespace = ?scope.namespace(), "Pod watcher namespace listed");
+            return;
+        }
+        self.registry.record_watch_synced(cluster, WatchKind::Pods);
+        info!(
+            cluster = %cluster,
+            count = self.registry.pod_ip_count(),
+            "Pod watcher initialization complete"
+        );
+    }
+
     fn handle_apply(&self, pod: &Pod) {
         let Some(namespace) = pod.metadata.namespace.as_ref() else {
             warn!(name = ?pod.metadata.name, "Pod has no namespace, skipping");
@@ -240,14 +1493,39 @@ impl PodWatcher {
             return;
         };
 
-        // Get pod IP from status (may be empty if Pod is not running)
+        // Get pod IP from status (may be empty if Pod is not running). An
+        // unready Pod is treated as having none, clearing any earlier IP
         let pod_ip = pod
             .status
             .as_ref()
+            .filter(|_| !self.require_ready || Self::is_ready(pod))
             .and_then(|s| s.pod_ip.clone())
             .unwrap_or_default();
 
-        self.registry.update_pod_ip(namespace, &devbox_name, pod_ip);
+        // Record the Pod identity so the consistency sweep can re-verify it
+        let pod_ref = pod
+            .metadata
+            .name
+            .clone()
+            .zip(pod.metadata.uid.clone())
+            .map(|(name, uid)| PodRef { name, uid });
+
+        if let Err(e) = self.registry.update_pod_endpoint(
+            &self.cluster.name,
+            namespace,
+            &devbox_name,
+            pod_ip.clone(),
+            pod_ref,
+        ) {
+            warn!(
+                cluster = %self.cluster.name,
+                namespace = %namespace,
+                devbox_name = %devbox_name,
+                pod_ip = %pod_ip,
+                error = %e,
+                "Ignoring invalid Pod IP"
+            );
+        }
     }
 
     fn handle_delete(&self, pod: &Pod) {
@@ -256,10 +1534,23 @@ impl PodWatcher {
         };
 
         if let Some(devbox_name) = Self::get_devbox_name(pod) {
-            self.registry.clear_pod_ip(namespace, &devbox_name);
+            self.registry
+                .clear_pod_ip(&self.cluster.name, namespace, &devbox_name);
         }
     }
 
+    /// Whether the Pod's `Ready` condition is true.
+    fn is_ready(pod: &Pod) -> bool {
+        pod.status
+            .as_ref()
+            .and_then(|s| s.conditions.as_ref())
+            .is_some_and(|conditions| {
+                conditions
+                    .iter()
+                    .any(|c| c.type_ == "Ready" && c.status == "True")
+            })
+    }
+
     /// Extract devbox name from `OwnerReferences`.
     ///
     /// Looks for an `OwnerReference` with kind "Devbox" and returns its name.
@@ -272,3 +1563,426 @@ impl PodWatcher {
             .map(|r| r.name.clone())
     }
 }
+
+// ============================================================================
+// Namespace Limits Watcher
+// ============================================================================
+
+/// Kubernetes watcher for the ConfigMap of per-namespace limit overrides.
+///
+/// Each key of the ConfigMap is a namespace and each value its limits (see
+/// [`limits::NamespaceLimit`]). The overrides are replaced whenever the
+/// ConfigMap changes, and cleared when it is deleted.
+pub struct LimitsWatcher {
+    limiter: Arc<NamespaceLimiter>,
+    namespace: String,
+    name: String,
+    /// Whether the ConfigMap was seen during the current initial list
+    listed: AtomicBool,
+}
+
+impl LimitsWatcher {
+    pub fn new(limiter: Arc<NamespaceLimiter>, namespace: String, name: String) -> Self {
+        Self {
+            limiter,
+            namespace,
+            name,
+            listed: AtomicBool::new(false),
+        }
+    }
+
+    /// Start watching the limits ConfigMap.
+    ///
+    /// This function runs indefinitely, processing watch events.
+    /// It should be spawned as a background task.
+    pub async fn run(&self) -> Result<()> {
+        let client = create_client().await?;
+        let configmaps: Api<ConfigMap> = Api::namespaced(client, &self.namespace);
+
+        info!(
+            namespace = %self.namespace,
+            name = %self.name,
+            "Starting namespace limits watcher"
+        );
+
+        let watcher_config =
+            watcher::Config::default().fields(&format!("metadata.name={}", self.name));
+        let stream = watcher(configmaps, watcher_config).default_backoff();
+        self.run_with_stream(stream).await;
+
+        warn!("Namespace limits watcher stream ended unexpectedly");
+        Ok(())
+    }
+
+    /// Apply watch events from `stream` to the limiter until it ends.
+    ///
+    /// See [`DevboxWatcher::run_with_stream`].
+    pub async fn run_with_stream<S>(&self, stream: S)
+    where
+        S: Stream<Item = std::result::Result<Event<ConfigMap>, watcher::Error>>,
+    {
+        let mut stream = std::pin::pin!(stream);
+        while let Some(event) = stream.next().await {
+            self.handle_event(event);
+        }
+    }
+
+    fn handle_event(&self, event: std::result::Result<Event<ConfigMap>, watcher::Error>) {
+        match event {
+            Ok(Event::Apply(configmap) | Event::InitApply(configmap)) => {
+                self.listed.store(true, Ordering::Relaxed);
+                let overrides = configmap
+                    .data
+                    .as_ref()
+                    .map(limits::parse_namespace_limits)
+                    .unwrap_or_default();
+                info!(namespaces = overrides.len(), "Namespace limits updated");
+                self.limiter.set_overrides(overrides);
+            }
+            Ok(Event::Delete(_)) => {
+                info!("Namespace limits ConfigMap deleted, using defaults");
+                self.limiter.set_overrides(HashMap::new());
+            }
+            Ok(Event::Init) => {
+                self.listed.store(false, Ordering::Relaxed);
+            }
+            Ok(Event::InitDone) => {
+                // Deleted while the watch was down
+                if !self.listed.load(Ordering::Relaxed) {
+                    info!("Namespace limits ConfigMap not found, using defaults");
+                    self.limiter.set_overrides(HashMap::new());
+                }
+            }
+            Err(e) => {
+                error!(error = %e, "Namespace limits watcher error");
+            }
+        }
+    }
+}
+
+// ============================================================================
+// TLS Secret Watcher
+// ============================================================================
+
+/// Kubernetes watcher for the `kubernetes.io/tls` Secret of `TLS_SECRET_REF`.
+///
+/// Each version of the Secret with a valid `tls.crt`/`tls.key` pair replaces
+/// the certificate of the [`CertStore`]. Invalid versions, and deletion of
+/// the Secret, leave the current certificate in place.
+pub struct TlsSecretWatcher {
+    certs: Arc<CertStore>,
+    namespace: String,
+    name: String,
+}
+
+impl TlsSecretWatcher {
+    pub fn new(certs: Arc<CertStore>, namespace: String, name: String) -> Self {
+        Self {
+            certs,
+            namespace,
+            name,
+        }
+    }
+
+    /// Start watching the TLS Secret.
+    ///
+    /// This function runs indefinitely, processing watch events.
+    /// It should be spawned as a background task.
+    pub async fn run(&self) -> Result<()> {
+        let client = create_client().await?;
+        let secrets: Api<Secret> = Api::namespaced(client, &self.namespace);
+
+        info!(
+            namespace = %self.namespace,
+            name = %self.name,
+            "Starting TLS Secret watcher"
+        );
+
+        let watcher_config =
+            watcher::Config::default().fields(&format!("metadata.name={}", self.name));
+        let stream = watcher(secrets, watcher_config).default_backoff();
+        self.run_with_stream(stream).await;
+
+        warn!("TLS Secret watcher stream ended unexpectedly");
+        Ok(())
+    }
+
+    /// Apply watch events from `stream` to the certificate store until it
+    /// ends.
+    ///
+    /// See [`DevboxWatcher::run_with_stream`].
+    pub async fn run_with_stream<S>(&self, stream: S)
+    where
+        S: Stream<Item = std::result::Result<Event<Secret>, watcher::Error>>,
+    {
+        let mut stream = std::pin::pin!(stream);
+        while let Some(event) = stream.next().await {
+            self.handle_event(event);
+        }
+    }
+
+    fn handle_event(&self, event: std::result::Result<Event<Secret>, watcher::Error>) {
+        match event {
+            Ok(Event::Apply(secret) | Event::InitApply(secret)) => {
+                let resource_version = secret.metadata.resource_version.clone();
+                match Self::server_cert(&secret) {
+                    Ok(cert) => {
+                        info!(
+                            namespace = %self.namespace,
+                            name = %self.name,
+                            resource_version = ?resource_version,
+                            not_after = cert.not_after,
+                            "TLS certificate loaded"
+                        );
+                        self.certs.swap(cert);
+                    }
+                    Err(e) => error!(
+                        namespace = %self.namespace,
+                        name = %self.name,
+                        resource_version = ?resource_version,
+                        error = %e,
+                        "Rejecting TLS Secret, keeping the current certificate"
+                    ),
+                }
+            }
+            Ok(Event::Delete(_)) => {
+                warn!(
+                    namespace = %self.namespace,
+                    name = %self.name,
+                    "TLS Secret deleted, keeping the current certificate"
+                );
+            }
+            Ok(Event::Init | Event::InitDone) => {}
+            Err(e) => {
+                error!(error = %e, "TLS Secret watcher error");
+            }
+        }
+    }
+
+    /// The certificate and key of a `kubernetes.io/tls` Secret.
+    fn server_cert(secret: &Secret) -> std::result::Result<ServerCert, String> {
+        let data = secret.data.as_ref();
+        let field = |key: &str| {
+            data.and_then(|d| d.get(key))
+                .map(|v| v.0.as_slice())
+                .ok_or_else(|| format!("missing {key}"))
+        };
+        ServerCert::from_pem(field("tls.crt")?, field("tls.key")?)
+    }
+}
+
+// ============================================================================
+// Client CA Watcher
+// ============================================================================
+
+/// A client CA Secret or ConfigMap, as far as the [`ClientCaStore`] cares.
+struct CaObject {
+    source: CaSource,
+    namespace: String,
+    name: String,
+    /// The `ca.crt` bundle, if the object has one
+    bundle: Option<Vec<u8>>,
+}
+
+impl CaObject {
+    fn from_secret(secret: &Secret) -> Self {
+        Self {
+            source: CaSource::Secret,
+            namespace: secret.metadata.namespace.clone().unwrap_or_default(),
+            name: secret.metadata.name.clone().unwrap_or_default(),
+            bundle: secret
+                .data
+                .as_ref()
+                .and_then(|data| data.get(CLIENT_CA_KEY))
+                .map(|bundle| bundle.0.clone()),
+        }
+    }
+
+    fn from_config_map(config_map: &ConfigMap) -> Self {
+        Self {
+            source: CaSource::ConfigMap,
+            namespace: config_map.metadata.namespace.clone().unwrap_or_default(),
+            name: config_map.metadata.name.clone().unwrap_or_default(),
+            bundle: config_map
+                .data
+                .as_ref()
+                .and_then(|data| data.get(CLIENT_CA_KEY))
+                .map(|bundle| bundle.clone().into_bytes()),
+        }
+    }
+}
+
+/// Namespace and name of the bundles seen during the initial list of a
+/// kind in a scope
+type ListedBundles = HashMap<(CaSource, WatchScope), HashSet<(String, String)>>;
+
+/// Kubernetes watcher for the client CA bundles of devboxes with a
+/// `client-ca` annotation: the Secrets and ConfigMaps labeled
+/// [`CLIENT_CA_LABEL`] in the watched namespaces.
+///
+/// As for the TLS Secret, each valid version of a bundle replaces the last
+/// and invalid ones leave it in place; a deleted object takes its bundle
+/// with it, so the devboxes referencing it turn every client away.
+pub struct ClientCaWatcher {
+    store: Arc<ClientCaStore>,
+    scopes: Vec<WatchScope>,
+    /// Bundles seen during the current initial list of each scope
+    listed: Mutex<ListedBundles>,
+}
+
+impl ClientCaWatcher {
+    pub fn new(store: Arc<ClientCaStore>, namespaces: &[String]) -> Self {
+        Self {
+            store,
+            scopes: WatchScope::for_namespaces(namespaces),
+            listed: Mutex::default(),
+        }
+    }
+
+    /// Start watching the client CA Secrets and ConfigMaps.
+    ///
+    /// This function runs indefinitely, processing watch events.
+    /// It should be spawned as a background task.
+    pub async fn run(&self) -> Result<()> {
+        let client = create_client().await?;
+
+        info!(
+            namespaces = ?self.scopes.iter().filter_map(WatchScope::namespace).collect::<Vec<_>>(),
+            label = CLIENT_CA_LABEL,
+            "Starting client CA watcher"
+        );
+
+        let watcher_config = watcher::Config::default().labels(CLIENT_CA_LABEL);
+        let secrets = stream::select_all(self.scopes.iter().map(|scope| {
+            let scope = scope.clone();
+            watcher(scope.api::<Secret>(client.clone()), watcher_config.clone())
+                .default_backoff()
+                .map(move |event| (scope.clone(), event))
+                .boxed()
+        }));
+        let config_maps = stream::select_all(self.scopes.iter().map(|scope| {
+            let scope = scope.clone();
+            watcher(
+                scope.api::<ConfigMap>(client.clone()),
+                watcher_config.clone(),
+            )
+            .default_backoff()
+            .map(move |event| (scope.clone(), event))
+            .boxed()
+        }));
+        self.run_with_streams(secrets, config_maps).await;
+
+        warn!("Client CA watcher streams ended unexpectedly");
+        Ok(())
+    }
+
+    /// Apply watch events of Secrets and ConfigMaps, each with the scope it
+    /// was watched in, to the store until both streams end.
+    ///
+    /// See [`DevboxWatcher::run_with_stream`].
+    pub async fn run_with_streams<S, C>(&self, secrets: S, config_maps: C)
+    where
+        S: Stream<
+            Item = (
+                WatchScope,
+                std::result::Result<Event<Secret>, watcher::Error>,
+            ),
+        >,
+        C: Stream<
+            Item = (
+                WatchScope,
+                std::result::Result<Event<ConfigMap>, watcher::Error>,
+            ),
+        >,
+    {
+        let secrets = secrets.for_each(|(scope, event)| {
+            self.handle_event(CaSource::Secret, &scope, event, CaObject::from_secret);
+            future::ready(())
+        });
+        let config_maps = config_maps.for_each(|(scope, event)| {
+            self.handle_event(
+                CaSource::ConfigMap,
+                &scope,
+                event,
+                CaObject::from_config_map,
+            );
+            future::ready(())
+        });
+        future::join(secrets, config_maps).await;
+    }
+
+    fn handle_event<K>(
+        &self,
+        source: CaSource,
+        scope: &WatchScope,
+        event: std::result::Result<Event<K>, watcher::Error>,
+        object: impl Fn(&K) -> CaObject,
+    ) {
+        match event {
+            Ok(Event::Apply(k)) => self.load(&object(&k)),
+            Ok(Event::InitApply(k)) => {
+                let object = object(&k);
+                if let Some(listed) = self
+                    .listed
+                    .lock()
+                    .unwrap()
+                    .get_mut(&(source, scope.clone()))
+                {
+                    listed.insert((object.namespace.clone(), object.name.clone()));
+                }
+                self.load(&object);
+            }
+            Ok(Event::Delete(k)) => {
+                let object = object(&k);
+                info!(
+                    kind = source.as_str(),
+                    namespace = %object.namespace,
+                    name = %object.name,
+                    "Client CA bundle deleted"
+                );
+                self.store.remove(source, &object.namespace, &object.name);
+            }
+            Ok(Event::Init) => {
+                self.listed
+                    .lock()
+                    .unwrap()
+                    .insert((source, scope.clone()), HashSet::new());
+            }
+            Ok(Event::InitDone) => {
+                // Bundles deleted while the watch was down are gone now
+                let listed = self.listed.lock().unwrap().remove(&(source, scope.clone()));
+                if let Some(listed) = listed {
+                    self.store.retain(source, scope.namespace(), &listed);
+                }
+            }
+            Err(e) => {
+                error!(kind = source.as_str(), error = %e, "Client CA watcher error");
+            }
+        }
+    }
+
+    fn load(&self, object: &CaObject) {
+        let result = match &object.bundle {
+            Some(bundle) => self
+                .store
+                .load(object.source, &object.namespace, &object.name, bundle),
+            None => Err(format!("missing {CLIENT_CA_KEY}")),
+        };
+        match result {
+            Ok(count) => info!(
+                kind = object.source.as_str(),
+                namespace = %object.namespace,
+                name = %object.name,
+                certificates = count,
+                "Client CA bundle loaded"
+            ),
+            Err(e) => error!(
+                kind = object.source.as_str(),
+                namespace = %object.namespace,
+                name = %object.name,
+                error = %e,
+                "Rejecting client CA bundle, keeping the current one"
+            ),
+        }
+    }
+}
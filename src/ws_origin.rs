@@ -0,0 +1,201 @@
+use std::fmt;
+
+/// Entry allowing only the devbox's own hostname as origin
+pub const SAME_HOST: &str = "same-host";
+
+/// One allowed origin host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OriginPattern {
+    /// The request's own host
+    SameHost,
+    /// Exactly this host
+    Host(String),
+    /// Any subdomain of this host (`*.example.com`), not the host itself
+    Subdomain(String),
+}
+
+/// Origins allowed to open WebSocket connections to a devbox, from the
+/// `ws-allowed-origins` annotation, compiled when the devbox is registered.
+///
+/// Entries are hosts (`app.example.com`, optionally with a scheme, which is
+/// ignored), wildcard subdomain patterns (`*.example.com`) or `same-host`.
+/// Ports are ignored on both sides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WsOrigins {
+    patterns: Vec<OriginPattern>,
+}
+
+/// Invalid entries of a `ws-allowed-origins` annotation, which are left out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidEntries(pub Vec<String>);
+
+impl fmt::Display for InvalidEntries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid origin patterns: {}", self.0.join(", "))
+    }
+}
+
+impl WsOrigins {
+    /// Compile a comma-separated list of entries.
+    ///
+    /// Invalid entries are dropped and returned along with the rest, so an
+    /// annotation with a typo still allows no more origins than it lists.
+    pub fn parse(value: &str) -> (Self, Option<InvalidEntries>) {
+        let mut patterns = Vec::new();
+        let mut invalid = Vec::new();
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match parse_pattern(entry) {
+                Some(pattern) => patterns.push(pattern),
+                None => invalid.push(entry.to_string()),
+            }
+        }
+        let invalid = (!invalid.is_empty()).then_some(InvalidEntries(invalid));
+        (Self { patterns }, invalid)
+    }
+
+    /// Whether a WebSocket request to `host` with `origin` may go through.
+    ///
+    /// Requests without `Origin` don't come from browsers, so can't be
+    /// hijacked cross-site, and are allowed; the opaque `null` origin never
+    /// is.
+    pub fn allows(&self, origin: Option<&str>, host: &str) -> bool {
+        let Some(origin) = origin else {
+            return true;
+        };
+        let Some(origin_host) = origin_host(origin) else {
+            return false;
+        };
+        let host = strip_port(host).to_ascii_lowercase();
+        self.patterns.iter().any(|pattern| match pattern {
+            OriginPattern::SameHost => origin_host == host,
+            OriginPattern::Host(allowed) => origin_host == *allowed,
+            OriginPattern::Subdomain(parent) => origin_host
+                .strip_suffix(parent.as_str())
+                .and_then(|sub| sub.strip_suffix('.'))
+                .is_some_and(|sub| !sub.is_empty()),
+        })
+    }
+}
+
+fn parse_pattern(entry: &str) -> Option<OriginPattern> {
+    if entry.eq_ignore_ascii_case(SAME_HOST) {
+        return Some(OriginPattern::SameHost);
+    }
+    let host = strip_port(strip_scheme(entry)).to_ascii_lowercase();
+    let (wildcard, name) = match host.strip_prefix("*.") {
+        Some(parent) => (true, parent.to_string()),
+        None => (false, host),
+    };
+    let valid = !name.is_empty()
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        });
+    if !valid {
+        return None;
+    }
+    Some(if wildcard {
+        OriginPattern::Subdomain(name)
+    } else {
+        OriginPattern::Host(name)
+    })
+}
+
+/// Lowercased host of an `Origin` value (`scheme://host[:port]`)
+fn origin_host(origin: &str) -> Option<String> {
+    let (_, authority) = origin.trim().split_once("://")?;
+    let host = strip_port(authority);
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+fn strip_scheme(value: &str) -> &str {
+    value.split_once("://").map_or(value, |(_, rest)| rest)
+}
+
+fn strip_port(authority: &str) -> &str {
+    authority
+        .rsplit_once(':')
+        .filter(|(_, port)| port.bytes().all(|b| b.is_ascii_digit()))
+        .map_or(authority, |(host, _)| host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: &str = "devbox-app-1234-8080.devbox.example.com";
+
+    fn origins(value: &str) -> WsOrigins {
+        let (origins, invalid) = WsOrigins::parse(value);
+        assert_eq!(invalid, None);
+        origins
+    }
+
+    #[test]
+    fn test_exact_hosts() {
+        let allowed = origins("app.example.com, https://Admin.Example.com");
+        assert!(allowed.allows(Some("https://app.example.com"), HOST));
+        assert!(allowed.allows(Some("http://app.example.com:3000"), HOST));
+        assert!(allowed.allows(Some("https://admin.example.com"), HOST));
+        assert!(!allowed.allows(Some("https://evil.example.com"), HOST));
+        assert!(!allowed.allows(Some("https://app.example.com.evil.net"), HOST));
+    }
+
+    #[test]
+    fn test_wildcard_subdomains() {
+        let allowed = origins("*.example.com");
+        assert!(allowed.allows(Some("https://app.example.com"), HOST));
+        assert!(allowed.allows(Some("https://a.b.example.com"), HOST));
+        // Not the parent itself, nor hosts merely ending the same way
+        assert!(!allowed.allows(Some("https://example.com"), HOST));
+        assert!(!allowed.allows(Some("https://evilexample.com"), HOST));
+    }
+
+    #[test]
+    fn test_missing_and_opaque_origin() {
+        let allowed = origins("app.example.com");
+        assert!(allowed.allows(None, HOST));
+        assert!(!allowed.allows(Some("null"), HOST));
+        assert!(!allowed.allows(Some(""), HOST));
+    }
+
+    #[test]
+    fn test_same_host() {
+        let allowed = origins(SAME_HOST);
+        assert!(allowed.allows(Some(&format!("https://{HOST}")), HOST));
+        assert!(allowed.allows(
+            Some(&format!("https://{}", HOST.to_uppercase())),
+            &format!("{HOST}:443")
+        ));
+        assert!(!allowed.allows(Some("https://app.example.com"), HOST));
+        assert!(!allowed.allows(
+            Some("https://devbox-other-1234-8080.devbox.example.com"),
+            HOST
+        ));
+
+        // Combined with other entries
+        let allowed = origins("same-host, *.example.com");
+        assert!(allowed.allows(Some("https://app.example.com"), HOST));
+    }
+
+    #[test]
+    fn test_invalid_entries_dropped() {
+        let (allowed, invalid) = WsOrigins::parse("app.example.com, *.*.com, *, foo bar");
+        assert_eq!(
+            invalid,
+            Some(InvalidEntries(vec![
+                "*.*.com".to_string(),
+                "*".to_string(),
+                "foo bar".to_string(),
+            ]))
+        );
+        assert!(allowed.allows(Some("https://app.example.com"), HOST));
+        assert!(!allowed.allows(Some("https://x.com"), HOST));
+
+        // Nothing valid: no origin is allowed
+        let (allowed, _) = WsOrigins::parse("*");
+        assert!(!allowed.allows(Some("https://app.example.com"), HOST));
+    }
+}
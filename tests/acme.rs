@@ -0,0 +1,74 @@
+//! End-to-end tests of ACME HTTP-01 challenges answered by the proxy.
+
+mod common;
+
+use std::sync::{Arc, OnceLock};
+
+use httpgate::acme::{Challenges, CHALLENGE_PREFIX};
+use httpgate::config::{Config, ListenerConfig};
+use httpgate::registry::DevboxRegistry;
+
+use common::{send, spawn_backend, spawn_gateway_with, status};
+
+const TOKEN: &str = "evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA";
+const KEY_AUTHORIZATION: &str = "evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA.thumbprint";
+
+/// Address of the proxy, with one challenge pending, and the host of a
+/// devbox routed to the backend.
+fn gateway() -> &'static (String, String) {
+    static GATEWAY: OnceLock<(String, String)> = OnceLock::new();
+    GATEWAY.get_or_init(|| {
+        let backend_port = spawn_backend();
+
+        let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+        registry.register_devbox(
+            "acme-test".to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+            .unwrap();
+
+        let challenges = Arc::new(Challenges::new());
+        challenges.insert(TOKEN.to_string(), KEY_AUTHORIZATION.to_string());
+
+        let config = Config::default();
+        let listener = ListenerConfig::from_config(&config).policy;
+        let addrs = spawn_gateway_with(registry, config, vec![listener], |proxy| {
+            proxy.with_acme_challenges(Arc::clone(&challenges))
+        });
+        let host = format!("devbox-acme-test-{backend_port}.devbox.local");
+        (addrs[0].clone(), host)
+    })
+}
+
+#[test]
+fn test_challenge_answered_before_routing() {
+    // The CA asks for the bare domain, which routes to no devbox
+    let (addr, _) = gateway();
+    let (head, body) = send(
+        addr,
+        &format!("GET {CHALLENGE_PREFIX}{TOKEN} HTTP/1.1\r\nHost: devbox.local\r\n\r\n"),
+    );
+    assert_eq!(status(&head), 200, "got: {head}");
+    assert_eq!(body, KEY_AUTHORIZATION);
+}
+
+#[test]
+fn test_unknown_challenge_routed() {
+    // Tokens the gateway didn't ask for are the devbox's own
+    let (addr, host) = gateway();
+    let (head, body) = send(
+        addr,
+        &format!("GET {CHALLENGE_PREFIX}other-token HTTP/1.1\r\nHost: {host}\r\n\r\n"),
+    );
+    assert_eq!(status(&head), 200, "got: {head}");
+    assert_eq!(body, "received 0 bytes");
+
+    let (head, _) = send(
+        addr,
+        &format!("GET {CHALLENGE_PREFIX}other-token HTTP/1.1\r\nHost: devbox.local\r\n\r\n"),
+    );
+    assert_eq!(status(&head), 404, "got: {head}");
+}
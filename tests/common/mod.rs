@@ -0,0 +1,220 @@
+//! Shared helpers for end-to-end tests.
+//!
+//! Runs real proxy services on loopback ports in front of a minimal
+//! HTTP/1.1 backend, and drives them with raw TCP clients so tests can
+//! observe exactly what goes over the wire.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use httpgate::blocklist::Blocklist;
+use httpgate::config::{Config, ListenerPolicy, ListenerTls};
+use httpgate::passthrough::PassthroughApp;
+use httpgate::proxy::DevboxProxy;
+use httpgate::registry::DevboxRegistry;
+use pingora_core::listeners::tls::TlsSettings;
+use pingora_core::server::Server;
+
+/// Self-signed certificate for `*.devbox.local`, used by TLS listeners.
+pub fn test_tls() -> ListenerTls {
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+    ListenerTls {
+        cert_path: format!("{fixtures}/tls.crt"),
+        key_path: format!("{fixtures}/tls.key"),
+    }
+}
+
+/// Reserve a free loopback address.
+pub fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// Block until something accepts connections on `addr`.
+pub fn wait_for_listener(addr: &str) {
+    for _ in 0..100 {
+        if TcpStream::connect(addr).is_ok() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("nothing listening on {addr}");
+}
+
+/// Start a Pingora server with one proxy service per listener policy.
+///
+/// Returns the address of each listener, in the same order.
+pub fn spawn_gateway(
+    registry: Arc<DevboxRegistry>,
+    config: Config,
+    listeners: Vec<ListenerPolicy>,
+) -> Vec<String> {
+    spawn_gateway_with(registry, config, listeners, |proxy| proxy)
+}
+
+/// Like [`spawn_gateway`], with each proxy set up by `configure`.
+pub fn spawn_gateway_with(
+    registry: Arc<DevboxRegistry>,
+    config: Config,
+    listeners: Vec<ListenerPolicy>,
+    configure: impl Fn(DevboxProxy) -> DevboxProxy,
+) -> Vec<String> {
+    let config = Arc::new(config);
+    let mut server = Server::new(None).unwrap();
+    server.bootstrap();
+
+    let mut addrs = Vec::new();
+    for listener in listeners {
+        let addr = free_addr();
+        let proxy = configure(DevboxProxy::with_listener(
+            Arc::clone(&registry),
+            Arc::clone(&config),
+            listener,
+        ));
+        let mut service = pingora_proxy::http_proxy_service(&server.configuration, proxy);
+        service.add_tcp(&addr);
+        server.add_service(service);
+        addrs.push(addr);
+    }
+
+    thread::spawn(move || server.run_forever());
+    for addr in &addrs {
+        wait_for_listener(addr);
+    }
+    addrs
+}
+
+/// Start a Pingora server with one TLS proxy listener offering h2 and http/1.1.
+///
+/// Returns the address of the listener.
+pub fn spawn_tls_gateway(
+    registry: Arc<DevboxRegistry>,
+    config: Config,
+    listener: ListenerPolicy,
+    tls: &ListenerTls,
+) -> String {
+    let mut server = Server::new(None).unwrap();
+    server.bootstrap();
+
+    let addr = free_addr();
+    let proxy = DevboxProxy::with_listener(registry, Arc::new(config), listener);
+    let mut service = pingora_proxy::http_proxy_service(&server.configuration, proxy);
+    let mut settings = TlsSettings::intermediate(&tls.cert_path, &tls.key_path).unwrap();
+    settings.enable_h2();
+    service.add_tls_with_settings(&addr, None, settings);
+    server.add_service(service);
+
+    thread::spawn(move || server.run_forever());
+    wait_for_listener(&addr);
+    addr
+}
+
+/// Start a Pingora server with a TCP passthrough service for `domains`.
+///
+/// Returns the address of the service.
+pub fn spawn_passthrough(registry: Arc<DevboxRegistry>, domains: Vec<String>) -> String {
+    let mut server = Server::new(None).unwrap();
+    server.bootstrap();
+
+    let addr = free_addr();
+    let blocklist = Arc::new(Blocklist::from_config(&Config::default()));
+    let mut service = PassthroughApp::new(registry, blocklist, domains).into_service();
+    service.add_tcp(&addr);
+    server.add_service(service);
+
+    thread::spawn(move || server.run_forever());
+    wait_for_listener(&addr);
+    addr
+}
+
+/// Start a backend that answers every request with the number of body
+/// bytes it received. Returns its port.
+pub fn spawn_backend() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || serve_backend(stream));
+        }
+    });
+    port
+}
+
+fn serve_backend(stream: TcpStream) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    loop {
+        let head = read_head(&mut reader);
+        if head.is_empty() {
+            return;
+        }
+        let length = content_length(&head);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        let reply = format!("received {length} bytes");
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{reply}",
+            reply.len()
+        )
+        .unwrap();
+    }
+}
+
+/// Read a request or response head up to the blank line.
+pub fn read_head(reader: &mut impl BufRead) -> String {
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+            return head;
+        }
+        head.push_str(&line);
+    }
+}
+
+/// Content-Length of a head, or 0 if absent.
+pub fn content_length(head: &str) -> usize {
+    head.lines()
+        .find_map(|l| {
+            let (name, value) = l.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse().unwrap())
+        })
+        .unwrap_or(0)
+}
+
+/// Open a client connection with a read timeout.
+pub fn connect(addr: &str) -> (TcpStream, BufReader<TcpStream>) {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    (stream, reader)
+}
+
+/// Send a raw request and return the response head and body.
+pub fn send(addr: &str, request: &str) -> (String, String) {
+    let (mut stream, mut reader) = connect(addr);
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let head = read_head(&mut reader);
+    let mut body = vec![0; content_length(&head)];
+    reader.read_exact(&mut body).unwrap();
+    (head, String::from_utf8(body).unwrap())
+}
+
+/// Status code from a response head.
+pub fn status(head: &str) -> u16 {
+    head.split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0)
+}
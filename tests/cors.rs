@@ -0,0 +1,302 @@
+//! End-to-end tests of gateway-level CORS.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock};
+
+use httpgate::config::{Config, ListenerConfig};
+use httpgate::policy::{
+    DevboxPolicy, ANNOTATION_AUTH_REQUIRED, ANNOTATION_BASIC_AUTH, ANNOTATION_CORS,
+};
+use httpgate::preview::{self, PreviewSigner};
+use httpgate::registry::{DevboxInfo, DevboxRegistry};
+
+use common::{send, spawn_backend, spawn_gateway, status};
+
+const ORIGIN: &str = "https://app.example.com";
+
+/// Origin allowed by the `cors` annotation of the annotated devbox
+const ANNOTATED_ORIGIN: &str = "https://annotated.example.com";
+
+/// Key signing the preview tokens of the protected gateway
+const SIGNING_KEY: &str = "cors-test-key";
+
+/// User of the devbox behind a basic-auth gate, whose password is `s3cret`
+const BASIC_AUTH_USER: &str = "alice:$apr1$Jd8KH1n2$w/Eo9G/CAjsPMjm3hUwV/1";
+
+/// Address of the proxy, the host routed to the backend, and the host of a
+/// devbox with its own CORS policy.
+fn gateway() -> &'static (String, String, String) {
+    static GATEWAY: OnceLock<(String, String, String)> = OnceLock::new();
+    GATEWAY.get_or_init(|| {
+        let backend_port = spawn_backend();
+
+        let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+        registry.register_devbox(
+            "cors-test".to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+            .unwrap();
+
+        let annotations = BTreeMap::from([(
+            ANNOTATION_CORS.to_string(),
+            format!("origins={ANNOTATED_ORIGIN};methods=GET,POST;credentials=true"),
+        )]);
+        registry.register_devbox_info(
+            "cors-annotated".to_string(),
+            DevboxInfo {
+                policy: Arc::new(DevboxPolicy::from_annotations(&annotations)),
+                ..DevboxInfo::new("ns-test".to_string(), "devbox2".to_string())
+            },
+        );
+        registry
+            .update_pod_ip("ns-test", "devbox2", "127.0.0.1".to_string())
+            .unwrap();
+
+        let config = Config {
+            cors_allowed_origins: vec![ORIGIN.to_string()],
+            ..Default::default()
+        };
+        let listener = ListenerConfig::from_config(&config).policy;
+        let addrs = spawn_gateway(registry, config, vec![listener]);
+        let host = format!("devbox-cors-test-{backend_port}.devbox.local");
+        let annotated_host = format!("devbox-cors-annotated-{backend_port}.devbox.local");
+        (addrs[0].clone(), host, annotated_host)
+    })
+}
+
+/// Address of a proxy, the hosts of devboxes with their own CORS policy
+/// that require a preview token or basic auth, and the port they route to.
+fn protected_gateway() -> &'static (String, String, String, u16) {
+    static GATEWAY: OnceLock<(String, String, String, u16)> = OnceLock::new();
+    GATEWAY.get_or_init(|| {
+        let backend_port = spawn_backend();
+
+        let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+        let annotations = BTreeMap::from([
+            (
+                ANNOTATION_CORS.to_string(),
+                format!("origins={ANNOTATED_ORIGIN};methods=GET,POST;credentials=true"),
+            ),
+            (ANNOTATION_AUTH_REQUIRED.to_string(), "true".to_string()),
+        ]);
+        registry.register_devbox_info(
+            "cors-protected".to_string(),
+            DevboxInfo {
+                policy: Arc::new(DevboxPolicy::from_annotations(&annotations)),
+                ..DevboxInfo::new("ns-test".to_string(), "devbox3".to_string())
+            },
+        );
+        registry
+            .update_pod_ip("ns-test", "devbox3", "127.0.0.1".to_string())
+            .unwrap();
+
+        let annotations = BTreeMap::from([
+            (
+                ANNOTATION_CORS.to_string(),
+                format!("origins={ANNOTATED_ORIGIN};methods=GET,POST;credentials=true"),
+            ),
+            (
+                ANNOTATION_BASIC_AUTH.to_string(),
+                BASIC_AUTH_USER.to_string(),
+            ),
+        ]);
+        registry.register_devbox_info(
+            "cors-basic-auth".to_string(),
+            DevboxInfo {
+                policy: Arc::new(DevboxPolicy::from_annotations(&annotations)),
+                ..DevboxInfo::new("ns-test".to_string(), "devbox4".to_string())
+            },
+        );
+        registry
+            .update_pod_ip("ns-test", "devbox4", "127.0.0.1".to_string())
+            .unwrap();
+
+        let config = Config {
+            signing_key: Some(SIGNING_KEY.to_string()),
+            ..Default::default()
+        };
+        let listener = ListenerConfig::from_config(&config).policy;
+        let addrs = spawn_gateway(registry, config, vec![listener]);
+        let host = format!("devbox-cors-protected-{backend_port}.devbox.local");
+        let basic_auth_host = format!("devbox-cors-basic-auth-{backend_port}.devbox.local");
+        (addrs[0].clone(), host, basic_auth_host, backend_port)
+    })
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().find_map(|l| {
+        let (n, v) = l.split_once(':')?;
+        n.eq_ignore_ascii_case(name).then(|| v.trim())
+    })
+}
+
+#[test]
+fn test_preflight_answered_by_gateway() {
+    let (addr, host, _) = gateway();
+    let (head, body) = send(
+        addr,
+        &format!(
+            "OPTIONS /api HTTP/1.1\r\nHost: {host}\r\nOrigin: {ORIGIN}\r\n\
+             Access-Control-Request-Method: PUT\r\n\r\n"
+        ),
+    );
+
+    // The backend would have answered 200 with a body
+    assert_eq!(status(&head), 204);
+    assert!(body.is_empty());
+    assert_eq!(header(&head, "access-control-allow-origin"), Some(ORIGIN));
+    assert!(header(&head, "access-control-allow-methods").is_some());
+}
+
+#[test]
+fn test_allow_origin_injected_into_responses() {
+    let (addr, host, _) = gateway();
+    let get = |origin: &str| {
+        send(
+            addr,
+            &format!("GET /api HTTP/1.1\r\nHost: {host}\r\nOrigin: {origin}\r\n\r\n"),
+        )
+    };
+
+    let (head, body) = get(ORIGIN);
+    assert_eq!(status(&head), 200);
+    assert_eq!(body, "received 0 bytes");
+    assert_eq!(header(&head, "access-control-allow-origin"), Some(ORIGIN));
+
+    let (head, _) = get("https://evil.example");
+    assert_eq!(status(&head), 200);
+    assert_eq!(header(&head, "access-control-allow-origin"), None);
+}
+
+#[test]
+fn test_annotation_policy() {
+    let (addr, _, host) = gateway();
+    let (head, body) = send(
+        addr,
+        &format!(
+            "OPTIONS /api HTTP/1.1\r\nHost: {host}\r\nOrigin: {ANNOTATED_ORIGIN}\r\n\
+             Access-Control-Request-Method: POST\r\n\r\n"
+        ),
+    );
+    assert_eq!(status(&head), 204);
+    assert!(body.is_empty());
+    assert_eq!(
+        header(&head, "access-control-allow-origin"),
+        Some(ANNOTATED_ORIGIN)
+    );
+    assert_eq!(
+        header(&head, "access-control-allow-methods"),
+        Some("GET,POST")
+    );
+    assert_eq!(
+        header(&head, "access-control-allow-credentials"),
+        Some("true")
+    );
+
+    // The global origins don't apply to this devbox
+    let (head, _) = send(
+        addr,
+        &format!("GET /api HTTP/1.1\r\nHost: {host}\r\nOrigin: {ORIGIN}\r\n\r\n"),
+    );
+    assert_eq!(status(&head), 200);
+    assert_eq!(header(&head, "access-control-allow-origin"), None);
+
+    let (head, _) = send(
+        addr,
+        &format!("GET /api HTTP/1.1\r\nHost: {host}\r\nOrigin: {ANNOTATED_ORIGIN}\r\n\r\n"),
+    );
+    assert_eq!(
+        header(&head, "access-control-allow-origin"),
+        Some(ANNOTATED_ORIGIN)
+    );
+    assert_eq!(
+        header(&head, "access-control-allow-credentials"),
+        Some("true")
+    );
+}
+
+#[test]
+fn test_preflight_answered_before_auth() {
+    let (addr, host, _, port) = protected_gateway();
+
+    // Preflights carry neither the preview cookie nor the query token
+    let (head, body) = send(
+        addr,
+        &format!(
+            "OPTIONS /api HTTP/1.1\r\nHost: {host}\r\nOrigin: {ANNOTATED_ORIGIN}\r\n\
+             Access-Control-Request-Method: POST\r\n\r\n"
+        ),
+    );
+    assert_eq!(status(&head), 204);
+    assert!(body.is_empty());
+    assert_eq!(
+        header(&head, "access-control-allow-origin"),
+        Some(ANNOTATED_ORIGIN)
+    );
+
+    // The request itself still needs a token
+    let get = |query: &str| {
+        send(
+            addr,
+            &format!(
+                "GET /api{query} HTTP/1.1\r\nHost: {host}\r\nOrigin: {ANNOTATED_ORIGIN}\r\n\r\n"
+            ),
+        )
+    };
+    let (head, _) = get("");
+    assert_eq!(status(&head), 401);
+
+    let token =
+        PreviewSigner::new(SIGNING_KEY).mint("cors-protected", *port, preview::unix_now() + 60);
+    let (head, body) = get(&format!("?{}={token}", preview::TOKEN_QUERY_PARAM));
+    assert_eq!(status(&head), 200);
+    assert_eq!(body, "received 0 bytes");
+}
+
+#[test]
+fn test_preflight_answered_before_basic_auth() {
+    let (addr, _, host, port) = protected_gateway();
+
+    let (head, body) = send(
+        addr,
+        &format!(
+            "OPTIONS /api HTTP/1.1\r\nHost: {host}\r\nOrigin: {ANNOTATED_ORIGIN}\r\n\
+             Access-Control-Request-Method: POST\r\n\r\n"
+        ),
+    );
+    assert_eq!(status(&head), 204);
+    assert!(body.is_empty());
+    assert_eq!(
+        header(&head, "access-control-allow-origin"),
+        Some(ANNOTATED_ORIGIN)
+    );
+
+    let get = |extra: &str| {
+        send(
+            addr,
+            &format!(
+                "GET /api HTTP/1.1\r\nHost: {host}\r\nOrigin: {ANNOTATED_ORIGIN}\r\n{extra}\r\n"
+            ),
+        )
+    };
+    let (head, _) = get("");
+    assert_eq!(status(&head), 401);
+    assert!(header(&head, "www-authenticate").is_some());
+
+    // "alice:s3cret"
+    let (head, body) = get("Authorization: Basic YWxpY2U6czNjcmV0\r\n");
+    assert_eq!(status(&head), 200);
+    assert_eq!(body, "received 0 bytes");
+
+    // A preview token stands in for the credentials
+    let token =
+        PreviewSigner::new(SIGNING_KEY).mint("cors-basic-auth", *port, preview::unix_now() + 60);
+    let (head, body) = get(&format!("Cookie: {}={token}\r\n", preview::TOKEN_COOKIE));
+    assert_eq!(status(&head), 200);
+    assert_eq!(body, "received 0 bytes");
+}
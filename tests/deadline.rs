@@ -0,0 +1,154 @@
+//! End-to-end tests of the deadline forwarded to backends.
+//!
+//! A local backend answers with the deadline header it received, after a
+//! delay chosen by the path, behind gateways with `REQUEST_TIMEOUT` set and
+//! `DEADLINE_HEADER` left at its default or set to `grpc-timeout`.
+
+mod common;
+
+use std::io::{BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use http::HeaderName;
+use httpgate::config::{Config, ListenerConfig};
+use httpgate::registry::DevboxRegistry;
+
+use common::{read_head, send, spawn_gateway, status};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Header value of `name` in a request or response head
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().find_map(|line| {
+        let (n, v) = line.split_once(':')?;
+        n.eq_ignore_ascii_case(name).then(|| v.trim())
+    })
+}
+
+/// Start a backend answering with the value of header `name`, after
+/// sleeping for the milliseconds of a `/sleep/{ms}` path. Returns its port.
+fn spawn_echo_backend(name: &'static str) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || serve(stream, name));
+        }
+    });
+    port
+}
+
+fn serve(stream: TcpStream, name: &str) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    loop {
+        let head = read_head(&mut reader);
+        if head.is_empty() {
+            return;
+        }
+        let path = head.split_whitespace().nth(1).unwrap_or("/");
+        if let Some(ms) = path.strip_prefix("/sleep/").and_then(|ms| ms.parse().ok()) {
+            thread::sleep(Duration::from_millis(ms));
+        }
+        let reply = header(&head, name).unwrap_or("none");
+        if write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{reply}",
+            reply.len()
+        )
+        .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Address of a gateway forwarding the deadline in header `name`, and the
+/// host routed to its backend.
+fn spawn(name: &'static str) -> (String, String) {
+    let backend_port = spawn_echo_backend(name);
+
+    let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+    registry.register_devbox(
+        "deadline-test".to_string(),
+        "ns-test".to_string(),
+        "devbox1".to_string(),
+    );
+    registry
+        .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+        .unwrap();
+
+    let config = Config {
+        request_timeout: Some(REQUEST_TIMEOUT),
+        deadline_header: HeaderName::from_static(name),
+        ..Default::default()
+    };
+    let listener = ListenerConfig::from_config(&config).policy;
+    let addrs = spawn_gateway(registry, config, vec![listener]);
+    let host = format!("devbox-deadline-test-{backend_port}.devbox.local");
+    (addrs[0].clone(), host)
+}
+
+fn default_gateway() -> &'static (String, String) {
+    static GATEWAY: OnceLock<(String, String)> = OnceLock::new();
+    GATEWAY.get_or_init(|| spawn("x-request-timeout-ms"))
+}
+
+fn grpc_gateway() -> &'static (String, String) {
+    static GATEWAY: OnceLock<(String, String)> = OnceLock::new();
+    GATEWAY.get_or_init(|| spawn("grpc-timeout"))
+}
+
+/// GET `path` through `gateway` with extra `headers`.
+fn get(gateway: &(String, String), path: &str, headers: &str) -> (String, String) {
+    let (addr, host) = gateway;
+    send(
+        addr,
+        &format!("GET {path} HTTP/1.1\r\nHost: {host}\r\n{headers}\r\n"),
+    )
+}
+
+/// Forwarded budget in milliseconds
+fn budget_ms(body: &str) -> u64 {
+    body.trim_end_matches('m').parse().unwrap()
+}
+
+#[test]
+fn test_request_timeout_forwarded() {
+    let (head, body) = get(default_gateway(), "/", "");
+    assert_eq!(status(&head), 200, "got: {head}");
+    let budget = budget_ms(&body);
+    assert!(budget <= 2000, "forwarded {budget}ms");
+    assert!(budget > 1500, "forwarded {budget}ms");
+}
+
+#[test]
+fn test_client_deadline_shortens_budget() {
+    let (head, body) = get(default_gateway(), "/", "X-Request-Timeout-Ms: 500\r\n");
+    assert_eq!(status(&head), 200, "got: {head}");
+    let budget = budget_ms(&body);
+    assert!(budget <= 500, "forwarded {budget}ms");
+    assert!(budget > 0, "forwarded {budget}ms");
+
+    // A longer one leaves the gateway's
+    let (_, body) = get(default_gateway(), "/", "X-Request-Timeout-Ms: 60000\r\n");
+    assert!(budget_ms(&body) <= 2000, "forwarded {body}");
+}
+
+#[test]
+fn test_grpc_timeout_format() {
+    let (head, body) = get(grpc_gateway(), "/", "");
+    assert_eq!(status(&head), 200, "got: {head}");
+    assert!(body.ends_with('m'), "forwarded {body}");
+    let budget = budget_ms(&body);
+    assert!(budget <= 2000 && budget > 1500, "forwarded {body}");
+}
+
+#[test]
+fn test_slow_backend_times_out() {
+    let (head, _) = get(default_gateway(), "/sleep/3000", "");
+    assert_eq!(status(&head), 504, "got: {head}");
+}
@@ -0,0 +1,120 @@
+//! End-to-end tests for connection handling around gateway-generated error
+//! responses.
+//!
+//! Clients reuse a connection after an error only if the response is framed
+//! and the gateway keeps the connection; these tests check both over raw
+//! HTTP/1 connections.
+
+mod common;
+
+use std::io::{BufRead, Read, Write};
+use std::sync::{Arc, OnceLock};
+
+use httpgate::config::{Config, ListenerConfig};
+use httpgate::registry::DevboxRegistry;
+
+use common::{connect, content_length, read_head, spawn_gateway, status};
+
+/// Address of the proxy, started once per test binary. No devbox is
+/// registered, so every request gets a 404.
+fn gateway() -> &'static str {
+    static GATEWAY: OnceLock<String> = OnceLock::new();
+    GATEWAY.get_or_init(|| {
+        let config = Config::default();
+        let listener = ListenerConfig::from_config(&config).policy;
+        let addrs = spawn_gateway(Arc::new(DevboxRegistry::new()), config, vec![listener]);
+        addrs[0].clone()
+    })
+}
+
+/// Value of header `name` in a response head.
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().find_map(|l| {
+        let (n, value) = l.split_once(':')?;
+        n.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Read one response, returning its head after consuming the body.
+fn read_response(reader: &mut impl BufRead) -> String {
+    let head = read_head(reader);
+    let mut body = vec![0; content_length(&head)];
+    reader.read_exact(&mut body).unwrap();
+    head
+}
+
+/// Whether the server closed the connection.
+fn closed(reader: &mut impl Read) -> bool {
+    let mut buf = [0; 1];
+    matches!(reader.read(&mut buf), Ok(0))
+}
+
+#[test]
+fn test_error_keeps_http11_connection() {
+    let (mut stream, mut reader) = connect(gateway());
+
+    for _ in 0..2 {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: devbox-gone-8080.devbox.local\r\n\r\n")
+            .unwrap();
+        let head = read_response(&mut reader);
+        assert_eq!(status(&head), 404, "got: {head}");
+        assert!(header(&head, "content-length").is_some(), "got: {head}");
+        assert_eq!(header(&head, "connection"), None, "got: {head}");
+    }
+}
+
+#[test]
+fn test_error_honors_connection_close() {
+    let (mut stream, mut reader) = connect(gateway());
+
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: devbox-gone-8080.devbox.local\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+    let head = read_response(&mut reader);
+    assert_eq!(status(&head), 404, "got: {head}");
+    assert_eq!(header(&head, "connection"), Some("close"), "got: {head}");
+    assert!(closed(&mut reader));
+}
+
+#[test]
+fn test_error_http10() {
+    let (mut stream, mut reader) = connect(gateway());
+    stream
+        .write_all(
+            b"GET / HTTP/1.0\r\nHost: devbox-gone-8080.devbox.local\r\nConnection: keep-alive\r\n\r\n",
+        )
+        .unwrap();
+    let head = read_response(&mut reader);
+    assert_eq!(status(&head), 404, "got: {head}");
+    assert_eq!(
+        header(&head, "connection"),
+        Some("keep-alive"),
+        "got: {head}"
+    );
+
+    // Without keep-alive, HTTP/1.0 connections end after the response
+    stream
+        .write_all(b"GET / HTTP/1.0\r\nHost: devbox-gone-8080.devbox.local\r\n\r\n")
+        .unwrap();
+    let head = read_response(&mut reader);
+    assert_eq!(header(&head, "connection"), Some("close"), "got: {head}");
+    assert!(closed(&mut reader));
+}
+
+#[test]
+fn test_error_with_unread_body_closes() {
+    let (mut stream, mut reader) = connect(gateway());
+
+    // Rejected before the body is sent
+    stream
+        .write_all(
+            b"POST / HTTP/1.1\r\nHost: devbox-gone-8080.devbox.local\r\nContent-Length: 64\r\n\r\n",
+        )
+        .unwrap();
+    let head = read_response(&mut reader);
+    assert_eq!(header(&head, "connection"), Some("close"), "got: {head}");
+    assert!(closed(&mut reader));
+}
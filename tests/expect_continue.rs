@@ -0,0 +1,91 @@
+//! End-to-end tests for `Expect: 100-continue` handling.
+//!
+//! Drives the proxy with a raw TCP client that waits for the interim
+//! response before uploading the body, the way curl and most HTTP
+//! libraries do.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::sync::{Arc, OnceLock};
+
+use httpgate::config::{Config, ExpectContinueMode, ListenerConfig};
+use httpgate::registry::DevboxRegistry;
+
+use common::{connect, content_length, read_head, spawn_backend, spawn_gateway};
+
+const UNIQUE_ID: &str = "expect-test";
+const MAX_BODY: u64 = 1024;
+
+/// Address of the proxy and port of the backend, started once per test binary.
+fn gateway() -> &'static (String, u16) {
+    static GATEWAY: OnceLock<(String, u16)> = OnceLock::new();
+    GATEWAY.get_or_init(|| {
+        let backend_port = spawn_backend();
+
+        let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+        registry.register_devbox(
+            UNIQUE_ID.to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+            .unwrap();
+
+        let config = Config {
+            expect_continue: ExpectContinueMode::Gateway,
+            max_request_body_bytes: Some(MAX_BODY),
+            ..Default::default()
+        };
+        let listener = ListenerConfig::from_config(&config).policy;
+
+        let addrs = spawn_gateway(registry, config, vec![listener]);
+        (addrs[0].clone(), backend_port)
+    })
+}
+
+#[test]
+fn test_gateway_sends_continue_before_body() {
+    let (proxy_addr, backend_port) = gateway();
+    let (mut stream, mut reader) = connect(proxy_addr);
+    let body = "x".repeat(100);
+
+    write!(
+        stream,
+        "POST /upload HTTP/1.1\r\nHost: devbox-{UNIQUE_ID}-{backend_port}.devbox.local\r\n\
+         Expect: 100-continue\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .unwrap();
+
+    // Wait for the interim response before sending anything else
+    let interim = read_head(&mut reader);
+    assert!(interim.starts_with("HTTP/1.1 100"), "got: {interim}");
+
+    stream.write_all(body.as_bytes()).unwrap();
+
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 200"), "got: {head}");
+    let mut reply = vec![0; content_length(&head)];
+    reader.read_exact(&mut reply).unwrap();
+    assert_eq!(reply, b"received 100 bytes");
+}
+
+#[test]
+fn test_oversized_body_rejected_instead_of_continue() {
+    let (proxy_addr, backend_port) = gateway();
+    let (mut stream, mut reader) = connect(proxy_addr);
+
+    write!(
+        stream,
+        "POST /upload HTTP/1.1\r\nHost: devbox-{UNIQUE_ID}-{backend_port}.devbox.local\r\n\
+         Expect: 100-continue\r\nContent-Length: {}\r\n\r\n",
+        MAX_BODY + 1
+    )
+    .unwrap();
+
+    // The final status arrives without an interim 100 and without sending the body
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 413"), "got: {head}");
+}
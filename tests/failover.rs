@@ -0,0 +1,154 @@
+//! End-to-end tests of failing over to a devbox's replacement Pod.
+//!
+//! Local backends stand in for Pods that die mid-request: one before its
+//! response starts, after the registry has already moved the devbox to a
+//! replacement Pod on another loopback address, and one halfway through
+//! its response body.
+
+mod common;
+
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use httpgate::config::{Config, ListenerConfig};
+use httpgate::registry::DevboxRegistry;
+
+use common::{connect, content_length, read_head, send, spawn_gateway, status};
+
+/// Address of the replacement Pod of the devbox that fails over
+const REPLACEMENT_IP: &str = "127.0.0.2";
+
+/// Start a backend running `serve` for each connection. Returns its port.
+fn spawn_dying_backend(serve: impl Fn(TcpStream) + Clone + Send + 'static) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let serve = serve.clone();
+            thread::spawn(move || serve(stream));
+        }
+    });
+    port
+}
+
+/// Read a request, then run `f` and close the connection without answering.
+fn die_after(f: impl Fn() + Send + Sync + 'static) -> impl Fn(TcpStream) + Clone + Send + 'static {
+    let f = Arc::new(f);
+    move |stream: TcpStream| {
+        let mut reader = BufReader::new(stream);
+        read_head(&mut reader);
+        f();
+    }
+}
+
+/// Start the replacement Pod on `port`, answering with a short body.
+fn spawn_replacement(port: u16) {
+    let listener = TcpListener::bind((REPLACEMENT_IP, port)).unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            read_head(&mut reader);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nreplacement");
+        }
+    });
+}
+
+/// Address of the proxy, with the hosts of the devbox that fails over, the
+/// one that dies without a replacement, and the one that dies mid-body.
+struct Gateway {
+    addr: String,
+    failover_host: String,
+    dead_host: String,
+    truncated_host: String,
+}
+
+fn gateway() -> &'static Gateway {
+    static GATEWAY: OnceLock<Gateway> = OnceLock::new();
+    GATEWAY.get_or_init(|| {
+        let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+        for (unique_id, name) in [
+            ("failover-test", "devbox1"),
+            ("dead-test", "devbox2"),
+            ("truncated-test", "devbox3"),
+        ] {
+            registry.register_devbox(
+                unique_id.to_string(),
+                "ns-test".to_string(),
+                name.to_string(),
+            );
+            registry
+                .update_pod_ip("ns-test", name, "127.0.0.1".to_string())
+                .unwrap();
+        }
+
+        // The Pod is replaced while the request is in flight
+        let moved = Arc::clone(&registry);
+        let failover_port = spawn_dying_backend(die_after(move || {
+            moved
+                .update_pod_ip("ns-test", "devbox1", REPLACEMENT_IP.to_string())
+                .unwrap();
+        }));
+        spawn_replacement(failover_port);
+
+        let dead_port = spawn_dying_backend(die_after(|| {}));
+
+        let truncated_port = spawn_dying_backend(|stream: TcpStream| {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            read_head(&mut reader);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1024\r\n\r\npartial");
+        });
+
+        let config = Config::default();
+        let listener = ListenerConfig::from_config(&config).policy;
+        let addrs = spawn_gateway(registry, config, vec![listener]);
+        Gateway {
+            addr: addrs[0].clone(),
+            failover_host: format!("devbox-failover-test-{failover_port}.devbox.local"),
+            dead_host: format!("devbox-dead-test-{dead_port}.devbox.local"),
+            truncated_host: format!("devbox-truncated-test-{truncated_port}.devbox.local"),
+        }
+    })
+}
+
+#[test]
+fn test_fails_over_to_replacement_pod() {
+    let gateway = gateway();
+    let (head, body) = send(
+        &gateway.addr,
+        &format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", gateway.failover_host),
+    );
+    assert_eq!(status(&head), 200, "got: {head}");
+    assert_eq!(body, "replacement");
+}
+
+#[test]
+fn test_no_replacement_pod() {
+    let gateway = gateway();
+    let (head, _) = send(
+        &gateway.addr,
+        &format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", gateway.dead_host),
+    );
+    assert_eq!(status(&head), 502, "got: {head}");
+}
+
+#[test]
+fn test_started_response_is_truncated() {
+    // The headers are out, so the connection is closed before the declared
+    // length instead
+    let gateway = gateway();
+    let (mut stream, mut reader) = connect(&gateway.addr);
+    stream
+        .write_all(format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", gateway.truncated_host).as_bytes())
+        .unwrap();
+
+    let head = read_head(&mut reader);
+    assert_eq!(status(&head), 200, "got: {head}");
+    assert_eq!(content_length(&head), 1024, "got: {head}");
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body).unwrap();
+    assert_eq!(body, b"partial");
+}
@@ -0,0 +1,171 @@
+//! End-to-end tests of responses to HTTP/1.0 clients.
+//!
+//! HTTP/1.0 has no chunked encoding and closes connections unless asked not
+//! to. A local backend answers `/chunked` with a chunked body and other paths
+//! with a framed one; both, and the gateway's own errors, must reach a 1.0
+//! client in a shape it can read.
+
+mod common;
+
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use httpgate::config::{Config, ListenerConfig};
+use httpgate::registry::DevboxRegistry;
+
+use common::{connect, content_length, read_head, spawn_gateway, status};
+
+const BODY: &str = "hello from the devbox";
+
+/// Header value of `name` in a response head
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().find_map(|line| {
+        let (n, v) = line.split_once(':')?;
+        n.eq_ignore_ascii_case(name).then(|| v.trim())
+    })
+}
+
+/// Start a backend answering with [`BODY`], chunked on `/chunked`. Returns
+/// its port.
+fn spawn_backend() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || serve(stream));
+        }
+    });
+    port
+}
+
+fn serve(stream: TcpStream) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    loop {
+        let head = read_head(&mut reader);
+        if head.is_empty() {
+            return;
+        }
+        let path = head.split_whitespace().nth(1).unwrap_or("/");
+        let (first, second) = BODY.split_at(BODY.len() / 2);
+        let written = if path == "/chunked" {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 {:x}\r\n{first}\r\n{:x}\r\n{second}\r\n0\r\n\r\n",
+                first.len(),
+                second.len()
+            )
+        } else {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{BODY}",
+                BODY.len()
+            )
+        };
+        if written.is_err() {
+            return;
+        }
+    }
+}
+
+/// Address of the gateway, with a running devbox on `app-on` and a stopped
+/// one on `app-off`, and the backend's port.
+fn gateway() -> &'static (String, u16) {
+    static GATEWAY: OnceLock<(String, u16)> = OnceLock::new();
+    GATEWAY.get_or_init(|| {
+        let backend_port = spawn_backend();
+        let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+        for (unique_id, name) in [("app-on", "devbox-on"), ("app-off", "devbox-off")] {
+            registry.register_devbox(
+                unique_id.to_string(),
+                "ns-test".to_string(),
+                name.to_string(),
+            );
+        }
+        registry
+            .update_pod_ip("ns-test", "devbox-on", "127.0.0.1".to_string())
+            .unwrap();
+
+        let config = Config::default();
+        let listener = ListenerConfig::from_config(&config).policy;
+        let addrs = spawn_gateway(registry, config, vec![listener]);
+        (addrs[0].clone(), backend_port)
+    })
+}
+
+/// Send an HTTP/1.0 GET of `path` on `unique_id`'s host over `stream`.
+fn get(stream: &mut TcpStream, unique_id: &str, path: &str, headers: &str) {
+    let (_, port) = gateway();
+    write!(
+        stream,
+        "GET {path} HTTP/1.0\r\nHost: devbox-{unique_id}-{port}.devbox.local\r\n{headers}\r\n"
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_chunked_response_until_close() {
+    let (mut stream, mut reader) = connect(&gateway().0);
+    get(
+        &mut stream,
+        "app-on",
+        "/chunked",
+        "Connection: keep-alive\r\n",
+    );
+
+    let head = read_head(&mut reader);
+    assert_eq!(status(&head), 200, "got: {head}");
+    assert!(header(&head, "transfer-encoding").is_none(), "got: {head}");
+    assert_eq!(header(&head, "connection"), Some("close"), "got: {head}");
+
+    // The body is the decoded content, ended by the connection
+    let mut body = String::new();
+    reader.read_to_string(&mut body).unwrap();
+    assert_eq!(body, BODY);
+}
+
+#[test]
+fn test_framed_response_keeps_alive() {
+    let (mut stream, mut reader) = connect(&gateway().0);
+    for _ in 0..2 {
+        get(&mut stream, "app-on", "/", "Connection: keep-alive\r\n");
+        let head = read_head(&mut reader);
+        assert_eq!(status(&head), 200, "got: {head}");
+        assert_eq!(content_length(&head), BODY.len(), "got: {head}");
+        assert_eq!(
+            header(&head, "connection"),
+            Some("keep-alive"),
+            "got: {head}"
+        );
+        let mut body = vec![0; BODY.len()];
+        reader.read_exact(&mut body).unwrap();
+        assert_eq!(body, BODY.as_bytes());
+    }
+
+    // Without keep-alive the connection ends after the response
+    get(&mut stream, "app-on", "/", "");
+    let head = read_head(&mut reader);
+    assert_eq!(header(&head, "connection"), Some("close"), "got: {head}");
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, BODY.as_bytes());
+}
+
+#[test]
+fn test_generated_errors() {
+    for (unique_id, expected) in [("app-off", 503), ("app-gone", 404)] {
+        let (mut stream, mut reader) = connect(&gateway().0);
+        get(&mut stream, unique_id, "/", "");
+
+        let head = read_head(&mut reader);
+        assert_eq!(status(&head), expected, "got: {head}");
+        assert!(header(&head, "transfer-encoding").is_none(), "got: {head}");
+        assert_eq!(header(&head, "connection"), Some("close"), "got: {head}");
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+        assert_eq!(body.len(), content_length(&head), "got: {head}");
+    }
+}
@@ -0,0 +1,72 @@
+//! End-to-end tests for HTTP/2 on TLS listeners.
+//!
+//! Uses a real h2-capable client so ALPN negotiation, `:authority` routing
+//! and the h1 fallback are exercised the way browsers see them.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+
+use httpgate::config::{Config, ListenerConfig};
+use httpgate::registry::DevboxRegistry;
+
+use common::{spawn_backend, spawn_tls_gateway, test_tls};
+
+const UNIQUE_ID: &str = "h2-test";
+
+/// Address of the TLS proxy and port of the backend, started once per test binary.
+fn gateway() -> &'static (SocketAddr, u16) {
+    static GATEWAY: OnceLock<(SocketAddr, u16)> = OnceLock::new();
+    GATEWAY.get_or_init(|| {
+        let backend_port = spawn_backend();
+
+        let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+        registry.register_devbox(
+            UNIQUE_ID.to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+            .unwrap();
+
+        let config = Config::default();
+        let listener = ListenerConfig::from_config(&config).policy;
+        let addr = spawn_tls_gateway(registry, config, listener, &test_tls());
+        (addr.parse().unwrap(), backend_port)
+    })
+}
+
+/// Client resolving the devbox host to the gateway; returns it with the request URL.
+fn client(builder: reqwest::ClientBuilder) -> (reqwest::Client, String) {
+    let (addr, backend_port) = *gateway();
+    let host = format!("devbox-{UNIQUE_ID}-{backend_port}.devbox.local");
+    let client = builder
+        .use_rustls_tls()
+        .danger_accept_invalid_certs(true)
+        .resolve(&host, addr)
+        .build()
+        .unwrap();
+    (client, format!("https://{host}:{}/upload", addr.port()))
+}
+
+#[tokio::test]
+async fn test_tls_listener_negotiates_h2() {
+    let (client, url) = client(reqwest::Client::builder());
+
+    let resp = client.post(url).body("hello").send().await.unwrap();
+    assert_eq!(resp.version(), reqwest::Version::HTTP_2);
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().await.unwrap(), "received 5 bytes");
+}
+
+#[tokio::test]
+async fn test_tls_listener_falls_back_to_http1() {
+    let (client, url) = client(reqwest::Client::builder().http1_only());
+
+    let resp = client.post(url).body("hello").send().await.unwrap();
+    assert_eq!(resp.version(), reqwest::Version::HTTP_11);
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text().await.unwrap(), "received 5 bytes");
+}
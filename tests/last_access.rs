@@ -0,0 +1,72 @@
+//! End-to-end tests of when a devbox counts as accessed.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use httpgate::config::{Config, ListenerConfig};
+use httpgate::policy::{DevboxPolicy, ANNOTATION_ALLOWED_CIDRS, ANNOTATION_BASIC_AUTH};
+use httpgate::registry::{DevboxInfo, DevboxRegistry};
+
+use common::{send, spawn_backend, spawn_gateway, status};
+
+#[test]
+fn test_rejected_requests_leave_last_access() {
+    let backend_port = spawn_backend();
+
+    let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+    for (unique_id, devbox_name, annotation) in [
+        ("open", "devbox1", None),
+        (
+            "private",
+            "devbox2",
+            Some((ANNOTATION_ALLOWED_CIDRS, "10.8.0.0/16")),
+        ),
+        (
+            "gated",
+            "devbox3",
+            Some((
+                ANNOTATION_BASIC_AUTH,
+                "alice:$apr1$Jd8KH1n2$w/Eo9G/CAjsPMjm3hUwV/1",
+            )),
+        ),
+    ] {
+        let annotations: BTreeMap<_, _> = annotation
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        registry.register_devbox_info(
+            unique_id.to_string(),
+            DevboxInfo {
+                policy: Arc::new(DevboxPolicy::from_annotations(&annotations)),
+                ..DevboxInfo::new("ns-test".to_string(), devbox_name.to_string())
+            },
+        );
+        registry
+            .update_pod_ip("ns-test", devbox_name, "127.0.0.1".to_string())
+            .unwrap();
+    }
+
+    let config = Config::default();
+    let listener = ListenerConfig::from_config(&config).policy;
+    let addrs = spawn_gateway(Arc::clone(&registry), config, vec![listener]);
+    let get = |unique_id: &str| {
+        let (head, _) = send(
+            &addrs[0],
+            &format!(
+                "GET / HTTP/1.1\r\nHost: devbox-{unique_id}-{backend_port}.devbox.local\r\n\r\n"
+            ),
+        );
+        status(&head)
+    };
+    let last_access = |unique_id| registry.get_devbox(unique_id).unwrap().last_access();
+
+    assert_eq!(get("private"), 403);
+    assert_eq!(last_access("private"), None);
+    assert_eq!(get("gated"), 401);
+    assert_eq!(last_access("gated"), None);
+
+    assert_eq!(get("open"), 200);
+    assert!(last_access("open").is_some());
+}
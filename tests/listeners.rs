@@ -0,0 +1,104 @@
+//! End-to-end tests of listeners with different policies sharing one registry.
+
+mod common;
+
+use std::sync::Arc;
+
+use httpgate::config::{Config, ListenerPolicy};
+use httpgate::registry::DevboxRegistry;
+
+use common::{send, spawn_backend, spawn_gateway, status};
+
+#[test]
+fn test_two_listeners_route_with_their_own_policies() {
+    let backend_port = spawn_backend();
+
+    let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+    registry.register_devbox(
+        "my-app".to_string(),
+        "ns-test".to_string(),
+        "devbox1".to_string(),
+    );
+    registry
+        .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+        .unwrap();
+
+    let public = ListenerPolicy {
+        name: "public".to_string(),
+        domain_suffixes: vec!["devbox.public.test".to_string()],
+        max_request_body_bytes: Some(16),
+        tls: false,
+        identity_headers: false,
+    };
+    let internal = ListenerPolicy {
+        name: "internal".to_string(),
+        domain_suffixes: vec!["devbox.internal.test".to_string()],
+        max_request_body_bytes: None,
+        tls: false,
+        identity_headers: false,
+    };
+    let addrs = spawn_gateway(registry, Config::default(), vec![public, internal]);
+    let (public_addr, internal_addr) = (&addrs[0], &addrs[1]);
+
+    let public_host = format!("devbox-my-app-{backend_port}.devbox.public.test");
+    let internal_host = format!("devbox-my-app-{backend_port}.devbox.internal.test");
+    let body = "x".repeat(100);
+
+    // Each listener routes its own domain
+    let get = |host: &str| format!("GET / HTTP/1.1\r\nHost: {host}\r\n\r\n");
+    assert_eq!(status(&send(public_addr, &get(&public_host)).0), 200);
+    assert_eq!(status(&send(internal_addr, &get(&internal_host)).0), 200);
+
+    // ...and rejects the other listener's domain
+    assert_eq!(status(&send(public_addr, &get(&internal_host)).0), 404);
+    assert_eq!(status(&send(internal_addr, &get(&public_host)).0), 404);
+
+    // Body limits differ per listener
+    let post = |host: &str| {
+        format!(
+            "POST / HTTP/1.1\r\nHost: {host}\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    };
+    assert_eq!(status(&send(public_addr, &post(&public_host)).0), 413);
+    let (head, reply) = send(internal_addr, &post(&internal_host));
+    assert_eq!(status(&head), 200);
+    assert_eq!(reply, "received 100 bytes");
+}
+
+#[test]
+fn test_misdirected_on_tls_listener() {
+    let backend_port = spawn_backend();
+    let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+    registry.register_devbox(
+        "my-app".to_string(),
+        "ns-test".to_string(),
+        "devbox1".to_string(),
+    );
+    registry
+        .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+        .unwrap();
+
+    // The gateway under test serves cleartext; only the policy flag decides
+    // between 421 and 404
+    let secure = ListenerPolicy {
+        name: "secure".to_string(),
+        domain_suffixes: vec!["devbox.public.test".to_string()],
+        max_request_body_bytes: None,
+        tls: true,
+        identity_headers: false,
+    };
+    let addrs = spawn_gateway(registry, Config::default(), vec![secure]);
+    let get = |host: &str| format!("GET / HTTP/1.1\r\nHost: {host}\r\n\r\n");
+
+    let host = format!("devbox-my-app-{backend_port}.devbox.public.test");
+    assert_eq!(status(&send(&addrs[0], &get(&host)).0), 200);
+
+    // Unknown devboxes of our domain are still not found
+    let unknown = format!("devbox-other-app-{backend_port}.devbox.public.test");
+    assert_eq!(status(&send(&addrs[0], &get(&unknown)).0), 404);
+
+    // Other domains were sent over a coalesced connection
+    let other = format!("devbox-my-app-{backend_port}.devbox.other.test");
+    assert_eq!(status(&send(&addrs[0], &get(&other)).0), 421);
+}
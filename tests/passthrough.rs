@@ -0,0 +1,168 @@
+//! End-to-end test of the TCP passthrough service.
+//!
+//! Relays TLS connections to an echo server terminating TLS itself, so the
+//! test checks that the handshake and data pass through the gateway
+//! unchanged.
+
+mod common;
+
+use std::sync::Arc;
+
+use httpgate::registry::DevboxRegistry;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature};
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, ServerConfig, SignatureScheme};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use common::test_tls;
+
+fn server_config() -> Arc<ServerConfig> {
+    let tls = test_tls();
+    let certs = rustls_pemfile::certs(&mut std::fs::read(&tls.cert_path).unwrap().as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let key = rustls_pemfile::private_key(&mut std::fs::read(&tls.key_path).unwrap().as_slice())
+        .unwrap()
+        .unwrap();
+    Arc::new(
+        ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .unwrap(),
+    )
+}
+
+/// Accepts the self-signed test certificate (which webpki rejects as a CA
+/// used as an end entity), still checking handshake signatures.
+#[derive(Debug)]
+struct AcceptTestCert;
+
+impl ServerCertVerifier for AcceptTestCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        let algorithms = ring::default_provider().signature_verification_algorithms;
+        verify_tls12_signature(message, cert, dss, &algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        let algorithms = ring::default_provider().signature_verification_algorithms;
+        verify_tls13_signature(message, cert, dss, &algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn client_config() -> Arc<ClientConfig> {
+    Arc::new(
+        ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptTestCert))
+            .with_no_client_auth(),
+    )
+}
+
+/// Start a TLS server echoing each line back, prefixed with the SNI it
+/// received. Returns its port.
+async fn spawn_tls_echo() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let acceptor = TlsAcceptor::from(server_config());
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let Ok(tls) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let sni = tls.get_ref().1.server_name().unwrap_or("").to_string();
+                let (reader, mut writer) = tokio::io::split(tls);
+                let mut lines = BufReader::new(reader).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    writer
+                        .write_all(format!("{sni}: {line}\n").as_bytes())
+                        .await
+                        .unwrap();
+                }
+            });
+        }
+    });
+    port
+}
+
+#[tokio::test]
+async fn test_passthrough_relays_tls() {
+    let backend_port = spawn_tls_echo().await;
+
+    let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+    registry.register_devbox(
+        "my-db".to_string(),
+        "ns-test".to_string(),
+        "devbox1".to_string(),
+    );
+    registry
+        .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+        .unwrap();
+    let addr = common::spawn_passthrough(registry, vec!["devbox.local".to_string()]);
+
+    // The backend terminates TLS and sees the client's SNI
+    let host = format!("devbox-my-db-{backend_port}.devbox.local");
+    let stream = TcpStream::connect(&addr).await.unwrap();
+    let connector = TlsConnector::from(client_config());
+    let mut tls = connector
+        .connect(ServerName::try_from(host.clone()).unwrap(), stream)
+        .await
+        .unwrap();
+
+    let expected = format!("{host}: hello\n");
+    let mut reply = vec![0u8; expected.len()];
+    for _ in 0..2 {
+        tls.write_all(b"hello\n").await.unwrap();
+        tls.read_exact(&mut reply).await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&reply), expected);
+    }
+
+    // Unknown devboxes and plaintext connections are closed
+    let stream = TcpStream::connect(&addr).await.unwrap();
+    let host = format!("devbox-unknown-{backend_port}.devbox.local");
+    assert!(connector
+        .connect(ServerName::try_from(host).unwrap(), stream)
+        .await
+        .is_err());
+
+    let mut plaintext = TcpStream::connect(&addr).await.unwrap();
+    plaintext
+        .write_all(b"GET / HTTP/1.1\r\nHost: devbox.local\r\n\r\n")
+        .await
+        .unwrap();
+    let mut rest = Vec::new();
+    assert_eq!(plaintext.read_to_end(&mut rest).await.unwrap_or(0), 0);
+}
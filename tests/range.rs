@@ -0,0 +1,255 @@
+//! End-to-end tests of `Range` requests through the body-touching features.
+//!
+//! A local backend serves one representation with byte-range support, the
+//! way static file servers do, behind gateways with the response cache and
+//! `MAX_RESPONSE_BODY_BYTES` each on or off. Partial responses must reach
+//! the client exactly as the backend sent them.
+
+mod common;
+
+use std::io::{BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use httpgate::config::{Config, ListenerConfig};
+use httpgate::registry::DevboxRegistry;
+
+use common::{read_head, send, spawn_gateway, status};
+
+const SIZE: usize = 4096;
+const ETAG: &str = "\"v1\"";
+/// Under `SIZE`, so only ranges fit
+const LIMIT: u64 = SIZE as u64 / 2;
+
+/// The backend's representation
+fn content() -> Vec<u8> {
+    (0..SIZE).map(|i| b'a' + (i % 26) as u8).collect()
+}
+
+/// Header value of `name` in a request or response head
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().find_map(|line| {
+        let (n, v) = line.split_once(':')?;
+        n.eq_ignore_ascii_case(name).then(|| v.trim())
+    })
+}
+
+/// Start a backend serving [`content`] on every path, honoring `Range`
+/// (one `bytes=first-[last]` range) and `If-Range`. Returns its port and
+/// the number of requests it answered.
+fn spawn_range_backend() -> (u16, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&requests);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let counter = Arc::clone(&counter);
+            thread::spawn(move || serve(stream, &counter));
+        }
+    });
+    (port, requests)
+}
+
+fn serve(stream: TcpStream, requests: &AtomicUsize) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    loop {
+        let head = read_head(&mut reader);
+        if head.is_empty() {
+            return;
+        }
+        requests.fetch_add(1, Ordering::SeqCst);
+
+        let content = content();
+        let fresh = header(&head, "if-range").unwrap_or(ETAG) == ETAG;
+        let range = header(&head, "range")
+            .filter(|_| fresh)
+            .and_then(|v| v.strip_prefix("bytes="))
+            .and_then(|v| v.split_once('-'))
+            .and_then(|(first, last)| {
+                let first: usize = first.parse().ok()?;
+                let last = last.parse().unwrap_or(SIZE - 1).min(SIZE - 1);
+                (first <= last).then_some((first, last))
+            });
+        let common = format!(
+            "ETag: {ETAG}\r\nAccept-Ranges: bytes\r\n\
+             Cache-Control: public, max-age=60, no-transform\r\n\
+             Content-Type: application/octet-stream"
+        );
+        let written = match range {
+            Some((first, last)) => write!(
+                stream,
+                "HTTP/1.1 206 Partial Content\r\n{common}\r\nContent-Range: bytes {first}-{last}/{SIZE}\r\n\
+                 Content-Length: {}\r\n\r\n",
+                last - first + 1
+            )
+            .and_then(|()| stream.write_all(&content[first..=last])),
+            None => write!(
+                stream,
+                "HTTP/1.1 200 OK\r\n{common}\r\nContent-Length: {SIZE}\r\n\r\n"
+            )
+            .and_then(|()| stream.write_all(&content)),
+        };
+        if written.is_err() {
+            return;
+        }
+    }
+}
+
+/// A gateway in front of its own backend.
+struct Gateway {
+    addr: String,
+    host: String,
+    backend_requests: Arc<AtomicUsize>,
+    cache: bool,
+    limit: bool,
+}
+
+impl Gateway {
+    fn spawn(cache: bool, limit: bool) -> Self {
+        let (backend_port, backend_requests) = spawn_range_backend();
+
+        let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+        registry.register_devbox(
+            "range-test".to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+            .unwrap();
+
+        let config = Config {
+            cache_max_bytes: cache.then_some(1024 * 1024),
+            max_response_body_bytes: limit.then_some(LIMIT),
+            ..Default::default()
+        };
+        let listener = ListenerConfig::from_config(&config).policy;
+        let addrs = spawn_gateway(registry, config, vec![listener]);
+        Self {
+            addr: addrs[0].clone(),
+            host: format!("devbox-range-test-{backend_port}.devbox.local"),
+            backend_requests,
+            cache,
+            limit,
+        }
+    }
+
+    /// GET `path` with `headers`, returning the response head and body.
+    fn get(&self, path: &str, headers: &str) -> (String, String) {
+        send(
+            &self.addr,
+            &format!(
+                "GET {path} HTTP/1.1\r\nHost: {}\r\n{headers}\r\n",
+                self.host
+            ),
+        )
+    }
+}
+
+/// One gateway per combination of the response cache and the body limit.
+fn gateways() -> &'static [Gateway] {
+    static GATEWAYS: OnceLock<Vec<Gateway>> = OnceLock::new();
+    GATEWAYS.get_or_init(|| {
+        [(false, false), (true, false), (false, true), (true, true)]
+            .into_iter()
+            .map(|(cache, limit)| Gateway::spawn(cache, limit))
+            .collect()
+    })
+}
+
+/// Check a `206` carries bytes `first..=last`, as the backend sent them.
+fn assert_partial(gateway: &Gateway, (head, body): (String, String), first: usize, last: usize) {
+    let combination = format!("cache: {}, limit: {}", gateway.cache, gateway.limit);
+    assert_eq!(status(&head), 206, "{combination}, got: {head}");
+    assert_eq!(
+        header(&head, "content-range"),
+        Some(format!("bytes {first}-{last}/{SIZE}").as_str()),
+        "{combination}"
+    );
+    assert_eq!(header(&head, "etag"), Some(ETAG), "{combination}");
+    assert!(header(&head, "age").is_none(), "{combination}, got: {head}");
+    assert_eq!(body.as_bytes(), &content()[first..=last], "{combination}");
+}
+
+#[test]
+fn test_ranges_pass_through() {
+    for gateway in gateways() {
+        let path = "/ranges";
+        assert_partial(gateway, gateway.get(path, "Range: bytes=0-99\r\n"), 0, 99);
+        assert_partial(
+            gateway,
+            gateway.get(path, "Range: bytes=1000-1999\r\n"),
+            1000,
+            1999,
+        );
+        // Up to the end, as video players ask when seeking
+        assert_partial(
+            gateway,
+            gateway.get(path, "Range: bytes=3000-\r\n"),
+            3000,
+            SIZE - 1,
+        );
+        // Resuming a download of the same version
+        assert_partial(
+            gateway,
+            gateway.get(
+                path,
+                &format!("Range: bytes=2048-2147\r\nIf-Range: {ETAG}\r\n"),
+            ),
+            2048,
+            2147,
+        );
+    }
+}
+
+#[test]
+fn test_full_responses() {
+    for gateway in gateways() {
+        let path = "/full";
+        let (head, body) = gateway.get(path, "");
+        if gateway.limit {
+            // The whole representation is over the limit, its ranges aren't
+            assert_eq!(status(&head), 502, "got: {head}");
+            continue;
+        }
+        assert_eq!(status(&head), 200, "got: {head}");
+        assert_eq!(
+            header(&head, "cache-control"),
+            Some("public, max-age=60, no-transform")
+        );
+        assert_eq!(body.as_bytes(), content());
+
+        // A changed version: the backend answers with all of it
+        let (head, body) = gateway.get(path, "Range: bytes=0-99\r\nIf-Range: \"v0\"\r\n");
+        assert_eq!(status(&head), 200, "got: {head}");
+        assert_eq!(body.as_bytes(), content());
+    }
+}
+
+#[test]
+fn test_cached_object_not_served_for_ranges() {
+    let gateway = &gateways()[1];
+    assert!(gateway.cache && !gateway.limit);
+    let path = "/cached";
+
+    gateway.get(path, "");
+    let (head, body) = gateway.get(path, "");
+    assert_eq!(status(&head), 200, "got: {head}");
+    assert!(header(&head, "age").is_some(), "got: {head}");
+    assert_eq!(body.as_bytes(), content());
+
+    // Ranges of the stored object still come from the backend
+    let before = gateway.backend_requests.load(Ordering::SeqCst);
+    assert_partial(gateway, gateway.get(path, "Range: bytes=10-19\r\n"), 10, 19);
+    assert_eq!(gateway.backend_requests.load(Ordering::SeqCst), before + 1);
+
+    // ...and aren't stored in place of it
+    let (head, body) = gateway.get(path, "");
+    assert_eq!(status(&head), 200, "got: {head}");
+    assert!(header(&head, "age").is_some(), "got: {head}");
+    assert_eq!(body.as_bytes(), content());
+}
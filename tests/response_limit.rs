@@ -0,0 +1,164 @@
+//! End-to-end tests of `MAX_RESPONSE_BODY_BYTES`.
+//!
+//! A local backend answers with bodies over the limit, declared up front or
+//! streamed without end, to check both ways a response is cut off.
+
+mod common;
+
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use httpgate::config::{Config, ListenerConfig};
+use httpgate::registry::DevboxRegistry;
+
+use common::{connect, content_length, read_head, send, spawn_gateway, status};
+
+const LIMIT: u64 = 1024;
+
+/// Start a backend answering by path:
+/// - `/declared`: a body over the limit with its `Content-Length`
+/// - `/endless`: a chunked body that never ends
+/// - `/events`: an event stream over the limit
+/// - anything else: a small body
+fn spawn_oversized_backend() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || serve(stream));
+        }
+    });
+    port
+}
+
+fn serve(stream: TcpStream) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    let head = read_head(&mut reader);
+    let path = head.split_whitespace().nth(1).unwrap_or("/").to_string();
+    let chunk = vec![b'x'; 512];
+    match path.as_str() {
+        "/declared" => {
+            let body = vec![b'x'; 4 * LIMIT as usize];
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            );
+            let _ = stream.write_all(&body);
+        }
+        "/endless" => {
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+            // Until the gateway gives up on the connection
+            while write!(stream, "{:x}\r\n", chunk.len())
+                .and_then(|()| stream.write_all(&chunk))
+                .and_then(|()| stream.write_all(b"\r\n"))
+                .is_ok()
+            {}
+        }
+        "/events" => {
+            let _ = stream.write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\n\r\n",
+            );
+            for _ in 0..8 {
+                let _ = write!(stream, "{:x}\r\n", chunk.len());
+                let _ = stream.write_all(&chunk);
+                let _ = stream.write_all(b"\r\n");
+            }
+            let _ = stream.write_all(b"0\r\n\r\n");
+        }
+        _ => {
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nsmall");
+        }
+    }
+}
+
+/// Address of the proxy and the host routed to the backend.
+fn gateway() -> &'static (String, String) {
+    static GATEWAY: OnceLock<(String, String)> = OnceLock::new();
+    GATEWAY.get_or_init(|| {
+        let backend_port = spawn_oversized_backend();
+
+        let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+        registry.register_devbox(
+            "limit-test".to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+            .unwrap();
+
+        let config = Config {
+            max_response_body_bytes: Some(LIMIT),
+            ..Default::default()
+        };
+        let listener = ListenerConfig::from_config(&config).policy;
+        let addrs = spawn_gateway(registry, config, vec![listener]);
+        let host = format!("devbox-limit-test-{backend_port}.devbox.local");
+        (addrs[0].clone(), host)
+    })
+}
+
+fn get(path: &str) -> String {
+    let (_, host) = gateway();
+    format!("GET {path} HTTP/1.1\r\nHost: {host}\r\n\r\n")
+}
+
+#[test]
+fn test_small_response_passes() {
+    let (addr, _) = gateway();
+    let (head, body) = send(addr, &get("/small"));
+    assert_eq!(status(&head), 200, "got: {head}");
+    assert_eq!(body, "small");
+}
+
+#[test]
+fn test_declared_length_over_limit() {
+    // Rejected before the headers are sent
+    let (addr, _) = gateway();
+    let (head, body) = send(addr, &get("/declared"));
+    assert_eq!(status(&head), 502, "got: {head}");
+    assert!(body.contains("response_too_large"), "got: {body}");
+}
+
+#[test]
+fn test_streamed_body_over_limit() {
+    // The headers are out, so the response is cut short by closing the
+    // connection instead of ending the chunked body
+    let (addr, _) = gateway();
+    let (mut stream, mut reader) = connect(addr);
+    stream.write_all(get("/endless").as_bytes()).unwrap();
+
+    let head = read_head(&mut reader);
+    assert_eq!(status(&head), 200, "got: {head}");
+    assert_eq!(content_length(&head), 0, "got: {head}");
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body).unwrap();
+    assert!(body.len() < 2 * LIMIT as usize, "read {} bytes", body.len());
+    assert!(!body.ends_with(b"0\r\n\r\n"));
+}
+
+#[test]
+fn test_event_stream_exempt() {
+    let (addr, _) = gateway();
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(
+            format!(
+                "GET /events HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                gateway().1
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+    let head = read_head(&mut reader);
+    assert_eq!(status(&head), 200, "got: {head}");
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body).unwrap();
+    assert!(body.len() > 4 * LIMIT as usize, "read {} bytes", body.len());
+    assert!(body.ends_with(b"0\r\n\r\n"));
+}
@@ -0,0 +1,1130 @@
+//! End-to-end tests of watcher events flowing into the registry and proxy.
+//!
+//! Feeds scripted watch event sequences to the watchers instead of a
+//! Kubernetes API server, and checks registry contents and request routing
+//! after each step.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::thread;
+use std::time::Duration;
+
+use futures::executor::block_on;
+use futures::{future, stream, StreamExt};
+use httpgate::client_cert::{ClientCaRef, ClientCaStore};
+use httpgate::config::DevboxWatchMode;
+use httpgate::config::{ClusterConfig, Config, ListenerConfig};
+use httpgate::crd::{
+    Devbox, DevboxAppPort, DevboxConfig, DevboxNetwork, DevboxSpec, DevboxStatus, SlimDevbox,
+};
+use httpgate::limits::{NamespaceLimit, NamespaceLimiter};
+use httpgate::registry::{DevboxRegistry, WatchKind, DEFAULT_CLUSTER};
+use httpgate::snapshot;
+use httpgate::tls_reload::CertStore;
+use httpgate::watcher::{
+    ClientCaWatcher, DevboxWatcher, LimitsWatcher, PodWatcher, ResyncSignal, TlsSecretWatcher,
+    WatchItem, WatchScope,
+};
+use k8s_openapi::api::core::v1::{ConfigMap, Pod, PodCondition, PodStatus, Secret};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference};
+use k8s_openapi::ByteString;
+use kube::runtime::watcher::{Error, Event};
+
+use common::{send, spawn_backend, spawn_gateway, status};
+
+const NAMESPACE: &str = "ns-test";
+
+fn devbox(name: &str, unique_id: &str) -> Devbox {
+    Devbox {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some(NAMESPACE.to_string()),
+            ..Default::default()
+        },
+        spec: DevboxSpec::default(),
+        status: Some(DevboxStatus {
+            network: Some(DevboxNetwork {
+                unique_id: Some(unique_id.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+    }
+}
+
+fn pod(devbox_name: &str, ip: Option<&str>) -> Pod {
+    Pod {
+        metadata: ObjectMeta {
+            name: Some(format!("{devbox_name}-pod")),
+            namespace: Some(NAMESPACE.to_string()),
+            uid: Some(format!("{devbox_name}-uid")),
+            owner_references: Some(vec![OwnerReference {
+                kind: "Devbox".to_string(),
+                name: devbox_name.to_string(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+        status: Some(PodStatus {
+            pod_ip: ip.map(ToString::to_string),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Devbox whose status records its serving IP at `status.network.podIP`
+fn devbox_with_ip(name: &str, unique_id: &str, ip: Option<&str>) -> Devbox {
+    let mut devbox = devbox(name, unique_id);
+    if let Some(ip) = ip {
+        let network = devbox.status.as_mut().unwrap().network.as_mut().unwrap();
+        network.extra.insert("podIP".to_string(), ip.into());
+    }
+    devbox
+}
+
+/// Devbox declaring `app_port` as its only app port
+fn devbox_with_app_port(name: &str, unique_id: &str, app_port: u16) -> Devbox {
+    let mut devbox = devbox(name, unique_id);
+    devbox.spec.config = Some(DevboxConfig {
+        app_ports: vec![DevboxAppPort { port: app_port }],
+    });
+    devbox
+}
+
+/// Which of `unique_ids` are registered, with their Pod IPs.
+fn snapshot(registry: &DevboxRegistry, unique_ids: &[&str]) -> Vec<(String, Option<String>)> {
+    unique_ids
+        .iter()
+        .filter_map(|id| {
+            let info = registry.get_devbox(id)?;
+            let endpoint =
+                registry.get_pod_endpoint(&info.cluster, &info.namespace, &info.devbox_name);
+            Some(((*id).to_string(), endpoint.map(|e| e.ip)))
+        })
+        .collect()
+}
+
+struct Harness {
+    registry: Arc<DevboxRegistry>,
+    devboxes: DevboxWatcher,
+    pods: PodWatcher,
+    proxy_addr: String,
+    backend_port: u16,
+}
+
+impl Harness {
+    /// Misses are 404s whenever the watchers last listed
+    fn new() -> Self {
+        Self::with_config(Config {
+            registry_sync_grace: None,
+            ..Default::default()
+        })
+    }
+
+    fn with_config(config: Config) -> Self {
+        let backend_port = spawn_backend();
+        let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+        let listener = ListenerConfig::from_config(&config).policy;
+        let addrs = spawn_gateway(Arc::clone(&registry), config, vec![listener]);
+
+        Self {
+            devboxes: DevboxWatcher::new(Arc::clone(&registry)),
+            pods: PodWatcher::new(Arc::clone(&registry)),
+            registry,
+            proxy_addr: addrs[0].clone(),
+            backend_port,
+        }
+    }
+
+    fn devbox_events(&self, events: Vec<Result<Event<Devbox>, Error>>) {
+        block_on(self.devboxes.run_with_stream(stream::iter(events)));
+    }
+
+    fn pod_events(&self, events: Vec<Result<Event<Pod>, Error>>) {
+        block_on(self.pods.run_with_stream(stream::iter(events)));
+    }
+
+    /// Status of a request routed to `unique_id`.
+    fn status(&self, unique_id: &str) -> u16 {
+        let host = format!("devbox-{unique_id}-{}.devbox.local", self.backend_port);
+        let (head, _) = send(
+            &self.proxy_addr,
+            &format!("GET / HTTP/1.1\r\nHost: {host}\r\n\r\n"),
+        );
+        status(&head)
+    }
+
+    /// Status of a request to the portless host of `unique_id`.
+    fn portless_status(&self, unique_id: &str) -> u16 {
+        let (head, _) = send(
+            &self.proxy_addr,
+            &format!("GET / HTTP/1.1\r\nHost: devbox-{unique_id}.devbox.local\r\n\r\n"),
+        );
+        status(&head)
+    }
+}
+
+#[test]
+fn test_initial_list_and_updates() {
+    let h = Harness::new();
+
+    h.devbox_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(devbox("devbox-a", "app-a"))),
+        Ok(Event::InitApply(devbox("devbox-b", "app-b"))),
+        Ok(Event::InitDone),
+    ]);
+    h.pod_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(pod("devbox-a", Some("127.0.0.1")))),
+        Ok(Event::InitApply(pod("devbox-b", None))),
+        Ok(Event::InitDone),
+    ]);
+    assert_eq!(
+        snapshot(&h.registry, &["app-a", "app-b"]),
+        vec![
+            ("app-a".to_string(), Some("127.0.0.1".to_string())),
+            ("app-b".to_string(), None),
+        ]
+    );
+    assert_eq!(h.status("app-a"), 200);
+    assert_eq!(h.status("app-b"), 503);
+    assert_eq!(h.status("app-c"), 404);
+
+    // A devbox created after the initial list, whose Pod comes up later
+    h.devbox_events(vec![Ok(Event::Apply(devbox("devbox-c", "app-c")))]);
+    assert_eq!(h.status("app-c"), 503);
+    h.pod_events(vec![Ok(Event::Apply(pod("devbox-c", Some("127.0.0.1"))))]);
+    assert_eq!(h.status("app-c"), 200);
+
+    // Pod deletion makes the devbox unavailable, devbox deletion unknown
+    h.pod_events(vec![Ok(Event::Delete(pod("devbox-c", Some("127.0.0.1"))))]);
+    assert_eq!(h.status("app-c"), 503);
+    h.devbox_events(vec![Ok(Event::Delete(devbox("devbox-c", "app-c")))]);
+    assert_eq!(h.status("app-c"), 404);
+
+    // Watch errors leave the registry untouched
+    h.devbox_events(vec![Err(Error::NoResourceVersion)]);
+    h.pod_events(vec![Err(Error::NoResourceVersion)]);
+    assert_eq!(h.registry.devbox_count(), 2);
+    assert_eq!(h.status("app-a"), 200);
+}
+
+#[test]
+fn test_misses_while_syncing() {
+    let grace = Duration::from_millis(300);
+    let h = Harness::with_config(Config {
+        registry_sync_grace: Some(grace),
+        ..Default::default()
+    });
+    // Watches that haven't listed yet don't count
+    assert_eq!(h.status("app-b"), 404);
+
+    h.devbox_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(devbox("devbox-a", "app-a"))),
+    ]);
+    assert_eq!(h.status("app-b"), 503);
+    h.devbox_events(vec![Ok(Event::InitDone)]);
+    assert_eq!(h.status("app-b"), 503);
+    thread::sleep(grace * 2);
+    assert_eq!(h.status("app-b"), 404);
+
+    // A relist of the other watch, e.g. after a reconnect
+    h.pod_events(vec![Ok(Event::Init)]);
+    assert_eq!(h.status("app-b"), 503);
+    h.pod_events(vec![
+        Ok(Event::InitApply(pod("devbox-a", Some("127.0.0.1")))),
+        Ok(Event::InitDone),
+    ]);
+    assert_eq!(h.status("app-a"), 200);
+    thread::sleep(grace * 2);
+    assert_eq!(h.status("app-b"), 404);
+}
+
+#[test]
+fn test_relist_clears_stale_entries() {
+    let h = Harness::new();
+
+    h.devbox_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(devbox("devbox-a", "app-a"))),
+        Ok(Event::InitApply(devbox("devbox-b", "app-b"))),
+        Ok(Event::InitDone),
+    ]);
+    h.pod_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(pod("devbox-a", Some("127.0.0.1")))),
+        Ok(Event::InitApply(pod("devbox-b", Some("127.0.0.1")))),
+        Ok(Event::InitDone),
+    ]);
+    assert_eq!(h.status("app-a"), 200);
+    assert_eq!(h.status("app-b"), 200);
+
+    // The watch reconnects and devbox-a was deleted while it was down: the
+    // relist starts from an empty registry
+    h.devbox_events(vec![Err(Error::NoResourceVersion), Ok(Event::Init)]);
+    assert_eq!(h.registry.devbox_count(), 0);
+    assert_eq!(h.status("app-b"), 404);
+
+    h.devbox_events(vec![
+        Ok(Event::InitApply(devbox("devbox-b", "app-b"))),
+        Ok(Event::InitDone),
+    ]);
+    assert_eq!(
+        snapshot(&h.registry, &["app-a", "app-b"]),
+        vec![("app-b".to_string(), Some("127.0.0.1".to_string()))]
+    );
+    assert_eq!(h.status("app-a"), 404);
+    assert_eq!(h.status("app-b"), 200);
+
+    // Same for Pods: a Pod missing from the relist loses its IP
+    h.pod_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(pod("devbox-a", Some("127.0.0.1")))),
+        Ok(Event::InitDone),
+    ]);
+    assert_eq!(h.registry.pod_ip_count(), 1);
+    assert_eq!(h.status("app-b"), 503);
+}
+
+#[test]
+fn test_resync_relists() {
+    let registry = Arc::new(DevboxRegistry::new());
+    registry.add_cluster(DEFAULT_CLUSTER);
+    let signal = ResyncSignal::default();
+    let watcher = DevboxWatcher::new(Arc::clone(&registry)).with_resync(signal.clone());
+
+    // Each watch lists one Devbox, then stays open
+    let opened = AtomicUsize::new(0);
+    let open = || {
+        let (name, unique_id) = match opened.fetch_add(1, Ordering::SeqCst) {
+            0 => ("devbox-a", "app-a"),
+            _ => ("devbox-b", "app-b"),
+        };
+        stream::iter(vec![
+            (WatchScope::Cluster, Ok(Event::Init)),
+            (
+                WatchScope::Cluster,
+                Ok(Event::InitApply(devbox(name, unique_id))),
+            ),
+            (WatchScope::Cluster, Ok(Event::InitDone)),
+        ])
+        .chain(stream::pending())
+    };
+    fn until(ready: impl Fn() -> bool) -> impl std::future::Future<Output = ()> {
+        future::poll_fn(move |cx| {
+            if ready() {
+                Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+    }
+
+    let driver = async {
+        until(|| registry.get_devbox("app-a").is_some()).await;
+        assert_eq!(signal.request(), 1);
+        until(|| registry.get_devbox("app-b").is_some()).await;
+    };
+    let run = watcher.run_with_streams(open);
+    block_on(future::select(std::pin::pin!(run), std::pin::pin!(driver)));
+
+    // The relist replaced the registry's entries
+    assert_eq!(opened.load(Ordering::SeqCst), 2);
+    assert!(registry.get_devbox("app-a").is_none());
+    assert_eq!(registry.devbox_count(), 1);
+}
+
+/// `devbox` as of `resource_version`
+fn versioned(mut devbox: Devbox, resource_version: &str) -> Devbox {
+    devbox.metadata.resource_version = Some(resource_version.to_string());
+    devbox
+}
+
+/// Registry restored from the snapshot of one that listed devbox-a and
+/// devbox-b, with their Pod IPs, and got to `resource_version` 100 of both
+/// watches
+fn restored_registry() -> Arc<DevboxRegistry> {
+    let source = DevboxRegistry::new();
+    for (name, unique_id) in [("devbox-a", "app-a"), ("devbox-b", "app-b")] {
+        source.register_devbox(
+            unique_id.to_string(),
+            NAMESPACE.to_string(),
+            name.to_string(),
+        );
+        source
+            .update_pod_ip(NAMESPACE, name, "10.0.0.1".to_string())
+            .unwrap();
+    }
+    for kind in [WatchKind::Devboxes, WatchKind::Pods] {
+        source.record_bookmark(DEFAULT_CLUSTER, kind, None, "100");
+    }
+    let document = serde_json::to_vec(&snapshot::export(&source)).unwrap();
+
+    let registry = Arc::new(DevboxRegistry::new());
+    registry.add_cluster(DEFAULT_CLUSTER);
+    let report = snapshot::restore(&registry, &snapshot::parse(&document).unwrap());
+    assert!(report.errors.is_empty(), "{report:?}");
+    registry
+}
+
+#[test]
+fn test_resume_from_bookmark() {
+    let registry = restored_registry();
+    let devboxes = DevboxWatcher::new(Arc::clone(&registry));
+    let pods = PodWatcher::new(Arc::clone(&registry));
+    let bookmark = |kind| registry.bookmark(DEFAULT_CLUSTER, kind, None);
+    assert_eq!(bookmark(WatchKind::Devboxes).as_deref(), Some("100"));
+    assert!(!registry.is_synced());
+
+    // The watches resume where the snapshot left off: the restored entries
+    // stand and only the changes since arrive
+    block_on(devboxes.run_with_scoped_items(stream::iter(vec![
+        (WatchScope::Cluster, WatchItem::Resumed),
+        (
+            WatchScope::Cluster,
+            WatchItem::Event(Ok(Event::Apply(versioned(
+                devbox("devbox-c", "app-c"),
+                "105",
+            )))),
+        ),
+        (
+            WatchScope::Cluster,
+            WatchItem::Event(Ok(Event::Delete(versioned(
+                devbox("devbox-b", "app-b"),
+                "106",
+            )))),
+        ),
+    ])));
+    assert_eq!(
+        snapshot(&registry, &["app-a", "app-b", "app-c"]),
+        vec![
+            ("app-a".to_string(), Some("10.0.0.1".to_string())),
+            ("app-c".to_string(), None),
+        ]
+    );
+    assert_eq!(bookmark(WatchKind::Devboxes).as_deref(), Some("106"));
+
+    block_on(pods.run_with_scoped_items(stream::iter(vec![
+        (WatchScope::Cluster, WatchItem::Resumed),
+        (WatchScope::Cluster, WatchItem::Bookmark("110".to_string())),
+    ])));
+    assert!(registry.is_synced());
+    assert_eq!(registry.pod_ip_count(), 2);
+    assert_eq!(bookmark(WatchKind::Pods).as_deref(), Some("110"));
+
+    // The next snapshot resumes from there
+    let saved = snapshot::export(&registry);
+    assert_eq!(
+        saved
+            .bookmarks
+            .iter()
+            .map(|b| (b.kind, b.resource_version.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(WatchKind::Devboxes, "106"), (WatchKind::Pods, "110")]
+    );
+}
+
+#[test]
+fn test_expired_bookmark_relists() {
+    let registry = restored_registry();
+    let watcher = DevboxWatcher::new(Arc::clone(&registry));
+    let bookmark = || registry.bookmark(DEFAULT_CLUSTER, WatchKind::Devboxes, None);
+
+    // The bookmark is too old to resume from (410 Gone)
+    block_on(watcher.run_with_scoped_items(stream::iter(vec![(
+        WatchScope::Cluster,
+        WatchItem::Expired,
+    )])));
+    assert_eq!(bookmark(), None);
+    assert_eq!(registry.devbox_count(), 2);
+
+    // devbox-a was deleted while the gateway was down: the list sweeps out
+    // the restored entries it doesn't bring back, and watches on from the
+    // newest Devbox listed
+    let items = vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(versioned(
+            devbox("devbox-b", "app-b"),
+            "120",
+        ))),
+        Ok(Event::InitDone),
+    ]
+    .into_iter()
+    .map(WatchItem::Event)
+    .chain([WatchItem::Bookmark("120".to_string())])
+    .map(|item| (WatchScope::Cluster, item));
+    block_on(watcher.run_with_scoped_items(stream::iter(items)));
+    assert_eq!(
+        snapshot(&registry, &["app-a", "app-b"]),
+        vec![("app-b".to_string(), Some("10.0.0.1".to_string()))]
+    );
+    assert!(registry.clusters()[DEFAULT_CLUSTER].devbox_watch.synced);
+    assert_eq!(bookmark().as_deref(), Some("120"));
+}
+
+#[test]
+fn test_unready_pods_not_routed() {
+    let h = Harness::new();
+    let pods = PodWatcher::new(Arc::clone(&h.registry)).with_require_ready(true);
+    let pod_events = |events: Vec<Result<Event<Pod>, Error>>| {
+        block_on(pods.run_with_stream(stream::iter(events)));
+    };
+    let with_ready = |ready: &str| {
+        let mut pod = pod("devbox-a", Some("127.0.0.1"));
+        pod.status.as_mut().unwrap().conditions = Some(vec![PodCondition {
+            type_: "Ready".to_string(),
+            status: ready.to_string(),
+            ..Default::default()
+        }]);
+        pod
+    };
+    h.devbox_events(vec![Ok(Event::Apply(devbox("devbox-a", "app-a")))]);
+
+    // An IP alone, or a failing readiness probe, isn't a backend
+    pod_events(vec![Ok(Event::Apply(pod("devbox-a", Some("127.0.0.1"))))]);
+    assert_eq!(h.registry.pod_ip_count(), 0);
+    pod_events(vec![Ok(Event::Apply(with_ready("False")))]);
+    assert_eq!(h.registry.pod_ip_count(), 0);
+    assert_eq!(h.status("app-a"), 503);
+
+    pod_events(vec![Ok(Event::Apply(with_ready("True")))]);
+    assert_eq!(h.registry.pod_ip_count(), 1);
+    assert_eq!(h.status("app-a"), 200);
+
+    // Turning unready again takes the Pod out of routing
+    pod_events(vec![Ok(Event::Apply(with_ready("False")))]);
+    assert_eq!(h.registry.pod_ip_count(), 0);
+    assert_eq!(h.status("app-a"), 503);
+
+    // Without the requirement the IP is enough
+    h.pod_events(vec![Ok(Event::Apply(with_ready("False")))]);
+    assert_eq!(h.registry.pod_ip_count(), 1);
+}
+
+#[test]
+fn test_two_clusters() {
+    let h = Harness::new();
+    let west = ClusterConfig::from_context("west");
+    let west_devboxes = DevboxWatcher::new(Arc::clone(&h.registry)).with_cluster(west.clone());
+    let west_pods = PodWatcher::new(Arc::clone(&h.registry)).with_cluster(west);
+    let west_devbox_events = |events: Vec<Result<Event<Devbox>, Error>>| {
+        block_on(west_devboxes.run_with_stream(stream::iter(events)));
+    };
+    let west_pod_events = |events: Vec<Result<Event<Pod>, Error>>| {
+        block_on(west_pods.run_with_stream(stream::iter(events)));
+    };
+    h.registry.add_cluster(DEFAULT_CLUSTER);
+    h.registry.add_cluster("west");
+
+    h.devbox_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(devbox("devbox-a", "app-a"))),
+        Ok(Event::InitDone),
+    ]);
+    h.pod_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(pod("devbox-a", Some("127.0.0.1")))),
+        Ok(Event::InitDone),
+    ]);
+    assert!(!h.registry.is_synced());
+
+    // The same namespace and names exist in both clusters; devbox-dup
+    // claims the uniqueID already taken in the default cluster
+    west_devbox_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(devbox("devbox-w", "app-w"))),
+        Ok(Event::InitApply(devbox("devbox-dup", "app-a"))),
+        Ok(Event::InitDone),
+    ]);
+    west_pod_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(pod("devbox-w", Some("127.0.0.1")))),
+        Ok(Event::InitApply(pod("devbox-dup", None))),
+        Ok(Event::InitDone),
+    ]);
+    assert!(h.registry.is_synced());
+    assert_eq!(h.registry.devbox_count(), 2);
+    assert_eq!(
+        &*h.registry.get_devbox("app-a").unwrap().cluster,
+        DEFAULT_CLUSTER
+    );
+    assert_eq!(&*h.registry.get_devbox("app-w").unwrap().cluster, "west");
+    assert_eq!(h.status("app-a"), 200);
+    assert_eq!(h.status("app-w"), 200);
+
+    // Deleting the conflicting devbox leaves the one serving the uniqueID
+    west_devbox_events(vec![Ok(Event::Delete(devbox("devbox-dup", "app-a")))]);
+    assert_eq!(h.status("app-a"), 200);
+
+    // Losing the west watch keeps its entries; its relist only clears its own
+    west_devbox_events(vec![Err(Error::NoResourceVersion)]);
+    let clusters = h.registry.clusters();
+    assert!(clusters["west"].devbox_watch.failing);
+    assert_eq!(clusters["west"].devbox_watch.errors, 1);
+    assert!(!clusters[DEFAULT_CLUSTER].devbox_watch.failing);
+    assert_eq!(h.status("app-w"), 200);
+
+    west_devbox_events(vec![Ok(Event::Init)]);
+    west_pod_events(vec![Ok(Event::Init)]);
+    assert!(!h.registry.is_synced());
+    let clusters = h.registry.clusters();
+    assert_eq!(
+        (clusters["west"].devboxes, clusters["west"].pod_ips),
+        (0, 0)
+    );
+    assert_eq!(
+        (
+            clusters[DEFAULT_CLUSTER].devboxes,
+            clusters[DEFAULT_CLUSTER].pod_ips
+        ),
+        (1, 1)
+    );
+    assert_eq!(h.status("app-w"), 404);
+    assert_eq!(h.status("app-a"), 200);
+}
+
+#[test]
+fn test_namespaced_watches() {
+    let h = Harness::new();
+    h.registry.add_cluster(DEFAULT_CLUSTER);
+    let namespaces = ["team-a", " team-b ", "team-a", ""].map(String::from);
+    let devboxes = DevboxWatcher::new(Arc::clone(&h.registry)).with_namespaces(&namespaces);
+    let pods = PodWatcher::new(Arc::clone(&h.registry)).with_namespaces(&namespaces);
+    let (a, b) = (
+        WatchScope::Namespace("team-a".to_string()),
+        WatchScope::Namespace("team-b".to_string()),
+    );
+    assert_eq!(devboxes.scopes(), [a.clone(), b.clone()]);
+    assert_eq!(pods.scopes(), [a.clone(), b.clone()]);
+    assert_eq!(
+        DevboxWatcher::new(Arc::clone(&h.registry)).scopes(),
+        [WatchScope::Cluster]
+    );
+
+    let in_namespace = |namespace: &str, name: &str, unique_id: &str| {
+        let mut devbox = devbox(name, unique_id);
+        devbox.metadata.namespace = Some(namespace.to_string());
+        devbox
+    };
+    let pod_in_namespace = |namespace: &str, name: &str| {
+        let mut pod = pod(name, Some("127.0.0.1"));
+        pod.metadata.namespace = Some(namespace.to_string());
+        pod
+    };
+    let devbox_events = |events: Vec<(WatchScope, Result<Event<Devbox>, Error>)>| {
+        block_on(devboxes.run_with_scoped_stream(stream::iter(events)));
+    };
+    let pod_events = |events: Vec<(WatchScope, Result<Event<Pod>, Error>)>| {
+        block_on(pods.run_with_scoped_stream(stream::iter(events)));
+    };
+    let devboxes_synced = || h.registry.clusters()[DEFAULT_CLUSTER].devbox_watch.synced;
+
+    // The streams of both namespaces interleave; the watch is only synced
+    // once both have listed
+    devbox_events(vec![
+        (a.clone(), Ok(Event::Init)),
+        (
+            a.clone(),
+            Ok(Event::InitApply(in_namespace(
+                "team-a", "devbox-a", "app-a",
+            ))),
+        ),
+        (b.clone(), Ok(Event::Init)),
+        (a.clone(), Ok(Event::InitDone)),
+        (
+            b.clone(),
+            Ok(Event::InitApply(in_namespace(
+                "team-b", "devbox-b", "app-b",
+            ))),
+        ),
+    ]);
+    assert!(!devboxes_synced());
+    devbox_events(vec![(b.clone(), Ok(Event::InitDone))]);
+    assert!(devboxes_synced());
+
+    pod_events(vec![
+        (b.clone(), Ok(Event::Init)),
+        (a.clone(), Ok(Event::Init)),
+        (
+            a.clone(),
+            Ok(Event::InitApply(pod_in_namespace("team-a", "devbox-a"))),
+        ),
+        (
+            b.clone(),
+            Ok(Event::InitApply(pod_in_namespace("team-b", "devbox-b"))),
+        ),
+        (b.clone(), Ok(Event::InitDone)),
+        (a.clone(), Ok(Event::InitDone)),
+    ]);
+    assert!(h.registry.is_synced());
+    assert_eq!(h.status("app-a"), 200);
+    assert_eq!(h.status("app-b"), 200);
+
+    // A relist of one namespace only clears that namespace's entries
+    devbox_events(vec![(a.clone(), Ok(Event::Init))]);
+    pod_events(vec![(a.clone(), Ok(Event::Init))]);
+    assert!(!devboxes_synced());
+    assert_eq!(h.status("app-a"), 404);
+    assert_eq!(h.status("app-b"), 200);
+
+    devbox_events(vec![
+        (
+            a.clone(),
+            Ok(Event::InitApply(in_namespace(
+                "team-a", "devbox-a", "app-a",
+            ))),
+        ),
+        (a.clone(), Ok(Event::InitDone)),
+    ]);
+    pod_events(vec![
+        (
+            a.clone(),
+            Ok(Event::InitApply(pod_in_namespace("team-a", "devbox-a"))),
+        ),
+        (a.clone(), Ok(Event::InitDone)),
+    ]);
+    assert!(h.registry.is_synced());
+    assert_eq!(h.status("app-a"), 200);
+}
+
+/// A Devbox object as the API server sends it, managed fields included
+fn devbox_object(name: &str, unique_id: &str, annotations: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "apiVersion": "devbox.sealos.io/v1alpha2",
+        "kind": "Devbox",
+        "metadata": {
+            "name": name,
+            "namespace": NAMESPACE,
+            "uid": format!("{name}-uid"),
+            "resourceVersion": "1",
+            "labels": {"app.kubernetes.io/name": name},
+            "annotations": annotations,
+            "managedFields": [{"manager": "devbox-controller", "operation": "Update", "fieldsV1": {}}]
+        },
+        "spec": {"state": "Running", "config": {"appPorts": [{"name": "app", "port": 3000}]}},
+        "status": {
+            "network": {"uniqueID": unique_id, "nodePort": 30000, "type": "NodePort"},
+            "phase": "Running"
+        }
+    })
+}
+
+#[test]
+fn test_slim_watch_matches_full() {
+    let objects = [
+        devbox_object("devbox-a", "app-a", serde_json::json!({})),
+        devbox_object(
+            "devbox-b",
+            "app-b",
+            serde_json::json!({"devbox.sealos.io/response-cache": "false"}),
+        ),
+        devbox_object("devbox-c", "app-c", serde_json::Value::Null),
+    ];
+    // Relist, then an update and a delete
+    let script = [
+        ("init", None),
+        ("apply", Some(&objects[0])),
+        ("apply", Some(&objects[1])),
+        ("apply", Some(&objects[2])),
+        ("done", None),
+        ("update", Some(&objects[1])),
+        ("delete", Some(&objects[2])),
+    ];
+    let events =
+        |decode: &dyn Fn(&serde_json::Value) -> Devbox| -> Vec<Result<Event<Devbox>, Error>> {
+            script
+                .iter()
+                .map(|(kind, object)| {
+                    Ok(match (*kind, object.map(decode)) {
+                        ("init", _) => Event::Init,
+                        ("apply", Some(devbox)) => Event::InitApply(devbox),
+                        ("done", _) => Event::InitDone,
+                        ("update", Some(devbox)) => Event::Apply(devbox),
+                        (_, Some(devbox)) => Event::Delete(devbox),
+                        _ => unreachable!(),
+                    })
+                })
+                .collect()
+        };
+    let full = events(&|object| serde_json::from_value(object.clone()).unwrap());
+    let slim = events(&|object| {
+        serde_json::from_value::<SlimDevbox>(object.clone())
+            .unwrap()
+            .into()
+    });
+
+    let registered = |events| {
+        let registry = Arc::new(DevboxRegistry::new());
+        let watcher = DevboxWatcher::new(Arc::clone(&registry));
+        block_on(watcher.run_with_stream(stream::iter(events)));
+        ["app-a", "app-b", "app-c"]
+            .into_iter()
+            .filter_map(|id| registry.get_devbox(id))
+            .map(|info| {
+                (
+                    info.namespace,
+                    info.devbox_name,
+                    info.app_port,
+                    (*info.policy).clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let registered_full = registered(full);
+    assert_eq!(registered_full.len(), 2);
+    assert_eq!(registered_full[0].2, Some(3000));
+    assert_eq!(registered(slim), registered_full);
+
+    // Pod IPs from the status need the full object
+    let registry = Arc::new(DevboxRegistry::new());
+    let watcher = DevboxWatcher::new(Arc::clone(&registry)).with_watch_mode(DevboxWatchMode::Slim);
+    assert_eq!(watcher.watch_mode(), DevboxWatchMode::Slim);
+    let watcher = watcher.with_pod_ip_status_field("network.podIP".to_string());
+    assert_eq!(watcher.watch_mode(), DevboxWatchMode::Full);
+}
+
+#[test]
+fn test_pod_ips_from_devbox_status() {
+    let h = Harness::new();
+    let devboxes = DevboxWatcher::new(Arc::clone(&h.registry))
+        .with_pod_ip_status_field("network.podIP".to_string());
+    let devbox_events = |events: Vec<Result<Event<Devbox>, Error>>| {
+        block_on(devboxes.run_with_stream(stream::iter(events)));
+    };
+    h.registry.add_cluster(DEFAULT_CLUSTER);
+
+    // No Pod watch: the Devbox watch alone makes the registry synced
+    devbox_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(devbox_with_ip(
+            "devbox-a",
+            "app-a",
+            Some("127.0.0.1"),
+        ))),
+        Ok(Event::InitApply(devbox_with_ip("devbox-b", "app-b", None))),
+        Ok(Event::InitDone),
+    ]);
+    assert!(h.registry.is_synced());
+    assert_eq!(
+        snapshot(&h.registry, &["app-a", "app-b"]),
+        vec![
+            ("app-a".to_string(), Some("127.0.0.1".to_string())),
+            ("app-b".to_string(), None),
+        ]
+    );
+    assert_eq!(h.status("app-a"), 200);
+    assert_eq!(h.status("app-b"), 503);
+
+    // The IP comes and goes with status updates
+    devbox_events(vec![Ok(Event::Apply(devbox_with_ip(
+        "devbox-b",
+        "app-b",
+        Some("127.0.0.1"),
+    )))]);
+    assert_eq!(h.status("app-b"), 200);
+    devbox_events(vec![Ok(Event::Apply(devbox_with_ip(
+        "devbox-a", "app-a", None,
+    )))]);
+    assert_eq!(h.status("app-a"), 503);
+
+    // Deleting the devbox drops its IP too
+    devbox_events(vec![Ok(Event::Delete(devbox_with_ip(
+        "devbox-b",
+        "app-b",
+        Some("127.0.0.1"),
+    )))]);
+    assert_eq!(h.status("app-b"), 404);
+    assert_eq!(h.registry.pod_ip_count(), 0);
+
+    // A relist starts the Pod index over as well
+    devbox_events(vec![Ok(Event::Apply(devbox_with_ip(
+        "devbox-a",
+        "app-a",
+        Some("127.0.0.1"),
+    )))]);
+    devbox_events(vec![Ok(Event::Init)]);
+    assert!(!h.registry.is_synced());
+    assert_eq!(h.registry.pod_ip_count(), 0);
+}
+
+#[test]
+fn test_portless_hosts_follow_app_port() {
+    let h = Harness::new();
+
+    h.devbox_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(devbox_with_app_port(
+            "devbox-a",
+            "app-a",
+            h.backend_port,
+        ))),
+        Ok(Event::InitApply(devbox("devbox-b", "app-b"))),
+        Ok(Event::InitDone),
+    ]);
+    h.pod_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(pod("devbox-a", Some("127.0.0.1")))),
+        Ok(Event::InitApply(pod("devbox-b", Some("127.0.0.1")))),
+        Ok(Event::InitDone),
+    ]);
+    assert_eq!(
+        h.registry.get_devbox("app-a").unwrap().app_port,
+        Some(h.backend_port)
+    );
+    assert_eq!(h.portless_status("app-a"), 200);
+    // Without a declared app port, only the numeric form routes
+    assert_eq!(h.portless_status("app-b"), 404);
+    assert_eq!(h.status("app-b"), 200);
+
+    // Spec updates move the portless host along
+    h.devbox_events(vec![Ok(Event::Apply(devbox_with_app_port(
+        "devbox-b",
+        "app-b",
+        h.backend_port,
+    )))]);
+    assert_eq!(h.portless_status("app-b"), 200);
+    h.devbox_events(vec![Ok(Event::Apply(devbox("devbox-a", "app-a")))]);
+    assert_eq!(h.registry.get_devbox("app-a").unwrap().app_port, None);
+    assert_eq!(h.portless_status("app-a"), 404);
+    assert_eq!(h.status("app-a"), 200);
+}
+
+fn limits_configmap(data: &[(&str, &str)]) -> ConfigMap {
+    ConfigMap {
+        metadata: ObjectMeta {
+            name: Some("httpgate-limits".to_string()),
+            namespace: Some("httpgate".to_string()),
+            ..Default::default()
+        },
+        data: Some(
+            data.iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect::<BTreeMap<_, _>>(),
+        ),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_namespace_limits() {
+    let defaults = NamespaceLimit {
+        max_inflight: Some(100),
+        rate: None,
+        log_sample: None,
+    };
+    let limiter = Arc::new(NamespaceLimiter::new(defaults));
+    let watcher = LimitsWatcher::new(
+        Arc::clone(&limiter),
+        "httpgate".to_string(),
+        "httpgate-limits".to_string(),
+    );
+    let apply = |events: Vec<Result<Event<ConfigMap>, Error>>| {
+        block_on(watcher.run_with_stream(stream::iter(events)));
+    };
+
+    apply(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(limits_configmap(&[
+            (NAMESPACE, "max-inflight=2"),
+            ("ns-bad", "max-inflight=lots"),
+        ]))),
+        Ok(Event::InitDone),
+    ]);
+    assert_eq!(limiter.limit_for(NAMESPACE).max_inflight, Some(2));
+    assert_eq!(limiter.limit_for("ns-bad"), defaults);
+    let _a = limiter.try_acquire(NAMESPACE).unwrap();
+    let _b = limiter.try_acquire(NAMESPACE).unwrap();
+    assert!(limiter.try_acquire(NAMESPACE).is_err());
+
+    apply(vec![Ok(Event::Apply(limits_configmap(&[(
+        NAMESPACE,
+        "max-inflight=5",
+    )])))]);
+    assert_eq!(limiter.limit_for(NAMESPACE).max_inflight, Some(5));
+    assert!(limiter.try_acquire(NAMESPACE).is_ok());
+
+    apply(vec![Ok(Event::Delete(limits_configmap(&[])))]);
+    assert_eq!(limiter.limit_for(NAMESPACE), defaults);
+
+    // Deleted while the watch was down: the relist finds nothing
+    apply(vec![Ok(Event::Apply(limits_configmap(&[(
+        NAMESPACE,
+        "max-inflight=1",
+    )])))]);
+    apply(vec![
+        Err(Error::NoResourceVersion),
+        Ok(Event::Init),
+        Ok(Event::InitDone),
+    ]);
+    assert_eq!(limiter.limit_for(NAMESPACE), defaults);
+}
+
+fn tls_secret(cert: &[u8], key: &[u8]) -> Secret {
+    Secret {
+        metadata: ObjectMeta {
+            name: Some("wildcard-tls".to_string()),
+            namespace: Some("ingress".to_string()),
+            ..Default::default()
+        },
+        type_: Some("kubernetes.io/tls".to_string()),
+        data: Some(BTreeMap::from([
+            ("tls.crt".to_string(), ByteString(cert.to_vec())),
+            ("tls.key".to_string(), ByteString(key.to_vec())),
+        ])),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_tls_secret_rotation() {
+    const CERT: &[u8] = include_bytes!("fixtures/tls.crt");
+    const KEY: &[u8] = include_bytes!("fixtures/tls.key");
+    const ROTATED_CERT: &[u8] = include_bytes!("fixtures/tls-rotated.crt");
+    const ROTATED_KEY: &[u8] = include_bytes!("fixtures/tls-rotated.key");
+
+    let certs = Arc::new(CertStore::new());
+    let watcher = TlsSecretWatcher::new(
+        Arc::clone(&certs),
+        "ingress".to_string(),
+        "wildcard-tls".to_string(),
+    );
+    let apply = |events: Vec<Result<Event<Secret>, Error>>| {
+        block_on(watcher.run_with_stream(stream::iter(events)));
+    };
+
+    apply(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(tls_secret(CERT, KEY))),
+        Ok(Event::InitDone),
+    ]);
+    assert_eq!(certs.not_after(), Some(4_945_740_598));
+
+    // A half-rotated Secret, with the new certificate but the old key, is
+    // rejected, as is one missing a field
+    apply(vec![Ok(Event::Apply(tls_secret(ROTATED_CERT, KEY)))]);
+    let mut incomplete = tls_secret(ROTATED_CERT, ROTATED_KEY);
+    incomplete.data.as_mut().unwrap().remove("tls.key");
+    apply(vec![Ok(Event::Apply(incomplete))]);
+    assert_eq!(certs.not_after(), Some(4_945_740_598));
+
+    apply(vec![Ok(Event::Apply(tls_secret(
+        ROTATED_CERT,
+        ROTATED_KEY,
+    )))]);
+    assert_eq!(certs.not_after(), Some(2_107_507_749));
+
+    // Deleting the Secret keeps serving the last certificate
+    apply(vec![
+        Ok(Event::Delete(tls_secret(ROTATED_CERT, ROTATED_KEY))),
+        Err(Error::NoResourceVersion),
+    ]);
+    assert_eq!(certs.not_after(), Some(2_107_507_749));
+}
+
+fn client_ca_secret(name: &str, bundle: &[u8]) -> Secret {
+    Secret {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: Some(NAMESPACE.to_string()),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([(
+            "ca.crt".to_string(),
+            ByteString(bundle.to_vec()),
+        )])),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_client_ca_reload() {
+    const CA: &[u8] = include_bytes!("fixtures/client-ca.crt");
+
+    let store = Arc::new(ClientCaStore::new());
+    let watcher = ClientCaWatcher::new(Arc::clone(&store), &[]);
+    let apply = |secrets: Vec<Result<Event<Secret>, Error>>,
+                 config_maps: Vec<Result<Event<ConfigMap>, Error>>| {
+        block_on(watcher.run_with_streams(
+            stream::iter(secrets).map(|event| (WatchScope::Cluster, event)),
+            stream::iter(config_maps).map(|event| (WatchScope::Cluster, event)),
+        ));
+    };
+    let secret_ca = ClientCaRef::parse("secret/client-ca").unwrap();
+    let config_map_ca = ClientCaRef::parse("configmap/client-ca").unwrap();
+
+    apply(
+        vec![
+            Ok(Event::Init),
+            Ok(Event::InitApply(client_ca_secret("client-ca", CA))),
+            Ok(Event::InitDone),
+        ],
+        vec![Ok(Event::Apply(ConfigMap {
+            metadata: ObjectMeta {
+                name: Some("client-ca".to_string()),
+                namespace: Some(NAMESPACE.to_string()),
+                ..Default::default()
+            },
+            data: Some(BTreeMap::from([(
+                "ca.crt".to_string(),
+                String::from_utf8(CA.to_vec()).unwrap(),
+            )])),
+            ..Default::default()
+        }))],
+    );
+    assert!(store.contains(NAMESPACE, &secret_ca));
+    assert!(store.contains(NAMESPACE, &config_map_ca));
+
+    // A broken version keeps the bundle, deletion drops it
+    apply(
+        vec![Ok(Event::Apply(client_ca_secret("client-ca", b"garbage")))],
+        vec![],
+    );
+    assert!(store.contains(NAMESPACE, &secret_ca));
+    apply(
+        vec![Ok(Event::Delete(client_ca_secret("client-ca", CA)))],
+        vec![],
+    );
+    assert!(!store.contains(NAMESPACE, &secret_ca));
+
+    // Deleted while the watch was down: the relist finds nothing
+    apply(
+        vec![],
+        vec![
+            Err(Error::NoResourceVersion),
+            Ok(Event::Init),
+            Ok(Event::InitDone),
+        ],
+    );
+    assert!(!store.contains(NAMESPACE, &config_map_ca));
+}
+
+#[test]
+fn test_registered_devbox_name() {
+    let h = Harness::new();
+
+    // The uniqueID has nothing to do with the Devbox's name
+    h.devbox_events(vec![
+        Ok(Event::Init),
+        Ok(Event::InitApply(devbox("my-devbox", "x7k2p9"))),
+        Ok(Event::InitDone),
+    ]);
+    let info = h.registry.get_devbox("x7k2p9").unwrap();
+    assert_eq!(info.namespace, NAMESPACE);
+    assert_eq!(info.devbox_name, "my-devbox");
+
+    // A Devbox recreated under another name takes over the uniqueID
+    h.devbox_events(vec![
+        Ok(Event::Delete(devbox("my-devbox", "x7k2p9"))),
+        Ok(Event::Apply(devbox("renamed-devbox", "x7k2p9"))),
+    ]);
+    let info = h.registry.get_devbox("x7k2p9").unwrap();
+    assert_eq!(info.devbox_name, "renamed-devbox");
+}
@@ -0,0 +1,127 @@
+//! End-to-end tests of `WEBSOCKET_IDLE_TIMEOUT`.
+//!
+//! A local backend accepts WebSocket upgrades and then echoes whatever it
+//! receives, so a client can keep the upgraded connection busy or let it go
+//! idle. Frames aren't parsed by the gateway, so raw bytes stand in for them.
+
+mod common;
+
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use httpgate::config::{Config, ListenerConfig};
+use httpgate::registry::DevboxRegistry;
+
+use common::{connect, read_head, spawn_gateway, status};
+
+const UNIQUE_ID: &str = "ws-test";
+const IDLE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Start a backend switching every request to an echoed byte stream.
+fn spawn_echo_backend() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || serve(stream));
+        }
+    });
+    port
+}
+
+fn serve(stream: TcpStream) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut stream = stream;
+    read_head(&mut reader);
+    let _ = write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n"
+    );
+    let mut buf = [0; 1024];
+    while let Ok(n @ 1..) = reader.read(&mut buf) {
+        if stream.write_all(&buf[..n]).is_err() {
+            return;
+        }
+    }
+}
+
+/// Address of the proxy and port of the backend, started once per test binary.
+fn gateway() -> &'static (String, u16) {
+    static GATEWAY: OnceLock<(String, u16)> = OnceLock::new();
+    GATEWAY.get_or_init(|| {
+        let backend_port = spawn_echo_backend();
+
+        let registry = Arc::new(DevboxRegistry::new().with_loopback_backends(true));
+        registry.register_devbox(
+            UNIQUE_ID.to_string(),
+            "ns-test".to_string(),
+            "devbox1".to_string(),
+        );
+        registry
+            .update_pod_ip("ns-test", "devbox1", "127.0.0.1".to_string())
+            .unwrap();
+
+        let config = Config {
+            websocket_idle_timeout: Some(IDLE_TIMEOUT),
+            ..Default::default()
+        };
+        let listener = ListenerConfig::from_config(&config).policy;
+
+        let addrs = spawn_gateway(registry, config, vec![listener]);
+        (addrs[0].clone(), backend_port)
+    })
+}
+
+/// Open an upgraded connection through the gateway.
+fn upgrade() -> (TcpStream, BufReader<TcpStream>) {
+    let (proxy_addr, backend_port) = gateway();
+    let (mut stream, mut reader) = connect(proxy_addr);
+    write!(
+        stream,
+        "GET /ws HTTP/1.1\r\nHost: devbox-{UNIQUE_ID}-{backend_port}.devbox.local\r\n\
+         Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+    )
+    .unwrap();
+    assert_eq!(status(&read_head(&mut reader)), 101);
+    (stream, reader)
+}
+
+/// Wait for the gateway to close the connection, returning how long it took.
+fn wait_for_close(reader: &mut BufReader<TcpStream>) -> Duration {
+    let start = Instant::now();
+    let mut buf = [0; 64];
+    // The client's own read timeout is far longer than the idle timeout
+    assert!(matches!(reader.read(&mut buf), Ok(0) | Err(_)));
+    let waited = start.elapsed();
+    assert!(waited < Duration::from_secs(4), "closed after {waited:?}");
+    waited
+}
+
+#[test]
+fn test_idle_connection_closed() {
+    let (_stream, mut reader) = upgrade();
+    let waited = wait_for_close(&mut reader);
+    assert!(waited >= IDLE_TIMEOUT / 2, "closed after {waited:?}");
+}
+
+#[test]
+fn test_busy_connection_kept_open() {
+    let (mut stream, mut reader) = upgrade();
+
+    // Data every so often keeps the connection open well past the timeout
+    let start = Instant::now();
+    let mut echoed = [0; 4];
+    while start.elapsed() < IDLE_TIMEOUT * 3 {
+        stream.write_all(b"ping").unwrap();
+        reader.read_exact(&mut echoed).unwrap();
+        assert_eq!(&echoed, b"ping");
+        thread::sleep(IDLE_TIMEOUT / 4);
+    }
+
+    // ...until it stops
+    wait_for_close(&mut reader);
+}